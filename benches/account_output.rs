@@ -0,0 +1,77 @@
+//! Compares the account report written through serde with the one formatted into a
+//! reused buffer, on about a million rows.
+//!
+//! `ClientId` is 16 bits wide, so a ledger holds at most 65,536 accounts and the
+//! report of a full ledger is written 16 times.
+use payments_engine::output::{write_accounts, write_accounts_serde, OutputError};
+use payments_engine::prelude::{Decimal, Deposit, Ledger, Transaction, Withdrawal};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const CLIENTS: u32 = 1 << 16;
+const PASSES: usize = 16;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Accounts with 4, 2 and no decimal places, and negative balances of disputes.
+fn ledger() -> Ledger {
+    let mut ledger = Ledger::new();
+    for client_id in 0..CLIENTS {
+        let amount = Decimal::new(i64::from(client_id) * 10_007 % 1_000_000, client_id % 5);
+        let deposit = Deposit::new(client_id as u16, client_id, amount);
+        ledger.execute(&Transaction::Deposit(deposit)).unwrap();
+        if client_id % 3 == 0 {
+            let withdrawal =
+                Withdrawal::new(client_id as u16, CLIENTS + client_id, amount / Decimal::TWO);
+            ledger.execute(&Transaction::Withdrawal(withdrawal)).ok();
+        }
+    }
+    ledger
+}
+
+async fn measure<'a, F, Fut>(name: &str, ledger: &'a Ledger, write: F)
+where
+    F: Fn(&'a Ledger, tokio::io::Sink) -> Fut,
+    Fut: Future<Output = Result<usize, OutputError>>,
+{
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut rows = 0;
+    for _ in 0..PASSES {
+        rows += write(ledger, tokio::io::sink()).await.unwrap();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<8} {:>8.2?}  {:.3} allocations/row ({} rows)",
+        name,
+        elapsed,
+        allocations as f64 / rows as f64,
+        rows
+    );
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let ledger = ledger();
+    measure("serde:", &ledger, write_accounts_serde).await;
+    measure("buffer:", &ledger, write_accounts).await;
+}
@@ -0,0 +1,50 @@
+//! Compares the flat account map with hot and cold tiers on a ledger where every
+//! client makes one deposit and only a few clients stay active afterwards.
+//!
+//! `ClientId` is 16 bits wide, so the ledger holds at most 65,536 accounts.
+use payments_engine::prelude::{AccountTiering, Decimal, Deposit, Ledger, Transaction, Withdrawal};
+use std::time::Instant;
+
+const CLIENTS: u32 = 1 << 16;
+const ACTIVE_CLIENTS: u32 = CLIENTS / 20;
+const ACTIVE_ROWS: u32 = 2_000_000;
+
+fn run(name: &str, mut ledger: Ledger) {
+    let start = Instant::now();
+    for client_id in 0..CLIENTS {
+        let deposit = Deposit::new(client_id as u16, client_id, Decimal::new(10_000, 2));
+        ledger.execute(&Transaction::Deposit(deposit)).unwrap();
+    }
+    let mut seed: u64 = 7;
+    for tx_id in CLIENTS..CLIENTS + ACTIVE_ROWS {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let client_id = ((seed >> 33) as u32 % ACTIVE_CLIENTS) as u16;
+        let tx = if tx_id % 2 == 0 {
+            Transaction::Deposit(Deposit::new(client_id, tx_id, Decimal::new(100, 2)))
+        } else {
+            Transaction::Withdrawal(Withdrawal::new(client_id, tx_id, Decimal::new(50, 2)))
+        };
+        ledger.execute(&tx).ok();
+    }
+    let elapsed = start.elapsed();
+    let stats = ledger.memory_stats();
+    println!(
+        "{:<6} {:>8.2?}  hot: {:>6} accounts {:>8} bytes  cold: {:>6} accounts {:>8} bytes",
+        name, elapsed, stats.hot_accounts, stats.hot_bytes, stats.cold_accounts, stats.cold_bytes
+    );
+}
+
+fn main() {
+    run("flat", Ledger::new());
+    run(
+        "tiered",
+        Ledger::builder()
+            .account_tiering(AccountTiering {
+                demote_after: 100_000,
+                hot_capacity: ACTIVE_CLIENTS as usize,
+            })
+            .build(),
+    );
+}
@@ -0,0 +1,62 @@
+//! Compares the memory and throughput of `DisputeTracking::Full` and `Disabled` on a
+//! file of 10M deposits, like a settlement extract without disputes.
+use payments_engine::ingest::{read_data_with_options, IngestOptions};
+use payments_engine::prelude::{DisputeTracking, Ledger};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const ROWS: u32 = 10_000_000;
+const CHANNEL_SIZE: usize = 4096;
+
+fn generate_input(path: &Path) {
+    let mut writer = BufWriter::new(std::fs::File::create(path).unwrap());
+    writeln!(writer, "type, client, tx, amount").unwrap();
+    let mut seed: u64 = 7;
+    let mut next = move || {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as u32
+    };
+    for tx_id in 1..=ROWS {
+        writeln!(
+            writer,
+            "deposit, {}, {}, {}.{:04}",
+            next() % 10_000 + 1,
+            tx_id,
+            next() % 1_000,
+            next() % 10_000
+        )
+        .unwrap();
+    }
+}
+
+async fn run(name: &str, path: &Path, tracking: DisputeTracking) {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(CHANNEL_SIZE);
+    tokio::spawn(read_data_with_options(
+        path.to_str().unwrap().to_string(),
+        sender,
+        IngestOptions::default(),
+    ));
+    let mut ledger = Ledger::builder().dispute_tracking(tracking).build();
+    let start = Instant::now();
+    while let Some(tx) = receiver.recv().await {
+        ledger.execute(&tx).ok();
+    }
+    let elapsed = start.elapsed();
+    let stats = ledger.memory_stats();
+    println!(
+        "{:<9} {:>8.2?}  accounts: {:>6} {:>9} bytes  records: {:>8} {:>10} bytes",
+        name, elapsed, stats.hot_accounts, stats.hot_bytes, stats.tx_records, stats.tx_record_bytes
+    );
+}
+
+fn main() {
+    let path: PathBuf = std::env::temp_dir().join("payments_engine_dispute_tracking_bench.csv");
+    generate_input(&path);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(run("full", &path, DisputeTracking::Full));
+    runtime.block_on(run("disabled", &path, DisputeTracking::Disabled));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,60 @@
+//! Compares executing transactions one at a time with `Ledger::execute_batch` on
+//! a million rows whose clients follow a Zipf distribution, so that a few clients
+//! make most of the transactions as in real traffic.
+use payments_engine::prelude::{Decimal, Deposit, Ledger, Transaction, Withdrawal};
+use std::time::Instant;
+
+const CLIENTS: usize = 1 << 16;
+const ROWS: u32 = 1_000_000;
+const BATCH: usize = 1024;
+/// Exponent of the Zipf distribution, where 1 is the classic rank-frequency law.
+const SKEW: f64 = 1.1;
+
+/// Transactions of Zipf-distributed clients, drawn by inverting the cumulative
+/// distribution with a binary search.
+fn transactions() -> Vec<Transaction> {
+    let mut cdf = Vec::with_capacity(CLIENTS);
+    let mut sum = 0.0;
+    for rank in 1..=CLIENTS {
+        sum += 1.0 / (rank as f64).powf(SKEW);
+        cdf.push(sum);
+    }
+    let mut seed: u64 = 7;
+    (1..=ROWS)
+        .map(|tx_id| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let uniform = (seed >> 11) as f64 / (1u64 << 53) as f64 * sum;
+            let client_id = cdf.partition_point(|&c| c < uniform).min(CLIENTS - 1) as u16;
+            if tx_id % 3 == 0 {
+                Transaction::Withdrawal(Withdrawal::new(client_id, tx_id, Decimal::new(50, 2)))
+            } else {
+                Transaction::Deposit(Deposit::new(client_id, tx_id, Decimal::new(100, 2)))
+            }
+        })
+        .collect()
+}
+
+fn main() {
+    let txs = transactions();
+
+    let mut ledger = Ledger::new();
+    let start = Instant::now();
+    let failed = txs.iter().filter(|tx| ledger.execute(*tx).is_err()).count();
+    println!("loop   {:>8.2?}  {} failed", start.elapsed(), failed);
+
+    let mut ledger = Ledger::new();
+    let start = Instant::now();
+    let failed: usize = txs
+        .chunks(BATCH)
+        .map(|batch| {
+            ledger
+                .execute_batch(batch)
+                .iter()
+                .filter(|r| r.is_err())
+                .count()
+        })
+        .sum();
+    println!("batch  {:>8.2?}  {} failed", start.elapsed(), failed);
+}
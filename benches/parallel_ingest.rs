@@ -0,0 +1,89 @@
+use payments_engine::accounting::Ledger;
+use payments_engine::ingest::{read_data_with_options, IngestOptions, IngestParser};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const ROWS: u32 = 5_000_000;
+const CHANNEL_SIZE: usize = 4096;
+
+fn generate_input(path: &Path) {
+    let mut writer = BufWriter::new(std::fs::File::create(path).unwrap());
+    writeln!(writer, "type, client, tx, amount").unwrap();
+    let mut seed: u64 = 7;
+    let mut next = move || {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as u32
+    };
+    for tx_id in 1..=ROWS {
+        let client_id = next() % 10_000 + 1;
+        match next() % 20 {
+            0 => writeln!(writer, "dispute, {}, {},", client_id, next() % tx_id + 1),
+            1 => writeln!(writer, "resolve, {}, {},", client_id, next() % tx_id + 1),
+            2..=7 => writeln!(
+                writer,
+                "withdrawal, {}, {}, {}.{:04}",
+                client_id,
+                tx_id,
+                next() % 100,
+                next() % 10_000
+            ),
+            _ => writeln!(
+                writer,
+                "deposit, {}, {}, {}.{:04}",
+                client_id,
+                tx_id,
+                next() % 1_000,
+                next() % 10_000
+            ),
+        }
+        .unwrap();
+    }
+}
+
+async fn run(path: &Path, workers: Option<usize>, parser: IngestParser) {
+    let file_path = path.to_str().unwrap().to_string();
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(CHANNEL_SIZE);
+    match workers {
+        Some(workers) => {
+            tokio::spawn(payments_engine::read_data_parallel(
+                file_path, sender, workers, false,
+            ));
+        }
+        None => {
+            let options = IngestOptions {
+                parser,
+                ..IngestOptions::default()
+            };
+            tokio::spawn(read_data_with_options(file_path, sender, options));
+        }
+    }
+    let mut ledger = Ledger::new();
+    while let Some(tx) = receiver.recv().await {
+        ledger.execute(&tx).ok();
+    }
+}
+
+fn main() {
+    let path: PathBuf = std::env::temp_dir().join("payments_engine_parallel_ingest_bench.csv");
+    generate_input(&path);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let start = Instant::now();
+    runtime.block_on(run(&path, None, IngestParser::Csv));
+    println!("serial:    {:>8.2?}", start.elapsed());
+
+    let start = Instant::now();
+    runtime.block_on(run(&path, None, IngestParser::Fast));
+    println!("fast:      {:>8.2?}", start.elapsed());
+
+    for workers in [1, 4, 8] {
+        let start = Instant::now();
+        runtime.block_on(run(&path, Some(workers), IngestParser::Csv));
+        println!("workers={}: {:>8.2?}", workers, start.elapsed());
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
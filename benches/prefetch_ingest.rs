@@ -0,0 +1,72 @@
+use payments_engine::accounting::Ledger;
+use payments_engine::ingest::prefetch::{IngestIoConfig, IngestReader};
+use payments_engine::ingest::transaction_stream;
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_stream::StreamExt;
+
+const ROWS: u32 = 1_000_000;
+const BUFFER_SIZE: usize = 64 * 1024;
+/// Latency of every read call, like a round trip to network storage.
+const READ_LATENCY: Duration = Duration::from_millis(2);
+
+/// In-memory input whose reads block for `READ_LATENCY`, as reads of a fuse mount do.
+struct ThrottledReader {
+    data: std::io::Cursor<Vec<u8>>,
+}
+
+impl AsyncRead for ThrottledReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        std::thread::sleep(READ_LATENCY);
+        Pin::new(&mut self.data).poll_read(cx, buf)
+    }
+}
+
+fn generate_input() -> Vec<u8> {
+    let mut input = Vec::new();
+    writeln!(input, "type, client, tx, amount").unwrap();
+    for tx_id in 1..=ROWS {
+        let client_id = tx_id % 1_000 + 1;
+        if tx_id % 4 == 0 {
+            writeln!(input, "withdrawal, {}, {}, 1.5", client_id, tx_id).unwrap();
+        } else {
+            writeln!(input, "deposit, {}, {}, 10.2500", client_id, tx_id).unwrap();
+        }
+    }
+    input
+}
+
+async fn run(input: Vec<u8>, prefetch: bool) {
+    let config = IngestIoConfig {
+        buffer_size: BUFFER_SIZE,
+        prefetch,
+    };
+    let mut reader = IngestReader::new(
+        ThrottledReader {
+            data: std::io::Cursor::new(input),
+        },
+        config,
+    );
+    let mut rows = transaction_stream(&mut reader);
+    let mut ledger = Ledger::new();
+    while let Some(tx) = rows.next().await {
+        ledger.execute(&tx.unwrap()).ok();
+    }
+}
+
+fn main() {
+    let input = generate_input();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for prefetch in [false, true] {
+        let start = Instant::now();
+        runtime.block_on(run(input.clone(), prefetch));
+        println!("prefetch={}: {:>8.2?}", prefetch, start.elapsed());
+    }
+}
@@ -0,0 +1,85 @@
+use payments_engine::accounting::transactions::TransactionLog;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const ROWS: usize = 1_000_000;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Previous layout of `TransactionLog` allocating a `String` for the type column.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct StringTagLog {
+    #[serde(rename = "type")]
+    tx_type: String,
+    #[serde(rename = "client")]
+    client_id: u16,
+    #[serde(rename = "tx")]
+    tx_id: u32,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    amount: Option<Decimal>,
+}
+
+fn generate_input() -> String {
+    let mut input = String::from("type, client, tx, amount\n");
+    for tx_id in 1..=ROWS {
+        let row = match tx_id % 5 {
+            0 => format!("dispute, {}, {},\n", tx_id % 1000, tx_id - 1),
+            1 | 2 => format!("withdrawal, {}, {}, 1.5\n", tx_id % 1000, tx_id),
+            _ => format!("deposit, {}, {}, 10.25\n", tx_id % 1000, tx_id),
+        };
+        input.push_str(&row);
+    }
+    input
+}
+
+fn measure<T: for<'de> Deserialize<'de>>(name: &str, input: &str) {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input.as_bytes());
+    let headers = reader.headers().unwrap().clone();
+    let mut record = csv::StringRecord::new();
+    let mut parsed = 0;
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    while reader.read_record(&mut record).unwrap() {
+        if record.deserialize::<T>(Some(&headers)).is_ok() {
+            parsed += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<14} {:>8.2?}  {:.3} allocations/row ({} rows)",
+        name,
+        elapsed,
+        allocations as f64 / parsed as f64,
+        parsed
+    );
+}
+
+fn main() {
+    let input = generate_input();
+    measure::<StringTagLog>("string tag:", &input);
+    measure::<TransactionLog>("enum tag:", &input);
+}
@@ -0,0 +1,104 @@
+//! Generates a synthetic CSV input, processes it like the binary does and writes the
+//! account report to a file.
+//!
+//! ```shell
+//! cargo run --example csv_roundtrip -- accounts.csv
+//! ```
+use payments_engine::output::write_accounts;
+use payments_engine::prelude::*;
+use std::path::PathBuf;
+
+const CLIENTS: ClientId = 10;
+const TRANSACTIONS: TxId = 1000;
+
+/// Deposits and withdrawals spread across clients, with a dispute after every
+/// tenth deposit and a chargeback after every hundredth.
+fn synthetic_transactions() -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    for tx_id in 1..=TRANSACTIONS {
+        let client_id = (tx_id % CLIENTS as TxId) as ClientId + 1;
+        let amount = Decimal::new(tx_id as i64 % 97 + 1, 1);
+        if tx_id % 3 == 0 {
+            transactions.push(Transaction::Withdrawal(Withdrawal::new(
+                client_id, tx_id, amount,
+            )));
+            continue;
+        }
+        transactions.push(Transaction::Deposit(Deposit::new(client_id, tx_id, amount)));
+        if tx_id % 10 == 1 {
+            transactions.push(Transaction::Dispute(Dispute::new(client_id, tx_id)));
+        }
+        if tx_id % 100 == 1 {
+            transactions.push(Transaction::Chargeback(Chargeback::new(client_id, tx_id)));
+        }
+    }
+    transactions
+}
+
+async fn roundtrip(
+    input_path: PathBuf,
+    output_path: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let transactions = synthetic_transactions();
+    write_transactions_csv(std::fs::File::create(&input_path)?, &transactions)?;
+
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(1024);
+    let reader = tokio::spawn(payments_engine::read_data(
+        input_path.to_string_lossy().into_owned(),
+        sender,
+    ));
+    let mut ledger = Ledger::new();
+    let mut rejected = 0;
+    while let Some(tx) = receiver.recv().await {
+        if ledger.execute(&tx).is_err() {
+            rejected += 1;
+        }
+    }
+    let report = reader.await??;
+    let rows = write_accounts(&ledger, tokio::fs::File::create(&output_path).await?).await?;
+    println!(
+        "{} transactions written to {}, {} malformed and {} rejected, {} accounts written to {}",
+        transactions.len(),
+        input_path.display(),
+        report.rows_malformed,
+        rejected,
+        rows,
+        output_path.display()
+    );
+    std::fs::remove_file(input_path)?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let output_path = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("payments_engine_roundtrip_accounts.csv"));
+    let input_path = std::env::temp_dir().join(format!(
+        "payments_engine_roundtrip_{}.csv",
+        std::process::id()
+    ));
+    roundtrip(input_path, output_path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn runs() {
+        let output_path = std::env::temp_dir().join(format!(
+            "payments_engine_roundtrip_accounts_{}.csv",
+            std::process::id()
+        ));
+        let input_path = std::env::temp_dir().join(format!(
+            "payments_engine_roundtrip_test_{}.csv",
+            std::process::id()
+        ));
+        roundtrip(input_path, output_path.clone()).await.unwrap();
+        let report = std::fs::read_to_string(&output_path).unwrap();
+        assert_eq!(report.lines().count(), CLIENTS as usize + 1);
+        std::fs::remove_file(output_path).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Feeds a pipeline from a custom `TransactionSource`, here rows kept as tuples in a
+//! `Vec`, with a filter and an audit stage.
+//!
+//! ```shell
+//! cargo run --example custom_source
+//! ```
+use payments_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+/// Rows of `(type, client, tx, amount)`, as they could come from a message queue.
+struct TupleSource {
+    rows: std::vec::IntoIter<(TxTypeTag, ClientId, TxId, Option<Decimal>)>,
+    line: u64,
+}
+
+impl TupleSource {
+    fn new(rows: Vec<(TxTypeTag, ClientId, TxId, Option<Decimal>)>) -> Self {
+        Self {
+            rows: rows.into_iter(),
+            line: 0,
+        }
+    }
+}
+
+impl TransactionSource for TupleSource {
+    fn next_row(&mut self) -> std::io::Result<Option<SourceRow<'_>>> {
+        let Some((tx_type, client_id, tx_id, amount)) = self.rows.next() else {
+            return Ok(None);
+        };
+        self.line += 1;
+        let tx = match (tx_type, amount) {
+            (TxTypeTag::Deposit, Some(amount)) => {
+                Ok(Transaction::Deposit(Deposit::new(client_id, tx_id, amount)))
+            }
+            (TxTypeTag::Withdrawal, Some(amount)) => Ok(Transaction::Withdrawal(Withdrawal::new(
+                client_id, tx_id, amount,
+            ))),
+            (TxTypeTag::WithdrawAuth, Some(amount)) => Ok(Transaction::WithdrawAuth(
+                WithdrawAuth::new(client_id, tx_id, amount),
+            )),
+            (TxTypeTag::Deposit | TxTypeTag::Withdrawal | TxTypeTag::WithdrawAuth, None) => {
+                Err(TransactionLogError::MissingAmount)
+            }
+            (TxTypeTag::Dispute, _) => Ok(Transaction::Dispute(Dispute::new(client_id, tx_id))),
+            (TxTypeTag::Resolve, _) => Ok(Transaction::Resolve(Resolve::new(client_id, tx_id))),
+            (TxTypeTag::Chargeback, _) => {
+                Ok(Transaction::Chargeback(Chargeback::new(client_id, tx_id)))
+            }
+            (TxTypeTag::Adjustment, _) => {
+                Ok(Transaction::Adjustment(Adjustment::new(client_id, tx_id)))
+            }
+            (TxTypeTag::WithdrawCapture, _) => Ok(Transaction::WithdrawCapture(
+                WithdrawCapture::new(client_id, tx_id),
+            )),
+            (TxTypeTag::WithdrawRelease, _) => Ok(Transaction::WithdrawRelease(
+                WithdrawRelease::new(client_id, tx_id),
+            )),
+            (TxTypeTag::Settle, _) => Ok(Transaction::Settle(Settle::new(client_id, tx_id))),
+        };
+        Ok(Some(SourceRow {
+            line: Some(self.line),
+            raw: None,
+            tx,
+            field: None,
+        }))
+    }
+}
+
+fn main() {
+    let source = TupleSource::new(vec![
+        (TxTypeTag::Deposit, 1, 1, Some(dec!(10))),
+        (TxTypeTag::Deposit, 2, 2, Some(dec!(20))),
+        (TxTypeTag::Withdrawal, 1, 3, None),
+        (TxTypeTag::Withdrawal, 2, 4, Some(dec!(25))),
+        (TxTypeTag::Deposit, 99, 5, Some(dec!(1000))),
+        (TxTypeTag::Dispute, 2, 2, None),
+    ]);
+    let mut ledger = Ledger::new();
+    let stats = Pipeline::new(source)
+        // Client 99 is a test account which must not reach the ledger.
+        .filter(|tx: &Transaction| tx.client_id() != 99)
+        .audit(|line: Option<u64>, outcome: &RowOutcome<'_>| {
+            println!("line {}: {:?}", line.unwrap_or_default(), outcome);
+            Ok(())
+        })
+        .run(&mut ledger)
+        .expect("in-memory sources don't fail");
+    println!(
+        "executed {}, filtered {}, rejected {}, malformed {}",
+        stats.rows_executed, stats.rows_filtered, stats.rows_rejected, stats.rows_malformed
+    );
+    assert!(ledger.account(99).is_none());
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn runs() {
+        super::main();
+    }
+}
@@ -0,0 +1,80 @@
+//! Walks deposits through dispute, resolve, chargeback and dispute expiry, printing
+//! the events reported to the ledger's listener.
+//!
+//! ```shell
+//! cargo run --example dispute_lifecycle
+//! ```
+use payments_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+fn print_event(event: &LedgerEvent) -> Result<(), SinkError> {
+    match event {
+        LedgerEvent::Disputed {
+            client_id,
+            tx_id,
+            amount,
+            reason,
+        } => println!(
+            "client {client_id} disputed tx {tx_id} for reason {}, holding {amount}",
+            reason.as_ref().map_or("-", DisputeReason::as_str)
+        ),
+        LedgerEvent::Resolved {
+            client_id,
+            tx_id,
+            amount,
+        } => println!("dispute of tx {tx_id} of client {client_id} resolved, releasing {amount}"),
+        LedgerEvent::ChargedBack {
+            client_id,
+            tx_id,
+            amount,
+            ..
+        } => println!("tx {tx_id} of client {client_id} charged back, {amount} withdrawn"),
+        LedgerEvent::AutoResolved {
+            client_id,
+            tx_id,
+            amount,
+        } => println!(
+            "stale dispute of tx {tx_id} of client {client_id} expired, releasing {amount}"
+        ),
+        other => println!("{other:?}"),
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut ledger = Ledger::builder().event_listener(print_event).build();
+    for tx in [
+        Transaction::Deposit(Deposit::new(1, 1, dec!(50))),
+        Transaction::Dispute(Dispute::new(1, 1).with_reason("10.4".parse().expect("short reason"))),
+        Transaction::Resolve(Resolve::new(1, 1)),
+        Transaction::Dispute(Dispute::new(1, 1)),
+        Transaction::Chargeback(Chargeback::new(1, 1)),
+        // Final after the chargeback.
+        Transaction::Dispute(Dispute::new(1, 1)),
+        Transaction::Deposit(Deposit::new(2, 2, dec!(5))),
+        Transaction::Dispute(Dispute::new(2, 2)),
+    ] {
+        if let Err(err) = ledger.execute(&tx) {
+            println!("{:?} rejected: {}", tx, err);
+        }
+    }
+
+    let account = ledger.account(1).expect("client 1 deposited");
+    println!(
+        "client 1: total {}, locked {}",
+        account.total(),
+        account.locked()
+    );
+    // Nothing happened since, but every dispute opened so far counts as stale here.
+    let expired = ledger.expire_disputes(ledger.tx_seq());
+    println!("{} disputes expired", expired.len());
+    assert_eq!(ledger.open_disputes().count(), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn runs() {
+        super::main();
+    }
+}
@@ -0,0 +1,56 @@
+//! Drives a small in-memory scenario using only the prelude.
+//!
+//! ```shell
+//! cargo run --example embedded
+//! ```
+use payments_engine::prelude::*;
+
+fn print_account(ledger: &Ledger, client_id: ClientId) {
+    match ledger.account(client_id) {
+        Some(account) => println!(
+            "client {}: available {}, held {}, total {}, locked {}",
+            client_id,
+            account.available(),
+            account.held(),
+            account.total(),
+            account.locked()
+        ),
+        None => println!("client {}: no account", client_id),
+    }
+}
+
+fn main() {
+    let mut ledger = Ledger::builder()
+        .lock_policy(LockPolicy::HoldIncoming)
+        .build();
+    let transactions = [
+        Transaction::Deposit(Deposit::new(1, 1, Decimal::from(100))),
+        Transaction::Deposit(Deposit::new(2, 2, Decimal::from(50))),
+        Transaction::Withdrawal(Withdrawal::new(1, 3, Decimal::from(30))),
+        Transaction::Dispute(Dispute::new(2, 2)),
+        Transaction::Withdrawal(Withdrawal::new(2, 4, Decimal::from(10))),
+        Transaction::Chargeback(Chargeback::new(2, 2)),
+        Transaction::Deposit(Deposit::new(2, 5, Decimal::from(20))),
+        Transaction::Adjustment(Adjustment::new(1, 1)),
+    ];
+    for tx in &transactions {
+        match ledger.execute(tx) {
+            Ok(()) => {}
+            Err(TxError::InsufficientFunds) => println!("{:?} rejected: insufficient funds", tx),
+            Err(err) => println!("{:?} rejected: {}", tx, err),
+        }
+    }
+
+    for client_id in [1, 2, 3] {
+        print_account(&ledger, client_id);
+    }
+    for dispute in ledger.open_disputes() {
+        println!(
+            "held deposit {} of client {}: {}",
+            dispute.tx_id, dispute.client_id, dispute.amount
+        );
+    }
+    if let Err(violation) = ledger.verify_invariants() {
+        println!("ledger invariant violated: {}", violation);
+    }
+}
@@ -0,0 +1,57 @@
+//! Builds transactions with the public constructors, executes them one by one and
+//! queries the resulting balances.
+//!
+//! ```shell
+//! cargo run --example in_memory
+//! ```
+use payments_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+fn main() {
+    let mut ledger = Ledger::new();
+    let transactions = [
+        Transaction::Deposit(Deposit::new(1, 1, dec!(100))),
+        Transaction::Deposit(Deposit::new(2, 2, dec!(25.5))),
+        Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(40))),
+        Transaction::Withdrawal(Withdrawal::new(2, 4, dec!(30))),
+        Transaction::Dispute(Dispute::new(1, 1)),
+        Transaction::Resolve(Resolve::new(1, 1)),
+    ];
+    for tx in &transactions {
+        if let Err(err) = ledger.execute(tx) {
+            println!(
+                "tx {} of client {} rejected: {}",
+                tx.tx_id(),
+                tx.client_id(),
+                err
+            );
+        }
+    }
+
+    let mut accounts = ledger
+        .accounts_iter()
+        .map(|(_client_id, account)| AccountView::from(account))
+        .collect::<Vec<_>>();
+    accounts.sort_unstable_by_key(|account| account.client_id);
+    for account in accounts {
+        println!(
+            "client {}: available {}, held {}, total {}, locked {}",
+            account.client_id, account.available, account.held, account.total, account.locked
+        );
+    }
+    let account = ledger.account(1).expect("client 1 deposited");
+    println!(
+        "client 1 deposited {} and withdrew {} in total",
+        account.total_deposited(),
+        account.total_withdrawn()
+    );
+    assert!(ledger.verify_invariants().is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn runs() {
+        super::main();
+    }
+}
@@ -0,0 +1,41 @@
+//! Applies transactions read line by line from stdin and prints an account snapshot
+//! every `SNAPSHOT_EVERY` lines. The last processed offset is committed to the file
+//! given as the first argument, so a restarted feed can resume from it.
+//!
+//! ```shell
+//! cat transactions.csv | cargo run --example line_protocol -- feed.offset
+//! ```
+use payments_engine::accounting::Ledger;
+use payments_engine::ingest::line_protocol::{run_line_protocol, FileAcker};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const SNAPSHOT_EVERY: usize = 1000;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let offset_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "line_protocol.offset".to_string());
+    let mut acker = FileAcker::new(offset_path);
+    let mut stdin = BufReader::new(tokio::io::stdin());
+    let mut ledger = Ledger::new();
+    let mut offset = 0;
+
+    loop {
+        let mut batch = Vec::new();
+        for _ in 0..SNAPSHOT_EVERY {
+            if stdin.read_until(b'\n', &mut batch).await? == 0 {
+                break;
+            }
+        }
+        if batch.is_empty() {
+            break;
+        }
+        run_line_protocol(batch.as_slice(), offset, &mut ledger, &mut acker).await?;
+        offset += batch.len() as u64;
+        payments_engine::output_data(&ledger)
+            .await
+            .map_err(|err| std::io::Error::other(err.to_string()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,6 @@
+//! Arbitrary bytes as a CSV input file and as a single headerless row.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| payments_engine::fuzzing::parse_bytes(data));
@@ -0,0 +1,49 @@
+//! Long sequences of structured records executed on the ledger, checking its
+//! invariants after every step.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use payments_engine::fuzzing::{execute_records, Record};
+use payments_engine::prelude::{Decimal, TxTypeTag};
+
+const TX_TYPES: [TxTypeTag; 10] = [
+    TxTypeTag::Deposit,
+    TxTypeTag::Withdrawal,
+    TxTypeTag::Dispute,
+    TxTypeTag::Resolve,
+    TxTypeTag::Chargeback,
+    TxTypeTag::Adjustment,
+    TxTypeTag::WithdrawAuth,
+    TxTypeTag::WithdrawCapture,
+    TxTypeTag::WithdrawRelease,
+    TxTypeTag::Settle,
+];
+
+/// Narrow ids, so that records keep hitting the same accounts and deposits.
+#[derive(Arbitrary, Debug)]
+struct ArbitraryRecord {
+    tx_type: u8,
+    client_id: u8,
+    tx_id: u8,
+    /// Mantissa, of which the upper 96 bits are used, and scale.
+    amount: Option<(i128, u8)>,
+}
+
+impl From<ArbitraryRecord> for Record {
+    fn from(record: ArbitraryRecord) -> Self {
+        Record {
+            tx_type: TX_TYPES[usize::from(record.tx_type) % TX_TYPES.len()],
+            client_id: record.client_id.into(),
+            tx_id: record.tx_id.into(),
+            amount: record.amount.and_then(|(mantissa, scale)| {
+                Decimal::try_from_i128_with_scale(mantissa >> 32, u32::from(scale % 29)).ok()
+            }),
+        }
+    }
+}
+
+fuzz_target!(|records: Vec<ArbitraryRecord>| {
+    let records = records.into_iter().map(Record::from).collect::<Vec<_>>();
+    execute_records(&records);
+});
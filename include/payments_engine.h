@@ -0,0 +1,154 @@
+#ifndef PAYMENTS_ENGINE_H
+#define PAYMENTS_ENGINE_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdint.h>
+#include <stddef.h>
+
+#define PE_OK 0
+
+#define PE_ERR_NULL_POINTER -1
+
+#define PE_ERR_INVALID_UTF8 -2
+
+#define PE_ERR_INVALID_TX_TYPE -3
+
+#define PE_ERR_MISSING_AMOUNT -4
+
+#define PE_ERR_OUTPUT -5
+
+#define PE_ERR_PANIC -6
+
+#define PE_ERR_TOO_FEW_FIELDS -7
+
+#define PE_ERR_TOO_MANY_FIELDS -8
+
+#define PE_ERR_AMBIGUOUS_AMOUNT -9
+
+#define PE_ERR_REFERENCE_TOO_LONG -10
+
+#define PE_ERR_NEGATIVE_AMOUNT_NOT_ALLOWED -11
+
+#define PE_ERR_TRAILING_CHARACTERS_IN_AMOUNT -12
+
+#define PE_ERR_REASON_TOO_LONG -13
+
+#define PE_ERR_EXCESS_PRECISION -14
+
+#define PE_ERR_CLIENT_ACCOUNT_LOCKED 1
+
+#define PE_ERR_INSUFFICIENT_FUNDS 2
+
+#define PE_ERR_CLIENT_ACCOUNT_NOT_FOUND 3
+
+#define PE_ERR_ORIGIN_TX_NOT_FOUND 4
+
+#define PE_ERR_TX_ALREADY_DISPUTED 5
+
+#define PE_ERR_TX_NOT_DISPUTED 6
+
+#define PE_ERR_TX_CHARGED_BACK 7
+
+#define PE_ERR_TX_REVERSED 8
+
+#define PE_ERR_HELD_BALANCE_INCONSISTENT 9
+
+#define PE_ERR_BALANCE_OVERFLOW 10
+
+#define PE_ERR_HOLD_NOT_FOUND 11
+
+#define PE_ERR_HOLD_ALREADY_SETTLED 12
+
+#define PE_ERR_VELOCITY_LIMIT_EXCEEDED 13
+
+#define PE_ERR_TX_NOT_DISPUTABLE 14
+
+#define PE_ERR_DISPUTE_EXPOSURE_LIMIT_EXCEEDED 15
+
+#define PE_ERR_DISPUTED_AMOUNT_MISMATCH 16
+
+#define PE_ERR_WITHDRAWAL_NOT_FOUND 17
+
+#define PE_ERR_WITHDRAWAL_ALREADY_SETTLED 18
+
+#define PE_ERR_TX_NOT_WITHDRAWAL 19
+
+#define PE_ERR_RESOLVE_BEFORE_DISPUTE 20
+
+#define PE_ERR_CHARGEBACK_BEFORE_DISPUTE 21
+
+#define PE_ERR_DUPLICATE_RESOLVE 22
+
+#define PE_ERR_TOO_MANY_OPEN_DISPUTES 23
+
+#define PE_ERR_UNKNOWN_DISPUTE_REASON 24
+
+#define PE_ERR_DISPUTE_WINDOW_EXPIRED 25
+
+#define PE_ERR_RESERVED_TX_ID 26
+
+#define PE_ERR_TX_ID_REUSE_AFTER_SETTLEMENT 27
+
+#define PE_ERR_DISPUTE_TRACKING_DISABLED 28
+
+#define PE_ERR_DUPLICATE_TX_ID 29
+
+/**
+ * Ledger handle owned by the caller.
+ */
+typedef struct PeLedger PeLedger;
+
+/**
+ * Creates an empty ledger, or returns null on failure. Free it with `pe_ledger_free`.
+ */
+PeLedger *pe_ledger_new(void);
+
+/**
+ * Frees a ledger created by `pe_ledger_new`. Null is ignored.
+ *
+ * # Safety
+ * `ledger` must be null or a pointer returned by `pe_ledger_new` which was not freed yet.
+ */
+void pe_ledger_free(PeLedger *ledger);
+
+/**
+ * Executes a single headerless CSV row `type,client,tx,amount`; the amount field
+ * may be empty but must be present. Returns `PE_OK`, a negative code when the row
+ * could not be applied at all, or a positive code when the ledger rejected the
+ * transaction.
+ *
+ * A ledger which panicked during execution should not be used any further.
+ *
+ * # Safety
+ * `ledger` must be a live pointer from `pe_ledger_new` and `row_ptr` must point to
+ * `row_len` readable bytes.
+ */
+int32_t pe_execute_csv_row(PeLedger *ledger, const uint8_t *row_ptr, size_t row_len);
+
+/**
+ * Writes the accounts as a NUL-terminated CSV string sorted by client id to
+ * `*out_buf_ptr` and its length without the terminator to `*out_len_ptr`. The
+ * string must be freed with `pe_string_free`.
+ *
+ * # Safety
+ * `ledger` must be a live pointer from `pe_ledger_new`, the output pointers must
+ * be valid for writes.
+ */
+int32_t pe_export_accounts_csv(const PeLedger *ledger, char **out_buf_ptr, size_t *out_len_ptr);
+
+/**
+ * Returns a copy of the message of the last failed call on this thread, or null
+ * if no call failed yet. The string must be freed with `pe_string_free`.
+ */
+char *pe_last_error_message(void);
+
+/**
+ * Frees a string returned by this library. Null is ignored.
+ *
+ * # Safety
+ * `string` must be null or a string returned by this library which was not freed yet.
+ */
+void pe_string_free(char *string);
+
+#endif /* PAYMENTS_ENGINE_H */
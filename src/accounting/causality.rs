@@ -0,0 +1,497 @@
+//! Order in which the disputes, resolves and chargebacks of deposits arrive, see
+//! `CausalityMode`.
+use crate::accounting::config::CausalityMode;
+use crate::accounting::events::LedgerEvent;
+use crate::accounting::reasons::DisputeReason;
+use crate::accounting::transactions::{Chargeback, Resolve, Transaction, TxTypeTag};
+use crate::accounting::{ExecutableTransaction, Ledger, TxError, TxOutcome};
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+
+/// Resolve or chargeback deferred until the dispute of its deposit arrives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingLifecycle {
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub kind: TxTypeTag,
+    /// Amount of the deposit stated by the message, see `DisputeAmountHandling`.
+    pub amount: Option<Decimal>,
+    /// Reason of a chargeback, see `Chargeback::with_reason`.
+    pub reason: Option<DisputeReason>,
+    /// Position of the message among the transactions executed while tracking,
+    /// starting at 1.
+    pub received_at: u64,
+}
+
+impl PendingLifecycle {
+    fn transaction(&self) -> Transaction {
+        match self.kind {
+            TxTypeTag::Resolve => {
+                let resolve = Resolve::new(self.client_id, self.tx_id);
+                Transaction::Resolve(match self.amount {
+                    Some(amount) => resolve.with_amount(amount),
+                    None => resolve,
+                })
+            }
+            _ => {
+                let mut chargeback = Chargeback::new(self.client_id, self.tx_id);
+                if let Some(amount) = self.amount {
+                    chargeback = chargeback.with_amount(amount);
+                }
+                if let Some(reason) = &self.reason {
+                    chargeback = chargeback.with_reason(reason.clone());
+                }
+                Transaction::Chargeback(chargeback)
+            }
+        }
+    }
+}
+
+/// Counts of the messages handled under `CausalityMode::BufferOutOfOrder`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CausalityStats {
+    /// Resolves and chargebacks added to the pending buffer.
+    pub deferred: u64,
+    /// Deferred messages applied after the dispute of their deposit.
+    pub reordered: u64,
+    /// Deferred messages dropped from the full buffer.
+    pub dropped: u64,
+    /// Deferred messages whose dispute did not arrive within the window, or which
+    /// failed when applied after it.
+    pub expired: u64,
+}
+
+/// Lifecycle messages seen for a tx id, including rejected ones.
+#[derive(Debug, Default, Clone, Copy)]
+struct Seen {
+    dispute: bool,
+    resolve: bool,
+}
+
+pub(crate) struct CausalityTracker {
+    mode: CausalityMode,
+    seen: HashMap<TxId, Seen>,
+    pending: VecDeque<PendingLifecycle>,
+    messages: u64,
+    stats: CausalityStats,
+}
+
+impl CausalityTracker {
+    /// Tracker for `mode`, or `None` when tracking is off.
+    pub(crate) fn new(mode: CausalityMode) -> Option<Self> {
+        (mode != CausalityMode::Off).then(|| Self {
+            mode,
+            seen: HashMap::new(),
+            pending: VecDeque::new(),
+            messages: 0,
+            stats: CausalityStats::default(),
+        })
+    }
+
+    /// Error telling why a resolve or chargeback failed with `TxError::TxNotDisputed`,
+    /// given the messages seen before it.
+    fn classify(kind: TxTypeTag, seen: Seen) -> TxError {
+        match kind {
+            TxTypeTag::Resolve if !seen.dispute => TxError::ResolveBeforeDispute,
+            TxTypeTag::Resolve if seen.resolve => TxError::DuplicateResolve,
+            TxTypeTag::Chargeback if !seen.dispute => TxError::ChargebackBeforeDispute,
+            _ => TxError::TxNotDisputed,
+        }
+    }
+
+    /// Discards pending messages which waited longer than the window.
+    fn expire(&mut self) {
+        let CausalityMode::BufferOutOfOrder { window, .. } = self.mode else {
+            return;
+        };
+        let messages = self.messages;
+        let pending = self.pending.len();
+        self.pending
+            .retain(|pending| messages - pending.received_at <= window);
+        self.stats.expired += (pending - self.pending.len()) as u64;
+    }
+
+    /// Adds `pending` to the buffer, returning the oldest message when it had to be
+    /// dropped to make room.
+    fn defer(&mut self, pending: PendingLifecycle, capacity: usize) -> Option<PendingLifecycle> {
+        let dropped = (self.pending.len() >= capacity)
+            .then(|| self.pending.pop_front())
+            .flatten();
+        self.stats.dropped += u64::from(dropped.is_some());
+        self.stats.deferred += 1;
+        self.pending.push_back(pending);
+        dropped
+    }
+
+    /// Takes the pending messages of the deposit `tx_id` of `client_id`, oldest first.
+    fn take_pending(&mut self, client_id: ClientId, tx_id: TxId) -> Vec<PendingLifecycle> {
+        let (due, kept): (VecDeque<_>, _) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|pending| pending.client_id == client_id && pending.tx_id == tx_id);
+        self.pending = kept;
+        due.into()
+    }
+
+    /// Combines the messages seen by both trackers, keeping the mode of `self`.
+    pub(crate) fn merge(&mut self, other: CausalityTracker) {
+        for (tx_id, seen) in other.seen {
+            let own = self.seen.entry(tx_id).or_default();
+            own.dispute |= seen.dispute;
+            own.resolve |= seen.resolve;
+        }
+        self.pending.extend(other.pending);
+    }
+}
+
+impl Ledger {
+    /// Like `execute_outcome` without tracking, remembering the lifecycle messages
+    /// and classifying or deferring resolves and chargebacks of undisputed deposits.
+    pub(crate) fn execute_causal(
+        &mut self,
+        tx: &impl ExecutableTransaction,
+    ) -> Result<TxOutcome, TxError> {
+        let (client_id, tx_id, kind) = (tx.client_id(), tx.tx_id(), tx.kind());
+        let tracker = self
+            .causality
+            .as_mut()
+            .expect("causal execution requires a tracker");
+        tracker.messages += 1;
+        tracker.expire();
+        let seen = tracker.seen.get(&tx_id).copied().unwrap_or_default();
+        match kind {
+            TxTypeTag::Dispute => tracker.seen.entry(tx_id).or_default().dispute = true,
+            TxTypeTag::Resolve => tracker.seen.entry(tx_id).or_default().resolve = true,
+            _ => {}
+        }
+        let mode = tracker.mode;
+
+        match (kind, self.execute_untracked(tx)) {
+            (TxTypeTag::Resolve | TxTypeTag::Chargeback, Err(TxError::TxNotDisputed)) => {
+                let err = CausalityTracker::classify(kind, seen);
+                let CausalityMode::BufferOutOfOrder { capacity, .. } = mode else {
+                    return Err(err);
+                };
+                if !matches!(
+                    err,
+                    TxError::ResolveBeforeDispute | TxError::ChargebackBeforeDispute
+                ) {
+                    return Err(err);
+                }
+                let tracker = self.causality.as_mut().expect("checked above");
+                let pending = PendingLifecycle {
+                    client_id,
+                    tx_id,
+                    kind,
+                    amount: tx.stated_amount(),
+                    reason: tx.reason().cloned(),
+                    received_at: tracker.messages,
+                };
+                if let Some(dropped) = tracker.defer(pending, capacity) {
+                    self.events.emit(LedgerEvent::ReorderDropped {
+                        client_id: dropped.client_id,
+                        tx_id: dropped.tx_id,
+                        kind: dropped.kind,
+                    });
+                }
+                Ok(TxOutcome::Deferred)
+            }
+            (TxTypeTag::Dispute, Ok(TxOutcome::Applied)) => {
+                self.apply_pending(client_id, tx_id);
+                Ok(TxOutcome::Applied)
+            }
+            (_, result) => result,
+        }
+    }
+
+    /// Applies the messages deferred until the dispute of `tx_id` just executed.
+    fn apply_pending(&mut self, client_id: ClientId, tx_id: TxId) {
+        let tracker = self.causality.as_mut().expect("called while tracking");
+        let messages = tracker.messages;
+        for pending in tracker.take_pending(client_id, tx_id) {
+            let applied = self.execute_untracked(&pending.transaction()).is_ok();
+            let tracker = self.causality.as_mut().expect("called while tracking");
+            if !applied {
+                tracker.stats.expired += 1;
+                continue;
+            }
+            tracker.stats.reordered += 1;
+            self.events.emit(LedgerEvent::ReorderedApplied {
+                client_id,
+                tx_id,
+                kind: pending.kind,
+                delay: messages - pending.received_at,
+            });
+        }
+    }
+
+    /// Resolves and chargebacks deferred under `CausalityMode::BufferOutOfOrder`,
+    /// oldest first.
+    pub fn pending_lifecycle(&self) -> Vec<PendingLifecycle> {
+        self.causality.as_ref().map_or_else(Vec::new, |tracker| {
+            tracker.pending.iter().cloned().collect()
+        })
+    }
+
+    /// Counts of deferred messages, all zero unless `CausalityMode::BufferOutOfOrder`
+    /// is configured.
+    pub fn causality_stats(&self) -> CausalityStats {
+        self.causality
+            .as_ref()
+            .map_or_else(CausalityStats::default, |tracker| tracker.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::config::DuplicateDisputePolicy;
+    use crate::accounting::transactions::{Deposit, Dispute};
+    use crate::engine::process_csv_str;
+    use rust_decimal_macros::dec;
+    use std::sync::{Arc, Mutex};
+
+    fn buffered(window: u64, capacity: usize) -> Ledger {
+        Ledger::builder()
+            .causality_mode(CausalityMode::BufferOutOfOrder { window, capacity })
+            .build()
+    }
+
+    fn deposits(ledger: &mut Ledger, tx_ids: impl IntoIterator<Item = TxId>) {
+        for tx_id in tx_ids {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(1, tx_id, dec!(10))))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn classifies_resolves_and_chargebacks_of_undisputed_deposits() {
+        let mut ledger = Ledger::builder()
+            .causality_mode(CausalityMode::Classify)
+            .build();
+        deposits(&mut ledger, [1, 2]);
+        let mut execute = |tx: Transaction| ledger.execute(&tx);
+        assert_eq!(
+            execute(Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::ResolveBeforeDispute)
+        );
+        assert_eq!(
+            execute(Transaction::Chargeback(Chargeback::new(1, 2))),
+            Err(TxError::ChargebackBeforeDispute)
+        );
+        assert_eq!(execute(Transaction::Dispute(Dispute::new(1, 1))), Ok(()));
+        assert_eq!(execute(Transaction::Resolve(Resolve::new(1, 1))), Ok(()));
+        assert_eq!(
+            execute(Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::DuplicateResolve)
+        );
+        // Disputed and resolved before, so neither early nor a duplicate resolve
+        assert_eq!(
+            execute(Transaction::Chargeback(Chargeback::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        // Unknown deposits are not reclassified
+        assert_eq!(
+            execute(Transaction::Resolve(Resolve::new(1, 9))),
+            Err(TxError::OriginTxNotFound)
+        );
+        assert!(ledger.pending_lifecycle().is_empty());
+        assert_eq!(ledger.causality_stats(), CausalityStats::default());
+    }
+
+    #[test]
+    fn rejected_disputes_count_as_seen() {
+        let mut ledger = Ledger::builder()
+            .causality_mode(CausalityMode::Classify)
+            .build();
+        deposits(&mut ledger, [1]);
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(2, 1))),
+            Err(TxError::ClientAccountNotFound)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+    }
+
+    #[test]
+    fn buffered_resolve_applies_after_its_dispute() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut ledger = Ledger::builder()
+            .causality_mode(CausalityMode::BufferOutOfOrder {
+                window: 3,
+                capacity: 4,
+            })
+            .event_listener(move |event| {
+                recorded.lock().unwrap().push(event.clone());
+                Ok(())
+            })
+            .build();
+        deposits(&mut ledger, [1, 2]);
+        assert_eq!(
+            ledger.execute_outcome(&Transaction::Resolve(
+                Resolve::new(1, 1).with_amount(dec!(10))
+            )),
+            Ok(TxOutcome::Deferred)
+        );
+        assert_eq!(
+            ledger.pending_lifecycle(),
+            [PendingLifecycle {
+                client_id: 1,
+                tx_id: 1,
+                kind: TxTypeTag::Resolve,
+                amount: Some(dec!(10)),
+                reason: None,
+                received_at: 3,
+            }]
+        );
+        deposits(&mut ledger, [3]);
+        let tx_seq = ledger.tx_seq();
+        assert_eq!(
+            ledger.execute_outcome(&Transaction::Dispute(Dispute::new(1, 1))),
+            Ok(TxOutcome::Applied)
+        );
+        // Both the dispute and the deferred resolve were executed
+        assert_eq!(ledger.tx_seq(), tx_seq + 2);
+        let account = ledger.account(1).unwrap();
+        assert_eq!((account.available(), account.held()), (dec!(30), dec!(0)));
+        assert!(ledger.pending_lifecycle().is_empty());
+        assert_eq!(
+            ledger.causality_stats(),
+            CausalityStats {
+                deferred: 1,
+                reordered: 1,
+                dropped: 0,
+                expired: 0,
+            }
+        );
+        assert_eq!(
+            events.lock().unwrap()[..],
+            [
+                LedgerEvent::Disputed {
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: dec!(10),
+                    reason: None,
+                },
+                LedgerEvent::Resolved {
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: dec!(10),
+                },
+                LedgerEvent::ReorderedApplied {
+                    client_id: 1,
+                    tx_id: 1,
+                    kind: TxTypeTag::Resolve,
+                    delay: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::DuplicateResolve)
+        );
+    }
+
+    #[test]
+    fn buffered_chargeback_expires_after_window() {
+        let mut ledger = buffered(2, 4);
+        deposits(&mut ledger, [1]);
+        assert_eq!(
+            ledger.execute_outcome(&Transaction::Chargeback(Chargeback::new(1, 1))),
+            Ok(TxOutcome::Deferred)
+        );
+        deposits(&mut ledger, [2, 3]);
+        ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+        let account = ledger.account(1).unwrap();
+        assert_eq!((account.held(), account.locked()), (dec!(10), false));
+        assert_eq!(ledger.causality_stats().expired, 1);
+        assert!(ledger.pending_lifecycle().is_empty());
+    }
+
+    #[test]
+    fn full_buffer_drops_oldest_pending() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut ledger = Ledger::builder()
+            .causality_mode(CausalityMode::BufferOutOfOrder {
+                window: 10,
+                capacity: 2,
+            })
+            .event_listener(move |event| {
+                recorded.lock().unwrap().push(event.clone());
+                Ok(())
+            })
+            .build();
+        deposits(&mut ledger, [1, 2, 3]);
+        for tx_id in [1, 2, 3] {
+            assert_eq!(
+                ledger.execute_outcome(&Transaction::Resolve(Resolve::new(1, tx_id))),
+                Ok(TxOutcome::Deferred)
+            );
+        }
+        let pending: Vec<_> = ledger
+            .pending_lifecycle()
+            .iter()
+            .map(|pending| pending.tx_id)
+            .collect();
+        assert_eq!(pending, [2, 3]);
+        assert_eq!(ledger.causality_stats().dropped, 1);
+        assert_eq!(
+            events.lock().unwrap()[..],
+            [LedgerEvent::ReorderDropped {
+                client_id: 1,
+                tx_id: 1,
+                kind: TxTypeTag::Resolve,
+            }]
+        );
+
+        // The dropped resolve is not applied when its dispute arrives
+        ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+        assert_eq!(ledger.account(1).unwrap().held(), dec!(10));
+    }
+
+    #[test]
+    fn default_mode_keeps_generic_rejections() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     resolve,1,1,\n\
+                     chargeback,1,1,\n\
+                     dispute,1,1,\n\
+                     resolve,1,1,\n\
+                     resolve,1,1,\n";
+        assert_eq!(
+            process_csv_str(input).unwrap(),
+            "client,available,held,total,locked\n1,10,0,10,false\n"
+        );
+
+        let mut ledger = Ledger::builder()
+            .duplicate_dispute_policy(DuplicateDisputePolicy::Error)
+            .build();
+        deposits(&mut ledger, [1]);
+        for tx in [
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ] {
+            assert_eq!(ledger.execute_outcome(&tx), Err(TxError::TxNotDisputed));
+        }
+        ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+        ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
+            .unwrap();
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        assert!(ledger.pending_lifecycle().is_empty());
+    }
+}
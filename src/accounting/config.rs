@@ -0,0 +1,602 @@
+#[cfg(feature = "digest")]
+use crate::accounting::digest::ExecutionDigest;
+use crate::accounting::events::{EventListener, LedgerEvent};
+use crate::accounting::journal::{Journal, JournalTarget};
+use crate::accounting::postings::{PostingRule, PostingRules};
+use crate::accounting::sampling::{LiabilitySampler, LiabilitySampling, SampleTarget};
+use crate::accounting::Ledger;
+use crate::amount_scale::MAX_SCALE;
+use crate::clock::SharedClock;
+use crate::core_types::ClientId;
+use crate::sink::{SinkError, SinkFailurePolicy};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Treatment of deposits arriving into a locked account.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LockPolicy {
+    /// Deposits are credited to available balance as for unlocked accounts.
+    #[default]
+    CreditAvailable,
+    /// Deposits are credited to held balance and can be released with `Resolve`
+    /// once the account is unlocked, or charged back.
+    HoldIncoming,
+}
+
+/// Treatment of a dispute, resolve or chargeback finding its deposit already in the
+/// state it would put it in, as when an upstream system retries a message.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DuplicateDisputePolicy {
+    /// The transaction is rejected as for any other deposit state.
+    #[default]
+    Error,
+    /// The transaction succeeds without changing anything, see `TxOutcome::Duplicate`.
+    Idempotent,
+}
+
+/// Treatment of an amount stated on a dispute, resolve or chargeback row, which the
+/// ledger otherwise takes from the recorded deposit. Rows without an amount are
+/// never checked.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DisputeAmountHandling {
+    /// The stated amount is not looked at.
+    #[default]
+    Ignore,
+    /// A stated amount differing from the deposit's is counted, see
+    /// `Ledger::dispute_amount_mismatches`, and reported as
+    /// `LedgerEvent::DisputedAmountMismatch`, while the transaction is applied.
+    WarnOnMismatch,
+    /// A stated amount differing from the deposit's rejects the transaction with
+    /// `TxError::DisputedAmountMismatch`.
+    RejectOnMismatch,
+}
+
+/// Reasons accepted on dispute and chargeback rows, see
+/// `LedgerBuilder::dispute_reason_allowlist`. Rows without a reason are never checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReasonAllowlist {
+    pub reasons: HashSet<String>,
+    pub action: UnknownReasonAction,
+}
+
+impl ReasonAllowlist {
+    pub(crate) fn allows(&self, reason: &str) -> bool {
+        self.reasons.contains(reason)
+    }
+}
+
+/// Treatment of a dispute or chargeback whose reason is not in the `ReasonAllowlist`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum UnknownReasonAction {
+    /// The transaction is rejected with `TxError::UnknownDisputeReason`.
+    #[default]
+    Reject,
+    /// The transaction is applied and keeps its reason, counted, see
+    /// `Ledger::unknown_dispute_reasons`, and reported as
+    /// `LedgerEvent::UnknownDisputeReason`.
+    Warn,
+}
+
+/// Treatment of a withdrawal, dispute, resolve or chargeback of a client without an
+/// account, see `LedgerBuilder::unknown_client_on_debit`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum UnknownClientOnDebit {
+    /// The transaction is rejected with `TxError::ClientAccountNotFound`.
+    #[default]
+    Error,
+    /// An account with zero balances is created for the client, see
+    /// `UserAccount::created_by_rejection`, and the transaction is still rejected: a
+    /// withdrawal with `TxError::InsufficientFunds` and a dispute, resolve or
+    /// chargeback with `TxError::OriginTxNotFound`. Nothing moves, so the account
+    /// only makes the client part of the report.
+    CreateEmpty,
+}
+
+/// Whether the ledger records deposits so that they can be disputed, see
+/// `LedgerBuilder::dispute_tracking`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DisputeTracking {
+    /// Every deposit is recorded, so the ledger takes memory per deposit.
+    #[default]
+    Full,
+    /// Deposits are not recorded, nor withdrawals without a reference, so the ledger
+    /// takes memory per account and per withdrawal with a reference, and disputes,
+    /// resolves, chargebacks and adjustments are rejected with
+    /// `TxError::DisputeTrackingDisabled`. Deposits and withdrawals reusing the tx id
+    /// of a transaction which was not recorded are not rejected with
+    /// `TxError::DuplicateTxId` either. For inputs of deposits and withdrawals only,
+    /// like settlement extracts.
+    Disabled,
+}
+
+/// Tracking of the order in which disputes, resolves and chargebacks of a deposit
+/// arrive, for upstream systems which may reorder them, see
+/// `LedgerBuilder::causality_mode`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CausalityMode {
+    /// Messages are not tracked and rejections stay generic.
+    #[default]
+    Off,
+    /// Every dispute, resolve and chargeback is remembered per tx id, even when
+    /// rejected, so that a resolve or chargeback failing with `TxError::TxNotDisputed`
+    /// is rejected with `TxError::ResolveBeforeDispute`,
+    /// `TxError::ChargebackBeforeDispute` or `TxError::DuplicateResolve` instead when
+    /// the messages seen before tell why.
+    Classify,
+    /// Like `Classify`, holding a resolve or chargeback which arrives before its
+    /// dispute in a pending buffer of up to `capacity` messages, see
+    /// `TxOutcome::Deferred`. It is applied right after the dispute when the dispute
+    /// arrives within the next `window` transactions and discarded otherwise. A full
+    /// buffer drops its oldest message, reported as `LedgerEvent::ReorderDropped`.
+    BufferOutOfOrder { window: u64, capacity: usize },
+}
+
+/// Split of the accounts into a hot and a cold tier, see `LedgerBuilder::account_tiering`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccountTiering {
+    /// Length in transactions of the generations at whose end accounts which were not
+    /// touched during the generation are demoted to the cold tier.
+    pub demote_after: u64,
+    /// Capacity the hot tier is allocated with and keeps when shrunk after demotion.
+    pub hot_capacity: usize,
+}
+
+/// Whether an executed chargeback locks the account, see
+/// `LedgerBuilder::chargeback_policy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ChargebackPolicy {
+    /// Every chargeback locks the account.
+    #[default]
+    LockAccount,
+    /// The account stays operational, as while a chargeback is contested in
+    /// pre-arbitration.
+    NoLock,
+    /// The chargeback taking the chargebacks of the account to N locks it, so that
+    /// e.g. only a second-cycle chargeback locks with N = 2.
+    LockAfterN(u32),
+}
+
+/// Deposits of a client which a `VelocityLimit` adds up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum WindowSpec {
+    /// The last deposits of the client, up to this many including the new one.
+    Transactions(usize),
+}
+
+impl WindowSpec {
+    /// Deposits kept per client to check the window, at least one.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            WindowSpec::Transactions(len) => (*len).max(1),
+        }
+    }
+}
+
+/// Treatment of a deposit exceeding the `VelocityLimit`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum VelocityAction {
+    /// The deposit is rejected with `TxError::VelocityLimitExceeded`.
+    #[default]
+    Reject,
+    /// The deposit is applied and reported as `LedgerEvent::VelocityFlagged`.
+    Flag,
+}
+
+/// Cap on the sum of the deposits of each client within a window, see
+/// `LedgerBuilder::velocity_limit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VelocityLimit {
+    /// Highest sum allowed, inclusive.
+    pub max_total: Decimal,
+    pub window: WindowSpec,
+    pub action: VelocityAction,
+}
+
+/// Cap on the held funds of a client which disputes may take it to, see
+/// `LedgerBuilder::dispute_limit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeLimit {
+    /// Highest held balance, inclusive.
+    Amount(Decimal),
+    /// Highest held balance as a percentage of the total of the account before the
+    /// dispute, inclusive, so that 25 allows a quarter of the funds to be held.
+    PercentOfTotal(Decimal),
+}
+
+impl DisputeLimit {
+    /// Highest held balance allowed for an account with `total` funds.
+    pub fn max_held(&self, total: Decimal) -> Decimal {
+        match self {
+            DisputeLimit::Amount(amount) => *amount,
+            DisputeLimit::PercentOfTotal(percent) => {
+                total.saturating_mul(*percent) / Decimal::ONE_HUNDRED
+            }
+        }
+    }
+}
+
+impl Display for DisputeLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputeLimit::Amount(amount) => write!(f, "{}", amount),
+            DisputeLimit::PercentOfTotal(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// Reads an amount, or a percentage with a `%` suffix.
+impl FromStr for DisputeLimit {
+    type Err = rust_decimal::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        match value.strip_suffix('%') {
+            Some(percent) => Ok(DisputeLimit::PercentOfTotal(percent.trim().parse()?)),
+            None => Ok(DisputeLimit::Amount(value.parse()?)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DisputeLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct LedgerConfig {
+    pub lock_policy: LockPolicy,
+    pub duplicate_dispute_policy: DuplicateDisputePolicy,
+    pub dispute_amount_handling: DisputeAmountHandling,
+    /// Handling of failures of the event listener.
+    pub event_failure_policy: SinkFailurePolicy,
+    /// Threshold for `Ledger::sweep_configured_dust`, which sweeps nothing when unset.
+    pub dust_threshold: Option<Decimal>,
+    /// Whether dust is swept from locked accounts too.
+    pub sweep_locked_dust: bool,
+    /// Lowest available balance a withdrawal may leave behind; negative values
+    /// allow an overdraft.
+    pub overdraft_limit: Decimal,
+    /// Limits of individual clients, overriding `overdraft_limit`.
+    pub overdraft_limits: HashMap<ClientId, Decimal>,
+    /// Hot and cold account tiers; all accounts are hot when unset.
+    pub account_tiering: Option<AccountTiering>,
+    /// Cap on the deposits of each client within a window; unlimited when unset.
+    pub velocity_limit: Option<VelocityLimit>,
+    pub chargeback_policy: ChargebackPolicy,
+    /// Available balance floors reported by `LedgerEvent::ThresholdBreached`, distinct
+    /// and sorted in descending order.
+    pub balance_thresholds: Vec<Decimal>,
+    /// Cap on the held funds disputes may take accounts to; unlimited when unset.
+    pub dispute_limit: Option<DisputeLimit>,
+    /// Caps of individual clients, overriding `dispute_limit`.
+    pub dispute_limits: HashMap<ClientId, DisputeLimit>,
+    /// Cap on the disputes each account may have open at once; unlimited when unset.
+    pub max_open_disputes_per_account: Option<u32>,
+    /// Reasons accepted on dispute and chargeback rows; any reason when unset.
+    pub dispute_reasons: Option<ReasonAllowlist>,
+    /// Age of the deposits which can still be disputed; any age when unset.
+    pub dispute_window: Option<Duration>,
+    /// Time of the ledger, see `clock`.
+    pub clock: SharedClock,
+    pub causality_mode: CausalityMode,
+    pub unknown_client_on_debit: UnknownClientOnDebit,
+    /// Rules deriving postings from applied transactions, see `accounting::postings`.
+    pub posting_rules: PostingRules,
+    /// Depth up to which derived postings are passed to the posting rules in turn;
+    /// they never are when 0.
+    pub rules_on_derived_postings: u32,
+    /// Decimals of the amounts in the account reports, which a snapshot must have
+    /// been taken under to be restored; reports write amounts as they are when unset.
+    pub amount_scale: Option<u32>,
+    /// Whether deposits resolved after a dispute are tombstoned like charged back
+    /// ones, see `LedgerBuilder::tombstone_resolved_deposits`.
+    pub tombstone_resolved: bool,
+    pub dispute_tracking: DisputeTracking,
+}
+
+impl LedgerConfig {
+    pub(crate) fn overdraft_limit_of(&self, client_id: ClientId) -> Decimal {
+        self.overdraft_limits
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.overdraft_limit)
+    }
+
+    pub(crate) fn dispute_limit_of(&self, client_id: ClientId) -> Option<DisputeLimit> {
+        self.dispute_limits
+            .get(&client_id)
+            .copied()
+            .or(self.dispute_limit)
+    }
+}
+
+#[derive(Default)]
+pub struct LedgerBuilder {
+    config: LedgerConfig,
+    journal: Option<JournalTarget>,
+    liability_sampling: Option<(LiabilitySampling, SampleTarget)>,
+    event_listener: Option<EventListener>,
+    #[cfg(feature = "digest")]
+    execution_digest: bool,
+}
+
+impl LedgerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lock_policy(mut self, lock_policy: LockPolicy) -> Self {
+        self.config.lock_policy = lock_policy;
+        self
+    }
+
+    pub fn duplicate_dispute_policy(mut self, policy: DuplicateDisputePolicy) -> Self {
+        self.config.duplicate_dispute_policy = policy;
+        self
+    }
+
+    pub fn dispute_amount_handling(mut self, handling: DisputeAmountHandling) -> Self {
+        self.config.dispute_amount_handling = handling;
+        self
+    }
+
+    /// Sweeps available balances below `threshold` when the ledger is prepared for
+    /// output, see `Ledger::sweep_configured_dust`.
+    pub fn dust_threshold(mut self, threshold: Decimal) -> Self {
+        self.config.dust_threshold = Some(threshold);
+        self
+    }
+
+    pub fn sweep_locked_dust(mut self, sweep_locked_dust: bool) -> Self {
+        self.config.sweep_locked_dust = sweep_locked_dust;
+        self
+    }
+
+    /// Sets the available balance floor of accounts without a limit of their own.
+    pub fn overdraft_limit(mut self, limit: Decimal) -> Self {
+        self.config.overdraft_limit = limit;
+        self
+    }
+
+    /// Sets the available balance floors of individual clients, see
+    /// `Ledger::set_overdraft_limit`.
+    pub fn overdraft_limits(mut self, limits: HashMap<ClientId, Decimal>) -> Self {
+        self.config.overdraft_limits = limits;
+        self
+    }
+
+    /// Rejects disputes which would take the held funds of accounts without a limit
+    /// of their own above `limit`, with `TxError::DisputeExposureLimitExceeded`.
+    /// Resolves and chargebacks are never limited, and free capacity by releasing
+    /// held funds.
+    pub fn dispute_limit(mut self, limit: DisputeLimit) -> Self {
+        self.config.dispute_limit = Some(limit);
+        self
+    }
+
+    /// Sets the dispute limits of individual clients, see `Ledger::set_dispute_limit`.
+    pub fn dispute_limits(mut self, limits: HashMap<ClientId, DisputeLimit>) -> Self {
+        self.config.dispute_limits = limits;
+        self
+    }
+
+    /// Rejects disputes of clients which already have `limit` open disputes, with
+    /// `TxError::TooManyOpenDisputes`. Deposits held on arrival are not disputes and
+    /// do not count. Resolves and chargebacks are never limited, and free capacity by
+    /// closing disputes.
+    pub fn max_open_disputes_per_account(mut self, limit: u32) -> Self {
+        self.config.max_open_disputes_per_account = Some(limit);
+        self
+    }
+
+    /// Checks the reasons of disputes and chargebacks against `allowlist`, rejecting or
+    /// reporting the others according to its `UnknownReasonAction`.
+    pub fn dispute_reason_allowlist(mut self, allowlist: ReasonAllowlist) -> Self {
+        self.config.dispute_reasons = Some(allowlist);
+        self
+    }
+
+    /// Rejects disputes of deposits recorded more than `window` ago by the clock of
+    /// the ledger, with `TxError::DisputeWindowExpired`. Resolves and chargebacks of
+    /// open disputes are never late.
+    pub fn dispute_window(mut self, window: Duration) -> Self {
+        self.config.dispute_window = Some(window);
+        self
+    }
+
+    /// Reads the time from `clock` instead of the `SystemClock`, e.g. a `ManualClock`
+    /// in tests.
+    pub fn clock(mut self, clock: impl Into<SharedClock>) -> Self {
+        self.config.clock = clock.into();
+        self
+    }
+
+    /// Creates empty accounts for the clients of withdrawals, disputes, resolves and
+    /// chargebacks rejected for lack of an account, see `UnknownClientOnDebit`, so that
+    /// the report lists every client the input refers to.
+    pub fn unknown_client_on_debit(mut self, policy: UnknownClientOnDebit) -> Self {
+        self.config.unknown_client_on_debit = policy;
+        self
+    }
+
+    /// Keeps accounts which are idle for `tiering.demote_after` transactions in a compact
+    /// cold tier, trading slower access to them for less memory. Cold accounts are
+    /// promoted back when a transaction touches them, so results are unchanged.
+    pub fn account_tiering(mut self, tiering: AccountTiering) -> Self {
+        self.config.account_tiering = Some(AccountTiering {
+            demote_after: tiering.demote_after.max(1),
+            ..tiering
+        });
+        self
+    }
+
+    /// Tracks the order of the lifecycle messages of deposits, see `CausalityMode`.
+    /// The pending buffer holds at least one message.
+    pub fn causality_mode(mut self, mode: CausalityMode) -> Self {
+        self.config.causality_mode = match mode {
+            CausalityMode::BufferOutOfOrder { window, capacity } => {
+                CausalityMode::BufferOutOfOrder {
+                    window,
+                    capacity: capacity.max(1),
+                }
+            }
+            mode => mode,
+        };
+        self
+    }
+
+    /// Caps the sum of the deposits of each client within `limit.window`. Each client
+    /// keeps the amounts of its last deposits up to the length of the window, which
+    /// is at least one deposit.
+    pub fn velocity_limit(mut self, limit: VelocityLimit) -> Self {
+        self.config.velocity_limit = Some(limit);
+        self
+    }
+
+    /// Sets when chargebacks lock the account. Locks are reported as
+    /// `LedgerEvent::AccountLocked` after the `LedgerEvent::ChargedBack` of the
+    /// chargeback.
+    pub fn chargeback_policy(mut self, policy: ChargebackPolicy) -> Self {
+        self.config.chargeback_policy = policy;
+        self
+    }
+
+    /// Reports transactions taking an available balance below any of `thresholds` as
+    /// `LedgerEvent::ThresholdBreached`, once per threshold until the balance recovers.
+    pub fn balance_thresholds(mut self, thresholds: impl IntoIterator<Item = Decimal>) -> Self {
+        let mut thresholds: Vec<_> = thresholds.into_iter().collect();
+        thresholds.sort_unstable_by(|a, b| b.cmp(a));
+        thresholds.dedup();
+        self.config.balance_thresholds = thresholds;
+        self
+    }
+
+    /// Executes the postings `rule` derives from every applied transaction right after
+    /// it, with synthetic tx ids from `DERIVED_TX_ID_START`, see `accounting::postings`.
+    /// Rules are asked in the order they were added.
+    pub fn posting_rule(mut self, rule: impl PostingRule + 'static) -> Self {
+        self.config.posting_rules.push(rule);
+        self
+    }
+
+    /// Passes derived postings up to `depth` to the posting rules too, so that the
+    /// postings derived from transactions of the input may trigger up to `depth` more
+    /// levels of postings. Derived postings trigger no rules by default.
+    pub fn rules_on_derived_postings(mut self, depth: u32) -> Self {
+        self.config.rules_on_derived_postings = depth;
+        self
+    }
+
+    /// Writes the amounts of the account reports with exactly `scale` decimals, the
+    /// scale the input was checked against with `IngestOptions::amount_scale`. The
+    /// ledger still computes with full precision. Panics beyond `amount_scale::MAX_SCALE`.
+    pub fn amount_scale(mut self, scale: u32) -> Self {
+        assert!(
+            scale <= MAX_SCALE,
+            "amount scale {} beyond {}",
+            scale,
+            MAX_SCALE
+        );
+        self.config.amount_scale = Some(scale);
+        self
+    }
+
+    /// Stops recording deposits under `DisputeTracking::Disabled`, for inputs without
+    /// disputes, resolves, chargebacks and adjustments, which are rejected.
+    /// Withdrawals with a reference are still recorded for their settlements, and so
+    /// are deposits held on arrival under `LockPolicy::HoldIncoming`, which are open
+    /// disputes.
+    pub fn dispute_tracking(mut self, tracking: DisputeTracking) -> Self {
+        self.config.dispute_tracking = tracking;
+        self
+    }
+
+    /// Rejects deposits reusing the tx id of a deposit resolved after a dispute with
+    /// `TxError::TxIdReuseAfterSettlement`, as deposits reusing the id of a charged
+    /// back one always are. Deposits which were never disputed are not affected.
+    pub fn tombstone_resolved_deposits(mut self, tombstone_resolved: bool) -> Self {
+        self.config.tombstone_resolved = tombstone_resolved;
+        self
+    }
+
+    /// Records the effect of every executed transaction in memory, see `Ledger::journal`.
+    pub fn journal(mut self) -> Self {
+        self.journal = Some(JournalTarget::Memory(Vec::new()));
+        self
+    }
+
+    /// Writes the effect of every executed transaction to `writer` as JSON lines.
+    pub fn journal_writer(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.journal = Some(JournalTarget::Writer(Box::new(writer)));
+        self
+    }
+
+    /// Samples the liabilities and the totals of the available and held balances every
+    /// `sampling.every_n_tx` executed transactions into memory, see
+    /// `Ledger::liability_samples`.
+    pub fn liability_sampling(mut self, sampling: LiabilitySampling) -> Self {
+        self.liability_sampling = Some((sampling, SampleTarget::Memory(Vec::new())));
+        self
+    }
+
+    /// Like `liability_sampling`, writing every sample to `writer` as CSV instead.
+    pub fn liability_sampling_writer(
+        mut self,
+        sampling: LiabilitySampling,
+        writer: impl Write + Send + 'static,
+    ) -> Self {
+        self.liability_sampling = Some((sampling, SampleTarget::writer(writer)));
+        self
+    }
+
+    /// Hashes every executed transaction and its result, see `Ledger::execution_digest`
+    /// and `accounting::digest`.
+    #[cfg(feature = "digest")]
+    pub fn execution_digest(mut self) -> Self {
+        self.execution_digest = true;
+        self
+    }
+
+    /// Reports changes to the dispute state of deposits, see `LedgerEvent`. The
+    /// listener is called after the change was applied, and its failures are handled
+    /// according to `event_failure_policy`.
+    pub fn event_listener(
+        mut self,
+        listener: impl FnMut(&LedgerEvent) -> Result<(), SinkError> + Send + 'static,
+    ) -> Self {
+        self.event_listener = Some(Box::new(listener));
+        self
+    }
+
+    pub fn event_failure_policy(mut self, policy: SinkFailurePolicy) -> Self {
+        self.config.event_failure_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Ledger {
+        let mut ledger = Ledger::with_config(self.config);
+        if let Some(target) = self.journal {
+            ledger.set_journal(Journal::new(target));
+        }
+        if let Some((sampling, target)) = self.liability_sampling {
+            ledger.set_liability_sampler(LiabilitySampler::new(sampling, target));
+        }
+        if let Some(listener) = self.event_listener {
+            ledger.set_event_listener(listener);
+        }
+        #[cfg(feature = "digest")]
+        if self.execution_digest {
+            ledger.set_execution_digest(ExecutionDigest::new());
+        }
+        ledger
+    }
+}
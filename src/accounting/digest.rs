@@ -0,0 +1,201 @@
+//! Digests for telling whether two runs behaved the same, e.g. on two machines or
+//! before and after a dependency upgrade.
+//!
+//! Both digests are the SHA-256, in lowercase hex, of a canonical UTF-8 encoding
+//! versioned by `DIGEST_VERSION`. A digest which changes along with its version
+//! may come from the encoding alone, while one which changes under the same
+//! version comes from a change in behavior. Version 1 is:
+//!
+//! * execution digest: the line `payments_engine/execution/v1`, followed by the line
+//!   `<tx>,<kind>,<client>,<amount>,<result>` for every transaction given to
+//!   `Ledger::execute` and its variants, in execution order. `kind` is the type as
+//!   written in the input, `amount` is the amount of the transaction or the one
+//!   stated by a dispute, resolve or chargeback, empty when there is none, and
+//!   `result` is `applied`, `duplicate`, `deferred` or the code of the `TxError`
+//! * state digest: the line `payments_engine/state/v1`, followed by the line
+//!   `<client>,<available>,<held>,<locked>` for every account in ascending client id
+//!
+//! Amounts and balances are written without trailing zeros, so `1.50` and `1.5` are
+//! the same, and every line ends with `\n`.
+#[cfg(feature = "digest")]
+use crate::accounting::{ExecutableTransaction, Ledger, TxError, TxOutcome};
+use serde::Serialize;
+#[cfg(feature = "digest")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "digest")]
+use std::fmt::Write;
+use std::fmt::{Display, Formatter};
+
+/// Version of the canonical encoding the digests are computed from.
+pub const DIGEST_VERSION: u32 = 1;
+
+/// Digest of a run or of the balances of a ledger, see the module documentation.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LedgerDigest {
+    pub digest_version: u32,
+    /// SHA-256 in lowercase hex.
+    pub digest: String,
+}
+
+/// Writes the digest in hex.
+impl Display for LedgerDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.digest)
+    }
+}
+
+#[cfg(feature = "digest")]
+#[derive(Clone)]
+struct CanonicalHasher {
+    hasher: Sha256,
+    /// Line being encoded, reused to avoid an allocation per line.
+    line: String,
+}
+
+#[cfg(feature = "digest")]
+impl CanonicalHasher {
+    fn new(domain: &str) -> Self {
+        let mut hasher = Self {
+            hasher: Sha256::new(),
+            line: String::new(),
+        };
+        hasher.line(format_args!(
+            "payments_engine/{}/v{}",
+            domain, DIGEST_VERSION
+        ));
+        hasher
+    }
+
+    fn line(&mut self, args: std::fmt::Arguments<'_>) {
+        self.line.clear();
+        self.line
+            .write_fmt(args)
+            .expect("formatting into a String does not fail");
+        self.line.push('\n');
+        self.hasher.update(self.line.as_bytes());
+    }
+
+    fn finish(self) -> LedgerDigest {
+        let digest =
+            self.hasher
+                .finalize()
+                .iter()
+                .fold(String::with_capacity(64), |mut hex, byte| {
+                    let _ = write!(hex, "{:02x}", byte);
+                    hex
+                });
+        LedgerDigest {
+            digest_version: DIGEST_VERSION,
+            digest,
+        }
+    }
+}
+
+/// Running execution digest of a ledger, see `LedgerBuilder::execution_digest`.
+#[cfg(feature = "digest")]
+#[derive(Clone)]
+pub(crate) struct ExecutionDigest(CanonicalHasher);
+
+#[cfg(feature = "digest")]
+impl ExecutionDigest {
+    pub(crate) fn new() -> Self {
+        ExecutionDigest(CanonicalHasher::new("execution"))
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        tx: &impl ExecutableTransaction,
+        result: &Result<TxOutcome, TxError>,
+    ) {
+        let amount = tx
+            .amount()
+            .or(tx.stated_amount())
+            .map(|amount| amount.normalize().to_string())
+            .unwrap_or_default();
+        let result = match result {
+            Ok(TxOutcome::Applied) => "applied",
+            Ok(TxOutcome::Duplicate) => "duplicate",
+            Ok(TxOutcome::Deferred) => "deferred",
+            Err(err) => err.code(),
+        };
+        self.0.line(format_args!(
+            "{},{},{},{},{}",
+            tx.tx_id(),
+            tx.kind(),
+            tx.client_id(),
+            amount,
+            result
+        ));
+    }
+}
+
+#[cfg(feature = "digest")]
+impl Ledger {
+    /// Digest of the transactions executed so far and their results, `None` unless
+    /// enabled with `LedgerBuilder::execution_digest`.
+    pub fn execution_digest(&self) -> Option<LedgerDigest> {
+        self.execution_digest
+            .as_ref()
+            .map(|digest| digest.0.clone().finish())
+    }
+
+    /// Digest of the balances and lock flags of every account, a cheaper comparison
+    /// of the end state only.
+    pub fn state_digest(&self) -> LedgerDigest {
+        let mut hasher = CanonicalHasher::new("state");
+        for user_account in self.view().accounts_sorted() {
+            hasher.line(format_args!(
+                "{},{},{},{}",
+                user_account.client_id(),
+                user_account.available().normalize(),
+                user_account.held().normalize(),
+                user_account.locked()
+            ));
+        }
+        hasher.finish()
+    }
+}
+
+#[cfg(all(test, feature = "digest"))]
+mod tests {
+    use crate::accounting::transactions::{Deposit, Dispute, Resolve, Transaction, Withdrawal};
+    use crate::accounting::Ledger;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn run(deposit: Decimal) -> Ledger {
+        let mut ledger = Ledger::builder().execution_digest().build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, deposit)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(4))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(2, 1)),
+        ] {
+            let _ = ledger.execute(&tx);
+        }
+        ledger
+    }
+
+    #[test]
+    fn digests_are_stable_and_follow_the_encoding() {
+        let ledger = run(dec!(10.50));
+        let digest = ledger.execution_digest().unwrap();
+        assert_eq!(digest.digest_version, 1);
+        assert_eq!(digest.digest.len(), 64);
+        assert_eq!(run(dec!(10.5)).execution_digest(), Some(digest.clone()));
+        // SHA-256 of the encoding spelled out in the module documentation
+        assert_eq!(
+            digest.digest,
+            "abfaadc65b6a03d40bee94b31c1ed0550f2097b3e6f0af9b4fbf97e965d9d661"
+        );
+        assert_eq!(ledger.state_digest(), run(dec!(10.5)).state_digest());
+        assert_eq!(Ledger::new().execution_digest(), None);
+    }
+
+    #[test]
+    fn digests_change_with_a_single_amount() {
+        let (ledger, changed) = (run(dec!(10)), run(dec!(10.01)));
+        assert_ne!(ledger.execution_digest(), changed.execution_digest());
+        assert_ne!(ledger.state_digest(), changed.state_digest());
+    }
+}
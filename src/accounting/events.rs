@@ -0,0 +1,156 @@
+use crate::accounting::postings::DerivedKind;
+use crate::accounting::reasons::DisputeReason;
+use crate::accounting::transactions::TxTypeTag;
+use crate::clock::SharedClock;
+use crate::core_types::{ClientId, TxId};
+use crate::sink::{deliver, Delivery, SinkError, SinkFailurePolicy};
+use rust_decimal::Decimal;
+
+/// Change to the dispute state of a deposit, an account locked by a chargeback, a
+/// deposit flagged by the velocity limit, a balance falling below a threshold, a
+/// disputed amount differing from the deposit's, a reason outside the allowlist, a
+/// reordered resolve or chargeback or a derived posting, reported to the listener set with
+/// `LedgerBuilder::event_listener`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum LedgerEvent {
+    /// An executed `Dispute` moved `amount` to held funds. `reason` is the one the
+    /// deposit was first disputed for, if any.
+    Disputed {
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+        reason: Option<DisputeReason>,
+    },
+    /// An executed `Resolve` released `amount` from held funds.
+    Resolved {
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+    },
+    /// An executed `Chargeback` withdrew the held `amount`. Whether it locked the
+    /// account is reported as `AccountLocked` right after. `reason` is the one of the
+    /// chargeback, or else of the dispute.
+    ChargedBack {
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+        reason: Option<DisputeReason>,
+    },
+    /// The chargeback `tx_id` locked the account, according to the `ChargebackPolicy`.
+    AccountLocked { client_id: ClientId, tx_id: TxId },
+    /// A stale dispute was resolved by `Ledger::expire_disputes`, releasing `amount`.
+    AutoResolved {
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+    },
+    /// A deposit took the deposits within the window of the `VelocityLimit` to
+    /// `window_total`, above the limit, and was applied under `VelocityAction::Flag`.
+    VelocityFlagged {
+        client_id: ClientId,
+        tx_id: TxId,
+        window_total: Decimal,
+    },
+    /// A transaction took the available balance to `available`, below `threshold` of
+    /// `LedgerBuilder::balance_thresholds`. Reported again only after the balance
+    /// recovered to the threshold or above.
+    ThresholdBreached {
+        client_id: ClientId,
+        threshold: Decimal,
+        available: Decimal,
+    },
+    /// A dispute, resolve or chargeback row stated `provided` as the amount of the
+    /// deposit `tx_id`, which recorded `expected`, and was applied under
+    /// `DisputeAmountHandling::WarnOnMismatch`.
+    DisputedAmountMismatch {
+        client_id: ClientId,
+        tx_id: TxId,
+        expected: Decimal,
+        provided: Decimal,
+    },
+    /// A dispute or chargeback of `tx_id` gave a `reason` outside the
+    /// `ReasonAllowlist`, and was applied under `UnknownReasonAction::Warn`.
+    UnknownDisputeReason {
+        client_id: ClientId,
+        tx_id: TxId,
+        kind: TxTypeTag,
+        reason: DisputeReason,
+    },
+    /// A resolve or chargeback deferred under `CausalityMode::BufferOutOfOrder` was
+    /// applied right after the dispute of its deposit, `delay` transactions after it
+    /// arrived. The change it made is reported by its own event before.
+    ReorderedApplied {
+        client_id: ClientId,
+        tx_id: TxId,
+        kind: TxTypeTag,
+        delay: u64,
+    },
+    /// A deferred resolve or chargeback was dropped from the full pending buffer to
+    /// make room for a newer one, and will not be applied.
+    ReorderDropped {
+        client_id: ClientId,
+        tx_id: TxId,
+        kind: TxTypeTag,
+    },
+    /// The posting rule `rule` derived a posting of `amount` from the transaction
+    /// `cause`, which was executed with the synthetic tx id `tx_id`, see
+    /// `accounting::postings`.
+    DerivedPosted {
+        client_id: ClientId,
+        tx_id: TxId,
+        cause: TxId,
+        rule: &'static str,
+        kind: DerivedKind,
+        amount: Decimal,
+    },
+}
+
+pub(crate) type EventListener = Box<dyn FnMut(&LedgerEvent) -> Result<(), SinkError> + Send>;
+
+/// The listener of a ledger with the outcome of its failures.
+#[derive(Default)]
+pub(crate) struct EventSink {
+    listener: Option<EventListener>,
+    policy: SinkFailurePolicy,
+    /// Clock waited on between retries.
+    clock: SharedClock,
+    dropped: u64,
+    /// Failure which should abort the run, until taken.
+    error: Option<SinkError>,
+}
+
+impl EventSink {
+    pub(crate) fn new(policy: SinkFailurePolicy, clock: SharedClock) -> Self {
+        Self {
+            policy,
+            clock,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn set_listener(&mut self, listener: EventListener) {
+        self.listener = Some(listener);
+    }
+
+    pub(crate) fn emit(&mut self, event: LedgerEvent) {
+        let Some(listener) = self.listener.as_mut() else {
+            return;
+        };
+        match deliver(self.policy, &*self.clock, || listener(&event)) {
+            Ok(Delivery::Delivered) => {}
+            Ok(Delivery::Dropped) => self.dropped += 1,
+            Err(err) => {
+                self.error.get_or_insert(err);
+            }
+        }
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<SinkError> {
+        self.error.take()
+    }
+}
@@ -1,7 +1,16 @@
-use crate::accounting::Ledger;
+use crate::accounting::reasons::DisputeReason;
+use crate::accounting::transactions::TxTypeTag;
+use crate::accounting::{Ledger, TxState};
+use crate::core_types::{ClientId, TxId};
 use enum_dispatch::enum_dispatch;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum TxError {
     ClientAccountLocked,
     InsufficientFunds,
@@ -9,9 +18,418 @@ pub enum TxError {
     OriginTxNotFound,
     TxAlreadyDisputed,
     TxNotDisputed,
+    /// Deposit was charged back, so it cannot be adjusted.
+    TxChargedBack,
+    /// Deposit was reversed by an adjustment, so it cannot be disputed or adjusted again.
+    TxReversed,
+    /// The referenced transaction is not a deposit, the only kind which can be
+    /// disputed or adjusted.
+    TxNotDisputable,
+    /// Held balance is lower than the disputed amount about to be released from it,
+    /// which indicates an internal bug or corrupted state.
+    HeldBalanceInconsistent {
+        expected: Decimal,
+        actual: Decimal,
+    },
+    /// A balance would exceed the range or precision of `Decimal`.
+    BalanceOverflow,
+    /// No `WithdrawAuth` of the client with this tx id was executed.
+    HoldNotFound,
+    /// The withdrawal hold was already captured or released, or an authorization
+    /// reuses the tx id of a captured or released one.
+    HoldAlreadySettled,
+    /// The deposit would take the client's deposits within the window of the
+    /// `VelocityLimit` above its maximum.
+    VelocityLimitExceeded,
+    /// The dispute would take the held funds of the client from `current_held` above
+    /// `limit`, the highest held balance its `DisputeLimit` allows.
+    DisputeExposureLimitExceeded {
+        current_held: Decimal,
+        limit: Decimal,
+    },
+    /// The client already has `open` disputes, the most its
+    /// `LedgerBuilder::max_open_disputes_per_account` allows.
+    TooManyOpenDisputes {
+        open: u32,
+        limit: u32,
+    },
+    /// The amount stated on a dispute, resolve or chargeback row differs from the
+    /// `expected` amount of the deposit, under `DisputeAmountHandling::RejectOnMismatch`.
+    DisputedAmountMismatch {
+        expected: Decimal,
+        provided: Decimal,
+    },
+    /// The reason of a dispute or chargeback is not in the `ReasonAllowlist`, under
+    /// `UnknownReasonAction::Reject`.
+    UnknownDisputeReason,
+    /// The deposit was recorded longer ago than the dispute window, see
+    /// `LedgerBuilder::dispute_window`.
+    DisputeWindowExpired,
+    /// No withdrawal of the client with this tx id was executed.
+    WithdrawalNotFound,
+    /// The withdrawal was already settled.
+    WithdrawalAlreadySettled,
+    /// The settled transaction is not a withdrawal.
+    TxNotWithdrawal,
+    /// The deposit is not disputed and no dispute of it was seen yet, under
+    /// `CausalityMode::Classify` or `CausalityMode::BufferOutOfOrder`.
+    ResolveBeforeDispute,
+    /// Like `ResolveBeforeDispute`, for a chargeback.
+    ChargebackBeforeDispute,
+    /// The deposit is not disputed and was resolved after its dispute already, under
+    /// `CausalityMode::Classify` or `CausalityMode::BufferOutOfOrder`.
+    DuplicateResolve,
+    /// A deposit, withdrawal or withdrawal authorization uses a tx id from the range
+    /// reserved for derived postings, while posting rules are configured, see
+    /// `accounting::postings`.
+    ReservedTxId,
+    /// A dispute, resolve, chargeback or adjustment under `DisputeTracking::Disabled`,
+    /// which does not record the deposits they refer to.
+    DisputeTrackingDisabled,
+    /// A deposit reuses the tx id of a deposit which was charged back, or resolved
+    /// under `LedgerBuilder::tombstone_resolved_deposits`, whether the record of that
+    /// deposit is kept or was pruned. `prior_state` is the state it was settled in.
+    TxIdReuseAfterSettlement {
+        tx_id: TxId,
+        prior_state: TxState,
+    },
+    /// A deposit or withdrawal reuses the tx id of a transaction whose record the
+    /// ledger still keeps, of this client or another one, a withdrawal the tx id of a
+    /// tombstone, or a withdrawal authorization the tx id of a hold which is still
+    /// open.
+    DuplicateTxId,
+    /// Execution panicked, possibly after applying the transaction partially, see
+    /// `Ledger::execute_catch_unwind`.
+    InternalPanic {
+        message: String,
+    },
+}
+
+impl TxError {
+    /// Stable snake_case code identifying the error in reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TxError::ClientAccountLocked => "client_account_locked",
+            TxError::InsufficientFunds => "insufficient_funds",
+            TxError::ClientAccountNotFound => "client_account_not_found",
+            TxError::OriginTxNotFound => "origin_tx_not_found",
+            TxError::TxAlreadyDisputed => "tx_already_disputed",
+            TxError::TxNotDisputed => "tx_not_disputed",
+            TxError::TxChargedBack => "tx_charged_back",
+            TxError::TxReversed => "tx_reversed",
+            TxError::TxNotDisputable => "tx_not_disputable",
+            TxError::HeldBalanceInconsistent { .. } => "held_balance_inconsistent",
+            TxError::BalanceOverflow => "balance_overflow",
+            TxError::HoldNotFound => "hold_not_found",
+            TxError::HoldAlreadySettled => "hold_already_settled",
+            TxError::VelocityLimitExceeded => "velocity_limit_exceeded",
+            TxError::DisputeExposureLimitExceeded { .. } => "dispute_exposure_limit_exceeded",
+            TxError::TooManyOpenDisputes { .. } => "too_many_open_disputes",
+            TxError::DisputedAmountMismatch { .. } => "disputed_amount_mismatch",
+            TxError::UnknownDisputeReason => "unknown_dispute_reason",
+            TxError::DisputeWindowExpired => "dispute_window_expired",
+            TxError::WithdrawalNotFound => "withdrawal_not_found",
+            TxError::WithdrawalAlreadySettled => "withdrawal_already_settled",
+            TxError::TxNotWithdrawal => "tx_not_withdrawal",
+            TxError::ResolveBeforeDispute => "resolve_before_dispute",
+            TxError::ChargebackBeforeDispute => "chargeback_before_dispute",
+            TxError::DuplicateResolve => "duplicate_resolve",
+            TxError::ReservedTxId => "reserved_tx_id",
+            TxError::DisputeTrackingDisabled => "dispute_tracking_disabled",
+            TxError::TxIdReuseAfterSettlement { .. } => "tx_id_reuse_after_settlement",
+            TxError::DuplicateTxId => "duplicate_tx_id",
+            TxError::InternalPanic { .. } => "internal_panic",
+        }
+    }
+}
+
+impl Display for TxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl std::error::Error for TxError {}
+
+/// Variant fields are not part of the code, so they are zeroed when parsed.
+impl FromStr for TxError {
+    type Err = UnknownErrorCode;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code {
+            "client_account_locked" => Ok(TxError::ClientAccountLocked),
+            "insufficient_funds" => Ok(TxError::InsufficientFunds),
+            "client_account_not_found" => Ok(TxError::ClientAccountNotFound),
+            "origin_tx_not_found" => Ok(TxError::OriginTxNotFound),
+            "tx_already_disputed" => Ok(TxError::TxAlreadyDisputed),
+            "tx_not_disputed" => Ok(TxError::TxNotDisputed),
+            "tx_charged_back" => Ok(TxError::TxChargedBack),
+            "tx_reversed" => Ok(TxError::TxReversed),
+            "tx_not_disputable" => Ok(TxError::TxNotDisputable),
+            "held_balance_inconsistent" => Ok(TxError::HeldBalanceInconsistent {
+                expected: Decimal::ZERO,
+                actual: Decimal::ZERO,
+            }),
+            "balance_overflow" => Ok(TxError::BalanceOverflow),
+            "hold_not_found" => Ok(TxError::HoldNotFound),
+            "hold_already_settled" => Ok(TxError::HoldAlreadySettled),
+            "velocity_limit_exceeded" => Ok(TxError::VelocityLimitExceeded),
+            "dispute_exposure_limit_exceeded" => Ok(TxError::DisputeExposureLimitExceeded {
+                current_held: Decimal::ZERO,
+                limit: Decimal::ZERO,
+            }),
+            "too_many_open_disputes" => Ok(TxError::TooManyOpenDisputes { open: 0, limit: 0 }),
+            "disputed_amount_mismatch" => Ok(TxError::DisputedAmountMismatch {
+                expected: Decimal::ZERO,
+                provided: Decimal::ZERO,
+            }),
+            "unknown_dispute_reason" => Ok(TxError::UnknownDisputeReason),
+            "dispute_window_expired" => Ok(TxError::DisputeWindowExpired),
+            "withdrawal_not_found" => Ok(TxError::WithdrawalNotFound),
+            "withdrawal_already_settled" => Ok(TxError::WithdrawalAlreadySettled),
+            "tx_not_withdrawal" => Ok(TxError::TxNotWithdrawal),
+            "resolve_before_dispute" => Ok(TxError::ResolveBeforeDispute),
+            "chargeback_before_dispute" => Ok(TxError::ChargebackBeforeDispute),
+            "duplicate_resolve" => Ok(TxError::DuplicateResolve),
+            "reserved_tx_id" => Ok(TxError::ReservedTxId),
+            "dispute_tracking_disabled" => Ok(TxError::DisputeTrackingDisabled),
+            "tx_id_reuse_after_settlement" => Ok(TxError::TxIdReuseAfterSettlement {
+                tx_id: 0,
+                prior_state: TxState::ChargedBack,
+            }),
+            "duplicate_tx_id" => Ok(TxError::DuplicateTxId),
+            "internal_panic" => Ok(TxError::InternalPanic {
+                message: String::new(),
+            }),
+            _ => Err(UnknownErrorCode(code.to_string())),
+        }
+    }
+}
+
+impl Serialize for TxError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for TxError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Returned when parsing an error from a code which does not belong to any variant.
+#[derive(Debug, PartialEq)]
+pub struct UnknownErrorCode(pub String);
+
+impl Display for UnknownErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown error code: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownErrorCode {}
+
+/// Message of a panic caught with `catch_unwind`, empty when the payload is not a string.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
 }
 
 #[enum_dispatch]
 pub trait ExecutableTransaction {
     fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError>;
+
+    fn client_id(&self) -> ClientId;
+
+    fn tx_id(&self) -> TxId;
+
+    fn kind(&self) -> TxTypeTag;
+
+    /// Amount of deposits, withdrawals and withdrawal authorizations, which are the
+    /// only transactions carrying one.
+    fn amount(&self) -> Option<Decimal> {
+        None
+    }
+
+    /// Amount of the deposit stated by a dispute, resolve or chargeback, see
+    /// `DisputeAmountHandling`.
+    fn stated_amount(&self) -> Option<Decimal> {
+        None
+    }
+
+    /// Reason of a dispute or chargeback, see `Dispute::with_reason`.
+    fn reason(&self) -> Option<&DisputeReason> {
+        None
+    }
+
+    /// Tx id of the transaction a derived posting was derived from, see
+    /// `accounting::postings`.
+    fn cause(&self) -> Option<TxId> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_tx_errors() -> Vec<TxError> {
+        let all = vec![
+            TxError::ClientAccountLocked,
+            TxError::InsufficientFunds,
+            TxError::ClientAccountNotFound,
+            TxError::OriginTxNotFound,
+            TxError::TxAlreadyDisputed,
+            TxError::TxNotDisputed,
+            TxError::TxChargedBack,
+            TxError::TxReversed,
+            TxError::TxNotDisputable,
+            TxError::HeldBalanceInconsistent {
+                expected: Decimal::ONE,
+                actual: Decimal::ZERO,
+            },
+            TxError::BalanceOverflow,
+            TxError::HoldNotFound,
+            TxError::HoldAlreadySettled,
+            TxError::VelocityLimitExceeded,
+            TxError::DisputeExposureLimitExceeded {
+                current_held: Decimal::TEN,
+                limit: Decimal::TEN,
+            },
+            TxError::TooManyOpenDisputes { open: 3, limit: 3 },
+            TxError::DisputedAmountMismatch {
+                expected: Decimal::TEN,
+                provided: Decimal::ONE,
+            },
+            TxError::UnknownDisputeReason,
+            TxError::DisputeWindowExpired,
+            TxError::WithdrawalNotFound,
+            TxError::WithdrawalAlreadySettled,
+            TxError::TxNotWithdrawal,
+            TxError::ResolveBeforeDispute,
+            TxError::ChargebackBeforeDispute,
+            TxError::DuplicateResolve,
+            TxError::ReservedTxId,
+            TxError::DisputeTrackingDisabled,
+            TxError::TxIdReuseAfterSettlement {
+                tx_id: 7,
+                prior_state: TxState::Resolved,
+            },
+            TxError::DuplicateTxId,
+            TxError::InternalPanic {
+                message: "boom".to_string(),
+            },
+        ];
+        // Fails to compile when a variant is added without being listed above
+        for error in &all {
+            match error {
+                TxError::ClientAccountLocked
+                | TxError::InsufficientFunds
+                | TxError::ClientAccountNotFound
+                | TxError::OriginTxNotFound
+                | TxError::TxAlreadyDisputed
+                | TxError::TxNotDisputed
+                | TxError::TxChargedBack
+                | TxError::TxReversed
+                | TxError::TxNotDisputable
+                | TxError::HeldBalanceInconsistent { .. }
+                | TxError::BalanceOverflow
+                | TxError::HoldNotFound
+                | TxError::HoldAlreadySettled
+                | TxError::VelocityLimitExceeded
+                | TxError::DisputeExposureLimitExceeded { .. }
+                | TxError::TooManyOpenDisputes { .. }
+                | TxError::DisputedAmountMismatch { .. }
+                | TxError::UnknownDisputeReason
+                | TxError::DisputeWindowExpired
+                | TxError::WithdrawalNotFound
+                | TxError::WithdrawalAlreadySettled
+                | TxError::TxNotWithdrawal
+                | TxError::ResolveBeforeDispute
+                | TxError::ChargebackBeforeDispute
+                | TxError::DuplicateResolve
+                | TxError::ReservedTxId
+                | TxError::DisputeTrackingDisabled
+                | TxError::TxIdReuseAfterSettlement { .. }
+                | TxError::DuplicateTxId
+                | TxError::InternalPanic { .. } => {}
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn codes_are_stable() {
+        assert_eq!(TxError::InsufficientFunds.code(), "insufficient_funds");
+        assert_eq!(TxError::ClientAccountLocked.code(), "client_account_locked");
+        assert_eq!(TxError::OriginTxNotFound.code(), "origin_tx_not_found");
+        assert_eq!(TxError::TxAlreadyDisputed.code(), "tx_already_disputed");
+        assert_eq!(TxError::TxNotDisputed.code(), "tx_not_disputed");
+        assert_eq!(TxError::TxChargedBack.code(), "tx_charged_back");
+        assert_eq!(TxError::TxReversed.code(), "tx_reversed");
+        assert_eq!(TxError::BalanceOverflow.code(), "balance_overflow");
+        assert_eq!(
+            TxError::ClientAccountNotFound.code(),
+            "client_account_not_found"
+        );
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let mut codes = all_tx_errors()
+            .iter()
+            .map(TxError::code)
+            .collect::<Vec<_>>();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), all_tx_errors().len());
+    }
+
+    #[test]
+    fn code_round_trip() {
+        for error in all_tx_errors() {
+            let code = error.to_string();
+            assert_eq!(code, error.code());
+            assert_eq!(code.parse::<TxError>().unwrap().to_string(), code);
+        }
+        assert_eq!(
+            "not_a_code".parse::<TxError>(),
+            Err(UnknownErrorCode("not_a_code".to_string()))
+        );
+    }
+
+    #[test]
+    fn unit_variants_parse_back_equal() {
+        for error in all_tx_errors() {
+            if !matches!(
+                error,
+                TxError::HeldBalanceInconsistent { .. }
+                    | TxError::DisputeExposureLimitExceeded { .. }
+                    | TxError::TooManyOpenDisputes { .. }
+                    | TxError::DisputedAmountMismatch { .. }
+                    | TxError::TxIdReuseAfterSettlement { .. }
+                    | TxError::InternalPanic { .. }
+            ) {
+                assert_eq!(error.code().parse::<TxError>(), Ok(error));
+            }
+        }
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        for error in all_tx_errors() {
+            let json = serde_json::to_string(&error).unwrap();
+            assert_eq!(json, format!("\"{}\"", error.code()));
+            assert_eq!(
+                serde_json::from_str::<TxError>(&json).unwrap().code(),
+                error.code()
+            );
+        }
+        assert!(serde_json::from_str::<TxError>("\"not_a_code\"").is_err());
+    }
 }
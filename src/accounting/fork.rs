@@ -0,0 +1,442 @@
+//! What-if analysis on a ledger: a fork executes transactions on private copies of
+//! the state they touch, and reports how the accounts would change without changing
+//! the ledger it was forked from.
+use crate::accounting::transactions::Transaction;
+use crate::accounting::{
+    AccountView, ExecutableTransaction, Ledger, TxError, TxOutcome, TxRecord, TxState, UserAccount,
+    WithdrawalHold,
+};
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use std::collections::{HashSet, VecDeque};
+
+/// Copy-on-write overlay of a ledger or of another fork, its parent, made by
+/// `Ledger::fork` and `LedgerFork::fork`.
+///
+/// The fork borrows its parent for as long as it lives, so the parent cannot be
+/// changed under it. A transaction executed on the fork copies the account of its
+/// client and the record of its tx from the parent the first time either is touched,
+/// and changes only the copies. Everything else is read from the parent. Events are
+/// not reported and nothing is journaled.
+pub struct LedgerFork<'a> {
+    parent: Parent<'a>,
+    /// Copies of the state of `clients` and `txs`, along with the balances of the
+    /// whole ledger, which are copied when forking.
+    overlay: Ledger,
+    /// Clients whose account, open disputes and velocity window were copied, whether
+    /// the parent had them or not.
+    clients: HashSet<ClientId>,
+    /// Transactions whose record and withdrawal hold were copied.
+    txs: HashSet<TxId>,
+    accounts_copied: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Parent<'a> {
+    Ledger(&'a Ledger),
+    Fork(&'a LedgerFork<'a>),
+}
+
+impl<'a> Parent<'a> {
+    fn account(self, client_id: ClientId) -> Option<&'a UserAccount> {
+        match self {
+            Parent::Ledger(ledger) => ledger.accounts.get(&client_id),
+            Parent::Fork(fork) => fork.account(client_id),
+        }
+    }
+
+    fn tx_record(self, tx_id: TxId) -> Option<&'a TxRecord> {
+        match self {
+            Parent::Ledger(ledger) => ledger.tx_states.get(&tx_id),
+            Parent::Fork(fork) => fork.tx_record(tx_id),
+        }
+    }
+
+    fn open_disputes(self, client_id: ClientId) -> Option<&'a HashSet<TxId>> {
+        match self {
+            Parent::Ledger(ledger) => ledger.open_disputes.get(&client_id),
+            Parent::Fork(fork) => fork.open_disputes(client_id),
+        }
+    }
+
+    fn withdrawal_hold(self, tx_id: TxId) -> Option<&'a WithdrawalHold> {
+        match self {
+            Parent::Ledger(ledger) => ledger.withdrawal_holds.get(&tx_id),
+            Parent::Fork(fork) => fork.withdrawal_hold(tx_id),
+        }
+    }
+
+    fn tombstone(self, tx_id: TxId) -> Option<TxState> {
+        match self {
+            Parent::Ledger(ledger) => ledger.tombstones.get(&tx_id).copied(),
+            Parent::Fork(fork) => fork.tombstone(tx_id),
+        }
+    }
+
+    fn velocity_window(self, client_id: ClientId) -> Option<&'a VecDeque<Decimal>> {
+        match self {
+            Parent::Ledger(ledger) => ledger.velocity.window(client_id),
+            Parent::Fork(fork) => fork.velocity_window(client_id),
+        }
+    }
+}
+
+/// Account changed by a fork, as it was in the parent and as it is in the fork.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDiff {
+    pub client_id: ClientId,
+    /// `None` when the fork created the account.
+    pub before: Option<AccountView>,
+    pub after: AccountView,
+}
+
+impl AccountDiff {
+    pub fn available_change(&self) -> Decimal {
+        self.after.available
+            - self
+                .before
+                .as_ref()
+                .map_or(Decimal::ZERO, |view| view.available)
+    }
+
+    pub fn held_change(&self) -> Decimal {
+        self.after.held - self.before.as_ref().map_or(Decimal::ZERO, |view| view.held)
+    }
+}
+
+/// Outcome of `Ledger::simulate`.
+#[derive(Debug, PartialEq)]
+pub struct SimulationResult {
+    /// Result of each transaction, in the order given.
+    pub results: Vec<Result<TxOutcome, TxError>>,
+    pub diff: Vec<AccountDiff>,
+}
+
+impl<'a> LedgerFork<'a> {
+    /// Fork of `parent`, whose ledger-wide balances are the ones of `base`.
+    fn new(parent: Parent<'a>, base: &Ledger) -> Self {
+        let mut config = base.config.clone();
+        config.account_tiering = None;
+        let mut overlay = Ledger::with_config(config);
+        overlay.liabilities = base.liabilities.clone();
+        overlay.total_available = base.total_available;
+        overlay.total_held = base.total_held;
+        overlay.dust = base.dust.clone();
+        overlay.applied_offset = base.applied_offset;
+        overlay.poisoned = base.poisoned;
+        overlay.tx_seq = base.tx_seq;
+        overlay.derived_postings = base.derived_postings;
+        Self {
+            parent,
+            overlay,
+            clients: HashSet::new(),
+            txs: HashSet::new(),
+            accounts_copied: 0,
+        }
+    }
+
+    pub fn execute(&mut self, tx: &impl ExecutableTransaction) -> Result<(), TxError> {
+        self.execute_outcome(tx).map(|_outcome| ())
+    }
+
+    /// Like `Ledger::execute_outcome`, changing only the fork.
+    pub fn execute_outcome(
+        &mut self,
+        tx: &impl ExecutableTransaction,
+    ) -> Result<TxOutcome, TxError> {
+        self.copy_client(tx.client_id());
+        self.copy_tx(tx.tx_id());
+        self.overlay.execute_outcome(tx)
+    }
+
+    /// Fork of this fork, which sees the changes made here so far.
+    pub fn fork(&self) -> LedgerFork<'_> {
+        LedgerFork::new(Parent::Fork(self), &self.overlay)
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<&UserAccount> {
+        match self.clients.contains(&client_id) {
+            true => self.overlay.accounts.get(&client_id),
+            false => self.parent.account(client_id),
+        }
+    }
+
+    pub fn tx_record(&self, tx_id: TxId) -> Option<&TxRecord> {
+        match self.txs.contains(&tx_id) {
+            true => self.overlay.tx_states.get(&tx_id),
+            false => self.parent.tx_record(tx_id),
+        }
+    }
+
+    /// Number of accounts copied from the parent; accounts created by the fork are
+    /// not counted.
+    pub fn accounts_copied(&self) -> usize {
+        self.accounts_copied
+    }
+
+    /// Clients whose account differs from the one of the parent, sorted by client id.
+    pub fn changed_accounts(&self) -> Vec<ClientId> {
+        self.diff()
+            .into_iter()
+            .map(|account_diff| account_diff.client_id)
+            .collect()
+    }
+
+    /// Accounts which differ from the ones of the parent, sorted by client id.
+    pub fn diff(&self) -> Vec<AccountDiff> {
+        let mut diff: Vec<_> = self
+            .clients
+            .iter()
+            .filter_map(|client_id| {
+                let after = AccountView::from(self.overlay.accounts.get(client_id)?);
+                let before = self.parent.account(*client_id).map(AccountView::from);
+                (before.as_ref() != Some(&after)).then_some(AccountDiff {
+                    client_id: *client_id,
+                    before,
+                    after,
+                })
+            })
+            .collect();
+        diff.sort_unstable_by_key(|account_diff| account_diff.client_id);
+        diff
+    }
+
+    fn open_disputes(&self, client_id: ClientId) -> Option<&HashSet<TxId>> {
+        match self.clients.contains(&client_id) {
+            true => self.overlay.open_disputes.get(&client_id),
+            false => self.parent.open_disputes(client_id),
+        }
+    }
+
+    fn withdrawal_hold(&self, tx_id: TxId) -> Option<&WithdrawalHold> {
+        match self.txs.contains(&tx_id) {
+            true => self.overlay.withdrawal_holds.get(&tx_id),
+            false => self.parent.withdrawal_hold(tx_id),
+        }
+    }
+
+    fn tombstone(&self, tx_id: TxId) -> Option<TxState> {
+        match self.txs.contains(&tx_id) {
+            true => self.overlay.tombstones.get(&tx_id).copied(),
+            false => self.parent.tombstone(tx_id),
+        }
+    }
+
+    fn velocity_window(&self, client_id: ClientId) -> Option<&VecDeque<Decimal>> {
+        match self.clients.contains(&client_id) {
+            true => self.overlay.velocity.window(client_id),
+            false => self.parent.velocity_window(client_id),
+        }
+    }
+
+    /// Copies the state of `client_id` from the parent the first time it is touched.
+    fn copy_client(&mut self, client_id: ClientId) {
+        if !self.clients.insert(client_id) {
+            return;
+        }
+        if let Some(user_account) = self.parent.account(client_id) {
+            self.overlay
+                .accounts
+                .get_or_insert_with(client_id, || user_account.clone());
+            self.accounts_copied += 1;
+        }
+        if let Some(tx_ids) = self.parent.open_disputes(client_id) {
+            self.overlay.open_disputes.insert(client_id, tx_ids.clone());
+        }
+        if let Some(window) = self.parent.velocity_window(client_id) {
+            self.overlay
+                .velocity
+                .insert_window(client_id, window.clone());
+        }
+    }
+
+    /// Copies the state of `tx_id` from the parent the first time it is touched.
+    fn copy_tx(&mut self, tx_id: TxId) {
+        if !self.txs.insert(tx_id) {
+            return;
+        }
+        if let Some(record) = self.parent.tx_record(tx_id) {
+            self.overlay.tx_states.insert(tx_id, record.clone());
+        }
+        if let Some(hold) = self.parent.withdrawal_hold(tx_id) {
+            self.overlay.withdrawal_holds.insert(tx_id, hold.clone());
+        }
+        if let Some(state) = self.parent.tombstone(tx_id) {
+            self.overlay.tombstones.insert(tx_id, state);
+        }
+    }
+}
+
+impl Ledger {
+    /// Fork for what-if analysis, see `LedgerFork`. The ledger cannot be changed
+    /// while the fork lives.
+    pub fn fork(&self) -> LedgerFork<'_> {
+        LedgerFork::new(Parent::Ledger(self), self)
+    }
+
+    /// Executes `txs` on a fork, returning the result of each and how the accounts
+    /// would change, while the ledger stays as it is.
+    pub fn simulate(&self, txs: &[Transaction]) -> SimulationResult {
+        let mut fork = self.fork();
+        let results = txs.iter().map(|tx| fork.execute_outcome(tx)).collect();
+        SimulationResult {
+            results,
+            diff: fork.diff(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{
+        Chargeback, Deposit, Dispute, WithdrawAuth, WithdrawCapture, Withdrawal,
+    };
+    use rust_decimal_macros::dec;
+
+    fn ledger_of(txs: impl IntoIterator<Item = Transaction>) -> Ledger {
+        let mut ledger = Ledger::new();
+        for tx in txs {
+            ledger.execute(&tx).unwrap();
+        }
+        ledger
+    }
+
+    /// Everything observable about the ledger, ordered.
+    fn state(ledger: &Ledger) -> String {
+        let mut accounts: Vec<_> = ledger
+            .accounts_iter()
+            .map(|(_client_id, account)| AccountView::from(account))
+            .collect();
+        accounts.sort_unstable_by_key(|view| view.client_id);
+        let mut records: Vec<_> = ledger.tx_records_iter().collect();
+        records.sort_unstable_by_key(|record| record.tx_id());
+        let mut disputes: Vec<_> = ledger.open_disputes().collect();
+        disputes.sort_unstable_by_key(|dispute| dispute.tx_id);
+        let mut holds: Vec<_> = ledger.open_withdrawal_holds().collect();
+        holds.sort_unstable_by_key(|hold| hold.tx_id);
+        format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{} {} {}",
+            accounts,
+            records,
+            disputes,
+            holds,
+            ledger.liabilities.balance,
+            ledger.tx_seq(),
+            ledger.is_poisoned()
+        )
+    }
+
+    #[test]
+    fn simulated_chargebacks_leave_the_ledger_as_it_was() {
+        let ledger = ledger_of([
+            Transaction::Deposit(Deposit::new(1, 1, dec!(100))),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(80))),
+            Transaction::Deposit(Deposit::new(2, 3, dec!(50))),
+            Transaction::Deposit(Deposit::new(2, 4, dec!(5))),
+            Transaction::Dispute(Dispute::new(2, 4)),
+            Transaction::WithdrawAuth(WithdrawAuth::new(2, 5, dec!(10))),
+            Transaction::Deposit(Deposit::new(3, 6, dec!(7))),
+        ]);
+        let before = state(&ledger);
+
+        let simulation = ledger.simulate(&[
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(2, 4)),
+            Transaction::WithdrawCapture(WithdrawCapture::new(2, 5)),
+            Transaction::Chargeback(Chargeback::new(3, 6)),
+        ]);
+        assert_eq!(
+            simulation.results,
+            vec![
+                Ok(TxOutcome::Applied),
+                Ok(TxOutcome::Applied),
+                Ok(TxOutcome::Applied),
+                Ok(TxOutcome::Applied),
+                Err(TxError::TxNotDisputed),
+            ]
+        );
+        let changes: Vec<_> = simulation
+            .diff
+            .iter()
+            .map(|account_diff| {
+                (
+                    account_diff.client_id,
+                    account_diff.after.available,
+                    account_diff.available_change(),
+                    account_diff.held_change(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (1, dec!(-80), dec!(-100), dec!(0)),
+                (2, dec!(40), dec!(0), dec!(-15)),
+            ]
+        );
+        assert!(simulation.diff[0].after.locked);
+        assert_eq!(state(&ledger), before);
+    }
+
+    #[test]
+    fn forks_copy_only_the_accounts_they_touch() {
+        let ledger = ledger_of((1..=100).map(|client_id| {
+            Transaction::Deposit(Deposit::new(client_id, client_id.into(), dec!(10)))
+        }));
+        let mut fork = ledger.fork();
+        fork.execute(&Deposit::new(3, 1000, dec!(5))).unwrap();
+        fork.execute(&Withdrawal::new(3, 1001, dec!(1))).unwrap();
+        fork.execute(&Deposit::new(500, 1002, dec!(2))).unwrap();
+        assert_eq!(
+            fork.execute(&Withdrawal::new(7, 1003, dec!(11))),
+            Err(TxError::InsufficientFunds)
+        );
+        assert_eq!(fork.accounts_copied(), 2);
+        assert_eq!(fork.changed_accounts(), vec![3, 500]);
+        assert_eq!(fork.diff()[1].before, None);
+        assert_eq!(fork.account(3).unwrap().available(), dec!(14));
+        assert!(std::ptr::eq(
+            fork.account(4).unwrap(),
+            ledger.account(4).unwrap()
+        ));
+        assert!(fork.tx_record(1002).is_some());
+        assert!(ledger.account(500).is_none());
+        assert_eq!(ledger.account(3).unwrap().available(), dec!(10));
+    }
+
+    #[test]
+    fn nested_forks_see_the_changes_of_their_parent() {
+        let ledger = ledger_of([
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(20))),
+        ]);
+        let before = state(&ledger);
+        let mut fork = ledger.fork();
+        fork.execute(&Dispute::new(1, 1)).unwrap();
+        fork.execute(&Deposit::new(2, 3, dec!(5))).unwrap();
+
+        let mut nested = fork.fork();
+        nested.execute(&Chargeback::new(1, 1)).unwrap();
+        assert_eq!(
+            nested.execute(&Dispute::new(1, 1)),
+            Err(TxError::TxAlreadyDisputed)
+        );
+        nested.execute(&Dispute::new(2, 3)).unwrap();
+        // Changes are relative to the fork, not to the ledger
+        let diff = nested.diff();
+        assert_eq!(nested.changed_accounts(), vec![1, 2]);
+        assert_eq!(diff[0].held_change(), dec!(-10));
+        assert_eq!(diff[0].before.as_ref().unwrap().held, dec!(10));
+        assert_eq!(diff[1].available_change(), dec!(-5));
+        assert_eq!(nested.accounts_copied(), 2);
+        drop(nested);
+
+        assert_eq!(fork.account(1).unwrap().held(), dec!(10));
+        assert!(!fork.account(1).unwrap().locked());
+        assert_eq!(fork.account(2).unwrap().available(), dec!(25));
+        assert_eq!(fork.changed_accounts(), vec![1, 2]);
+        assert_eq!(state(&ledger), before);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::accounting::transactions::TxTypeTag;
+use crate::accounting::{make_tx, AccountView, Ledger, SubAccount, UserAccount};
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+/// Effect of one successfully executed transaction on its client's account.
+///
+/// Liabilities change by the negated sum of both deltas, so they are not stored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    /// Position of the entry in the journal, starting at 0.
+    pub seq: u64,
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    pub kind: TxTypeTag,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub available_delta: Decimal,
+    pub held_delta: Decimal,
+    /// New value of the lock flag when the transaction changed it.
+    pub locked: Option<bool>,
+    /// Tx id of the transaction a derived posting was derived from, see
+    /// `accounting::postings`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<TxId>,
+}
+
+pub(crate) enum JournalTarget {
+    Memory(Vec<JournalEntry>),
+    /// Entries are written as JSON lines.
+    Writer(Box<dyn Write + Send>),
+}
+
+pub(crate) struct Journal {
+    target: JournalTarget,
+    next_seq: u64,
+    /// First write error, kept until `Ledger::flush_journal` reports it.
+    error: Option<std::io::Error>,
+}
+
+impl Journal {
+    pub(crate) fn new(target: JournalTarget) -> Self {
+        Self {
+            target,
+            next_seq: 0,
+            error: None,
+        }
+    }
+
+    pub(crate) fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub(crate) fn append(&mut self, entry: JournalEntry) {
+        self.next_seq += 1;
+        match &mut self.target {
+            JournalTarget::Memory(entries) => entries.push(entry),
+            JournalTarget::Writer(writer) => {
+                if self.error.is_none() {
+                    self.error = write_entry(writer, &entry).err();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn entries(&self) -> &[JournalEntry] {
+        match &self.target {
+            JournalTarget::Memory(entries) => entries,
+            JournalTarget::Writer(_) => &[],
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        match &mut self.target {
+            JournalTarget::Memory(_) => Ok(()),
+            JournalTarget::Writer(writer) => writer.flush(),
+        }
+    }
+}
+
+fn write_entry(writer: &mut impl Write, entry: &JournalEntry) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, entry)?;
+    writer.write_all(b"\n")
+}
+
+/// Writes the entries as JSON lines, the same format as a journal writer produces.
+pub fn write_journal(entries: &[JournalEntry], mut writer: impl Write) -> std::io::Result<()> {
+    for entry in entries {
+        write_entry(&mut writer, entry)?;
+    }
+    writer.flush()
+}
+
+/// Reads entries written by `write_journal` or a journal writer.
+pub fn read_journal(reader: impl BufRead) -> std::io::Result<Vec<JournalEntry>> {
+    journal_entries(reader).collect()
+}
+
+/// Like `read_journal`, one entry at a time.
+pub fn journal_entries(
+    reader: impl BufRead,
+) -> impl Iterator<Item = std::io::Result<JournalEntry>> {
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+}
+
+impl Ledger {
+    /// Rebuilds balances and lock flags right before the entry `seq` was applied.
+    ///
+    /// Deltas are applied without executing transactions, so the returned ledger
+    /// has no deposit history and cannot process disputes of earlier deposits.
+    /// Operations outside `execute`, such as `unlock`, are not journaled.
+    pub fn replay_until(journal: &[JournalEntry], seq: u64) -> Ledger {
+        let mut ledger = Ledger::new();
+        let mut disputed = HashSet::new();
+        for entry in journal.iter().filter(|entry| entry.seq < seq) {
+            let user_account = ledger
+                .accounts
+                .get_or_insert_with(entry.client_id, || UserAccount::new(entry.client_id));
+            apply_entry(&mut ledger.liabilities, user_account, &mut disputed, entry);
+            ledger.total_available += entry.available_delta;
+            ledger.total_held += entry.held_delta;
+        }
+        ledger
+    }
+
+    /// Balance of a single client right before the entry `seq` was applied, or `None`
+    /// when no earlier entry touched the client.
+    pub fn balance_at(
+        journal: &[JournalEntry],
+        client_id: ClientId,
+        seq: u64,
+    ) -> Option<AccountView> {
+        let mut liabilities = SubAccount::new();
+        let mut user_account = None;
+        let mut disputed = HashSet::new();
+        for entry in journal
+            .iter()
+            .filter(|entry| entry.seq < seq && entry.client_id == client_id)
+        {
+            let user_account = user_account.get_or_insert_with(|| UserAccount::new(client_id));
+            apply_entry(&mut liabilities, user_account, &mut disputed, entry);
+        }
+        user_account.as_ref().map(AccountView::from)
+    }
+}
+
+/// Applies `entry` to the account, tracking the deposits it disputes in `disputed`.
+fn apply_entry(
+    liabilities: &mut SubAccount,
+    user_account: &mut UserAccount,
+    disputed: &mut HashSet<TxId>,
+    entry: &JournalEntry,
+) {
+    make_tx(
+        liabilities,
+        &mut user_account.available,
+        entry.available_delta,
+    );
+    make_tx(liabilities, &mut user_account.held, entry.held_delta);
+    if let Some(locked) = entry.locked {
+        user_account.locked = locked;
+    }
+    if entry.kind == TxTypeTag::Chargeback {
+        user_account.chargebacks = user_account.chargebacks.saturating_add(1);
+    }
+    // Entries don't tell deposits held on arrival from disputed ones, so their
+    // resolves and chargebacks count as well
+    let outcomes = match entry.kind {
+        TxTypeTag::Dispute => Some(&mut user_account.disputes_opened),
+        TxTypeTag::Resolve => Some(&mut user_account.disputes_resolved),
+        TxTypeTag::Chargeback => Some(&mut user_account.disputes_charged_back),
+        _ => None,
+    };
+    if let Some(count) = outcomes {
+        *count = count.saturating_add(1);
+    }
+    // Resolves and chargebacks of deposits held on arrival close no dispute
+    match entry.kind {
+        TxTypeTag::Dispute => {
+            disputed.insert(entry.tx_id);
+            user_account.open_disputes += 1;
+        }
+        TxTypeTag::Resolve | TxTypeTag::Chargeback if disputed.remove(&entry.tx_id) => {
+            user_account.open_disputes = user_account.open_disputes.saturating_sub(1);
+        }
+        _ => {}
+    }
+    user_account.version += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{
+        Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal,
+    };
+    use rust_decimal_macros::dec;
+
+    fn view(
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+        version: u64,
+    ) -> AccountView {
+        AccountView {
+            client_id,
+            available,
+            held,
+            total: available + held,
+            locked,
+            version,
+            // The scenarios charge back once per account, which locks it
+            chargebacks: u32::from(locked),
+            // and holds a single disputed deposit at most
+            open_disputes: u32::from(!held.is_zero()),
+            // which it disputed once, without resolving it
+            disputes_opened: u32::from(locked || !held.is_zero()),
+            disputes_resolved: 0,
+            disputes_charged_back: u32::from(locked),
+        }
+    }
+
+    fn chargeback_scenario(ledger: &mut Ledger) {
+        let transactions = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Chargeback(Chargeback::new(1, 2)),
+            Transaction::Deposit(Deposit::new(2, 4, dec!(60.0))),
+            // Rejected, so not journaled
+            Transaction::Withdrawal(Withdrawal::new(1, 5, dec!(10.0))),
+            Transaction::Resolve(Resolve::new(2, 4)),
+            Transaction::Withdrawal(Withdrawal::new(2, 6, dec!(20.0))),
+            Transaction::Dispute(Dispute::new(2, 4)),
+            Transaction::Chargeback(Chargeback::new(2, 4)),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).ok();
+        }
+    }
+
+    #[test]
+    fn balances_at_sequence_points() {
+        let mut ledger = Ledger::builder().journal().build();
+        chargeback_scenario(&mut ledger);
+        let journal = ledger.journal();
+        assert_eq!(journal.len(), 9);
+        assert_eq!(
+            journal[3],
+            JournalEntry {
+                seq: 3,
+                tx_id: 2,
+                kind: TxTypeTag::Dispute,
+                client_id: 1,
+                available_delta: dec!(-30.0),
+                held_delta: dec!(30.0),
+                locked: None,
+                cause: None,
+            }
+        );
+        assert_eq!(journal[4].locked, Some(true));
+        assert_eq!(journal[6].tx_id, 6);
+
+        assert_eq!(Ledger::balance_at(journal, 1, 0), None);
+        assert_eq!(
+            Ledger::balance_at(journal, 1, 2),
+            Some(view(1, dec!(80.0), dec!(0.0), false, 2))
+        );
+        assert_eq!(
+            Ledger::balance_at(journal, 1, 4),
+            Some(view(1, dec!(30.0), dec!(30.0), false, 4))
+        );
+        assert_eq!(
+            Ledger::balance_at(journal, 1, 5),
+            Some(view(1, dec!(30.0), dec!(0.0), true, 5))
+        );
+        assert_eq!(Ledger::balance_at(journal, 2, 5), None);
+        assert_eq!(
+            Ledger::balance_at(journal, 2, 8),
+            Some(view(2, dec!(-20.0), dec!(60.0), false, 3))
+        );
+        assert_eq!(
+            Ledger::balance_at(journal, 2, u64::MAX),
+            Some(view(2, dec!(-20.0), dec!(0.0), true, 4))
+        );
+
+        let replayed = Ledger::replay_until(journal, 7);
+        assert_eq!(replayed.liabilities.balance, dec!(-70.0));
+        assert_eq!(
+            replayed.account(2).map(AccountView::from),
+            Some(view(2, dec!(40.0), dec!(0.0), false, 2))
+        );
+        assert_eq!(
+            replayed.account(1).map(AccountView::from),
+            ledger.account(1).map(AccountView::from)
+        );
+    }
+
+    #[test]
+    fn journal_writer_round_trip() {
+        let mut expected = Ledger::builder().journal().build();
+        chargeback_scenario(&mut expected);
+
+        let path = std::env::temp_dir().join(format!(
+            "payments_engine_journal_{}.jsonl",
+            std::process::id()
+        ));
+        let mut ledger = Ledger::builder()
+            .journal_writer(std::fs::File::create(&path).unwrap())
+            .build();
+        chargeback_scenario(&mut ledger);
+        ledger.flush_journal().unwrap();
+        assert!(ledger.journal().is_empty());
+
+        let file = std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+        let journal = read_journal(file).unwrap();
+        assert_eq!(journal, expected.journal());
+        std::fs::remove_file(&path).unwrap();
+
+        let mut written = Vec::new();
+        write_journal(&journal, &mut written).unwrap();
+        assert_eq!(read_journal(written.as_slice()).unwrap(), journal);
+    }
+}
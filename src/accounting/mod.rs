@@ -1,13 +1,55 @@
-use crate::accounting::executable_tx::{ExecutableTransaction, TxError};
+use crate::accounting::causality::CausalityTracker;
+use crate::accounting::config::{
+    DisputeLimit, DisputeTracking, DuplicateDisputePolicy, LedgerBuilder, LedgerConfig,
+    UnknownClientOnDebit,
+};
+#[cfg(feature = "digest")]
+use crate::accounting::digest::ExecutionDigest;
+use crate::accounting::events::{EventListener, EventSink, LedgerEvent};
+use crate::accounting::journal::{Journal, JournalEntry};
+use crate::accounting::postings::{DerivedOutcome, DerivedPosting, DERIVED_TX_ID_START};
+use crate::accounting::reasons::{DisputeReason, ReasonTable};
+use crate::accounting::sampling::{LiabilitySample, LiabilitySampler};
+use crate::accounting::storage::{AccountStore, MemoryStats};
+use crate::accounting::thresholds::BalanceWatch;
+use crate::accounting::transactions::TxTypeTag;
+use crate::accounting::velocity::VelocityWindows;
+use crate::clock::{SharedClock, Timestamp};
 use crate::core_types::{ClientId, TxId};
+use crate::sink::SinkError;
 use rust_decimal::Decimal;
-use serde::Serialize;
-use std::collections::hash_map::Iter;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::mem::size_of;
+use std::ops::{AddAssign, SubAssign};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+#[cfg(feature = "io")]
+use tokio_stream::Stream;
 
+pub mod causality;
+pub mod config;
+pub mod digest;
+pub mod events;
 mod executable_tx;
+pub mod fork;
+pub mod journal;
+pub mod postings;
+pub mod reasons;
+pub mod sampling;
+pub mod shared;
+#[cfg(feature = "io")]
+pub(crate) mod snapshot;
+pub mod storage;
+mod thresholds;
 pub mod transactions;
+pub mod velocity;
+pub mod view;
 
+pub(crate) use executable_tx::panic_message;
+pub use executable_tx::{ExecutableTransaction, TxError, UnknownErrorCode};
+
+#[derive(Clone)]
 struct SubAccount {
     balance: Decimal,
 }
@@ -20,11 +62,30 @@ impl SubAccount {
     }
 }
 
+#[derive(Clone)]
 pub struct UserAccount {
     client_id: ClientId,
     available: SubAccount,
     held: SubAccount,
     locked: bool,
+    overdraft_limit: Decimal,
+    /// Deposits net of adjustments, saturated at the bounds of `Decimal`.
+    deposited: Decimal,
+    withdrawn: Decimal,
+    charged_back: Decimal,
+    /// Chargebacks executed on this ledger, see `ChargebackPolicy::LockAfterN`.
+    chargebacks: u32,
+    /// Disputed deposits of the account in `Ledger::open_disputes`, see
+    /// `UserAccount::open_disputes`.
+    open_disputes: u32,
+    /// See `UserAccount::disputes_opened`.
+    disputes_opened: u32,
+    disputes_resolved: u32,
+    disputes_charged_back: u32,
+    /// Number of changes made to the account, see `UserAccount::version`.
+    version: u64,
+    /// See `UserAccount::created_by_rejection`.
+    created_by_rejection: bool,
 }
 
 impl UserAccount {
@@ -34,22 +95,200 @@ impl UserAccount {
             available: SubAccount::new(),
             held: SubAccount::new(),
             locked: false,
+            overdraft_limit: Decimal::ZERO,
+            deposited: Decimal::ZERO,
+            withdrawn: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            chargebacks: 0,
+            open_disputes: 0,
+            disputes_opened: 0,
+            disputes_resolved: 0,
+            disputes_charged_back: 0,
+            version: 0,
+            created_by_rejection: false,
+        }
+    }
+
+    pub(crate) fn with_overdraft_limit(client_id: ClientId, overdraft_limit: Decimal) -> Self {
+        Self {
+            overdraft_limit,
+            ..Self::new(client_id)
         }
     }
 
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.available.balance
+    }
+
+    pub fn held(&self) -> Decimal {
+        self.held.balance
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Increases with every change to the account: executed transactions, `unlock`,
+    /// overdraft limits, expired disputes, swept dust and merges.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Sum of available and held funds, saturated at the bounds of `Decimal`.
     pub fn total(&self) -> Decimal {
-        self.available.balance + self.held.balance
+        self.available.balance.saturating_add(self.held.balance)
+    }
+
+    /// Lowest available balance a withdrawal may leave behind.
+    pub fn overdraft_limit(&self) -> Decimal {
+        self.overdraft_limit
+    }
+
+    /// Amount deposited by transactions executed on this ledger, less deposits
+    /// reversed by `Adjustment`.
+    pub fn total_deposited(&self) -> Decimal {
+        self.deposited
+    }
+
+    /// Amount withdrawn by transactions executed on this ledger.
+    pub fn total_withdrawn(&self) -> Decimal {
+        self.withdrawn
+    }
+
+    /// Amount charged back by transactions executed on this ledger.
+    pub fn total_charged_back(&self) -> Decimal {
+        self.charged_back
+    }
+
+    /// Number of chargebacks executed on this ledger.
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
+    }
+
+    /// Number of disputes of the account's deposits which are open. Deposits held on
+    /// arrival are not disputes, so they are left out.
+    pub fn open_disputes(&self) -> u32 {
+        self.open_disputes
+    }
+
+    /// Number of disputes executed on this ledger, counting every dispute of a
+    /// deposit disputed again after its resolution. Deposits held on arrival into a
+    /// locked account are not disputes, so this and the two counters below leave
+    /// them out.
+    pub fn disputes_opened(&self) -> u32 {
+        self.disputes_opened
+    }
+
+    /// Number of disputes resolved in the client's favor.
+    pub fn disputes_resolved(&self) -> u32 {
+        self.disputes_resolved
+    }
+
+    /// Number of disputes lost to a chargeback. Unlike `UserAccount::chargebacks`,
+    /// chargebacks of deposits held on arrival are left out.
+    pub fn disputes_charged_back(&self) -> u32 {
+        self.disputes_charged_back
+    }
+
+    /// Whether the account was created by a rejected transaction, see
+    /// `UnknownClientOnDebit::CreateEmpty`. It stays set when later transactions of the
+    /// client are executed.
+    pub fn created_by_rejection(&self) -> bool {
+        self.created_by_rejection
+    }
+}
+
+/// Snapshot of an account's balances.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AccountView {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub version: u64,
+    /// See `UserAccount::chargebacks`.
+    pub chargebacks: u32,
+    /// See `UserAccount::open_disputes`.
+    pub open_disputes: u32,
+    /// See `UserAccount::disputes_opened`.
+    pub disputes_opened: u32,
+    /// See `UserAccount::disputes_resolved`.
+    pub disputes_resolved: u32,
+    /// See `UserAccount::disputes_charged_back`.
+    pub disputes_charged_back: u32,
+}
+
+impl From<&UserAccount> for AccountView {
+    fn from(user_account: &UserAccount) -> Self {
+        AccountView {
+            client_id: user_account.client_id,
+            available: user_account.available(),
+            held: user_account.held(),
+            total: user_account.total(),
+            locked: user_account.locked,
+            version: user_account.version,
+            chargebacks: user_account.chargebacks,
+            open_disputes: user_account.open_disputes,
+            disputes_opened: user_account.disputes_opened,
+            disputes_resolved: user_account.disputes_resolved,
+            disputes_charged_back: user_account.disputes_charged_back,
+        }
     }
 }
 
+/// Row of the account report, see `Ledger::account_logs`. The client column holds
+/// another identifier than the client id when ids are mapped for sharing the report,
+/// see `AccountLog::with_client`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountLog<C = ClientId> {
+    #[serde(rename = "client")]
+    pub client_id: C,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// `AccountLog` with the overdraft limit, activity totals, open disputes and
+/// `UserAccount::created_by_rejection` of the account, for the extended output.
 #[derive(Serialize)]
-pub struct AccountLog {
+pub struct ExtendedAccountLog {
     #[serde(rename = "client")]
     client_id: ClientId,
     available: Decimal,
     held: Decimal,
     total: Decimal,
     locked: bool,
+    overdraft_limit: Decimal,
+    deposited: Decimal,
+    withdrawn: Decimal,
+    charged_back: Decimal,
+    open_disputes: u32,
+    created_by_rejection: bool,
+}
+
+impl From<&UserAccount> for ExtendedAccountLog {
+    fn from(user_account: &UserAccount) -> Self {
+        ExtendedAccountLog {
+            client_id: user_account.client_id,
+            available: user_account.available.balance,
+            held: user_account.held.balance,
+            total: user_account.total(),
+            locked: user_account.locked,
+            overdraft_limit: user_account.overdraft_limit,
+            deposited: user_account.deposited,
+            withdrawn: user_account.withdrawn,
+            charged_back: user_account.charged_back,
+            open_disputes: user_account.open_disputes,
+            created_by_rejection: user_account.created_by_rejection,
+        }
+    }
 }
 
 impl From<&UserAccount> for AccountLog {
@@ -65,151 +304,2086 @@ impl From<&UserAccount> for AccountLog {
     }
 }
 
-#[derive(PartialEq)]
-enum TxState {
+/// Row of a report written from a snapshot, see `SharedLedger::snapshot_accounts`.
+impl From<&AccountView> for AccountLog {
+    fn from(view: &AccountView) -> Self {
+        AccountLog {
+            client_id: view.client_id,
+            available: view.available,
+            held: view.held,
+            total: view.total,
+            locked: view.locked,
+        }
+    }
+}
+
+#[cfg(feature = "io")]
+impl<C> AccountLog<C> {
+    pub(crate) fn client(&self) -> &C {
+        &self.client_id
+    }
+
+    /// The same row with `client` in the client column.
+    pub(crate) fn with_client<D>(self, client: D) -> AccountLog<D> {
+        AccountLog {
+            client_id: client,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+}
+
+#[cfg(feature = "io")]
+impl AccountLog {
+    /// Appends the row as a CSV record ending with `\n`, the same bytes as its serde
+    /// serialization: integers, `Decimal`s and booleans never need quoting, and both
+    /// write decimals with their scale and a `-` for negative values, including `-0`.
+    pub(crate) fn write_csv_record(&self, record: &mut Vec<u8>) {
+        push_digits(record, u64::from(self.client_id));
+        for balance in [self.available, self.held, self.total] {
+            record.push(b',');
+            push_decimal(record, balance);
+        }
+        record.extend_from_slice(match self.locked {
+            true => b",true\n",
+            false => b",false\n",
+        });
+    }
+}
+
+/// Appends the decimal digits of `value`.
+#[cfg(feature = "io")]
+fn push_digits(out: &mut Vec<u8>, mut value: u64) {
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(&digits[start..]);
+}
+
+/// Appends `value` as its `Display` implementation writes it, without going through
+/// `fmt`: the mantissa with a decimal point `scale` digits from the right.
+#[cfg(feature = "io")]
+fn push_decimal(out: &mut Vec<u8>, value: Decimal) {
+    // The mantissa takes 96 bits, at most 29 digits, and the scale at most 28.
+    let mut digits = [b'0'; 30];
+    let mut start = digits.len();
+    let mut mantissa = value.mantissa().unsigned_abs();
+    while mantissa > u128::from(u64::MAX) {
+        start -= 1;
+        digits[start] = b'0' + (mantissa % 10) as u8;
+        mantissa /= 10;
+    }
+    let mut mantissa = mantissa as u64;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (mantissa % 10) as u8;
+        mantissa /= 10;
+        if mantissa == 0 {
+            break;
+        }
+    }
+    let scale = value.scale() as usize;
+    // Leading zeros, so that at least one digit precedes the decimal point
+    let start = start.min(digits.len() - scale - 1);
+    if value.is_sign_negative() {
+        out.push(b'-');
+    }
+    let point = digits.len() - scale;
+    out.extend_from_slice(&digits[start..point]);
+    if scale > 0 {
+        out.push(b'.');
+        out.extend_from_slice(&digits[point..]);
+    }
+}
+
+/// State of a transaction in `Ledger::tx_states`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxState {
+    /// Not disputed, either never or since a `Resolve`.
     Resolved,
     Disputed,
     ChargedBack,
+    /// Deposited into a locked account under `LockPolicy::HoldIncoming`.
+    HeldOnArrival,
+    /// Returned to liabilities by an `Adjustment`.
+    Reversed,
+}
+
+impl TxState {
+    /// Value of the `state` column of `output_tx_states`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxState::Resolved => "resolved",
+            TxState::Disputed => "disputed",
+            TxState::ChargedBack => "charged_back",
+            TxState::HeldOnArrival => "held_on_arrival",
+            TxState::Reversed => "reversed",
+        }
+    }
+}
+
+impl Display for TxState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Payout state of a recorded withdrawal.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WithdrawalRecord {
+    reference: Option<Box<str>>,
+    settled: bool,
+}
+
+impl WithdrawalRecord {
+    /// Reference of the payout given on the withdrawal row, at most
+    /// `MAX_REFERENCE_LEN` bytes.
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    /// Whether a `Settle` confirmed the payout.
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
 }
 
-struct DepositState {
+/// Transaction which later transactions refer to by its tx id. Deposits and
+/// withdrawals are recorded, and disputes, resolves, chargebacks and adjustments
+/// reject the records of other kinds than deposits with `TxError::TxNotDisputable`.
+#[derive(Debug, Clone)]
+pub struct TxRecord {
     client_id: ClientId,
     tx_id: TxId,
+    kind: TxTypeTag,
     amount: Decimal,
     state: TxState,
+    /// Time of the ledger clock when the transaction was executed.
+    recorded_at: Timestamp,
+    /// `Ledger::tx_seq` of the dispute, while the deposit is disputed.
+    disputed_at: Option<u64>,
+    /// Whether a `Resolve` released the deposit, which tells a resolved deposit
+    /// from one that was never disputed.
+    resolved: bool,
+    /// Reason of the first dispute giving one, replaced by the reason of a chargeback.
+    reason: Option<DisputeReason>,
+    /// Set for withdrawals only, boxed to keep the records of deposits small.
+    withdrawal: Option<Box<WithdrawalRecord>>,
 }
 
-impl DepositState {
-    fn new(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
+impl TxRecord {
+    fn new(
+        client_id: ClientId,
+        tx_id: TxId,
+        kind: TxTypeTag,
+        amount: Decimal,
+        recorded_at: Timestamp,
+    ) -> Self {
         Self {
             client_id,
             tx_id,
+            kind,
             amount,
             state: TxState::Resolved,
+            recorded_at,
+            disputed_at: None,
+            resolved: false,
+            reason: None,
+            withdrawal: (kind == TxTypeTag::Withdrawal).then(Box::default),
+        }
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    pub fn kind(&self) -> TxTypeTag {
+        self.kind
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn state(&self) -> TxState {
+        self.state
+    }
+
+    pub fn recorded_at(&self) -> Timestamp {
+        self.recorded_at
+    }
+
+    /// Reason of the dispute or chargeback of a deposit, see `Dispute::with_reason`.
+    pub fn reason(&self) -> Option<&DisputeReason> {
+        self.reason.as_ref()
+    }
+
+    /// Payout state of a withdrawal, `None` for other kinds.
+    pub fn withdrawal(&self) -> Option<&WithdrawalRecord> {
+        self.withdrawal.as_deref()
+    }
+
+    /// Bytes taken by the reference of a withdrawal.
+    fn reference_len(&self) -> Option<usize> {
+        self.withdrawal()?.reference().map(str::len)
+    }
+
+    /// State of a deposit settled for good, whose tx id later deposits cannot reuse:
+    /// charged back, or resolved after a dispute when `tombstone_resolved` is set.
+    fn settlement(&self, tombstone_resolved: bool) -> Option<TxState> {
+        let settled = self.kind == TxTypeTag::Deposit
+            && match self.state {
+                TxState::ChargedBack => true,
+                TxState::Resolved => tombstone_resolved && self.resolved,
+                TxState::Disputed | TxState::HeldOnArrival | TxState::Reversed => false,
+            };
+        settled.then_some(self.state)
+    }
+
+    /// Fails with `TxError::TxNotDisputable` unless the record is a deposit.
+    fn check_disputable(&self) -> Result<(), TxError> {
+        match self.kind {
+            TxTypeTag::Deposit => Ok(()),
+            _ => Err(TxError::TxNotDisputable),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum HoldState {
+    Held,
+    Captured,
+    Released,
+}
+
+/// Funds of a `WithdrawAuth`, held until the withdrawal is captured or released.
+#[derive(Clone)]
+struct WithdrawalHold {
+    client_id: ClientId,
+    tx_id: TxId,
+    amount: Decimal,
+    state: HoldState,
+}
+
+impl WithdrawalHold {
+    fn new(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            amount,
+            state: HoldState::Held,
         }
     }
 }
 
+/// Effect of a transaction accepted by the ledger.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxOutcome {
+    Applied,
+    /// Repeated dispute, resolve or chargeback of a deposit already in the requested
+    /// state under `DuplicateDisputePolicy::Idempotent`, which changed nothing.
+    Duplicate,
+    /// Resolve or chargeback which arrived before the dispute of its deposit, held
+    /// until the dispute arrives under `CausalityMode::BufferOutOfOrder`.
+    Deferred,
+}
+
+/// Account dropped by `Ledger::remove_account`.
+#[derive(Debug, PartialEq)]
+pub struct RemovedAccount {
+    pub account: AccountView,
+    /// Deposits of the client dropped with the account.
+    pub deposits_removed: usize,
+}
+
+/// Resolved, never disputed or charged back deposit offered to the predicate of
+/// `Ledger::prune_settled_tx_states`.
+#[derive(Debug, PartialEq)]
+pub struct SettledDeposit {
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub amount: Decimal,
+    pub charged_back: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct OpenDispute {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    pub amount: Decimal,
+    pub reason: Option<DisputeReason>,
+}
+
+/// Withdrawal whose payout was not confirmed by a `Settle` yet.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UnsettledWithdrawal<'a> {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    pub amount: Decimal,
+    pub reference: Option<&'a str>,
+}
+
+/// Authorized withdrawal which was neither captured nor released yet.
+#[derive(Debug, PartialEq)]
+pub struct OpenWithdrawalHold {
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub amount: Decimal,
+}
+
 pub struct Ledger {
     liabilities: SubAccount,
-    accounts: HashMap<ClientId, UserAccount>,
-    deposit_states: HashMap<TxId, DepositState>,
+    /// Sums of the available and held balances of every account, kept up to date by
+    /// every change to a balance instead of being recomputed.
+    total_available: ExactSum,
+    total_held: ExactSum,
+    /// Residual balances swept from client accounts by `sweep_dust`.
+    dust: SubAccount,
+    accounts: AccountStore,
+    tx_states: HashMap<TxId, TxRecord>,
+    /// Ids of settled deposits whose records were pruned or removed, with the state
+    /// they were settled in, so that later deposits cannot reuse them.
+    tombstones: HashMap<TxId, TxState>,
+    open_disputes: HashMap<ClientId, HashSet<TxId>>,
+    withdrawal_holds: HashMap<TxId, WithdrawalHold>,
+    velocity: VelocityWindows,
+    balance_watch: BalanceWatch,
+    /// Transactions applied under `DisputeAmountHandling::WarnOnMismatch` although
+    /// the amount they stated differed from the deposit's.
+    dispute_amount_mismatches: u64,
+    /// Withdrawal references in `tx_states` and their total length in bytes.
+    withdrawal_references: usize,
+    withdrawal_reference_bytes: usize,
+    /// Reasons of the disputes and chargebacks in `tx_states`.
+    reasons: ReasonTable,
+    /// Disputes and chargebacks applied under `UnknownReasonAction::Warn` although
+    /// their reason is not in the `ReasonAllowlist`.
+    unknown_dispute_reasons: u64,
+    applied_offset: Option<u64>,
+    journal: Option<Journal>,
+    sampler: Option<LiabilitySampler>,
+    #[cfg(feature = "digest")]
+    execution_digest: Option<ExecutionDigest>,
+    /// Lifecycle messages seen per tx id, unless `CausalityMode::Off`.
+    causality: Option<CausalityTracker>,
+    config: LedgerConfig,
+    /// Set when a transaction panicked during `execute_catch_unwind`.
+    poisoned: bool,
+    tx_seq: u64,
+    /// Derived postings executed or rejected, which also numbers their tx ids.
+    derived_postings: u64,
+    /// Derived postings of the transaction executed last, see `last_derived_postings`.
+    last_derived: Vec<DerivedOutcome>,
+    events: EventSink,
+    #[cfg(test)]
+    panic_on_tx: Option<TxId>,
 }
 
 impl Ledger {
     pub fn new() -> Self {
+        Self::with_config(LedgerConfig::default())
+    }
+
+    pub fn builder() -> LedgerBuilder {
+        LedgerBuilder::new()
+    }
+
+    pub fn with_config(config: LedgerConfig) -> Self {
+        let events = EventSink::new(config.event_failure_policy, config.clock.clone());
         Self {
             liabilities: SubAccount::new(),
-            accounts: HashMap::new(),
-            deposit_states: HashMap::new(),
+            total_available: ExactSum::default(),
+            total_held: ExactSum::default(),
+            dust: SubAccount::new(),
+            accounts: AccountStore::new(config.account_tiering),
+            tx_states: HashMap::new(),
+            tombstones: HashMap::new(),
+            open_disputes: HashMap::new(),
+            withdrawal_holds: HashMap::new(),
+            velocity: VelocityWindows::default(),
+            balance_watch: BalanceWatch::default(),
+            dispute_amount_mismatches: 0,
+            withdrawal_references: 0,
+            withdrawal_reference_bytes: 0,
+            reasons: ReasonTable::default(),
+            unknown_dispute_reasons: 0,
+            applied_offset: None,
+            journal: None,
+            sampler: None,
+            #[cfg(feature = "digest")]
+            execution_digest: None,
+            causality: CausalityTracker::new(config.causality_mode),
+            config,
+            poisoned: false,
+            tx_seq: 0,
+            derived_postings: 0,
+            last_derived: Vec::new(),
+            events,
+            #[cfg(test)]
+            panic_on_tx: None,
         }
     }
 
     pub fn execute(&mut self, tx: &impl ExecutableTransaction) -> Result<(), TxError> {
-        tx.execute_tx(self)
+        self.execute_outcome(tx).map(|_outcome| ())
     }
 
-    pub fn accounts_iter(&self) -> Iter<ClientId, UserAccount> {
-        self.accounts.iter()
+    /// Executes `txs` in order, returning the result of each at its index, as
+    /// executing them one by one would. Room for the accounts of the clients new to
+    /// the ledger and for the records of the deposits and withdrawals is reserved
+    /// upfront, instead of growing the maps while the batch runs. Transactions are
+    /// not regrouped by client, as that would change the outcome of those depending
+    /// on each other.
+    pub fn execute_batch<T: ExecutableTransaction>(
+        &mut self,
+        txs: &[T],
+    ) -> Vec<Result<(), TxError>> {
+        // One bit per client id, for counting each new client once
+        let mut seen = vec![0u64; (ClientId::MAX as usize + 1) / 64];
+        let mut new_clients = 0;
+        let mut new_records = 0;
+        for tx in txs {
+            let client_id = tx.client_id();
+            let (word, bit) = (client_id as usize / 64, 1 << (client_id % 64));
+            if seen[word] & bit == 0 {
+                seen[word] |= bit;
+                if !self.accounts.contains_key(&client_id) {
+                    new_clients += 1;
+                }
+            }
+            if matches!(
+                tx.kind(),
+                TxTypeTag::Deposit | TxTypeTag::Withdrawal | TxTypeTag::WithdrawAuth
+            ) {
+                new_records += 1;
+            }
+        }
+        self.accounts.reserve(new_clients);
+        self.tx_states.reserve(new_records);
+        txs.iter().map(|tx| self.execute(tx)).collect()
     }
-}
 
-impl Default for Ledger {
-    fn default() -> Self {
-        Self::new()
+    /// Like `execute`, telling apart duplicates accepted without any effect and
+    /// deferred transactions.
+    pub fn execute_outcome(
+        &mut self,
+        tx: &impl ExecutableTransaction,
+    ) -> Result<TxOutcome, TxError> {
+        self.last_derived.clear();
+        let result = match self.causality {
+            Some(_) => self.execute_causal(tx),
+            None => self.execute_untracked(tx),
+        };
+        #[cfg(feature = "digest")]
+        if let Some(digest) = self.execution_digest.as_mut() {
+            digest.record(tx, &result);
+        }
+        result
     }
-}
 
-fn make_tx(source: &mut SubAccount, destination: &mut SubAccount, amount: Decimal) {
-    source.balance -= amount;
-    destination.balance += amount;
-}
+    fn execute_untracked(&mut self, tx: &impl ExecutableTransaction) -> Result<TxOutcome, TxError> {
+        if self.config.posting_rules.is_empty() {
+            return self.apply(tx);
+        }
+        let creates_tx = matches!(
+            tx.kind(),
+            TxTypeTag::Deposit | TxTypeTag::Withdrawal | TxTypeTag::WithdrawAuth
+        );
+        if creates_tx && tx.tx_id() >= DERIVED_TX_ID_START {
+            return Err(TxError::ReservedTxId);
+        }
+        let outcome = self.apply(tx)?;
+        if outcome == TxOutcome::Applied {
+            self.post_derived(tx, 0);
+        }
+        Ok(outcome)
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::accounting::executable_tx::TxError;
-    use crate::accounting::transactions::{
-        Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal,
-    };
-    use crate::accounting::Ledger;
-    use crate::core_types::ClientId;
-    use rust_decimal::Decimal;
-    use rust_decimal_macros::dec;
+    /// Executes the postings the posting rules derive from `cause`, which was applied
+    /// and derived at `depth`, 0 for transactions of the input.
+    fn post_derived(&mut self, cause: &impl ExecutableTransaction, depth: u32) {
+        let rules = self.config.posting_rules.clone();
+        for rule in rules.iter() {
+            for derived in rule.postings(cause, self) {
+                // Ids wrap around within the reserved range after 2^31 postings
+                let posting = DerivedPosting {
+                    tx_id: DERIVED_TX_ID_START
+                        | (self.derived_postings as TxId & !DERIVED_TX_ID_START),
+                    cause: cause.tx_id(),
+                    rule: rule.name(),
+                    client_id: derived.client_id,
+                    kind: derived.kind,
+                    amount: derived.amount,
+                    depth: depth + 1,
+                };
+                self.derived_postings += 1;
+                let result = self.apply(&posting);
+                #[cfg(feature = "digest")]
+                if let Some(digest) = self.execution_digest.as_mut() {
+                    digest.record(&posting, &result);
+                }
+                let applied = result.is_ok();
+                if applied {
+                    self.events.emit(LedgerEvent::DerivedPosted {
+                        client_id: posting.client_id,
+                        tx_id: posting.tx_id,
+                        cause: posting.cause,
+                        rule: posting.rule,
+                        kind: posting.kind,
+                        amount: posting.amount,
+                    });
+                }
+                self.last_derived.push(DerivedOutcome {
+                    posting: posting.clone(),
+                    result: result.map(|_outcome| ()),
+                });
+                if applied && posting.depth <= self.config.rules_on_derived_postings {
+                    self.post_derived(&posting, posting.depth);
+                }
+            }
+        }
+    }
 
-    fn verify_balances(ledger: &Ledger, client_id: ClientId, available: Decimal, held: Decimal) {
-        let user_account = ledger.accounts.get(&client_id).unwrap();
-        assert_eq!(user_account.available.balance, available);
-        assert_eq!(user_account.held.balance, held);
-        assert_eq!(user_account.available.balance, available);
+    fn apply(&mut self, tx: &impl ExecutableTransaction) -> Result<TxOutcome, TxError> {
+        #[cfg(test)]
+        if self.panic_on_tx == Some(tx.tx_id()) {
+            panic!("injected panic in tx {}", tx.tx_id());
+        }
+        self.accounts.tick();
+        if self.config.dispute_tracking == DisputeTracking::Disabled
+            && matches!(
+                tx.kind(),
+                TxTypeTag::Dispute
+                    | TxTypeTag::Resolve
+                    | TxTypeTag::Chargeback
+                    | TxTypeTag::Adjustment
+            )
+        {
+            return Err(TxError::DisputeTrackingDisabled);
+        }
+        if self.config.duplicate_dispute_policy == DuplicateDisputePolicy::Idempotent
+            && self.is_duplicate(tx)
+        {
+            return Ok(TxOutcome::Duplicate);
+        }
+        let client_id = tx.client_id();
+        if self.journal.is_none() {
+            let before = self.balances_of(client_id);
+            let result = self.execute_tx(tx);
+            self.track_totals(client_id, before);
+            result?;
+            self.bump_version(client_id);
+            self.tx_seq += 1;
+            self.sample_liabilities();
+            self.notify_executed(tx);
+            self.watch_balance(client_id);
+            return Ok(TxOutcome::Applied);
+        }
+        let before = self.account(client_id).map(AccountView::from);
+        let balances = self.balances_of(client_id);
+        let result = self.execute_tx(tx);
+        self.track_totals(client_id, balances);
+        result?;
+        self.bump_version(client_id);
+        let after = AccountView::from(&self.accounts[&client_id]);
+        let (available, held, locked) = before
+            .map_or((Decimal::ZERO, Decimal::ZERO, false), |view| {
+                (view.available, view.held, view.locked)
+            });
+        if let Some(journal) = self.journal.as_mut() {
+            journal.append(JournalEntry {
+                seq: journal.next_seq(),
+                tx_id: tx.tx_id(),
+                kind: tx.kind(),
+                client_id,
+                available_delta: after.available - available,
+                held_delta: after.held - held,
+                locked: (after.locked != locked).then_some(after.locked),
+                cause: tx.cause(),
+            });
+        }
+        self.tx_seq += 1;
+        self.sample_liabilities();
+        self.notify_executed(tx);
+        self.watch_balance(client_id);
+        Ok(TxOutcome::Applied)
     }
 
-    fn verify_liabilities(ledger: &Ledger, liabilities: Decimal) {
-        assert_eq!(ledger.liabilities.balance, liabilities);
+    /// Executes `tx`, creating an empty account for its client when it is rejected for
+    /// lack of one under `UnknownClientOnDebit::CreateEmpty`.
+    fn execute_tx(&mut self, tx: &impl ExecutableTransaction) -> Result<(), TxError> {
+        let result = tx.execute_tx(self);
+        if result != Err(TxError::ClientAccountNotFound)
+            || self.config.unknown_client_on_debit != UnknownClientOnDebit::CreateEmpty
+        {
+            return result;
+        }
+        let rejection = match tx.kind() {
+            TxTypeTag::Withdrawal => TxError::InsufficientFunds,
+            TxTypeTag::Dispute | TxTypeTag::Resolve | TxTypeTag::Chargeback => {
+                TxError::OriginTxNotFound
+            }
+            _ => return result,
+        };
+        let client_id = tx.client_id();
+        let overdraft_limit = self.config.overdraft_limit_of(client_id);
+        self.accounts.get_or_insert_with(client_id, || UserAccount {
+            created_by_rejection: true,
+            ..UserAccount::with_overdraft_limit(client_id, overdraft_limit)
+        });
+        Err(rejection)
     }
 
-    fn verify_account_locked(ledger: &Ledger, client_id: ClientId) {
-        let locked = ledger.accounts.get(&client_id).unwrap().locked;
-        assert!(locked);
+    /// Available and held balance of `client_id`, zero without an account.
+    fn balances_of(&self, client_id: ClientId) -> (Decimal, Decimal) {
+        self.accounts
+            .get(&client_id)
+            .map_or((Decimal::ZERO, Decimal::ZERO), |user_account| {
+                (user_account.available.balance, user_account.held.balance)
+            })
     }
 
-    fn verify_account_not_locked(ledger: &Ledger, client_id: ClientId) {
-        let locked = ledger.accounts.get(&client_id).unwrap().locked;
-        assert!(!locked);
+    /// Adds the change of the balances of `client_id` since `before` to the running
+    /// totals. Transactions only change the balances of their own client, and failed
+    /// ones are tracked too in case they changed anything before failing.
+    fn track_totals(&mut self, client_id: ClientId, before: (Decimal, Decimal)) {
+        let (available, held) = self.balances_of(client_id);
+        self.total_available += available;
+        self.total_available -= before.0;
+        self.total_held += held;
+        self.total_held -= before.1;
     }
 
-    #[test]
-    fn deposits_and_withdrawals_only() {
-        let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(1.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(1.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-1.0));
+    /// Records a sample when one is due after the transaction just executed.
+    fn sample_liabilities(&mut self) {
+        let Some(sampler) = self.sampler.as_mut() else {
+            return;
+        };
+        if sampler.is_due(self.tx_seq) {
+            sampler.record(LiabilitySample {
+                seq: self.tx_seq,
+                liabilities: self.liabilities.balance,
+                total_available: self.total_available.to_decimal(),
+                total_held: self.total_held.to_decimal(),
+                accounts: self.accounts.len(),
+            });
+        }
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(2, 2, dec!(2.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(1.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-3.0));
+    fn bump_version(&mut self, client_id: ClientId) {
+        if let Some(user_account) = self.accounts.get_mut(&client_id) {
+            user_account.version += 1;
+        }
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 3, dec!(2.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(3.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-5.0));
+    /// Reports an executed dispute or resolve to the event listener. Chargebacks
+    /// report themselves, since they may lock the account too.
+    fn notify_executed(&mut self, tx: &impl ExecutableTransaction) {
+        let (client_id, tx_id) = (tx.client_id(), tx.tx_id());
+        let Some(deposit) = self.tx_states.get(&tx_id) else {
+            return;
+        };
+        let amount = deposit.amount;
+        let event = match tx.kind() {
+            TxTypeTag::Dispute => LedgerEvent::Disputed {
+                client_id,
+                tx_id,
+                amount,
+                reason: deposit.reason.clone(),
+            },
+            TxTypeTag::Resolve => LedgerEvent::Resolved {
+                client_id,
+                tx_id,
+                amount,
+            },
+            _ => return,
+        };
+        self.events.emit(event);
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(1.5))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(1.5), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-3.5));
+    /// Whether `tx` is a dispute, resolve or chargeback of a deposit of its client
+    /// which is already in the state the transaction would put it in.
+    fn is_duplicate(&self, tx: &impl ExecutableTransaction) -> bool {
+        let Some(deposit) = self.tx_states.get(&tx.tx_id()) else {
+            return false;
+        };
+        deposit.client_id == tx.client_id()
+            && match tx.kind() {
+                TxTypeTag::Dispute => deposit.state == TxState::Disputed,
+                TxTypeTag::Resolve => deposit.state == TxState::Resolved && deposit.resolved,
+                TxTypeTag::Chargeback => deposit.state == TxState::ChargedBack,
+                _ => false,
+            }
+    }
 
-        assert_eq!(
-            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(2, 5, dec!(3.0)))),
-            Err(TxError::InsufficientFunds)
-        );
-        verify_balances(&ledger, 1, dec!(1.5), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-3.5));
+    /// Number of successfully executed transactions, which is also the sequence
+    /// number of the next journal entry when the journal was enabled from the start.
+    pub fn tx_seq(&self) -> u64 {
+        self.tx_seq
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(3, 6, dec!(100.0001))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(1.5), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
-        verify_balances(&ledger, 3, dec!(100.0001), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-103.5001));
+    /// Number of derived postings executed or rejected, see `accounting::postings`.
+    pub fn derived_postings(&self) -> u64 {
+        self.derived_postings
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(
-                3,
-                7,
-                dec!(50.0002)
-            )))
-            .is_ok());
+    /// Derived postings of the transaction executed last, in the order they were
+    /// executed, including those of transactions it released from the pending buffer
+    /// of `CausalityMode::BufferOutOfOrder`.
+    pub fn last_derived_postings(&self) -> &[DerivedOutcome] {
+        &self.last_derived
+    }
+
+    /// Like `execute`, but a panic of the transaction is returned as
+    /// `TxError::InternalPanic` and poisons the ledger, since the transaction may
+    /// have been applied partially.
+    pub fn execute_catch_unwind(
+        &mut self,
+        tx: &impl ExecutableTransaction,
+    ) -> Result<TxOutcome, TxError> {
+        match catch_unwind(AssertUnwindSafe(|| self.execute_outcome(tx))) {
+            Ok(result) => result,
+            Err(payload) => {
+                self.poisoned = true;
+                let result = Err(TxError::InternalPanic {
+                    message: panic_message(payload.as_ref()),
+                });
+                #[cfg(feature = "digest")]
+                if let Some(digest) = self.execution_digest.as_mut() {
+                    digest.record(tx, &result);
+                }
+                result
+            }
+        }
+    }
+
+    /// Whether a transaction panicked, so balances may be inconsistent; see
+    /// `verify_invariants`.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    #[cfg(test)]
+    pub(crate) fn panic_on_tx(&mut self, tx_id: TxId) {
+        self.panic_on_tx = Some(tx_id);
+    }
+
+    /// Disputes, resolves and chargebacks applied although the amount they stated
+    /// differed from the deposit's, see `DisputeAmountHandling::WarnOnMismatch`.
+    pub fn dispute_amount_mismatches(&self) -> u64 {
+        self.dispute_amount_mismatches
+    }
+
+    /// Counts and reports the `provided` amount of a dispute, resolve or chargeback
+    /// found by `check_stated_amount`, if any.
+    fn warn_stated_amount(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TxId,
+        expected: Decimal,
+        provided: Option<Decimal>,
+    ) {
+        if let Some(provided) = provided {
+            self.dispute_amount_mismatches += 1;
+            self.events.emit(LedgerEvent::DisputedAmountMismatch {
+                client_id,
+                tx_id,
+                expected,
+                provided,
+            });
+        }
+    }
+
+    /// Disputes and chargebacks applied although their reason is not in the
+    /// `ReasonAllowlist`, see `UnknownReasonAction::Warn`.
+    pub fn unknown_dispute_reasons(&self) -> u64 {
+        self.unknown_dispute_reasons
+    }
+
+    /// Counts and reports the `reason` of a dispute or chargeback found outside the
+    /// allowlist by `check_reason`, if any.
+    fn warn_unknown_reason(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TxId,
+        kind: TxTypeTag,
+        reason: Option<DisputeReason>,
+    ) {
+        if let Some(reason) = reason {
+            self.unknown_dispute_reasons += 1;
+            self.events.emit(LedgerEvent::UnknownDisputeReason {
+                client_id,
+                tx_id,
+                kind,
+                reason,
+            });
+        }
+    }
+
+    pub(crate) fn set_event_listener(&mut self, listener: EventListener) {
+        self.events.set_listener(listener);
+    }
+
+    /// Events dropped because the listener failed under `SinkFailurePolicy::Ignore`.
+    pub fn events_dropped(&self) -> u64 {
+        self.events.dropped()
+    }
+
+    /// Failure of the listener under `SinkFailurePolicy::Abort` or after all retries
+    /// of `SinkFailurePolicy::RetryN`, which should stop processing. The event was
+    /// lost, while the change it reported stays applied.
+    pub fn take_event_sink_error(&mut self) -> Option<SinkError> {
+        self.events.take_error()
+    }
+
+    pub(crate) fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub(crate) fn set_liability_sampler(&mut self, sampler: LiabilitySampler) {
+        self.sampler = Some(sampler);
+    }
+
+    #[cfg(feature = "digest")]
+    pub(crate) fn set_execution_digest(&mut self, digest: ExecutionDigest) {
+        self.execution_digest = Some(digest);
+    }
+
+    /// Samples kept in memory, see `LedgerBuilder::liability_sampling`; empty when
+    /// sampling is disabled or goes to a writer.
+    pub fn liability_samples(&self) -> &[LiabilitySample] {
+        self.sampler.as_ref().map_or(&[], LiabilitySampler::samples)
+    }
+
+    /// Flushes the sample writer, reporting the first error of any earlier write.
+    pub fn flush_liability_samples(&mut self) -> std::io::Result<()> {
+        self.sampler
+            .as_mut()
+            .map_or(Ok(()), LiabilitySampler::flush)
+    }
+
+    /// Entries of an in-memory journal; empty when journaling is disabled or goes
+    /// to a writer.
+    pub fn journal(&self) -> &[JournalEntry] {
+        self.journal.as_ref().map_or(&[], Journal::entries)
+    }
+
+    /// Flushes the journal writer, reporting the first error of any earlier write.
+    pub fn flush_journal(&mut self) -> std::io::Result<()> {
+        self.journal.as_mut().map_or(Ok(()), Journal::flush)
+    }
+
+    /// Accounts of both tiers in no particular order.
+    pub fn accounts_iter(&self) -> impl Iterator<Item = (&ClientId, &UserAccount)> + '_ {
+        self.accounts.iter()
+    }
+
+    /// Rows of the account report sorted by client id, for exporters writing them
+    /// elsewhere than to CSV.
+    pub fn account_logs(&self) -> impl Iterator<Item = AccountLog> + '_ {
+        self.view().account_logs()
+    }
+
+    /// `account_logs` as a `Stream`, for consumers batching the rows asynchronously.
+    #[cfg(feature = "io")]
+    pub fn account_log_stream(&self) -> impl Stream<Item = AccountLog> + '_ {
+        tokio_stream::iter(self.account_logs())
+    }
+
+    /// Moves every account to the cold tier, see `LedgerBuilder::account_tiering`.
+    pub fn compact(&mut self) {
+        self.accounts.compact();
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            tx_records: self.tx_states.len(),
+            tx_record_bytes: self.tx_states.capacity() * (size_of::<(TxId, TxRecord)>() + 1),
+            withdrawal_references: self.withdrawal_references,
+            withdrawal_reference_bytes: self.withdrawal_reference_bytes,
+            interned_reasons: self.reasons.len(),
+            tombstones: self.tombstones.len(),
+            tombstone_bytes: self.tombstones.capacity() * (size_of::<(TxId, TxState)>() + 1),
+            ..self.accounts.memory_stats()
+        }
+    }
+
+    /// State of the settled deposit with `tx_id`, from its record or from its tombstone
+    /// once the record was dropped, see `TxError::TxIdReuseAfterSettlement`.
+    fn settled_state(&self, tx_id: TxId) -> Option<TxState> {
+        self.tx_states
+            .get(&tx_id)
+            .and_then(|record| record.settlement(self.config.tombstone_resolved))
+            .or_else(|| self.tombstones.get(&tx_id).copied())
+    }
+
+    /// Records an executed withdrawal, whose tx id was checked to be free.
+    fn record_withdrawal(&mut self, record: TxRecord) {
+        if let Some(len) = record.reference_len() {
+            self.withdrawal_references += 1;
+            self.withdrawal_reference_bytes += len;
+        }
+        self.tx_states.insert(record.tx_id, record);
+    }
+
+    pub fn config(&self) -> &LedgerConfig {
+        &self.config
+    }
+
+    /// Clock of the ledger, which components working with it read the time from too.
+    pub fn clock(&self) -> &SharedClock {
+        &self.config.clock
+    }
+
+    pub fn unlock(&mut self, client_id: ClientId) -> Result<(), TxError> {
+        let user_account = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(TxError::ClientAccountNotFound)?;
+        user_account.locked = false;
+        user_account.version += 1;
+        Ok(())
+    }
+
+    /// Locks the account like a chargeback does, e.g. to close it, so that it takes no
+    /// further withdrawals and deposits follow `LedgerConfig::lock_policy`. Like
+    /// `unlock`, locking is not journaled.
+    pub fn lock(&mut self, client_id: ClientId) -> Result<(), TxError> {
+        let user_account = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(TxError::ClientAccountNotFound)?;
+        user_account.locked = true;
+        user_account.version += 1;
+        Ok(())
+    }
+
+    /// Sets the lowest available balance withdrawals of `client_id` may leave behind,
+    /// for its existing account as well as an account created later. Disputes and
+    /// chargebacks ignore the limit.
+    pub fn set_overdraft_limit(&mut self, client_id: ClientId, limit: Decimal) {
+        self.config.overdraft_limits.insert(client_id, limit);
+        if let Some(user_account) = self.accounts.get_mut(&client_id) {
+            user_account.overdraft_limit = limit;
+            user_account.version += 1;
+        }
+    }
+
+    /// Sets the cap on the held funds disputes of `client_id` may take its account to,
+    /// overriding `LedgerConfig::dispute_limit`.
+    pub fn set_dispute_limit(&mut self, client_id: ClientId, limit: DisputeLimit) {
+        self.config.dispute_limits.insert(client_id, limit);
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<&UserAccount> {
+        self.accounts.get(&client_id)
+    }
+
+    /// Recorded transactions with their current state, in no particular order.
+    pub fn tx_records_iter(&self) -> impl Iterator<Item = &TxRecord> + '_ {
+        self.tx_states.values()
+    }
+
+    /// Deposits whose funds are currently held, either disputed or held on arrival,
+    /// in no particular order.
+    pub fn open_disputes(&self) -> impl Iterator<Item = OpenDispute> + '_ {
+        self.open_disputes
+            .iter()
+            .flat_map(|(client_id, tx_ids)| tx_ids.iter().map(move |tx_id| (*client_id, *tx_id)))
+            .filter_map(|(client_id, tx_id)| {
+                self.tx_states.get(&tx_id).map(|deposit| OpenDispute {
+                    client_id,
+                    tx_id,
+                    amount: deposit.amount,
+                    reason: deposit.reason.clone(),
+                })
+            })
+    }
+
+    /// Withdrawals not confirmed by a `Settle` yet, sorted by client and tx id.
+    pub fn unsettled_withdrawals(&self) -> Vec<UnsettledWithdrawal<'_>> {
+        let mut unsettled = self
+            .tx_states
+            .values()
+            .filter_map(|record| {
+                let withdrawal = record.withdrawal()?;
+                (!withdrawal.settled).then_some(UnsettledWithdrawal {
+                    client_id: record.client_id,
+                    tx_id: record.tx_id,
+                    amount: record.amount,
+                    reference: withdrawal.reference(),
+                })
+            })
+            .collect::<Vec<_>>();
+        unsettled.sort_unstable_by_key(|withdrawal| (withdrawal.client_id, withdrawal.tx_id));
+        unsettled
+    }
+
+    /// Withdrawals authorized by a `WithdrawAuth` whose funds are still held, in no
+    /// particular order.
+    pub fn open_withdrawal_holds(&self) -> impl Iterator<Item = OpenWithdrawalHold> + '_ {
+        self.withdrawal_holds
+            .values()
+            .filter(|hold| hold.state == HoldState::Held)
+            .map(|hold| OpenWithdrawalHold {
+                client_id: hold.client_id,
+                tx_id: hold.tx_id,
+                amount: hold.amount,
+            })
+    }
+
+    /// Resolves disputes opened by a transaction with a `tx_seq` lower than
+    /// `older_than_seq`, releasing their held funds, and returns them sorted by client
+    /// and transaction id. Every resolved dispute is reported as
+    /// `LedgerEvent::AutoResolved`.
+    ///
+    /// Deposits held on arrival into a locked account are not disputes and stay held.
+    /// Like `unlock`, expiry is not journaled.
+    pub fn expire_disputes(&mut self, older_than_seq: u64) -> Vec<OpenDispute> {
+        let mut expired = self
+            .open_disputes()
+            .filter(|dispute| {
+                self.tx_states.get(&dispute.tx_id).is_some_and(|deposit| {
+                    deposit.state == TxState::Disputed
+                        && deposit
+                            .disputed_at
+                            .is_some_and(|disputed_at| disputed_at < older_than_seq)
+                })
+            })
+            .collect::<Vec<_>>();
+        expired.sort_unstable_by_key(|dispute| (dispute.client_id, dispute.tx_id));
+        expired.retain(|dispute| {
+            let user_account = self
+                .accounts
+                .get_mut(&dispute.client_id)
+                .expect("disputed deposits belong to existing accounts");
+            if transfer(
+                &mut user_account.held,
+                &mut user_account.available,
+                dispute.amount,
+            )
+            .is_err()
+            {
+                return false;
+            }
+            user_account.version += 1;
+            self.total_held -= dispute.amount;
+            self.total_available += dispute.amount;
+            let deposit = self
+                .tx_states
+                .get_mut(&dispute.tx_id)
+                .expect("open disputes have deposits");
+            deposit.state = TxState::Resolved;
+            deposit.disputed_at = None;
+            deposit.resolved = true;
+            close_dispute(&mut self.open_disputes, user_account, dispute.tx_id);
+            self.events.emit(LedgerEvent::AutoResolved {
+                client_id: dispute.client_id,
+                tx_id: dispute.tx_id,
+                amount: dispute.amount,
+            });
+            true
+        });
+        expired
+    }
+
+    /// Offset of the last line applied from a line protocol feed, used to skip redelivered lines.
+    pub fn applied_offset(&self) -> Option<u64> {
+        self.applied_offset
+    }
+
+    #[cfg(feature = "io")]
+    pub(crate) fn set_applied_offset(&mut self, offset: u64) {
+        self.applied_offset = Some(offset);
+    }
+
+    /// Total of the residual balances swept so far.
+    pub fn dust(&self) -> Decimal {
+        self.dust.balance
+    }
+
+    /// Moves positive available balances below `threshold` into the dust sub-account
+    /// and returns the swept amounts sorted by client id. Accounts with held funds are
+    /// left alone, and so are locked accounts unless `sweep_locked_dust` is configured.
+    pub fn sweep_dust(&mut self, threshold: Decimal) -> Vec<(ClientId, Decimal)> {
+        let mut swept = Vec::new();
+        for user_account in self.accounts.values_mut() {
+            let available = user_account.available.balance;
+            if available <= Decimal::ZERO
+                || available >= threshold
+                || user_account.held.balance != Decimal::ZERO
+                || (user_account.locked && !self.config.sweep_locked_dust)
+            {
+                continue;
+            }
+            make_tx(&mut user_account.available, &mut self.dust, available);
+            user_account.version += 1;
+            self.total_available -= available;
+            swept.push((user_account.client_id, available));
+        }
+        swept.sort_unstable();
+        swept
+    }
+
+    /// Sweeps dust with the threshold configured on the builder, meant to run right
+    /// before the accounts are written out.
+    pub fn sweep_configured_dust(&mut self) -> Vec<(ClientId, Decimal)> {
+        match self.config.dust_threshold {
+            Some(threshold) => self.sweep_dust(threshold),
+            None => Vec::new(),
+        }
+    }
+
+    /// Liabilities derived from scratch as the negated sum of the available and held
+    /// balances of every account and the swept dust. Sums beyond the range of
+    /// `Decimal` saturate.
+    pub fn recompute_liabilities(&self) -> Decimal {
+        -self
+            .accounts
+            .values()
+            .flat_map(|account| [account.available.balance, account.held.balance])
+            .chain([self.dust.balance])
+            .fold(Decimal::ZERO, Decimal::saturating_add)
+    }
+
+    /// Compares the liabilities maintained by every transaction with
+    /// `recompute_liabilities`, in a single pass over the accounts.
+    pub fn audit_liabilities(&self) -> Result<(), LiabilityDrift> {
+        let balances = self
+            .accounts
+            .values()
+            .flat_map(|account| [account.available.balance, account.held.balance]);
+        if sums_to_zero(balances.chain([self.liabilities.balance, self.dust.balance])) {
+            return Ok(());
+        }
+        let expected = self.recompute_liabilities();
+        Err(LiabilityDrift {
+            expected,
+            actual: self.liabilities.balance,
+            delta: self.liabilities.balance.saturating_sub(expected),
+        })
+    }
+
+    /// Sum of the available balances of every account, maintained by every change to
+    /// a balance. Sums beyond the range of `Decimal` saturate.
+    pub fn total_available(&self) -> Decimal {
+        self.total_available.to_decimal()
+    }
+
+    /// Sum of the held balances of every account, like `total_available`.
+    pub fn total_held(&self) -> Decimal {
+        self.total_held.to_decimal()
+    }
+
+    /// Sums of the available and held balances derived from scratch. Sums beyond the
+    /// range of `Decimal` saturate.
+    pub fn recompute_totals(&self) -> (Decimal, Decimal) {
+        self.accounts.values().fold(
+            (Decimal::ZERO, Decimal::ZERO),
+            |(available, held), account| {
+                (
+                    available.saturating_add(account.available.balance),
+                    held.saturating_add(account.held.balance),
+                )
+            },
+        )
+    }
+
+    /// Compares `total_available` and `total_held` with the account balances, summed
+    /// exactly like the running totals, and reports them with `recompute_totals` when
+    /// they differ.
+    pub fn audit_totals(&self) -> Result<(), TotalsDrift> {
+        let (mut available, mut held) = (ExactSum::default(), ExactSum::default());
+        for account in self.accounts.values() {
+            available += account.available.balance;
+            held += account.held.balance;
+        }
+        if (available, held) == (self.total_available, self.total_held) {
+            return Ok(());
+        }
+        let (expected_available, expected_held) = self.recompute_totals();
+        Err(TotalsDrift {
+            expected_available,
+            expected_held,
+            available: self.total_available(),
+            held: self.total_held(),
+        })
+    }
+
+    /// Checks that client totals and swept dust offset liabilities, that the running
+    /// totals match the accounts, that each held balance equals the sum of its
+    /// client's currently held deposits and open withdrawal holds, and that the open
+    /// dispute count of each account matches its disputed deposits in the index of
+    /// open disputes.
+    pub fn verify_invariants(&self) -> Result<(), InvariantViolation> {
+        if self.audit_liabilities().is_err() {
+            return Err(InvariantViolation::UnbalancedLiabilities {
+                liabilities: self.liabilities.balance,
+                accounts_total: self
+                    .accounts
+                    .values()
+                    .flat_map(|account| [account.available.balance, account.held.balance])
+                    .fold(Decimal::ZERO, Decimal::saturating_add),
+                dust: self.dust.balance,
+            });
+        }
+        self.audit_totals()
+            .map_err(InvariantViolation::RunningTotalsDrift)?;
+
+        let mut disputed: HashMap<ClientId, Decimal> = HashMap::new();
+        for deposit in self
+            .tx_states
+            .values()
+            .filter(|deposit| matches!(deposit.state, TxState::Disputed | TxState::HeldOnArrival))
+        {
+            *disputed.entry(deposit.client_id).or_default() += deposit.amount;
+        }
+        for hold in self.open_withdrawal_holds() {
+            *disputed.entry(hold.client_id).or_default() += hold.amount;
+        }
+        let mut client_ids = self.accounts.keys().copied().collect::<Vec<_>>();
+        client_ids.sort_unstable();
+        for client_id in client_ids {
+            let expected = disputed.get(&client_id).copied().unwrap_or_default();
+            let actual = self.accounts[&client_id].held.balance;
+            if expected != actual {
+                return Err(InvariantViolation::HeldBalanceInconsistent {
+                    client_id,
+                    expected,
+                    actual,
+                });
+            }
+            let indexed = self.open_disputes.get(&client_id).map_or(0, |tx_ids| {
+                tx_ids
+                    .iter()
+                    .filter(|tx_id| {
+                        self.tx_states
+                            .get(tx_id)
+                            .is_some_and(|deposit| deposit.state == TxState::Disputed)
+                    })
+                    .count()
+            });
+            let counted = self.accounts[&client_id].open_disputes;
+            if indexed != counted as usize {
+                return Err(InvariantViolation::OpenDisputeCountInconsistent {
+                    client_id,
+                    indexed,
+                    counted,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the balances and deposits of `from` into `into`, creating `into` if needed.
+    ///
+    /// The merged account is locked if either account was locked, and disputes of `from`
+    /// stay open under `into`, so they can still be resolved or charged back. The
+    /// velocity window of `from` is appended to the one of `into`, and the balance
+    /// thresholds `into` is below are updated without reporting breaches.
+    pub fn merge_accounts(&mut self, from: ClientId, into: ClientId) -> Result<(), MergeError> {
+        if from == into {
+            return Err(MergeError::CyclicRemap(from));
+        }
+        let mut source = self
+            .accounts
+            .remove(&from)
+            .ok_or(MergeError::ClientAccountNotFound(from))?;
+        let overdraft_limit = self.config.overdraft_limit_of(into);
+        let target = self.accounts.get_or_insert_with(into, || {
+            UserAccount::with_overdraft_limit(into, overdraft_limit)
+        });
+        let available = source.available.balance;
+        make_tx(&mut source.available, &mut target.available, available);
+        let held = source.held.balance;
+        make_tx(&mut source.held, &mut target.held, held);
+        target.locked |= source.locked;
+        target.deposited = target.deposited.saturating_add(source.deposited);
+        target.withdrawn = target.withdrawn.saturating_add(source.withdrawn);
+        target.charged_back = target.charged_back.saturating_add(source.charged_back);
+        target.chargebacks = target.chargebacks.saturating_add(source.chargebacks);
+        target.open_disputes += source.open_disputes;
+        target.disputes_opened = target
+            .disputes_opened
+            .saturating_add(source.disputes_opened);
+        target.disputes_resolved = target
+            .disputes_resolved
+            .saturating_add(source.disputes_resolved);
+        target.disputes_charged_back = target
+            .disputes_charged_back
+            .saturating_add(source.disputes_charged_back);
+        target.created_by_rejection &= source.created_by_rejection;
+        target.version += 1;
+
+        for deposit in self
+            .tx_states
+            .values_mut()
+            .filter(|deposit| deposit.client_id == from)
+        {
+            deposit.client_id = into;
+        }
+        if let Some(tx_ids) = self.open_disputes.remove(&from) {
+            self.open_disputes.entry(into).or_default().extend(tx_ids);
+        }
+        for hold in self
+            .withdrawal_holds
+            .values_mut()
+            .filter(|hold| hold.client_id == from)
+        {
+            hold.client_id = into;
+        }
+        self.velocity
+            .move_window(from, into, self.config.velocity_limit.as_ref());
+        self.balance_watch.remove(from);
+        self.rewatch_balance(into);
+        Ok(())
+    }
+
+    /// Merges every account in the map into its target, following chains of remapped
+    /// ids to the final target. Ids without an account are ignored.
+    ///
+    /// Nothing is merged when the map contains a cycle.
+    pub fn apply_client_remap(
+        &mut self,
+        remap: &HashMap<ClientId, ClientId>,
+    ) -> Result<(), MergeError> {
+        let mut merges = Vec::with_capacity(remap.len());
+        for &from in remap.keys() {
+            let mut visited = HashSet::from([from]);
+            let mut into = remap[&from];
+            while let Some(&next) = remap.get(&into) {
+                if !visited.insert(into) {
+                    return Err(MergeError::CyclicRemap(from));
+                }
+                into = next;
+            }
+            merges.push((from, into));
+        }
+        merges.sort_unstable();
+
+        for (from, into) in merges {
+            if self.accounts.contains_key(&from) {
+                self.merge_accounts(from, into)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Combines the ledgers of two shards or files, see `merge_with_policy`. Accounts
+    /// present in both ledgers are a conflict.
+    pub fn merge(self, other: Ledger) -> Result<Ledger, MergeConflict> {
+        self.merge_with_policy(other, MergePolicy::FailOnOverlap)
+    }
+
+    /// Combines the ledgers of two shards or files. Accounts of only one ledger are
+    /// taken as they are, and accounts of both are combined following `policy`.
+    /// Deposits and withdrawal holds must have distinct ids across the ledgers, so
+    /// that disputes and settlements of either still find them, and deposits must
+    /// not reuse the tombstoned ids of the other ledger. Tombstones of both ledgers
+    /// are kept. Liabilities and dust add up, which keeps the invariants of both
+    /// ledgers.
+    ///
+    /// The configuration, event listener, journal and applied offset of `self` are
+    /// kept, and `tx_seq` is the larger of both. Like `merge_accounts`, merging is not
+    /// journaled.
+    pub fn merge_with_policy(
+        mut self,
+        mut other: Ledger,
+        policy: MergePolicy,
+    ) -> Result<Ledger, MergeConflict> {
+        let duplicate_tx = other
+            .tx_states
+            .keys()
+            .filter(|tx_id| {
+                self.tx_states.contains_key(tx_id) || self.tombstones.contains_key(tx_id)
+            })
+            .chain(
+                self.tx_states
+                    .keys()
+                    .filter(|tx_id| other.tombstones.contains_key(tx_id)),
+            )
+            .chain(
+                other
+                    .withdrawal_holds
+                    .keys()
+                    .filter(|tx_id| self.withdrawal_holds.contains_key(tx_id)),
+            )
+            .min();
+        if let Some(&tx_id) = duplicate_tx {
+            return Err(MergeConflict::DuplicateTx(tx_id));
+        }
+        let mut client_ids = other.accounts.keys().copied().collect::<Vec<_>>();
+        client_ids.sort_unstable();
+        if policy == MergePolicy::FailOnOverlap {
+            if let Some(&client_id) = client_ids
+                .iter()
+                .find(|client_id| self.accounts.contains_key(client_id))
+            {
+                return Err(MergeConflict::OverlappingAccount(client_id));
+            }
+        }
+
+        let add = |a: Decimal, b: Decimal| a.checked_add(b).ok_or(MergeConflict::BalanceOverflow);
+        self.liabilities.balance = add(self.liabilities.balance, other.liabilities.balance)?;
+        self.dust.balance = add(self.dust.balance, other.dust.balance)?;
+        self.total_available += other.total_available;
+        self.total_held += other.total_held;
+        for client_id in client_ids {
+            let source = other
+                .accounts
+                .remove(&client_id)
+                .expect("listed from the accounts above");
+            if !self.accounts.contains_key(&client_id) {
+                self.accounts.get_or_insert_with(client_id, || source);
+                self.rewatch_balance(client_id);
+                continue;
+            }
+            let target = self
+                .accounts
+                .get_mut(&client_id)
+                .expect("checked to exist above");
+            target.available.balance = add(target.available.balance, source.available.balance)?;
+            target.held.balance = add(target.held.balance, source.held.balance)?;
+            target.locked |= source.locked;
+            target.deposited = target.deposited.saturating_add(source.deposited);
+            target.withdrawn = target.withdrawn.saturating_add(source.withdrawn);
+            target.charged_back = target.charged_back.saturating_add(source.charged_back);
+            target.chargebacks = target.chargebacks.saturating_add(source.chargebacks);
+            target.open_disputes += source.open_disputes;
+            target.disputes_opened = target
+                .disputes_opened
+                .saturating_add(source.disputes_opened);
+            target.disputes_resolved = target
+                .disputes_resolved
+                .saturating_add(source.disputes_resolved);
+            target.disputes_charged_back = target
+                .disputes_charged_back
+                .saturating_add(source.disputes_charged_back);
+            target.version += 1;
+            self.rewatch_balance(client_id);
+        }
+
+        self.tx_states.extend(other.tx_states);
+        self.tombstones.extend(other.tombstones);
+        self.withdrawal_references += other.withdrawal_references;
+        self.withdrawal_reference_bytes += other.withdrawal_reference_bytes;
+        for (client_id, tx_ids) in other.open_disputes {
+            self.open_disputes
+                .entry(client_id)
+                .or_default()
+                .extend(tx_ids);
+        }
+        self.withdrawal_holds.extend(other.withdrawal_holds);
+        self.velocity
+            .merge(other.velocity, self.config.velocity_limit.as_ref());
+        self.dispute_amount_mismatches += other.dispute_amount_mismatches;
+        self.unknown_dispute_reasons += other.unknown_dispute_reasons;
+        if let (Some(tracker), Some(other_tracker)) = (self.causality.as_mut(), other.causality) {
+            tracker.merge(other_tracker);
+        }
+        self.poisoned |= other.poisoned;
+        self.tx_seq = self.tx_seq.max(other.tx_seq);
+        self.derived_postings = self.derived_postings.max(other.derived_postings);
+        Ok(self)
+    }
+
+    /// Drops the account of `client_id` and the states of its deposits and
+    /// withdrawal holds, e.g. to enforce data retention for closed accounts. Only
+    /// accounts without any funds, open disputes and open withdrawal holds can be
+    /// removed.
+    ///
+    /// Later disputes of the client's deposits fail like disputes of unknown ones,
+    /// and a later deposit opens a new account. Like `unlock`, removal is not
+    /// journaled.
+    pub fn remove_account(&mut self, client_id: ClientId) -> Result<RemovedAccount, RemoveError> {
+        let user_account = self
+            .accounts
+            .get(&client_id)
+            .ok_or(RemoveError::ClientAccountNotFound(client_id))?;
+        if let Some(tx_id) = self
+            .open_disputes
+            .get(&client_id)
+            .and_then(|tx_ids| tx_ids.iter().min())
+        {
+            return Err(RemoveError::OpenDispute(*tx_id));
+        }
+        if let Some(tx_id) = self
+            .open_withdrawal_holds()
+            .filter(|hold| hold.client_id == client_id)
+            .map(|hold| hold.tx_id)
+            .min()
+        {
+            return Err(RemoveError::OpenWithdrawalHold(tx_id));
+        }
+        if !user_account.available.balance.is_zero() || !user_account.held.balance.is_zero() {
+            return Err(RemoveError::NonZeroBalance {
+                available: user_account.available.balance,
+                held: user_account.held.balance,
+            });
+        }
+
+        let user_account = self
+            .accounts
+            .remove(&client_id)
+            .expect("account was found above");
+        let mut deposits_removed = 0;
+        let tombstone_resolved = self.config.tombstone_resolved;
+        self.tx_states.retain(|tx_id, record| {
+            if record.client_id != client_id {
+                return true;
+            }
+            if let Some(state) = record.settlement(tombstone_resolved) {
+                self.tombstones.insert(*tx_id, state);
+            }
+            if let Some(len) = record.reference_len() {
+                self.withdrawal_references -= 1;
+                self.withdrawal_reference_bytes -= len;
+            }
+            deposits_removed += usize::from(record.kind == TxTypeTag::Deposit);
+            false
+        });
+        self.withdrawal_holds
+            .retain(|_tx_id, hold| hold.client_id != client_id);
+        self.velocity.remove(client_id);
+        self.balance_watch.remove(client_id);
+        Ok(RemovedAccount {
+            account: AccountView::from(&user_account),
+            deposits_removed,
+        })
+    }
+
+    /// Drops the states of resolved, never disputed and charged back deposits for
+    /// which `predicate` returns true, and returns how many were dropped. Disputes,
+    /// resolves, chargebacks and adjustments of a dropped deposit fail with
+    /// `TxError::OriginTxNotFound` afterwards.
+    ///
+    /// Disputed, reversed and held on arrival deposits are kept, since their funds
+    /// may still move. The ids of dropped charged back deposits, and of resolved ones
+    /// under `LedgerBuilder::tombstone_resolved_deposits`, are kept as tombstones, so
+    /// that later deposits reusing them still fail with
+    /// `TxError::TxIdReuseAfterSettlement`.
+    pub fn prune_settled_tx_states(
+        &mut self,
+        mut predicate: impl FnMut(&SettledDeposit) -> bool,
+    ) -> usize {
+        let deposits = self.tx_states.len();
+        let tombstone_resolved = self.config.tombstone_resolved;
+        self.tx_states.retain(|tx_id, deposit| {
+            if deposit.kind != TxTypeTag::Deposit {
+                return true;
+            }
+            let charged_back = match deposit.state {
+                TxState::Resolved => false,
+                TxState::ChargedBack => true,
+                TxState::Disputed | TxState::HeldOnArrival | TxState::Reversed => return true,
+            };
+            let dropped = predicate(&SettledDeposit {
+                client_id: deposit.client_id,
+                tx_id: deposit.tx_id,
+                amount: deposit.amount,
+                charged_back,
+            });
+            if !dropped {
+                return true;
+            }
+            if let Some(state) = deposit.settlement(tombstone_resolved) {
+                self.tombstones.insert(*tx_id, state);
+            }
+            // Only disputed deposits are counted, and those are never dropped
+            unindex_open_dispute(&mut self.open_disputes, deposit.client_id, *tx_id);
+            false
+        });
+        deposits - self.tx_states.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn inject_liability_drift(&mut self, delta: Decimal) {
+        self.liabilities.balance += delta;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn move_held_to_available(&mut self, client_id: ClientId, amount: Decimal) {
+        let user_account = self.accounts.get_mut(&client_id).unwrap();
+        make_tx(&mut user_account.held, &mut user_account.available, amount);
+        self.total_held -= amount;
+        self.total_available += amount;
+    }
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InvariantViolation {
+    UnbalancedLiabilities {
+        liabilities: Decimal,
+        accounts_total: Decimal,
+        dust: Decimal,
+    },
+    HeldBalanceInconsistent {
+        client_id: ClientId,
+        expected: Decimal,
+        actual: Decimal,
+    },
+    RunningTotalsDrift(TotalsDrift),
+    /// `UserAccount::open_disputes` differs from the disputed deposits of the client
+    /// in `Ledger::open_disputes`.
+    OpenDisputeCountInconsistent {
+        client_id: ClientId,
+        indexed: usize,
+        counted: u32,
+    },
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvariantViolation::UnbalancedLiabilities {
+                liabilities,
+                accounts_total,
+                dust,
+            } => write!(
+                f,
+                "liabilities {} do not offset client totals {} and dust {}",
+                liabilities, accounts_total, dust
+            ),
+            InvariantViolation::HeldBalanceInconsistent {
+                client_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {} held balance {} differs from disputed amount {}",
+                client_id, actual, expected
+            ),
+            InvariantViolation::RunningTotalsDrift(drift) => drift.fmt(f),
+            InvariantViolation::OpenDisputeCountInconsistent {
+                client_id,
+                indexed,
+                counted,
+            } => write!(
+                f,
+                "client {} counts {} open disputes but has {}",
+                client_id, counted, indexed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// Liabilities maintained by the ledger which differ from those recomputed from
+/// the account balances.
+#[derive(Debug, PartialEq)]
+pub struct LiabilityDrift {
+    /// Liabilities recomputed by `Ledger::recompute_liabilities`.
+    pub expected: Decimal,
+    pub actual: Decimal,
+    /// `actual - expected`.
+    pub delta: Decimal,
+}
+
+impl Display for LiabilityDrift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "liabilities {} drifted by {} from {} recomputed from account balances",
+            self.actual, self.delta, self.expected
+        )
+    }
+}
+
+impl std::error::Error for LiabilityDrift {}
+
+/// Running totals of the available and held balances which differ from those
+/// recomputed from the accounts.
+#[derive(Debug, PartialEq)]
+pub struct TotalsDrift {
+    /// Totals recomputed by `Ledger::recompute_totals`.
+    pub expected_available: Decimal,
+    pub expected_held: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+impl Display for TotalsDrift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "running totals available {} and held {} differ from {} and {} recomputed from \
+             account balances",
+            self.available, self.held, self.expected_available, self.expected_held
+        )
+    }
+}
+
+impl std::error::Error for TotalsDrift {}
+
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+    ClientAccountNotFound(ClientId),
+    /// Following the remapping from this client id leads back to it.
+    CyclicRemap(ClientId),
+}
+
+impl Display for MergeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::ClientAccountNotFound(client_id) => {
+                write!(f, "client {} has no account to merge", client_id)
+            }
+            MergeError::CyclicRemap(client_id) => {
+                write!(f, "remapping of client {} is cyclic", client_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+/// Treatment of accounts present in both ledgers of `Ledger::merge_with_policy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum MergePolicy {
+    /// Adds up the balances and activity totals, locking the account if either was
+    /// locked. Only valid when each transaction of the client went to exactly one of
+    /// the ledgers, together with the disputes of its deposits.
+    SumBalances,
+    #[default]
+    FailOnOverlap,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MergeConflict {
+    /// Both ledgers have a deposit, or both a withdrawal hold, with this id, or one
+    /// ledger has a deposit with an id tombstoned by the other.
+    DuplicateTx(TxId),
+    /// Both ledgers have an account of this client under `MergePolicy::FailOnOverlap`.
+    OverlappingAccount(ClientId),
+    /// A merged balance exceeds the range of `Decimal`.
+    BalanceOverflow,
+}
+
+impl Display for MergeConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeConflict::DuplicateTx(tx_id) => {
+                write!(f, "transaction {} is in both ledgers", tx_id)
+            }
+            MergeConflict::OverlappingAccount(client_id) => {
+                write!(f, "client {} has an account in both ledgers", client_id)
+            }
+            MergeConflict::BalanceOverflow => {
+                write!(f, "merged balances exceed the range of Decimal")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeConflict {}
+
+#[derive(Debug, PartialEq)]
+pub enum RemoveError {
+    ClientAccountNotFound(ClientId),
+    /// The deposit with this id is still disputed or held on arrival.
+    OpenDispute(TxId),
+    /// The withdrawal authorized with this id was neither captured nor released.
+    OpenWithdrawalHold(TxId),
+    NonZeroBalance {
+        available: Decimal,
+        held: Decimal,
+    },
+}
+
+impl Display for RemoveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoveError::ClientAccountNotFound(client_id) => {
+                write!(f, "client {} has no account to remove", client_id)
+            }
+            RemoveError::OpenDispute(tx_id) => {
+                write!(f, "deposit {} of the account is still held", tx_id)
+            }
+            RemoveError::OpenWithdrawalHold(tx_id) => {
+                write!(f, "withdrawal {} of the account is still held", tx_id)
+            }
+            RemoveError::NonZeroBalance { available, held } => write!(
+                f,
+                "account still has available {} and held {}",
+                available, held
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RemoveError {}
+
+/// Adds `tx_id` to the open disputes of the account, keeping its count in step.
+fn open_dispute(
+    open_disputes: &mut HashMap<ClientId, HashSet<TxId>>,
+    user_account: &mut UserAccount,
+    tx_id: TxId,
+) {
+    if index_open_dispute(open_disputes, user_account.client_id, tx_id) {
+        user_account.open_disputes += 1;
+    }
+}
+
+fn close_dispute(
+    open_disputes: &mut HashMap<ClientId, HashSet<TxId>>,
+    user_account: &mut UserAccount,
+    tx_id: TxId,
+) {
+    if unindex_open_dispute(open_disputes, user_account.client_id, tx_id) {
+        user_account.open_disputes -= 1;
+    }
+}
+
+/// Adds `tx_id` to the open disputes of `client_id` without counting it, as for
+/// deposits held on arrival, and returns whether it was added.
+fn index_open_dispute(
+    open_disputes: &mut HashMap<ClientId, HashSet<TxId>>,
+    client_id: ClientId,
+    tx_id: TxId,
+) -> bool {
+    open_disputes.entry(client_id).or_default().insert(tx_id)
+}
+
+/// Removes `tx_id` from the open disputes of `client_id` without counting it, as for
+/// deposits held on arrival and records which are dropped, and returns whether it
+/// was there.
+fn unindex_open_dispute(
+    open_disputes: &mut HashMap<ClientId, HashSet<TxId>>,
+    client_id: ClientId,
+    tx_id: TxId,
+) -> bool {
+    let Some(tx_ids) = open_disputes.get_mut(&client_id) else {
+        return false;
+    };
+    let removed = tx_ids.remove(&tx_id);
+    if tx_ids.is_empty() {
+        open_disputes.remove(&client_id);
+    }
+    removed
+}
+
+fn make_tx(source: &mut SubAccount, destination: &mut SubAccount, amount: Decimal) {
+    source.balance -= amount;
+    destination.balance += amount;
+}
+
+/// Sum of `a` and `b`, or `None` when it overflows or has to be rounded to fit, which
+/// lowers its scale. Zero sums are exact but lose their scale too.
+fn exact_add(a: Decimal, b: Decimal) -> Option<Decimal> {
+    a.checked_add(b)
+        .filter(|sum| sum.is_zero() || sum.scale() >= a.scale().max(b.scale()))
+}
+
+/// Whether `terms` add up to exactly zero.
+fn sums_to_zero(terms: impl Iterator<Item = Decimal>) -> bool {
+    let mut sum = ExactSum::default();
+    for term in terms {
+        sum += term;
+    }
+    sum == ExactSum::default()
+}
+
+/// Sum of `Decimal`s whose integer and fractional parts are summed separately as
+/// `i128`, which neither overflows nor rounds like a `Decimal` sum. The fraction is
+/// carried into the integer after every term, so equal sums compare equal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ExactSum {
+    integer: i128,
+    /// Fraction in units of `10^-FRACTION_SCALE`, in `0..10^FRACTION_SCALE`.
+    fraction: i128,
+}
+
+impl ExactSum {
+    const FRACTION_SCALE: u32 = 28;
+    const UNIT: i128 = 10i128.pow(Self::FRACTION_SCALE);
+
+    /// The normalized sum as a `Decimal`, saturating beyond its range and rounding
+    /// fractions which do not fit next to a large integer part.
+    fn to_decimal(self) -> Decimal {
+        let Ok(integer) = Decimal::try_from_i128_with_scale(self.integer, 0) else {
+            return match self.integer.is_negative() {
+                true => Decimal::MIN,
+                false => Decimal::MAX,
+            };
+        };
+        let fraction = Decimal::from_i128_with_scale(self.fraction, Self::FRACTION_SCALE);
+        integer.saturating_add(fraction).normalize()
+    }
+}
+
+impl AddAssign<Decimal> for ExactSum {
+    fn add_assign(&mut self, term: Decimal) {
+        let mut fraction = term.fract();
+        fraction.rescale(Self::FRACTION_SCALE);
+        let fraction = self.fraction + fraction.mantissa();
+        self.integer += term.trunc().mantissa() + fraction.div_euclid(Self::UNIT);
+        self.fraction = fraction.rem_euclid(Self::UNIT);
+    }
+}
+
+impl SubAssign<Decimal> for ExactSum {
+    fn sub_assign(&mut self, term: Decimal) {
+        *self += -term;
+    }
+}
+
+impl AddAssign for ExactSum {
+    fn add_assign(&mut self, other: ExactSum) {
+        let fraction = self.fraction + other.fraction;
+        self.integer += other.integer + fraction.div_euclid(Self::UNIT);
+        self.fraction = fraction.rem_euclid(Self::UNIT);
+    }
+}
+
+/// Like `make_tx`, but fails without changing either balance when one of them would
+/// overflow or lose precision.
+fn transfer(
+    source: &mut SubAccount,
+    destination: &mut SubAccount,
+    amount: Decimal,
+) -> Result<(), TxError> {
+    let balances = exact_add(source.balance, -amount).zip(exact_add(destination.balance, amount));
+    let (source_balance, destination_balance) = balances.ok_or(TxError::BalanceOverflow)?;
+    source.balance = source_balance;
+    destination.balance = destination_balance;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accounting::config::{
+        AccountTiering, ChargebackPolicy, DisputeAmountHandling, DisputeLimit, DisputeTracking,
+        DuplicateDisputePolicy, LockPolicy, ReasonAllowlist, UnknownClientOnDebit,
+        UnknownReasonAction, VelocityAction, VelocityLimit, WindowSpec,
+    };
+    use crate::accounting::events::LedgerEvent;
+    use crate::accounting::executable_tx::TxError;
+    use crate::accounting::reasons::DisputeReason;
+    use crate::accounting::sampling::{LiabilitySample, LiabilitySampling};
+    use crate::accounting::transactions::TxTypeTag;
+    use crate::accounting::transactions::{
+        Adjustment, Chargeback, Deposit, Dispute, Resolve, Settle, Transaction, WithdrawAuth,
+        WithdrawCapture, WithdrawRelease, Withdrawal,
+    };
+    use crate::accounting::velocity::VelocityWindowState;
+    use crate::accounting::{
+        AccountLog, AccountView, ExecutableTransaction, InvariantViolation, Ledger, LiabilityDrift,
+        MergeConflict, MergeError, MergePolicy, OpenDispute, OpenWithdrawalHold, RemoveError,
+        RemovedAccount, TotalsDrift, TxOutcome, TxRecord, TxState, UnsettledWithdrawal,
+    };
+    use crate::clock::{ManualClock, Timestamp};
+    use crate::core_types::{ClientId, TxId};
+    use crate::scenario::ScenarioBuilder;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn verify_balances(ledger: &Ledger, client_id: ClientId, available: Decimal, held: Decimal) {
+        let user_account = ledger.accounts.get(&client_id).unwrap();
+        assert_eq!(user_account.available.balance, available);
+        assert_eq!(user_account.held.balance, held);
+        assert_eq!(user_account.available.balance, available);
+        assert!(ledger.is_poisoned() || ledger.audit_liabilities().is_ok());
+    }
+
+    fn verify_liabilities(ledger: &Ledger, liabilities: Decimal) {
+        assert_eq!(ledger.liabilities.balance, liabilities);
+        assert_eq!(ledger.recompute_liabilities(), liabilities);
+        assert_eq!(ledger.audit_liabilities(), Ok(()));
+        assert_eq!(ledger.audit_totals(), Ok(()));
+    }
+
+    fn verify_account_locked(ledger: &Ledger, client_id: ClientId) {
+        let locked = ledger.accounts.get(&client_id).unwrap().locked;
+        assert!(locked);
+    }
+
+    fn verify_account_not_locked(ledger: &Ledger, client_id: ClientId) {
+        let locked = ledger.accounts.get(&client_id).unwrap().locked;
+        assert!(!locked);
+    }
+
+    #[test]
+    fn deposits_and_withdrawals_only() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(1.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(1.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-1.0));
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(2, 2, dec!(2.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(1.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-3.0));
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 3, dec!(2.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(3.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-5.0));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(1.5))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(1.5), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-3.5));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(2, 5, dec!(3.0)))),
+            Err(TxError::InsufficientFunds)
+        );
+        verify_balances(&ledger, 1, dec!(1.5), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-3.5));
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(3, 6, dec!(100.0001))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(1.5), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
+        verify_balances(&ledger, 3, dec!(100.0001), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-103.5001));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(
+                3,
+                7,
+                dec!(50.0002)
+            )))
+            .is_ok());
         verify_balances(&ledger, 1, dec!(1.5), dec!(0.0));
         verify_balances(&ledger, 2, dec!(2.0), dec!(0.0));
         verify_balances(&ledger, 3, dec!(49.9999), dec!(0.0));
@@ -217,448 +2391,3203 @@ mod tests {
     }
 
     #[test]
-    fn dispute_resolved() {
+    fn dispute_resolved() {
+        ScenarioBuilder::new()
+            .deposit(1, 1, "50.0")
+            .expect_balance(1, "50.0", "0.0")
+            .expect_liabilities("-50.0")
+            .deposit(1, 2, "30.0")
+            .expect_balance(1, "80.0", "0.0")
+            .expect_liabilities("-80.0")
+            .withdraw(1, 3, "20.0")
+            .expect_balance(1, "60.0", "0.0")
+            .expect_liabilities("-60.0")
+            .dispute(1, 2)
+            .expect_balance(1, "30.0", "30.0")
+            .expect_liabilities("-60.0")
+            .resolve(1, 2)
+            .expect_balance(1, "60.0", "0.0")
+            .expect_liabilities("-60.0")
+            .run();
+    }
+
+    #[test]
+    fn already_disputed() {
+        ScenarioBuilder::new()
+            .deposit(1, 1, "50.0")
+            .deposit(1, 2, "30.0")
+            .withdraw(1, 3, "20.0")
+            .dispute(1, 2)
+            .expect_balance(1, "30.0", "30.0")
+            .expect_liabilities("-60.0")
+            .dispute(1, 2)
+            .expect_err(TxError::TxAlreadyDisputed)
+            .expect_balance(1, "30.0", "30.0")
+            .expect_liabilities("-60.0")
+            .resolve(1, 2)
+            .expect_balance(1, "60.0", "0.0")
+            .expect_liabilities("-60.0")
+            .run();
+    }
+
+    #[test]
+    fn insufficient_funds_while_disputed() {
+        ScenarioBuilder::new()
+            .deposit(1, 1, "50.0")
+            .deposit(1, 2, "30.0")
+            .dispute(1, 2)
+            .expect_balance(1, "50.0", "30.0")
+            .expect_liabilities("-80.0")
+            .withdraw(1, 3, "60.0")
+            .expect_err(TxError::InsufficientFunds)
+            .expect_balance(1, "50.0", "30.0")
+            .expect_liabilities("-80.0")
+            .run();
+    }
+
+    #[test]
+    fn client_account_not_found() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(2, 2, dec!(60.0)))),
+            Err(TxError::ClientAccountNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(2, 2))),
+            Err(TxError::ClientAccountNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(2, 2))),
+            Err(TxError::ClientAccountNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(2, 2))),
+            Err(TxError::ClientAccountNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+    }
+
+    #[test]
+    fn origin_tx_not_found() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(30.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 3))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 3))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 3))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        // Withdrawals are recorded for settlement but cannot be disputed
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
+            Err(TxError::TxNotDisputable)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
+            Err(TxError::TxNotDisputable)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 2))),
+            Err(TxError::TxNotDisputable)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+    }
+
+    #[test]
+    fn tx_not_disputed() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(30.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(-30.0), dec!(50.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert!(ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+    }
+
+    #[test]
+    fn chargeback() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(80.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-80.0));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-60.0));
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(30.0));
+        verify_liabilities(&ledger, dec!(-60.0));
+
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 2)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-30.0));
+        verify_account_locked(&ledger, 1);
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(2, 4, dec!(60.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(60.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-90.0));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(2, 5, dec!(20.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(40.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(2, 4)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(-20.0), dec!(60.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(2, 4)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(-20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-10.0));
+        verify_account_locked(&ledger, 1);
+        verify_account_locked(&ledger, 2);
+    }
+
+    #[test]
+    fn ops_after_chargeback() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(80.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-80.0));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-60.0));
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(30.0));
+        verify_liabilities(&ledger, dec!(-60.0));
+
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 2)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-30.0));
+        verify_account_locked(&ledger, 1);
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 4, dec!(40.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(1, 5, dec!(40.0)))),
+            Err(TxError::ClientAccountLocked)
+        );
+        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
+            Err(TxError::TxAlreadyDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
+            Err(TxError::TxNotDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 2))),
+            Err(TxError::TxNotDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(50.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert!(ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(50.0));
+        verify_liabilities(&ledger, dec!(-70.0));
+        verify_account_locked(&ledger, 1);
+
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+        verify_account_locked(&ledger, 1);
+    }
+
+    #[test]
+    fn mismatch_client_id_and_tx_id() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(2, 2, dec!(30.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-80.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-80.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-80.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 2))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-80.0));
+
+        verify_account_not_locked(&ledger, 1);
+        verify_account_not_locked(&ledger, 2);
+    }
+
+    #[test]
+    fn held_balance_inconsistent() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(0.0), dec!(50.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+
+        ledger.move_held_to_available(1, dec!(30.0));
+        verify_balances(&ledger, 1, dec!(30.0), dec!(20.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+        assert_eq!(
+            ledger.verify_invariants(),
+            Err(InvariantViolation::HeldBalanceInconsistent {
+                client_id: 1,
+                expected: dec!(50.0),
+                actual: dec!(20.0),
+            })
+        );
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::HeldBalanceInconsistent {
+                expected: dec!(50.0),
+                actual: dec!(20.0),
+            })
+        );
+        verify_balances(&ledger, 1, dec!(30.0), dec!(20.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 1))),
+            Err(TxError::HeldBalanceInconsistent {
+                expected: dec!(50.0),
+                actual: dec!(20.0),
+            })
+        );
+        verify_balances(&ledger, 1, dec!(30.0), dec!(20.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+        verify_account_not_locked(&ledger, 1);
+
+        ledger.move_held_to_available(1, dec!(-30.0));
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(0.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(0.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn hold_incoming_while_locked() {
+        let mut ledger = Ledger::builder()
+            .lock_policy(LockPolicy::HoldIncoming)
+            .build();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(0.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(0.0));
+        verify_account_locked(&ledger, 1);
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(40.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(0.0), dec!(40.0));
+        verify_liabilities(&ledger, dec!(-40.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
+            Err(TxError::ClientAccountLocked)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
+            Err(TxError::TxAlreadyDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(0.0), dec!(40.0));
+        verify_liabilities(&ledger, dec!(-40.0));
+
+        assert!(ledger.unlock(1).is_ok());
+        verify_account_not_locked(&ledger, 1);
+        assert!(ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 2)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(40.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-40.0));
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
+            Err(TxError::TxNotDisputed)
+        );
+
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 3, dec!(10.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-50.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn held_on_arrival_charged_back() {
+        let mut ledger = Ledger::builder()
+            .lock_policy(LockPolicy::HoldIncoming)
+            .build();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(40.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(0.0), dec!(40.0));
+        verify_liabilities(&ledger, dec!(-40.0));
+
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 2)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(0.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(0.0));
+        verify_account_locked(&ledger, 1);
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn unlock_unknown_account() {
+        let mut ledger = Ledger::new();
+        assert_eq!(ledger.unlock(1), Err(TxError::ClientAccountNotFound));
+    }
+
+    #[test]
+    fn locked_accounts_take_no_withdrawals() {
+        let mut ledger = Ledger::new();
+        assert_eq!(ledger.lock(1), Err(TxError::ClientAccountNotFound));
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(5.0))))
+            .is_ok());
+        assert_eq!(ledger.lock(1), Ok(()));
+        verify_account_locked(&ledger, 1);
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(1.0)))),
+            Err(TxError::ClientAccountLocked)
+        );
+    }
+
+    #[test]
+    fn merge_with_open_dispute() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(20.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(2, 3, dec!(5.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+
+        assert_eq!(ledger.merge_accounts(1, 2), Ok(()));
+        assert!(ledger.account(1).is_none());
+        verify_balances(&ledger, 2, dec!(25.0), dec!(50.0));
+        verify_liabilities(&ledger, dec!(-75.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 1))),
+            Err(TxError::ClientAccountNotFound)
+        );
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(2, 1)))
+            .is_ok());
+        verify_balances(&ledger, 2, dec!(25.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-25.0));
+        verify_account_locked(&ledger, 2);
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(2, 2)))
+            .is_ok());
+        verify_balances(&ledger, 2, dec!(5.0), dec!(20.0));
+        assert_eq!(ledger.open_disputes().count(), 1);
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn merges_carry_velocity_windows_and_balance_watches() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let ledger = || {
+            let received = events.clone();
+            Ledger::builder()
+                .velocity_limit(VelocityLimit {
+                    max_total: dec!(100),
+                    window: WindowSpec::Transactions(3),
+                    action: VelocityAction::Reject,
+                })
+                .overdraft_limit(dec!(-100))
+                .balance_thresholds([dec!(0)])
+                .event_listener(move |event| {
+                    if let LedgerEvent::ThresholdBreached { client_id, .. } = event {
+                        received.lock().unwrap().push(*client_id);
+                    }
+                    Ok(())
+                })
+                .build()
+        };
+        let txs = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(40))),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(45))),
+            Transaction::Deposit(Deposit::new(2, 3, dec!(50))),
+            Transaction::Withdrawal(Withdrawal::new(2, 4, dec!(48))),
+        ];
+        // After the merge, 2 has the deposits of 40 and 50 in its window and is
+        // below 0 already, so the withdrawal reports no breach.
+        let check = |merged: &mut Ledger| {
+            assert_eq!(
+                merged.velocity_windows(),
+                [VelocityWindowState {
+                    client_id: 2,
+                    recent_deposits: vec![dec!(50), dec!(40)],
+                }]
+            );
+            assert_eq!(
+                merged.execute(&Transaction::Deposit(Deposit::new(2, 5, dec!(20)))),
+                Err(TxError::VelocityLimitExceeded)
+            );
+            merged
+                .execute(&Transaction::Withdrawal(Withdrawal::new(2, 6, dec!(1))))
+                .unwrap();
+            merged
+                .execute(&Transaction::Deposit(Deposit::new(2, 7, dec!(5))))
+                .unwrap();
+            assert!(events.lock().unwrap().is_empty());
+            assert_eq!(merged.verify_invariants(), Ok(()));
+        };
+
+        let mut merged = ledger();
+        for tx in &txs {
+            merged.execute(tx).unwrap();
+        }
+        merged.merge_accounts(1, 2).unwrap();
+        verify_balances(&merged, 2, dec!(-3), dec!(0));
+        assert_eq!(*events.lock().unwrap(), [1]);
+        events.lock().unwrap().clear();
+        check(&mut merged);
+
+        // Both ledgers know client 2, which is below 0 only in the second
+        let (mut shard, mut other) = (ledger(), ledger());
+        for tx in &txs[2..] {
+            shard.execute(tx).unwrap();
+        }
+        for tx in [
+            Transaction::Deposit(Deposit::new(2, 1, dec!(40))),
+            Transaction::Withdrawal(Withdrawal::new(2, 2, dec!(45))),
+        ] {
+            other.execute(&tx).unwrap();
+        }
+        assert_eq!(*events.lock().unwrap(), [2]);
+        events.lock().unwrap().clear();
+        let mut merged = shard
+            .merge_with_policy(other, MergePolicy::SumBalances)
+            .unwrap();
+        verify_balances(&merged, 2, dec!(-3), dec!(0));
+        check(&mut merged);
+    }
+
+    #[test]
+    fn merge_locked_accounts() {
+        let mut ledger = Ledger::new();
+        for (client_id, tx_id) in [(1, 1), (2, 2)] {
+            assert!(ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    tx_id,
+                    dec!(10.0)
+                )))
+                .is_ok());
+            assert!(ledger
+                .execute(&Transaction::Dispute(Dispute::new(client_id, tx_id)))
+                .is_ok());
+            assert!(ledger
+                .execute(&Transaction::Chargeback(Chargeback::new(client_id, tx_id)))
+                .is_ok());
+        }
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 3, dec!(7.0))))
+            .is_ok());
+
+        assert_eq!(ledger.merge_accounts(1, 2), Ok(()));
+        verify_balances(&ledger, 2, dec!(7.0), dec!(0.0));
+        verify_account_locked(&ledger, 2);
+
+        assert_eq!(ledger.merge_accounts(2, 3), Ok(()));
+        verify_balances(&ledger, 3, dec!(7.0), dec!(0.0));
+        verify_account_locked(&ledger, 3);
+        assert_eq!(
+            ledger.merge_accounts(2, 3),
+            Err(MergeError::ClientAccountNotFound(2))
+        );
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn client_remap() {
+        let mut ledger = Ledger::new();
+        for client_id in 1..=4 {
+            assert!(ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    client_id as u32,
+                    dec!(1.0)
+                )))
+                .is_ok());
+        }
+
+        let cyclic = HashMap::from([(1, 2), (2, 3), (3, 1)]);
+        assert!(matches!(
+            ledger.apply_client_remap(&cyclic),
+            Err(MergeError::CyclicRemap(_))
+        ));
+        assert!(matches!(
+            ledger.apply_client_remap(&HashMap::from([(4, 4)])),
+            Err(MergeError::CyclicRemap(4))
+        ));
+        assert_eq!(ledger.accounts_iter().count(), 4);
+
+        let chained = HashMap::from([(1, 2), (2, 5), (3, 5), (6, 1)]);
+        assert_eq!(ledger.apply_client_remap(&chained), Ok(()));
+        let mut client_ids = ledger
+            .accounts_iter()
+            .map(|(client_id, _)| *client_id)
+            .collect::<Vec<_>>();
+        client_ids.sort_unstable();
+        assert_eq!(client_ids, vec![4, 5]);
+        verify_balances(&ledger, 5, dec!(3.0), dec!(0.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn adjustment_reverses_deposit() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(500.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Adjustment(Adjustment::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-30.0));
+        verify_account_not_locked(&ledger, 1);
+
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 1))),
+            Err(TxError::TxReversed)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Adjustment(Adjustment::new(1, 1))),
+            Err(TxError::TxReversed)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 1))),
+            Err(TxError::TxNotDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
+
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(10.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn adjustment_during_dispute_lifecycle() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(20.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        assert_eq!(
+            ledger.execute(&Transaction::Adjustment(Adjustment::new(1, 1))),
+            Err(TxError::TxAlreadyDisputed)
+        );
+        verify_balances(&ledger, 1, dec!(20.0), dec!(50.0));
+
+        assert!(ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Adjustment(Adjustment::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-20.0));
+
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 2)))
+            .is_ok());
+        assert_eq!(
+            ledger.execute(&Transaction::Adjustment(Adjustment::new(1, 2))),
+            Err(TxError::TxChargedBack)
+        );
+        verify_balances(&ledger, 1, dec!(0.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(0.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn adjustment_insufficient_funds() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(500.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(460.0))))
+            .is_ok());
+        assert_eq!(
+            ledger.execute(&Transaction::Adjustment(Adjustment::new(1, 1))),
+            Err(TxError::InsufficientFunds)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Adjustment(Adjustment::new(2, 1))),
+            Err(TxError::ClientAccountNotFound)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Adjustment(Adjustment::new(1, 3))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(40.0), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-40.0));
+    }
+
+    #[test]
+    fn sweep_dust() {
         let mut ledger = Ledger::new();
+        for (client_id, tx_id, amount) in [
+            (1, 1, dec!(10.00005)),
+            (2, 2, dec!(3.0)),
+            (3, 3, dec!(0.00003)),
+            (4, 4, dec!(0.00002)),
+            (5, 5, dec!(0.00004)),
+        ] {
+            assert!(ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id, tx_id, amount
+                )))
+                .is_ok());
+        }
         assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 6, dec!(10.0))))
             .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(4, 4)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(5, 5)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(5, 5)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(5, 7, dec!(0.00001))))
+            .is_ok());
+
+        assert_eq!(
+            ledger.sweep_dust(dec!(0.0001)),
+            vec![(1, dec!(0.00005)), (3, dec!(0.00003))]
+        );
+        verify_balances(&ledger, 1, dec!(0.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(3.0), dec!(0.0));
+        verify_balances(&ledger, 4, dec!(0.0), dec!(0.00002));
+        verify_balances(&ledger, 5, dec!(0.00001), dec!(0.0));
+        verify_liabilities(&ledger, dec!(-3.00011));
+        assert_eq!(ledger.dust(), dec!(0.00008));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+        assert!(ledger.sweep_dust(dec!(0.0001)).is_empty());
+        assert!(ledger.sweep_configured_dust().is_empty());
+    }
 
+    #[test]
+    fn sweep_configured_dust_from_locked() {
+        let mut ledger = Ledger::builder()
+            .dust_threshold(dec!(0.0001))
+            .sweep_locked_dust(true)
+            .build();
         assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(1.0))))
             .is_ok());
-        verify_balances(&ledger, 1, dec!(80.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(0.00002))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .is_ok());
+
+        assert_eq!(ledger.sweep_configured_dust(), vec![(1, dec!(0.00002))]);
+        verify_balances(&ledger, 1, dec!(0.0), dec!(0.0));
+        verify_account_locked(&ledger, 1);
+        verify_liabilities(&ledger, dec!(-0.00002));
+        assert_eq!(ledger.dust(), dec!(0.00002));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn negative_overdraft_limit_allows_overdraft() {
+        let mut ledger = Ledger::builder().overdraft_limit(dec!(-50)).build();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(20.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(60.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(-40.0), dec!(0.0));
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(
+                1,
+                3,
+                dec!(10.0001)
+            ))),
+            Err(TxError::InsufficientFunds)
+        );
+        assert_eq!(ledger.account(1).unwrap().overdraft_limit(), dec!(-50));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
 
+    #[test]
+    fn withdrawal_down_to_overdraft_limit() {
+        let mut ledger = Ledger::builder()
+            .overdraft_limit(dec!(-10))
+            .overdraft_limits(HashMap::from([(2, dec!(5))]))
+            .build();
+        for (client_id, tx_id) in [(1, 1), (2, 2)] {
+            assert!(ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    tx_id,
+                    dec!(10.0)
+                )))
+                .is_ok());
+        }
         assert!(ledger
             .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))))
             .is_ok());
-        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(
+                1,
+                4,
+                dec!(0.0001)
+            ))),
+            Err(TxError::InsufficientFunds)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(
+                2,
+                5,
+                dec!(5.0001)
+            ))),
+            Err(TxError::InsufficientFunds)
+        );
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(2, 6, dec!(5.0))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(-10.0), dec!(0.0));
+        verify_balances(&ledger, 2, dec!(5.0), dec!(0.0));
 
+        ledger.set_overdraft_limit(2, Decimal::ZERO);
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(2, 7, dec!(5.0))))
+            .is_ok());
+        verify_balances(&ledger, 2, dec!(0.0), dec!(0.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn dispute_and_chargeback_ignore_overdraft_limit() {
+        let mut ledger = Ledger::builder().overdraft_limit(dec!(-50)).build();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(100.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(140.0))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(-140.0), dec!(100.0));
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(-140.0), dec!(0.0));
+        verify_account_locked(&ledger, 1);
+        verify_liabilities(&ledger, dec!(140.0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn disputes_up_to_the_exposure_limit() {
+        let mut ledger = Ledger::builder()
+            .dispute_limit(DisputeLimit::Amount(dec!(50)))
+            .dispute_limits(HashMap::from([(2, DisputeLimit::Amount(dec!(100)))]))
+            .build();
+        for (tx_id, amount) in [(1, dec!(30)), (2, dec!(20)), (3, dec!(10))] {
+            assert!(ledger
+                .execute(&Transaction::Deposit(Deposit::new(1, tx_id, amount)))
+                .is_ok());
+        }
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        // Reaching the limit is allowed, exceeding it is not
         assert!(ledger
             .execute(&Transaction::Dispute(Dispute::new(1, 2)))
             .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(30.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 3))),
+            Err(TxError::DisputeExposureLimitExceeded {
+                current_held: dec!(50),
+                limit: dec!(50),
+            })
+        );
+        verify_balances(&ledger, 1, dec!(10), dec!(50));
+        assert_eq!(ledger.tx_states[&3].state, TxState::Resolved);
 
+        // Resolving frees capacity
         assert!(ledger
-            .execute(&Transaction::Resolve(Resolve::new(1, 2)))
+            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
             .is_ok());
-        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 3)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(30), dec!(30));
+
+        // Client overrides beat the default, in both directions
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(2, 4, dec!(80))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(2, 4)))
+            .is_ok());
+        ledger.set_dispute_limit(3, DisputeLimit::Amount(Decimal::ZERO));
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(3, 5, dec!(1))))
+            .is_ok());
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(3, 5))),
+            Err(TxError::DisputeExposureLimitExceeded {
+                current_held: dec!(0),
+                limit: dec!(0),
+            })
+        );
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn percentage_dispute_limit_follows_the_total() {
+        let mut ledger = Ledger::builder()
+            .dispute_limit(DisputeLimit::PercentOfTotal(dec!(50)))
+            .build();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(100))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(40))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(60))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 4, dec!(10))))
+            .is_ok());
+        // Half of the total of 90 leaves room for 5 more
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 4))),
+            Err(TxError::DisputeExposureLimitExceeded {
+                current_held: dec!(40),
+                limit: dec!(45),
+            })
+        );
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 5, dec!(20))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 4)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(60), dec!(50));
+        assert_eq!("25%".parse(), Ok(DisputeLimit::PercentOfTotal(dec!(25))));
+        assert_eq!(" 7.5 ".parse(), Ok(DisputeLimit::Amount(dec!(7.5))));
+    }
+
+    #[test]
+    fn chargebacks_ignore_the_dispute_limit() {
+        let mut ledger = Ledger::builder()
+            .dispute_limit(DisputeLimit::Amount(dec!(50)))
+            .build();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50))))
+            .is_ok());
+        assert!(ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .is_ok());
+        ledger.set_dispute_limit(1, DisputeLimit::Amount(Decimal::ZERO));
+        assert!(ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(0), dec!(0));
+        verify_account_locked(&ledger, 1);
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn open_disputes_are_capped_per_account() {
+        let mut ledger = Ledger::builder()
+            .max_open_disputes_per_account(3)
+            .chargeback_policy(ChargebackPolicy::NoLock)
+            .build();
+        for tx_id in 1..=5 {
+            assert_eq!(
+                ledger.execute(&Transaction::Deposit(Deposit::new(1, tx_id, dec!(10)))),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(2, 6, dec!(10)))),
+            Ok(())
+        );
+        for tx_id in 1..=3 {
+            assert_eq!(
+                ledger.execute(&Transaction::Dispute(Dispute::new(1, tx_id))),
+                Ok(())
+            );
+        }
+        assert_eq!(ledger.account(1).unwrap().open_disputes(), 3);
+        let full = Err(TxError::TooManyOpenDisputes { open: 3, limit: 3 });
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 4))),
+            full
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(2, 6))),
+            Ok(())
+        );
+
+        assert_eq!(
+            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
+            Ok(())
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 4))),
+            Ok(())
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 5))),
+            full
+        );
+        // Chargebacks proceed at the cap and reopen capacity too
+        assert_eq!(
+            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 2))),
+            Ok(())
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 5))),
+            Ok(())
+        );
+        assert_eq!(
+            AccountView::from(ledger.account(1).unwrap()).open_disputes,
+            3
+        );
+        verify_balances(&ledger, 1, dec!(10), dec!(30));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn deposits_held_on_arrival_do_not_count_toward_the_open_dispute_cap() {
+        let mut ledger = Ledger::builder()
+            .max_open_disputes_per_account(2)
+            .lock_policy(LockPolicy::HoldIncoming)
+            .build();
+        for tx_id in 1..=3 {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(1, tx_id, dec!(10))))
+                .unwrap();
+        }
+        ledger.lock(1).unwrap();
+        for tx_id in 4..=6 {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(1, tx_id, dec!(10))))
+                .unwrap();
+        }
+        assert_eq!(ledger.account(1).unwrap().open_disputes(), 0);
+        assert_eq!(ledger.open_disputes().count(), 3);
+
+        let full = || Err(TxError::TooManyOpenDisputes { open: 2, limit: 2 });
+        for (tx, expected) in [
+            (Transaction::Dispute(Dispute::new(1, 1)), Ok(())),
+            (Transaction::Dispute(Dispute::new(1, 2)), Ok(())),
+            (Transaction::Dispute(Dispute::new(1, 3)), full()),
+            // Settling a deposit held on arrival reopens no capacity
+            (Transaction::Chargeback(Chargeback::new(1, 5)), Ok(())),
+            (Transaction::Dispute(Dispute::new(1, 3)), full()),
+            (Transaction::Resolve(Resolve::new(1, 1)), Ok(())),
+            (Transaction::Dispute(Dispute::new(1, 3)), Ok(())),
+        ] {
+            assert_eq!(ledger.execute(&tx), expected);
+            assert_eq!(ledger.verify_invariants(), Ok(()));
+        }
+        assert_eq!(ledger.account(1).unwrap().open_disputes(), 2);
+        let mut open: Vec<_> = ledger
+            .open_disputes()
+            .map(|dispute| dispute.tx_id)
+            .collect();
+        open.sort_unstable();
+        assert_eq!(open, [2, 3, 4, 6]);
+        verify_balances(&ledger, 1, dec!(10), dec!(40));
+    }
+
+    /// Results of a run referring to clients 2 to 7 without depositing for them first.
+    fn run_with_unknown_clients(policy: UnknownClientOnDebit) -> (Ledger, Vec<TxError>) {
+        let mut ledger = Ledger::builder()
+            .overdraft_limit(dec!(-100))
+            .unknown_client_on_debit(policy)
+            .build();
+        let rejections = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Withdrawal(Withdrawal::new(2, 2, dec!(5))),
+            Transaction::Dispute(Dispute::new(3, 1)),
+            Transaction::Resolve(Resolve::new(4, 9)),
+            Transaction::Chargeback(Chargeback::new(5, 9)),
+            Transaction::Adjustment(Adjustment::new(6, 1)),
+            Transaction::WithdrawAuth(WithdrawAuth::new(7, 3, dec!(1))),
+            Transaction::Deposit(Deposit::new(2, 4, dec!(3))),
+        ]
+        .iter()
+        .filter_map(|tx| ledger.execute(tx).err())
+        .collect();
+        (ledger, rejections)
+    }
+
+    #[test]
+    fn unknown_clients_of_debits_get_empty_accounts() {
+        let (ledger, rejections) = run_with_unknown_clients(UnknownClientOnDebit::Error);
+        assert_eq!(rejections, [const { TxError::ClientAccountNotFound }; 6]);
+        let roster: Vec<_> = ledger
+            .view()
+            .accounts_sorted()
+            .iter()
+            .map(|user_account| user_account.client_id())
+            .collect();
+        assert_eq!(roster, [1, 2]);
+        assert!(!ledger.account(2).unwrap().created_by_rejection());
+
+        let (ledger, rejections) = run_with_unknown_clients(UnknownClientOnDebit::CreateEmpty);
+        assert_eq!(
+            rejections,
+            [
+                TxError::InsufficientFunds,
+                TxError::OriginTxNotFound,
+                TxError::OriginTxNotFound,
+                TxError::OriginTxNotFound,
+                TxError::ClientAccountNotFound,
+                TxError::ClientAccountNotFound,
+            ]
+        );
+        let roster: Vec<_> = ledger
+            .view()
+            .accounts_sorted()
+            .iter()
+            .map(|user_account| {
+                (
+                    user_account.client_id(),
+                    user_account.total(),
+                    user_account.created_by_rejection(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            roster,
+            [
+                (1, dec!(10), false),
+                (2, dec!(3), true),
+                (3, dec!(0), true),
+                (4, dec!(0), true),
+                (5, dec!(0), true),
+            ]
+        );
+        // Nothing moved despite the overdraft, and the deposit of client 1 stays undisputed
+        assert_eq!(ledger.account(2).unwrap().total_withdrawn(), dec!(0));
+        assert_eq!(ledger.open_disputes().count(), 0);
+        assert_eq!(ledger.tx_seq(), 2);
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    const DISPUTE_AMOUNT_HANDLINGS: [DisputeAmountHandling; 3] = [
+        DisputeAmountHandling::Ignore,
+        DisputeAmountHandling::WarnOnMismatch,
+        DisputeAmountHandling::RejectOnMismatch,
+    ];
+
+    #[test]
+    fn matching_and_absent_dispute_amounts_are_always_accepted() {
+        for handling in DISPUTE_AMOUNT_HANDLINGS {
+            let mut ledger = Ledger::builder().dispute_amount_handling(handling).build();
+            for tx in [
+                Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+                Transaction::Dispute(Dispute::new(1, 1).with_amount(dec!(10.00))),
+                Transaction::Resolve(Resolve::new(1, 1)),
+                Transaction::Dispute(Dispute::new(1, 1)),
+                Transaction::Chargeback(Chargeback::new(1, 1).with_amount(dec!(10))),
+            ] {
+                assert_eq!(ledger.execute(&tx), Ok(()), "{:?}", handling);
+            }
+            verify_balances(&ledger, 1, dec!(0), dec!(0));
+            assert_eq!(ledger.dispute_amount_mismatches(), 0);
+        }
+    }
+
+    #[test]
+    fn mismatching_dispute_amounts_by_handling() {
+        for handling in DISPUTE_AMOUNT_HANDLINGS {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let received = events.clone();
+            let mut ledger = Ledger::builder()
+                .dispute_amount_handling(handling)
+                .event_listener(move |event| {
+                    if let LedgerEvent::DisputedAmountMismatch { .. } = event {
+                        received.lock().unwrap().push(event.clone());
+                    }
+                    Ok(())
+                })
+                .build();
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+                .unwrap();
+            let dispute = ledger.execute(&Transaction::Dispute(
+                Dispute::new(1, 1).with_amount(dec!(9)),
+            ));
+            let resolve = ledger.execute(&Transaction::Resolve(
+                Resolve::new(1, 1).with_amount(dec!(11)),
+            ));
+            let mismatch = |provided| LedgerEvent::DisputedAmountMismatch {
+                client_id: 1,
+                tx_id: 1,
+                expected: dec!(10),
+                provided,
+            };
+            match handling {
+                DisputeAmountHandling::Ignore => {
+                    assert_eq!((dispute, resolve), (Ok(()), Ok(())));
+                    assert_eq!(*events.lock().unwrap(), vec![]);
+                }
+                DisputeAmountHandling::WarnOnMismatch => {
+                    assert_eq!((dispute, resolve), (Ok(()), Ok(())));
+                    assert_eq!(
+                        *events.lock().unwrap(),
+                        vec![mismatch(dec!(9)), mismatch(dec!(11))]
+                    );
+                }
+                DisputeAmountHandling::RejectOnMismatch => {
+                    assert_eq!(
+                        dispute,
+                        Err(TxError::DisputedAmountMismatch {
+                            expected: dec!(10),
+                            provided: dec!(9),
+                        })
+                    );
+                    assert_eq!(resolve, Err(TxError::TxNotDisputed));
+                    assert_eq!(*events.lock().unwrap(), vec![]);
+                }
+            }
+            verify_balances(&ledger, 1, dec!(10), dec!(0));
+            let warned = handling == DisputeAmountHandling::WarnOnMismatch;
+            assert_eq!(
+                ledger.dispute_amount_mismatches(),
+                if warned { 2 } else { 0 }
+            );
+        }
+    }
+
+    #[test]
+    fn overflowing_transactions_are_rejected() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, Decimal::MAX)))
+            .is_ok());
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(1)))),
+            Err(TxError::BalanceOverflow)
+        );
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(2, 3, dec!(1)))),
+            Err(TxError::BalanceOverflow)
+        );
+        assert!(ledger.account(2).is_none());
+        verify_balances(&ledger, 1, Decimal::MAX, dec!(0));
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn transactions_losing_precision_are_rejected() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(1e27))))
+            .is_ok());
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(0.001)))),
+            Err(TxError::BalanceOverflow)
+        );
+        verify_balances(&ledger, 1, dec!(1e27), dec!(0));
+        assert!(ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 3, dec!(1))))
+            .is_ok());
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    /// Credits liabilities and panics before crediting the account.
+    struct HalfAppliedTx;
+
+    impl ExecutableTransaction for HalfAppliedTx {
+        fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+            ledger.liabilities.balance -= dec!(5);
+            panic!("half applied");
+        }
+
+        fn client_id(&self) -> ClientId {
+            1
+        }
+
+        fn tx_id(&self) -> TxId {
+            2
+        }
+
+        fn kind(&self) -> TxTypeTag {
+            TxTypeTag::Deposit
+        }
+    }
+
+    #[test]
+    fn panicking_transaction_poisons_ledger() {
+        let mut ledger = Ledger::new();
+        assert!(ledger
+            .execute_catch_unwind(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+            .is_ok());
+        assert!(!ledger.is_poisoned());
+        assert_eq!(
+            ledger.execute_catch_unwind(&HalfAppliedTx),
+            Err(TxError::InternalPanic {
+                message: "half applied".to_string()
+            })
+        );
+        assert!(ledger.is_poisoned());
+        assert!(matches!(
+            ledger.verify_invariants(),
+            Err(InvariantViolation::UnbalancedLiabilities { .. })
+        ));
+        assert_eq!(ledger.audit_liabilities().unwrap_err().delta, dec!(-5));
+        assert!(ledger
+            .execute_catch_unwind(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(4))))
+            .is_ok());
+        verify_balances(&ledger, 1, dec!(6), dec!(0));
+    }
+
+    fn charge_back(ledger: &mut Ledger, client_id: ClientId, tx_id: TxId) {
+        for tx in [
+            Transaction::Deposit(Deposit::new(client_id, tx_id, dec!(10))),
+            Transaction::Dispute(Dispute::new(client_id, tx_id)),
+            Transaction::Chargeback(Chargeback::new(client_id, tx_id)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+    }
+
+    #[test]
+    fn chargeback_without_lock_keeps_account_operational() {
+        let mut ledger = Ledger::builder()
+            .chargeback_policy(ChargebackPolicy::NoLock)
+            .build();
+        charge_back(&mut ledger, 1, 1);
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(5))))
+            .unwrap();
+        ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(3))))
+            .unwrap();
+        let account = ledger.account(1).unwrap();
+        assert!(!account.locked);
+        assert_eq!(account.chargebacks(), 1);
+        assert_eq!(account.available(), dec!(2));
+    }
+
+    #[test]
+    fn chargeback_locks_after_n() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener_events = events.clone();
+        let mut ledger = Ledger::builder()
+            .chargeback_policy(ChargebackPolicy::LockAfterN(2))
+            .event_listener(move |event| {
+                if let LedgerEvent::AccountLocked { .. } = event {
+                    listener_events.lock().unwrap().push(event.clone());
+                }
+                Ok(())
+            })
+            .build();
+        charge_back(&mut ledger, 1, 1);
+        assert!(!ledger.account(1).unwrap().locked);
+        assert!(events.lock().unwrap().is_empty());
+
+        charge_back(&mut ledger, 1, 2);
+        let account = ledger.account(1).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.chargebacks(), 2);
+        assert_eq!(AccountView::from(account).chargebacks, 2);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![LedgerEvent::AccountLocked {
+                client_id: 1,
+                tx_id: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn dispute_lifecycle_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener_events = events.clone();
+        let mut ledger = Ledger::builder()
+            .event_listener(move |event| {
+                listener_events.lock().unwrap().push(event.clone());
+                Ok(())
+            })
+            .build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            // Rejected transactions are not reported.
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ] {
+            ledger.execute(&tx).ok();
+        }
+        let (client_id, tx_id, amount) = (1, 1, dec!(10));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                LedgerEvent::Disputed {
+                    client_id,
+                    tx_id,
+                    amount,
+                    reason: None,
+                },
+                LedgerEvent::Resolved {
+                    client_id,
+                    tx_id,
+                    amount
+                },
+                LedgerEvent::Disputed {
+                    client_id,
+                    tx_id,
+                    amount,
+                    reason: None,
+                },
+                LedgerEvent::ChargedBack {
+                    client_id,
+                    tx_id,
+                    amount,
+                    reason: None,
+                },
+                LedgerEvent::AccountLocked { client_id, tx_id },
+            ]
+        );
+    }
+
+    #[test]
+    fn first_dispute_reason_wins_until_the_chargeback() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener_events = events.clone();
+        let mut ledger = Ledger::builder()
+            .event_listener(move |event| {
+                listener_events.lock().unwrap().push(event.clone());
+                Ok(())
+            })
+            .build();
+        let reason = |reason: &str| reason.parse::<DisputeReason>().unwrap();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Dispute(Dispute::new(1, 1).with_reason(reason("10.4"))),
+            Transaction::Dispute(Dispute::new(1, 2).with_reason(reason("10.4"))),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Dispute(Dispute::new(1, 1).with_reason(reason("13.1"))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let record = |ledger: &Ledger, tx_id| ledger.tx_states[&tx_id].reason.clone();
+        assert_eq!(record(&ledger, 1), Some(reason("10.4")));
+        assert_eq!(ledger.memory_stats().interned_reasons, 2);
+        let shared = [record(&ledger, 1).unwrap(), record(&ledger, 2).unwrap()];
+        assert!(std::ptr::eq(shared[0].as_str(), shared[1].as_str()));
+
+        ledger
+            .execute(&Transaction::Chargeback(
+                Chargeback::new(1, 1).with_reason(reason("13.1")),
+            ))
+            .unwrap();
+        ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 2)))
+            .unwrap();
+        assert_eq!(record(&ledger, 1), Some(reason("13.1")));
+        assert_eq!(record(&ledger, 2), Some(reason("10.4")));
+        let reported: Vec<_> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                LedgerEvent::Disputed { tx_id, reason, .. } => {
+                    Some(("disputed", *tx_id, reason.clone()))
+                }
+                LedgerEvent::ChargedBack { tx_id, reason, .. } => {
+                    Some(("charged_back", *tx_id, reason.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                ("disputed", 1, Some(reason("10.4"))),
+                ("disputed", 2, Some(reason("10.4"))),
+                ("disputed", 1, Some(reason("10.4"))),
+                ("charged_back", 1, Some(reason("13.1"))),
+                ("charged_back", 2, Some(reason("10.4"))),
+            ]
+        );
     }
 
     #[test]
-    fn already_disputed() {
-        let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+    fn reasons_outside_the_allowlist() {
+        let allowlist = |action| ReasonAllowlist {
+            reasons: ["10.4".to_string(), "13.1".to_string()].into(),
+            action,
+        };
+        let reason = |reason: &str| reason.parse::<DisputeReason>().unwrap();
+        let txs = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1).with_reason(reason("99.9"))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1).with_reason(reason("4837"))),
+        ];
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(80.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+        let mut rejecting = Ledger::builder()
+            .dispute_reason_allowlist(allowlist(UnknownReasonAction::Reject))
+            .build();
+        let results: Vec<_> = txs.iter().map(|tx| rejecting.execute(tx)).collect();
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(TxError::UnknownDisputeReason),
+                Ok(()),
+                Err(TxError::UnknownDisputeReason),
+            ]
+        );
+        verify_balances(&rejecting, 1, dec!(0), dec!(10));
+        assert_eq!(rejecting.unknown_dispute_reasons(), 0);
 
-        assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener_events = events.clone();
+        let mut warning = Ledger::builder()
+            .dispute_reason_allowlist(allowlist(UnknownReasonAction::Warn))
+            .event_listener(move |event| {
+                if let LedgerEvent::UnknownDisputeReason { .. } = event {
+                    listener_events.lock().unwrap().push(event.clone());
+                }
+                Ok(())
+            })
+            .build();
+        let results: Vec<_> = txs.iter().map(|tx| warning.execute(tx)).collect();
+        assert_eq!(
+            results,
+            [Ok(()), Ok(()), Err(TxError::TxAlreadyDisputed), Ok(())]
+        );
+        assert_eq!(warning.unknown_dispute_reasons(), 2);
+        assert_eq!(warning.tx_states[&1].reason, Some(reason("4837")));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                LedgerEvent::UnknownDisputeReason {
+                    client_id: 1,
+                    tx_id: 1,
+                    kind: TxTypeTag::Dispute,
+                    reason: reason("99.9"),
+                },
+                LedgerEvent::UnknownDisputeReason {
+                    client_id: 1,
+                    tx_id: 1,
+                    kind: TxTypeTag::Chargeback,
+                    reason: reason("4837"),
+                },
+            ]
+        );
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(30.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+    #[test]
+    fn disputes_after_the_window_expire() {
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix_millis(1_000)));
+        let mut ledger = Ledger::builder()
+            .clock(Arc::clone(&clock))
+            .dispute_window(30 * DAY)
+            .build();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+            .unwrap();
+        clock.advance(10 * DAY);
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(5))))
+            .unwrap();
+        assert_eq!(
+            ledger.tx_states[&2].recorded_at(),
+            Timestamp::UNIX_EPOCH.saturating_add(10 * DAY + Duration::from_secs(1))
+        );
 
+        // On the last day of the window of tx 1
+        clock.advance(20 * DAY);
+        ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+        clock.advance(Duration::from_millis(1));
+        // Resolves of open disputes are never late
+        ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
+            .unwrap();
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 1))),
+            Err(TxError::DisputeWindowExpired)
+        );
+        verify_balances(&ledger, 1, dec!(15), dec!(0));
+        clock.advance(10 * DAY);
         assert_eq!(
             ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
-            Err(TxError::TxAlreadyDisputed)
+            Err(TxError::DisputeWindowExpired)
         );
-        verify_balances(&ledger, 1, dec!(30.0), dec!(30.0));
-        verify_liabilities(&ledger, dec!(-60.0));
 
-        assert!(ledger
-            .execute(&Transaction::Resolve(Resolve::new(1, 2)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+        // Without a window, deposits can be disputed at any age
+        let mut unlimited = Ledger::builder().clock(Arc::clone(&clock)).build();
+        unlimited
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+            .unwrap();
+        clock.advance(3650 * DAY);
+        unlimited
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
     }
 
     #[test]
-    fn insufficient_funds_while_disputed() {
-        let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+    fn expire_stale_disputes() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener_events = events.clone();
+        let mut ledger = Ledger::builder()
+            .event_listener(move |event| {
+                if let LedgerEvent::AutoResolved { .. } = event {
+                    listener_events.lock().unwrap().push(event.clone());
+                }
+                Ok(())
+            })
+            .build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(5))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Withdrawal(Withdrawal::new(2, 3, dec!(1))),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            // Rejected transactions don't advance the sequence.
+            Transaction::Withdrawal(Withdrawal::new(2, 4, dec!(100))),
+        ] {
+            ledger.execute(&tx).ok();
+        }
+        assert_eq!(ledger.tx_seq(), 5);
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(80.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+        // The dispute of client 1 was opened at 2 and the one of client 2 at 4.
+        assert_eq!(
+            ledger.expire_disputes(3),
+            vec![OpenDispute {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+                reason: None,
+            }]
+        );
+        verify_balances(&ledger, 1, dec!(10), dec!(0));
+        verify_balances(&ledger, 2, dec!(-1), dec!(5));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![LedgerEvent::AutoResolved {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10)
+            }]
+        );
+        assert!(ledger.expire_disputes(3).is_empty());
+        assert!(ledger.verify_invariants().is_ok());
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(30.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+        // The resolved deposit can be disputed again, which restarts its timer.
+        ledger
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+        assert_eq!(ledger.expire_disputes(5).len(), 1);
+        verify_balances(&ledger, 1, dec!(0), dec!(10));
+        verify_balances(&ledger, 2, dec!(4), dec!(0));
+        assert_eq!(ledger.open_disputes().count(), 1);
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn expiry_keeps_deposits_held_on_arrival() {
+        let mut ledger = Ledger::builder()
+            .lock_policy(LockPolicy::HoldIncoming)
+            .build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(3))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        assert!(ledger.expire_disputes(u64::MAX).is_empty());
+        verify_balances(&ledger, 1, dec!(0), dec!(3));
+    }
+
+    #[test]
+    fn dispute_and_chargeback_of_demoted_account() {
+        let mut ledger = Ledger::builder()
+            .account_tiering(AccountTiering {
+                demote_after: 2,
+                hot_capacity: 0,
+            })
+            .build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(5))),
+            Transaction::Deposit(Deposit::new(1, 3, dec!(2))),
+            Transaction::Deposit(Deposit::new(3, 4, dec!(1))),
+            Transaction::Deposit(Deposit::new(3, 5, dec!(1))),
+            // Ends the second generation, during which only client 3 was active.
+            Transaction::Deposit(Deposit::new(3, 6, dec!(1))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let stats = ledger.memory_stats();
+        assert_eq!((stats.hot_accounts, stats.cold_accounts), (1, 2));
+        assert!(ledger.account(2).is_some());
 
+        ledger
+            .execute(&Transaction::Dispute(Dispute::new(2, 2)))
+            .unwrap();
+        verify_balances(&ledger, 2, dec!(0), dec!(5));
+        ledger.compact();
+        assert_eq!(ledger.memory_stats().hot_accounts, 0);
+        ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(2, 2)))
+            .unwrap();
+        verify_balances(&ledger, 2, dec!(0), dec!(0));
+        verify_account_locked(&ledger, 2);
+        ledger.compact();
         assert_eq!(
-            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(60.0)))),
-            Err(TxError::InsufficientFunds)
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(2, 7, dec!(1)))),
+            Err(TxError::ClientAccountLocked)
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(30.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+        verify_balances(&ledger, 1, dec!(12), dec!(0));
+        verify_liabilities(&ledger, dec!(-15));
+        assert_eq!(ledger.accounts_iter().count(), 3);
+        assert!(ledger.verify_invariants().is_ok());
     }
 
     #[test]
-    fn client_account_not_found() {
+    fn remove_account_blocked_by_open_dispute() {
         let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
-
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        assert_eq!(ledger.remove_account(1), Err(RemoveError::OpenDispute(2)));
+        ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 2)))
+            .unwrap();
         assert_eq!(
-            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(2, 2, dec!(60.0)))),
-            Err(TxError::ClientAccountNotFound)
+            ledger.remove_account(1),
+            Err(RemoveError::NonZeroBalance {
+                available: dec!(5),
+                held: dec!(0)
+            })
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        assert_eq!(
+            ledger.remove_account(2),
+            Err(RemoveError::ClientAccountNotFound(2))
+        );
+        verify_balances(&ledger, 1, dec!(5), dec!(0));
+    }
 
+    #[test]
+    fn records_of_other_kinds_are_not_disputable() {
+        let mut ledger = Ledger::new();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+            .unwrap();
+        ledger.tx_states.insert(
+            2,
+            TxRecord::new(1, 2, TxTypeTag::Withdrawal, dec!(4), Timestamp::UNIX_EPOCH),
+        );
+        for tx in [
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Resolve(Resolve::new(1, 2)),
+            Transaction::Chargeback(Chargeback::new(1, 2)),
+            Transaction::Adjustment(Adjustment::new(1, 2)),
+        ] {
+            assert_eq!(ledger.execute(&tx), Err(TxError::TxNotDisputable));
+        }
+        // A record of another client is still reported as not found
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(2, 3, dec!(1)))),
+            Ok(())
+        );
         assert_eq!(
             ledger.execute(&Transaction::Dispute(Dispute::new(2, 2))),
-            Err(TxError::ClientAccountNotFound)
+            Err(TxError::OriginTxNotFound)
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        verify_balances(&ledger, 1, dec!(10), dec!(0));
 
+        let mut records: Vec<_> = ledger
+            .tx_records_iter()
+            .map(|record| (record.tx_id(), record.kind(), record.state()))
+            .collect();
+        records.sort_unstable_by_key(|(tx_id, _kind, _state)| *tx_id);
         assert_eq!(
-            ledger.execute(&Transaction::Resolve(Resolve::new(2, 2))),
-            Err(TxError::ClientAccountNotFound)
+            records,
+            [
+                (1, TxTypeTag::Deposit, TxState::Resolved),
+                (2, TxTypeTag::Withdrawal, TxState::Resolved),
+                (3, TxTypeTag::Deposit, TxState::Resolved),
+            ]
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        // Pruning only considers deposits
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| true), 2);
+        assert_eq!(ledger.tx_records_iter().count(), 1);
+    }
+
+    #[test]
+    fn remove_account_then_late_dispute() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(3))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(10))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let version = ledger.account(1).unwrap().version();
+        assert_eq!(
+            ledger.remove_account(1),
+            Ok(RemovedAccount {
+                account: AccountView {
+                    client_id: 1,
+                    available: dec!(0),
+                    held: dec!(0),
+                    total: dec!(0),
+                    locked: false,
+                    version,
+                    chargebacks: 0,
+                    open_disputes: 0,
+                    disputes_opened: 0,
+                    disputes_resolved: 0,
+                    disputes_charged_back: 0,
+                },
+                deposits_removed: 1,
+            })
+        );
+        assert!(ledger.account(1).is_none());
+        assert_eq!(ledger.tx_states.len(), 1);
+        assert!(ledger.verify_invariants().is_ok());
 
         assert_eq!(
-            ledger.execute(&Transaction::Chargeback(Chargeback::new(2, 2))),
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 1))),
             Err(TxError::ClientAccountNotFound)
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 4, dec!(2))))
+            .unwrap();
+        assert_eq!(
+            ledger.execute(&Transaction::Dispute(Dispute::new(1, 1))),
+            Err(TxError::OriginTxNotFound)
+        );
+        verify_balances(&ledger, 1, dec!(2), dec!(0));
+        verify_liabilities(&ledger, dec!(-5));
+        assert!(ledger.verify_invariants().is_ok());
     }
 
     #[test]
-    fn origin_tx_not_found() {
+    fn prune_settled_tx_states() {
+        let mut ledger = Ledger::builder()
+            .lock_policy(LockPolicy::HoldIncoming)
+            .build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Resolve(Resolve::new(1, 2)),
+            Transaction::Deposit(Deposit::new(2, 3, dec!(4))),
+            Transaction::Dispute(Dispute::new(2, 3)),
+            Transaction::Deposit(Deposit::new(3, 4, dec!(7))),
+            Transaction::Dispute(Dispute::new(3, 4)),
+            Transaction::Chargeback(Chargeback::new(3, 4)),
+            Transaction::Deposit(Deposit::new(3, 5, dec!(1))),
+            Transaction::Deposit(Deposit::new(1, 6, dec!(2))),
+            Transaction::Adjustment(Adjustment::new(1, 6)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+
+        let mut offered = Vec::new();
+        let pruned = ledger.prune_settled_tx_states(|deposit| {
+            offered.push((deposit.tx_id, deposit.charged_back));
+            deposit.tx_id != 1
+        });
+        offered.sort_unstable();
+        // Disputed 3, held on arrival 5 and reversed 6 are never offered.
+        assert_eq!(offered, [(1, false), (2, false), (4, true)]);
+        assert_eq!(pruned, 2);
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| false), 0);
+        assert!(ledger.verify_invariants().is_ok());
+
+        for (tx, expected) in [
+            (
+                Transaction::Dispute(Dispute::new(1, 2)),
+                Err(TxError::OriginTxNotFound),
+            ),
+            (
+                Transaction::Chargeback(Chargeback::new(3, 4)),
+                Err(TxError::OriginTxNotFound),
+            ),
+            (Transaction::Dispute(Dispute::new(1, 1)), Ok(())),
+            (Transaction::Resolve(Resolve::new(2, 3)), Ok(())),
+        ] {
+            assert_eq!(ledger.execute(&tx), expected);
+        }
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| true), 1);
+        assert!(ledger.verify_invariants().is_ok());
+    }
+
+    #[test]
+    fn open_dispute_index_tolerates_and_drops_stale_ids() {
         let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        // Corrupt the index with the id of the resolved deposit and an unknown one
+        for tx_id in [1, 99] {
+            super::index_open_dispute(&mut ledger.open_disputes, 1, tx_id);
+        }
+        let open = |ledger: &Ledger| {
+            let mut tx_ids: Vec<_> = ledger
+                .open_disputes()
+                .map(|dispute| dispute.tx_id)
+                .collect();
+            tx_ids.sort_unstable();
+            tx_ids
+        };
+        assert_eq!(open(&ledger), [1, 2]);
+        assert_eq!(ledger.expire_disputes(u64::MAX).len(), 1);
 
-        assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(30.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        // Pruning the record of the resolved deposit drops its id from the index
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| true), 2);
+        assert!(open(&ledger).is_empty());
+        assert_eq!(ledger.open_disputes[&1], HashSet::from([99]));
+        assert_eq!(ledger.account(1).unwrap().open_disputes(), 0);
+    }
 
+    #[test]
+    fn deposits_cannot_reuse_the_id_of_a_charged_back_deposit() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let reuse = TxError::TxIdReuseAfterSettlement {
+            tx_id: 1,
+            prior_state: TxState::ChargedBack,
+        };
+        // Without the tombstone, the deposit would replace the charged back record
+        // and be charged back again.
+        for (tx, expected) in [
+            (
+                Transaction::Deposit(Deposit::new(2, 1, dec!(10))),
+                Err(reuse),
+            ),
+            (
+                Transaction::Dispute(Dispute::new(2, 1)),
+                Err(TxError::ClientAccountNotFound),
+            ),
+            (
+                Transaction::Chargeback(Chargeback::new(2, 1)),
+                Err(TxError::ClientAccountNotFound),
+            ),
+        ] {
+            assert_eq!(ledger.execute(&tx), expected);
+        }
+        assert!(ledger.account(2).is_none());
+        assert_eq!(ledger.account(1).unwrap().total_charged_back(), dec!(10));
         assert_eq!(
-            ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
-            Err(TxError::OriginTxNotFound)
+            ledger.tx_records_iter().next().unwrap().state(),
+            TxState::ChargedBack
         );
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
 
+        // The tombstone outlives the record
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| true), 1);
+        assert_eq!(ledger.memory_stats().tombstones, 1);
+        assert!(ledger.memory_stats().tombstone_bytes > 0);
         assert_eq!(
-            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
-            Err(TxError::OriginTxNotFound)
+            ledger.execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10)))),
+            Err(TxError::TxIdReuseAfterSettlement {
+                tx_id: 1,
+                prior_state: TxState::ChargedBack,
+            })
         );
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        assert!(ledger.verify_invariants().is_ok());
+    }
 
+    #[test]
+    fn deposits_cannot_reuse_the_id_of_another_clients_disputed_deposit() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
         assert_eq!(
-            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 2))),
-            Err(TxError::OriginTxNotFound)
+            ledger.execute(&Transaction::Deposit(Deposit::new(2, 1, dec!(4)))),
+            Err(TxError::DuplicateTxId)
         );
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        assert!(ledger.account(2).is_none());
+        assert!(ledger.verify_invariants().is_ok());
+        assert_eq!(
+            ledger
+                .open_disputes()
+                .map(|dispute| (dispute.client_id, dispute.amount))
+                .collect::<Vec<_>>(),
+            vec![(1, dec!(10))]
+        );
+        // The dispute of client 1 can still be settled
+        ledger
+            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
+            .unwrap();
+        let account = ledger.account(1).unwrap();
+        assert_eq!(account.held(), dec!(0));
+        assert_eq!(account.available(), dec!(10));
+        assert!(ledger.verify_invariants().is_ok());
     }
 
     #[test]
-    fn tx_not_disputed() {
+    fn resolved_deposits_are_tombstoned_on_request() {
+        let txs = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+        ];
         let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        for tx in &txs {
+            ledger.execute(tx).unwrap();
+        }
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(3)))),
+            Err(TxError::DuplicateTxId)
+        );
+        // Without the tombstone, the id is free again once the record is pruned
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| true), 2);
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(3))))
+            .unwrap();
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(18));
 
+        let mut ledger = Ledger::builder().tombstone_resolved_deposits(true).build();
+        for tx in &txs {
+            ledger.execute(tx).unwrap();
+        }
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(3)))),
+            Err(TxError::TxIdReuseAfterSettlement {
+                tx_id: 1,
+                prior_state: TxState::Resolved,
+            })
+        );
+        // Pruning keeps the tombstone of the resolved deposit only
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| true), 2);
+        assert_eq!(ledger.memory_stats().tombstones, 1);
         assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(30.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(3))))
+            .is_err());
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(3))))
+            .unwrap();
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(18));
+    }
 
+    #[test]
+    fn ids_of_unsettled_deposits_follow_the_duplicate_policy() {
+        let mut ledger = Ledger::builder()
+            .duplicate_dispute_policy(DuplicateDisputePolicy::Idempotent)
+            .build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
         assert_eq!(
-            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
-            Err(TxError::TxNotDisputed)
+            ledger.execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10)))),
+            Err(TxError::DuplicateTxId)
         );
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        assert_eq!(
+            ledger.execute_outcome(&Transaction::Dispute(Dispute::new(1, 1))),
+            Ok(TxOutcome::Duplicate)
+        );
+        ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .unwrap();
+        assert_eq!(
+            ledger.execute_outcome(&Transaction::Chargeback(Chargeback::new(1, 1))),
+            Ok(TxOutcome::Duplicate)
+        );
+        assert!(matches!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10)))),
+            Err(TxError::TxIdReuseAfterSettlement { .. })
+        ));
+        assert_eq!(ledger.memory_stats().tombstones, 0);
+    }
 
+    #[test]
+    fn withdrawal_hold_captured() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 2, dec!(4))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        verify_balances(&ledger, 1, dec!(6), dec!(4));
         assert_eq!(
-            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 1))),
-            Err(TxError::TxNotDisputed)
+            ledger.open_withdrawal_holds().collect::<Vec<_>>(),
+            [OpenWithdrawalHold {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(4)
+            }]
         );
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        // Held funds can't be withdrawn a second time.
+        assert_eq!(
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(7)))),
+            Err(TxError::InsufficientFunds)
+        );
+        assert!(ledger.verify_invariants().is_ok());
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(-30.0), dec!(50.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        ledger
+            .execute(&Transaction::WithdrawCapture(WithdrawCapture::new(1, 2)))
+            .unwrap();
+        verify_balances(&ledger, 1, dec!(6), dec!(0));
+        verify_liabilities(&ledger, dec!(-6));
+        assert_eq!(ledger.account(1).unwrap().total_withdrawn(), dec!(4));
+        assert_eq!(ledger.open_withdrawal_holds().count(), 0);
+        assert!(ledger.verify_invariants().is_ok());
 
-        assert!(ledger
-            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        for tx in [
+            Transaction::WithdrawCapture(WithdrawCapture::new(1, 2)),
+            Transaction::WithdrawRelease(WithdrawRelease::new(1, 2)),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 2, dec!(1))),
+        ] {
+            assert_eq!(ledger.execute(&tx), Err(TxError::HoldAlreadySettled));
+        }
+        verify_balances(&ledger, 1, dec!(6), dec!(0));
+    }
 
+    #[test]
+    fn withdrawal_hold_released() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 2, dec!(4))),
+            Transaction::WithdrawRelease(WithdrawRelease::new(1, 2)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        verify_balances(&ledger, 1, dec!(10), dec!(0));
+        verify_liabilities(&ledger, dec!(-10));
+        assert_eq!(ledger.account(1).unwrap().total_withdrawn(), dec!(0));
         assert_eq!(
-            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 1))),
-            Err(TxError::TxNotDisputed)
+            ledger.execute(&Transaction::WithdrawCapture(WithdrawCapture::new(1, 2))),
+            Err(TxError::HoldAlreadySettled)
         );
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        assert!(ledger.verify_invariants().is_ok());
+    }
+
+    #[test]
+    fn withdrawal_hold_rejections() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(5))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(2, 3, dec!(5))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        for (tx, expected) in [
+            (
+                Transaction::WithdrawCapture(WithdrawCapture::new(1, 4)),
+                TxError::HoldNotFound,
+            ),
+            (
+                Transaction::WithdrawRelease(WithdrawRelease::new(1, 4)),
+                TxError::HoldNotFound,
+            ),
+            // The hold of another client.
+            (
+                Transaction::WithdrawCapture(WithdrawCapture::new(1, 3)),
+                TxError::HoldNotFound,
+            ),
+            (
+                Transaction::WithdrawCapture(WithdrawCapture::new(3, 3)),
+                TxError::ClientAccountNotFound,
+            ),
+            (
+                Transaction::WithdrawAuth(WithdrawAuth::new(1, 4, dec!(10.01))),
+                TxError::InsufficientFunds,
+            ),
+            (
+                Transaction::WithdrawAuth(WithdrawAuth::new(3, 4, dec!(1))),
+                TxError::ClientAccountNotFound,
+            ),
+            // The id of the open hold of client 2, by the same and another client.
+            (
+                Transaction::WithdrawAuth(WithdrawAuth::new(2, 3, dec!(0))),
+                TxError::DuplicateTxId,
+            ),
+            (
+                Transaction::WithdrawAuth(WithdrawAuth::new(1, 3, dec!(1))),
+                TxError::DuplicateTxId,
+            ),
+        ] {
+            assert_eq!(ledger.execute(&tx), Err(expected));
+        }
+        verify_balances(&ledger, 1, dec!(10), dec!(0));
+        verify_balances(&ledger, 2, dec!(0), dec!(5));
 
+        ledger.accounts.get_mut(&1).unwrap().locked = true;
         assert_eq!(
-            ledger.execute(&Transaction::Resolve(Resolve::new(1, 1))),
-            Err(TxError::TxNotDisputed)
+            ledger.execute(&Transaction::WithdrawAuth(WithdrawAuth::new(1, 4, dec!(1)))),
+            Err(TxError::ClientAccountLocked)
         );
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
+        assert!(ledger.verify_invariants().is_ok());
+    }
+
+    #[test]
+    fn dispute_of_deposit_funding_withdrawal_hold() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 2, dec!(8))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        // Like a withdrawal, the hold leaves too little available to cover the dispute.
+        verify_balances(&ledger, 1, dec!(-8), dec!(18));
+        assert!(ledger.verify_invariants().is_ok());
+
+        ledger
+            .execute(&Transaction::WithdrawCapture(WithdrawCapture::new(1, 2)))
+            .unwrap();
+        verify_balances(&ledger, 1, dec!(-8), dec!(10));
+        ledger
+            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .unwrap();
+        verify_balances(&ledger, 1, dec!(-8), dec!(0));
+        verify_liabilities(&ledger, dec!(8));
+        verify_account_locked(&ledger, 1);
+        assert!(ledger.verify_invariants().is_ok());
+    }
+
+    #[test]
+    fn withdrawal_hold_released_after_chargeback() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 2, dec!(8))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        // The hold outlives the chargeback which locked the account.
+        verify_balances(&ledger, 1, dec!(-8), dec!(8));
+        ledger
+            .execute(&Transaction::WithdrawRelease(WithdrawRelease::new(1, 2)))
+            .unwrap();
+        verify_balances(&ledger, 1, dec!(0), dec!(0));
+        verify_liabilities(&ledger, dec!(0));
+        assert!(ledger.verify_invariants().is_ok());
+    }
+
+    #[test]
+    fn remove_account_blocked_by_withdrawal_hold() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 2, dec!(4))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 3, dec!(6))),
+            Transaction::WithdrawCapture(WithdrawCapture::new(1, 2)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        assert_eq!(
+            ledger.remove_account(1),
+            Err(RemoveError::OpenWithdrawalHold(3))
+        );
+        ledger
+            .execute(&Transaction::WithdrawCapture(WithdrawCapture::new(1, 3)))
+            .unwrap();
+        assert!(ledger.remove_account(1).is_ok());
+        assert!(ledger.withdrawal_holds.is_empty());
+        assert!(ledger.verify_invariants().is_ok());
     }
 
     #[test]
-    fn chargeback() {
+    fn merge_with_open_withdrawal_hold() {
         let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 2, dec!(4))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        ledger.merge_accounts(1, 2).unwrap();
+        verify_balances(&ledger, 2, dec!(6), dec!(4));
+        assert!(ledger.verify_invariants().is_ok());
+        ledger
+            .execute(&Transaction::WithdrawRelease(WithdrawRelease::new(2, 2)))
+            .unwrap();
+        verify_balances(&ledger, 2, dec!(10), dec!(0));
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(80.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+    fn ledger_of(txs: impl IntoIterator<Item = Transaction>) -> Ledger {
+        let mut ledger = Ledger::new();
+        for tx in txs {
+            ledger.execute(&tx).unwrap();
+        }
+        ledger
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+    #[test]
+    fn merge_disjoint_ledgers() {
+        let shard = ledger_of([
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ]);
+        let other = ledger_of([
+            Transaction::Deposit(Deposit::new(2, 3, dec!(5))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(2, 4, dec!(2))),
+            Transaction::Deposit(Deposit::new(3, 5, dec!(1))),
+            Transaction::Dispute(Dispute::new(3, 5)),
+            Transaction::Chargeback(Chargeback::new(3, 5)),
+        ]);
+        let mut merged = shard.merge(other).unwrap();
+        assert_eq!(merged.accounts_iter().count(), 3);
+        verify_balances(&merged, 1, dec!(0), dec!(10));
+        verify_balances(&merged, 2, dec!(3), dec!(2));
+        verify_balances(&merged, 3, dec!(0), dec!(0));
+        verify_account_locked(&merged, 3);
+        verify_liabilities(&merged, dec!(-15));
+        assert_eq!(merged.tx_seq(), 5);
+        assert!(merged.verify_invariants().is_ok());
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(30.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+        for tx in [
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::WithdrawCapture(WithdrawCapture::new(2, 4)),
+        ] {
+            merged.execute(&tx).unwrap();
+        }
+        verify_balances(&merged, 1, dec!(10), dec!(0));
+        verify_balances(&merged, 2, dec!(3), dec!(0));
+        assert!(merged.verify_invariants().is_ok());
 
-        assert!(ledger
-            .execute(&Transaction::Chargeback(Chargeback::new(1, 2)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-30.0));
-        verify_account_locked(&ledger, 1);
+        // Reasons applied with a warning are counted across both shards
+        let warning = |client_id: ClientId, tx_id: TxId| {
+            let mut ledger = Ledger::builder()
+                .dispute_reason_allowlist(ReasonAllowlist {
+                    reasons: ["10.4".to_string()].into(),
+                    action: UnknownReasonAction::Warn,
+                })
+                .build();
+            let reason = "99.9".parse::<DisputeReason>().unwrap();
+            for tx in [
+                Transaction::Deposit(Deposit::new(client_id, tx_id, dec!(1))),
+                Transaction::Dispute(Dispute::new(client_id, tx_id).with_reason(reason)),
+            ] {
+                ledger.execute(&tx).unwrap();
+            }
+            ledger
+        };
+        let merged = warning(1, 1).merge(warning(2, 2)).unwrap();
+        assert_eq!(merged.unknown_dispute_reasons(), 2);
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(2, 4, dec!(60.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(60.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-90.0));
+    #[test]
+    fn merge_rejects_duplicate_tx() {
+        let deposits = || ledger_of([Transaction::Deposit(Deposit::new(1, 7, dec!(1)))]);
+        let other = ledger_of([
+            Transaction::Deposit(Deposit::new(2, 7, dec!(1))),
+            Transaction::Deposit(Deposit::new(2, 8, dec!(1))),
+        ]);
+        assert_eq!(
+            deposits().merge(other).err(),
+            Some(MergeConflict::DuplicateTx(7))
+        );
 
-        assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(2, 5, dec!(20.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(40.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-70.0));
+        let holds = ledger_of([
+            Transaction::Deposit(Deposit::new(1, 1, dec!(1))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 9, dec!(1))),
+        ]);
+        let other = ledger_of([
+            Transaction::Deposit(Deposit::new(2, 2, dec!(1))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(2, 9, dec!(1))),
+        ]);
+        assert_eq!(
+            holds.merge(other).err(),
+            Some(MergeConflict::DuplicateTx(9))
+        );
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(2, 4)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(-20.0), dec!(60.0));
-        verify_liabilities(&ledger, dec!(-70.0));
+    #[test]
+    fn merge_checks_and_keeps_tombstones() {
+        // The account of 1 is removed after its deposit 7 was charged back
+        let tombstoned = || {
+            let mut ledger = ledger_of([
+                Transaction::Deposit(Deposit::new(1, 7, dec!(1))),
+                Transaction::Dispute(Dispute::new(1, 7)),
+                Transaction::Chargeback(Chargeback::new(1, 7)),
+                Transaction::Deposit(Deposit::new(3, 1, dec!(2))),
+            ]);
+            ledger.remove_account(1).unwrap();
+            ledger
+        };
+        let reused = || ledger_of([Transaction::Deposit(Deposit::new(2, 7, dec!(1)))]);
+        assert_eq!(
+            tombstoned().merge(reused()).err(),
+            Some(MergeConflict::DuplicateTx(7))
+        );
+        assert_eq!(
+            reused().merge(tombstoned()).err(),
+            Some(MergeConflict::DuplicateTx(7))
+        );
 
-        assert!(ledger
-            .execute(&Transaction::Chargeback(Chargeback::new(2, 4)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(-20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-10.0));
-        verify_account_locked(&ledger, 1);
-        verify_account_locked(&ledger, 2);
+        let other = ledger_of([Transaction::Deposit(Deposit::new(2, 8, dec!(1)))]);
+        let mut merged = other.merge(tombstoned()).unwrap();
+        assert_eq!(
+            merged.execute(&Transaction::Deposit(Deposit::new(2, 7, dec!(1)))),
+            Err(TxError::TxIdReuseAfterSettlement {
+                tx_id: 7,
+                prior_state: TxState::ChargedBack,
+            })
+        );
+        assert_eq!(merged.verify_invariants(), Ok(()));
     }
 
     #[test]
-    fn ops_after_chargeback() {
-        let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+    fn merge_overlapping_accounts() {
+        let shard = || {
+            ledger_of([
+                Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+                Transaction::Deposit(Deposit::new(2, 2, dec!(3))),
+                Transaction::Dispute(Dispute::new(2, 2)),
+            ])
+        };
+        let other = || {
+            ledger_of([
+                Transaction::Deposit(Deposit::new(2, 3, dec!(4))),
+                Transaction::Dispute(Dispute::new(2, 3)),
+                Transaction::Chargeback(Chargeback::new(2, 3)),
+                Transaction::Deposit(Deposit::new(2, 4, dec!(1.5))),
+                Transaction::Deposit(Deposit::new(3, 5, dec!(1))),
+            ])
+        };
+        assert_eq!(
+            shard().merge(other()).err(),
+            Some(MergeConflict::OverlappingAccount(2))
+        );
+        assert_eq!(
+            shard()
+                .merge_with_policy(other(), MergePolicy::FailOnOverlap)
+                .err(),
+            Some(MergeConflict::OverlappingAccount(2))
+        );
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(80.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+        let mut merged = shard()
+            .merge_with_policy(other(), MergePolicy::SumBalances)
+            .unwrap();
+        verify_balances(&merged, 1, dec!(10), dec!(0));
+        verify_balances(&merged, 2, dec!(1.5), dec!(3));
+        verify_balances(&merged, 3, dec!(1), dec!(0));
+        verify_account_locked(&merged, 2);
+        let user_account = merged.accounts.get(&2).unwrap();
+        assert_eq!(user_account.deposited, dec!(8.5));
+        assert_eq!(user_account.charged_back, dec!(4));
+        verify_liabilities(&merged, dec!(-15.5));
+        assert!(merged.verify_invariants().is_ok());
 
-        assert!(ledger
-            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(60.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+        merged
+            .execute(&Transaction::Chargeback(Chargeback::new(2, 2)))
+            .unwrap();
+        verify_balances(&merged, 2, dec!(1.5), dec!(0));
+        assert!(merged.verify_invariants().is_ok());
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(1, 2)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(30.0));
-        verify_liabilities(&ledger, dec!(-60.0));
+    #[test]
+    fn injected_liability_drift_is_detected() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(0.05))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(4))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        ledger.sweep_dust(dec!(0.1));
+        assert_eq!(ledger.dust(), dec!(0.05));
+        verify_liabilities(&ledger, dec!(-0.05) + dec!(4));
+        assert_eq!(ledger.recompute_liabilities(), dec!(3.95));
 
-        assert!(ledger
-            .execute(&Transaction::Chargeback(Chargeback::new(1, 2)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(30.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-30.0));
-        verify_account_locked(&ledger, 1);
+        ledger.inject_liability_drift(dec!(-0.0001));
+        assert_eq!(
+            ledger.audit_liabilities(),
+            Err(LiabilityDrift {
+                expected: dec!(3.95),
+                actual: dec!(3.9499),
+                delta: dec!(-0.0001),
+            })
+        );
+        assert!(matches!(
+            ledger.verify_invariants(),
+            Err(InvariantViolation::UnbalancedLiabilities { .. })
+        ));
+        ledger.inject_liability_drift(dec!(0.0001));
+        assert_eq!(ledger.audit_liabilities(), Ok(()));
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 4, dec!(40.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
+    fn withdrawals_with_references() -> ScenarioBuilder {
+        ScenarioBuilder::new()
+            .deposit(1, 1, "100")
+            .transaction(Transaction::Withdrawal(
+                Withdrawal::new(1, 2, dec!(30))
+                    .with_reference("PAYOUT-2")
+                    .unwrap(),
+            ))
+            .withdraw(1, 3, "20")
+            .deposit(2, 4, "10")
+    }
 
+    #[test]
+    fn settle_withdrawal() {
+        let ledger = withdrawals_with_references()
+            .settle(1, 2)
+            .expect_balance(1, "50", "0")
+            .expect_liabilities("-60")
+            .run();
         assert_eq!(
-            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(1, 5, dec!(40.0)))),
-            Err(TxError::ClientAccountLocked)
+            ledger.unsettled_withdrawals(),
+            [UnsettledWithdrawal {
+                client_id: 1,
+                tx_id: 3,
+                amount: dec!(20),
+                reference: None,
+            }]
         );
-        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
+        let record = ledger.tx_states.get(&2).unwrap().withdrawal().unwrap();
+        assert_eq!(record.reference(), Some("PAYOUT-2"));
+        assert!(record.is_settled());
+    }
+
+    #[test]
+    fn settle_twice() {
+        let ledger = withdrawals_with_references()
+            .settle(1, 3)
+            .settle(1, 3)
+            .expect_err(TxError::WithdrawalAlreadySettled)
+            .expect_balance(1, "50", "0")
+            .run();
+        assert_eq!(ledger.unsettled_withdrawals().len(), 1);
+    }
+
+    #[test]
+    fn settle_of_other_transactions() {
+        withdrawals_with_references()
+            .settle(1, 1)
+            .expect_err(TxError::TxNotWithdrawal)
+            .settle(1, 9)
+            .expect_err(TxError::WithdrawalNotFound)
+            // Withdrawal of another client
+            .settle(2, 2)
+            .expect_err(TxError::WithdrawalNotFound)
+            .expect_balance(1, "50", "0")
+            .expect_balance(2, "10", "0")
+            .run();
+    }
 
+    #[test]
+    fn withdrawals_cannot_reuse_tx_ids() {
+        let mut ledger = withdrawals_with_references()
+            // Ids of a withdrawal, a deposit and a withdrawal of another client
+            .withdraw(1, 2, "5")
+            .expect_err(TxError::DuplicateTxId)
+            .withdraw(1, 1, "5")
+            .expect_err(TxError::DuplicateTxId)
+            .withdraw(2, 3, "5")
+            .expect_err(TxError::DuplicateTxId)
+            .expect_balance(1, "50", "0")
+            .expect_balance(2, "10", "0")
+            .expect_liabilities("-60")
+            .settle(1, 2)
+            .dispute(2, 4)
+            .chargeback(2, 4)
+            .run();
         assert_eq!(
-            ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
-            Err(TxError::TxAlreadyDisputed)
+            ledger.unsettled_withdrawals(),
+            [UnsettledWithdrawal {
+                client_id: 1,
+                tx_id: 3,
+                amount: dec!(20),
+                reference: None,
+            }]
         );
-        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
 
         assert_eq!(
-            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
-            Err(TxError::TxNotDisputed)
+            ledger.prune_settled_tx_states(|deposit| deposit.tx_id == 4),
+            1
         );
-        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
-
         assert_eq!(
-            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 2))),
-            Err(TxError::TxNotDisputed)
+            ledger.execute(&Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(5)))),
+            Err(TxError::DuplicateTxId)
         );
-        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
+        verify_balances(&ledger, 1, dec!(50), dec!(0));
+        verify_liabilities(&ledger, dec!(-50));
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(20.0), dec!(50.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
+    #[test]
+    fn memory_stats_count_withdrawal_references() {
+        let mut ledger = withdrawals_with_references().withdraw(1, 5, "50").run();
+        let stats = ledger.memory_stats();
+        assert_eq!(stats.withdrawal_references, 1);
+        assert_eq!(stats.withdrawal_reference_bytes, "PAYOUT-2".len());
 
-        assert!(ledger
-            .execute(&Transaction::Resolve(Resolve::new(1, 1)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(70.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
+        ledger.remove_account(1).unwrap();
+        let stats = ledger.memory_stats();
+        assert_eq!(stats.withdrawal_references, 0);
+        assert_eq!(stats.withdrawal_reference_bytes, 0);
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(20.0), dec!(50.0));
-        verify_liabilities(&ledger, dec!(-70.0));
-        verify_account_locked(&ledger, 1);
+    fn mixed_transactions() -> Vec<Transaction> {
+        vec![
+            Transaction::Deposit(Deposit::new(1, 1, dec!(100))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(50))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(30))),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            // Insufficient funds while the deposit is disputed
+            Transaction::Withdrawal(Withdrawal::new(2, 4, dec!(10))),
+            Transaction::Deposit(Deposit::new(3, 5, dec!(20))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 6, dec!(40))),
+            Transaction::Resolve(Resolve::new(2, 2)),
+            Transaction::WithdrawCapture(WithdrawCapture::new(1, 6)),
+            Transaction::Deposit(Deposit::new(1, 7, dec!(5))),
+            Transaction::Dispute(Dispute::new(1, 7)),
+            Transaction::Chargeback(Chargeback::new(1, 7)),
+            Transaction::WithdrawAuth(WithdrawAuth::new(3, 8, dec!(15))),
+            Transaction::WithdrawRelease(WithdrawRelease::new(3, 8)),
+            Transaction::Adjustment(Adjustment::new(3, 5)),
+            Transaction::Deposit(Deposit::new(4, 9, dec!(12.5))),
+            Transaction::Dispute(Dispute::new(4, 9)),
+        ]
+    }
 
-        assert!(ledger
-            .execute(&Transaction::Chargeback(Chargeback::new(1, 1)))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(20.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-20.0));
-        verify_account_locked(&ledger, 1);
+    #[test]
+    fn running_totals_match_recomputed_totals_at_samples() {
+        let mut ledger = Ledger::builder()
+            .liability_sampling(LiabilitySampling::every(3))
+            .build();
+        let mut checked = 0;
+        for tx in mixed_transactions() {
+            let _ = ledger.execute(&tx);
+            let sampled = ledger.liability_samples().last();
+            if sampled.is_some_and(|sample| sample.seq == ledger.tx_seq()) {
+                assert_eq!(ledger.audit_totals(), Ok(()));
+                let (available, held) = ledger.recompute_totals();
+                let sample = sampled.unwrap();
+                assert_eq!(sample.total_available, available);
+                assert_eq!(sample.total_held, held);
+                assert_eq!(sample.liabilities, ledger.recompute_liabilities());
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, ledger.tx_seq() / 3);
+        assert_eq!(checked, 5);
+        assert_eq!(
+            ledger.liability_samples().last(),
+            Some(&LiabilitySample {
+                seq: 15,
+                liabilities: dec!(-92.5),
+                total_available: dec!(92.5),
+                total_held: dec!(0),
+                accounts: 4,
+            })
+        );
+        assert_eq!(ledger.verify_invariants(), Ok(()));
     }
 
     #[test]
-    fn mismatch_client_id_and_tx_id() {
-        let mut ledger = Ledger::new();
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-50.0));
+    fn running_totals_follow_operations_outside_execute() {
+        let mut ledger = Ledger::builder().dust_threshold(dec!(1)).build();
+        for tx in mixed_transactions() {
+            let _ = ledger.execute(&tx);
+        }
+        ledger.execute(&Deposit::new(5, 10, dec!(0.5))).unwrap();
+        ledger.sweep_configured_dust();
+        assert_eq!(ledger.audit_totals(), Ok(()));
+        ledger.expire_disputes(u64::MAX);
+        assert_eq!(ledger.total_held(), dec!(0));
+        ledger.merge_accounts(4, 1).unwrap();
+        assert_eq!(ledger.audit_totals(), Ok(()));
 
-        assert!(ledger
-            .execute(&Transaction::Deposit(Deposit::new(2, 2, dec!(30.0))))
-            .is_ok());
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+        let other = ScenarioBuilder::new().deposit(9, 20, "7").run();
+        let merged = ledger.merge(other).unwrap();
+        assert_eq!(merged.total_available(), dec!(99.5));
+        assert_eq!(merged.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn totals_drift_violates_invariants() {
+        let mut ledger = ScenarioBuilder::new().deposit(1, 1, "10").run();
+        ledger.total_held += dec!(2);
+        let drift = TotalsDrift {
+            expected_available: dec!(10),
+            expected_held: dec!(0),
+            available: dec!(10),
+            held: dec!(2),
+        };
+        assert_eq!(ledger.audit_totals(), Err(drift));
+        assert!(matches!(
+            ledger.verify_invariants(),
+            Err(InvariantViolation::RunningTotalsDrift(_))
+        ));
+    }
 
+    #[test]
+    fn liability_samples_stream_to_writer() {
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let buffer = SharedBuffer::default();
+        let mut ledger = Ledger::builder()
+            .liability_sampling_writer(LiabilitySampling::every(8), buffer.clone())
+            .build();
+        for tx in mixed_transactions() {
+            let _ = ledger.execute(&tx);
+        }
+        ledger.flush_liability_samples().unwrap();
+        assert!(ledger.liability_samples().is_empty());
         assert_eq!(
-            ledger.execute(&Transaction::Dispute(Dispute::new(1, 2))),
-            Err(TxError::OriginTxNotFound)
+            String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(),
+            "seq,liabilities,total_available,total_held,accounts\n\
+             8,-100,100,0,3\n\
+             16,-92.5,80,12.5,4\n"
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+    }
+
+    fn spread_ledger() -> Ledger {
+        let mut ledger = Ledger::builder()
+            .account_tiering(AccountTiering {
+                demote_after: 2,
+                hot_capacity: 4,
+            })
+            .build();
+        for (tx_id, client_id) in (1..).zip([900, 3, 70, 1, 5, 65_535, 42]) {
+            ledger
+                .execute(&Deposit::new(client_id, tx_id, Decimal::from(tx_id)))
+                .unwrap();
+            if client_id == 1 {
+                ledger.compact();
+            }
+        }
+        ledger
+    }
 
+    #[test]
+    fn account_logs_are_sorted_by_client_id() {
+        let ledger = spread_ledger();
+        let logs: Vec<_> = ledger.account_logs().collect();
+        let client_ids: Vec<_> = logs.iter().map(|log| log.client_id).collect();
+        assert_eq!(client_ids, [1, 3, 5, 42, 70, 900, 65_535]);
         assert_eq!(
-            ledger.execute(&Transaction::Resolve(Resolve::new(1, 2))),
-            Err(TxError::OriginTxNotFound)
+            logs[0],
+            AccountLog {
+                client_id: 1,
+                available: dec!(4),
+                held: dec!(0),
+                total: dec!(4),
+                locked: false,
+            }
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
+    }
+
+    #[cfg(feature = "io")]
+    #[tokio::test]
+    async fn account_log_stream_feeds_chunking_consumers() {
+        use tokio_stream::StreamExt;
+
+        let ledger = spread_ledger();
+        let mut stream = std::pin::pin!(ledger.account_log_stream());
+        let mut chunks = vec![Vec::new()];
+        while let Some(log) = stream.next().await {
+            if chunks.last().unwrap().len() == 3 {
+                chunks.push(Vec::new());
+            }
+            chunks.last_mut().unwrap().push(log.client_id);
+        }
+        assert_eq!(chunks, [vec![1, 3, 5], vec![42, 70, 900], vec![65_535]]);
+    }
+
+    #[test]
+    fn account_logs_round_trip_through_csv() {
+        let ledger = spread_ledger();
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for log in ledger.account_logs() {
+            writer.serialize(log).unwrap();
+        }
+        let csv = writer.into_inner().unwrap();
+        assert!(csv.starts_with(b"client,available,held,total,locked\n1,4,0,4,false\n"));
+        let read: Vec<AccountLog> = csv::Reader::from_reader(csv.as_slice())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, ledger.account_logs().collect::<Vec<_>>());
+    }
+
+    /// Random transactions of a few clients, most referencing earlier ones, from a
+    /// linear congruential generator so that failures reproduce from the seed.
+    fn random_transactions(seed: u64, count: u32) -> Vec<Transaction> {
+        let mut state = seed;
+        let mut next = |bound: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % bound
+        };
+        (1..=count)
+            .map(|tx_id| {
+                let client_id = next(8) as ClientId;
+                let earlier = next(tx_id as u64) as TxId + 1;
+                let amount = Decimal::new(next(5_000) as i64, 2);
+                match next(10) {
+                    0..=3 => Transaction::Deposit(Deposit::new(client_id, tx_id, amount)),
+                    4 | 5 => Transaction::Withdrawal(Withdrawal::new(client_id, tx_id, amount)),
+                    6 => Transaction::Dispute(Dispute::new(client_id, earlier)),
+                    7 => Transaction::Resolve(Resolve::new(client_id, earlier)),
+                    8 => Transaction::Chargeback(Chargeback::new(client_id, earlier)),
+                    // Repeats an id, which is rejected as a duplicate
+                    _ => Transaction::Deposit(Deposit::new(client_id, earlier, amount)),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn execute_batch_matches_executing_one_by_one() {
+        for seed in 0..50 {
+            let txs = random_transactions(seed, 400);
+            let mut one_by_one = Ledger::new();
+            let expected: Vec<_> = txs.iter().map(|tx| one_by_one.execute(tx)).collect();
+
+            // Split into uneven batches, so that batches start on a ledger with state
+            let mut batched = Ledger::new();
+            let mut results = Vec::new();
+            for batch in txs.chunks(1 + seed as usize * 7) {
+                results.extend(batched.execute_batch(batch));
+            }
+            assert_eq!(results, expected, "seed {}", seed);
+            assert!(results.iter().any(Result::is_err), "seed {}", seed);
+            assert_eq!(
+                batched.account_logs().collect::<Vec<_>>(),
+                one_by_one.account_logs().collect::<Vec<_>>(),
+                "seed {}",
+                seed
+            );
+            assert_eq!(batched.view().summary(), one_by_one.view().summary());
+            let states = |ledger: &Ledger| {
+                let mut states: Vec<_> = ledger
+                    .tx_records_iter()
+                    .map(|record| (record.tx_id, record.state))
+                    .collect();
+                states.sort_unstable_by_key(|(tx_id, _state)| *tx_id);
+                states
+            };
+            assert_eq!(states(&batched), states(&one_by_one), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn disabled_dispute_tracking_keeps_the_balances_of_dispute_free_inputs() {
+        for seed in 0..20 {
+            // Without records, deposits reusing a tx id are not detected
+            let mut tx_ids = HashSet::new();
+            let txs: Vec<_> = random_transactions(seed, 400)
+                .into_iter()
+                .filter(|tx| matches!(tx.kind(), TxTypeTag::Deposit | TxTypeTag::Withdrawal))
+                .filter(|tx| tx_ids.insert(tx.tx_id()))
+                .collect();
+            let mut full = Ledger::new();
+            let mut disabled = Ledger::builder()
+                .dispute_tracking(DisputeTracking::Disabled)
+                .build();
+            for tx in &txs {
+                assert_eq!(disabled.execute(tx), full.execute(tx), "seed {}", seed);
+            }
+            assert_eq!(
+                disabled.account_logs().collect::<Vec<_>>(),
+                full.account_logs().collect::<Vec<_>>(),
+                "seed {}",
+                seed
+            );
+            // Withdrawals without a reference are not recorded either
+            assert_eq!(disabled.memory_stats().tx_records, 0);
+        }
+    }
+
+    #[test]
+    fn disabled_dispute_tracking_takes_no_memory_per_withdrawal() {
+        let mut ledger = Ledger::builder()
+            .dispute_tracking(DisputeTracking::Disabled)
+            .build();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(100000))))
+            .unwrap();
+        let withdraw = |ledger: &mut Ledger, tx_ids: std::ops::Range<TxId>| {
+            for tx_id in tx_ids {
+                let tx = Withdrawal::new(1, tx_id, dec!(1));
+                ledger.execute(&Transaction::Withdrawal(tx)).unwrap();
+            }
+        };
+        withdraw(&mut ledger, 2..102);
+        let stats = ledger.memory_stats();
+        withdraw(&mut ledger, 102..10_102);
+        assert_eq!(ledger.memory_stats(), stats);
+        assert_eq!(stats.tx_records, 0);
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(89900));
 
+        // Withdrawals with a reference are recorded, so that they can be settled
+        let tx = Withdrawal::new(1, 10_102, dec!(1))
+            .with_reference("PAYOUT-1")
+            .unwrap();
+        ledger.execute(&Transaction::Withdrawal(tx)).unwrap();
+        assert_eq!(ledger.memory_stats().tx_records, 1);
+        assert_eq!(ledger.memory_stats().withdrawal_references, 1);
+        ledger
+            .execute(&Transaction::Settle(Settle::new(1, 10_102)))
+            .unwrap();
+    }
+
+    #[test]
+    fn disabled_dispute_tracking_rejects_disputes_without_changes() {
+        let mut ledger = Ledger::builder()
+            .dispute_tracking(DisputeTracking::Disabled)
+            .unknown_client_on_debit(UnknownClientOnDebit::CreateEmpty)
+            .build();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+            .unwrap();
+        let before: Vec<_> = ledger.account_logs().collect();
+        for tx in [
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+            Transaction::Adjustment(Adjustment::new(1, 1)),
+            Transaction::Dispute(Dispute::new(2, 1)),
+        ] {
+            assert_eq!(ledger.execute(&tx), Err(TxError::DisputeTrackingDisabled));
+        }
+        assert_eq!(ledger.account_logs().collect::<Vec<_>>(), before);
+        assert!(ledger.account(2).is_none());
+        assert_eq!(ledger.tx_seq(), 1);
+        assert!(ledger.verify_invariants().is_ok());
+        ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(4))))
+            .unwrap();
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(6));
+    }
+
+    #[test]
+    fn execute_batch_reports_errors_at_their_index() {
+        let mut ledger = Ledger::new();
+        let results = ledger.execute_batch(&[
+            Transaction::Deposit(Deposit::new(1, 1, dec!(5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(6))),
+            Transaction::Dispute(Dispute::new(2, 1)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(5))),
+        ]);
         assert_eq!(
-            ledger.execute(&Transaction::Chargeback(Chargeback::new(1, 2))),
-            Err(TxError::OriginTxNotFound)
+            results,
+            [
+                Ok(()),
+                Err(TxError::InsufficientFunds),
+                Err(TxError::ClientAccountNotFound),
+                Ok(()),
+            ]
         );
-        verify_balances(&ledger, 1, dec!(50.0), dec!(0.0));
-        verify_balances(&ledger, 2, dec!(30.0), dec!(0.0));
-        verify_liabilities(&ledger, dec!(-80.0));
-
-        verify_account_not_locked(&ledger, 1);
-        verify_account_not_locked(&ledger, 2);
+        assert!(ledger.execute_batch::<Transaction>(&[]).is_empty());
     }
 }
@@ -0,0 +1,422 @@
+//! Transactions the ledger derives from executed ones, such as a fee charged for every
+//! chargeback, see `PostingRule`.
+//!
+//! Rules are asked for postings after every applied transaction, and the postings
+//! are executed right away, before the next transaction. Each gets a synthetic tx id
+//! from `DERIVED_TX_ID_START` up, the range with the top bit set. While rules are
+//! configured, deposits, withdrawals and withdrawal authorizations with an id in that
+//! range are rejected with `TxError::ReservedTxId`, so derived ids never collide
+//! with the ids of the input.
+//!
+//! Postings move funds between the available balance of an account and the
+//! liabilities, regardless of the lock and the overdraft limit of the account, so a
+//! fee may take the balance below zero. They are not recorded as transactions, so
+//! they cannot be disputed or settled. The journal marks them with the tx id of the
+//! transaction causing them, and they are reported as `LedgerEvent::DerivedPosted`
+//! and `RowOutcome::Derived`.
+use crate::accounting::transactions::TxTypeTag;
+use crate::accounting::{transfer, ExecutableTransaction, Ledger, TxError};
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+/// First synthetic tx id of derived postings.
+pub const DERIVED_TX_ID_START: TxId = 1 << 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivedKind {
+    /// Debits the available balance, like a withdrawal.
+    Fee,
+    /// Credits the available balance, like a deposit.
+    Credit,
+}
+
+impl Display for DerivedKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DerivedKind::Fee => "fee",
+            DerivedKind::Credit => "credit",
+        })
+    }
+}
+
+/// Posting requested by a `PostingRule`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedTransaction {
+    pub client_id: ClientId,
+    pub kind: DerivedKind,
+    pub amount: Decimal,
+}
+
+impl DerivedTransaction {
+    pub fn fee(client_id: ClientId, amount: Decimal) -> Self {
+        Self {
+            client_id,
+            kind: DerivedKind::Fee,
+            amount,
+        }
+    }
+
+    pub fn credit(client_id: ClientId, amount: Decimal) -> Self {
+        Self {
+            client_id,
+            kind: DerivedKind::Credit,
+            amount,
+        }
+    }
+}
+
+/// Derives postings from applied transactions, see `LedgerBuilder::posting_rule`.
+pub trait PostingRule: Send + Sync {
+    /// Name of the rule, reported with its postings.
+    fn name(&self) -> &'static str;
+
+    /// Postings due after `tx` was applied to `ledger`. `tx` is a derived posting
+    /// itself, with a `cause`, only when `LedgerBuilder::rules_on_derived_postings`
+    /// allows it.
+    fn postings(&self, tx: &dyn ExecutableTransaction, ledger: &Ledger) -> Vec<DerivedTransaction>;
+}
+
+/// Charges the client `amount` for every chargeback applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChargebackFeeRule {
+    pub amount: Decimal,
+}
+
+impl PostingRule for ChargebackFeeRule {
+    fn name(&self) -> &'static str {
+        "chargeback_fee"
+    }
+
+    fn postings(
+        &self,
+        tx: &dyn ExecutableTransaction,
+        _ledger: &Ledger,
+    ) -> Vec<DerivedTransaction> {
+        match tx.kind() {
+            TxTypeTag::Chargeback => vec![DerivedTransaction::fee(tx.client_id(), self.amount)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Rules of a ledger in the order they are asked for postings. Rule lists are equal
+/// when they hold the same rules.
+#[derive(Clone, Default)]
+pub struct PostingRules(Vec<Arc<dyn PostingRule>>);
+
+impl PostingRules {
+    pub fn push(&mut self, rule: impl PostingRule + 'static) {
+        self.0.push(Arc::new(rule));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn PostingRule>> {
+        self.0.iter()
+    }
+}
+
+impl PartialEq for PostingRules {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|(rule, other)| Arc::ptr_eq(rule, other))
+    }
+}
+
+impl Debug for PostingRules {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|rule| rule.name()))
+            .finish()
+    }
+}
+
+/// Derived posting executed by the ledger.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedPosting {
+    pub tx_id: TxId,
+    /// Tx id of the transaction the posting was derived from.
+    pub cause: TxId,
+    /// Name of the rule which requested the posting.
+    pub rule: &'static str,
+    pub client_id: ClientId,
+    pub kind: DerivedKind,
+    pub amount: Decimal,
+    /// 1 for postings derived from transactions of the input, 2 for postings derived
+    /// from those, and so on.
+    pub depth: u32,
+}
+
+impl ExecutableTransaction for DerivedPosting {
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        let client_account = ledger
+            .accounts
+            .get_mut(&self.client_id)
+            .ok_or(TxError::ClientAccountNotFound)?;
+        match self.kind {
+            DerivedKind::Fee => transfer(
+                &mut client_account.available,
+                &mut ledger.liabilities,
+                self.amount,
+            ),
+            DerivedKind::Credit => transfer(
+                &mut ledger.liabilities,
+                &mut client_account.available,
+                self.amount,
+            ),
+        }
+    }
+
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    /// Withdrawal for fees and deposit for credits, which move funds the same way.
+    fn kind(&self) -> TxTypeTag {
+        match self.kind {
+            DerivedKind::Fee => TxTypeTag::Withdrawal,
+            DerivedKind::Credit => TxTypeTag::Deposit,
+        }
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
+    fn cause(&self) -> Option<TxId> {
+        Some(self.cause)
+    }
+}
+
+/// Derived posting and the result of executing it.
+#[derive(Debug, PartialEq)]
+pub struct DerivedOutcome {
+    pub posting: DerivedPosting,
+    pub result: Result<(), TxError>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::events::LedgerEvent;
+    use crate::accounting::transactions::{Chargeback, Deposit, Dispute, Withdrawal};
+    use crate::accounting::TxOutcome;
+    use rust_decimal_macros::dec;
+    use std::sync::Mutex;
+
+    fn charged_back(ledger: &mut Ledger, client_id: ClientId, tx_id: TxId, amount: Decimal) {
+        ledger
+            .execute(&Deposit::new(client_id, tx_id, amount))
+            .unwrap();
+        ledger.execute(&Dispute::new(client_id, tx_id)).unwrap();
+        ledger.execute(&Chargeback::new(client_id, tx_id)).unwrap();
+    }
+
+    #[test]
+    fn chargebacks_are_charged_a_fee() {
+        let mut ledger = Ledger::builder()
+            .posting_rule(ChargebackFeeRule { amount: dec!(15) })
+            .journal()
+            .build();
+        ledger.execute(&Deposit::new(1, 1, dec!(100))).unwrap();
+        charged_back(&mut ledger, 1, 2, dec!(20));
+        let posting = DerivedPosting {
+            tx_id: DERIVED_TX_ID_START,
+            cause: 2,
+            rule: "chargeback_fee",
+            client_id: 1,
+            kind: DerivedKind::Fee,
+            amount: dec!(15),
+            depth: 1,
+        };
+        assert_eq!(
+            ledger.last_derived_postings(),
+            [DerivedOutcome {
+                posting,
+                result: Ok(())
+            }]
+        );
+        let account = ledger.account(1).unwrap();
+        assert_eq!(account.available(), dec!(85));
+        assert!(account.locked());
+        assert_eq!(account.total_withdrawn(), dec!(0));
+        assert_eq!(ledger.derived_postings(), 1);
+        assert!(ledger.audit_liabilities().is_ok());
+        assert!(ledger.audit_totals().is_ok());
+        let entry = ledger.journal().last().unwrap();
+        assert_eq!(
+            (entry.tx_id, entry.kind, entry.cause),
+            (DERIVED_TX_ID_START, TxTypeTag::Withdrawal, Some(2))
+        );
+        assert_eq!(entry.available_delta, dec!(-15));
+        // Postings belong to the transaction executed last only
+        ledger.execute(&Deposit::new(2, 3, dec!(1))).unwrap();
+        assert!(ledger.last_derived_postings().is_empty());
+    }
+
+    #[test]
+    fn derived_ids_count_up_from_the_reserved_range() {
+        let mut ledger = Ledger::builder()
+            .posting_rule(ChargebackFeeRule { amount: dec!(1) })
+            .build();
+        for client_id in 1..=3 {
+            charged_back(&mut ledger, client_id, client_id.into(), dec!(5));
+            let outcome = &ledger.last_derived_postings()[0];
+            assert_eq!(
+                outcome.posting.tx_id,
+                DERIVED_TX_ID_START + TxId::from(client_id) - 1
+            );
+        }
+        for tx in [
+            Deposit::new(4, DERIVED_TX_ID_START, dec!(1)).into(),
+            Withdrawal::new(1, TxId::MAX, dec!(1)).into(),
+        ] {
+            let tx: crate::accounting::transactions::Transaction = tx;
+            assert_eq!(ledger.execute(&tx), Err(TxError::ReservedTxId));
+        }
+        // The range is only reserved while rules are configured
+        let mut ledger = Ledger::new();
+        assert_eq!(
+            ledger.execute_outcome(&Deposit::new(4, DERIVED_TX_ID_START, dec!(1))),
+            Ok(TxOutcome::Applied)
+        );
+    }
+
+    #[test]
+    fn fees_take_locked_balances_below_zero() {
+        let mut ledger = Ledger::builder()
+            .posting_rule(ChargebackFeeRule { amount: dec!(15) })
+            .build();
+        charged_back(&mut ledger, 1, 1, dec!(10));
+        let account = ledger.account(1).unwrap();
+        assert!(account.locked());
+        assert_eq!(account.available(), dec!(-15));
+        assert_eq!(account.total(), dec!(-15));
+        assert!(ledger.audit_liabilities().is_ok());
+        // Withdrawals stay bound by the overdraft limit
+        ledger.unlock(1).unwrap();
+        assert_eq!(
+            ledger.execute(&Withdrawal::new(1, 2, dec!(1))),
+            Err(TxError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn derived_postings_are_reported_with_their_cause() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut ledger = Ledger::builder()
+            .posting_rule(ChargebackFeeRule { amount: dec!(2.5) })
+            .event_listener(move |event| {
+                recorded.lock().unwrap().push(event.clone());
+                Ok(())
+            })
+            .build();
+        charged_back(&mut ledger, 7, 9, dec!(10));
+        assert_eq!(
+            events.lock().unwrap().last(),
+            Some(&LedgerEvent::DerivedPosted {
+                client_id: 7,
+                tx_id: DERIVED_TX_ID_START,
+                cause: 9,
+                rule: "chargeback_fee",
+                kind: DerivedKind::Fee,
+                amount: dec!(2.5),
+            })
+        );
+    }
+
+    /// Credits a cent for every transaction, derived postings included.
+    struct Cashback;
+
+    impl PostingRule for Cashback {
+        fn name(&self) -> &'static str {
+            "cashback"
+        }
+
+        fn postings(
+            &self,
+            tx: &dyn ExecutableTransaction,
+            _ledger: &Ledger,
+        ) -> Vec<DerivedTransaction> {
+            vec![DerivedTransaction::credit(tx.client_id(), dec!(0.01))]
+        }
+    }
+
+    #[test]
+    fn derived_postings_trigger_rules_only_when_allowed() {
+        let mut ledger = Ledger::builder().posting_rule(Cashback).build();
+        ledger.execute(&Deposit::new(1, 1, dec!(1))).unwrap();
+        assert_eq!(ledger.last_derived_postings().len(), 1);
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(1.01));
+
+        let mut ledger = Ledger::builder()
+            .posting_rule(Cashback)
+            .rules_on_derived_postings(2)
+            .build();
+        ledger.execute(&Deposit::new(1, 1, dec!(1))).unwrap();
+        let postings: Vec<_> = ledger
+            .last_derived_postings()
+            .iter()
+            .map(|outcome| {
+                (
+                    outcome.posting.tx_id,
+                    outcome.posting.cause,
+                    outcome.posting.depth,
+                )
+            })
+            .collect();
+        assert_eq!(
+            postings,
+            [
+                (DERIVED_TX_ID_START, 1, 1),
+                (DERIVED_TX_ID_START + 1, DERIVED_TX_ID_START, 2),
+                (DERIVED_TX_ID_START + 2, DERIVED_TX_ID_START + 1, 3),
+            ]
+        );
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(1.03));
+    }
+
+    #[test]
+    fn failed_postings_leave_the_cause_applied() {
+        struct FeeOfAnother;
+
+        impl PostingRule for FeeOfAnother {
+            fn name(&self) -> &'static str {
+                "fee_of_another"
+            }
+
+            fn postings(
+                &self,
+                _tx: &dyn ExecutableTransaction,
+                _ledger: &Ledger,
+            ) -> Vec<DerivedTransaction> {
+                vec![DerivedTransaction::fee(99, dec!(1))]
+            }
+        }
+
+        let mut ledger = Ledger::builder().posting_rule(FeeOfAnother).build();
+        assert_eq!(
+            ledger.execute_outcome(&Deposit::new(1, 1, dec!(1))),
+            Ok(TxOutcome::Applied)
+        );
+        assert_eq!(
+            ledger.last_derived_postings()[0].result,
+            Err(TxError::ClientAccountNotFound)
+        );
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(1));
+        assert!(ledger.account(99).is_none());
+    }
+}
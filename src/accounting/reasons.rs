@@ -0,0 +1,136 @@
+//! Reason codes of disputes and chargebacks, such as the `10.4` (fraud) or `13.1`
+//! (merchandise not received) codes of card networks.
+//!
+//! A feed uses a handful of distinct reasons for any number of disputes, so a ledger
+//! interns them in a `ReasonTable` and the deposits disputed for the same reason share
+//! one allocation. Reasons are at most `MAX_REASON_LEN` bytes.
+use crate::accounting::transactions::TransactionLogError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Longest reason in bytes, which bounds the memory every unshared reason may take.
+pub const MAX_REASON_LEN: usize = 32;
+
+/// Distinct reasons a ledger interns. Reasons beyond are stored unshared, so that a
+/// feed of ever new reasons does not grow the table.
+pub(crate) const MAX_INTERNED_REASONS: usize = 256;
+
+/// Reason given on a dispute or chargeback row, cheap to clone. Parsing fails with
+/// `TransactionLogError::ReasonTooLong` beyond `MAX_REASON_LEN` bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DisputeReason(Arc<str>);
+
+impl DisputeReason {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for DisputeReason {
+    type Err = TransactionLogError;
+
+    fn from_str(reason: &str) -> Result<Self, Self::Err> {
+        if reason.len() > MAX_REASON_LEN {
+            return Err(TransactionLogError::ReasonTooLong);
+        }
+        Ok(DisputeReason(reason.into()))
+    }
+}
+
+impl Borrow<str> for DisputeReason {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for DisputeReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for DisputeReason {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for DisputeReason {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let reason = String::deserialize(deserializer)?;
+        reason.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Reasons stored by a ledger, see the module documentation.
+#[derive(Default)]
+pub(crate) struct ReasonTable {
+    reasons: HashSet<DisputeReason>,
+}
+
+impl ReasonTable {
+    /// The interned copy of `reason`, interning it while the table has room.
+    pub(crate) fn intern(&mut self, reason: &DisputeReason) -> DisputeReason {
+        if let Some(interned) = self.reasons.get(reason) {
+            return interned.clone();
+        }
+        if self.reasons.len() < MAX_INTERNED_REASONS {
+            self.reasons.insert(reason.clone());
+        }
+        reason.clone()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.reasons.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(reason: &str) -> DisputeReason {
+        reason.parse().unwrap()
+    }
+
+    #[test]
+    fn interned_reasons_share_their_allocation() {
+        let mut table = ReasonTable::default();
+        let fraud = table.intern(&reason("10.4"));
+        let again = table.intern(&reason("10.4"));
+        assert!(Arc::ptr_eq(&fraud.0, &again.0));
+        assert_eq!(again.as_str(), "10.4");
+        assert_eq!(table.intern(&reason("13.1")).to_string(), "13.1");
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn the_table_stops_growing_when_full() {
+        let mut table = ReasonTable::default();
+        for code in 0..MAX_INTERNED_REASONS + 10 {
+            table.intern(&reason(&code.to_string()));
+        }
+        assert_eq!(table.len(), MAX_INTERNED_REASONS);
+        let fresh = reason("fresh");
+        assert!(Arc::ptr_eq(&table.intern(&fresh).0, &fresh.0));
+        assert_eq!(table.len(), MAX_INTERNED_REASONS);
+        let zero = table.intern(&reason("0"));
+        assert!(Arc::ptr_eq(&zero.0, &table.intern(&reason("0")).0));
+    }
+
+    #[test]
+    fn reasons_are_capped() {
+        let long = "x".repeat(MAX_REASON_LEN + 1);
+        assert_eq!(
+            long.parse::<DisputeReason>(),
+            Err(TransactionLogError::ReasonTooLong)
+        );
+        assert_eq!(reason(&long[1..]).as_str().len(), MAX_REASON_LEN);
+        assert_eq!(serde_json::to_string(&reason("10.4")).unwrap(), "\"10.4\"");
+        assert!(serde_json::from_str::<DisputeReason>(&format!("\"{}\"", long)).is_err());
+    }
+}
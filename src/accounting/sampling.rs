@@ -0,0 +1,146 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io::Write;
+
+/// Samples kept in memory by default before they are thinned out.
+pub const DEFAULT_MAX_LIABILITY_SAMPLES: usize = 10_000;
+
+/// Samples the ledger-wide balances every `every_n_tx` executed transactions, to follow
+/// how the liabilities evolved over a run, see `LedgerBuilder::liability_sampling`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiabilitySampling {
+    pub every_n_tx: u64,
+    /// Samples kept in memory. Once a sample would exceed it, every other sample is
+    /// dropped and the interval doubles, so that the samples still span the whole run.
+    /// Samples streamed to a writer are not limited.
+    pub max_samples: usize,
+}
+
+impl LiabilitySampling {
+    pub fn every(every_n_tx: u64) -> Self {
+        Self {
+            every_n_tx,
+            max_samples: DEFAULT_MAX_LIABILITY_SAMPLES,
+        }
+    }
+}
+
+/// Ledger-wide balances right after the `seq`-th executed transaction.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LiabilitySample {
+    pub seq: u64,
+    pub liabilities: Decimal,
+    pub total_available: Decimal,
+    pub total_held: Decimal,
+    pub accounts: usize,
+}
+
+pub(crate) enum SampleTarget {
+    Memory(Vec<LiabilitySample>),
+    /// Samples are written as CSV, the same format as `output_liability_curve`.
+    Writer(Box<csv::Writer<Box<dyn Write + Send>>>),
+}
+
+impl SampleTarget {
+    pub(crate) fn writer(writer: impl Write + Send + 'static) -> Self {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        SampleTarget::Writer(Box::new(csv::Writer::from_writer(writer)))
+    }
+}
+
+pub(crate) struct LiabilitySampler {
+    every_n_tx: u64,
+    max_samples: usize,
+    target: SampleTarget,
+    /// First write error, kept until `Ledger::flush_liability_samples` reports it.
+    error: Option<csv::Error>,
+}
+
+impl LiabilitySampler {
+    pub(crate) fn new(sampling: LiabilitySampling, target: SampleTarget) -> Self {
+        Self {
+            every_n_tx: sampling.every_n_tx.max(1),
+            max_samples: sampling.max_samples.max(1),
+            target,
+            error: None,
+        }
+    }
+
+    /// Whether a sample is due after the `seq`-th executed transaction.
+    pub(crate) fn is_due(&self, seq: u64) -> bool {
+        seq.is_multiple_of(self.every_n_tx)
+    }
+
+    pub(crate) fn record(&mut self, sample: LiabilitySample) {
+        match &mut self.target {
+            SampleTarget::Memory(samples) => {
+                if samples.len() == self.max_samples {
+                    self.every_n_tx = self.every_n_tx.saturating_mul(2);
+                    let every_n_tx = self.every_n_tx;
+                    samples.retain(|kept| kept.seq.is_multiple_of(every_n_tx));
+                    if !sample.seq.is_multiple_of(every_n_tx) {
+                        return;
+                    }
+                }
+                samples.push(sample);
+            }
+            SampleTarget::Writer(writer) => {
+                if self.error.is_none() {
+                    self.error = writer.serialize(sample).err();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn samples(&self) -> &[LiabilitySample] {
+        match &self.target {
+            SampleTarget::Memory(samples) => samples,
+            SampleTarget::Writer(_) => &[],
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+        match &mut self.target {
+            SampleTarget::Memory(_) => Ok(()),
+            SampleTarget::Writer(writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seq: u64) -> LiabilitySample {
+        LiabilitySample {
+            seq,
+            liabilities: Decimal::ZERO,
+            total_available: Decimal::ZERO,
+            total_held: Decimal::ZERO,
+            accounts: 0,
+        }
+    }
+
+    #[test]
+    fn full_memory_thins_out_samples() {
+        let mut sampler = LiabilitySampler::new(
+            LiabilitySampling {
+                every_n_tx: 10,
+                max_samples: 4,
+            },
+            SampleTarget::Memory(Vec::new()),
+        );
+        for seq in 1..=100 {
+            if sampler.is_due(seq) {
+                sampler.record(sample(seq));
+            }
+        }
+        let seqs = sampler.samples().iter().map(|s| s.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, [40, 80]);
+        assert!(sampler.is_due(120));
+        assert!(!sampler.is_due(100));
+    }
+}
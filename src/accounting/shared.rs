@@ -0,0 +1,356 @@
+//! Ledger shared between threads, with optimistic concurrency for admin tools.
+use crate::accounting::causality::PendingLifecycle;
+use crate::accounting::view::LedgerView;
+use crate::accounting::{AccountView, ExecutableTransaction, Ledger, OpenDispute, TxError};
+use crate::clock::SharedClock;
+use crate::core_types::ClientId;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+#[derive(Debug, PartialEq)]
+pub enum ExecuteIfError {
+    /// The account changed since its version was read, so nothing was applied.
+    VersionConflict {
+        current: u64,
+    },
+    Rejected(TxError),
+}
+
+impl Display for ExecuteIfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecuteIfError::VersionConflict { current } => {
+                write!(f, "account changed, current version is {}", current)
+            }
+            ExecuteIfError::Rejected(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ExecuteIfError {}
+
+/// Accounts of a `SharedLedger` at a single point between two transactions, sorted by
+/// client id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountsSnapshot {
+    tx_seq: u64,
+    accounts: Vec<AccountView>,
+    pending: Vec<PendingLifecycle>,
+}
+
+impl AccountsSnapshot {
+    /// Transactions executed before the snapshot was taken, see `Ledger::tx_seq`.
+    pub fn tx_seq(&self) -> u64 {
+        self.tx_seq
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, AccountView> {
+        self.accounts.iter()
+    }
+
+    /// Resolves and chargebacks deferred at the time of the snapshot, see
+    /// `Ledger::pending_lifecycle`.
+    pub fn pending(&self) -> &[PendingLifecycle] {
+        &self.pending
+    }
+}
+
+impl IntoIterator for AccountsSnapshot {
+    type Item = AccountView;
+    type IntoIter = std::vec::IntoIter<AccountView>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.accounts.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a AccountsSnapshot {
+    type Item = &'a AccountView;
+    type IntoIter = std::slice::Iter<'a, AccountView>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AccountCounts {
+    pub accounts: u64,
+    pub locked: u64,
+}
+
+/// Handle to a ledger shared between threads. Clones refer to the same ledger, which
+/// executes one transaction at a time.
+///
+/// A transaction panicking poisons the ledger rather than the lock, see
+/// `Ledger::execute_catch_unwind`.
+#[derive(Clone)]
+pub struct SharedLedger {
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+impl SharedLedger {
+    pub fn new(ledger: Ledger) -> Self {
+        Self {
+            ledger: Arc::new(Mutex::new(ledger)),
+        }
+    }
+
+    pub fn execute(&self, tx: &impl ExecutableTransaction) -> Result<(), TxError> {
+        self.lock().execute_catch_unwind(tx).map(|_outcome| ())
+    }
+
+    /// Executes `tx` only if the account of `client_id` is still at `expected_version`,
+    /// as read from `AccountView::version`. Clients without an account are at version 0.
+    pub fn execute_if_version(
+        &self,
+        tx: &impl ExecutableTransaction,
+        client_id: ClientId,
+        expected_version: u64,
+    ) -> Result<(), ExecuteIfError> {
+        let mut ledger = self.lock();
+        let current = ledger
+            .account(client_id)
+            .map_or(0, |user_account| user_account.version());
+        if current != expected_version {
+            return Err(ExecuteIfError::VersionConflict { current });
+        }
+        ledger
+            .execute_catch_unwind(tx)
+            .map(|_outcome| ())
+            .map_err(ExecuteIfError::Rejected)
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<AccountView> {
+        self.lock().account(client_id).map(AccountView::from)
+    }
+
+    /// Copies the balances of every account while holding the lock, so that the
+    /// snapshot reflects a single point between two transactions, or two calls of
+    /// `with_ledger`, and never a transaction applied in part. The lock is held only for
+    /// the copy, so writing a report from the snapshot doesn't block execution.
+    pub fn snapshot_accounts(&self) -> AccountsSnapshot {
+        let (tx_seq, mut accounts, pending) = {
+            let ledger = self.lock();
+            let accounts: Vec<_> = ledger
+                .accounts_iter()
+                .map(|(_client_id, user_account)| AccountView::from(user_account))
+                .collect();
+            (ledger.tx_seq(), accounts, ledger.pending_lifecycle())
+        };
+        accounts.sort_unstable_by_key(|view| view.client_id);
+        AccountsSnapshot {
+            tx_seq,
+            accounts,
+            pending,
+        }
+    }
+
+    /// Numbers of accounts and of locked ones, counted without copying the accounts.
+    pub fn account_counts(&self) -> AccountCounts {
+        let ledger = self.lock();
+        let mut counts = AccountCounts::default();
+        for (_client_id, user_account) in ledger.accounts_iter() {
+            counts.accounts += 1;
+            counts.locked += u64::from(user_account.locked());
+        }
+        counts
+    }
+
+    /// Open disputes sorted by client and transaction id, see `Ledger::open_disputes`.
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        let mut disputes: Vec<_> = self.lock().open_disputes().collect();
+        disputes.sort_unstable_by_key(|dispute| (dispute.client_id, dispute.tx_id));
+        disputes
+    }
+
+    /// Clock of the ledger, see `Ledger::clock`.
+    pub fn clock(&self) -> SharedClock {
+        self.lock().clock().clone()
+    }
+
+    /// Runs `f` with exclusive access to the ledger.
+    pub fn with_ledger<T>(&self, f: impl FnOnce(&mut Ledger) -> T) -> T {
+        f(&mut self.lock())
+    }
+
+    /// Runs `f` with read-only access to the ledger, holding the lock until it returns.
+    pub fn with_view<T>(&self, f: impl FnOnce(LedgerView<'_>) -> T) -> T {
+        f(self.lock().view())
+    }
+
+    /// Returns the ledger if this is the last handle to it.
+    pub fn try_into_inner(self) -> Result<Ledger, Self> {
+        Arc::try_unwrap(self.ledger)
+            .map(|ledger| ledger.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .map_err(|ledger| Self { ledger })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ledger> {
+        self.ledger.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{
+        Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal,
+    };
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn execute_if_version_rejects_stale_versions() {
+        let shared = SharedLedger::new(Ledger::new());
+        let deposit = Transaction::Deposit(Deposit::new(1, 1, dec!(10)));
+        assert_eq!(
+            shared.execute_if_version(&deposit, 1, 1),
+            Err(ExecuteIfError::VersionConflict { current: 0 })
+        );
+        shared.execute_if_version(&deposit, 1, 0).unwrap();
+        shared
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+        assert_eq!(shared.account(1).unwrap().version, 2);
+
+        // Rejected transactions don't change the version.
+        let resolve = Transaction::Resolve(Resolve::new(1, 2));
+        assert_eq!(
+            shared.execute_if_version(&resolve, 1, 2),
+            Err(ExecuteIfError::Rejected(TxError::OriginTxNotFound))
+        );
+        shared.with_ledger(|ledger| ledger.unlock(1)).unwrap();
+        assert_eq!(
+            shared.execute_if_version(&resolve, 1, 2),
+            Err(ExecuteIfError::VersionConflict { current: 3 })
+        );
+    }
+
+    #[test]
+    fn racing_resolve_and_chargeback_have_one_winner() {
+        for _ in 0..50 {
+            let shared = SharedLedger::new(Ledger::new());
+            shared
+                .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+                .unwrap();
+            shared
+                .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+                .unwrap();
+            let version = shared.account(1).unwrap().version;
+            let barrier = Arc::new(Barrier::new(2));
+            let racers = [
+                Transaction::Resolve(Resolve::new(1, 1)),
+                Transaction::Chargeback(Chargeback::new(1, 1)),
+            ]
+            .map(|tx| {
+                let shared = shared.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    shared.execute_if_version(&tx, 1, version)
+                })
+            });
+            let results = racers.map(|racer| racer.join().unwrap());
+
+            let winners = results.iter().filter(|result| result.is_ok()).count();
+            assert_eq!(winners, 1, "{:?}", results);
+            let account = shared.account(1).unwrap();
+            assert_eq!(account.version, version + 1);
+            assert!(results.contains(&Err(ExecuteIfError::VersionConflict {
+                current: account.version
+            })));
+            let ledger = shared.try_into_inner().ok().unwrap();
+            assert!(ledger.verify_invariants().is_ok());
+            assert_eq!(ledger.open_disputes().count(), 0);
+        }
+    }
+
+    #[test]
+    fn snapshots_never_see_a_transfer_in_part() {
+        const CLIENTS: u16 = 8;
+        const WRITERS: u32 = 4;
+        const TRANSFERS: u32 = 500;
+        let shared = SharedLedger::new(Ledger::new());
+        for client_id in 0..CLIENTS {
+            shared
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    u32::from(client_id),
+                    dec!(100),
+                )))
+                .unwrap();
+        }
+        let expected_total = dec!(100) * Decimal::from(CLIENTS);
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let shared = shared.clone();
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                let mut snapshots = 0;
+                let mut last_tx_seq = 0;
+                while !done.load(Ordering::Acquire) || snapshots == 0 {
+                    let snapshot = shared.snapshot_accounts();
+                    let total: Decimal = snapshot.iter().map(|view| view.total).sum();
+                    assert_eq!(total, expected_total, "at tx_seq {}", snapshot.tx_seq());
+                    assert_eq!(snapshot.len(), usize::from(CLIENTS));
+                    assert!(snapshot.tx_seq() >= last_tx_seq);
+                    last_tx_seq = snapshot.tx_seq();
+                    snapshots += 1;
+                }
+                snapshots
+            })
+        };
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for transfer in 0..TRANSFERS {
+                        // Tx ids above the initial deposits, two per transfer
+                        let tx_id = 1000 + (writer * TRANSFERS + transfer) * 2;
+                        let from = ((writer + transfer) % u32::from(CLIENTS)) as u16;
+                        let to = (from + 1 + (transfer % 3) as u16) % CLIENTS;
+                        let amount = Decimal::from(1 + transfer % 7);
+                        shared.with_ledger(|ledger| {
+                            let withdrawal = Withdrawal::new(from, tx_id, amount);
+                            if ledger.execute(&Transaction::Withdrawal(withdrawal)).is_ok() {
+                                ledger
+                                    .execute(&Transaction::Deposit(Deposit::new(
+                                        to,
+                                        tx_id + 1,
+                                        amount,
+                                    )))
+                                    .unwrap();
+                            }
+                        });
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::Release);
+        assert!(reader.join().unwrap() > 0);
+
+        let snapshot = shared.snapshot_accounts();
+        let client_ids: Vec<_> = snapshot.iter().map(|view| view.client_id).collect();
+        assert_eq!(client_ids, (0..CLIENTS).collect::<Vec<_>>());
+        assert_eq!(
+            snapshot.iter().map(|view| view.total).sum::<Decimal>(),
+            expected_total
+        );
+    }
+}
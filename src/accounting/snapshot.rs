@@ -0,0 +1,605 @@
+//! Complete state of a ledger as a serde value, saved by `Checkpointer` so that a
+//! ledger can be restored where a run stopped.
+//!
+//! The snapshot holds what later transactions depend on: the accounts, the records
+//! of past transactions and the tombstones of pruned ones, open disputes and withdrawal holds, velocity windows and
+//! the counters of the ledger. Running totals and the withdrawal reference counts
+//! are recomputed on restore, dispute reasons are interned again, and threshold
+//! breaches are rechecked against the thresholds of the restored ledger. Records keep
+//! the time they were executed at, so dispute windows run on across a restore. The
+//! configuration, including the clock, journal, liability samples
+//! and execution digest are not part of the state, and the lifecycle messages of
+//! `CausalityMode` cannot be saved, so a ledger tracking them has no snapshot. The
+//! amount scale is recorded, as a ledger restored under another one would write
+//! reports its input was never checked against.
+use crate::accounting::config::{CausalityMode, LedgerConfig};
+use crate::accounting::reasons::DisputeReason;
+use crate::accounting::transactions::TxTypeTag;
+use crate::accounting::velocity::VelocityWindowState;
+use crate::accounting::{
+    HoldState, Ledger, SubAccount, TxRecord, TxState, UserAccount, WithdrawalHold, WithdrawalRecord,
+};
+use crate::clock::Timestamp;
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::fmt::{Display, Formatter};
+
+/// State of a ledger, with every list sorted by client id and then tx id so that
+/// equal ledgers have equal snapshots.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct LedgerSnapshot {
+    liabilities: Decimal,
+    dust: Decimal,
+    accounts: Vec<AccountSnapshot>,
+    tx_records: Vec<TxRecordSnapshot>,
+    #[serde(default)]
+    tombstones: Vec<(TxId, TxState)>,
+    open_disputes: Vec<(ClientId, Vec<TxId>)>,
+    withdrawal_holds: Vec<HoldSnapshot>,
+    velocity_windows: Vec<VelocityWindowState>,
+    velocity_flagged: u64,
+    dispute_amount_mismatches: u64,
+    #[serde(default)]
+    unknown_dispute_reasons: u64,
+    applied_offset: Option<u64>,
+    poisoned: bool,
+    tx_seq: u64,
+    #[serde(default)]
+    derived_postings: u64,
+    #[serde(default)]
+    amount_scale: Option<u32>,
+}
+
+/// Failure of restoring a ledger from a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// The ledger to restore tracks lifecycle messages under a `CausalityMode`, which
+    /// are not part of a snapshot.
+    CausalityTracked,
+    /// The snapshot was taken under another `LedgerConfig::amount_scale` than the one
+    /// of the ledger to restore.
+    ScaleMismatch {
+        snapshot: Option<u32>,
+        config: Option<u32>,
+    },
+    /// Description of the first inconsistency of the snapshot, e.g. an account listed
+    /// twice or balances which break `Ledger::verify_invariants`.
+    Inconsistent(String),
+}
+
+impl SnapshotError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SnapshotError::CausalityTracked => "causality_tracked",
+            SnapshotError::ScaleMismatch { .. } => "scale_mismatch",
+            SnapshotError::Inconsistent(_) => "inconsistent",
+        }
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let scale = |scale: &Option<u32>| scale.map_or("none".to_string(), |s| s.to_string());
+        match self {
+            SnapshotError::CausalityTracked => {
+                f.write_str("lifecycle messages cannot be restored under a causality mode")
+            }
+            SnapshotError::ScaleMismatch { snapshot, config } => write!(
+                f,
+                "snapshot taken under amount scale {} cannot be restored under amount scale {}",
+                scale(snapshot),
+                scale(config)
+            ),
+            SnapshotError::Inconsistent(description) => f.write_str(description),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct AccountSnapshot {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    overdraft_limit: Decimal,
+    deposited: Decimal,
+    withdrawn: Decimal,
+    charged_back: Decimal,
+    chargebacks: u32,
+    open_disputes: u32,
+    #[serde(default)]
+    disputes_opened: u32,
+    #[serde(default)]
+    disputes_resolved: u32,
+    #[serde(default)]
+    disputes_charged_back: u32,
+    version: u64,
+    created_by_rejection: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct TxRecordSnapshot {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    tx_id: TxId,
+    kind: TxTypeTag,
+    amount: Decimal,
+    state: TxState,
+    #[serde(default)]
+    recorded_at: Timestamp,
+    disputed_at: Option<u64>,
+    resolved: bool,
+    #[serde(default)]
+    reason: Option<DisputeReason>,
+    withdrawal: Option<WithdrawalSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct WithdrawalSnapshot {
+    reference: Option<String>,
+    settled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct HoldSnapshot {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    tx_id: TxId,
+    amount: Decimal,
+    state: HoldState,
+}
+
+impl From<&UserAccount> for AccountSnapshot {
+    fn from(user_account: &UserAccount) -> Self {
+        AccountSnapshot {
+            client_id: user_account.client_id,
+            available: user_account.available.balance,
+            held: user_account.held.balance,
+            locked: user_account.locked,
+            overdraft_limit: user_account.overdraft_limit,
+            deposited: user_account.deposited,
+            withdrawn: user_account.withdrawn,
+            charged_back: user_account.charged_back,
+            chargebacks: user_account.chargebacks,
+            open_disputes: user_account.open_disputes,
+            disputes_opened: user_account.disputes_opened,
+            disputes_resolved: user_account.disputes_resolved,
+            disputes_charged_back: user_account.disputes_charged_back,
+            version: user_account.version,
+            created_by_rejection: user_account.created_by_rejection,
+        }
+    }
+}
+
+impl From<AccountSnapshot> for UserAccount {
+    fn from(account: AccountSnapshot) -> Self {
+        UserAccount {
+            client_id: account.client_id,
+            available: SubAccount {
+                balance: account.available,
+            },
+            held: SubAccount {
+                balance: account.held,
+            },
+            locked: account.locked,
+            overdraft_limit: account.overdraft_limit,
+            deposited: account.deposited,
+            withdrawn: account.withdrawn,
+            charged_back: account.charged_back,
+            chargebacks: account.chargebacks,
+            open_disputes: account.open_disputes,
+            disputes_opened: account.disputes_opened,
+            disputes_resolved: account.disputes_resolved,
+            disputes_charged_back: account.disputes_charged_back,
+            version: account.version,
+            created_by_rejection: account.created_by_rejection,
+        }
+    }
+}
+
+impl From<&TxRecord> for TxRecordSnapshot {
+    fn from(record: &TxRecord) -> Self {
+        TxRecordSnapshot {
+            client_id: record.client_id,
+            tx_id: record.tx_id,
+            kind: record.kind,
+            amount: record.amount,
+            state: record.state,
+            recorded_at: record.recorded_at,
+            disputed_at: record.disputed_at,
+            resolved: record.resolved,
+            reason: record.reason.clone(),
+            withdrawal: record.withdrawal().map(|withdrawal| WithdrawalSnapshot {
+                reference: withdrawal.reference().map(str::to_string),
+                settled: withdrawal.settled,
+            }),
+        }
+    }
+}
+
+impl From<TxRecordSnapshot> for TxRecord {
+    fn from(record: TxRecordSnapshot) -> Self {
+        TxRecord {
+            client_id: record.client_id,
+            tx_id: record.tx_id,
+            kind: record.kind,
+            amount: record.amount,
+            state: record.state,
+            recorded_at: record.recorded_at,
+            disputed_at: record.disputed_at,
+            resolved: record.resolved,
+            reason: record.reason,
+            withdrawal: record.withdrawal.map(|withdrawal| {
+                Box::new(WithdrawalRecord {
+                    reference: withdrawal.reference.map(String::into_boxed_str),
+                    settled: withdrawal.settled,
+                })
+            }),
+        }
+    }
+}
+
+impl Ledger {
+    /// Snapshot of the state of the ledger, `None` while it tracks lifecycle messages
+    /// under a `CausalityMode` other than `Off`.
+    pub(crate) fn snapshot(&self) -> Option<LedgerSnapshot> {
+        if self.causality.is_some() {
+            return None;
+        }
+        let mut accounts: Vec<_> = self.accounts.values().map(AccountSnapshot::from).collect();
+        accounts.sort_unstable_by_key(|account| account.client_id);
+        let mut tx_records: Vec<_> = self
+            .tx_states
+            .values()
+            .map(TxRecordSnapshot::from)
+            .collect();
+        tx_records.sort_unstable_by_key(|record| record.tx_id);
+        let mut tombstones: Vec<_> = self
+            .tombstones
+            .iter()
+            .map(|(tx_id, state)| (*tx_id, *state))
+            .collect();
+        tombstones.sort_unstable_by_key(|(tx_id, _state)| *tx_id);
+        let mut open_disputes: Vec<_> = self
+            .open_disputes
+            .iter()
+            .map(|(client_id, tx_ids)| {
+                let mut tx_ids: Vec<_> = tx_ids.iter().copied().collect();
+                tx_ids.sort_unstable();
+                (*client_id, tx_ids)
+            })
+            .collect();
+        open_disputes.sort_unstable_by_key(|(client_id, _tx_ids)| *client_id);
+        let mut withdrawal_holds: Vec<_> = self
+            .withdrawal_holds
+            .values()
+            .map(|hold| HoldSnapshot {
+                client_id: hold.client_id,
+                tx_id: hold.tx_id,
+                amount: hold.amount,
+                state: hold.state.clone(),
+            })
+            .collect();
+        withdrawal_holds.sort_unstable_by_key(|hold| hold.tx_id);
+        Some(LedgerSnapshot {
+            liabilities: self.liabilities.balance,
+            dust: self.dust.balance,
+            accounts,
+            tx_records,
+            tombstones,
+            open_disputes,
+            withdrawal_holds,
+            velocity_windows: self.velocity_windows(),
+            velocity_flagged: self.velocity_flagged(),
+            dispute_amount_mismatches: self.dispute_amount_mismatches,
+            unknown_dispute_reasons: self.unknown_dispute_reasons,
+            applied_offset: self.applied_offset,
+            poisoned: self.poisoned,
+            tx_seq: self.tx_seq,
+            derived_postings: self.derived_postings,
+            amount_scale: self.config.amount_scale,
+        })
+    }
+
+    /// Ledger configured with `config` holding the state of `snapshot`, which must have
+    /// been taken under the amount scale of `config`.
+    pub(crate) fn restore(
+        config: LedgerConfig,
+        snapshot: LedgerSnapshot,
+    ) -> Result<Self, SnapshotError> {
+        if config.causality_mode != CausalityMode::Off {
+            return Err(SnapshotError::CausalityTracked);
+        }
+        if snapshot.amount_scale != config.amount_scale {
+            return Err(SnapshotError::ScaleMismatch {
+                snapshot: snapshot.amount_scale,
+                config: config.amount_scale,
+            });
+        }
+        let mut ledger = Ledger::with_config(config);
+        ledger.liabilities.balance = snapshot.liabilities;
+        ledger.dust.balance = snapshot.dust;
+        for account in snapshot.accounts {
+            let client_id = account.client_id;
+            if ledger.accounts.contains_key(&client_id) {
+                return Err(SnapshotError::Inconsistent(format!(
+                    "account of client {} listed twice",
+                    client_id
+                )));
+            }
+            ledger.total_available += account.available;
+            ledger.total_held += account.held;
+            ledger
+                .accounts
+                .get_or_insert_with(client_id, || UserAccount::from(account));
+            ledger.rewatch_balance(client_id);
+        }
+        for record in snapshot.tx_records {
+            let mut record = TxRecord::from(record);
+            record.reason = record.reason.map(|reason| ledger.reasons.intern(&reason));
+            if let Some(len) = record.reference_len() {
+                ledger.withdrawal_references += 1;
+                ledger.withdrawal_reference_bytes += len;
+            }
+            match ledger.tx_states.entry(record.tx_id) {
+                Entry::Occupied(_) => {
+                    return Err(SnapshotError::Inconsistent(format!(
+                        "record of tx {} listed twice",
+                        record.tx_id
+                    )))
+                }
+                Entry::Vacant(entry) => entry.insert(record),
+            };
+        }
+        ledger.tombstones.extend(snapshot.tombstones);
+        for (client_id, tx_ids) in snapshot.open_disputes {
+            ledger
+                .open_disputes
+                .insert(client_id, tx_ids.into_iter().collect());
+        }
+        for hold in snapshot.withdrawal_holds {
+            ledger.withdrawal_holds.insert(
+                hold.tx_id,
+                WithdrawalHold {
+                    client_id: hold.client_id,
+                    tx_id: hold.tx_id,
+                    amount: hold.amount,
+                    state: hold.state,
+                },
+            );
+        }
+        ledger.restore_velocity_windows(snapshot.velocity_windows);
+        ledger.velocity.restore_flagged(snapshot.velocity_flagged);
+        ledger.dispute_amount_mismatches = snapshot.dispute_amount_mismatches;
+        ledger.unknown_dispute_reasons = snapshot.unknown_dispute_reasons;
+        ledger.applied_offset = snapshot.applied_offset;
+        ledger.poisoned = snapshot.poisoned;
+        ledger.tx_seq = snapshot.tx_seq;
+        ledger.derived_postings = snapshot.derived_postings;
+        ledger
+            .verify_invariants()
+            .map_err(|violation| SnapshotError::Inconsistent(violation.to_string()))?;
+        Ok(ledger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{
+        Chargeback, Deposit, Dispute, Resolve, Transaction, WithdrawAuth, Withdrawal,
+    };
+    use crate::accounting::{AccountView, TxError};
+    use crate::clock::ManualClock;
+    use crate::test_utils::render;
+    use rust_decimal_macros::dec;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn restored_ledger_continues_like_the_original() {
+        let head = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(20.5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(2))),
+            Transaction::Dispute(Dispute::new(2, 2).with_reason("10.4".parse().unwrap())),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 4, dec!(3))),
+        ];
+        let tail = [
+            Transaction::Chargeback(Chargeback::new(2, 2).with_reason("13.1".parse().unwrap())),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(1))),
+        ];
+        // Both read one clock, so that the records of the tail are stamped alike
+        let mut ledger = Ledger::builder()
+            .clock(Arc::new(ManualClock::default()))
+            .build();
+        for tx in &head {
+            let _ = ledger.execute(tx);
+        }
+        let snapshot = ledger.snapshot().unwrap();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let mut restored = Ledger::restore(
+            ledger.config().clone(),
+            serde_json::from_str(&json).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored.snapshot(), Some(snapshot));
+        assert_eq!(restored.memory_stats().interned_reasons, 1);
+        for tx in &tail {
+            assert_eq!(restored.execute(tx), ledger.execute(tx));
+        }
+        assert_eq!(render(&restored), render(&ledger));
+        assert_eq!(restored.snapshot(), ledger.snapshot());
+    }
+
+    #[test]
+    fn dispute_outcome_counters_survive_a_restore() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let json = serde_json::to_string(&ledger.snapshot().unwrap()).unwrap();
+        let restored = Ledger::restore(
+            LedgerConfig::default(),
+            serde_json::from_str(&json).unwrap(),
+        )
+        .unwrap();
+        let view = AccountView::from(restored.account(1).unwrap());
+        assert_eq!(
+            (
+                view.disputes_opened,
+                view.disputes_resolved,
+                view.disputes_charged_back
+            ),
+            (2, 1, 1)
+        );
+        assert_eq!(view, AccountView::from(ledger.account(1).unwrap()));
+
+        // Snapshots taken before the counters existed restore them as 0
+        let old = json
+            .replace("\"disputes_opened\":2,", "")
+            .replace("\"disputes_resolved\":1,", "")
+            .replace("\"disputes_charged_back\":1,", "");
+        assert_ne!(old, json);
+        let restored =
+            Ledger::restore(LedgerConfig::default(), serde_json::from_str(&old).unwrap()).unwrap();
+        assert_eq!(restored.account(1).unwrap().disputes_opened(), 0);
+    }
+
+    #[test]
+    fn tombstones_survive_pruning_and_a_restore() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        assert_eq!(ledger.prune_settled_tx_states(|_deposit| true), 1);
+        let json = serde_json::to_string(&ledger.snapshot().unwrap()).unwrap();
+        let mut restored = Ledger::restore(
+            LedgerConfig::default(),
+            serde_json::from_str(&json).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored.snapshot(), ledger.snapshot());
+        assert_eq!(restored.memory_stats().tombstones, 1);
+        assert_eq!(
+            restored.execute(&Transaction::Deposit(Deposit::new(2, 1, dec!(10)))),
+            Err(TxError::TxIdReuseAfterSettlement {
+                tx_id: 1,
+                prior_state: TxState::ChargedBack,
+            })
+        );
+
+        // Snapshots taken before tombstones existed restore without any
+        let old = json.replace(",\"tombstones\":[[1,\"charged_back\"]]", "");
+        assert_ne!(old, json);
+        let restored =
+            Ledger::restore(LedgerConfig::default(), serde_json::from_str(&old).unwrap()).unwrap();
+        assert_eq!(restored.memory_stats().tombstones, 0);
+    }
+
+    #[test]
+    fn dispute_windows_run_on_after_a_restore() {
+        let clock = Arc::new(ManualClock::default());
+        let mut ledger = Ledger::builder()
+            .clock(Arc::clone(&clock))
+            .dispute_window(Duration::from_secs(60))
+            .build();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+            .unwrap();
+        clock.advance(Duration::from_secs(45));
+        let restored = |ledger: &Ledger| {
+            Ledger::restore(ledger.config().clone(), ledger.snapshot().unwrap()).unwrap()
+        };
+        let mut within = restored(&ledger);
+        assert_eq!(
+            within.tx_records_iter().next().unwrap().recorded_at(),
+            Timestamp::UNIX_EPOCH
+        );
+        within
+            .execute(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+
+        clock.advance(Duration::from_secs(16));
+        assert_eq!(
+            restored(&ledger).execute(&Transaction::Dispute(Dispute::new(1, 1))),
+            Err(TxError::DisputeWindowExpired)
+        );
+    }
+
+    #[test]
+    fn inconsistent_snapshots_are_refused() {
+        let mut ledger = Ledger::new();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10))))
+            .unwrap();
+        let mut snapshot = ledger.snapshot().unwrap();
+        snapshot.accounts.push(snapshot.accounts[0].clone());
+        assert_eq!(
+            Ledger::restore(LedgerConfig::default(), snapshot).err(),
+            Some(SnapshotError::Inconsistent(
+                "account of client 1 listed twice".to_string()
+            ))
+        );
+        let mut snapshot = ledger.snapshot().unwrap();
+        snapshot.liabilities = dec!(5);
+        assert!(Ledger::restore(LedgerConfig::default(), snapshot).is_err());
+
+        let causal = Ledger::builder()
+            .causality_mode(CausalityMode::Classify)
+            .build();
+        assert_eq!(causal.snapshot(), None);
+    }
+
+    #[test]
+    fn snapshots_are_restored_under_their_amount_scale_only() {
+        let mut ledger = Ledger::builder().amount_scale(2).build();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(10.25))))
+            .unwrap();
+        let snapshot = ledger.snapshot().unwrap();
+        let restored = Ledger::restore(ledger.config().clone(), snapshot.clone()).unwrap();
+        assert_eq!(restored.state_digest(), ledger.state_digest());
+
+        let err = Ledger::restore(
+            Ledger::builder().amount_scale(4).build().config().clone(),
+            snapshot.clone(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err,
+            SnapshotError::ScaleMismatch {
+                snapshot: Some(2),
+                config: Some(4),
+            }
+        );
+        assert_eq!(err.code(), "scale_mismatch");
+        assert_eq!(
+            Ledger::restore(LedgerConfig::default(), snapshot)
+                .err()
+                .unwrap()
+                .to_string(),
+            "snapshot taken under amount scale 2 cannot be restored under amount scale none"
+        );
+    }
+}
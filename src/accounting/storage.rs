@@ -0,0 +1,266 @@
+//! Account storage of the ledger, optionally split into a hot and a cold tier.
+use crate::accounting::config::AccountTiering;
+use crate::accounting::UserAccount;
+use crate::core_types::ClientId;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::ops::Index;
+
+/// Memory taken by the account tiers and the transaction records, estimated from the
+/// capacity of their collections, by the references of recorded withdrawals, by the interned dispute
+/// reasons and by the tombstones of settled deposits, see `Ledger::memory_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemoryStats {
+    pub hot_accounts: usize,
+    /// Bytes of the hot tier, including free slots of its hash tables.
+    pub hot_bytes: usize,
+    pub cold_accounts: usize,
+    pub cold_bytes: usize,
+    /// Records of deposits and withdrawals, and the bytes of their map, see
+    /// `DisputeTracking`.
+    pub tx_records: usize,
+    pub tx_record_bytes: usize,
+    /// Recorded withdrawals with a reference, and the bytes of the references.
+    pub withdrawal_references: usize,
+    pub withdrawal_reference_bytes: usize,
+    /// Distinct dispute reasons interned, each shared by the deposits disputed for it.
+    pub interned_reasons: usize,
+    /// Tx ids of pruned or removed settled deposits which later deposits cannot reuse,
+    /// and the bytes of their set, including free slots.
+    pub tombstones: usize,
+    pub tombstone_bytes: usize,
+}
+
+/// Accounts by client id. Recently touched accounts are kept in a hash map and the
+/// others in a vector sorted by client id, which takes less memory per account.
+///
+/// Shared access reads either tier, while mutable access promotes a cold account to
+/// the hot tier first. With `AccountTiering` configured, accounts which were not
+/// touched during a generation of `demote_after` transactions are demoted at its end,
+/// so an idle account is demoted after between one and two generations.
+pub(crate) struct AccountStore {
+    hot: HashMap<ClientId, UserAccount>,
+    /// Sorted by client id.
+    cold: Vec<UserAccount>,
+    tiering: Option<AccountTiering>,
+    /// Accounts touched during the current generation.
+    touched: HashSet<ClientId>,
+    /// Transactions executed during the current generation.
+    ticks: u64,
+}
+
+impl AccountStore {
+    pub(crate) fn new(tiering: Option<AccountTiering>) -> Self {
+        Self {
+            hot: HashMap::with_capacity(tiering.map_or(0, |tiering| tiering.hot_capacity)),
+            cold: Vec::new(),
+            tiering,
+            touched: HashSet::new(),
+            ticks: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.hot.len() + self.cold.len()
+    }
+
+    /// Reserves room in the hot tier for `additional` new accounts.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.hot.reserve(additional);
+    }
+
+    pub(crate) fn contains_key(&self, client_id: &ClientId) -> bool {
+        self.get(client_id).is_some()
+    }
+
+    pub(crate) fn get(&self, client_id: &ClientId) -> Option<&UserAccount> {
+        self.hot.get(client_id).or_else(|| {
+            let position = self.cold_position(*client_id).ok()?;
+            Some(&self.cold[position])
+        })
+    }
+
+    pub(crate) fn get_mut(&mut self, client_id: &ClientId) -> Option<&mut UserAccount> {
+        if !self.hot.contains_key(client_id) {
+            let position = self.cold_position(*client_id).ok()?;
+            self.hot.insert(*client_id, self.cold.remove(position));
+        }
+        self.touch(*client_id);
+        self.hot.get_mut(client_id)
+    }
+
+    /// Returns the account of `client_id`, inserting the one returned by `default`
+    /// when the client has none.
+    pub(crate) fn get_or_insert_with(
+        &mut self,
+        client_id: ClientId,
+        default: impl FnOnce() -> UserAccount,
+    ) -> &mut UserAccount {
+        self.touch(client_id);
+        let cold = &mut self.cold;
+        self.hot.entry(client_id).or_insert_with(|| {
+            match cold.binary_search_by_key(&client_id, |user_account| user_account.client_id) {
+                Ok(position) => cold.remove(position),
+                Err(_) => default(),
+            }
+        })
+    }
+
+    pub(crate) fn remove(&mut self, client_id: &ClientId) -> Option<UserAccount> {
+        self.touched.remove(client_id);
+        self.hot.remove(client_id).or_else(|| {
+            let position = self.cold_position(*client_id).ok()?;
+            Some(self.cold.remove(position))
+        })
+    }
+
+    /// Accounts of the hot tier in no particular order, followed by the cold tier.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&ClientId, &UserAccount)> + Clone + '_ {
+        self.hot.iter().chain(
+            self.cold
+                .iter()
+                .map(|user_account| (&user_account.client_id, user_account)),
+        )
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &ClientId> + Clone + '_ {
+        self.iter().map(|(client_id, _user_account)| client_id)
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &UserAccount> + Clone + '_ {
+        self.iter().map(|(_client_id, user_account)| user_account)
+    }
+
+    /// Mutable access to every account, without promoting cold ones.
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut UserAccount> + '_ {
+        self.hot.values_mut().chain(self.cold.iter_mut())
+    }
+
+    /// Counts an executed transaction, ending the generation after `demote_after`.
+    pub(crate) fn tick(&mut self) {
+        let Some(tiering) = self.tiering else {
+            return;
+        };
+        self.ticks += 1;
+        if self.ticks >= tiering.demote_after {
+            let touched = std::mem::take(&mut self.touched);
+            self.demote(|client_id| !touched.contains(client_id));
+        }
+    }
+
+    /// Demotes every hot account.
+    pub(crate) fn compact(&mut self) {
+        self.touched = HashSet::new();
+        self.demote(|_client_id| true);
+    }
+
+    pub(crate) fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            hot_accounts: self.hot.len(),
+            hot_bytes: self.hot.capacity() * (size_of::<(ClientId, UserAccount)>() + 1)
+                + self.touched.capacity() * (size_of::<ClientId>() + 1),
+            cold_accounts: self.cold.len(),
+            cold_bytes: self.cold.capacity() * size_of::<UserAccount>(),
+            ..MemoryStats::default()
+        }
+    }
+
+    fn touch(&mut self, client_id: ClientId) {
+        if self.tiering.is_some() {
+            self.touched.insert(client_id);
+        }
+    }
+
+    fn cold_position(&self, client_id: ClientId) -> Result<usize, usize> {
+        self.cold
+            .binary_search_by_key(&client_id, |user_account| user_account.client_id)
+    }
+
+    fn demote(&mut self, mut demote: impl FnMut(&ClientId) -> bool) {
+        self.ticks = 0;
+        let mut demoted = self
+            .hot
+            .extract_if(|client_id, _user_account| demote(client_id))
+            .map(|(_client_id, user_account)| user_account)
+            .collect::<Vec<_>>();
+        self.hot
+            .shrink_to(self.tiering.map_or(0, |tiering| tiering.hot_capacity));
+        if demoted.is_empty() {
+            return;
+        }
+        demoted.sort_unstable_by_key(|user_account| user_account.client_id);
+        self.cold.reserve_exact(demoted.len());
+        self.cold.extend(demoted);
+        // The stable sort merges the two sorted runs in linear time.
+        self.cold.sort_by_key(|user_account| user_account.client_id);
+    }
+}
+
+impl Index<&ClientId> for AccountStore {
+    type Output = UserAccount;
+
+    fn index(&self, client_id: &ClientId) -> &UserAccount {
+        self.get(client_id).expect("Account should exist")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiering(demote_after: u64) -> Option<AccountTiering> {
+        Some(AccountTiering {
+            demote_after,
+            hot_capacity: 0,
+        })
+    }
+
+    fn client_ids(store: &AccountStore) -> (Vec<ClientId>, Vec<ClientId>) {
+        let mut hot = store.hot.keys().copied().collect::<Vec<_>>();
+        hot.sort_unstable();
+        let cold = store.cold.iter().map(|account| account.client_id).collect();
+        (hot, cold)
+    }
+
+    #[test]
+    fn idle_accounts_are_demoted_and_promoted_on_access() {
+        let mut store = AccountStore::new(tiering(2));
+        for client_id in [5, 1, 3] {
+            store.get_or_insert_with(client_id, || UserAccount::new(client_id));
+        }
+        store.tick();
+        store.get_mut(&3).unwrap();
+        store.tick();
+        // All accounts were touched during the first generation.
+        assert_eq!(client_ids(&store), (vec![1, 3, 5], vec![]));
+        store.get_mut(&3).unwrap();
+        store.tick();
+        store.tick();
+        assert_eq!(client_ids(&store), (vec![3], vec![1, 5]));
+
+        assert_eq!(store.get(&5).unwrap().client_id, 5);
+        assert_eq!(client_ids(&store), (vec![3], vec![1, 5]));
+        store.get_mut(&5).unwrap().locked = true;
+        assert_eq!(client_ids(&store), (vec![3, 5], vec![1]));
+        assert!(store.get_mut(&2).is_none());
+
+        store.compact();
+        assert_eq!(client_ids(&store), (vec![], vec![1, 3, 5]));
+        assert!(store.get(&5).unwrap().locked);
+        assert_eq!(store.remove(&3).unwrap().client_id, 3);
+        assert!(!store.contains_key(&3));
+        let mut all = store.keys().copied().collect::<Vec<_>>();
+        all.sort_unstable();
+        assert_eq!(all, vec![1, 5]);
+        assert_eq!(
+            store.memory_stats(),
+            MemoryStats {
+                hot_accounts: 0,
+                hot_bytes: 0,
+                cold_accounts: 2,
+                cold_bytes: store.cold.capacity() * size_of::<UserAccount>(),
+                ..MemoryStats::default()
+            }
+        );
+    }
+}
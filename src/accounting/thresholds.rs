@@ -0,0 +1,176 @@
+//! Available balance floors of the accounts, reported once per breach as
+//! `LedgerEvent::ThresholdBreached` and listed by `Ledger::accounts_below`.
+use crate::accounting::events::LedgerEvent;
+use crate::accounting::{AccountView, Ledger};
+use crate::core_types::ClientId;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// Thresholds each account is below, as the number of them counted from the highest,
+/// since an account below a threshold is below every higher one too. Accounts above
+/// all thresholds have no entry.
+#[derive(Default)]
+pub(crate) struct BalanceWatch {
+    breached: HashMap<ClientId, usize>,
+}
+
+impl BalanceWatch {
+    /// Records the thresholds `available` is below and returns the ones it wasn't
+    /// below before, highest first. `thresholds` are sorted in descending order.
+    pub(crate) fn update<'a>(
+        &mut self,
+        client_id: ClientId,
+        available: Decimal,
+        thresholds: &'a [Decimal],
+    ) -> &'a [Decimal] {
+        let breached = breached(available, thresholds);
+        let before = match breached {
+            0 => self.breached.remove(&client_id),
+            _ => self.breached.insert(client_id, breached),
+        }
+        .unwrap_or(0);
+        thresholds.get(before..breached).unwrap_or_default()
+    }
+
+    pub(crate) fn remove(&mut self, client_id: ClientId) {
+        self.breached.remove(&client_id);
+    }
+}
+
+/// Number of `thresholds`, sorted in descending order, which `available` is below.
+fn breached(available: Decimal, thresholds: &[Decimal]) -> usize {
+    thresholds
+        .iter()
+        .take_while(|threshold| available < **threshold)
+        .count()
+}
+
+impl Ledger {
+    /// Accounts whose available balance is below `threshold`, in no particular order.
+    pub fn accounts_below(&self, threshold: Decimal) -> impl Iterator<Item = AccountView> + '_ {
+        self.accounts_iter()
+            .filter(move |(_client_id, user_account)| user_account.available() < threshold)
+            .map(|(_client_id, user_account)| AccountView::from(user_account))
+    }
+
+    /// Reports the configured thresholds the available balance of `client_id` fell
+    /// below since the last check. Thresholds it recovered to are reported again on
+    /// the next breach only.
+    pub(crate) fn watch_balance(&mut self, client_id: ClientId) {
+        if self.config.balance_thresholds.is_empty() {
+            return;
+        }
+        let Some(available) = self.account(client_id).map(|account| account.available()) else {
+            return;
+        };
+        let thresholds = &self.config.balance_thresholds;
+        for threshold in self.balance_watch.update(client_id, available, thresholds) {
+            self.events.emit(LedgerEvent::ThresholdBreached {
+                client_id,
+                threshold: *threshold,
+                available,
+            });
+        }
+    }
+
+    /// Like `watch_balance`, without reporting the breaches, for changes which are not
+    /// transactions such as merges.
+    pub(crate) fn rewatch_balance(&mut self, client_id: ClientId) {
+        if let Some(available) = self.account(client_id).map(|account| account.available()) {
+            let thresholds = &self.config.balance_thresholds;
+            self.balance_watch.update(client_id, available, thresholds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accounting::events::LedgerEvent;
+    use crate::accounting::transactions::{Deposit, Dispute, Resolve, Transaction, Withdrawal};
+    use crate::accounting::Ledger;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::sync::{Arc, Mutex};
+
+    fn watched_ledger(thresholds: &[Decimal]) -> (Ledger, Arc<Mutex<Vec<LedgerEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let received = events.clone();
+        let ledger = Ledger::builder()
+            .overdraft_limit(dec!(-100))
+            .balance_thresholds(thresholds.iter().copied())
+            .event_listener(move |event| {
+                received.lock().unwrap().push(event.clone());
+                Ok(())
+            })
+            .build();
+        (ledger, events)
+    }
+
+    fn breach(threshold: Decimal, available: Decimal) -> LedgerEvent {
+        LedgerEvent::ThresholdBreached {
+            client_id: 1,
+            threshold,
+            available,
+        }
+    }
+
+    #[test]
+    fn breaches_are_reported_once_until_recovered() {
+        let (mut ledger, events) = watched_ledger(&[dec!(0)]);
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(8))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(1))),
+            Transaction::Deposit(Deposit::new(1, 4, dec!(2))),
+            Transaction::Deposit(Deposit::new(1, 5, dec!(2))),
+            Transaction::Withdrawal(Withdrawal::new(1, 6, dec!(2.5))),
+            Transaction::Deposit(Deposit::new(1, 7, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 7)),
+            Transaction::Resolve(Resolve::new(1, 7)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let events = events.lock().unwrap();
+        let breaches: Vec<_> = events
+            .iter()
+            .filter(|event| matches!(event, LedgerEvent::ThresholdBreached { .. }))
+            .cloned()
+            .collect();
+        assert_eq!(
+            breaches,
+            [
+                breach(dec!(0), dec!(-3)),
+                breach(dec!(0), dec!(-2.5)),
+                breach(dec!(0), dec!(-2.5))
+            ]
+        );
+    }
+
+    #[test]
+    fn crossing_several_thresholds_reports_each() {
+        let (mut ledger, events) = watched_ledger(&[dec!(0), dec!(10), dec!(-50)]);
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(20))))
+            .unwrap();
+        ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(25))))
+            .unwrap();
+        ledger
+            .execute(&Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(50))))
+            .unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                breach(dec!(10), dec!(-5)),
+                breach(dec!(0), dec!(-5)),
+                breach(dec!(-50), dec!(-55))
+            ]
+        );
+        let below: Vec<_> = ledger
+            .accounts_below(dec!(-50))
+            .map(|view| view.client_id)
+            .collect();
+        assert_eq!(below, [1]);
+        assert_eq!(ledger.accounts_below(dec!(-55)).count(), 0);
+    }
+}
@@ -1,26 +1,227 @@
-use crate::accounting::executable_tx::{ExecutableTransaction, TxError};
-use crate::accounting::{make_tx, DepositState, Ledger, TxState, UserAccount};
+use crate::accounting::config::{
+    ChargebackPolicy, DisputeAmountHandling, DisputeTracking, LockPolicy, ReasonAllowlist,
+    UnknownReasonAction, VelocityAction,
+};
+use crate::accounting::events::LedgerEvent;
+use crate::accounting::executable_tx::{ExecutableTransaction, TxError, UnknownErrorCode};
+use crate::accounting::reasons::DisputeReason;
+use crate::accounting::{
+    close_dispute, exact_add, index_open_dispute, open_dispute, transfer, unindex_open_dispute,
+    HoldState, Ledger, TxRecord, TxState, UserAccount, WithdrawalHold,
+};
+use crate::amount_scale::AmountScale;
 use crate::core_types::{ClientId, TxId};
+use crate::number_locale::parse_amount;
 use enum_dispatch::enum_dispatch;
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
-const DEPOSIT_TAG: &str = "deposit";
-const WITHDRAWAL_TAG: &str = "withdrawal";
-const DISPUTE_TAG: &str = "dispute";
-const RESOLVE_TAG: &str = "resolve";
-const CHARGEBACK_TAG: &str = "chargeback";
+/// Longest reference of a withdrawal in bytes, which bounds the memory every recorded
+/// withdrawal may take.
+pub const MAX_REFERENCE_LEN: usize = 64;
 
-#[derive(Deserialize, Debug, PartialEq)]
+/// Value of the `type` column. Unknown values fail deserialization of the whole record.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TxTypeTag {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Adjustment,
+    #[serde(rename = "withdraw_auth")]
+    WithdrawAuth,
+    #[serde(rename = "withdraw_capture")]
+    WithdrawCapture,
+    #[serde(rename = "withdraw_release")]
+    WithdrawRelease,
+    Settle,
+}
+
+impl TxTypeTag {
+    pub const ALL: [TxTypeTag; 10] = [
+        TxTypeTag::Deposit,
+        TxTypeTag::Withdrawal,
+        TxTypeTag::Dispute,
+        TxTypeTag::Resolve,
+        TxTypeTag::Chargeback,
+        TxTypeTag::Adjustment,
+        TxTypeTag::WithdrawAuth,
+        TxTypeTag::WithdrawCapture,
+        TxTypeTag::WithdrawRelease,
+        TxTypeTag::Settle,
+    ];
+
+    /// Value of the `type` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxTypeTag::Deposit => "deposit",
+            TxTypeTag::Withdrawal => "withdrawal",
+            TxTypeTag::Dispute => "dispute",
+            TxTypeTag::Resolve => "resolve",
+            TxTypeTag::Chargeback => "chargeback",
+            TxTypeTag::Adjustment => "adjustment",
+            TxTypeTag::WithdrawAuth => "withdraw_auth",
+            TxTypeTag::WithdrawCapture => "withdraw_capture",
+            TxTypeTag::WithdrawRelease => "withdraw_release",
+            TxTypeTag::Settle => "settle",
+        }
+    }
+}
+
+impl Display for TxTypeTag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct TransactionLog {
     #[serde(rename = "type")]
-    tx_type: String,
+    tx_type: TxTypeTag,
     #[serde(rename = "client")]
     client_id: ClientId,
     #[serde(rename = "tx")]
     tx_id: TxId,
-    #[serde(default, deserialize_with = "csv::invalid_option")]
-    amount: Option<Decimal>,
+    /// Amount read with `parse_amount`. Amounts which are not numbers are read as
+    /// none, as if the column were empty, while trailing characters are kept to fail
+    /// the row.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_amount",
+        serialize_with = "serialize_amount"
+    )]
+    amount: Option<Result<Decimal, TransactionLogError>>,
+    /// Optional `reference` column of withdrawals, read but never written.
+    #[serde(default, skip_serializing)]
+    reference: Option<String>,
+    /// Optional `reason` column of disputes and chargebacks, read but never written.
+    #[serde(default, skip_serializing)]
+    reason: Option<String>,
+}
+
+impl TransactionLog {
+    pub(crate) fn new(
+        tx_type: TxTypeTag,
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Self {
+            tx_type,
+            client_id,
+            tx_id,
+            amount: amount.map(Ok),
+            reference: None,
+            reason: None,
+        }
+    }
+
+    pub fn tx_type(&self) -> TxTypeTag {
+        self.tx_type
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    pub fn amount(&self) -> Option<Decimal> {
+        self.read_amount().ok().flatten()
+    }
+
+    /// The amount, failing with `TrailingCharactersInAmount` when it was read with
+    /// trailing characters.
+    pub(crate) fn read_amount(&self) -> Result<Option<Decimal>, TransactionLogError> {
+        match &self.amount {
+            Some(Err(error)) => Err(error.clone()),
+            Some(Ok(amount)) => Ok(Some(*amount)),
+            None => Ok(None),
+        }
+    }
+
+    /// The amount of a deposit or withdrawal, which must be present and not negative.
+    pub(crate) fn required_amount(&self) -> Result<Decimal, TransactionLogError> {
+        self.read_amount().and_then(required_amount)
+    }
+
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+}
+
+/// The reason of a dispute or chargeback row, which may be empty.
+fn optional_reason(reason: Option<&str>) -> Result<Option<DisputeReason>, TransactionLogError> {
+    match reason {
+        None | Some("") => Ok(None),
+        Some(reason) => reason.parse().map(Some),
+    }
+}
+
+fn required_amount(amount: Option<Decimal>) -> Result<Decimal, TransactionLogError> {
+    match amount {
+        None => Err(TransactionLogError::MissingAmount),
+        Some(amount) if amount < Decimal::ZERO => {
+            Err(TransactionLogError::NegativeAmountNotAllowed)
+        }
+        Some(amount) => Ok(amount),
+    }
+}
+
+fn deserialize_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Result<Decimal, TransactionLogError>>, D::Error> {
+    struct AmountVisitor;
+
+    impl<'de> Visitor<'de> for AmountVisitor {
+        type Value = Option<Result<Decimal, TransactionLogError>>;
+
+        fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.write_str("an amount")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, amount: &str) -> Result<Self::Value, E> {
+            match parse_amount(amount) {
+                Err(TransactionLogError::MissingAmount) => Ok(None),
+                parsed => Ok(Some(parsed)),
+            }
+        }
+
+        fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<Self::Value, D::Error> {
+            deserializer.deserialize_str(self)
+        }
+    }
+
+    deserializer.deserialize_option(AmountVisitor)
+}
+
+fn serialize_amount<S: Serializer>(
+    amount: &Option<Result<Decimal, TransactionLogError>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match amount {
+        Some(Ok(amount)) => serializer.serialize_some(amount),
+        _ => serializer.serialize_none(),
+    }
 }
 
 #[enum_dispatch(ExecutableTransaction)]
@@ -31,6 +232,93 @@ pub enum Transaction {
     Dispute,
     Resolve,
     Chargeback,
+    Adjustment,
+    WithdrawAuth,
+    WithdrawCapture,
+    WithdrawRelease,
+    Settle,
+}
+
+/// Accessors common to all transactions, usable without importing
+/// `ExecutableTransaction`.
+impl Transaction {
+    pub fn client_id(&self) -> ClientId {
+        ExecutableTransaction::client_id(self)
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        ExecutableTransaction::tx_id(self)
+    }
+
+    /// Type of the transaction, for routing transactions without matching every
+    /// variant.
+    ///
+    /// ```
+    /// use payments_engine::prelude::*;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let txs = [
+    ///     Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+    ///     Transaction::Dispute(Dispute::new(1, 1)),
+    ///     Transaction::Withdrawal(Withdrawal::new(2, 2, dec!(5))),
+    /// ];
+    /// let disputes: Vec<TxId> = txs
+    ///     .iter()
+    ///     .filter(|tx| tx.kind() == TxTypeTag::Dispute)
+    ///     .map(Transaction::tx_id)
+    ///     .collect();
+    /// assert_eq!(disputes, [1]);
+    /// assert_eq!(txs[2].kind().to_string(), "withdrawal");
+    /// ```
+    pub fn kind(&self) -> TxTypeTag {
+        ExecutableTransaction::kind(self)
+    }
+
+    /// Amount of deposits, withdrawals and withdrawal authorizations, which are the
+    /// only transactions carrying one.
+    pub fn amount(&self) -> Option<Decimal> {
+        ExecutableTransaction::amount(self)
+    }
+
+    /// Amount of the deposit stated by a dispute, resolve or chargeback, see
+    /// `DisputeAmountHandling`.
+    pub fn stated_amount(&self) -> Option<Decimal> {
+        ExecutableTransaction::stated_amount(self)
+    }
+
+    /// Payout reference of a withdrawal, see `Withdrawal::with_reference`.
+    pub fn reference(&self) -> Option<&str> {
+        match self {
+            Transaction::Withdrawal(withdrawal) => withdrawal.reference.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Reason of a dispute or chargeback, see `Dispute::with_reason`.
+    pub fn reason(&self) -> Option<&DisputeReason> {
+        ExecutableTransaction::reason(self)
+    }
+
+    /// The transaction with its amount, or the stated amount of a dispute, resolve or
+    /// chargeback, checked against `scale` by `AmountScale::check`.
+    pub fn within_scale(mut self, scale: AmountScale) -> Result<Self, TransactionLogError> {
+        let amount = match &mut self {
+            Transaction::Deposit(deposit) => Some(&mut deposit.amount),
+            Transaction::Withdrawal(withdrawal) => Some(&mut withdrawal.amount),
+            Transaction::WithdrawAuth(auth) => Some(&mut auth.amount),
+            Transaction::Dispute(dispute) => dispute.amount.as_mut(),
+            Transaction::Resolve(resolve) => resolve.amount.as_mut(),
+            Transaction::Chargeback(chargeback) => chargeback.amount.as_mut(),
+            Transaction::Adjustment(_)
+            | Transaction::WithdrawCapture(_)
+            | Transaction::WithdrawRelease(_)
+            | Transaction::Settle(_) => None,
+        };
+        if let Some(amount) = amount {
+            *amount = scale.check(*amount)?;
+        }
+        Ok(self)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -51,214 +339,1083 @@ impl Deposit {
 }
 
 impl ExecutableTransaction for Deposit {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::Deposit
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
     fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
-        let client_account = ledger
-            .accounts
-            .entry(self.client_id)
-            .or_insert(UserAccount::new(self.client_id));
-        make_tx(
-            &mut ledger.liabilities,
-            &mut client_account.available,
-            self.amount,
-        );
-        ledger.deposit_states.insert(
+        // Checked before the account is created, so that a rejected deposit does not
+        // leave an empty account behind.
+        if let Some(prior_state) = ledger.settled_state(self.tx_id) {
+            return Err(TxError::TxIdReuseAfterSettlement {
+                tx_id: self.tx_id,
+                prior_state,
+            });
+        }
+        // A deposit replacing a live record would orphan the disputes and holds of the
+        // earlier transaction, whichever client it belongs to.
+        if ledger.tx_states.contains_key(&self.tx_id) {
+            return Err(TxError::DuplicateTxId);
+        }
+        if exact_add(ledger.liabilities.balance, -self.amount).is_none() {
+            return Err(TxError::BalanceOverflow);
+        }
+        let velocity_limit = ledger.config.velocity_limit;
+        // A window total out of the range of `Decimal` is above any limit.
+        let exceeding_total = velocity_limit.and_then(|limit| {
+            match ledger
+                .velocity
+                .total_with(&limit, self.client_id, self.amount)
+            {
+                Some(total) if total <= limit.max_total => None,
+                total => Some(total.unwrap_or(Decimal::MAX)),
+            }
+        });
+        if exceeding_total.is_some()
+            && velocity_limit.is_some_and(|limit| limit.action == VelocityAction::Reject)
+        {
+            return Err(TxError::VelocityLimitExceeded);
+        }
+        let overdraft_limit = ledger.config.overdraft_limit_of(self.client_id);
+        let client_account = ledger.accounts.get_or_insert_with(self.client_id, || {
+            UserAccount::with_overdraft_limit(self.client_id, overdraft_limit)
+        });
+        let mut deposit_state = TxRecord::new(
+            self.client_id,
             self.tx_id,
-            DepositState::new(self.client_id, self.tx_id, self.amount),
+            TxTypeTag::Deposit,
+            self.amount,
+            ledger.config.clock.now(),
         );
+        if client_account.locked && ledger.config.lock_policy == LockPolicy::HoldIncoming {
+            transfer(
+                &mut ledger.liabilities,
+                &mut client_account.held,
+                self.amount,
+            )?;
+            deposit_state.state = TxState::HeldOnArrival;
+            index_open_dispute(&mut ledger.open_disputes, self.client_id, self.tx_id);
+        } else {
+            transfer(
+                &mut ledger.liabilities,
+                &mut client_account.available,
+                self.amount,
+            )?;
+        }
+        client_account.deposited = client_account.deposited.saturating_add(self.amount);
+        if ledger.config.dispute_tracking == DisputeTracking::Full
+            || deposit_state.state == TxState::HeldOnArrival
+        {
+            ledger.tx_states.insert(self.tx_id, deposit_state);
+        }
+        if let Some(limit) = velocity_limit {
+            ledger.velocity.record(&limit, self.client_id, self.amount);
+        }
+        if let Some(window_total) = exceeding_total {
+            ledger.velocity.count_flagged();
+            ledger.events.emit(LedgerEvent::VelocityFlagged {
+                client_id: self.client_id,
+                tx_id: self.tx_id,
+                window_total,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Withdrawal {
+    client_id: ClientId,
+    tx_id: TxId,
+    amount: Decimal,
+    /// External reference of the payout, kept until the withdrawal is settled.
+    reference: Option<Box<str>>,
+}
+
+impl Withdrawal {
+    pub fn new(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            amount,
+            reference: None,
+        }
+    }
+
+    /// Attaches the reference of the payout, such as the provider's payout id, which
+    /// the ledger keeps with the withdrawal. Fails with
+    /// `TransactionLogError::ReferenceTooLong` beyond `MAX_REFERENCE_LEN` bytes.
+    pub fn with_reference(self, reference: &str) -> Result<Self, TransactionLogError> {
+        if reference.len() > MAX_REFERENCE_LEN {
+            return Err(TransactionLogError::ReferenceTooLong);
+        }
+        Ok(Self {
+            reference: Some(reference.into()),
+            ..self
+        })
+    }
+}
+
+impl ExecutableTransaction for Withdrawal {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::Withdrawal
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
+            // Its record would replace the earlier one, which could then no longer be
+            // disputed or settled.
+            if ledger.tx_states.contains_key(&self.tx_id)
+                || ledger.tombstones.contains_key(&self.tx_id)
+            {
+                return Err(TxError::DuplicateTxId);
+            }
+            if client_account.locked {
+                return Err(TxError::ClientAccountLocked);
+            }
+            match client_account.available.balance.checked_sub(self.amount) {
+                Some(remaining) if remaining >= client_account.overdraft_limit => {}
+                _ => return Err(TxError::InsufficientFunds),
+            }
+            transfer(
+                &mut client_account.available,
+                &mut ledger.liabilities,
+                self.amount,
+            )?;
+            client_account.withdrawn = client_account.withdrawn.saturating_add(self.amount);
+            if ledger.config.dispute_tracking == DisputeTracking::Full || self.reference.is_some() {
+                let mut record = TxRecord::new(
+                    self.client_id,
+                    self.tx_id,
+                    TxTypeTag::Withdrawal,
+                    self.amount,
+                    ledger.config.clock.now(),
+                );
+                if let Some(withdrawal) = record.withdrawal.as_mut() {
+                    withdrawal.reference.clone_from(&self.reference);
+                }
+                ledger.record_withdrawal(record);
+            }
+            Ok(())
+        } else {
+            Err(TxError::ClientAccountNotFound)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Dispute {
+    client_id: ClientId,
+    tx_id: TxId,
+    /// Amount of the deposit stated on the row, see `DisputeAmountHandling`.
+    amount: Option<Decimal>,
+    reason: Option<DisputeReason>,
+}
+
+impl Dispute {
+    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            amount: None,
+            reason: None,
+        }
+    }
+
+    /// States `amount` as the amount of the deposit, to be checked against the
+    /// recorded one according to `DisputeAmountHandling`.
+    pub fn with_amount(self, amount: Decimal) -> Self {
+        Self {
+            amount: Some(amount),
+            ..self
+        }
+    }
+
+    /// Gives the reason of the dispute, such as a reason code of the card network,
+    /// checked against the `ReasonAllowlist` if any. The deposit keeps the reason of
+    /// its first dispute giving one.
+    pub fn with_reason(self, reason: DisputeReason) -> Self {
+        Self {
+            reason: Some(reason),
+            ..self
+        }
+    }
+}
+
+impl ExecutableTransaction for Dispute {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::Dispute
+    }
+
+    fn stated_amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    fn reason(&self) -> Option<&DisputeReason> {
+        self.reason.as_ref()
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
+            if let Some(deposit) = ledger.tx_states.get_mut(&self.tx_id) {
+                if deposit.tx_id != self.tx_id || deposit.client_id != self.client_id {
+                    return Err(TxError::OriginTxNotFound);
+                }
+                deposit.check_disputable()?;
+                match deposit.state {
+                    TxState::Resolved => {}
+                    TxState::Reversed => return Err(TxError::TxReversed),
+                    _ => return Err(TxError::TxAlreadyDisputed),
+                }
+                if let Some(window) = ledger.config.dispute_window {
+                    let age = ledger
+                        .config
+                        .clock
+                        .now()
+                        .saturating_duration_since(deposit.recorded_at);
+                    if age > window {
+                        return Err(TxError::DisputeWindowExpired);
+                    }
+                }
+                let mismatch = check_stated_amount(
+                    ledger.config.dispute_amount_handling,
+                    self.amount,
+                    deposit.amount,
+                )?;
+                let unknown_reason =
+                    check_reason(ledger.config.dispute_reasons.as_ref(), self.reason.as_ref())?;
+                if let Some(limit) = ledger.config.max_open_disputes_per_account {
+                    if client_account.open_disputes >= limit {
+                        return Err(TxError::TooManyOpenDisputes {
+                            open: client_account.open_disputes,
+                            limit,
+                        });
+                    }
+                }
+                // Percentages apply to the total before the dispute
+                if let Some(limit) = ledger.config.dispute_limit_of(self.client_id) {
+                    let limit = limit.max_held(client_account.total());
+                    let current_held = client_account.held.balance;
+                    if current_held.saturating_add(deposit.amount) > limit {
+                        return Err(TxError::DisputeExposureLimitExceeded {
+                            current_held,
+                            limit,
+                        });
+                    }
+                }
+                transfer(
+                    &mut client_account.available,
+                    &mut client_account.held,
+                    deposit.amount,
+                )?;
+                deposit.state = TxState::Disputed;
+                deposit.disputed_at = Some(ledger.tx_seq);
+                let reason = self
+                    .reason
+                    .as_ref()
+                    .map(|reason| ledger.reasons.intern(reason));
+                if deposit.reason.is_none() {
+                    deposit.reason.clone_from(&reason);
+                }
+                let expected = deposit.amount;
+                open_dispute(&mut ledger.open_disputes, client_account, self.tx_id);
+                client_account.disputes_opened = client_account.disputes_opened.saturating_add(1);
+                ledger.warn_stated_amount(self.client_id, self.tx_id, expected, mismatch);
+                ledger.warn_unknown_reason(
+                    self.client_id,
+                    self.tx_id,
+                    TxTypeTag::Dispute,
+                    reason.filter(|_reason| unknown_reason),
+                );
+                Ok(())
+            } else {
+                Err(TxError::OriginTxNotFound)
+            }
+        } else {
+            Err(TxError::ClientAccountNotFound)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Resolve {
+    client_id: ClientId,
+    tx_id: TxId,
+    /// Amount of the deposit stated on the row, see `DisputeAmountHandling`.
+    amount: Option<Decimal>,
+}
+
+impl Resolve {
+    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            amount: None,
+        }
+    }
+
+    /// States `amount` as the amount of the deposit, to be checked against the
+    /// recorded one according to `DisputeAmountHandling`.
+    pub fn with_amount(self, amount: Decimal) -> Self {
+        Self {
+            amount: Some(amount),
+            ..self
+        }
+    }
+}
+
+impl ExecutableTransaction for Resolve {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::Resolve
+    }
+
+    fn stated_amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
+            if let Some(deposit) = ledger.tx_states.get_mut(&self.tx_id) {
+                if deposit.tx_id != self.tx_id || deposit.client_id != self.client_id {
+                    return Err(TxError::OriginTxNotFound);
+                }
+                deposit.check_disputable()?;
+                match deposit.state {
+                    TxState::Disputed => {}
+                    TxState::HeldOnArrival if client_account.locked => {
+                        return Err(TxError::ClientAccountLocked);
+                    }
+                    TxState::HeldOnArrival => {}
+                    _ => return Err(TxError::TxNotDisputed),
+                }
+                let disputed = deposit.state == TxState::Disputed;
+                let mismatch = check_stated_amount(
+                    ledger.config.dispute_amount_handling,
+                    self.amount,
+                    deposit.amount,
+                )?;
+                if client_account.held.balance < deposit.amount {
+                    return Err(TxError::HeldBalanceInconsistent {
+                        expected: deposit.amount,
+                        actual: client_account.held.balance,
+                    });
+                }
+                transfer(
+                    &mut client_account.held,
+                    &mut client_account.available,
+                    deposit.amount,
+                )?;
+                deposit.state = TxState::Resolved;
+                deposit.disputed_at = None;
+                deposit.resolved = true;
+                let expected = deposit.amount;
+                if disputed {
+                    close_dispute(&mut ledger.open_disputes, client_account, self.tx_id);
+                    client_account.disputes_resolved =
+                        client_account.disputes_resolved.saturating_add(1);
+                } else {
+                    unindex_open_dispute(&mut ledger.open_disputes, self.client_id, self.tx_id);
+                }
+                ledger.warn_stated_amount(self.client_id, self.tx_id, expected, mismatch);
+                Ok(())
+            } else {
+                Err(TxError::OriginTxNotFound)
+            }
+        } else {
+            Err(TxError::ClientAccountNotFound)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Chargeback {
+    client_id: ClientId,
+    tx_id: TxId,
+    /// Amount of the deposit stated on the row, see `DisputeAmountHandling`.
+    amount: Option<Decimal>,
+    reason: Option<DisputeReason>,
+}
+
+impl Chargeback {
+    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            amount: None,
+            reason: None,
+        }
+    }
+
+    /// States `amount` as the amount of the deposit, to be checked against the
+    /// recorded one according to `DisputeAmountHandling`.
+    pub fn with_amount(self, amount: Decimal) -> Self {
+        Self {
+            amount: Some(amount),
+            ..self
+        }
+    }
+
+    /// Gives the reason of the chargeback, which replaces the reason of the dispute,
+    /// see `Dispute::with_reason`.
+    pub fn with_reason(self, reason: DisputeReason) -> Self {
+        Self {
+            reason: Some(reason),
+            ..self
+        }
+    }
+}
+
+impl ExecutableTransaction for Chargeback {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::Chargeback
+    }
+
+    fn stated_amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    fn reason(&self) -> Option<&DisputeReason> {
+        self.reason.as_ref()
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
+            if let Some(deposit) = ledger.tx_states.get_mut(&self.tx_id) {
+                if deposit.tx_id != self.tx_id || deposit.client_id != self.client_id {
+                    return Err(TxError::OriginTxNotFound);
+                }
+                deposit.check_disputable()?;
+                if !matches!(deposit.state, TxState::Disputed | TxState::HeldOnArrival) {
+                    return Err(TxError::TxNotDisputed);
+                }
+                let disputed = deposit.state == TxState::Disputed;
+                let mismatch = check_stated_amount(
+                    ledger.config.dispute_amount_handling,
+                    self.amount,
+                    deposit.amount,
+                )?;
+                let unknown_reason =
+                    check_reason(ledger.config.dispute_reasons.as_ref(), self.reason.as_ref())?;
+                if client_account.held.balance < deposit.amount {
+                    return Err(TxError::HeldBalanceInconsistent {
+                        expected: deposit.amount,
+                        actual: client_account.held.balance,
+                    });
+                }
+                transfer(
+                    &mut client_account.held,
+                    &mut ledger.liabilities,
+                    deposit.amount,
+                )?;
+                deposit.state = TxState::ChargedBack;
+                deposit.disputed_at = None;
+                let reason = self
+                    .reason
+                    .as_ref()
+                    .map(|reason| ledger.reasons.intern(reason));
+                if reason.is_some() {
+                    deposit.reason.clone_from(&reason);
+                }
+                client_account.charged_back =
+                    client_account.charged_back.saturating_add(deposit.amount);
+                client_account.chargebacks = client_account.chargebacks.saturating_add(1);
+                if disputed {
+                    close_dispute(&mut ledger.open_disputes, client_account, self.tx_id);
+                    client_account.disputes_charged_back =
+                        client_account.disputes_charged_back.saturating_add(1);
+                } else {
+                    unindex_open_dispute(&mut ledger.open_disputes, self.client_id, self.tx_id);
+                }
+                let locks = match ledger.config.chargeback_policy {
+                    ChargebackPolicy::LockAccount => true,
+                    ChargebackPolicy::NoLock => false,
+                    ChargebackPolicy::LockAfterN(n) => client_account.chargebacks >= n,
+                };
+                let newly_locked = locks && !client_account.locked;
+                client_account.locked |= locks;
+                ledger.events.emit(LedgerEvent::ChargedBack {
+                    client_id: self.client_id,
+                    tx_id: self.tx_id,
+                    amount: deposit.amount,
+                    reason: deposit.reason.clone(),
+                });
+                if newly_locked {
+                    ledger.events.emit(LedgerEvent::AccountLocked {
+                        client_id: self.client_id,
+                        tx_id: self.tx_id,
+                    });
+                }
+                let expected = deposit.amount;
+                ledger.warn_stated_amount(self.client_id, self.tx_id, expected, mismatch);
+                ledger.warn_unknown_reason(
+                    self.client_id,
+                    self.tx_id,
+                    TxTypeTag::Chargeback,
+                    reason.filter(|_reason| unknown_reason),
+                );
+                Ok(())
+            } else {
+                Err(TxError::OriginTxNotFound)
+            }
+        } else {
+            Err(TxError::ClientAccountNotFound)
+        }
+    }
+}
+
+/// Operator correction reversing an erroneous deposit without locking the account.
+#[derive(Debug, PartialEq)]
+pub struct Adjustment {
+    client_id: ClientId,
+    tx_id: TxId,
+}
+
+impl Adjustment {
+    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
+        Self { client_id, tx_id }
+    }
+}
+
+impl ExecutableTransaction for Adjustment {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::Adjustment
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
+            if let Some(deposit) = ledger.tx_states.get_mut(&self.tx_id) {
+                if deposit.tx_id != self.tx_id || deposit.client_id != self.client_id {
+                    return Err(TxError::OriginTxNotFound);
+                }
+                deposit.check_disputable()?;
+                match deposit.state {
+                    TxState::Resolved => {}
+                    TxState::Disputed | TxState::HeldOnArrival => {
+                        return Err(TxError::TxAlreadyDisputed)
+                    }
+                    TxState::ChargedBack => return Err(TxError::TxChargedBack),
+                    TxState::Reversed => return Err(TxError::TxReversed),
+                }
+                if client_account.available.balance < deposit.amount {
+                    return Err(TxError::InsufficientFunds);
+                }
+                transfer(
+                    &mut client_account.available,
+                    &mut ledger.liabilities,
+                    deposit.amount,
+                )?;
+                deposit.state = TxState::Reversed;
+                client_account.deposited = client_account.deposited.saturating_sub(deposit.amount);
+                Ok(())
+            } else {
+                Err(TxError::OriginTxNotFound)
+            }
+        } else {
+            Err(TxError::ClientAccountNotFound)
+        }
+    }
+}
+
+/// First step of a two-phase withdrawal, moving the amount from available to held
+/// until a `WithdrawCapture` or `WithdrawRelease` of the same tx settles it.
+#[derive(Debug, PartialEq)]
+pub struct WithdrawAuth {
+    client_id: ClientId,
+    tx_id: TxId,
+    amount: Decimal,
+}
+
+impl WithdrawAuth {
+    pub fn new(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            amount,
+        }
+    }
+}
+
+impl ExecutableTransaction for WithdrawAuth {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::WithdrawAuth
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
+            if client_account.locked {
+                return Err(TxError::ClientAccountLocked);
+            }
+            if let Some(hold) = ledger.withdrawal_holds.get(&self.tx_id) {
+                return Err(match hold.state {
+                    HoldState::Held => TxError::DuplicateTxId,
+                    HoldState::Captured | HoldState::Released => TxError::HoldAlreadySettled,
+                });
+            }
+            match client_account.available.balance.checked_sub(self.amount) {
+                Some(remaining) if remaining >= client_account.overdraft_limit => {}
+                _ => return Err(TxError::InsufficientFunds),
+            }
+            transfer(
+                &mut client_account.available,
+                &mut client_account.held,
+                self.amount,
+            )?;
+            ledger.withdrawal_holds.insert(
+                self.tx_id,
+                WithdrawalHold::new(self.client_id, self.tx_id, self.amount),
+            );
+            Ok(())
+        } else {
+            Err(TxError::ClientAccountNotFound)
+        }
+    }
+}
+
+/// Pays out the amount held by the `WithdrawAuth` of the same tx.
+#[derive(Debug, PartialEq)]
+pub struct WithdrawCapture {
+    client_id: ClientId,
+    tx_id: TxId,
+}
+
+impl WithdrawCapture {
+    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
+        Self { client_id, tx_id }
+    }
+}
+
+impl ExecutableTransaction for WithdrawCapture {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::WithdrawCapture
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        let amount = open_hold_amount(ledger, self.client_id, self.tx_id)?;
+        let client_account = ledger
+            .accounts
+            .get_mut(&self.client_id)
+            .expect("hold was checked to belong to an account");
+        transfer(&mut client_account.held, &mut ledger.liabilities, amount)?;
+        client_account.withdrawn = client_account.withdrawn.saturating_add(amount);
+        settle_hold(ledger, self.tx_id, HoldState::Captured);
+        Ok(())
+    }
+}
+
+/// Returns the amount held by the `WithdrawAuth` of the same tx to available, e.g.
+/// after the payout failed.
+#[derive(Debug, PartialEq)]
+pub struct WithdrawRelease {
+    client_id: ClientId,
+    tx_id: TxId,
+}
+
+impl WithdrawRelease {
+    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
+        Self { client_id, tx_id }
+    }
+}
+
+impl ExecutableTransaction for WithdrawRelease {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::WithdrawRelease
+    }
+
+    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
+        let amount = open_hold_amount(ledger, self.client_id, self.tx_id)?;
+        let client_account = ledger
+            .accounts
+            .get_mut(&self.client_id)
+            .expect("hold was checked to belong to an account");
+        transfer(
+            &mut client_account.held,
+            &mut client_account.available,
+            amount,
+        )?;
+        settle_hold(ledger, self.tx_id, HoldState::Released);
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Withdrawal {
-    client_id: ClientId,
-    tx_id: TxId,
-    amount: Decimal,
-}
-
-impl Withdrawal {
-    pub fn new(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
-        Self {
-            client_id,
-            tx_id,
-            amount,
+/// Amount stated by a dispute, resolve or chargeback which differs from the
+/// `expected` amount of its deposit and is to be warned about, checked before the
+/// transaction changes anything.
+fn check_stated_amount(
+    handling: DisputeAmountHandling,
+    stated: Option<Decimal>,
+    expected: Decimal,
+) -> Result<Option<Decimal>, TxError> {
+    let Some(provided) = stated.filter(|stated| *stated != expected) else {
+        return Ok(None);
+    };
+    match handling {
+        DisputeAmountHandling::Ignore => Ok(None),
+        DisputeAmountHandling::WarnOnMismatch => Ok(Some(provided)),
+        DisputeAmountHandling::RejectOnMismatch => {
+            Err(TxError::DisputedAmountMismatch { expected, provided })
         }
     }
 }
 
-impl ExecutableTransaction for Withdrawal {
-    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
-        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
-            if client_account.locked {
-                return Err(TxError::ClientAccountLocked);
-            }
-            if client_account.available.balance < self.amount {
-                return Err(TxError::InsufficientFunds);
+/// Checks the reason of a dispute or chargeback against `allowlist`, telling whether
+/// it is outside of it under `UnknownReasonAction::Warn`.
+fn check_reason(
+    allowlist: Option<&ReasonAllowlist>,
+    reason: Option<&DisputeReason>,
+) -> Result<bool, TxError> {
+    match (allowlist, reason) {
+        (Some(allowlist), Some(reason)) if !allowlist.allows(reason.as_str()) => {
+            match allowlist.action {
+                UnknownReasonAction::Reject => Err(TxError::UnknownDisputeReason),
+                UnknownReasonAction::Warn => Ok(true),
             }
-            make_tx(
-                &mut client_account.available,
-                &mut ledger.liabilities,
-                self.amount,
-            );
-            Ok(())
-        } else {
-            Err(TxError::ClientAccountNotFound)
         }
+        _ => Ok(false),
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Dispute {
-    client_id: ClientId,
-    tx_id: TxId,
+/// Amount of the open withdrawal hold of `tx_id`, settled by a capture or release,
+/// checking that it is still held.
+fn open_hold_amount(ledger: &Ledger, client_id: ClientId, tx_id: TxId) -> Result<Decimal, TxError> {
+    let client_account = ledger
+        .accounts
+        .get(&client_id)
+        .ok_or(TxError::ClientAccountNotFound)?;
+    let hold = ledger
+        .withdrawal_holds
+        .get(&tx_id)
+        .filter(|hold| hold.client_id == client_id)
+        .ok_or(TxError::HoldNotFound)?;
+    if hold.state != HoldState::Held {
+        return Err(TxError::HoldAlreadySettled);
+    }
+    if client_account.held.balance < hold.amount {
+        return Err(TxError::HeldBalanceInconsistent {
+            expected: hold.amount,
+            actual: client_account.held.balance,
+        });
+    }
+    Ok(hold.amount)
 }
 
-impl Dispute {
-    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
-        Self { client_id, tx_id }
-    }
+/// Marks the hold checked by `open_hold_amount` as settled.
+fn settle_hold(ledger: &mut Ledger, tx_id: TxId, state: HoldState) {
+    ledger
+        .withdrawal_holds
+        .get_mut(&tx_id)
+        .expect("hold was checked to be open")
+        .state = state;
 }
 
-impl ExecutableTransaction for Dispute {
-    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
-        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
-            if let Some(deposit) = ledger.deposit_states.get_mut(&self.tx_id) {
-                if deposit.tx_id != self.tx_id || deposit.client_id != self.client_id {
-                    return Err(TxError::OriginTxNotFound);
-                }
-                if deposit.state != TxState::Resolved {
-                    return Err(TxError::TxAlreadyDisputed);
-                }
-                deposit.state = TxState::Disputed;
-                make_tx(
-                    &mut client_account.available,
-                    &mut client_account.held,
-                    deposit.amount,
-                );
-                Ok(())
-            } else {
-                Err(TxError::OriginTxNotFound)
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionLogError {
+    InvalidTxType,
+    MissingAmount,
+    /// The row has fewer fields than the header.
+    TooFewFields,
+    /// The row has more fields than the header.
+    TooManyFields,
+    /// The separators of the amount don't fit the `NumberLocale` of the input, or
+    /// leave it open whether they group thousands or start the fraction.
+    AmbiguousAmount,
+    /// The reference of a withdrawal is longer than `MAX_REFERENCE_LEN` bytes.
+    ReferenceTooLong,
+    /// The reason of a dispute or chargeback is longer than `MAX_REASON_LEN` bytes.
+    ReasonTooLong,
+    /// The amount of a deposit or withdrawal starts with `-`.
+    NegativeAmountNotAllowed,
+    /// The amount `raw` starts with a number followed by other characters, the first
+    /// of which is at byte `at_offset`. Parsed from its code, both are empty.
+    TrailingCharactersInAmount {
+        raw: String,
+        at_offset: usize,
+    },
+    /// The amount has more fractional digits than the `AmountScale` of the run.
+    ExcessPrecision,
+}
+
+impl TransactionLogError {
+    /// Stable snake_case code identifying the error in reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionLogError::InvalidTxType => "invalid_tx_type",
+            TransactionLogError::MissingAmount => "missing_amount",
+            TransactionLogError::TooFewFields => "too_few_fields",
+            TransactionLogError::TooManyFields => "too_many_fields",
+            TransactionLogError::AmbiguousAmount => "ambiguous_amount",
+            TransactionLogError::ReferenceTooLong => "reference_too_long",
+            TransactionLogError::ReasonTooLong => "reason_too_long",
+            TransactionLogError::NegativeAmountNotAllowed => "negative_amount_not_allowed",
+            TransactionLogError::TrailingCharactersInAmount { .. } => {
+                "trailing_characters_in_amount"
             }
-        } else {
-            Err(TxError::ClientAccountNotFound)
+            TransactionLogError::ExcessPrecision => "excess_precision",
         }
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub struct Resolve {
-    client_id: ClientId,
-    tx_id: TxId,
+    /// Checks that a row has as many fields as the header has columns.
+    pub(crate) fn check_field_count(fields: usize, columns: usize) -> Result<(), Self> {
+        match fields.cmp(&columns) {
+            Ordering::Less => Err(TransactionLogError::TooFewFields),
+            Ordering::Equal => Ok(()),
+            Ordering::Greater => Err(TransactionLogError::TooManyFields),
+        }
+    }
 }
 
-impl Resolve {
-    pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
-        Self { client_id, tx_id }
+impl Display for TransactionLogError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
     }
 }
 
-impl ExecutableTransaction for Resolve {
-    fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
-        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
-            if let Some(deposit) = ledger.deposit_states.get_mut(&self.tx_id) {
-                if deposit.tx_id != self.tx_id || deposit.client_id != self.client_id {
-                    return Err(TxError::OriginTxNotFound);
-                }
-                if deposit.state != TxState::Disputed {
-                    return Err(TxError::TxNotDisputed);
-                }
-                deposit.state = TxState::Resolved;
-                make_tx(
-                    &mut client_account.held,
-                    &mut client_account.available,
-                    deposit.amount,
-                );
-                Ok(())
-            } else {
-                Err(TxError::OriginTxNotFound)
+impl std::error::Error for TransactionLogError {}
+
+impl FromStr for TransactionLogError {
+    type Err = UnknownErrorCode;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code {
+            "invalid_tx_type" => Ok(TransactionLogError::InvalidTxType),
+            "missing_amount" => Ok(TransactionLogError::MissingAmount),
+            "too_few_fields" => Ok(TransactionLogError::TooFewFields),
+            "too_many_fields" => Ok(TransactionLogError::TooManyFields),
+            "ambiguous_amount" => Ok(TransactionLogError::AmbiguousAmount),
+            "reference_too_long" => Ok(TransactionLogError::ReferenceTooLong),
+            "reason_too_long" => Ok(TransactionLogError::ReasonTooLong),
+            "negative_amount_not_allowed" => Ok(TransactionLogError::NegativeAmountNotAllowed),
+            "trailing_characters_in_amount" => {
+                Ok(TransactionLogError::TrailingCharactersInAmount {
+                    raw: String::new(),
+                    at_offset: 0,
+                })
             }
-        } else {
-            Err(TxError::ClientAccountNotFound)
+            "excess_precision" => Ok(TransactionLogError::ExcessPrecision),
+            _ => Err(UnknownErrorCode(code.to_string())),
         }
     }
 }
 
+impl Serialize for TransactionLogError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionLogError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Marks the withdrawal of the same tx as settled by the payout provider. Balances
+/// are not touched, since the withdrawal already took the funds.
 #[derive(Debug, PartialEq)]
-pub struct Chargeback {
+pub struct Settle {
     client_id: ClientId,
     tx_id: TxId,
 }
 
-impl Chargeback {
+impl Settle {
     pub fn new(client_id: ClientId, tx_id: TxId) -> Self {
         Self { client_id, tx_id }
     }
 }
 
-impl ExecutableTransaction for Chargeback {
+impl ExecutableTransaction for Settle {
+    fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    fn kind(&self) -> TxTypeTag {
+        TxTypeTag::Settle
+    }
+
     fn execute_tx(&self, ledger: &mut Ledger) -> Result<(), TxError> {
-        if let Some(client_account) = ledger.accounts.get_mut(&self.client_id) {
-            if let Some(deposit) = ledger.deposit_states.get_mut(&self.tx_id) {
-                if deposit.tx_id != self.tx_id || deposit.client_id != self.client_id {
-                    return Err(TxError::OriginTxNotFound);
-                }
-                if deposit.state != TxState::Disputed {
-                    return Err(TxError::TxNotDisputed);
-                }
-                deposit.state = TxState::ChargedBack;
-                client_account.locked = true;
-                make_tx(
-                    &mut client_account.held,
-                    &mut ledger.liabilities,
-                    deposit.amount,
-                );
-                Ok(())
-            } else {
-                Err(TxError::OriginTxNotFound)
-            }
-        } else {
-            Err(TxError::ClientAccountNotFound)
+        let record = ledger
+            .tx_states
+            .get_mut(&self.tx_id)
+            .filter(|record| record.client_id == self.client_id)
+            .ok_or(TxError::WithdrawalNotFound)?;
+        let withdrawal = record.withdrawal.as_mut().ok_or(TxError::TxNotWithdrawal)?;
+        if withdrawal.settled {
+            return Err(TxError::WithdrawalAlreadySettled);
         }
+        withdrawal.settled = true;
+        Ok(())
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum TransactionLogError {
-    InvalidTxType,
-    MissingAmount,
-}
-
 impl TryFrom<TransactionLog> for Transaction {
     type Error = TransactionLogError;
 
     fn try_from(log: TransactionLog) -> Result<Self, Self::Error> {
+        let amount = log.read_amount()?;
         let TransactionLog {
             tx_type,
             client_id,
             tx_id,
-            amount,
+            reference,
+            reason,
+            ..
         } = log;
-        match tx_type.as_str() {
-            DEPOSIT_TAG => {
-                let amount = amount.ok_or(TransactionLogError::MissingAmount)?;
+        match tx_type {
+            TxTypeTag::Deposit => {
+                let amount = required_amount(amount)?;
                 Ok(Transaction::Deposit(Deposit {
                     client_id,
                     tx_id,
                     amount,
                 }))
             }
-            WITHDRAWAL_TAG => {
-                let amount = log.amount.ok_or(TransactionLogError::MissingAmount)?;
-                Ok(Transaction::Withdrawal(Withdrawal {
+            TxTypeTag::Withdrawal => {
+                let amount = required_amount(amount)?;
+                let withdrawal = Withdrawal::new(client_id, tx_id, amount);
+                match reference.as_deref() {
+                    None | Some("") => Ok(Transaction::Withdrawal(withdrawal)),
+                    Some(reference) => withdrawal
+                        .with_reference(reference)
+                        .map(Transaction::Withdrawal),
+                }
+            }
+            TxTypeTag::Dispute => Ok(Transaction::Dispute(Dispute {
+                client_id,
+                tx_id,
+                amount,
+                reason: optional_reason(reason.as_deref())?,
+            })),
+            TxTypeTag::Resolve => Ok(Transaction::Resolve(Resolve {
+                client_id,
+                tx_id,
+                amount,
+            })),
+            TxTypeTag::Chargeback => Ok(Transaction::Chargeback(Chargeback {
+                client_id,
+                tx_id,
+                amount,
+                reason: optional_reason(reason.as_deref())?,
+            })),
+            TxTypeTag::Adjustment => Ok(Transaction::Adjustment(Adjustment { client_id, tx_id })),
+            TxTypeTag::WithdrawAuth => {
+                let amount = required_amount(amount)?;
+                Ok(Transaction::WithdrawAuth(WithdrawAuth {
                     client_id,
                     tx_id,
                     amount,
                 }))
             }
-            DISPUTE_TAG => Ok(Transaction::Dispute(Dispute { client_id, tx_id })),
-            RESOLVE_TAG => Ok(Transaction::Resolve(Resolve { client_id, tx_id })),
-            CHARGEBACK_TAG => Ok(Transaction::Chargeback(Chargeback { client_id, tx_id })),
-            _ => Err(TransactionLogError::InvalidTxType),
+            TxTypeTag::WithdrawCapture => Ok(Transaction::WithdrawCapture(WithdrawCapture {
+                client_id,
+                tx_id,
+            })),
+            TxTypeTag::WithdrawRelease => Ok(Transaction::WithdrawRelease(WithdrawRelease {
+                client_id,
+                tx_id,
+            })),
+            TxTypeTag::Settle => Ok(Transaction::Settle(Settle { client_id, tx_id })),
+        }
+    }
+}
+
+/// Row of `tx`, which parses back into the same transaction. The reference of a
+/// withdrawal and the reason of a dispute or chargeback are kept in the row but not
+/// serialized.
+impl From<&Transaction> for TransactionLog {
+    fn from(tx: &Transaction) -> Self {
+        let amount = tx.amount().or(tx.stated_amount());
+        TransactionLog {
+            reference: tx.reference().map(str::to_string),
+            reason: tx.reason().map(DisputeReason::to_string),
+            ..TransactionLog::new(tx.kind(), tx.client_id(), tx.tx_id(), amount)
         }
     }
 }
@@ -266,6 +1423,7 @@ impl TryFrom<TransactionLog> for Transaction {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::accounting::reasons::MAX_REASON_LEN;
     use csv::{ReaderBuilder, Trim};
     use rust_decimal_macros::dec;
 
@@ -281,6 +1439,7 @@ mod tests {
             dispute, 1, 3,
             resolve, 1, 3,
             chargeback, 1, 1,
+            adjustment, 2, 2,
         "#;
 
         let mut reader = ReaderBuilder::new()
@@ -293,10 +1452,12 @@ mod tests {
         assert_eq!(
             deposit1,
             TransactionLog {
-                tx_type: DEPOSIT_TAG.to_string(),
+                tx_type: TxTypeTag::Deposit,
                 client_id: 1,
                 tx_id: 1,
-                amount: Some(dec!(1.0)),
+                amount: Some(Ok(dec!(1.0))),
+                reference: None,
+                reason: None,
             }
         );
 
@@ -304,10 +1465,12 @@ mod tests {
         assert_eq!(
             deposit2,
             TransactionLog {
-                tx_type: "deposit".to_string(),
+                tx_type: TxTypeTag::Deposit,
                 client_id: 2,
                 tx_id: 2,
-                amount: Some(dec!(2.0)),
+                amount: Some(Ok(dec!(2.0))),
+                reference: None,
+                reason: None,
             }
         );
 
@@ -315,10 +1478,12 @@ mod tests {
         assert_eq!(
             deposit3,
             TransactionLog {
-                tx_type: DEPOSIT_TAG.to_string(),
+                tx_type: TxTypeTag::Deposit,
                 client_id: 1,
                 tx_id: 3,
-                amount: Some(dec!(2.0)),
+                amount: Some(Ok(dec!(2.0))),
+                reference: None,
+                reason: None,
             }
         );
 
@@ -326,10 +1491,12 @@ mod tests {
         assert_eq!(
             withdrawal1,
             TransactionLog {
-                tx_type: WITHDRAWAL_TAG.to_string(),
+                tx_type: TxTypeTag::Withdrawal,
                 client_id: 1,
                 tx_id: 4,
-                amount: Some(dec!(1.5)),
+                amount: Some(Ok(dec!(1.5))),
+                reference: None,
+                reason: None,
             }
         );
 
@@ -337,10 +1504,12 @@ mod tests {
         assert_eq!(
             withdrawal2,
             TransactionLog {
-                tx_type: WITHDRAWAL_TAG.to_string(),
+                tx_type: TxTypeTag::Withdrawal,
                 client_id: 2,
                 tx_id: 5,
-                amount: Some(dec!(3.0)),
+                amount: Some(Ok(dec!(3.0))),
+                reference: None,
+                reason: None,
             }
         );
 
@@ -348,10 +1517,12 @@ mod tests {
         assert_eq!(
             dispute,
             TransactionLog {
-                tx_type: DISPUTE_TAG.to_string(),
+                tx_type: TxTypeTag::Dispute,
                 client_id: 1,
                 tx_id: 3,
                 amount: None,
+                reference: None,
+                reason: None,
             }
         );
 
@@ -359,10 +1530,12 @@ mod tests {
         assert_eq!(
             resolve,
             TransactionLog {
-                tx_type: RESOLVE_TAG.to_string(),
+                tx_type: TxTypeTag::Resolve,
                 client_id: 1,
                 tx_id: 3,
                 amount: None,
+                reference: None,
+                reason: None,
             }
         );
 
@@ -370,10 +1543,25 @@ mod tests {
         assert_eq!(
             chargeback,
             TransactionLog {
-                tx_type: CHARGEBACK_TAG.to_string(),
+                tx_type: TxTypeTag::Chargeback,
                 client_id: 1,
                 tx_id: 1,
                 amount: None,
+                reference: None,
+                reason: None,
+            }
+        );
+
+        let adjustment = reader_iter.next().unwrap().unwrap();
+        assert_eq!(
+            adjustment,
+            TransactionLog {
+                tx_type: TxTypeTag::Adjustment,
+                client_id: 2,
+                tx_id: 2,
+                amount: None,
+                reference: None,
+                reason: None,
             }
         );
     }
@@ -381,10 +1569,12 @@ mod tests {
     #[test]
     fn convert_into_transaction() {
         let deposit1 = Transaction::try_from(TransactionLog {
-            tx_type: DEPOSIT_TAG.to_string(),
+            tx_type: TxTypeTag::Deposit,
             client_id: 1,
             tx_id: 1,
-            amount: Some(dec!(1.0)),
+            amount: Some(Ok(dec!(1.0))),
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(
@@ -397,10 +1587,12 @@ mod tests {
         );
 
         let deposit2 = Transaction::try_from(TransactionLog {
-            tx_type: "deposit".to_string(),
+            tx_type: TxTypeTag::Deposit,
             client_id: 2,
             tx_id: 2,
-            amount: Some(dec!(2.0)),
+            amount: Some(Ok(dec!(2.0))),
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(
@@ -413,10 +1605,12 @@ mod tests {
         );
 
         let deposit3 = Transaction::try_from(TransactionLog {
-            tx_type: DEPOSIT_TAG.to_string(),
+            tx_type: TxTypeTag::Deposit,
             client_id: 1,
             tx_id: 3,
-            amount: Some(dec!(2.0)),
+            amount: Some(Ok(dec!(2.0))),
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(
@@ -429,10 +1623,12 @@ mod tests {
         );
 
         let withdrawal1 = Transaction::try_from(TransactionLog {
-            tx_type: WITHDRAWAL_TAG.to_string(),
+            tx_type: TxTypeTag::Withdrawal,
             client_id: 1,
             tx_id: 4,
-            amount: Some(dec!(1.5)),
+            amount: Some(Ok(dec!(1.5))),
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(
@@ -441,14 +1637,17 @@ mod tests {
                 client_id: 1,
                 tx_id: 4,
                 amount: dec!(1.5),
+                reference: None,
             }))
         );
 
         let withdrawal2 = Transaction::try_from(TransactionLog {
-            tx_type: WITHDRAWAL_TAG.to_string(),
+            tx_type: TxTypeTag::Withdrawal,
             client_id: 2,
             tx_id: 5,
-            amount: Some(dec!(3.0)),
+            amount: Some(Ok(dec!(3.0))),
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(
@@ -457,82 +1656,453 @@ mod tests {
                 client_id: 2,
                 tx_id: 5,
                 amount: dec!(3.0),
+                reference: None,
             }))
         );
 
         let dispute = Transaction::try_from(TransactionLog {
-            tx_type: DISPUTE_TAG.to_string(),
+            tx_type: TxTypeTag::Dispute,
             client_id: 1,
             tx_id: 3,
             amount: None,
+            reference: None,
+            reason: None,
         });
 
-        assert_eq!(
-            dispute,
-            Ok(Transaction::Dispute(Dispute {
-                client_id: 1,
-                tx_id: 3
-            }))
-        );
+        assert_eq!(dispute, Ok(Transaction::Dispute(Dispute::new(1, 3))));
 
         let resolve = Transaction::try_from(TransactionLog {
-            tx_type: RESOLVE_TAG.to_string(),
+            tx_type: TxTypeTag::Resolve,
             client_id: 1,
             tx_id: 3,
             amount: None,
+            reference: None,
+            reason: None,
         });
 
-        assert_eq!(
-            resolve,
-            Ok(Transaction::Resolve(Resolve {
-                client_id: 1,
-                tx_id: 3
-            }))
-        );
+        assert_eq!(resolve, Ok(Transaction::Resolve(Resolve::new(1, 3))));
 
         let chargeback = Transaction::try_from(TransactionLog {
-            tx_type: CHARGEBACK_TAG.to_string(),
+            tx_type: TxTypeTag::Chargeback,
             client_id: 1,
             tx_id: 1,
             amount: None,
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(
             chargeback,
-            Ok(Transaction::Chargeback(Chargeback {
-                client_id: 1,
-                tx_id: 1
+            Ok(Transaction::Chargeback(Chargeback::new(1, 1)))
+        );
+
+        let adjustment = Transaction::try_from(TransactionLog {
+            tx_type: TxTypeTag::Adjustment,
+            client_id: 2,
+            tx_id: 2,
+            amount: None,
+            reference: None,
+            reason: None,
+        });
+
+        assert_eq!(
+            adjustment,
+            Ok(Transaction::Adjustment(Adjustment {
+                client_id: 2,
+                tx_id: 2
             }))
         );
 
         let deposit_no_amount = Transaction::try_from(TransactionLog {
-            tx_type: DEPOSIT_TAG.to_string(),
+            tx_type: TxTypeTag::Deposit,
             client_id: 1,
             tx_id: 1,
             amount: None,
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(deposit_no_amount, Err(TransactionLogError::MissingAmount));
 
         let withdrawal_no_amount = Transaction::try_from(TransactionLog {
-            tx_type: WITHDRAWAL_TAG.to_string(),
+            tx_type: TxTypeTag::Withdrawal,
             client_id: 2,
             tx_id: 5,
             amount: None,
+            reference: None,
+            reason: None,
         });
 
         assert_eq!(
             withdrawal_no_amount,
             Err(TransactionLogError::MissingAmount)
         );
+    }
 
-        let invalid_log = Transaction::try_from(TransactionLog {
-            tx_type: "Abcd".to_string(),
-            client_id: 2,
-            tx_id: 5,
-            amount: Some(dec!(35.0)),
-        });
+    #[test]
+    fn deserialize_unknown_tx_type() {
+        let data = r#"
+            type, client, tx, amount
+            Abcd, 2, 5, 35.0
+            Deposit, 2, 6, 35.0
+            deposit, 2, 7, 35.0
+        "#;
+
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+
+        let mut reader_iter = reader.deserialize::<TransactionLog>();
+        assert!(reader_iter.next().unwrap().is_err());
+        assert!(reader_iter.next().unwrap().is_err());
+        assert_eq!(
+            reader_iter.next().unwrap().unwrap(),
+            TransactionLog {
+                tx_type: TxTypeTag::Deposit,
+                client_id: 2,
+                tx_id: 7,
+                amount: Some(Ok(dec!(35.0))),
+                reference: None,
+                reason: None,
+            }
+        );
+    }
+
+    #[test]
+    fn signed_amounts_and_trailing_characters() {
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,+50.00\n\
+                    deposit,1,2,-50.00\n\
+                    withdrawal,1,3,-1\n\
+                    withdraw_auth,1,4,-1\n\
+                    deposit,1,5,50.00abc\n\
+                    dispute,1,1,10x\n\
+                    dispute,1,1,-10\n\
+                    dispute,1,1,abc\n";
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let transactions = reader
+            .deserialize::<TransactionLog>()
+            .map(|log| Transaction::try_from(log.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transactions,
+            [
+                Ok(Transaction::Deposit(Deposit::new(1, 1, dec!(50)))),
+                Err(TransactionLogError::NegativeAmountNotAllowed),
+                Err(TransactionLogError::NegativeAmountNotAllowed),
+                Err(TransactionLogError::NegativeAmountNotAllowed),
+                Err(TransactionLogError::TrailingCharactersInAmount {
+                    raw: "50.00abc".to_string(),
+                    at_offset: 5,
+                }),
+                Err(TransactionLogError::TrailingCharactersInAmount {
+                    raw: "10x".to_string(),
+                    at_offset: 2,
+                }),
+                // Stated amounts are compared rather than applied
+                Ok(Transaction::Dispute(Dispute {
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: Some(dec!(-10)),
+                    reason: None,
+                })),
+                Ok(Transaction::Dispute(Dispute::new(1, 1))),
+            ]
+        );
+    }
+
+    #[test]
+    fn withdrawal_hold_rows() {
+        let data = "type,client,tx,amount\n\
+                    withdraw_auth,1,2,3.5\n\
+                    withdraw_capture,1,2,\n\
+                    withdraw_release,1,2,\n\
+                    withdraw_auth,1,3,\n";
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let transactions = reader
+            .deserialize::<TransactionLog>()
+            .map(|log| Transaction::try_from(log.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transactions,
+            [
+                Ok(Transaction::WithdrawAuth(WithdrawAuth::new(
+                    1,
+                    2,
+                    dec!(3.5)
+                ))),
+                Ok(Transaction::WithdrawCapture(WithdrawCapture::new(1, 2))),
+                Ok(Transaction::WithdrawRelease(WithdrawRelease::new(1, 2))),
+                Err(TransactionLogError::MissingAmount),
+            ]
+        );
+        for tx in transactions.iter().flatten() {
+            let log = TransactionLog::from(tx);
+            assert_eq!(log.tx_type().as_str(), tx.kind().as_str());
+            assert_eq!(Transaction::try_from(log).as_ref(), Ok(tx));
+        }
+    }
+
+    #[test]
+    fn withdrawal_references_and_settle_rows() {
+        let long = "x".repeat(MAX_REFERENCE_LEN + 1);
+        let data = format!(
+            "type,client,tx,amount,reference\n\
+             withdrawal,1,2,3.5,po_2\n\
+             withdrawal,1,3,1,\n\
+             settle,1,2,,\n\
+             withdrawal,1,4,1,{}\n",
+            long
+        );
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transactions = reader
+            .deserialize::<TransactionLog>()
+            .map(|log| Transaction::try_from(log.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transactions,
+            [
+                Withdrawal::new(1, 2, dec!(3.5))
+                    .with_reference("po_2")
+                    .map(Transaction::Withdrawal),
+                Ok(Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(1)))),
+                Ok(Transaction::Settle(Settle::new(1, 2))),
+                Err(TransactionLogError::ReferenceTooLong),
+            ]
+        );
+        assert_eq!(transactions[0].as_ref().unwrap().reference(), Some("po_2"));
+        for tx in transactions.iter().flatten() {
+            assert_eq!(
+                Transaction::try_from(TransactionLog::from(tx)).as_ref(),
+                Ok(tx)
+            );
+        }
+        assert!(Withdrawal::new(1, 5, dec!(1))
+            .with_reference(&long[1..])
+            .is_ok());
+    }
+
+    #[test]
+    fn accessors_of_every_variant() {
+        let cases = [
+            (
+                Transaction::Deposit(Deposit::new(1, 10, dec!(1.5))),
+                TxTypeTag::Deposit,
+                Some(dec!(1.5)),
+            ),
+            (
+                Transaction::Withdrawal(Withdrawal::new(2, 11, dec!(0.25))),
+                TxTypeTag::Withdrawal,
+                Some(dec!(0.25)),
+            ),
+            (
+                Transaction::Dispute(Dispute::new(3, 12)),
+                TxTypeTag::Dispute,
+                None,
+            ),
+            (
+                Transaction::Resolve(Resolve::new(4, 13)),
+                TxTypeTag::Resolve,
+                None,
+            ),
+            (
+                Transaction::Chargeback(Chargeback::new(5, 14)),
+                TxTypeTag::Chargeback,
+                None,
+            ),
+            (
+                Transaction::Adjustment(Adjustment::new(6, 15)),
+                TxTypeTag::Adjustment,
+                None,
+            ),
+            (
+                Transaction::WithdrawAuth(WithdrawAuth::new(7, 16, dec!(3))),
+                TxTypeTag::WithdrawAuth,
+                Some(dec!(3)),
+            ),
+            (
+                Transaction::WithdrawCapture(WithdrawCapture::new(8, 17)),
+                TxTypeTag::WithdrawCapture,
+                None,
+            ),
+            (
+                Transaction::WithdrawRelease(WithdrawRelease::new(9, 18)),
+                TxTypeTag::WithdrawRelease,
+                None,
+            ),
+        ];
+        for (index, (tx, kind, amount)) in cases.iter().enumerate() {
+            assert_eq!(tx.client_id(), index as ClientId + 1);
+            assert_eq!(tx.tx_id(), index as TxId + 10);
+            assert_eq!(tx.kind(), *kind);
+            assert_eq!(tx.kind().to_string(), kind.as_str());
+            assert_eq!(tx.amount(), *amount);
+        }
+    }
+
+    #[test]
+    fn equality_and_debug_tell_transactions_apart() {
+        let deposit = Transaction::Deposit(Deposit::new(1, 1, dec!(1)));
+        assert_eq!(deposit, Transaction::Deposit(Deposit::new(1, 1, dec!(1.0))));
+        for other in [
+            Transaction::Deposit(Deposit::new(2, 1, dec!(1))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(1))),
+            Transaction::Deposit(Deposit::new(1, 1, dec!(2))),
+            Transaction::Withdrawal(Withdrawal::new(1, 1, dec!(1))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 1, dec!(1))),
+        ] {
+            assert_ne!(deposit, other);
+        }
+        assert_ne!(
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1))
+        );
+        assert_eq!(
+            format!("{:?}", deposit),
+            "Deposit(Deposit { client_id: 1, tx_id: 1, amount: 1 })"
+        );
+        assert_eq!(
+            format!("{:?}", Transaction::Chargeback(Chargeback::new(4, 7))),
+            "Chargeback(Chargeback { client_id: 4, tx_id: 7, amount: None, reason: None })"
+        );
+    }
+
+    #[test]
+    fn stated_amounts_of_dispute_rows() {
+        let data = "type,client,tx,amount\n\
+                    dispute,1,1,10.5\n\
+                    resolve,1,1,\n\
+                    chargeback,1,1,1.0\n\
+                    adjustment,1,1,3\n";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transactions = reader
+            .deserialize::<TransactionLog>()
+            .map(|log| Transaction::try_from(log.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transactions,
+            [
+                Transaction::Dispute(Dispute::new(1, 1).with_amount(dec!(10.5))),
+                Transaction::Resolve(Resolve::new(1, 1)),
+                Transaction::Chargeback(Chargeback::new(1, 1).with_amount(dec!(1))),
+                Transaction::Adjustment(Adjustment::new(1, 1)),
+            ]
+        );
+        let stated: Vec<_> = transactions
+            .iter()
+            .map(Transaction::stated_amount)
+            .collect();
+        assert_eq!(stated, [Some(dec!(10.5)), None, Some(dec!(1)), None]);
+        assert!(transactions.iter().all(|tx| tx.amount().is_none()));
+        for tx in &transactions[..3] {
+            assert_eq!(
+                Transaction::try_from(TransactionLog::from(tx)).as_ref(),
+                Ok(tx)
+            );
+        }
+    }
+
+    #[test]
+    fn reasons_of_dispute_rows() {
+        let data = format!(
+            "type,client,tx,amount,reason\n\
+             dispute,1,1,,10.4\n\
+             chargeback,1,1,,\n\
+             dispute,1,2,,{}\n",
+            "x".repeat(MAX_REASON_LEN + 1)
+        );
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transactions = reader
+            .deserialize::<TransactionLog>()
+            .map(|log| Transaction::try_from(log.unwrap()))
+            .collect::<Vec<_>>();
+        let fraud: DisputeReason = "10.4".parse().unwrap();
+        assert_eq!(
+            transactions,
+            [
+                Ok(Transaction::Dispute(
+                    Dispute::new(1, 1).with_reason(fraud.clone())
+                )),
+                Ok(Transaction::Chargeback(Chargeback::new(1, 1))),
+                Err(TransactionLogError::ReasonTooLong),
+            ]
+        );
+        let dispute = transactions[0].as_ref().unwrap();
+        assert_eq!(dispute.reason(), Some(&fraud));
+        assert_eq!(
+            Transaction::try_from(TransactionLog::from(dispute)).as_ref(),
+            Ok(dispute)
+        );
+    }
+
+    fn all_log_errors() -> Vec<TransactionLogError> {
+        let all = vec![
+            TransactionLogError::InvalidTxType,
+            TransactionLogError::MissingAmount,
+            TransactionLogError::TooFewFields,
+            TransactionLogError::TooManyFields,
+            TransactionLogError::AmbiguousAmount,
+            TransactionLogError::ReferenceTooLong,
+            TransactionLogError::ReasonTooLong,
+            TransactionLogError::NegativeAmountNotAllowed,
+            TransactionLogError::TrailingCharactersInAmount {
+                raw: String::new(),
+                at_offset: 0,
+            },
+            TransactionLogError::ExcessPrecision,
+        ];
+        // Fails to compile when a variant is added without being listed above
+        for error in &all {
+            match error {
+                TransactionLogError::InvalidTxType
+                | TransactionLogError::MissingAmount
+                | TransactionLogError::TooFewFields
+                | TransactionLogError::TooManyFields
+                | TransactionLogError::AmbiguousAmount
+                | TransactionLogError::ReferenceTooLong
+                | TransactionLogError::ReasonTooLong
+                | TransactionLogError::NegativeAmountNotAllowed
+                | TransactionLogError::TrailingCharactersInAmount { .. }
+                | TransactionLogError::ExcessPrecision => {}
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn log_error_code_round_trip() {
+        assert_eq!(TransactionLogError::InvalidTxType.code(), "invalid_tx_type");
+        assert_eq!(TransactionLogError::MissingAmount.code(), "missing_amount");
+        for error in all_log_errors() {
+            let code = error.to_string();
+            assert_eq!(code, error.code());
+            assert_eq!(
+                code.parse::<TransactionLogError>().unwrap().to_string(),
+                code
+            );
+            assert_eq!(code.parse::<TransactionLogError>(), Ok(error));
+        }
+        assert_eq!(
+            "not_a_code".parse::<TransactionLogError>(),
+            Err(UnknownErrorCode("not_a_code".to_string()))
+        );
+    }
 
-        assert_eq!(invalid_log, Err(TransactionLogError::InvalidTxType));
+    #[test]
+    fn log_error_serde_round_trip() {
+        for error in all_log_errors() {
+            let json = serde_json::to_string(&error).unwrap();
+            assert_eq!(json, format!("\"{}\"", error.code()));
+            assert_eq!(
+                serde_json::from_str::<TransactionLogError>(&json).unwrap(),
+                error
+            );
+        }
     }
 }
@@ -0,0 +1,284 @@
+//! Recent deposits of each client, checked against the `VelocityLimit` of the ledger.
+use crate::accounting::config::VelocityLimit;
+use crate::accounting::{exact_add, Ledger};
+use crate::core_types::ClientId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Deposits in the window of a client, see `Ledger::velocity_windows`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VelocityWindowState {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// Oldest first.
+    pub recent_deposits: Vec<Decimal>,
+}
+
+/// Amounts of the last deposits of each client, at most as many as the window is long.
+#[derive(Default)]
+pub(crate) struct VelocityWindows {
+    windows: HashMap<ClientId, VecDeque<Decimal>>,
+    flagged: u64,
+}
+
+impl VelocityWindows {
+    /// Sum of the window of `client_id` once `amount` is deposited, `None` when it
+    /// exceeds the range of `Decimal`.
+    pub(crate) fn total_with(
+        &self,
+        limit: &VelocityLimit,
+        client_id: ClientId,
+        amount: Decimal,
+    ) -> Option<Decimal> {
+        let earlier = limit.window.len() - 1;
+        self.windows
+            .get(&client_id)
+            .into_iter()
+            .flat_map(|window| window.iter().rev().take(earlier))
+            .try_fold(amount, |total, deposit| exact_add(total, *deposit))
+    }
+
+    pub(crate) fn record(&mut self, limit: &VelocityLimit, client_id: ClientId, amount: Decimal) {
+        let len = limit.window.len();
+        let window = self
+            .windows
+            .entry(client_id)
+            .or_insert_with(|| VecDeque::with_capacity(len));
+        if window.len() == len {
+            window.pop_front();
+        }
+        window.push_back(amount);
+    }
+
+    pub(crate) fn count_flagged(&mut self) {
+        self.flagged += 1;
+    }
+
+    pub(crate) fn flagged(&self) -> u64 {
+        self.flagged
+    }
+
+    #[cfg(feature = "io")]
+    pub(crate) fn restore_flagged(&mut self, flagged: u64) {
+        self.flagged = flagged;
+    }
+
+    pub(crate) fn remove(&mut self, client_id: ClientId) {
+        self.windows.remove(&client_id);
+    }
+
+    pub(crate) fn window(&self, client_id: ClientId) -> Option<&VecDeque<Decimal>> {
+        self.windows.get(&client_id)
+    }
+
+    pub(crate) fn insert_window(&mut self, client_id: ClientId, window: VecDeque<Decimal>) {
+        self.windows.insert(client_id, window);
+    }
+
+    /// Moves the windows and flag count of `other` into these, appending the
+    /// deposits of clients with a window in both after the ones here.
+    pub(crate) fn merge(&mut self, other: VelocityWindows, limit: Option<&VelocityLimit>) {
+        for (client_id, deposits) in other.windows {
+            let window = self.windows.entry(client_id).or_default();
+            window.extend(deposits);
+            truncate(window, limit);
+        }
+        self.flagged += other.flagged;
+    }
+
+    /// Appends the deposits in the window of `from` to the window of `into`, like
+    /// `merge` does for a client of both ledgers.
+    pub(crate) fn move_window(
+        &mut self,
+        from: ClientId,
+        into: ClientId,
+        limit: Option<&VelocityLimit>,
+    ) {
+        if let Some(deposits) = self.windows.remove(&from) {
+            let window = self.windows.entry(into).or_default();
+            window.extend(deposits);
+            truncate(window, limit);
+        }
+    }
+}
+
+/// Drops the oldest deposits beyond the length of the window.
+fn truncate(window: &mut VecDeque<Decimal>, limit: Option<&VelocityLimit>) {
+    if let Some(limit) = limit {
+        let excess = window.len().saturating_sub(limit.window.len());
+        window.drain(..excess);
+    }
+}
+
+impl Ledger {
+    /// Deposits in the velocity window of every client with one, sorted by client id,
+    /// for saving along with the ledger.
+    pub fn velocity_windows(&self) -> Vec<VelocityWindowState> {
+        let mut states: Vec<_> = self
+            .velocity
+            .windows
+            .iter()
+            .map(|(client_id, window)| VelocityWindowState {
+                client_id: *client_id,
+                recent_deposits: window.iter().copied().collect(),
+            })
+            .collect();
+        states.sort_unstable_by_key(|state| state.client_id);
+        states
+    }
+
+    /// Replaces the velocity windows of the clients of `states`, as saved by
+    /// `velocity_windows`, so that a restored ledger counts the deposits made before.
+    /// Deposits beyond the length of the configured window are dropped, oldest first.
+    pub fn restore_velocity_windows(
+        &mut self,
+        states: impl IntoIterator<Item = VelocityWindowState>,
+    ) {
+        let limit = self.config.velocity_limit;
+        for state in states {
+            let mut window = VecDeque::from(state.recent_deposits);
+            truncate(&mut window, limit.as_ref());
+            self.velocity.windows.insert(state.client_id, window);
+        }
+    }
+
+    /// Deposits applied under `VelocityAction::Flag` although they exceeded the limit.
+    pub fn velocity_flagged(&self) -> u64 {
+        self.velocity.flagged()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::config::{VelocityAction, WindowSpec};
+    use crate::accounting::events::LedgerEvent;
+    use crate::accounting::transactions::{Deposit, Transaction};
+    use crate::accounting::TxError;
+    use crate::pipeline::{CsvSource, Pipeline};
+    use rust_decimal_macros::dec;
+    use std::sync::{Arc, Mutex};
+
+    fn limit(max_total: Decimal, window: usize, action: VelocityAction) -> VelocityLimit {
+        VelocityLimit {
+            max_total,
+            window: WindowSpec::Transactions(window),
+            action,
+        }
+    }
+
+    fn deposit(ledger: &mut Ledger, tx_id: u32, amount: Decimal) -> Result<(), TxError> {
+        ledger.execute(&Transaction::Deposit(Deposit::new(1, tx_id, amount)))
+    }
+
+    #[test]
+    fn limit_is_inclusive() {
+        let mut ledger = Ledger::builder()
+            .velocity_limit(limit(dec!(100), 3, VelocityAction::Reject))
+            .build();
+        deposit(&mut ledger, 1, dec!(40)).unwrap();
+        deposit(&mut ledger, 2, dec!(35)).unwrap();
+        assert_eq!(
+            deposit(&mut ledger, 3, dec!(25.0001)),
+            Err(TxError::VelocityLimitExceeded)
+        );
+        deposit(&mut ledger, 4, dec!(25)).unwrap();
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(100));
+
+        // A deposit alone above the limit opens no account
+        assert_eq!(
+            ledger.execute(&Transaction::Deposit(Deposit::new(2, 5, dec!(100.01)))),
+            Err(TxError::VelocityLimitExceeded)
+        );
+        assert!(ledger.account(2).is_none());
+    }
+
+    #[test]
+    fn old_deposits_age_out_of_the_window() {
+        let mut ledger = Ledger::builder()
+            .velocity_limit(limit(dec!(10), 2, VelocityAction::Reject))
+            .build();
+        deposit(&mut ledger, 1, dec!(6)).unwrap();
+        assert_eq!(
+            deposit(&mut ledger, 2, dec!(5)),
+            Err(TxError::VelocityLimitExceeded)
+        );
+        deposit(&mut ledger, 3, dec!(4)).unwrap();
+        // The deposit of 6 left the window, rejected deposits never entered it
+        deposit(&mut ledger, 4, dec!(6)).unwrap();
+        assert_eq!(
+            ledger.velocity_windows(),
+            [VelocityWindowState {
+                client_id: 1,
+                recent_deposits: vec![dec!(4), dec!(6)],
+            }]
+        );
+    }
+
+    #[test]
+    fn flag_applies_the_deposit_and_reports_it() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener_events = Arc::clone(&events);
+        let mut ledger = Ledger::builder()
+            .velocity_limit(limit(dec!(10), 2, VelocityAction::Flag))
+            .event_listener(move |event| {
+                listener_events.lock().unwrap().push(event.clone());
+                Ok(())
+            })
+            .build();
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,6\n\
+                    deposit,1,2,5\n\
+                    deposit,1,3,6\n\
+                    deposit,1,4,1\n";
+        let stats = Pipeline::new(CsvSource::new(data.as_bytes()))
+            .run(&mut ledger)
+            .unwrap();
+        assert_eq!((stats.rows_executed, stats.velocity_flagged), (4, 2));
+        assert_eq!(ledger.velocity_flagged(), 2);
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(18));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                LedgerEvent::VelocityFlagged {
+                    client_id: 1,
+                    tx_id: 2,
+                    window_total: dec!(11),
+                },
+                LedgerEvent::VelocityFlagged {
+                    client_id: 1,
+                    tx_id: 3,
+                    window_total: dec!(11),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn restored_windows_are_enforced() {
+        let velocity_limit = limit(dec!(10), 3, VelocityAction::Reject);
+        let mut ledger = Ledger::builder().velocity_limit(velocity_limit).build();
+        deposit(&mut ledger, 1, dec!(3)).unwrap();
+        deposit(&mut ledger, 2, dec!(4)).unwrap();
+        let saved = serde_json::to_string(&ledger.velocity_windows()).unwrap();
+
+        let mut restored = Ledger::builder().velocity_limit(velocity_limit).build();
+        restored.restore_velocity_windows(
+            serde_json::from_str::<Vec<VelocityWindowState>>(&saved).unwrap(),
+        );
+        assert_eq!(restored.velocity_windows(), ledger.velocity_windows());
+        assert_eq!(
+            deposit(&mut restored, 3, dec!(3.5)),
+            Err(TxError::VelocityLimitExceeded)
+        );
+        deposit(&mut restored, 4, dec!(3)).unwrap();
+
+        // Windows saved under a longer window keep only the newest deposits
+        let mut shorter = Ledger::builder()
+            .velocity_limit(limit(dec!(10), 1, VelocityAction::Reject))
+            .build();
+        shorter.restore_velocity_windows(restored.velocity_windows());
+        assert_eq!(shorter.velocity_windows()[0].recent_deposits, vec![dec!(3)]);
+    }
+}
@@ -0,0 +1,222 @@
+//! Read-only access to a ledger for the code writing reports and exports, which
+//! cannot reach anything changing the ledger through it.
+use crate::accounting::storage::MemoryStats;
+use crate::accounting::{
+    AccountLog, AccountView, ExtendedAccountLog, Ledger, OpenDispute, OpenWithdrawalHold, TxRecord,
+    TxState, UnsettledWithdrawal, UserAccount,
+};
+use crate::amount_scale::render;
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Query surface of a `Ledger`, made by `Ledger::view` or `SharedLedger::with_view`.
+///
+/// The report and export functions take `impl Into<LedgerView>`, so that they can be
+/// given a `&Ledger` as before.
+#[derive(Clone, Copy)]
+pub struct LedgerView<'a> {
+    ledger: &'a Ledger,
+}
+
+/// Counts and balances of the whole ledger, see `LedgerView::summary`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct LedgerSummary {
+    pub tx_seq: u64,
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    pub open_disputes: u64,
+    pub liabilities: Decimal,
+    pub total_available: Decimal,
+    pub total_held: Decimal,
+}
+
+impl<'a> LedgerView<'a> {
+    /// Accounts in no particular order.
+    pub fn accounts_iter(&self) -> impl Iterator<Item = (&'a ClientId, &'a UserAccount)> + 'a {
+        self.ledger.accounts_iter()
+    }
+
+    /// Accounts sorted by client id.
+    pub fn accounts_sorted(&self) -> Vec<&'a UserAccount> {
+        let mut accounts: Vec<_> = self
+            .accounts_iter()
+            .map(|(_client_id, user_account)| user_account)
+            .collect();
+        accounts.sort_unstable_by_key(|user_account| user_account.client_id());
+        accounts
+    }
+
+    /// See `Ledger::account_logs`.
+    pub fn account_logs(&self) -> impl Iterator<Item = AccountLog> + 'a {
+        let view = *self;
+        self.accounts_sorted()
+            .into_iter()
+            .map(move |user_account| view.account_log(user_account))
+    }
+
+    /// See `LedgerBuilder::amount_scale`.
+    pub fn amount_scale(&self) -> Option<u32> {
+        self.ledger.config.amount_scale
+    }
+
+    /// Report row of `user_account`, with the amounts written at `amount_scale`.
+    pub fn account_log(&self, user_account: &UserAccount) -> AccountLog {
+        let log = AccountLog::from(user_account);
+        match self.amount_scale() {
+            Some(scale) => AccountLog {
+                available: render(log.available, scale),
+                held: render(log.held, scale),
+                total: render(log.total, scale),
+                ..log
+            },
+            None => log,
+        }
+    }
+
+    /// Extended report row of `user_account`, with the amounts written at
+    /// `amount_scale`.
+    pub fn extended_account_log(&self, user_account: &UserAccount) -> ExtendedAccountLog {
+        let log = ExtendedAccountLog::from(user_account);
+        match self.amount_scale() {
+            Some(scale) => ExtendedAccountLog {
+                available: render(log.available, scale),
+                held: render(log.held, scale),
+                total: render(log.total, scale),
+                overdraft_limit: render(log.overdraft_limit, scale),
+                deposited: render(log.deposited, scale),
+                withdrawn: render(log.withdrawn, scale),
+                charged_back: render(log.charged_back, scale),
+                ..log
+            },
+            None => log,
+        }
+    }
+
+    /// `amount` as the account reports write it, see `amount_scale`.
+    pub fn render_amount(&self, amount: Decimal) -> Decimal {
+        self.amount_scale()
+            .map_or(amount, |scale| render(amount, scale))
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<&'a UserAccount> {
+        self.ledger.account(client_id)
+    }
+
+    /// Accounts whose available balance is below `threshold`, in no particular order.
+    pub fn accounts_below(&self, threshold: Decimal) -> impl Iterator<Item = AccountView> + 'a {
+        self.ledger.accounts_below(threshold)
+    }
+
+    /// Current state of the recorded transaction `tx_id`.
+    pub fn transaction_state(&self, tx_id: TxId) -> Option<TxState> {
+        self.ledger.tx_states.get(&tx_id).map(TxRecord::state)
+    }
+
+    /// Recorded transactions with their current state, in no particular order.
+    pub fn tx_records_iter(&self) -> impl Iterator<Item = &'a TxRecord> + 'a {
+        self.ledger.tx_records_iter()
+    }
+
+    /// See `Ledger::open_disputes`.
+    pub fn open_disputes(&self) -> impl Iterator<Item = OpenDispute> + 'a {
+        self.ledger.open_disputes()
+    }
+
+    /// See `Ledger::open_withdrawal_holds`.
+    pub fn open_withdrawal_holds(&self) -> impl Iterator<Item = OpenWithdrawalHold> + 'a {
+        self.ledger.open_withdrawal_holds()
+    }
+
+    /// See `Ledger::unsettled_withdrawals`.
+    pub fn unsettled_withdrawals(&self) -> Vec<UnsettledWithdrawal<'a>> {
+        self.ledger.unsettled_withdrawals()
+    }
+
+    /// Liabilities maintained by the transactions, the negated sum of the balances of
+    /// every account and the swept dust.
+    pub fn liabilities(&self) -> Decimal {
+        self.ledger.liabilities.balance
+    }
+
+    pub fn summary(&self) -> LedgerSummary {
+        let mut summary = LedgerSummary {
+            tx_seq: self.ledger.tx_seq(),
+            accounts: 0,
+            locked_accounts: 0,
+            open_disputes: 0,
+            liabilities: self.liabilities(),
+            total_available: self.ledger.total_available(),
+            total_held: self.ledger.total_held(),
+        };
+        for (_client_id, user_account) in self.accounts_iter() {
+            summary.accounts += 1;
+            summary.locked_accounts += u64::from(user_account.locked());
+        }
+        summary.open_disputes = self
+            .ledger
+            .open_disputes
+            .values()
+            .map(|tx_ids| tx_ids.len() as u64)
+            .sum();
+        summary
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.ledger.memory_stats()
+    }
+}
+
+impl<'a> From<&'a Ledger> for LedgerView<'a> {
+    fn from(ledger: &'a Ledger) -> Self {
+        ledger.view()
+    }
+}
+
+impl Ledger {
+    pub fn view(&self) -> LedgerView<'_> {
+        LedgerView { ledger: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Dispute, Transaction, Withdrawal};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn summary_counts_the_ledger() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(2, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(2))),
+            Transaction::Dispute(Dispute::new(2, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let view = ledger.view();
+        assert_eq!(
+            view.summary(),
+            LedgerSummary {
+                tx_seq: 4,
+                accounts: 2,
+                locked_accounts: 0,
+                open_disputes: 1,
+                liabilities: dec!(-13),
+                total_available: dec!(3),
+                total_held: dec!(10),
+            }
+        );
+        let client_ids: Vec<_> = view
+            .accounts_sorted()
+            .iter()
+            .map(|user_account| user_account.client_id())
+            .collect();
+        assert_eq!(client_ids, [1, 2]);
+        assert_eq!(view.transaction_state(1), Some(TxState::Disputed));
+        assert_eq!(view.transaction_state(2), Some(TxState::Resolved));
+        assert_eq!(view.transaction_state(4), None);
+    }
+}
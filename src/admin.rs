@@ -0,0 +1,214 @@
+//! HTTP admin listener for a running server, answering health checks, Prometheus
+//! scrapes and account lookups.
+//!
+//! Routes, all `GET`:
+//! - `/healthz`: 200 while the server accepts transactions, 503 otherwise.
+//! - `/metrics`: the `ServiceStats` counters and account counts in the Prometheus
+//!   text format.
+//! - `/account/<client_id>`: the `AccountView` as JSON, 404 for clients without an
+//!   account and 400 for ids which aren't a client id.
+//! - `/disputes`: the open disputes as a JSON array, sorted by client and transaction.
+//!
+//! Every request reads the ledger through a `LedgerView` of the `SharedLedger`,
+//! holding its lock only for the copy, so scrapes don't hold up the transactions
+//! being executed.
+use crate::accounting::shared::SharedLedger;
+use crate::accounting::transactions::TxTypeTag;
+use crate::accounting::view::{LedgerSummary, LedgerView};
+use crate::accounting::{AccountView, OpenDispute};
+use crate::core_types::ClientId;
+use crate::server::ServiceStats;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+const JSON: &str = "application/json";
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4";
+const TEXT: &str = "text/plain";
+
+/// Listener started by `spawn_admin`. Dropping the handle leaves it running.
+#[derive(Debug)]
+pub struct AdminHandle {
+    local_addr: SocketAddr,
+    task: JoinHandle<io::Error>,
+}
+
+impl AdminHandle {
+    /// The address the listener is bound to, with the actual port when bound to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting connections. Connections already accepted are served to the end.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    /// Waits for the listener to stop, returning the error which stopped it or `None`
+    /// when it was aborted.
+    pub async fn join(self) -> Option<io::Error> {
+        self.task.await.ok()
+    }
+}
+
+/// Binds `addr` and serves the admin routes for `ledger` and `stats` on a spawned task,
+/// until accepting a connection fails.
+pub async fn spawn_admin(
+    addr: SocketAddr,
+    ledger: SharedLedger,
+    stats: Arc<ServiceStats>,
+) -> io::Result<AdminHandle> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let task = tokio::spawn(async move {
+        loop {
+            let (stream, _peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => break err,
+            };
+            let ledger = ledger.clone();
+            let stats = stats.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let response = route(&request, &ledger, &stats);
+                    async move { Ok::<_, Infallible>(response) }
+                });
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    eprintln!("Dropped admin connection: {}", err);
+                }
+            });
+        }
+    });
+    Ok(AdminHandle { local_addr, task })
+}
+
+fn route(
+    request: &Request<Incoming>,
+    ledger: &SharedLedger,
+    stats: &ServiceStats,
+) -> Response<Full<Bytes>> {
+    if request.method() != Method::GET {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, TEXT, "method not allowed\n");
+    }
+    let path = request.uri().path();
+    match path {
+        "/healthz" if stats.is_accepting() => respond(StatusCode::OK, TEXT, "ok\n"),
+        "/healthz" => respond(StatusCode::SERVICE_UNAVAILABLE, TEXT, "not accepting\n"),
+        "/metrics" => {
+            let summary = ledger.with_view(|view| view.summary());
+            respond(StatusCode::OK, PROMETHEUS_TEXT, metrics(&summary, stats))
+        }
+        "/disputes" => json(&ledger.with_view(sorted_disputes)),
+        _ => match path.strip_prefix("/account/") {
+            Some(client_id) => match client_id.parse::<ClientId>() {
+                Ok(client_id) => match ledger.with_view(|view| account(view, client_id)) {
+                    Some(view) => json(&view),
+                    None => respond(StatusCode::NOT_FOUND, TEXT, "no such account\n"),
+                },
+                Err(_) => respond(StatusCode::BAD_REQUEST, TEXT, "invalid client id\n"),
+            },
+            None => respond(StatusCode::NOT_FOUND, TEXT, "not found\n"),
+        },
+    }
+}
+
+fn account(view: LedgerView<'_>, client_id: ClientId) -> Option<AccountView> {
+    view.account(client_id).map(AccountView::from)
+}
+
+fn sorted_disputes(view: LedgerView<'_>) -> Vec<OpenDispute> {
+    let mut disputes: Vec<_> = view.open_disputes().collect();
+    disputes.sort_unstable_by_key(|dispute| (dispute.client_id, dispute.tx_id));
+    disputes
+}
+
+/// The counters in the Prometheus text exposition format, with one sample per
+/// transaction kind so that kinds which never occurred still show as 0.
+fn metrics(summary: &LedgerSummary, stats: &ServiceStats) -> String {
+    let mut text = String::new();
+    for (name, help, count) in [
+        (
+            "payments_engine_transactions_applied_total",
+            "Transactions applied to the ledger.",
+            ServiceStats::applied as fn(&ServiceStats, TxTypeTag) -> u64,
+        ),
+        (
+            "payments_engine_transactions_rejected_total",
+            "Transactions rejected by the ledger.",
+            ServiceStats::rejected,
+        ),
+    ] {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} counter", name);
+        for kind in TxTypeTag::ALL {
+            let _ = writeln!(
+                text,
+                "{}{{kind=\"{}\"}} {}",
+                name,
+                kind.as_str(),
+                count(stats, kind)
+            );
+        }
+    }
+    for (name, help, value) in [
+        (
+            "payments_engine_accounts",
+            "Accounts in the ledger.",
+            summary.accounts,
+        ),
+        (
+            "payments_engine_accounts_locked",
+            "Accounts locked by a chargeback.",
+            summary.locked_accounts,
+        ),
+        (
+            "payments_engine_queue_depth",
+            "Transactions received and waiting for the ledger.",
+            stats.queue_depth(),
+        ),
+    ] {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} gauge", name);
+        let _ = writeln!(text, "{} {}", name, value);
+    }
+    text
+}
+
+fn json(value: &impl Serialize) -> Response<Full<Bytes>> {
+    match serde_json::to_vec(value) {
+        Ok(body) => respond(StatusCode::OK, JSON, body),
+        Err(err) => respond(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            TEXT,
+            format!("cannot serialize response: {}\n", err),
+        ),
+    }
+}
+
+fn respond(
+    status: StatusCode,
+    content_type: &'static str,
+    body: impl Into<Bytes>,
+) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
@@ -0,0 +1,666 @@
+//! Manual interventions of operators, such as unlocking client 7, raising the
+//! overdraft limit of client 9 or merging client 12 into 13, applied in bulk from a
+//! CSV file with `op,client,arg1,arg2` columns:
+//!
+//! - `unlock,<client>` unlocks the account, see `Ledger::unlock`.
+//! - `close,<client>` locks the account, so it takes no further withdrawals, see
+//!   `Ledger::lock`.
+//! - `set_overdraft_limit,<client>,<limit>` sets the overdraft limit, see
+//!   `Ledger::set_overdraft_limit`.
+//! - `set_dispute_limit,<client>,<limit>` sets the dispute limit to an amount or a
+//!   percentage such as `25%`, see `Ledger::set_dispute_limit`.
+//! - `merge_accounts,<client>,<into>` merges the account into the one of `into`, see
+//!   `Ledger::merge_accounts`.
+//! - `remove_account,<client>` removes the account, see `Ledger::remove_account`.
+//!
+//! Arguments an op does not take must be empty. `arg2` is taken by no op yet.
+//!
+//! Every row is validated before any op is applied, so that under
+//! `AdminOpsMode::StopOnError` a file with a malformed row changes nothing.
+use crate::accounting::config::DisputeLimit;
+use crate::accounting::{Ledger, MergeError, RemoveError, TxError};
+use crate::core_types::ClientId;
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::str::FromStr;
+
+/// Columns of an admin ops file, in order.
+pub const ADMIN_OPS_HEADERS: [&str; 4] = ["op", "client", "arg1", "arg2"];
+
+/// Names of the ops in the `op` column.
+pub const ADMIN_OP_NAMES: [&str; 6] = [
+    "unlock",
+    "close",
+    "set_overdraft_limit",
+    "set_dispute_limit",
+    "merge_accounts",
+    "remove_account",
+];
+
+/// Row of an admin ops file, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminOp {
+    Unlock(ClientId),
+    Close(ClientId),
+    SetOverdraftLimit {
+        client_id: ClientId,
+        limit: Decimal,
+    },
+    SetDisputeLimit {
+        client_id: ClientId,
+        limit: DisputeLimit,
+    },
+    MergeAccounts {
+        from: ClientId,
+        into: ClientId,
+    },
+    RemoveAccount(ClientId),
+}
+
+impl AdminOp {
+    /// Parses the fields of a row after the header.
+    pub fn parse(record: &StringRecord) -> Result<Self, AdminOpError> {
+        let field = |index| record.get(index).filter(|field| !field.is_empty());
+        let op = match field(0).ok_or(AdminOpError::MissingArgument("op"))? {
+            "unlock" => AdminOp::Unlock(argument("client", field(1))?),
+            "close" => AdminOp::Close(argument("client", field(1))?),
+            "set_overdraft_limit" => AdminOp::SetOverdraftLimit {
+                client_id: argument("client", field(1))?,
+                limit: argument("limit", field(2))?,
+            },
+            "set_dispute_limit" => AdminOp::SetDisputeLimit {
+                client_id: argument("client", field(1))?,
+                limit: argument("limit", field(2))?,
+            },
+            "merge_accounts" => AdminOp::MergeAccounts {
+                from: argument("client", field(1))?,
+                into: argument("into", field(2))?,
+            },
+            "remove_account" => AdminOp::RemoveAccount(argument("client", field(1))?),
+            op => return Err(AdminOpError::UnknownOp(op.to_string())),
+        };
+        let taken = 2 + op.argument_count();
+        match record.iter().skip(taken).find(|field| !field.is_empty()) {
+            Some(unexpected) => Err(AdminOpError::UnexpectedArgument(unexpected.to_string())),
+            None => Ok(op),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminOp::Unlock(_) => "unlock",
+            AdminOp::Close(_) => "close",
+            AdminOp::SetOverdraftLimit { .. } => "set_overdraft_limit",
+            AdminOp::SetDisputeLimit { .. } => "set_dispute_limit",
+            AdminOp::MergeAccounts { .. } => "merge_accounts",
+            AdminOp::RemoveAccount(_) => "remove_account",
+        }
+    }
+
+    /// Number of arguments taken after the client.
+    fn argument_count(&self) -> usize {
+        match self {
+            AdminOp::Unlock(_) | AdminOp::Close(_) | AdminOp::RemoveAccount(_) => 0,
+            AdminOp::SetOverdraftLimit { .. }
+            | AdminOp::SetDisputeLimit { .. }
+            | AdminOp::MergeAccounts { .. } => 1,
+        }
+    }
+
+    pub fn apply(&self, ledger: &mut Ledger) -> Result<(), AdminOpError> {
+        match *self {
+            AdminOp::Unlock(client_id) => ledger.unlock(client_id)?,
+            AdminOp::Close(client_id) => ledger.lock(client_id)?,
+            AdminOp::SetOverdraftLimit { client_id, limit } => {
+                ledger.set_overdraft_limit(client_id, limit)
+            }
+            AdminOp::SetDisputeLimit { client_id, limit } => {
+                ledger.set_dispute_limit(client_id, limit)
+            }
+            AdminOp::MergeAccounts { from, into } => ledger.merge_accounts(from, into)?,
+            AdminOp::RemoveAccount(client_id) => {
+                ledger.remove_account(client_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn argument<T: FromStr>(name: &'static str, value: Option<&str>) -> Result<T, AdminOpError> {
+    let value = value.ok_or(AdminOpError::MissingArgument(name))?;
+    value.parse().map_err(|_err| AdminOpError::InvalidArgument {
+        name,
+        value: value.to_string(),
+    })
+}
+
+/// Reason a row of an admin ops file was rejected, either by validation or by the
+/// ledger.
+#[derive(Debug, PartialEq)]
+pub enum AdminOpError {
+    /// The `op` column names none of `ADMIN_OP_NAMES`.
+    UnknownOp(String),
+    /// The op or an argument it takes is empty.
+    MissingArgument(&'static str),
+    InvalidArgument {
+        name: &'static str,
+        value: String,
+    },
+    /// A column after the arguments of the op is not empty.
+    UnexpectedArgument(String),
+    Rejected(TxError),
+    MergeRejected(MergeError),
+    RemovalRejected(RemoveError),
+}
+
+impl AdminOpError {
+    /// Stable snake_case code identifying the error in reports, the code of the
+    /// `TxError` for ops the ledger rejected with one.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AdminOpError::UnknownOp(_) => "unknown_admin_op",
+            AdminOpError::MissingArgument(_) => "missing_argument",
+            AdminOpError::InvalidArgument { .. } => "invalid_argument",
+            AdminOpError::UnexpectedArgument(_) => "unexpected_argument",
+            AdminOpError::Rejected(err) => err.code(),
+            AdminOpError::MergeRejected(_) => "merge_rejected",
+            AdminOpError::RemovalRejected(_) => "removal_rejected",
+        }
+    }
+
+    /// Whether the row was rejected by validation, before reaching the ledger.
+    pub fn is_invalid_row(&self) -> bool {
+        !matches!(
+            self,
+            AdminOpError::Rejected(_)
+                | AdminOpError::MergeRejected(_)
+                | AdminOpError::RemovalRejected(_)
+        )
+    }
+}
+
+impl Display for AdminOpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminOpError::UnknownOp(op) => write!(
+                f,
+                "unknown admin op {:?}, expected one of {}",
+                op,
+                ADMIN_OP_NAMES.join(", ")
+            ),
+            AdminOpError::MissingArgument(name) => write!(f, "missing {}", name),
+            AdminOpError::InvalidArgument { name, value } => {
+                write!(f, "invalid {} {:?}", name, value)
+            }
+            AdminOpError::UnexpectedArgument(value) => {
+                write!(f, "unexpected argument {:?}", value)
+            }
+            AdminOpError::Rejected(err) => write!(f, "{}", err),
+            AdminOpError::MergeRejected(err) => write!(f, "{}", err),
+            AdminOpError::RemovalRejected(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AdminOpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AdminOpError::Rejected(err) => Some(err),
+            AdminOpError::MergeRejected(err) => Some(err),
+            AdminOpError::RemovalRejected(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TxError> for AdminOpError {
+    fn from(err: TxError) -> Self {
+        AdminOpError::Rejected(err)
+    }
+}
+
+impl From<MergeError> for AdminOpError {
+    fn from(err: MergeError) -> Self {
+        AdminOpError::MergeRejected(err)
+    }
+}
+
+impl From<RemoveError> for AdminOpError {
+    fn from(err: RemoveError) -> Self {
+        AdminOpError::RemovalRejected(err)
+    }
+}
+
+/// Failure to read an admin ops file as a whole, before any op was applied.
+#[derive(Debug)]
+pub enum AdminOpsError {
+    Csv(csv::Error),
+    /// The header is not `ADMIN_OPS_HEADERS`.
+    UnexpectedHeader(Vec<String>),
+}
+
+impl AdminOpsError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AdminOpsError::Csv(_) => "csv",
+            AdminOpsError::UnexpectedHeader(_) => "unexpected_header",
+        }
+    }
+}
+
+impl Display for AdminOpsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminOpsError::Csv(err) => write!(f, "failed to read admin ops: {}", err),
+            AdminOpsError::UnexpectedHeader(found) => write!(
+                f,
+                "unexpected admin ops header {:?}, expected {:?}",
+                found, ADMIN_OPS_HEADERS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AdminOpsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AdminOpsError::Csv(err) => Some(err),
+            AdminOpsError::UnexpectedHeader(_) => None,
+        }
+    }
+}
+
+impl From<csv::Error> for AdminOpsError {
+    fn from(err: csv::Error) -> Self {
+        AdminOpsError::Csv(err)
+    }
+}
+
+/// Treatment of rows after an invalid or failing one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AdminOpsMode {
+    /// Applies no op when a row is invalid, and stops at the first op the ledger
+    /// rejects.
+    #[default]
+    StopOnError,
+    /// Applies every valid op, whatever happened to the rows before.
+    ContinueOnError,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AdminOpStatus {
+    Applied,
+    Failed(AdminOpError),
+    /// Not applied because of an earlier error under `AdminOpsMode::StopOnError`.
+    NotRun,
+}
+
+/// What happened to a row of an admin ops file.
+#[derive(Debug, PartialEq)]
+pub struct AdminOpOutcome {
+    /// 1-based line number of the row within the file.
+    pub line: u64,
+    /// The `op` column as given.
+    pub op: String,
+    pub status: AdminOpStatus,
+}
+
+/// Outcome of every row of an admin ops file, in file order.
+#[derive(Debug, Default, PartialEq)]
+pub struct AdminOpsReport {
+    pub outcomes: Vec<AdminOpOutcome>,
+}
+
+impl AdminOpsReport {
+    pub fn applied(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.status == AdminOpStatus::Applied)
+            .count()
+    }
+
+    /// Rows which failed with their line numbers, in file order.
+    pub fn failures(&self) -> impl Iterator<Item = (u64, &AdminOpError)> + '_ {
+        self.outcomes
+            .iter()
+            .filter_map(|outcome| match &outcome.status {
+                AdminOpStatus::Failed(err) => Some((outcome.line, err)),
+                _ => None,
+            })
+    }
+
+    pub fn not_run(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.status == AdminOpStatus::NotRun)
+            .count()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.outcomes.len() == self.applied()
+    }
+}
+
+/// Applies the ops of an admin ops file to `ledger` in file order, see the module
+/// documentation. Fails only when the file cannot be read or has an unexpected
+/// header, before any op was applied.
+pub fn apply_admin_ops(
+    ledger: &mut Ledger,
+    reader: impl Read,
+    mode: AdminOpsMode,
+) -> Result<AdminOpsReport, AdminOpsError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = reader.headers()?;
+    if !headers.iter().eq(ADMIN_OPS_HEADERS) {
+        return Err(AdminOpsError::UnexpectedHeader(
+            headers.iter().map(str::to_string).collect(),
+        ));
+    }
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, csv::Position::line);
+        let op = record.get(0).unwrap_or_default().to_string();
+        rows.push((line, op, AdminOp::parse(&record)));
+    }
+
+    let mut stopped = mode == AdminOpsMode::StopOnError
+        && rows.iter().any(|(_line, _op, parsed)| parsed.is_err());
+    let outcomes = rows
+        .into_iter()
+        .map(|(line, op, parsed)| {
+            let status = match parsed {
+                Err(err) => AdminOpStatus::Failed(err),
+                Ok(_op) if stopped => AdminOpStatus::NotRun,
+                Ok(parsed) => match parsed.apply(ledger) {
+                    Ok(()) => AdminOpStatus::Applied,
+                    Err(err) => {
+                        stopped = mode == AdminOpsMode::StopOnError;
+                        AdminOpStatus::Failed(err)
+                    }
+                },
+            };
+            AdminOpOutcome { line, op, status }
+        })
+        .collect();
+    Ok(AdminOpsReport { outcomes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Dispute, Transaction};
+    use rust_decimal_macros::dec;
+
+    fn parse(row: &str) -> Result<AdminOp, AdminOpError> {
+        let mut record = StringRecord::new();
+        crate::engine::read_line_record(row.as_bytes(), &mut record).unwrap();
+        AdminOp::parse(&record)
+    }
+
+    fn ledger() -> Ledger {
+        let mut ledger = Ledger::new();
+        for (client_id, tx_id) in [(1, 1), (2, 2), (3, 3)] {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    tx_id,
+                    dec!(10),
+                )))
+                .unwrap();
+        }
+        ledger
+            .execute(&Transaction::Dispute(Dispute::new(3, 3)))
+            .unwrap();
+        ledger
+    }
+
+    /// Every op, where removing client 3 fails on its open dispute and unlocking
+    /// client 9 on its missing account.
+    const MIXED: &str = "op,client,arg1,arg2\n\
+                         close,1,,\n\
+                         set_overdraft_limit,2,-5,\n\
+                         remove_account,3,,\n\
+                         set_dispute_limit,2,25%,\n\
+                         unlock,9,,\n\
+                         merge_accounts,2,4,\n\
+                         unlock,1,,\n";
+
+    fn statuses(report: &AdminOpsReport) -> Vec<(u64, &str)> {
+        report
+            .outcomes
+            .iter()
+            .map(|outcome| {
+                let status = match &outcome.status {
+                    AdminOpStatus::Applied => "applied",
+                    AdminOpStatus::Failed(err) => err.code(),
+                    AdminOpStatus::NotRun => "not_run",
+                };
+                (outcome.line, status)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rows_parse_into_ops() {
+        assert_eq!(parse("unlock,7,,"), Ok(AdminOp::Unlock(7)));
+        assert_eq!(parse("close, 7"), Ok(AdminOp::Close(7)));
+        assert_eq!(
+            parse("set_overdraft_limit,9,-100.5,"),
+            Ok(AdminOp::SetOverdraftLimit {
+                client_id: 9,
+                limit: dec!(-100.5)
+            })
+        );
+        assert_eq!(
+            parse("set_dispute_limit,9,25%,"),
+            Ok(AdminOp::SetDisputeLimit {
+                client_id: 9,
+                limit: DisputeLimit::PercentOfTotal(dec!(25))
+            })
+        );
+        assert_eq!(
+            parse("merge_accounts,12,13,"),
+            Ok(AdminOp::MergeAccounts { from: 12, into: 13 })
+        );
+        assert_eq!(parse("remove_account,3,,"), Ok(AdminOp::RemoveAccount(3)));
+        for name in ADMIN_OP_NAMES {
+            let op = parse(&format!("{},1,1,", name))
+                .or_else(|_err| parse(&format!("{},1,,", name)))
+                .unwrap();
+            assert_eq!(op.name(), name);
+        }
+    }
+
+    #[test]
+    fn malformed_rows_are_rejected_precisely() {
+        assert_eq!(
+            parse("unlcok,7,,"),
+            Err(AdminOpError::UnknownOp("unlcok".to_string()))
+        );
+        assert_eq!(parse(",7,,"), Err(AdminOpError::MissingArgument("op")));
+        assert_eq!(
+            parse("unlock,,,"),
+            Err(AdminOpError::MissingArgument("client"))
+        );
+        assert_eq!(
+            parse("unlock,70000,,"),
+            Err(AdminOpError::InvalidArgument {
+                name: "client",
+                value: "70000".to_string()
+            })
+        );
+        assert_eq!(
+            parse("set_overdraft_limit,9,,"),
+            Err(AdminOpError::MissingArgument("limit"))
+        );
+        assert_eq!(
+            parse("set_dispute_limit,9,lots,"),
+            Err(AdminOpError::InvalidArgument {
+                name: "limit",
+                value: "lots".to_string()
+            })
+        );
+        assert_eq!(
+            parse("merge_accounts,12,,"),
+            Err(AdminOpError::MissingArgument("into"))
+        );
+        assert_eq!(
+            parse("unlock,7,now,"),
+            Err(AdminOpError::UnexpectedArgument("now".to_string()))
+        );
+        assert_eq!(
+            parse("merge_accounts,12,13,14"),
+            Err(AdminOpError::UnexpectedArgument("14".to_string()))
+        );
+        assert_eq!(
+            parse("close,7,,,extra"),
+            Err(AdminOpError::UnexpectedArgument("extra".to_string()))
+        );
+    }
+
+    #[test]
+    fn stops_at_the_first_failing_op() {
+        let mut ledger = ledger();
+        let report =
+            apply_admin_ops(&mut ledger, MIXED.as_bytes(), AdminOpsMode::StopOnError).unwrap();
+        assert_eq!(
+            statuses(&report),
+            [
+                (2, "applied"),
+                (3, "applied"),
+                (4, "removal_rejected"),
+                (5, "not_run"),
+                (6, "not_run"),
+                (7, "not_run"),
+                (8, "not_run"),
+            ]
+        );
+        assert_eq!((report.applied(), report.not_run()), (2, 4));
+        assert!(!report.is_clean());
+        assert!(ledger.account(1).unwrap().locked());
+        assert_eq!(ledger.account(2).unwrap().overdraft_limit(), dec!(-5));
+        assert!(ledger.account(3).is_some());
+        assert!(ledger.account(4).is_none());
+    }
+
+    #[test]
+    fn continues_past_failing_ops() {
+        let mut ledger = ledger();
+        let report =
+            apply_admin_ops(&mut ledger, MIXED.as_bytes(), AdminOpsMode::ContinueOnError).unwrap();
+        assert_eq!(
+            statuses(&report),
+            [
+                (2, "applied"),
+                (3, "applied"),
+                (4, "removal_rejected"),
+                (5, "applied"),
+                (6, "client_account_not_found"),
+                (7, "applied"),
+                (8, "applied"),
+            ]
+        );
+        assert_eq!(
+            report
+                .failures()
+                .map(|(line, err)| (line, err.to_string()))
+                .collect::<Vec<_>>(),
+            [
+                (4, "deposit 3 of the account is still held".to_string()),
+                (6, TxError::ClientAccountNotFound.to_string()),
+            ]
+        );
+        assert!(!ledger.account(1).unwrap().locked());
+        assert!(ledger.account(2).is_none());
+        assert_eq!(ledger.account(4).unwrap().total(), dec!(10));
+        assert_eq!(
+            ledger.config().dispute_limits.get(&2),
+            Some(&DisputeLimit::PercentOfTotal(dec!(25)))
+        );
+    }
+
+    #[test]
+    fn invalid_rows_stop_the_file_before_any_op() {
+        let ops = "op,client,arg1,arg2\n\
+                   close,1,,\n\
+                   freeze,2,,\n\
+                   set_overdraft_limit,2,lots,\n";
+        let mut ledger = ledger();
+        let report =
+            apply_admin_ops(&mut ledger, ops.as_bytes(), AdminOpsMode::StopOnError).unwrap();
+        assert_eq!(
+            statuses(&report),
+            [
+                (2, "not_run"),
+                (3, "unknown_admin_op"),
+                (4, "invalid_argument")
+            ]
+        );
+        assert!(!ledger.account(1).unwrap().locked());
+
+        let report =
+            apply_admin_ops(&mut ledger, ops.as_bytes(), AdminOpsMode::ContinueOnError).unwrap();
+        assert_eq!(
+            statuses(&report),
+            [
+                (2, "applied"),
+                (3, "unknown_admin_op"),
+                (4, "invalid_argument")
+            ]
+        );
+        assert!(ledger.account(1).unwrap().locked());
+    }
+
+    #[test]
+    fn unexpected_headers_fail_the_file() {
+        let err = apply_admin_ops(
+            &mut Ledger::new(),
+            "op,client,limit\nunlock,1,\n".as_bytes(),
+            AdminOpsMode::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), "unexpected_header");
+        assert_eq!(
+            err.to_string(),
+            "unexpected admin ops header [\"op\", \"client\", \"limit\"], expected \
+             [\"op\", \"client\", \"arg1\", \"arg2\"]"
+        );
+    }
+
+    #[test]
+    fn every_error_has_a_code() {
+        let errors = [
+            AdminOpError::UnknownOp("freeze".to_string()),
+            AdminOpError::MissingArgument("client"),
+            AdminOpError::InvalidArgument {
+                name: "limit",
+                value: "lots".to_string(),
+            },
+            AdminOpError::UnexpectedArgument("14".to_string()),
+            AdminOpError::Rejected(TxError::ClientAccountNotFound),
+            AdminOpError::MergeRejected(MergeError::CyclicRemap(1)),
+            AdminOpError::RemovalRejected(RemoveError::OpenDispute(3)),
+        ];
+        let codes: Vec<_> = errors.iter().map(AdminOpError::code).collect();
+        assert_eq!(
+            codes,
+            [
+                "unknown_admin_op",
+                "missing_argument",
+                "invalid_argument",
+                "unexpected_argument",
+                "client_account_not_found",
+                "merge_rejected",
+                "removal_rejected",
+            ]
+        );
+        let invalid: Vec<_> = errors.iter().map(AdminOpError::is_invalid_row).collect();
+        assert_eq!(invalid, [true, true, true, true, false, false, false]);
+    }
+}
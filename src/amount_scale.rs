@@ -0,0 +1,117 @@
+//! Smallest unit of the amounts of a run, such as cents with a scale of 2 or whole
+//! points with a scale of 0. Input amounts are checked against it and the account
+//! reports are written with exactly that many decimals, while the ledger keeps
+//! computing with full `Decimal` precision.
+use crate::accounting::transactions::TransactionLogError;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// Scale of the input spec, four decimal places.
+pub const DEFAULT_AMOUNT_SCALE: u32 = 4;
+
+/// Largest scale a `Decimal` can hold.
+pub const MAX_SCALE: u32 = 28;
+
+/// Treatment of amounts with more fractional digits than the scale. Trailing zeros
+/// don't count, so `1.50` fits a scale of 1.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ExcessDigits {
+    /// The row is malformed with `TransactionLogError::ExcessPrecision`.
+    #[default]
+    Reject,
+    /// The amount is rounded to the scale, half away from zero.
+    Round,
+}
+
+/// Amount scale of a run and the treatment of finer amounts, see
+/// `IngestOptions::amount_scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmountScale {
+    pub scale: u32,
+    pub excess: ExcessDigits,
+}
+
+impl Default for AmountScale {
+    fn default() -> Self {
+        AmountScale::new(DEFAULT_AMOUNT_SCALE)
+    }
+}
+
+impl AmountScale {
+    /// Scale rejecting finer amounts. Panics beyond `MAX_SCALE`.
+    pub fn new(scale: u32) -> Self {
+        assert!(
+            scale <= MAX_SCALE,
+            "amount scale {} beyond {}",
+            scale,
+            MAX_SCALE
+        );
+        AmountScale {
+            scale,
+            excess: ExcessDigits::Reject,
+        }
+    }
+
+    /// The same scale, rounding finer amounts instead of rejecting them.
+    pub fn rounding(self) -> Self {
+        AmountScale {
+            excess: ExcessDigits::Round,
+            ..self
+        }
+    }
+
+    /// `amount` if it fits the scale, otherwise rejected or rounded per `excess`.
+    pub fn check(self, amount: Decimal) -> Result<Decimal, TransactionLogError> {
+        if fits(amount, self.scale) {
+            return Ok(amount);
+        }
+        match self.excess {
+            ExcessDigits::Reject => Err(TransactionLogError::ExcessPrecision),
+            ExcessDigits::Round => {
+                Ok(amount
+                    .round_dp_with_strategy(self.scale, RoundingStrategy::MidpointAwayFromZero))
+            }
+        }
+    }
+}
+
+/// Whether `amount` has at most `scale` fractional digits besides trailing zeros.
+pub fn fits(amount: Decimal, scale: u32) -> bool {
+    amount.normalize().scale() <= scale
+}
+
+/// `amount` with exactly `scale` decimals, as the account reports write it. Finer
+/// amounts are rounded half away from zero.
+pub fn render(amount: Decimal, scale: u32) -> Decimal {
+    let mut rendered = amount;
+    rendered.rescale(scale);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn trailing_zeros_fit_and_finer_amounts_follow_the_policy() {
+        let cents = AmountScale::new(2);
+        assert_eq!(cents.check(dec!(1.50)), Ok(dec!(1.50)));
+        assert_eq!(cents.check(dec!(1.5000)), Ok(dec!(1.5000)));
+        assert_eq!(
+            cents.check(dec!(1.005)),
+            Err(TransactionLogError::ExcessPrecision)
+        );
+        assert_eq!(cents.rounding().check(dec!(1.005)), Ok(dec!(1.01)));
+        assert_eq!(cents.rounding().check(dec!(-1.005)), Ok(dec!(-1.01)));
+        assert_eq!(AmountScale::new(0).rounding().check(dec!(2.5)), Ok(dec!(3)));
+        assert_eq!(AmountScale::default().scale, DEFAULT_AMOUNT_SCALE);
+    }
+
+    #[test]
+    fn render_writes_exactly_the_scale() {
+        assert_eq!(render(dec!(1.5), 4).to_string(), "1.5000");
+        assert_eq!(render(dec!(1.5), 2).to_string(), "1.50");
+        assert_eq!(render(dec!(2), 0).to_string(), "2");
+        assert_eq!(render(dec!(1.23456), 2).to_string(), "1.23");
+    }
+}
@@ -0,0 +1,35 @@
+//! Writes the test vectors of `payments_engine::vectors` into `tests/vectors/`, or the
+//! directory given with `--out`, replacing vectors of the same name.
+//!
+//! ```shell
+//! cargo run --features testkit --bin genvectors -- --seed 42
+//! ```
+use payments_engine::vectors::generate_vectors;
+
+fn main() {
+    let usage = "Usage: genvectors [--seed <N>] [--out <dir>]";
+    let mut args = std::env::args().skip(1);
+    let mut seed = 42;
+    let mut out = "tests/vectors".to_string();
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--seed" => args
+                .next()
+                .and_then(|value| value.parse().ok())
+                .map(|value| seed = value),
+            "--out" => args.next().map(|value| out = value),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+    }
+    for vector in generate_vectors(seed) {
+        if let Err(err) = vector.write(&out) {
+            eprintln!("Failed to write vector {}: {}", vector.metadata.name, err);
+            std::process::exit(1);
+        }
+        eprintln!("wrote {}/{}", out, vector.metadata.name);
+    }
+}
@@ -0,0 +1,366 @@
+//! Checkpoints of a ledger consuming a stream, for restarting where it stopped.
+//!
+//! A checkpoint is one JSON file holding the state of the ledger, the cursor of the
+//! stream the state covers and metadata of the run, written under a temporary name
+//! and renamed into place with `Staging`. A crash therefore leaves either the whole
+//! checkpoint or none of it, and never a state without its cursor or the other way
+//! around. Checkpoints are named `checkpoint-<id>.json` with a zero-padded id, which
+//! increases with every checkpoint of the directory.
+//!
+//! On restart, `Checkpointer::latest` restores the ledger of the newest readable
+//! checkpoint, and the stream is replayed from its cursor. A checkpoint taken under
+//! another amount scale than the configured one is not restored, see
+//! `Checkpointer::restore_latest`. Transactions applied after
+//! the checkpoint are applied again on the restored ledger, which has not seen them.
+use crate::accounting::config::LedgerConfig;
+use crate::accounting::snapshot::LedgerSnapshot;
+pub use crate::accounting::snapshot::SnapshotError;
+use crate::accounting::Ledger;
+use crate::clock::Timestamp;
+use crate::run_id::RunId;
+use crate::staging::Staging;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+/// Version of the layout of checkpoint files, checked when reading them.
+pub const CHECKPOINT_FORMAT_VERSION: u32 = 1;
+
+/// Checkpoints kept by default, see `Checkpointer::retain`.
+pub const DEFAULT_RETAINED_CHECKPOINTS: usize = 3;
+
+const FILE_PREFIX: &str = "checkpoint-";
+const FILE_SUFFIX: &str = ".json";
+
+/// Position in the stream up to which a checkpointed ledger consumed it, e.g. the
+/// byte offset of the first line of a feed which was not processed yet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub offset: u64,
+}
+
+/// Id of a checkpoint within its directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CheckpointId(pub u64);
+
+impl Display for CheckpointId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Encoding(serde_json::Error),
+    /// The ledger tracks lifecycle messages under a `CausalityMode`, which are not
+    /// part of its saved state.
+    CausalityTracked,
+}
+
+impl CheckpointError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CheckpointError::Io(_) => "io",
+            CheckpointError::Encoding(_) => "encoding",
+            CheckpointError::CausalityTracked => "causality_tracked",
+        }
+    }
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "failed to write checkpoint: {}", err),
+            CheckpointError::Encoding(err) => write!(f, "failed to encode checkpoint: {}", err),
+            CheckpointError::CausalityTracked => f.write_str(
+                "cannot checkpoint a ledger tracking lifecycle messages under a causality mode",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io(err) => Some(err),
+            CheckpointError::Encoding(err) => Some(err),
+            CheckpointError::CausalityTracked => None,
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CheckpointError {
+    fn from(err: serde_json::Error) -> Self {
+        CheckpointError::Encoding(err)
+    }
+}
+
+/// Contents of a checkpoint file.
+#[derive(Serialize, Deserialize)]
+struct CheckpointFile {
+    format_version: u32,
+    id: u64,
+    run_id: Option<String>,
+    engine_version: String,
+    /// Time of the clock of the ledger.
+    created_at_unix_ms: Timestamp,
+    cursor: Cursor,
+    ledger: LedgerSnapshot,
+}
+
+/// Writes checkpoints to a directory and restores the latest one, see the module
+/// documentation.
+pub struct Checkpointer {
+    dir: PathBuf,
+    retain: usize,
+    run_id: Option<RunId>,
+    ledger_config: LedgerConfig,
+    /// Held while a checkpoint is written, so that concurrent ones get distinct ids.
+    writing: Mutex<()>,
+}
+
+impl Checkpointer {
+    /// Checkpointer of `dir`, which must exist.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            retain: DEFAULT_RETAINED_CHECKPOINTS,
+            run_id: None,
+            ledger_config: LedgerConfig::default(),
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Keeps the `retain` newest checkpoints, at least one, removing older ones after
+    /// each checkpoint.
+    pub fn retain(mut self, retain: usize) -> Self {
+        self.retain = retain.max(1);
+        self
+    }
+
+    /// Stamps the checkpoints with `run_id`.
+    pub fn run_id(mut self, run_id: RunId) -> Self {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    /// Configuration of the ledgers restored by `latest`, which is not saved along
+    /// with their state.
+    pub fn ledger_config(mut self, config: LedgerConfig) -> Self {
+        self.ledger_config = config;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the checkpoint `id`.
+    pub fn path(&self, id: CheckpointId) -> PathBuf {
+        self.dir
+            .join(format!("{}{:020}{}", FILE_PREFIX, id.0, FILE_SUFFIX))
+    }
+
+    /// Ids of the checkpoints in the directory, oldest first.
+    pub fn list(&self) -> io::Result<Vec<CheckpointId>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let file_name = entry?.file_name();
+            let id = file_name
+                .to_str()
+                .and_then(|name| name.strip_prefix(FILE_PREFIX))
+                .and_then(|name| name.strip_suffix(FILE_SUFFIX))
+                .and_then(|id| id.parse().ok());
+            ids.extend(id.map(CheckpointId));
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Saves the state of `ledger`, which has consumed its stream up to `cursor`, as
+    /// a new checkpoint, then removes the checkpoints beyond the retained ones.
+    pub async fn checkpoint(
+        &self,
+        ledger: &Ledger,
+        cursor: Cursor,
+    ) -> Result<CheckpointId, CheckpointError> {
+        let snapshot = ledger.snapshot().ok_or(CheckpointError::CausalityTracked)?;
+        let _writing = self.writing.lock().await;
+        let id = CheckpointId(self.list()?.last().map_or(1, |id| id.0 + 1));
+        let file = CheckpointFile {
+            format_version: CHECKPOINT_FORMAT_VERSION,
+            id: id.0,
+            run_id: self.run_id.as_ref().map(|run_id| run_id.to_string()),
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at_unix_ms: ledger.clock().now(),
+            cursor,
+            ledger: snapshot,
+        };
+        let path = self.path(id);
+        tokio::task::spawn_blocking(move || write_checkpoint(&path, &file))
+            .await
+            .map_err(io::Error::other)??;
+        self.remove_old()?;
+        Ok(id)
+    }
+
+    /// Ledger and cursor of the newest checkpoint which can be read and restored,
+    /// configured with `Checkpointer::ledger_config`. Checkpoints which cannot, e.g.
+    /// from another format version, are skipped in favor of older ones. `None` when
+    /// `restore_latest` fails.
+    pub fn latest(&self) -> Option<(Ledger, Cursor)> {
+        self.restore_latest().ok().flatten()
+    }
+
+    /// Like `latest`, failing with `SnapshotError::ScaleMismatch` instead of falling
+    /// back to older checkpoints when the newest readable one was taken under another
+    /// amount scale, since the older ones were taken under it too.
+    pub fn restore_latest(&self) -> Result<Option<(Ledger, Cursor)>, SnapshotError> {
+        let Ok(ids) = self.list() else {
+            return Ok(None);
+        };
+        for id in ids.into_iter().rev() {
+            match self.load(id) {
+                Some(Ok(restored)) => return Ok(Some(restored)),
+                Some(Err(err @ SnapshotError::ScaleMismatch { .. })) => return Err(err),
+                Some(Err(_)) | None => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// `None` when the checkpoint cannot be read.
+    fn load(&self, id: CheckpointId) -> Option<Result<(Ledger, Cursor), SnapshotError>> {
+        let contents = std::fs::read(self.path(id)).ok()?;
+        let file: CheckpointFile = serde_json::from_slice(&contents).ok()?;
+        if file.format_version != CHECKPOINT_FORMAT_VERSION {
+            return None;
+        }
+        let restored = Ledger::restore(self.ledger_config.clone(), file.ledger);
+        Some(restored.map(|ledger| (ledger, file.cursor)))
+    }
+
+    fn remove_old(&self) -> io::Result<()> {
+        let ids = self.list()?;
+        let excess = ids.len().saturating_sub(self.retain);
+        for id in &ids[..excess] {
+            std::fs::remove_file(self.path(*id))?;
+        }
+        Ok(())
+    }
+}
+
+fn write_checkpoint(path: &Path, file: &CheckpointFile) -> Result<(), CheckpointError> {
+    let mut staging = Staging::new();
+    let mut writer = BufWriter::new(staging.create(path)?);
+    serde_json::to_writer(&mut writer, file)?;
+    writer.flush()?;
+    drop(writer);
+    staging.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::config::CausalityMode;
+    use crate::accounting::transactions::{Deposit, Transaction};
+    use rust_decimal_macros::dec;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "payments_engine_checkpoint_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn keeps_the_newest_checkpoints() {
+        let dir = temp_dir("retain");
+        let checkpointer = Checkpointer::new(&dir).retain(2);
+        assert!(checkpointer.latest().is_none());
+        let mut ledger = Ledger::new();
+        for tx_id in 1..=4 {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(1, tx_id, dec!(1))))
+                .unwrap();
+            let id = checkpointer
+                .checkpoint(
+                    &ledger,
+                    Cursor {
+                        offset: tx_id.into(),
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(id, CheckpointId(tx_id.into()));
+        }
+        assert_eq!(
+            checkpointer.list().unwrap(),
+            [CheckpointId(3), CheckpointId(4)]
+        );
+        let (restored, cursor) = checkpointer.latest().unwrap();
+        assert_eq!(cursor, Cursor { offset: 4 });
+        assert_eq!(restored.state_digest(), ledger.state_digest());
+
+        // A damaged newest checkpoint falls back to the one before
+        std::fs::write(checkpointer.path(CheckpointId(4)), "{").unwrap();
+        assert_eq!(checkpointer.latest().unwrap().1, Cursor { offset: 3 });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn causality_tracking_ledgers_are_refused() {
+        let dir = temp_dir("causality");
+        let ledger = Ledger::builder()
+            .causality_mode(CausalityMode::Classify)
+            .build();
+        let err = Checkpointer::new(&dir)
+            .checkpoint(&ledger, Cursor::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "causality_tracked");
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn checkpoints_of_another_amount_scale_are_not_restored() {
+        let dir = temp_dir("amount_scale");
+        let mut ledger = Ledger::builder().amount_scale(2).build();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(1.50))))
+            .unwrap();
+        let checkpointer = Checkpointer::new(&dir).ledger_config(ledger.config().clone());
+        checkpointer
+            .checkpoint(&ledger, Cursor { offset: 1 })
+            .await
+            .unwrap();
+        assert_eq!(checkpointer.latest().unwrap().1, Cursor { offset: 1 });
+
+        let rescaled = Checkpointer::new(&dir)
+            .ledger_config(Ledger::builder().amount_scale(0).build().config().clone());
+        assert!(matches!(
+            rescaled.restore_latest(),
+            Err(SnapshotError::ScaleMismatch {
+                snapshot: Some(2),
+                config: Some(0),
+            })
+        ));
+        assert!(rescaled.latest().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+//! Source of the current time for everything which consults it, such as dispute
+//! windows, checkpoint metadata, run ids, retry backoffs and the settle period of the
+//! directory watcher.
+//!
+//! Time is never read ambiently: a ledger reads its `LedgerConfig::clock`, and the
+//! components around it read the clock they were given or the one of their ledger.
+//! `SystemClock` is the default, and `ManualClock` is set and advanced by tests, whose
+//! sleeps advance it instead of waiting, so time based behavior is tested without
+//! real sleeping.
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display, Formatter};
+#[cfg(feature = "io")]
+use std::future::Future;
+use std::ops::Deref;
+#[cfg(feature = "io")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Point in time as milliseconds since the Unix epoch, serialized as the number.
+#[derive(
+    Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(transparent)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub const UNIX_EPOCH: Timestamp = Timestamp(0);
+
+    pub const fn from_unix_millis(millis: u64) -> Self {
+        Timestamp(millis)
+    }
+
+    pub const fn unix_millis(self) -> u64 {
+        self.0
+    }
+
+    pub fn saturating_add(self, duration: Duration) -> Self {
+        let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        Timestamp(self.0.saturating_add(millis))
+    }
+
+    /// Time passed from `earlier` to `self`, zero when `earlier` is later.
+    pub fn saturating_duration_since(self, earlier: Timestamp) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Source of the current time, see the module documentation.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Timestamp;
+
+    /// Blocks the thread for `duration`, e.g. between retries of a sink.
+    fn sleep_blocking(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    /// Completes after `duration`, e.g. between the reports of a server.
+    #[cfg(feature = "io")]
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Wall clock of the system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn now(&self) -> Timestamp {
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Timestamp(millis)
+    }
+
+    /// There is no clock on `wasm32-unknown-unknown`, so it is always the epoch.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn now(&self) -> Timestamp {
+        Timestamp::UNIX_EPOCH
+    }
+}
+
+/// Clock which only moves when set or advanced, including by its sleeps, which
+/// return immediately.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    millis: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            millis: AtomicU64::new(now.0),
+        }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.millis.store(now.0, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        // Saturating, like `Timestamp::saturating_add`
+        let _ = self
+            .millis
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |now| {
+                Some(now.saturating_add(millis))
+            });
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp(self.millis.load(Ordering::SeqCst))
+    }
+
+    fn sleep_blocking(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    #[cfg(feature = "io")]
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.advance(duration);
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// Clock shared by a ledger and the components around it, the `SystemClock` by
+/// default. Shared clocks are equal when they are the same clock.
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock::new(SystemClock)
+    }
+}
+
+impl<C: Clock + 'static> From<Arc<C>> for SharedClock {
+    fn from(clock: Arc<C>) -> Self {
+        SharedClock(clock)
+    }
+}
+
+impl Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl PartialEq for SharedClock {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Debug for SharedClock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SharedClock({})", self.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clocks_move_only_when_told() {
+        let clock = ManualClock::new(Timestamp::from_unix_millis(1_000));
+        assert_eq!(clock.now(), Timestamp::from_unix_millis(1_000));
+        clock.advance(Duration::from_secs(2));
+        clock.sleep_blocking(Duration::from_millis(500));
+        assert_eq!(clock.now().unix_millis(), 3_500);
+        clock.set(Timestamp::UNIX_EPOCH);
+        assert_eq!(clock.now(), Timestamp::UNIX_EPOCH);
+        clock.advance(Duration::MAX);
+        assert_eq!(clock.now().unix_millis(), u64::MAX);
+    }
+
+    #[test]
+    fn timestamps_saturate() {
+        let now = Timestamp::from_unix_millis(5_000);
+        assert_eq!(
+            now.saturating_duration_since(Timestamp::from_unix_millis(1_500)),
+            Duration::from_millis(3_500)
+        );
+        assert_eq!(
+            Timestamp::UNIX_EPOCH.saturating_duration_since(now),
+            Duration::ZERO
+        );
+        assert_eq!(
+            now.saturating_add(Duration::MAX),
+            Timestamp::from_unix_millis(u64::MAX)
+        );
+        assert_eq!(serde_json::to_string(&now).unwrap(), "5000");
+        assert_eq!(serde_json::from_str::<Timestamp>("5000").unwrap(), now);
+    }
+
+    #[test]
+    fn shared_clocks_are_equal_when_the_same() {
+        let manual = Arc::new(ManualClock::default());
+        let shared = SharedClock::from(Arc::clone(&manual));
+        assert_eq!(shared, shared.clone());
+        assert_ne!(shared, SharedClock::from(Arc::new(ManualClock::default())));
+        manual.advance(Duration::from_millis(7));
+        assert_eq!(shared.now().unix_millis(), 7);
+        assert_eq!(format!("{:?}", shared), "SharedClock(7)");
+        assert!(SystemClock.now() > Timestamp::UNIX_EPOCH);
+    }
+
+    #[cfg(feature = "io")]
+    #[tokio::test]
+    async fn manual_sleeps_do_not_wait() {
+        let clock = ManualClock::default();
+        clock.sleep(Duration::from_secs(3_600)).await;
+        assert_eq!(clock.now().unix_millis(), 3_600_000);
+    }
+}
@@ -0,0 +1,449 @@
+//! Semantic comparison of two account reports, e.g. the outputs of the same input run
+//! on two versions of the engine. Rows are matched by client whatever their order,
+//! columns by name whatever their order, and amounts compare as `Decimal`, so that
+//! `1.5` and `1.5000` are equal.
+use crate::core_types::ClientId;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::str::FromStr;
+
+/// Columns of the account report besides `client`, which both reports must have
+/// unless ignored.
+const ACCOUNT_COLUMNS: [&str; 4] = ["available", "held", "total", "locked"];
+
+/// Columns holding amounts, which must parse as `Decimal` in both reports.
+const AMOUNT_COLUMNS: [&str; 3] = ["available", "held", "total"];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompareOptions {
+    /// Columns left out of the comparison, e.g. a column only the newer version
+    /// writes. The `client` column is always compared, as it matches the rows.
+    pub ignore_columns: BTreeSet<String>,
+}
+
+impl CompareOptions {
+    pub fn ignore_column(mut self, column: impl Into<String>) -> Self {
+        self.ignore_columns.insert(column.into());
+        self
+    }
+}
+
+/// Which of the two compared reports something refers to.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportSide {
+    A,
+    B,
+}
+
+impl Display for ReportSide {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportSide::A => f.write_str("first report"),
+            ReportSide::B => f.write_str("second report"),
+        }
+    }
+}
+
+/// Field of a client on both sides whose values differ.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldDifference {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub column: String,
+    pub a: String,
+    pub b: String,
+    /// `b` minus `a` when both are amounts.
+    pub delta: Option<Decimal>,
+}
+
+/// Outcome of `compare_reports`, with everything sorted by client and column.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct CompareReport {
+    /// Clients in both reports.
+    pub clients_compared: usize,
+    /// Clients only in the second report.
+    pub added_clients: Vec<ClientId>,
+    /// Clients only in the first report.
+    pub removed_clients: Vec<ClientId>,
+    /// Columns only in the second report, besides the ignored ones.
+    pub added_columns: Vec<String>,
+    /// Columns only in the first report, besides the ignored ones.
+    pub removed_columns: Vec<String>,
+    pub differences: Vec<FieldDifference>,
+}
+
+impl CompareReport {
+    /// Whether the reports hold the same accounts with the same values.
+    pub fn is_identical(&self) -> bool {
+        self.added_clients.is_empty()
+            && self.removed_clients.is_empty()
+            && self.added_columns.is_empty()
+            && self.removed_columns.is_empty()
+            && self.differences.is_empty()
+    }
+}
+
+impl Display for CompareReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} clients compared, {} added, {} removed, {} field differences, \
+             {} columns added, {} columns removed",
+            self.clients_compared,
+            self.added_clients.len(),
+            self.removed_clients.len(),
+            self.differences.len(),
+            self.added_columns.len(),
+            self.removed_columns.len()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum CompareError {
+    Csv {
+        side: ReportSide,
+        error: csv::Error,
+    },
+    /// The report has no `client` column, or lacks a column of the account report
+    /// which is not ignored.
+    MissingColumn {
+        side: ReportSide,
+        column: &'static str,
+    },
+    /// A client id, amount or locked flag which does not parse.
+    InvalidValue {
+        side: ReportSide,
+        line: u64,
+        column: String,
+        value: String,
+    },
+    DuplicateClient {
+        side: ReportSide,
+        client_id: ClientId,
+    },
+}
+
+impl CompareError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompareError::Csv { .. } => "csv",
+            CompareError::MissingColumn { .. } => "missing_column",
+            CompareError::InvalidValue { .. } => "invalid_value",
+            CompareError::DuplicateClient { .. } => "duplicate_client",
+        }
+    }
+}
+
+impl Display for CompareError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompareError::Csv { side, error } => write!(f, "failed to read {}: {}", side, error),
+            CompareError::MissingColumn { side, column } => {
+                write!(f, "{} has no {} column", side, column)
+            }
+            CompareError::InvalidValue {
+                side,
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "{} has invalid {} {:?} on line {}",
+                side, column, value, line
+            ),
+            CompareError::DuplicateClient { side, client_id } => {
+                write!(f, "client {} appears twice in the {}", client_id, side)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompareError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompareError::Csv { error, .. } => Some(error),
+            CompareError::MissingColumn { .. }
+            | CompareError::InvalidValue { .. }
+            | CompareError::DuplicateClient { .. } => None,
+        }
+    }
+}
+
+/// Typed value of a field, amounts of unknown columns included.
+#[derive(Debug, PartialEq)]
+enum Value {
+    Amount(Decimal),
+    Flag(bool),
+    Text,
+}
+
+/// Field as written in the report, with its typed value.
+struct Field {
+    raw: String,
+    value: Value,
+}
+
+impl Field {
+    fn matches(&self, other: &Field) -> bool {
+        match (&self.value, &other.value) {
+            (Value::Text, _) | (_, Value::Text) => self.raw == other.raw,
+            (value, other_value) => value == other_value,
+        }
+    }
+}
+
+/// Account report read into its columns and rows by client.
+struct ParsedReport {
+    columns: BTreeSet<String>,
+    rows: BTreeMap<ClientId, BTreeMap<String, Field>>,
+}
+
+fn parse_report(
+    side: ReportSide,
+    report: impl Read,
+    options: &CompareOptions,
+) -> Result<ParsedReport, CompareError> {
+    let csv_error = |error| CompareError::Csv { side, error };
+    // Comment lines are those of `--stamp-outputs` and output selections.
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(report);
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(csv_error)?
+        .iter()
+        .map(str::to_string)
+        .collect();
+    let client_index = headers.iter().position(|column| column == "client").ok_or(
+        CompareError::MissingColumn {
+            side,
+            column: "client",
+        },
+    )?;
+    if let Some(column) = ACCOUNT_COLUMNS.into_iter().find(|column| {
+        !options.ignore_columns.contains(*column) && !headers.iter().any(|header| header == column)
+    }) {
+        return Err(CompareError::MissingColumn { side, column });
+    }
+
+    let mut rows = BTreeMap::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let line = record.position().map_or(0, |position| position.line());
+        let invalid = |column: &str, value: &str| CompareError::InvalidValue {
+            side,
+            line,
+            column: column.to_string(),
+            value: value.to_string(),
+        };
+        let client = &record[client_index];
+        let client_id = ClientId::from_str(client).map_err(|_| invalid("client", client))?;
+        let mut fields = BTreeMap::new();
+        for (column, raw) in headers.iter().zip(&record) {
+            if column == "client" || options.ignore_columns.contains(column) {
+                continue;
+            }
+            let value = if AMOUNT_COLUMNS.contains(&column.as_str()) {
+                Value::Amount(raw.parse().map_err(|_| invalid(column, raw))?)
+            } else if column == "locked" {
+                Value::Flag(raw.parse().map_err(|_| invalid(column, raw))?)
+            } else {
+                raw.parse().map_or(Value::Text, Value::Amount)
+            };
+            let field = Field {
+                raw: raw.to_string(),
+                value,
+            };
+            fields.insert(column.clone(), field);
+        }
+        if rows.insert(client_id, fields).is_some() {
+            return Err(CompareError::DuplicateClient { side, client_id });
+        }
+    }
+    let columns = headers
+        .into_iter()
+        .filter(|column| column != "client" && !options.ignore_columns.contains(column))
+        .collect();
+    Ok(ParsedReport { columns, rows })
+}
+
+/// Compares the account reports `a` and `b`, CSV in the account output schema with
+/// any further columns, which are compared as amounts when both sides parse as one
+/// and as text otherwise. Columns only one side has are reported once, not per client.
+pub fn compare_reports(
+    a: impl Read,
+    b: impl Read,
+    options: &CompareOptions,
+) -> Result<CompareReport, CompareError> {
+    let a = parse_report(ReportSide::A, a, options)?;
+    let mut b = parse_report(ReportSide::B, b, options)?;
+    let mut report = CompareReport {
+        added_columns: b.columns.difference(&a.columns).cloned().collect(),
+        removed_columns: a.columns.difference(&b.columns).cloned().collect(),
+        ..CompareReport::default()
+    };
+    for (client_id, a_fields) in a.rows {
+        let Some(mut b_fields) = b.rows.remove(&client_id) else {
+            report.removed_clients.push(client_id);
+            continue;
+        };
+        report.clients_compared += 1;
+        for (column, a_field) in a_fields {
+            let Some(b_field) = b_fields.remove(&column) else {
+                continue;
+            };
+            if a_field.matches(&b_field) {
+                continue;
+            }
+            let delta = match (&a_field.value, &b_field.value) {
+                (Value::Amount(a_amount), Value::Amount(b_amount)) => Some(b_amount - a_amount),
+                _ => None,
+            };
+            report.differences.push(FieldDifference {
+                client_id,
+                column,
+                a: a_field.raw,
+                b: b_field.raw,
+                delta,
+            });
+        }
+    }
+    report.added_clients = b.rows.into_keys().collect();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const BASE: &str = "client,available,held,total,locked\n\
+                        1,10.5,0,10.5,false\n\
+                        2,3.0001,1,4.0001,true\n";
+
+    fn compare(a: &str, b: &str, options: &CompareOptions) -> CompareReport {
+        compare_reports(a.as_bytes(), b.as_bytes(), options).unwrap()
+    }
+
+    #[test]
+    fn order_and_formatting_do_not_matter() {
+        let reordered = "# run_id=nightly\n\
+                         total, locked, client, held, available\n\
+                         4.0001, true, 2, 1.0000, 3.0001\n\
+                         10.5000, false, 1, 0.00, 10.50\n";
+        let report = compare(BASE, reordered, &CompareOptions::default());
+        assert!(report.is_identical(), "{:?}", report);
+        assert_eq!(report.clients_compared, 2);
+    }
+
+    #[test]
+    fn differences_carry_their_delta() {
+        let changed = "client,available,held,total,locked\n\
+                       2,3.0001,1,4.0001,false\n\
+                       3,0,0,0,false\n\
+                       1,10.49,0,10.49,false\n";
+        let report = compare(BASE, changed, &CompareOptions::default());
+        assert_eq!(report.added_clients, [3]);
+        assert!(report.removed_clients.is_empty());
+        assert_eq!(
+            report.differences,
+            [
+                FieldDifference {
+                    client_id: 1,
+                    column: "available".into(),
+                    a: "10.5".into(),
+                    b: "10.49".into(),
+                    delta: Some(dec!(-0.01)),
+                },
+                FieldDifference {
+                    client_id: 1,
+                    column: "total".into(),
+                    a: "10.5".into(),
+                    b: "10.49".into(),
+                    delta: Some(dec!(-0.01)),
+                },
+                FieldDifference {
+                    client_id: 2,
+                    column: "locked".into(),
+                    a: "true".into(),
+                    b: "false".into(),
+                    delta: None,
+                },
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "2 clients compared, 1 added, 0 removed, 3 field differences, \
+             0 columns added, 0 columns removed"
+        );
+    }
+
+    #[test]
+    fn extra_columns_differ_unless_ignored() {
+        let flagged = "client,available,held,total,locked,flags\n\
+                       1,10.5,0,10.5,false,vip\n\
+                       2,3.0001,1,4.0001,true,\n";
+        let report = compare(BASE, flagged, &CompareOptions::default());
+        assert_eq!(report.added_columns, ["flags"]);
+        assert!(report.differences.is_empty());
+        assert!(!report.is_identical());
+        let ignoring = CompareOptions::default().ignore_column("flags");
+        assert!(compare(BASE, flagged, &ignoring).is_identical());
+
+        // Ignoring an account column lets a report without it compare
+        let without_total = "client,available,held,locked\n1,10.5,0,false\n2,3.0001,1,true\n";
+        assert!(matches!(
+            compare_reports(
+                BASE.as_bytes(),
+                without_total.as_bytes(),
+                &CompareOptions::default()
+            ),
+            Err(CompareError::MissingColumn {
+                side: ReportSide::B,
+                column: "total",
+            })
+        ));
+        let ignoring = CompareOptions::default().ignore_column("total");
+        assert!(compare(BASE, without_total, &ignoring).is_identical());
+    }
+
+    #[test]
+    fn unparseable_reports() {
+        let options = CompareOptions::default();
+        let invalid_amount = "client,available,held,total,locked\n1,ten,0,10,false\n";
+        let err =
+            compare_reports(BASE.as_bytes(), invalid_amount.as_bytes(), &options).unwrap_err();
+        assert_eq!(err.code(), "invalid_value");
+        assert_eq!(
+            err.to_string(),
+            "second report has invalid available \"ten\" on line 2"
+        );
+        let duplicate = "client,available,held,total,locked\n1,1,0,1,false\n1,1,0,1,false\n";
+        assert!(matches!(
+            compare_reports(duplicate.as_bytes(), BASE.as_bytes(), &options),
+            Err(CompareError::DuplicateClient {
+                side: ReportSide::A,
+                client_id: 1,
+            })
+        ));
+        let ragged = "client,available,held,total,locked\n1,1,0\n";
+        assert_eq!(
+            compare_reports(ragged.as_bytes(), BASE.as_bytes(), &options)
+                .unwrap_err()
+                .code(),
+            "csv"
+        );
+        assert_eq!(
+            compare_reports("a,b\n1,2\n".as_bytes(), BASE.as_bytes(), &options)
+                .unwrap_err()
+                .to_string(),
+            "first report has no client column"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Distribution of the amounts of deposits and withdrawals over a run: count, sum,
+//! minimum, maximum and approximate percentiles, kept in bounded memory while the run
+//! executes.
+//!
+//! Percentiles are estimated from a histogram with logarithmically sized buckets: an
+//! amount `v` falls into bucket `i` when `γ^(i-1) < |v| <= γ^i`, with
+//! `γ = (1 + α) / (1 - α)`, and a bucket is estimated by the point `2γ^i / (γ + 1)`
+//! which is at most `α` away from any amount of it relative to the amount. With
+//! `α` just below 1%, and the estimates rounded to six significant digits, every
+//! percentile is within 1% of the exact nearest-rank percentile of the amounts. The
+//! estimates are also clamped to the exact minimum and maximum.
+//!
+//! The nonzero magnitudes of `Decimal` span 10^-28 to about 7.9 * 10^28, which takes
+//! at most 6,700 buckets for each sign, whatever the number of amounts. Sums saturate
+//! at the bounds of `Decimal`.
+use crate::accounting::transactions::TxTypeTag;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// Relative accuracy of the buckets, leaving room for the rounding of the estimates.
+const ALPHA: f64 = 0.0099;
+
+/// Significant digits of the estimated percentiles.
+const ESTIMATE_DIGITS: u32 = 6;
+
+/// Percentiles of `AmountSummary`, as fractions.
+const PERCENTILES: [f64; 4] = [0.5, 0.9, 0.95, 0.99];
+
+/// Count, sum, extremes and bucket counts of a stream of amounts.
+#[derive(Debug, Clone, Default)]
+pub struct AmountSketch {
+    count: u64,
+    sum: Decimal,
+    min: Option<Decimal>,
+    max: Option<Decimal>,
+    /// Counts by bucket index of the positive amounts, and of the magnitudes of the
+    /// negative ones.
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zero: u64,
+}
+
+impl AmountSketch {
+    pub fn record(&mut self, amount: Decimal) {
+        self.count += 1;
+        self.sum = self.sum.saturating_add(amount);
+        self.min = Some(self.min.map_or(amount, |min| min.min(amount)));
+        self.max = Some(self.max.map_or(amount, |max| max.max(amount)));
+        if amount.is_zero() {
+            self.zero += 1;
+            return;
+        }
+        let buckets = match amount.is_sign_negative() {
+            true => &mut self.negative,
+            false => &mut self.positive,
+        };
+        *buckets.entry(bucket(amount.abs())).or_default() += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Estimate of the nearest-rank `fraction` percentile, the smallest amount which at
+    /// least `fraction` of the amounts are not above, or `None` without amounts.
+    pub fn percentile(&self, fraction: f64) -> Option<Decimal> {
+        let (min, max) = self.min.zip(self.max)?;
+        let rank = ((fraction * self.count as f64).ceil() as u64).clamp(1, self.count);
+        // Negative amounts first, the largest magnitude first
+        let buckets = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(index, count)| (Some((*index, true)), *count))
+            .chain(std::iter::once((None, self.zero)))
+            .chain(
+                self.positive
+                    .iter()
+                    .map(|(index, count)| (Some((*index, false)), *count)),
+            );
+        let mut seen = 0;
+        for (bucket, count) in buckets {
+            seen += count;
+            if seen < rank {
+                continue;
+            }
+            let Some((index, negative)) = bucket else {
+                return Some(Decimal::ZERO.clamp(min, max));
+            };
+            let magnitude = 2.0 * gamma().powi(index) / (gamma() + 1.0);
+            let estimate = if negative { -magnitude } else { magnitude };
+            return Some(to_decimal(estimate, min, max));
+        }
+        Some(max)
+    }
+
+    pub fn summary(&self) -> AmountSummary {
+        let [p50, p90, p95, p99] = PERCENTILES.map(|fraction| self.percentile(fraction));
+        AmountSummary {
+            count: self.count,
+            sum: self.sum,
+            min: self.min,
+            max: self.max,
+            p50,
+            p90,
+            p95,
+            p99,
+        }
+    }
+}
+
+fn gamma() -> f64 {
+    (1.0 + ALPHA) / (1.0 - ALPHA)
+}
+
+/// Index of the bucket of the positive `magnitude`.
+fn bucket(magnitude: Decimal) -> i32 {
+    let magnitude = magnitude.to_f64().unwrap_or(f64::MAX);
+    (magnitude.ln() / gamma().ln()).ceil() as i32
+}
+
+/// `estimate` rounded to `ESTIMATE_DIGITS` and clamped to `min..=max`, which also keeps
+/// it within the range of `Decimal`.
+fn to_decimal(estimate: f64, min: Decimal, max: Decimal) -> Decimal {
+    if estimate <= min.to_f64().unwrap_or(f64::MIN) {
+        return min;
+    }
+    if estimate >= max.to_f64().unwrap_or(f64::MAX) {
+        return max;
+    }
+    Decimal::from_f64(estimate)
+        .map(|estimate| estimate.round_sf(ESTIMATE_DIGITS).unwrap_or(estimate))
+        .map_or(max, |estimate| estimate.clamp(min, max).normalize())
+}
+
+/// Statistics of the amounts of one kind of transaction, `None` without amounts.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AmountSummary {
+    pub count: u64,
+    pub sum: Decimal,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+    pub p50: Option<Decimal>,
+    pub p90: Option<Decimal>,
+    pub p95: Option<Decimal>,
+    pub p99: Option<Decimal>,
+}
+
+/// Amounts of one kind of transaction, and the number of rejected ones.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct KindDistribution {
+    /// Amounts of the applied transactions, and of the rejected ones when
+    /// `DistributionReport::include_rejected` is set.
+    pub amounts: AmountSummary,
+    pub rejected: u64,
+}
+
+/// Distribution of the amounts of a run, see `Pipeline::distribution_stats` and
+/// `RunStats::distribution`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct DistributionReport {
+    pub include_rejected: bool,
+    pub deposits: KindDistribution,
+    pub withdrawals: KindDistribution,
+}
+
+/// Records the amounts of deposits and withdrawals during a run.
+#[derive(Debug, Clone, Default)]
+pub struct DistributionTracker {
+    include_rejected: bool,
+    deposits: AmountSketch,
+    withdrawals: AmountSketch,
+    deposits_rejected: u64,
+    withdrawals_rejected: u64,
+}
+
+impl DistributionTracker {
+    /// Tracker of the applied amounts, and of the rejected ones too when
+    /// `include_rejected` is set. Rejections are counted either way.
+    pub fn new(include_rejected: bool) -> Self {
+        Self {
+            include_rejected,
+            ..Self::default()
+        }
+    }
+
+    /// Records the `amount` of a transaction of `kind`, which is ignored unless it is a
+    /// deposit or a withdrawal.
+    pub fn record(&mut self, kind: TxTypeTag, amount: Decimal, applied: bool) {
+        let (sketch, rejected) = match kind {
+            TxTypeTag::Deposit => (&mut self.deposits, &mut self.deposits_rejected),
+            TxTypeTag::Withdrawal => (&mut self.withdrawals, &mut self.withdrawals_rejected),
+            _ => return,
+        };
+        if !applied {
+            *rejected += 1;
+        }
+        if applied || self.include_rejected {
+            sketch.record(amount);
+        }
+    }
+
+    pub fn report(&self) -> DistributionReport {
+        DistributionReport {
+            include_rejected: self.include_rejected,
+            deposits: KindDistribution {
+                amounts: self.deposits.summary(),
+                rejected: self.deposits_rejected,
+            },
+            withdrawals: KindDistribution {
+                amounts: self.withdrawals.summary(),
+                rejected: self.withdrawals_rejected,
+            },
+        }
+    }
+}
+
+/// Writes one line per kind, e.g. for `stderr`.
+impl Display for DistributionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let included = match self.include_rejected {
+            true => "included",
+            false => "not included",
+        };
+        for (name, kind) in [
+            ("Deposits", &self.deposits),
+            ("Withdrawals", &self.withdrawals),
+        ] {
+            let amounts = &kind.amounts;
+            write!(f, "{}: count {}", name, amounts.count)?;
+            if let (Some(min), Some(max), Some(p50), Some(p90), Some(p95), Some(p99)) = (
+                amounts.min,
+                amounts.max,
+                amounts.p50,
+                amounts.p90,
+                amounts.p95,
+                amounts.p99,
+            ) {
+                write!(
+                    f,
+                    ", sum {}, min {}, max {}, p50 {}, p90 {}, p95 {}, p99 {}",
+                    amounts.sum, min, max, p50, p90, p95, p99
+                )?;
+            }
+            writeln!(f, "; {} rejected, {}", kind.rejected, included)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    /// Exact nearest-rank percentile of sorted `amounts`.
+    fn exact(amounts: &[Decimal], fraction: f64) -> Decimal {
+        let rank = (fraction * amounts.len() as f64).ceil() as usize;
+        amounts[rank.clamp(1, amounts.len()) - 1]
+    }
+
+    fn assert_within_bounds(sketch: &AmountSketch, sorted: &[Decimal]) {
+        for fraction in PERCENTILES {
+            let exact = exact(sorted, fraction);
+            let estimate = sketch.percentile(fraction).unwrap();
+            assert!(
+                (estimate - exact).abs() <= exact.abs() / dec!(100),
+                "p{}: {} estimated as {}",
+                fraction * 100.0,
+                exact,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn uniform_percentiles_are_within_one_percent() {
+        let mut sketch = AmountSketch::default();
+        let mut lcg = crate::scenario::Lcg::new(430);
+        let mut amounts: Vec<_> = (1..=10_000).map(Decimal::from).collect();
+        // Recorded in random order
+        for i in (1..amounts.len()).rev() {
+            amounts.swap(i, lcg.next() as usize % (i + 1));
+        }
+        for amount in &amounts {
+            sketch.record(*amount);
+        }
+        amounts.sort_unstable();
+        assert_within_bounds(&sketch, &amounts);
+
+        let summary = sketch.summary();
+        assert_eq!(summary.count, 10_000);
+        assert_eq!(summary.sum, dec!(50_005_000));
+        assert_eq!(
+            (summary.min, summary.max),
+            (Some(dec!(1)), Some(dec!(10_000)))
+        );
+    }
+
+    #[test]
+    fn amounts_across_the_decimal_range() {
+        let mut sketch = AmountSketch::default();
+        let mut amounts = vec![
+            Decimal::MAX,
+            Decimal::MIN,
+            Decimal::MAX,
+            Decimal::new(1, 28),
+            Decimal::new(-1, 28),
+            Decimal::ZERO,
+            dec!(0.0001),
+            dec!(12.5),
+            dec!(-3),
+        ];
+        for amount in &amounts {
+            sketch.record(*amount);
+        }
+        amounts.sort_unstable();
+        assert_within_bounds(&sketch, &amounts);
+        assert_eq!(sketch.percentile(0.5), Some(Decimal::new(1, 28)));
+        let summary = sketch.summary();
+        assert_eq!(
+            (summary.min, summary.max),
+            (Some(Decimal::MIN), Some(Decimal::MAX))
+        );
+    }
+
+    #[test]
+    fn empty_input_has_no_percentiles() {
+        let tracker = DistributionTracker::new(false);
+        let report = tracker.report();
+        assert_eq!(report.deposits, KindDistribution::default());
+        assert_eq!(report.deposits.amounts.p50, None);
+        assert_eq!(
+            report.to_string(),
+            "Deposits: count 0; 0 rejected, not included\n\
+             Withdrawals: count 0; 0 rejected, not included\n"
+        );
+    }
+
+    #[test]
+    fn rejected_amounts_are_counted_and_included_on_request() {
+        for include_rejected in [false, true] {
+            let mut tracker = DistributionTracker::new(include_rejected);
+            tracker.record(TxTypeTag::Deposit, dec!(10), true);
+            tracker.record(TxTypeTag::Deposit, dec!(20), true);
+            tracker.record(TxTypeTag::Withdrawal, dec!(1000), false);
+            tracker.record(TxTypeTag::Dispute, dec!(5), true);
+            let report = tracker.report();
+            assert_eq!(report.deposits.amounts.count, 2);
+            assert_eq!(report.withdrawals.rejected, 1);
+            assert_eq!(
+                report.withdrawals.amounts.count,
+                u64::from(include_rejected)
+            );
+        }
+        let mut tracker = DistributionTracker::new(false);
+        tracker.record(TxTypeTag::Deposit, dec!(10), true);
+        assert_eq!(
+            tracker.report().to_string(),
+            "Deposits: count 1, sum 10, min 10, max 10, p50 10, p90 10, p95 10, p99 10; \
+             0 rejected, not included\n\
+             Withdrawals: count 0; 0 rejected, not included\n"
+        );
+    }
+}
@@ -0,0 +1,805 @@
+//! Blocking processing of in-memory CSV, available without the `io` feature so that
+//! the ledger can run on `wasm32-unknown-unknown`.
+use crate::accounting::digest::LedgerDigest;
+use crate::accounting::transactions::{Transaction, TransactionLog, TransactionLogError};
+use crate::accounting::view::LedgerView;
+use crate::accounting::{AccountLog, InvariantViolation, Ledger, TxError};
+use crate::distribution::DistributionReport;
+#[cfg(feature = "io")]
+use crate::ingest::IngestError;
+use crate::number_locale::{AmountParsing, NumberLocale};
+#[cfg(feature = "io")]
+use crate::output::OutputError;
+use crate::pipeline::{CsvSource, Pipeline, Quarantine};
+use crate::preflight::{preflight, PreflightFinding, PreflightReport};
+use crate::run_id::RunId;
+use crate::sink::SinkError;
+use crate::top_k::TopKReport;
+use csv::{StringRecord, Trim};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// Error of the entry points of the crate, such as `process_csv_str`, `read_data`,
+/// `output_data` and `PaymentsEngine::run`, wrapping the error of the failed step.
+/// Functions of a single step return the error of that step.
+///
+/// The wrapped error is the `source` of each variant.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum EngineError {
+    Csv(csv::Error),
+    InvariantViolated(InvariantViolation),
+    Parse(TransactionLogError),
+    Tx(TxError),
+    #[cfg(feature = "io")]
+    Ingest(IngestError),
+    #[cfg(feature = "io")]
+    Output(OutputError),
+    /// Preflight found errors, so nothing was executed.
+    Preflight(PreflightReport),
+    Io(std::io::Error),
+}
+
+impl EngineError {
+    /// Stable snake_case code identifying the error in reports, which is the code of
+    /// the wrapped error when it has one.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::Csv(_) => "csv",
+            EngineError::InvariantViolated(_) => "invariant_violated",
+            EngineError::Parse(err) => err.code(),
+            EngineError::Tx(err) => err.code(),
+            #[cfg(feature = "io")]
+            EngineError::Ingest(err) => err.code(),
+            #[cfg(feature = "io")]
+            EngineError::Output(_) => "output",
+            EngineError::Preflight(_) => "preflight",
+            EngineError::Io(_) => "io",
+        }
+    }
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::Csv(err) => write!(f, "failed to write accounts: {}", err),
+            EngineError::InvariantViolated(violation) => {
+                write!(f, "ledger invariant violated: {}", violation)
+            }
+            EngineError::Parse(err) => write!(f, "malformed row: {}", err),
+            EngineError::Tx(err) => write!(f, "transaction rejected: {}", err),
+            #[cfg(feature = "io")]
+            EngineError::Ingest(err) => write!(f, "{}", err),
+            #[cfg(feature = "io")]
+            EngineError::Output(err) => write!(f, "{}", err),
+            EngineError::Preflight(report) => {
+                write!(f, "preflight found {} errors", report.errors.len())
+            }
+            EngineError::Io(err) => write!(f, "I/O error during run: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Csv(err) => Some(err),
+            EngineError::InvariantViolated(violation) => Some(violation),
+            EngineError::Parse(err) => Some(err),
+            EngineError::Tx(err) => Some(err),
+            #[cfg(feature = "io")]
+            EngineError::Ingest(err) => Some(err),
+            #[cfg(feature = "io")]
+            EngineError::Output(err) => Some(err),
+            EngineError::Preflight(_) => None,
+            EngineError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<csv::Error> for EngineError {
+    fn from(err: csv::Error) -> Self {
+        EngineError::Csv(err)
+    }
+}
+
+impl From<InvariantViolation> for EngineError {
+    fn from(violation: InvariantViolation) -> Self {
+        EngineError::InvariantViolated(violation)
+    }
+}
+
+impl From<TransactionLogError> for EngineError {
+    fn from(err: TransactionLogError) -> Self {
+        EngineError::Parse(err)
+    }
+}
+
+impl From<TxError> for EngineError {
+    fn from(err: TxError) -> Self {
+        EngineError::Tx(err)
+    }
+}
+
+#[cfg(feature = "io")]
+impl From<IngestError> for EngineError {
+    fn from(err: IngestError) -> Self {
+        EngineError::Ingest(err)
+    }
+}
+
+#[cfg(feature = "io")]
+impl From<OutputError> for EngineError {
+    fn from(err: OutputError) -> Self {
+        EngineError::Output(err)
+    }
+}
+
+impl From<TwoPassError> for EngineError {
+    fn from(err: TwoPassError) -> Self {
+        match err {
+            TwoPassError::Preflight(report) => EngineError::Preflight(report),
+            TwoPassError::Io(err) => EngineError::Io(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for EngineError {
+    fn from(err: std::io::Error) -> Self {
+        EngineError::Io(err)
+    }
+}
+
+/// Applies the transactions of a CSV input to a new ledger and returns the accounts
+/// as CSV sorted by client id. Malformed rows and rejected transactions are skipped,
+/// the same way as in `read_data`.
+pub fn process_csv_str(input: &str) -> Result<String, EngineError> {
+    let mut ledger = Ledger::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input.strip_prefix('\u{feff}').unwrap_or(input).as_bytes());
+    let headers = reader.headers()?.clone();
+    for mut record in reader.records().filter_map(Result::ok) {
+        let parsed = NumberLocale::default()
+            .normalize_amount_field(&headers, &mut record)
+            .and_then(|()| {
+                record
+                    .deserialize::<TransactionLog>(Some(&headers))
+                    .map_err(|_err| TransactionLogError::InvalidTxType)
+            })
+            .and_then(Transaction::try_from);
+        if let Ok(tx) = parsed {
+            ledger.execute_catch_unwind(&tx).ok();
+        }
+    }
+    ledger
+        .verify_invariants()
+        .map_err(EngineError::InvariantViolated)?;
+    Ok(accounts_csv(ledger.view())?)
+}
+
+/// Writes `transactions` as a CSV input with a header, e.g. to generate test data.
+pub fn write_transactions_csv<'a>(
+    writer: impl Write,
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for tx in transactions {
+        writer.serialize(TransactionLog::from(tx))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serializes the accounts of the ledger as CSV sorted by client id.
+pub(crate) fn accounts_csv(ledger: LedgerView<'_>) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for user_account in ledger.accounts_sorted() {
+        writer.serialize(AccountLog::from(user_account))?;
+    }
+    let output = writer
+        .into_inner()
+        .map_err(|err| csv::Error::from(err.into_error()))?;
+    Ok(String::from_utf8(output).expect("CSV writer produces UTF-8 from UTF-8 fields"))
+}
+
+/// A non-blank data line of the input.
+pub(crate) struct Row<'a> {
+    /// 1-based line number within the input.
+    pub(crate) line: u64,
+    /// The line as read, without its line terminator.
+    pub(crate) raw: &'a [u8],
+    pub(crate) headers: &'a StringRecord,
+    pub(crate) log: Result<TransactionLog, TransactionLogError>,
+}
+
+/// Reads the input line by line, taking the first non-blank line as the header and
+/// skipping blank ones, so that every row keeps its exact line number.
+///
+/// Quoted fields spanning multiple lines are not supported.
+pub(crate) struct RowReader<R> {
+    reader: R,
+    headers: Option<StringRecord>,
+    record: StringRecord,
+    line: Vec<u8>,
+    line_number: u64,
+    number_locale: NumberLocale,
+    amount_parsing: AmountParsing,
+}
+
+impl<R: BufRead> RowReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            headers: None,
+            record: StringRecord::new(),
+            line: Vec::new(),
+            line_number: 0,
+            number_locale: NumberLocale::default(),
+            amount_parsing: AmountParsing::default(),
+        }
+    }
+
+    pub(crate) fn with_number_locale(mut self, number_locale: NumberLocale) -> Self {
+        self.number_locale = number_locale;
+        self
+    }
+
+    pub(crate) fn with_amount_parsing(mut self, amount_parsing: AmountParsing) -> Self {
+        self.amount_parsing = amount_parsing;
+        self
+    }
+
+    pub(crate) fn next_row(&mut self) -> std::io::Result<Option<Row<'_>>> {
+        loop {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            if self.line_number == 1 && self.line.starts_with(UTF8_BOM) {
+                self.line.drain(..UTF8_BOM.len());
+            }
+            if self
+                .line
+                .iter()
+                .all(|byte| byte.is_ascii_whitespace() || *byte == b',')
+            {
+                continue;
+            }
+            if self.headers.is_none() {
+                read_line_record(&self.line, &mut self.record).ok();
+                self.headers = Some(self.record.clone());
+                continue;
+            }
+            let headers = self.headers.as_ref().expect("Headers were read above");
+            let log = parse_normalized_line(
+                &self.line,
+                headers,
+                &mut self.record,
+                self.number_locale,
+                &self.amount_parsing,
+            );
+            let line = &self.line;
+            let raw = line
+                .strip_suffix(b"\n")
+                .map(|raw| raw.strip_suffix(b"\r").unwrap_or(raw))
+                .unwrap_or(line);
+            return Ok(Some(Row {
+                line: self.line_number,
+                raw,
+                headers,
+                log,
+            }));
+        }
+    }
+}
+
+/// Calls `on_row` with every row of the input, see `RowReader`.
+pub(crate) fn read_rows(
+    reader: impl BufRead,
+    mut on_row: impl FnMut(Row<'_>),
+) -> std::io::Result<()> {
+    let mut rows = RowReader::new(reader);
+    while let Some(row) = rows.next_row()? {
+        on_row(row);
+    }
+    Ok(())
+}
+
+/// Columns of the input, in order. Re-exported as `ingest::EXPECTED_HEADERS`, and
+/// defined here so that builds without the `io` feature share it.
+pub const EXPECTED_HEADERS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// UTF-8 byte order mark, which spreadsheet exports on Windows put before the header.
+pub(crate) const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+pub(crate) fn read_line_record(line: &[u8], record: &mut StringRecord) -> csv::Result<bool> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(line)
+        .read_record(record)
+}
+
+/// Parses a single CSV line, which must have as many fields as `headers`.
+pub(crate) fn parse_line(
+    line: &[u8],
+    headers: &StringRecord,
+    record: &mut StringRecord,
+    number_locale: NumberLocale,
+) -> Result<TransactionLog, TransactionLogError> {
+    parse_normalized_line(
+        line,
+        headers,
+        record,
+        number_locale,
+        &AmountParsing::default(),
+    )
+}
+
+/// Like `parse_line`, for amounts decorated as `amount_parsing` allows.
+pub(crate) fn parse_normalized_line(
+    line: &[u8],
+    headers: &StringRecord,
+    record: &mut StringRecord,
+    number_locale: NumberLocale,
+    amount_parsing: &AmountParsing,
+) -> Result<TransactionLog, TransactionLogError> {
+    match read_line_record(line, record) {
+        Ok(true) => {
+            TransactionLogError::check_field_count(record.len(), headers.len())?;
+            amount_parsing.normalize_amount_field(number_locale, headers, record)?;
+            record
+                .deserialize::<TransactionLog>(Some(headers))
+                .map_err(|_err| TransactionLogError::InvalidTxType)
+        }
+        _ => Err(TransactionLogError::InvalidTxType),
+    }
+}
+
+/// Statistics of an executed run.
+#[derive(Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct RunStats {
+    /// Id of the run, set by `Pipeline::run`, see `Pipeline::run_id`.
+    pub run_id: Option<RunId>,
+    pub rows_executed: u64,
+    /// Postings derived from the rows and executed, which `rows_executed` does not
+    /// count, see `accounting::postings`.
+    pub derived_postings: u64,
+    /// Derived postings which failed, leaving the rows they were derived from applied.
+    pub derived_postings_rejected: u64,
+    /// Rows accepted as duplicates without any effect, see `TxOutcome::Duplicate`.
+    pub rows_duplicate: u64,
+    /// Resolves and chargebacks held until their dispute arrives, see
+    /// `TxOutcome::Deferred`.
+    pub rows_deferred: u64,
+    pub rows_rejected: u64,
+    pub rows_malformed: u64,
+    /// Rows not executed because a pipeline filter did not accept them.
+    pub rows_filtered: u64,
+    /// Rejected disputes, resolves, chargebacks, adjustments, captures and releases
+    /// referring to the tx id of a filtered row, most likely rejected because that row
+    /// was not executed.
+    pub rows_rejected_after_filter: u64,
+    /// Malformed and rejected rows written to the quarantine.
+    pub rows_quarantined: u64,
+    /// Preflight warnings of a two-pass run.
+    pub preflight_warnings: Vec<PreflightFinding>,
+    /// Rejected disputes, resolves, chargebacks and adjustments under
+    /// `DisputeTracking::Disabled`, which an input expected to have none has.
+    pub rows_dispute_tracking_disabled: u64,
+    /// Rejected rows whose execution panicked, see `Ledger::execute_catch_unwind`.
+    pub rows_panicked: u64,
+    /// Outcome of `Ledger::verify_invariants` at the end of a run which left the
+    /// ledger poisoned.
+    pub poison_check: Option<Result<(), InvariantViolation>>,
+    /// Audit records and ledger events dropped under `SinkFailurePolicy::Ignore`.
+    pub events_dropped: u64,
+    /// Deposits applied although they exceeded the velocity limit, see
+    /// `VelocityAction::Flag`.
+    pub velocity_flagged: u64,
+    /// Disputes, resolves and chargebacks applied although the amount they stated
+    /// differed from the deposit's, see `DisputeAmountHandling::WarnOnMismatch`.
+    pub dispute_amount_mismatches: u64,
+    /// Sink failure which stopped the run. Rows before it, and the row whose record
+    /// failed, stay applied.
+    pub sink_error: Option<SinkError>,
+    /// Digest of every transaction the ledger executed, including those before the
+    /// run, when enabled with `LedgerBuilder::execution_digest`.
+    pub execution_digest: Option<LedgerDigest>,
+    /// Top-K lists of the run, when enabled with `Pipeline::top_k`.
+    pub top_k: Option<TopKReport>,
+    /// Distribution of the amounts of deposits and withdrawals, when enabled with
+    /// `Pipeline::distribution_stats`.
+    pub distribution: Option<DistributionReport>,
+    /// Rows matching each rule of `Pipeline::tag_rules` by rule name, including
+    /// rules which matched none.
+    pub tag_counts: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub enum TwoPassError {
+    /// Preflight found errors, so nothing was executed.
+    Preflight(PreflightReport),
+    Io(std::io::Error),
+}
+
+impl Display for TwoPassError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TwoPassError::Preflight(report) => {
+                write!(f, "preflight found {} errors", report.errors.len())
+            }
+            TwoPassError::Io(err) => write!(f, "I/O error during run: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TwoPassError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TwoPassError::Preflight(_) => None,
+            TwoPassError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for TwoPassError {
+    fn from(err: std::io::Error) -> Self {
+        TwoPassError::Io(err)
+    }
+}
+
+/// Runs files through a ledger with blocking IO.
+pub struct PaymentsEngine {
+    ledger: Ledger,
+    strict_order: bool,
+    quarantine: Option<Quarantine<Box<dyn Write + Send>>>,
+    run_id: Option<RunId>,
+}
+
+impl PaymentsEngine {
+    pub fn new(ledger: Ledger) -> Self {
+        Self {
+            ledger,
+            strict_order: false,
+            quarantine: None,
+            run_id: None,
+        }
+    }
+
+    /// Writes malformed and rejected rows to `writer` as CSV, with the header of the
+    /// input followed by `error_code` and `line_number`. The header is written with
+    /// the first quarantined row.
+    pub fn quarantine(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.quarantine = Some(Quarantine::new(Box::new(writer)));
+        self
+    }
+
+    /// Makes references preceding their transaction preflight errors instead of warnings.
+    pub fn strict_order(mut self, strict_order: bool) -> Self {
+        self.strict_order = strict_order;
+        self
+    }
+
+    /// Runs under `run_id` instead of a generated id, see `Pipeline::run_id`.
+    pub fn run_id(mut self, run_id: RunId) -> Self {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn into_ledger(self) -> Ledger {
+        self.ledger
+    }
+
+    /// Validates the whole file with `preflight` and executes it only when no errors
+    /// were found.
+    pub fn run_two_pass(&mut self, path: impl AsRef<Path>) -> Result<RunStats, TwoPassError> {
+        let path = path.as_ref();
+        let report = preflight(BufReader::new(File::open(path)?), self.strict_order)?;
+        if !report.is_clean() {
+            return Err(TwoPassError::Preflight(report));
+        }
+
+        let stats = self.execute_rows(BufReader::new(File::open(path)?))?;
+        Ok(RunStats {
+            preflight_warnings: report.warnings,
+            ..stats
+        })
+    }
+
+    /// Executes the file in a single pass, skipping malformed and rejected rows.
+    pub fn run(&mut self, path: impl AsRef<Path>) -> Result<RunStats, EngineError> {
+        Ok(self.execute_rows(BufReader::new(File::open(path)?))?)
+    }
+
+    fn execute_rows(&mut self, reader: impl BufRead) -> std::io::Result<RunStats> {
+        let pipeline = Pipeline::new(CsvSource::new(reader)).quarantine(self.quarantine.as_mut());
+        match &self.run_id {
+            Some(run_id) => pipeline.run_id(run_id.clone()).run(&mut self.ledger),
+            None => pipeline.run(&mut self.ledger),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preflight::PreflightIssue;
+    use crate::test_utils::write_input;
+
+    #[test]
+    fn processes_csv_string() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 2, 1, 2.0\n\
+                     deposit, 1, 2, 1.5\n\
+                     withdrawal, 1, 3, 0.5\n\
+                     refund, 1, 4, 1.0\n\
+                     dispute, 2, 1,\n\
+                     withdrawal, 2, 5, 1.0\n\
+                     chargeback, 2, 1,\n";
+        assert_eq!(
+            process_csv_str(input).unwrap(),
+            "client,available,held,total,locked\n1,1.0,0,1.0,false\n2,0,0,0,true\n"
+        );
+    }
+
+    #[test]
+    fn written_transactions_parse_back() {
+        use crate::accounting::transactions::{Adjustment, Deposit, Dispute, Withdrawal};
+        use crate::pipeline::TransactionSource;
+        use rust_decimal_macros::dec;
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, dec!(2.5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(0.0001))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Adjustment(Adjustment::new(2, 3)),
+        ];
+        let mut output = Vec::new();
+        write_transactions_csv(&mut output, &transactions).unwrap();
+        let input = String::from_utf8(output).unwrap();
+        assert_eq!(
+            input,
+            "type,client,tx,amount\n\
+             deposit,1,1,2.5\n\
+             withdrawal,1,2,0.0001\n\
+             dispute,1,1,\n\
+             adjustment,2,3,\n"
+        );
+        let mut source = CsvSource::new(input.as_bytes());
+        let mut parsed = Vec::new();
+        while let Some(row) = source.next_row().unwrap() {
+            parsed.push(row.tx.unwrap());
+        }
+        assert_eq!(parsed, transactions);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(process_csv_str("").unwrap(), "");
+        assert_eq!(process_csv_str("type,client,tx,amount\n").unwrap(), "");
+    }
+
+    #[test]
+    fn rows_keep_line_numbers() {
+        let data =
+            "\n type,client,tx,amount\r\n\ndeposit, 1, 1, 1.0\r\n,,\nrefund,1,2,1.0\ndispute,1,1";
+        let mut rows = Vec::new();
+        read_rows(data.as_bytes(), |row| {
+            rows.push((row.line, row.log.is_ok()))
+        })
+        .unwrap();
+        assert_eq!(rows, vec![(4, true), (6, false), (7, false),]);
+    }
+
+    #[test]
+    fn two_pass_executes_clean_file() {
+        let path = write_input(
+            "two_pass_clean",
+            "type, client, tx, amount\n\
+             dispute, 1, 1,\n\
+             deposit, 1, 1, 5.0\n\
+             withdrawal, 1, 2, 2.0\n\
+             withdrawal, 1, 3, 4.0\n",
+        );
+        let mut engine = PaymentsEngine::new(Ledger::new());
+        let stats = engine.run_two_pass(&path).unwrap();
+        assert_eq!(stats.rows_executed, 2);
+        assert_eq!(stats.rows_rejected, 2);
+        assert_eq!(stats.rows_malformed, 0);
+        assert_eq!(stats.preflight_warnings.len(), 1);
+        assert_eq!(
+            engine
+                .ledger()
+                .account(1)
+                .map(|account| account.available()),
+            Some(rust_decimal_macros::dec!(3.0))
+        );
+
+        let mut strict = PaymentsEngine::new(Ledger::new()).strict_order(true);
+        match strict.run_two_pass(&path) {
+            Err(TwoPassError::Preflight(report)) => {
+                assert_eq!(report.errors.len(), 1);
+                assert_eq!(report.errors[0].issue, PreflightIssue::ReferenceBeforeTx(1));
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(strict.ledger().accounts_iter().count(), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn quarantined_rows_replay_to_clean_result() {
+        let clean = write_input(
+            "quarantine_clean",
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             deposit, 1, 2, 5.0\n\
+             withdrawal, 1, 3, 4.0\n\
+             dispute, 1, 2,\n\
+             deposit, 2, 4, 1.0\n\
+             resolve, 1, 2,\n",
+        );
+        let dirty = write_input(
+            "quarantine_dirty",
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             deposit, 1, 2, 5.0.0\r\n\
+             withdrawal, 1, 3, 4.0\n\
+             dispute, 1, 2,\n\
+             \n\
+             deposit, 2, 4\n\
+             resolve, 1, 2,",
+        );
+        let quarantine_path = write_input("quarantine_rows", "");
+
+        let mut expected = PaymentsEngine::new(Ledger::new());
+        expected.run(&clean).unwrap();
+
+        let mut engine =
+            PaymentsEngine::new(Ledger::new()).quarantine(File::create(&quarantine_path).unwrap());
+        let stats = engine.run(&dirty).unwrap();
+        assert_eq!(stats.rows_executed, 2);
+        assert_eq!(stats.rows_rejected, 2);
+        assert_eq!(stats.rows_malformed, 2);
+        assert_eq!(stats.rows_quarantined, 4);
+        let quarantined = std::fs::read_to_string(&quarantine_path).unwrap();
+        assert_eq!(
+            quarantined,
+            "type,client,tx,amount,error_code,line_number,error_column,error_value,error_reason\n\
+             deposit, 1, 2, 5.0.0,trailing_characters_in_amount,3,amount,5.0.0,\
+             trailing_characters at offset 3\n\
+             dispute, 1, 2,,origin_tx_not_found,5,,,\n\
+             deposit, 2, 4,too_few_fields,7,,,\n\
+             resolve, 1, 2,,origin_tx_not_found,8,,,\n"
+        );
+
+        let fixed = write_input(
+            "quarantine_fixed",
+            &quarantined
+                .replace("5.0.0", "5.0")
+                .replace("deposit, 2, 4,", "deposit, 2, 4, 1.0,"),
+        );
+        let mut engine = PaymentsEngine::new(engine.into_ledger());
+        let stats = engine.run(&fixed).unwrap();
+        assert_eq!(stats.rows_executed, 4);
+        assert_eq!(
+            accounts_csv(engine.ledger().view()).unwrap(),
+            accounts_csv(expected.ledger().view()).unwrap()
+        );
+        engine.ledger().verify_invariants().unwrap();
+
+        for path in [clean, dirty, quarantine_path, fixed] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn two_pass_does_not_execute_with_errors() {
+        let path = write_input(
+            "two_pass_errors",
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 1, 1, 5.0\n",
+        );
+        let mut engine = PaymentsEngine::new(Ledger::new());
+        assert!(matches!(
+            engine.run_two_pass(&path),
+            Err(TwoPassError::Preflight(_))
+        ));
+        assert_eq!(engine.ledger().accounts_iter().count(), 0);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            engine.run_two_pass(&path),
+            Err(TwoPassError::Io(_))
+        ));
+    }
+
+    /// Errors from `err` down its chain of sources.
+    fn source_chain(err: &EngineError) -> Vec<&(dyn std::error::Error + 'static)> {
+        std::iter::successors(Some(err as &(dyn std::error::Error + 'static)), |err| {
+            err.source()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn engine_error_sources() {
+        let err = EngineError::from(TxError::InsufficientFunds);
+        assert_eq!(err.code(), "insufficient_funds");
+        let chain = source_chain(&err);
+        assert_eq!(chain.len(), 2);
+        assert_eq!(
+            chain[1].downcast_ref::<TxError>(),
+            Some(&TxError::InsufficientFunds)
+        );
+
+        let err = EngineError::from(TransactionLogError::MissingAmount);
+        assert_eq!(err.code(), "missing_amount");
+        assert_eq!(
+            source_chain(&err)[1].downcast_ref::<TransactionLogError>(),
+            Some(&TransactionLogError::MissingAmount)
+        );
+
+        let violation = InvariantViolation::UnbalancedLiabilities {
+            liabilities: rust_decimal::Decimal::ONE,
+            accounts_total: rust_decimal::Decimal::ZERO,
+            dust: rust_decimal::Decimal::ZERO,
+        };
+        let err = EngineError::from(violation);
+        assert_eq!(err.code(), "invariant_violated");
+        assert!(source_chain(&err)[1]
+            .downcast_ref::<InvariantViolation>()
+            .is_some());
+
+        let mut engine = PaymentsEngine::new(Ledger::new());
+        let err = engine.run("missing/engine_error_sources.csv").unwrap_err();
+        assert_eq!(err.code(), "io");
+        let io_error = source_chain(&err)[1]
+            .downcast_ref::<std::io::Error>()
+            .unwrap();
+        assert_eq!(io_error.kind(), std::io::ErrorKind::NotFound);
+
+        let path = write_input(
+            "engine_error_preflight",
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 1, 1, 5.0\n",
+        );
+        let err = EngineError::from(engine.run_two_pass(&path).unwrap_err());
+        assert!(matches!(&err, EngineError::Preflight(report) if report.errors.len() == 1));
+        assert_eq!(err.code(), "preflight");
+        assert_eq!(source_chain(&err).len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "io")]
+    #[tokio::test]
+    async fn io_error_through_ingest_error() {
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let err = crate::read_data(
+            "missing/io_error_through_ingest_error.csv".to_string(),
+            sender,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), "io");
+        let chain = source_chain(&err);
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain[1].downcast_ref::<IngestError>().unwrap().code(), "io");
+        let io_error = chain[2].downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io_error.kind(), std::io::ErrorKind::NotFound);
+    }
+}
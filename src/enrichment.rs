@@ -0,0 +1,216 @@
+//! Flags and notes which operations keep about clients, such as `vip` or
+//! `under_review`, joined to the account output so that the report is self-contained.
+//! They decorate the output only and never reach the ledger.
+use crate::accounting::view::LedgerView;
+use crate::core_types::ClientId;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::path::Path;
+
+/// Separator of the flags within the `flags` column.
+pub const FLAG_SEPARATOR: char = ';';
+
+#[derive(Deserialize)]
+struct EnrichmentRow {
+    client: ClientId,
+    flags: String,
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientEnrichment {
+    pub flags: Vec<String>,
+    pub note: String,
+}
+
+impl ClientEnrichment {
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|own| own == flag)
+    }
+
+    /// Flags joined with `FLAG_SEPARATOR`, as in the `flags` column.
+    pub fn flags_field(&self) -> String {
+        self.flags.join(&FLAG_SEPARATOR.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub enum EnrichmentError {
+    Csv(csv::Error),
+    DuplicateClient(ClientId),
+}
+
+impl Display for EnrichmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnrichmentError::Csv(err) => write!(f, "failed to read enrichment: {}", err),
+            EnrichmentError::DuplicateClient(client_id) => {
+                write!(f, "client {} appears twice in the enrichment", client_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnrichmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EnrichmentError::Csv(err) => Some(err),
+            EnrichmentError::DuplicateClient(_) => None,
+        }
+    }
+}
+
+impl From<csv::Error> for EnrichmentError {
+    fn from(err: csv::Error) -> Self {
+        EnrichmentError::Csv(err)
+    }
+}
+
+/// Enrichment of the clients, optionally restricting the report to clients with a
+/// flag.
+#[derive(Debug, Clone, Default)]
+pub struct Enrichment {
+    clients: HashMap<ClientId, ClientEnrichment>,
+    only_flag: Option<String>,
+}
+
+impl Enrichment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads CSV with `client,flags,note` columns, where `flags` are separated by
+    /// `FLAG_SEPARATOR` and both `flags` and `note` may be empty or left out.
+    pub fn read(reader: impl Read) -> Result<Self, EnrichmentError> {
+        let mut enrichment = Self::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(reader);
+        let headers = reader.headers()?.clone();
+        for record in reader.records() {
+            let mut record = record?;
+            // Rows may leave out the trailing columns.
+            while record.len() < headers.len() {
+                record.push_field("");
+            }
+            let row: EnrichmentRow = record.deserialize(Some(&headers))?;
+            if enrichment.clients.contains_key(&row.client) {
+                return Err(EnrichmentError::DuplicateClient(row.client));
+            }
+            let flags = row
+                .flags
+                .split(FLAG_SEPARATOR)
+                .map(str::trim)
+                .filter(|flag| !flag.is_empty())
+                .map(str::to_string)
+                .collect();
+            enrichment.insert(
+                row.client,
+                ClientEnrichment {
+                    flags,
+                    note: row.note,
+                },
+            );
+        }
+        Ok(enrichment)
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, EnrichmentError> {
+        let file = std::fs::File::open(path).map_err(csv::Error::from)?;
+        Self::read(std::io::BufReader::new(file))
+    }
+
+    pub fn insert(&mut self, client_id: ClientId, enrichment: ClientEnrichment) {
+        self.clients.insert(client_id, enrichment);
+    }
+
+    /// Restricts the report to the clients with `flag`.
+    pub fn only_flag(mut self, flag: impl Into<String>) -> Self {
+        self.only_flag = Some(flag.into());
+        self
+    }
+
+    pub fn get(&self, client_id: ClientId) -> Option<&ClientEnrichment> {
+        self.clients.get(&client_id)
+    }
+
+    /// Whether the report includes the account of the client.
+    pub fn includes(&self, client_id: ClientId) -> bool {
+        match &self.only_flag {
+            Some(flag) => self
+                .get(client_id)
+                .is_some_and(|enrichment| enrichment.has_flag(flag)),
+            None => true,
+        }
+    }
+
+    /// Number of enriched clients without an account in `ledger`, whose enrichment
+    /// is not written anywhere.
+    pub fn unknown_clients<'a>(&self, ledger: impl Into<LedgerView<'a>>) -> usize {
+        let ledger: LedgerView = ledger.into();
+        self.clients
+            .keys()
+            .filter(|client_id| ledger.account(**client_id).is_none())
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Transaction};
+    use crate::accounting::Ledger;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn reads_flags_and_notes() {
+        let enrichment = Enrichment::read(
+            "client,flags,note\n\
+             1,vip; under_review,\"called, awaiting documents\"\n\
+             2,,\n\
+             3,collections\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            enrichment.get(1),
+            Some(&ClientEnrichment {
+                flags: vec!["vip".to_string(), "under_review".to_string()],
+                note: "called, awaiting documents".to_string(),
+            })
+        );
+        assert_eq!(enrichment.get(1).unwrap().flags_field(), "vip;under_review");
+        assert_eq!(enrichment.get(2), Some(&ClientEnrichment::default()));
+        assert!(enrichment.get(3).unwrap().has_flag("collections"));
+        assert_eq!(enrichment.get(4), None);
+        assert!(enrichment.includes(4));
+
+        let enrichment = enrichment.only_flag("under_review");
+        assert!(enrichment.includes(1));
+        assert!(!enrichment.includes(3));
+        assert!(!enrichment.includes(4));
+    }
+
+    #[test]
+    fn duplicate_clients_fail() {
+        assert!(matches!(
+            Enrichment::read("client,flags,note\n1,vip,\n1,,\n".as_bytes()),
+            Err(EnrichmentError::DuplicateClient(1))
+        ));
+    }
+
+    #[test]
+    fn counts_clients_without_account() {
+        let mut ledger = Ledger::new();
+        ledger
+            .execute(&Transaction::Deposit(Deposit::new(1, 1, dec!(1))))
+            .unwrap();
+        let enrichment =
+            Enrichment::read("client,flags,note\n1,vip,\n7,vip,\n9,,\n".as_bytes()).unwrap();
+        assert_eq!(enrichment.unknown_clients(&ledger), 2);
+    }
+}
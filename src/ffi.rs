@@ -0,0 +1,276 @@
+//! C ABI of the engine for in-process use from other languages.
+//!
+//! The header is generated with `cbindgen --config cbindgen.toml --output
+//! include/payments_engine.h`. Functions never unwind into the caller: panics are
+//! caught and reported as `PE_ERR_PANIC`. The message of the last failed call on
+//! the calling thread is available through `pe_last_error_message`.
+use crate::accounting::transactions::{Transaction, TransactionLogError};
+use crate::accounting::{panic_message, Ledger, TxError};
+use crate::engine::{accounts_csv, parse_line, EXPECTED_HEADERS};
+use crate::number_locale::NumberLocale;
+use csv::StringRecord;
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::fmt::{Display, Formatter};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+pub const PE_OK: i32 = 0;
+pub const PE_ERR_NULL_POINTER: i32 = -1;
+pub const PE_ERR_INVALID_UTF8: i32 = -2;
+pub const PE_ERR_INVALID_TX_TYPE: i32 = -3;
+pub const PE_ERR_MISSING_AMOUNT: i32 = -4;
+pub const PE_ERR_OUTPUT: i32 = -5;
+pub const PE_ERR_PANIC: i32 = -6;
+pub const PE_ERR_TOO_FEW_FIELDS: i32 = -7;
+pub const PE_ERR_TOO_MANY_FIELDS: i32 = -8;
+pub const PE_ERR_AMBIGUOUS_AMOUNT: i32 = -9;
+pub const PE_ERR_REFERENCE_TOO_LONG: i32 = -10;
+pub const PE_ERR_NEGATIVE_AMOUNT_NOT_ALLOWED: i32 = -11;
+pub const PE_ERR_TRAILING_CHARACTERS_IN_AMOUNT: i32 = -12;
+pub const PE_ERR_REASON_TOO_LONG: i32 = -13;
+pub const PE_ERR_EXCESS_PRECISION: i32 = -14;
+pub const PE_ERR_CLIENT_ACCOUNT_LOCKED: i32 = 1;
+pub const PE_ERR_INSUFFICIENT_FUNDS: i32 = 2;
+pub const PE_ERR_CLIENT_ACCOUNT_NOT_FOUND: i32 = 3;
+pub const PE_ERR_ORIGIN_TX_NOT_FOUND: i32 = 4;
+pub const PE_ERR_TX_ALREADY_DISPUTED: i32 = 5;
+pub const PE_ERR_TX_NOT_DISPUTED: i32 = 6;
+pub const PE_ERR_TX_CHARGED_BACK: i32 = 7;
+pub const PE_ERR_TX_REVERSED: i32 = 8;
+pub const PE_ERR_HELD_BALANCE_INCONSISTENT: i32 = 9;
+pub const PE_ERR_BALANCE_OVERFLOW: i32 = 10;
+pub const PE_ERR_HOLD_NOT_FOUND: i32 = 11;
+pub const PE_ERR_HOLD_ALREADY_SETTLED: i32 = 12;
+pub const PE_ERR_VELOCITY_LIMIT_EXCEEDED: i32 = 13;
+pub const PE_ERR_TX_NOT_DISPUTABLE: i32 = 14;
+pub const PE_ERR_DISPUTE_EXPOSURE_LIMIT_EXCEEDED: i32 = 15;
+pub const PE_ERR_DISPUTED_AMOUNT_MISMATCH: i32 = 16;
+pub const PE_ERR_WITHDRAWAL_NOT_FOUND: i32 = 17;
+pub const PE_ERR_WITHDRAWAL_ALREADY_SETTLED: i32 = 18;
+pub const PE_ERR_TX_NOT_WITHDRAWAL: i32 = 19;
+pub const PE_ERR_RESOLVE_BEFORE_DISPUTE: i32 = 20;
+pub const PE_ERR_CHARGEBACK_BEFORE_DISPUTE: i32 = 21;
+pub const PE_ERR_DUPLICATE_RESOLVE: i32 = 22;
+pub const PE_ERR_TOO_MANY_OPEN_DISPUTES: i32 = 23;
+pub const PE_ERR_UNKNOWN_DISPUTE_REASON: i32 = 24;
+pub const PE_ERR_DISPUTE_WINDOW_EXPIRED: i32 = 25;
+pub const PE_ERR_RESERVED_TX_ID: i32 = 26;
+pub const PE_ERR_TX_ID_REUSE_AFTER_SETTLEMENT: i32 = 27;
+pub const PE_ERR_DISPUTE_TRACKING_DISABLED: i32 = 28;
+pub const PE_ERR_DUPLICATE_TX_ID: i32 = 29;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Ledger handle owned by the caller.
+pub struct PeLedger {
+    ledger: Ledger,
+}
+
+enum Failure {
+    NullPointer,
+    InvalidUtf8(std::str::Utf8Error),
+    Parse(TransactionLogError),
+    Rejected(TxError),
+    Output(csv::Error),
+    Panic(String),
+}
+
+impl Failure {
+    fn status(&self) -> i32 {
+        match self {
+            Failure::NullPointer => PE_ERR_NULL_POINTER,
+            Failure::InvalidUtf8(_) => PE_ERR_INVALID_UTF8,
+            Failure::Parse(TransactionLogError::InvalidTxType) => PE_ERR_INVALID_TX_TYPE,
+            Failure::Parse(TransactionLogError::MissingAmount) => PE_ERR_MISSING_AMOUNT,
+            Failure::Parse(TransactionLogError::TooFewFields) => PE_ERR_TOO_FEW_FIELDS,
+            Failure::Parse(TransactionLogError::TooManyFields) => PE_ERR_TOO_MANY_FIELDS,
+            Failure::Parse(TransactionLogError::AmbiguousAmount) => PE_ERR_AMBIGUOUS_AMOUNT,
+            Failure::Parse(TransactionLogError::ReferenceTooLong) => PE_ERR_REFERENCE_TOO_LONG,
+            Failure::Parse(TransactionLogError::ReasonTooLong) => PE_ERR_REASON_TOO_LONG,
+            Failure::Parse(TransactionLogError::NegativeAmountNotAllowed) => {
+                PE_ERR_NEGATIVE_AMOUNT_NOT_ALLOWED
+            }
+            Failure::Parse(TransactionLogError::TrailingCharactersInAmount { .. }) => {
+                PE_ERR_TRAILING_CHARACTERS_IN_AMOUNT
+            }
+            Failure::Parse(TransactionLogError::ExcessPrecision) => PE_ERR_EXCESS_PRECISION,
+            Failure::Rejected(err) => match err {
+                TxError::ClientAccountLocked => PE_ERR_CLIENT_ACCOUNT_LOCKED,
+                TxError::InsufficientFunds => PE_ERR_INSUFFICIENT_FUNDS,
+                TxError::ClientAccountNotFound => PE_ERR_CLIENT_ACCOUNT_NOT_FOUND,
+                TxError::OriginTxNotFound => PE_ERR_ORIGIN_TX_NOT_FOUND,
+                TxError::TxAlreadyDisputed => PE_ERR_TX_ALREADY_DISPUTED,
+                TxError::TxNotDisputed => PE_ERR_TX_NOT_DISPUTED,
+                TxError::TxChargedBack => PE_ERR_TX_CHARGED_BACK,
+                TxError::TxReversed => PE_ERR_TX_REVERSED,
+                TxError::HeldBalanceInconsistent { .. } => PE_ERR_HELD_BALANCE_INCONSISTENT,
+                TxError::BalanceOverflow => PE_ERR_BALANCE_OVERFLOW,
+                TxError::HoldNotFound => PE_ERR_HOLD_NOT_FOUND,
+                TxError::HoldAlreadySettled => PE_ERR_HOLD_ALREADY_SETTLED,
+                TxError::VelocityLimitExceeded => PE_ERR_VELOCITY_LIMIT_EXCEEDED,
+                TxError::TxNotDisputable => PE_ERR_TX_NOT_DISPUTABLE,
+                TxError::DisputeExposureLimitExceeded { .. } => {
+                    PE_ERR_DISPUTE_EXPOSURE_LIMIT_EXCEEDED
+                }
+                TxError::DisputedAmountMismatch { .. } => PE_ERR_DISPUTED_AMOUNT_MISMATCH,
+                TxError::UnknownDisputeReason => PE_ERR_UNKNOWN_DISPUTE_REASON,
+                TxError::DisputeWindowExpired => PE_ERR_DISPUTE_WINDOW_EXPIRED,
+                TxError::WithdrawalNotFound => PE_ERR_WITHDRAWAL_NOT_FOUND,
+                TxError::WithdrawalAlreadySettled => PE_ERR_WITHDRAWAL_ALREADY_SETTLED,
+                TxError::TxNotWithdrawal => PE_ERR_TX_NOT_WITHDRAWAL,
+                TxError::ResolveBeforeDispute => PE_ERR_RESOLVE_BEFORE_DISPUTE,
+                TxError::ChargebackBeforeDispute => PE_ERR_CHARGEBACK_BEFORE_DISPUTE,
+                TxError::DuplicateResolve => PE_ERR_DUPLICATE_RESOLVE,
+                TxError::ReservedTxId => PE_ERR_RESERVED_TX_ID,
+                TxError::TxIdReuseAfterSettlement { .. } => PE_ERR_TX_ID_REUSE_AFTER_SETTLEMENT,
+                TxError::DisputeTrackingDisabled => PE_ERR_DISPUTE_TRACKING_DISABLED,
+                TxError::DuplicateTxId => PE_ERR_DUPLICATE_TX_ID,
+                TxError::TooManyOpenDisputes { .. } => PE_ERR_TOO_MANY_OPEN_DISPUTES,
+                TxError::InternalPanic { .. } => PE_ERR_PANIC,
+            },
+            Failure::Output(_) => PE_ERR_OUTPUT,
+            Failure::Panic(_) => PE_ERR_PANIC,
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::NullPointer => f.write_str("null pointer argument"),
+            Failure::InvalidUtf8(err) => write!(f, "row is not valid UTF-8: {}", err),
+            Failure::Parse(err) => write!(f, "invalid row: {}", err),
+            Failure::Rejected(err) => write!(f, "transaction rejected: {}", err),
+            Failure::Output(err) => write!(f, "failed to write accounts: {}", err),
+            Failure::Panic(message) => write!(f, "panic: {}", message),
+        }
+    }
+}
+
+/// Runs `body`, turning failures and panics into a status code and recording
+/// their message as the last error of the thread.
+fn boundary(body: impl FnOnce() -> Result<(), Failure>) -> i32 {
+    let failure = match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return PE_OK,
+        Ok(Err(failure)) => failure,
+        Err(payload) => Failure::Panic(panic_message(payload.as_ref())),
+    };
+    let message =
+        CString::new(failure.to_string().replace('\0', " ")).expect("NUL bytes were replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    failure.status()
+}
+
+/// Creates an empty ledger, or returns null on failure. Free it with `pe_ledger_free`.
+#[no_mangle]
+pub extern "C" fn pe_ledger_new() -> *mut PeLedger {
+    let mut handle = ptr::null_mut();
+    boundary(|| {
+        handle = Box::into_raw(Box::new(PeLedger {
+            ledger: Ledger::new(),
+        }));
+        Ok(())
+    });
+    handle
+}
+
+/// Frees a ledger created by `pe_ledger_new`. Null is ignored.
+///
+/// # Safety
+/// `ledger` must be null or a pointer returned by `pe_ledger_new` which was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pe_ledger_free(ledger: *mut PeLedger) {
+    if !ledger.is_null() {
+        boundary(|| {
+            drop(Box::from_raw(ledger));
+            Ok(())
+        });
+    }
+}
+
+/// Executes a single headerless CSV row `type,client,tx,amount`; the amount field
+/// may be empty but must be present. Returns `PE_OK`, a negative code when the row
+/// could not be applied at all, or a positive code when the ledger rejected the
+/// transaction.
+///
+/// A ledger which panicked during execution should not be used any further.
+///
+/// # Safety
+/// `ledger` must be a live pointer from `pe_ledger_new` and `row_ptr` must point to
+/// `row_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pe_execute_csv_row(
+    ledger: *mut PeLedger,
+    row_ptr: *const u8,
+    row_len: usize,
+) -> i32 {
+    boundary(|| {
+        let ledger = ledger.as_mut().ok_or(Failure::NullPointer)?;
+        if row_ptr.is_null() {
+            return Err(Failure::NullPointer);
+        }
+        let row = std::slice::from_raw_parts(row_ptr, row_len);
+        let row = std::str::from_utf8(row).map_err(Failure::InvalidUtf8)?;
+        let log = parse_line(
+            row.as_bytes(),
+            &StringRecord::from(EXPECTED_HEADERS.to_vec()),
+            &mut StringRecord::new(),
+            NumberLocale::default(),
+        )
+        .map_err(Failure::Parse)?;
+        let tx = Transaction::try_from(log).map_err(Failure::Parse)?;
+        ledger.ledger.execute(&tx).map_err(Failure::Rejected)
+    })
+}
+
+/// Writes the accounts as a NUL-terminated CSV string sorted by client id to
+/// `*out_buf_ptr` and its length without the terminator to `*out_len_ptr`. The
+/// string must be freed with `pe_string_free`.
+///
+/// # Safety
+/// `ledger` must be a live pointer from `pe_ledger_new`, the output pointers must
+/// be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn pe_export_accounts_csv(
+    ledger: *const PeLedger,
+    out_buf_ptr: *mut *mut c_char,
+    out_len_ptr: *mut usize,
+) -> i32 {
+    boundary(|| {
+        let ledger = ledger.as_ref().ok_or(Failure::NullPointer)?;
+        if out_buf_ptr.is_null() || out_len_ptr.is_null() {
+            return Err(Failure::NullPointer);
+        }
+        let output = accounts_csv(ledger.ledger.view()).map_err(Failure::Output)?;
+        let len = output.len();
+        let output = CString::new(output).expect("accounts CSV has no NUL bytes");
+        *out_buf_ptr = output.into_raw();
+        *out_len_ptr = len;
+        Ok(())
+    })
+}
+
+/// Returns a copy of the message of the last failed call on this thread, or null
+/// if no call failed yet. The string must be freed with `pe_string_free`.
+#[no_mangle]
+pub extern "C" fn pe_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null_mut(), |message| message.clone().into_raw())
+    })
+}
+
+/// Frees a string returned by this library. Null is ignored.
+///
+/// # Safety
+/// `string` must be null or a string returned by this library which was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pe_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
@@ -0,0 +1,310 @@
+//! Column-level context of malformed rows.
+//!
+//! Rows are parsed as a whole, so their errors only tell which rule failed. When a row
+//! is malformed because of a single value, its fields are checked one by one against
+//! the types of the columns to find the column at fault.
+use crate::accounting::transactions::{TransactionLogError, TxTypeTag};
+use crate::core_types::{ClientId, TxId};
+use crate::engine::read_line_record;
+use crate::number_locale::parse_amount;
+use csv::StringRecord;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::num::IntErrorKind;
+
+/// Characters of a raw value shown by `Display` and by default in the quarantine.
+pub const DEFAULT_MAX_VALUE_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldErrorReason {
+    /// Not one of the transaction types.
+    UnknownTxType,
+    /// Not an unsigned integer.
+    InvalidId,
+    /// An unsigned integer above the maximum of the id.
+    IdOverflow,
+    /// Not a number, or one out of the range of amounts.
+    InvalidAmount,
+    /// A number followed by other characters, the first of which is at byte
+    /// `at_offset` of the amount as normalized, see `TrailingCharactersInAmount`.
+    TrailingCharacters { at_offset: usize },
+    /// A negative amount of a deposit or withdrawal.
+    NegativeAmount,
+}
+
+impl FieldErrorReason {
+    /// Stable snake_case code identifying the reason in reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FieldErrorReason::UnknownTxType => "unknown_tx_type",
+            FieldErrorReason::InvalidId => "invalid_id",
+            FieldErrorReason::IdOverflow => "id_overflow",
+            FieldErrorReason::InvalidAmount => "invalid_amount",
+            FieldErrorReason::TrailingCharacters { .. } => "trailing_characters",
+            FieldErrorReason::NegativeAmount => "negative_amount",
+        }
+    }
+}
+
+/// Writes the code, followed by the offset of trailing characters.
+impl Display for FieldErrorReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldErrorReason::TrailingCharacters { at_offset } => {
+                write!(f, "{} at offset {}", self.code(), at_offset)
+            }
+            _ => f.write_str(self.code()),
+        }
+    }
+}
+
+/// The value which made a row malformed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// 1-based line number within the input.
+    pub line: u64,
+    /// Name of the column in the header of the input.
+    pub column_name: String,
+    /// The value as read, trimmed like every field.
+    pub raw_value: String,
+    pub reason: FieldErrorReason,
+}
+
+impl FieldError {
+    /// `raw_value` cut to at most `max_chars` characters, the last of which is `…` when
+    /// it was cut.
+    pub fn truncated_value(&self, max_chars: usize) -> Cow<'_, str> {
+        match self.raw_value.char_indices().nth(max_chars) {
+            None => Cow::Borrowed(&self.raw_value),
+            Some(_) => {
+                let kept = self
+                    .raw_value
+                    .char_indices()
+                    .nth(max_chars.saturating_sub(1))
+                    .map_or(0, |(end, _char)| end);
+                let mut value = self.raw_value[..kept].to_string();
+                if max_chars > 0 {
+                    value.push('…');
+                }
+                Cow::Owned(value)
+            }
+        }
+    }
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {:?}: {} {:?}",
+            self.line,
+            self.column_name,
+            self.reason,
+            self.truncated_value(DEFAULT_MAX_VALUE_CHARS)
+        )
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+/// Finds the field of `fields` which made the row fail with `error`, naming it after
+/// `headers`. Only invalid values lead to `InvalidTxType`, or to `MissingAmount` when
+/// the amount cannot be read, and the amount errors are about the amount, so other
+/// errors have no field.
+pub(crate) fn diagnose_fields<'r>(
+    line: u64,
+    headers: impl IntoIterator<Item = &'r str>,
+    fields: impl IntoIterator<Item = &'r str>,
+    error: &TransactionLogError,
+) -> Option<FieldError> {
+    let amount_reason = match error {
+        TransactionLogError::InvalidTxType | TransactionLogError::MissingAmount => None,
+        TransactionLogError::TrailingCharactersInAmount { at_offset, .. } => {
+            Some(FieldErrorReason::TrailingCharacters {
+                at_offset: *at_offset,
+            })
+        }
+        TransactionLogError::NegativeAmountNotAllowed => Some(FieldErrorReason::NegativeAmount),
+        _ => return None,
+    };
+    headers
+        .into_iter()
+        .zip(fields)
+        .find_map(|(column_name, raw_value)| {
+            let reason = match (column_name, amount_reason) {
+                ("amount", Some(reason)) => Some(reason),
+                (_, Some(_reason)) => None,
+                ("type", None) => check_tx_type(raw_value),
+                ("client", None) => check_id::<ClientId>(raw_value),
+                ("tx", None) => check_id::<TxId>(raw_value),
+                ("amount", None) => check_amount(raw_value),
+                _ => None,
+            }?;
+            Some(FieldError {
+                line,
+                column_name: column_name.to_string(),
+                raw_value: raw_value.to_string(),
+                reason,
+            })
+        })
+}
+
+/// Like `diagnose_fields`, reading the fields from the raw line again, so that values
+/// are reported as they are in the input even when they were normalized.
+pub(crate) fn diagnose_line(
+    line: u64,
+    raw: &[u8],
+    headers: &StringRecord,
+    error: &TransactionLogError,
+) -> Option<FieldError> {
+    let mut record = StringRecord::new();
+    match read_line_record(raw, &mut record) {
+        Ok(true) => diagnose_fields(line, headers, &record, error),
+        _ => None,
+    }
+}
+
+/// Deserializes a single value the way it is deserialized as a field of a row.
+fn deserialize_field<T: DeserializeOwned>(raw_value: &str) -> csv::Result<T> {
+    StringRecord::from(vec![raw_value]).deserialize::<T>(None)
+}
+
+fn check_tx_type(raw_value: &str) -> Option<FieldErrorReason> {
+    deserialize_field::<TxTypeTag>(raw_value)
+        .err()
+        .map(|_err| FieldErrorReason::UnknownTxType)
+}
+
+fn check_id<T: DeserializeOwned>(raw_value: &str) -> Option<FieldErrorReason> {
+    let err = deserialize_field::<T>(raw_value).err()?;
+    let overflow = match err.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => matches!(
+            err.kind(),
+            csv::DeserializeErrorKind::ParseInt(err) if *err.kind() == IntErrorKind::PosOverflow
+        ),
+        _ => false,
+    };
+    Some(if overflow {
+        FieldErrorReason::IdOverflow
+    } else {
+        FieldErrorReason::InvalidId
+    })
+}
+
+/// Empty amounts are missing rather than invalid.
+fn check_amount(raw_value: &str) -> Option<FieldErrorReason> {
+    (!raw_value.is_empty() && parse_amount(raw_value).is_err())
+        .then_some(FieldErrorReason::InvalidAmount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_error(
+        line: u64,
+        column_name: &str,
+        raw_value: &str,
+        reason: FieldErrorReason,
+    ) -> FieldError {
+        FieldError {
+            line,
+            column_name: column_name.to_string(),
+            raw_value: raw_value.to_string(),
+            reason,
+        }
+    }
+
+    #[test]
+    fn one_failure_per_column() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let diagnose = |raw: &str, error| diagnose_line(7, raw.as_bytes(), &headers, &error);
+        assert_eq!(
+            diagnose("refund, 1, 1, 1.0", TransactionLogError::InvalidTxType),
+            Some(field_error(
+                7,
+                "type",
+                "refund",
+                FieldErrorReason::UnknownTxType
+            ))
+        );
+        assert_eq!(
+            diagnose("deposit, 70000, 1, 1.0", TransactionLogError::InvalidTxType),
+            Some(field_error(
+                7,
+                "client",
+                "70000",
+                FieldErrorReason::IdOverflow
+            ))
+        );
+        assert_eq!(
+            diagnose("deposit, -1, 1, 1.0", TransactionLogError::InvalidTxType),
+            Some(field_error(7, "client", "-1", FieldErrorReason::InvalidId))
+        );
+        assert_eq!(
+            diagnose(
+                "deposit, 1, 4294967296, 1.0",
+                TransactionLogError::InvalidTxType
+            ),
+            Some(field_error(
+                7,
+                "tx",
+                "4294967296",
+                FieldErrorReason::IdOverflow
+            ))
+        );
+        assert_eq!(
+            diagnose("deposit, 1, ten, 1.0", TransactionLogError::InvalidTxType),
+            Some(field_error(7, "tx", "ten", FieldErrorReason::InvalidId))
+        );
+        assert_eq!(
+            diagnose("deposit, 1, 1, 1.2.3", TransactionLogError::MissingAmount),
+            Some(field_error(
+                7,
+                "amount",
+                "1.2.3",
+                FieldErrorReason::InvalidAmount
+            ))
+        );
+        assert_eq!(
+            diagnose("deposit, 1, 1, ", TransactionLogError::MissingAmount),
+            None
+        );
+        assert_eq!(
+            diagnose("deposit, 1, 1, 1.0, 2", TransactionLogError::TooManyFields),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_multi_byte_values_and_truncates_on_characters() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let error = diagnose_line(
+            2,
+            "dépôt, 1, 1, 1.0".as_bytes(),
+            &headers,
+            &TransactionLogError::InvalidTxType,
+        )
+        .unwrap();
+        assert_eq!(error.raw_value, "dépôt");
+        assert_eq!(error.truncated_value(5), "dépôt");
+        assert_eq!(error.truncated_value(4), "dép…");
+        assert_eq!(error.truncated_value(1), "…");
+        assert_eq!(error.truncated_value(0), "");
+
+        let long = field_error(
+            3,
+            "amount",
+            &"9".repeat(100),
+            FieldErrorReason::InvalidAmount,
+        );
+        assert_eq!(
+            long.to_string(),
+            format!(
+                "line 3, column \"amount\": invalid_amount \"{}…\"",
+                "9".repeat(DEFAULT_MAX_VALUE_CHARS - 1)
+            )
+        );
+    }
+}
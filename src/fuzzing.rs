@@ -0,0 +1,201 @@
+//! Harnesses of the cargo-fuzz targets in `fuzz/`, also run on seeded random
+//! inputs by the bounded mini-fuzz tests below.
+use crate::accounting::config::{AccountTiering, LockPolicy};
+use crate::accounting::transactions::{Transaction, TransactionLog, TxTypeTag};
+use crate::accounting::{AccountView, Ledger};
+use crate::core_types::{ClientId, TxId};
+use crate::engine::{parse_line, EXPECTED_HEADERS};
+use crate::number_locale::NumberLocale;
+use crate::pipeline::{CsvSource, TransactionSource};
+use csv::StringRecord;
+use rust_decimal::Decimal;
+
+/// Transaction row as the fuzzer generates it; the amount is kept for every type.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub tx_type: TxTypeTag,
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub amount: Option<Decimal>,
+}
+
+/// Parses `data` as a CSV input and as a single headerless row, and executes the
+/// parsed transactions. Every row must turn into a transaction or a typed error
+/// without panicking, and the ledger must stay consistent.
+pub fn parse_bytes(data: &[u8]) {
+    let mut ledger = Ledger::new();
+    let mut source = CsvSource::new(data);
+    while let Ok(Some(row)) = source.next_row() {
+        if let Ok(tx) = row.tx {
+            execute_checked(&mut ledger, &tx);
+        }
+    }
+    let headers = StringRecord::from(EXPECTED_HEADERS.to_vec());
+    if let Ok(tx) = parse_line(
+        data,
+        &headers,
+        &mut StringRecord::new(),
+        NumberLocale::default(),
+    )
+    .and_then(Transaction::try_from)
+    {
+        execute_checked(&mut ledger, &tx);
+    }
+}
+
+/// Executes `records` on a default ledger and on one holding deposits into locked
+/// accounts, allowing an overdraft and demoting idle accounts to the cold tier,
+/// checking the ledger after every step. Records reusing a transaction id are
+/// executed too, since the ledger rejects deposits taking over a recorded id.
+pub fn execute_records(records: &[Record]) {
+    let mut ledgers = [
+        Ledger::new(),
+        Ledger::builder()
+            .lock_policy(LockPolicy::HoldIncoming)
+            .overdraft_limit(Decimal::from(-100))
+            .account_tiering(AccountTiering {
+                demote_after: 3,
+                hot_capacity: 0,
+            })
+            .build(),
+    ];
+    for record in records {
+        let log = TransactionLog::new(
+            record.tx_type,
+            record.client_id,
+            record.tx_id,
+            record.amount,
+        );
+        let Ok(tx) = Transaction::try_from(log) else {
+            continue;
+        };
+        for ledger in &mut ledgers {
+            execute_checked(ledger, &tx);
+            // Unlocking lets later records exercise deposits after a chargeback.
+            if record.tx_id % 7 == 0 {
+                ledger.unlock(record.client_id).ok();
+            }
+        }
+    }
+}
+
+/// Executes `tx`, asserting that the invariants hold afterwards and that a rejected
+/// transaction leaves the account unchanged.
+fn execute_checked(ledger: &mut Ledger, tx: &Transaction) {
+    let before = ledger.account(tx.client_id()).map(AccountView::from);
+    let result = ledger.execute(tx);
+    if let Err(violation) = ledger.verify_invariants() {
+        panic!("{:?} broke the ledger: {}", tx, violation);
+    }
+    if let Err(err) = result {
+        let after = ledger.account(tx.client_id()).map(AccountView::from);
+        assert_eq!(
+            after, before,
+            "{:?} was rejected with {} but applied",
+            tx, err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TX_TYPES: [TxTypeTag; 10] = [
+        TxTypeTag::Deposit,
+        TxTypeTag::Withdrawal,
+        TxTypeTag::Dispute,
+        TxTypeTag::Resolve,
+        TxTypeTag::Chargeback,
+        TxTypeTag::Adjustment,
+        TxTypeTag::WithdrawAuth,
+        TxTypeTag::WithdrawCapture,
+        TxTypeTag::WithdrawRelease,
+        TxTypeTag::Settle,
+    ];
+
+    /// Linear congruential generator, so that failures reproduce from the seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 11
+        }
+
+        fn below(&mut self, bound: u64) -> u64 {
+            self.next() % bound
+        }
+
+        fn amount(&mut self) -> Decimal {
+            match self.below(5) {
+                0 => Decimal::MAX,
+                1 => Decimal::new(self.below(10_000) as i64, self.below(5) as u32),
+                2 => -Decimal::new(self.below(10_000) as i64, self.below(5) as u32),
+                _ => Decimal::from_i128_with_scale(
+                    (self.next() as i128) << self.below(44),
+                    self.below(29) as u32,
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn mini_fuzz_parse_bytes() {
+        const FIELDS: [&str; 16] = [
+            "deposit",
+            "withdrawal",
+            "dispute",
+            "chargeback",
+            "1",
+            "65536",
+            "-1",
+            "2.5",
+            "1e400",
+            "18446744073709551616",
+            "79228162514264337593543950335",
+            "\"",
+            "",
+            " ",
+            "\u{fffd}",
+            "0.00000000000000000000000000001",
+        ];
+        let mut rng = Rng(7);
+        for _ in 0..3000 {
+            let mut data = b"type,client,tx,amount\n".to_vec();
+            for _ in 0..rng.below(8) {
+                for field in 0..rng.below(6) {
+                    if field > 0 {
+                        data.push(b',');
+                    }
+                    data.extend_from_slice(FIELDS[rng.below(16) as usize].as_bytes());
+                }
+                data.push(b'\n');
+            }
+            for _ in 0..rng.below(4) {
+                let position = rng.below(data.len() as u64) as usize;
+                data[position] = rng.next() as u8;
+            }
+            parse_bytes(&data);
+        }
+    }
+
+    #[test]
+    fn mini_fuzz_execute_records() {
+        let mut rng = Rng(11);
+        for _ in 0..2000 {
+            let records = (0..rng.below(64))
+                .map(|_| Record {
+                    tx_type: TX_TYPES[rng.below(6) as usize],
+                    client_id: rng.below(4) as ClientId,
+                    tx_id: rng.below(16) as TxId,
+                    amount: (rng.below(8) != 0).then(|| rng.amount()),
+                })
+                .collect::<Vec<_>>();
+            execute_records(&records);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! UTF-8 byte order marks at the start of inputs, which spreadsheet exports on
+//! Windows write.
+//!
+//! The csv parsers skip a BOM only when it arrives within their first read, so a BOM
+//! split across reads would otherwise be glued onto the `type` header, failing the
+//! header check and every row deserialized by column name. `SkipBom` removes it from
+//! the bytes before any parser sees them, whatever the read sizes.
+use crate::engine::UTF8_BOM;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Passes the bytes of `inner` on without a leading UTF-8 BOM.
+pub struct SkipBom<R> {
+    inner: R,
+    /// Bytes read while looking for the BOM, passed on from `passed` unless they were
+    /// the BOM.
+    start: [u8; 3],
+    read: usize,
+    passed: usize,
+    checked: bool,
+}
+
+impl<R> SkipBom<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            start: [0; 3],
+            read: 0,
+            passed: 0,
+            checked: false,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SkipBom<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        while !this.checked {
+            let mut start = ReadBuf::new(&mut this.start[this.read..]);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut start))?;
+            let read = start.filled().len();
+            this.read += read;
+            let start = &this.start[..this.read];
+            if read == 0 || start.len() == UTF8_BOM.len() || !UTF8_BOM.starts_with(start) {
+                this.checked = true;
+                if start == UTF8_BOM {
+                    this.passed = this.read;
+                }
+            }
+        }
+        if this.passed < this.read {
+            let pending = &this.start[this.passed..this.read];
+            let len = pending.len().min(buf.remaining());
+            buf.put_slice(&pending[..len]);
+            this.passed += len;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Reader returning at most `chunk` bytes per read.
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl AsyncRead for Chunked<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let len = self.chunk.min(self.data.len()).min(buf.remaining());
+            buf.put_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn skipped(data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut read = Vec::new();
+        SkipBom::new(Chunked { data, chunk })
+            .read_to_end(&mut read)
+            .await
+            .unwrap();
+        read
+    }
+
+    #[tokio::test]
+    async fn leading_boms_are_skipped_whatever_the_read_sizes() {
+        for chunk in [1, 2, 3, 4, 1024] {
+            assert_eq!(skipped(b"\xef\xbb\xbftype\n", chunk).await, b"type\n");
+            assert_eq!(skipped(b"type\n", chunk).await, b"type\n");
+            // Only the first BOM is a byte order mark
+            assert_eq!(
+                skipped(b"\xef\xbb\xbf\xef\xbb\xbfa", chunk).await,
+                b"\xef\xbb\xbfa"
+            );
+            // Bytes starting like a BOM are passed on
+            assert_eq!(skipped(b"\xef\xbbx", chunk).await, b"\xef\xbbx");
+            assert_eq!(skipped(b"\xef\xbb", chunk).await, b"\xef\xbb");
+            assert_eq!(skipped(b"", chunk).await, b"");
+            assert_eq!(skipped(b"\xef\xbb\xbf", chunk).await, b"");
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Detection of distinct upstream clients whose ids were truncated into the same
+//! `ClientId`, whose funds would otherwise be mixed in one account without a trace.
+use crate::core_types::ClientId;
+use crate::ingest::column_map::{ColumnIndices, ColumnMap};
+use crate::ingest::{parallel, IngestError, EXPECTED_HEADERS};
+use csv_async::StringRecord;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+/// Optional column holding the upstream id of the client, next to the truncated one.
+pub const RAW_CLIENT_COLUMN: &str = "raw_client";
+
+/// Several raw ids were read for `client`, so its account may hold the funds of
+/// several upstream clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PossibleClientCollision {
+    pub client: ClientId,
+    /// In ascending order.
+    pub raw_ids: Vec<u64>,
+}
+
+impl Display for PossibleClientCollision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let raw_ids = self.raw_ids.iter().map(u64::to_string).collect::<Vec<_>>();
+        write!(
+            f,
+            "client {} was read for raw ids {}",
+            self.client,
+            raw_ids.join(", ")
+        )
+    }
+}
+
+/// Raw ids read for each client. The raw id of a row is its `RAW_CLIENT_COLUMN` when
+/// the input has one, and otherwise its client field, which is wrapped into the
+/// `ClientId` range when it exceeds it.
+#[derive(Debug, Default)]
+pub struct ClientIdTracker {
+    raw_ids: BTreeMap<ClientId, BTreeSet<u64>>,
+}
+
+impl ClientIdTracker {
+    /// Records `raw` as read for the client it truncates to, which is returned.
+    pub fn track(&mut self, raw: u64) -> ClientId {
+        let client = raw as ClientId;
+        self.observe(raw, client);
+        client
+    }
+
+    /// Records `raw` as read for `client`, as given by an input with both columns.
+    pub fn observe(&mut self, raw: u64, client: ClientId) {
+        self.raw_ids.entry(client).or_default().insert(raw);
+    }
+
+    /// Clients read for more than one raw id, in ascending order.
+    pub fn collisions(&self) -> Vec<PossibleClientCollision> {
+        self.raw_ids
+            .iter()
+            .filter(|(_client, raw_ids)| raw_ids.len() > 1)
+            .map(|(client, raw_ids)| PossibleClientCollision {
+                client: *client,
+                raw_ids: raw_ids.iter().copied().collect(),
+            })
+            .collect()
+    }
+
+    /// Records the raw id of a data row, replacing a client field beyond the
+    /// `ClientId` range with the id it wraps to. Fields which are not ids are left
+    /// for the row to be rejected as malformed.
+    pub(crate) fn track_record(&mut self, record: &mut StringRecord, columns: &TrackedColumns) {
+        let Some(client_field) = record.get(columns.client) else {
+            return;
+        };
+        let Ok(client_value) = client_field.parse::<u64>() else {
+            return;
+        };
+        let raw = columns
+            .raw_client
+            .and_then(|column| record.get(column))
+            .and_then(|raw| raw.parse::<u64>().ok());
+        match (raw, ClientId::try_from(client_value)) {
+            (Some(raw), Ok(client)) => self.observe(raw, client),
+            (None, Ok(client)) => self.observe(client_value, client),
+            (raw, Err(_out_of_range)) => {
+                let client = self.track(client_value);
+                if let Some(raw) = raw.filter(|raw| *raw != client_value) {
+                    self.observe(raw, client);
+                }
+                let client = client.to_string();
+                *record = record
+                    .iter()
+                    .enumerate()
+                    .map(|(index, field)| match index == columns.client {
+                        true => client.as_str(),
+                        false => field,
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+/// Positions of the client columns in the header of an input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TrackedColumns {
+    pub(crate) client: usize,
+    pub(crate) raw_client: Option<usize>,
+}
+
+/// Finds the columns of `column_map` in the header of the input file, like
+/// `check_file_columns`, along with its `RAW_CLIENT_COLUMN`. Extra columns are
+/// accepted when the input has one.
+pub(crate) fn check_tracked_columns(
+    file_path: &str,
+    column_map: &ColumnMap,
+    allow_extra_columns: bool,
+) -> Result<(ColumnIndices, TrackedColumns), IngestError> {
+    let Some(header) = parallel::read_header(file_path)? else {
+        let indices = ColumnMap::default().resolve(EXPECTED_HEADERS, false)?;
+        let columns = TrackedColumns {
+            client: indices.client_index(),
+            raw_client: None,
+        };
+        return Ok((indices, columns));
+    };
+    let raw_client = header
+        .record
+        .iter()
+        .position(|column| column == RAW_CLIENT_COLUMN);
+    let indices =
+        column_map.resolve(&header.record, allow_extra_columns || raw_client.is_some())?;
+    let columns = TrackedColumns {
+        client: indices.client_index(),
+        raw_client,
+    };
+    Ok((indices, columns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_out_of_range_ids_and_reports_shared_clients() {
+        let mut tracker = ClientIdTracker::default();
+        assert_eq!(tracker.track(7), 7);
+        assert_eq!(tracker.track(65_543), 7);
+        assert_eq!(tracker.track(65_543), 7);
+        assert_eq!(tracker.track(8), 8);
+        tracker.observe(4_294_901_768, 8);
+        tracker.observe(9, 9);
+        assert_eq!(
+            tracker.collisions(),
+            vec![
+                PossibleClientCollision {
+                    client: 7,
+                    raw_ids: vec![7, 65_543],
+                },
+                PossibleClientCollision {
+                    client: 8,
+                    raw_ids: vec![8, 4_294_901_768],
+                },
+            ]
+        );
+        assert_eq!(
+            tracker.collisions()[0].to_string(),
+            "client 7 was read for raw ids 7, 65543"
+        );
+    }
+
+    #[test]
+    fn replaces_only_out_of_range_client_fields() {
+        let columns = TrackedColumns {
+            client: 1,
+            raw_client: Some(4),
+        };
+        let mut tracker = ClientIdTracker::default();
+        let mut record = StringRecord::from(vec!["deposit", "3", "1", "1.0", "131075"]);
+        tracker.track_record(&mut record, &columns);
+        assert_eq!(record, vec!["deposit", "3", "1", "1.0", "131075"]);
+        let mut record = StringRecord::from(vec!["deposit", "65539", "2", "1.0", ""]);
+        tracker.track_record(&mut record, &columns);
+        assert_eq!(record, vec!["deposit", "3", "2", "1.0", ""]);
+        let mut record = StringRecord::from(vec!["deposit", "x", "3", "1.0", "5"]);
+        tracker.track_record(&mut record, &columns);
+        assert_eq!(record, vec!["deposit", "x", "3", "1.0", "5"]);
+        assert_eq!(
+            tracker.collisions(),
+            vec![PossibleClientCollision {
+                client: 3,
+                raw_ids: vec![65_539, 131_075],
+            }]
+        );
+    }
+}
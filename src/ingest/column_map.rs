@@ -0,0 +1,235 @@
+//! Inputs whose columns have other names than `EXPECTED_HEADERS`, such as bank
+//! exports with `txn_type,customer_id,reference,value` columns.
+use crate::accounting::transactions::{TransactionLog, TransactionLogError, TxTypeTag};
+use crate::ingest::{IngestError, AMOUNT_COLUMN, EXPECTED_HEADERS};
+use crate::number_locale::{AmountParsing, NumberLocale};
+use csv::StringRecord;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Header names of the logical columns, read from TOML or JSON:
+/// ```toml
+/// type = "txn_type"
+/// client = "customer_id"
+/// tx = "reference"
+/// amount = "value"
+///
+/// [type_aliases]
+/// credit = "deposit"
+/// debit = "withdrawal"
+/// ```
+/// Omitted columns keep their names from `EXPECTED_HEADERS`. Values of the `type`
+/// column found in `type_aliases` are read as the transaction type they map to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnMap {
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: String,
+    pub tx: String,
+    pub amount: String,
+    pub type_aliases: HashMap<String, TxTypeTag>,
+}
+
+impl Default for ColumnMap {
+    fn default() -> Self {
+        let [tx_type, client, tx, amount] = EXPECTED_HEADERS.map(str::to_string);
+        Self {
+            tx_type,
+            client,
+            tx,
+            amount,
+            type_aliases: HashMap::new(),
+        }
+    }
+}
+
+impl ColumnMap {
+    pub fn from_toml(text: &str) -> Result<Self, IngestError> {
+        toml::from_str(text).map_err(|err| IngestError::InvalidColumnMap(err.message().to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, IngestError> {
+        serde_json::from_str(text).map_err(|err| IngestError::InvalidColumnMap(err.to_string()))
+    }
+
+    /// Reads a `.json` file as JSON and any other file as TOML.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, IngestError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| {
+            IngestError::InvalidColumnMap(format!("failed to read {}: {}", path.display(), err))
+        })?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&text),
+            _ => Self::from_toml(&text),
+        }
+    }
+
+    fn columns(&self) -> [(&'static str, &str); 4] {
+        [
+            (EXPECTED_HEADERS[0], &self.tx_type),
+            (EXPECTED_HEADERS[1], &self.client),
+            (EXPECTED_HEADERS[2], &self.tx),
+            (EXPECTED_HEADERS[3], &self.amount),
+        ]
+    }
+
+    /// Finds the mapped columns in trimmed `headers`, which may list them in any
+    /// order. Other columns are accepted with `allow_extra_columns`.
+    pub fn resolve<'h>(
+        &self,
+        headers: impl IntoIterator<Item = &'h str>,
+        allow_extra_columns: bool,
+    ) -> Result<ColumnIndices, IngestError> {
+        let found = headers
+            .into_iter()
+            .map(|header| header.trim().to_string())
+            .collect::<Vec<_>>();
+        let mut indices = [0; 4];
+        for (index, (field, column)) in indices.iter_mut().zip(self.columns()) {
+            *index = found
+                .iter()
+                .position(|header| header == column)
+                .ok_or_else(|| IngestError::MissingColumn {
+                    field,
+                    column: column.to_string(),
+                })?;
+        }
+        if !allow_extra_columns && found.len() > EXPECTED_HEADERS.len() {
+            return Err(IngestError::UnexpectedHeader {
+                expected: self
+                    .columns()
+                    .map(|(_field, column)| column.to_string())
+                    .to_vec(),
+                found,
+            });
+        }
+        Ok(ColumnIndices {
+            indices,
+            columns: found.len(),
+            type_aliases: self.type_aliases.clone(),
+            number_locale: NumberLocale::default(),
+            amount_parsing: AmountParsing::default(),
+        })
+    }
+}
+
+/// Positions of the mapped columns in the header of an input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnIndices {
+    /// Positions of the columns of `EXPECTED_HEADERS`, in that order.
+    indices: [usize; 4],
+    columns: usize,
+    type_aliases: HashMap<String, TxTypeTag>,
+    number_locale: NumberLocale,
+    amount_parsing: AmountParsing,
+}
+
+impl ColumnIndices {
+    /// Reads amounts with the separators of `number_locale`.
+    pub fn with_number_locale(mut self, number_locale: NumberLocale) -> Self {
+        self.number_locale = number_locale;
+        self
+    }
+
+    /// Reads amounts decorated as `amount_parsing` allows.
+    pub fn with_amount_parsing(mut self, amount_parsing: AmountParsing) -> Self {
+        self.amount_parsing = amount_parsing;
+        self
+    }
+
+    /// Position of the client column.
+    pub(crate) fn client_index(&self) -> usize {
+        self.indices[1]
+    }
+
+    /// Reads a row of the input the same way as a row of the expected format.
+    pub fn extract<'r>(
+        &self,
+        record: impl IntoIterator<Item = &'r str>,
+    ) -> Result<TransactionLog, TransactionLogError> {
+        let fields = record.into_iter().collect::<Vec<_>>();
+        TransactionLogError::check_field_count(fields.len(), self.columns)?;
+        let mut row = StringRecord::from(self.indices.map(|index| fields[index]).to_vec());
+        if let Some(tag) = self.type_aliases.get(&row[0]) {
+            row = StringRecord::from(vec![tag.as_str(), &row[1], &row[2], &row[3]]);
+        }
+        self.amount_parsing
+            .normalize_field(self.number_locale, &mut row, AMOUNT_COLUMN)?;
+        row.deserialize::<TransactionLog>(Some(&StringRecord::from(EXPECTED_HEADERS.to_vec())))
+            .map_err(|_err| TransactionLogError::InvalidTxType)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BANK_MAP: &str = "type = \"txn_type\"\n\
+                            client = \"customer_id\"\n\
+                            tx = \"reference\"\n\
+                            amount = \"value\"\n\
+                            [type_aliases]\n\
+                            credit = \"deposit\"\n\
+                            debit = \"withdrawal\"\n";
+
+    #[test]
+    fn reads_toml_and_json() {
+        let column_map = ColumnMap::from_toml(BANK_MAP).unwrap();
+        assert_eq!(column_map.client, "customer_id");
+        assert_eq!(column_map.type_aliases["credit"], TxTypeTag::Deposit);
+        assert_eq!(
+            ColumnMap::from_json(r#"{"tx": "reference"}"#).unwrap(),
+            ColumnMap {
+                tx: "reference".to_string(),
+                ..ColumnMap::default()
+            }
+        );
+        assert!(matches!(
+            ColumnMap::from_toml("customer = \"customer_id\""),
+            Err(IngestError::InvalidColumnMap(message)) if message.contains("customer")
+        ));
+        assert!(ColumnMap::from_toml("[type_aliases]\ncredit = \"refund\"").is_err());
+    }
+
+    #[test]
+    fn resolves_columns_in_any_order() {
+        let column_map = ColumnMap::from_toml(BANK_MAP).unwrap();
+        let indices = column_map
+            .resolve(["value", " reference", "txn_type", "customer_id"], false)
+            .unwrap();
+        assert_eq!(
+            indices.extract(["2.5", "7", "credit", "3"]),
+            Ok(TransactionLog::new(
+                TxTypeTag::Deposit,
+                3,
+                7,
+                Some(rust_decimal_macros::dec!(2.5))
+            ))
+        );
+        assert_eq!(
+            indices.extract(["", "7", "dispute", "3"]),
+            Ok(TransactionLog::new(TxTypeTag::Dispute, 3, 7, None))
+        );
+        assert_eq!(
+            indices.extract(["1", "7", "refund", "3"]),
+            Err(TransactionLogError::InvalidTxType)
+        );
+        assert_eq!(
+            indices.extract(["7", "credit", "3"]),
+            Err(TransactionLogError::TooFewFields)
+        );
+
+        assert_eq!(
+            column_map.resolve(["txn_type", "customer_id", "reference", "amount"], false),
+            Err(IngestError::MissingColumn {
+                field: "amount",
+                column: "value".to_string()
+            })
+        );
+        let with_extra = ["txn_type", "customer_id", "reference", "value", "note"];
+        assert!(column_map.resolve(with_extra, false).is_err());
+        assert!(column_map.resolve(with_extra, true).is_ok());
+    }
+}
@@ -0,0 +1,130 @@
+//! Skipping of rows repeated within a window, for inputs which overlap at their
+//! boundaries, like exports re-emitting their most recent rows in the next file.
+use crate::accounting::transactions::{Transaction, TxTypeTag};
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+
+/// Remembers the last `window` distinct `(tx id, type)` pairs read. A row repeating a
+/// remembered pair with the same client and amount is skipped, and one with another
+/// client or amount fails the ingestion with `IngestError::DedupConflict`.
+///
+/// This is independent of `DuplicateDisputePolicy` of the ledger, which only applies
+/// to rows that reach it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DedupConfig {
+    pub window: usize,
+}
+
+/// Client and amount of a row, which repeats of it within the window must match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DedupFingerprint {
+    pub client_id: ClientId,
+    pub amount: Option<Decimal>,
+}
+
+impl DedupFingerprint {
+    pub(crate) fn of(tx: &Transaction) -> Self {
+        Self {
+            client_id: tx.client_id(),
+            amount: tx.amount(),
+        }
+    }
+}
+
+impl Display for DedupFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "client {}", self.client_id)?;
+        match self.amount {
+            Some(amount) => write!(f, " amount {}", amount),
+            None => write!(f, " without amount"),
+        }
+    }
+}
+
+pub(crate) enum Seen {
+    New,
+    /// Same client and amount as the remembered row, to be skipped.
+    Repeat,
+    /// Differs from the remembered row, whose fingerprint this is.
+    Conflict(DedupFingerprint),
+}
+
+/// Pairs in the order they were first read, evicting the oldest once full.
+pub(crate) struct DedupWindow {
+    window: usize,
+    seen: HashMap<(TxId, TxTypeTag), DedupFingerprint>,
+    order: VecDeque<(TxId, TxTypeTag)>,
+}
+
+impl DedupWindow {
+    pub(crate) fn new(config: DedupConfig) -> Self {
+        // The window may be far larger than the input, so it is not allocated up front.
+        Self {
+            window: config.window,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn check(&mut self, tx: &Transaction) -> Seen {
+        let key = (tx.tx_id(), tx.kind());
+        let fingerprint = DedupFingerprint::of(tx);
+        if let Some(first) = self.seen.get(&key) {
+            return if *first == fingerprint {
+                Seen::Repeat
+            } else {
+                Seen::Conflict(*first)
+            };
+        }
+        if self.window == 0 {
+            return Seen::New;
+        }
+        if self.order.len() == self.window {
+            let oldest = self.order.pop_front().expect("window is not empty");
+            self.seen.remove(&oldest);
+        }
+        self.order.push_back(key);
+        self.seen.insert(key, fingerprint);
+        Seen::New
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Dispute};
+    use rust_decimal_macros::dec;
+
+    fn deposit(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Transaction {
+        Transaction::Deposit(Deposit::new(client_id, tx_id, amount))
+    }
+
+    #[test]
+    fn window_forgets_oldest_pairs() {
+        let mut window = DedupWindow::new(DedupConfig { window: 2 });
+        assert!(matches!(window.check(&deposit(1, 1, dec!(5))), Seen::New));
+        // Another type with the same tx id is another pair
+        assert!(matches!(
+            window.check(&Transaction::Dispute(Dispute::new(1, 1))),
+            Seen::New
+        ));
+        assert!(matches!(
+            window.check(&deposit(1, 1, dec!(5))),
+            Seen::Repeat
+        ));
+        assert!(matches!(
+            window.check(&deposit(2, 1, dec!(5))),
+            Seen::Conflict(DedupFingerprint {
+                client_id: 1,
+                amount: Some(_),
+            })
+        ));
+        assert!(matches!(window.check(&deposit(1, 2, dec!(1))), Seen::New));
+        // The deposit of tx 1 was evicted by the deposit of tx 2
+        assert!(matches!(window.check(&deposit(3, 1, dec!(9))), Seen::New));
+        assert_eq!(window.order.len(), 2);
+        assert_eq!(window.seen.len(), 2);
+    }
+}
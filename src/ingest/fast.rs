@@ -0,0 +1,322 @@
+use crate::accounting::transactions::{
+    Transaction, TransactionLog, TransactionLogError, TxTypeTag,
+};
+use crate::engine::{parse_line, read_line_record, UTF8_BOM};
+use crate::field_error::diagnose_line;
+use crate::ingest::{RowError, EXPECTED_HEADERS};
+use crate::number_locale::{parse_amount, NumberLocale};
+use csv::StringRecord;
+use memchr::{memchr, memchr2, memchr_iter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_stream::Stream;
+
+const READ_SIZE: usize = 1024 * 1024;
+
+/// Parses the CSV input like `transaction_stream`, but splits lines and fields with
+/// `memchr` and parses them by hand. Lines the fast path cannot handle, such as ones
+/// with quotes, non-ASCII bytes or values failing to parse, go through the csv parser,
+/// so both parsers produce the same transactions and errors.
+///
+/// A UTF-8 BOM before the header is skipped, and lines may end with `\n` or `\r\n`.
+/// Quoted fields spanning multiple lines and lone `\r` line terminators are not
+/// supported.
+pub fn transaction_stream<R: AsyncRead + Unpin>(reader: R) -> FastTransactionStream<R> {
+    FastTransactionStream {
+        reader,
+        buffer: Vec::with_capacity(READ_SIZE),
+        start: 0,
+        eof: false,
+        line: 0,
+        headers: None,
+        record: StringRecord::new(),
+    }
+}
+
+pub struct FastTransactionStream<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// Start of the data in `buffer` which was not parsed yet.
+    start: usize,
+    eof: bool,
+    /// Number of the last line taken from `buffer`.
+    line: u64,
+    /// Header record and whether it allows the fast path.
+    headers: Option<(StringRecord, bool)>,
+    record: StringRecord,
+}
+
+impl<R: AsyncRead + Unpin> FastTransactionStream<R> {
+    /// Moves the unparsed data to the front of the buffer and reads more after it.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        let filled = self.buffer.len();
+        self.buffer.resize(filled + READ_SIZE, 0);
+        let mut read_buf = ReadBuf::new(&mut self.buffer[filled..]);
+        let poll = Pin::new(&mut self.reader).poll_read(cx, &mut read_buf);
+        let read = read_buf.filled().len();
+        self.buffer.truncate(filled + read);
+        if let Poll::Ready(Ok(())) = poll {
+            self.eof = read == 0;
+        }
+        poll
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for FastTransactionStream<R> {
+    type Item = Result<Transaction, RowError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let pending = &this.buffer[this.start..];
+            let line_end = match memchr(b'\n', pending) {
+                Some(position) => this.start + position + 1,
+                None if this.eof && pending.is_empty() => return Poll::Ready(None),
+                None if this.eof => this.buffer.len(),
+                None => match this.poll_fill(cx) {
+                    Poll::Ready(Ok(())) => continue,
+                    Poll::Ready(Err(_err)) => {
+                        this.eof = true;
+                        this.buffer.clear();
+                        this.start = 0;
+                        return Poll::Ready(Some(Err(RowError::new(
+                            this.line + 1,
+                            TransactionLogError::InvalidTxType,
+                        ))));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+            };
+            let mut raw = &this.buffer[this.start..line_end];
+            this.start = line_end;
+            this.line += 1;
+            if this.line == 1 {
+                raw = raw.strip_prefix(UTF8_BOM).unwrap_or(raw);
+            }
+            if let Some(parsed) = parse_row(raw, &mut this.headers, &mut this.record) {
+                let line = this.line;
+                let headers = this
+                    .headers
+                    .as_ref()
+                    .map(|(headers, _fast_headers)| headers);
+                return Poll::Ready(Some(parsed.map_err(|error| {
+                    let field =
+                        headers.and_then(|headers| diagnose_line(line, raw, headers, &error));
+                    RowError::new(line, error).with_field(field)
+                })));
+            }
+        }
+    }
+}
+
+/// Parses a line into a transaction, or returns `None` for the header and lines
+/// without data.
+fn parse_row(
+    line: &[u8],
+    headers: &mut Option<(StringRecord, bool)>,
+    record: &mut StringRecord,
+) -> Option<Result<Transaction, TransactionLogError>> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if trim(line).is_empty() {
+        return None;
+    }
+    let Some((headers, fast_headers)) = headers else {
+        return match read_line_record(line, record) {
+            Ok(true) if !record.iter().all(str::is_empty) => {
+                let fast_headers = record.iter().eq(EXPECTED_HEADERS);
+                *headers = Some((record.clone(), fast_headers));
+                None
+            }
+            Ok(_) => None,
+            Err(_err) => Some(Err(TransactionLogError::InvalidTxType)),
+        };
+    };
+    let simple = line.is_ascii() && memchr2(b'"', b'\r', line).is_none();
+    if simple && *fast_headers {
+        if let Some(log) = parse_fields(line) {
+            return Some(Transaction::try_from(log));
+        }
+    }
+    match read_line_record(line, record) {
+        Ok(true) if record.iter().all(str::is_empty) => None,
+        Ok(false) => None,
+        _ => Some(
+            parse_line(line, headers, record, NumberLocale::default())
+                .and_then(Transaction::try_from),
+        ),
+    }
+}
+
+/// Splits a line with the `type,client,tx,amount` layout into its fields.
+fn parse_fields(line: &[u8]) -> Option<TransactionLog> {
+    let mut fields = [&line[..0]; 4];
+    let mut field_start = 0;
+    let mut field_count = 0;
+    for separator in memchr_iter(b',', line).chain(std::iter::once(line.len())) {
+        *fields.get_mut(field_count)? = trim(&line[field_start..separator]);
+        field_count += 1;
+        field_start = separator + 1;
+    }
+    if field_count != fields.len() {
+        return None;
+    }
+    let [tx_type, client_id, tx_id, amount] = fields;
+    let tx_type = match tx_type {
+        b"deposit" => TxTypeTag::Deposit,
+        b"withdrawal" => TxTypeTag::Withdrawal,
+        b"dispute" => TxTypeTag::Dispute,
+        b"resolve" => TxTypeTag::Resolve,
+        b"chargeback" => TxTypeTag::Chargeback,
+        b"adjustment" => TxTypeTag::Adjustment,
+        b"withdraw_auth" => TxTypeTag::WithdrawAuth,
+        b"withdraw_capture" => TxTypeTag::WithdrawCapture,
+        b"withdraw_release" => TxTypeTag::WithdrawRelease,
+        b"settle" => TxTypeTag::Settle,
+        _ => return None,
+    };
+    let amount = match amount {
+        b"" => None,
+        amount => Some(parse_amount(std::str::from_utf8(amount).ok()?).ok()?),
+    };
+    Some(TransactionLog::new(
+        tx_type,
+        parse_integer(client_id)?.try_into().ok()?,
+        parse_integer(tx_id)?.try_into().ok()?,
+        amount,
+    ))
+}
+
+/// Parses a non-empty string of at most 19 ASCII digits.
+fn parse_integer(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 19 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, digit| {
+        digit
+            .is_ascii_digit()
+            .then(|| value * 10 + u64::from(digit - b'0'))
+    })
+}
+
+/// Trims the same ASCII characters as `str::trim`, which the csv parser uses.
+fn trim(field: &[u8]) -> &[u8] {
+    let is_space = |byte: &u8| matches!(byte, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r');
+    let start = field
+        .iter()
+        .position(|byte| !is_space(byte))
+        .unwrap_or(field.len());
+    let end = field
+        .iter()
+        .rposition(|byte| !is_space(byte))
+        .map_or(start, |end| end + 1);
+    &field[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::Ledger;
+    use crate::field_error::{FieldError, FieldErrorReason};
+    use crate::test_utils::{generate_input, render};
+    use tokio_stream::StreamExt;
+
+    type Parsed = Vec<Result<Transaction, TransactionLogError>>;
+
+    async fn parse_both(input: &str) -> (Parsed, Parsed) {
+        let csv = crate::ingest::transaction_stream(input.as_bytes())
+            .collect::<Vec<_>>()
+            .await;
+        let fast = transaction_stream(input.as_bytes())
+            .map(|row| row.map_err(|err| err.error))
+            .collect::<Vec<_>>()
+            .await;
+        (csv, fast)
+    }
+
+    #[tokio::test]
+    async fn matches_csv_parser_on_generated_input() {
+        let (csv, fast) = parse_both(&generate_input(1_000_000)).await;
+        assert_eq!(csv.len(), 1_000_000);
+        assert!(csv == fast);
+
+        let mut csv_ledger = Ledger::new();
+        let mut fast_ledger = Ledger::new();
+        for (csv_tx, fast_tx) in csv.iter().zip(&fast) {
+            csv_ledger.execute(csv_tx.as_ref().unwrap()).ok();
+            fast_ledger.execute(fast_tx.as_ref().unwrap()).ok();
+        }
+        assert_eq!(render(&fast_ledger), render(&csv_ledger));
+    }
+
+    #[tokio::test]
+    async fn matches_csv_parser_on_edge_cases() {
+        for input in [
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             \"deposit\", 2, 2, \"1,5\"\n\
+             Deposit, 1, 3, 1.0\r\n\
+             \r\n\
+             deposit,+4,4,1\n\
+             deposit, 0x10, 5, 2.50\n\
+             deposit, 70000, 6, 1.0\n\
+             deposit, 1, 7, 1e3\n\
+             deposit, 1, 8, -2.5\n\
+             deposit, 1, 9, .5\n\
+             deposit, 1, 10, 5.\n\
+             deposit, 1, 11, 123456789012.123456789\n\
+             deposit, 1, 12, 18446744073709551616\n\
+             deposit, 1, 13, abc\n\
+             deposit, 1, 14, ,\n\
+             withdrawal, 1, 15\n\
+             dispute, 1, 1,\n\
+             ,,,\n\
+             \u{a0}deposit, 1, 16, 1.0\n\
+             \x0bdeposit\x0b, 1, 17, 1.0\n\
+             deposit, 1, 18, +50.00\n\
+             deposit, 1, 19, 50.00abc\n\
+             withdrawal, 1, 20, -1\n\
+             dispute, 1, 1, 1x\n\
+             resolve, 1, 1,",
+            "\n\n client , type, tx, amount\n1, deposit, 1, 2.0\n",
+            "",
+            ",,\n",
+            "type,client,tx,amount",
+        ] {
+            let (csv, fast) = parse_both(input).await;
+            assert_eq!(fast, csv, "{:?}", input);
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_rows_keep_line_numbers() {
+        let input = "\ntype, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     refund, 1, 2, 2.0\r\n\
+                     \n\
+                     ,  , ,\n\
+                     withdrawal, 2, 5,\n\
+                     \"dispute\", 1, \"1\"\n\
+                     deposit, 3, 6, 3.0";
+        let errors = transaction_stream(input.as_bytes())
+            .filter_map(Result::err)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            errors,
+            vec![
+                RowError::new(4, TransactionLogError::InvalidTxType).with_field(Some(FieldError {
+                    line: 4,
+                    column_name: "type".to_string(),
+                    raw_value: "refund".to_string(),
+                    reason: FieldErrorReason::UnknownTxType,
+                })),
+                RowError::new(7, TransactionLogError::MissingAmount),
+                RowError::new(8, TransactionLogError::TooFewFields),
+            ]
+        );
+    }
+}
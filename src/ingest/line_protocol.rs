@@ -0,0 +1,501 @@
+use crate::accounting::transactions::{Transaction, TransactionLog, TransactionLogError};
+use crate::accounting::Ledger;
+use crate::checkpoint::{Checkpointer, Cursor};
+use crate::ingest::{is_blank_line, AMOUNT_COLUMN};
+use crate::number_locale::NumberLocale;
+use crate::pipeline::{SourceRow, TransactionSource};
+use csv::{StringRecord, Trim};
+use std::borrow::Cow;
+use std::io::BufRead;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Receives offsets of processed lines so that an external consumer can commit them.
+///
+/// A line is acknowledged only after its transaction was applied to the ledger or
+/// definitively rejected, which gives at-least-once processing of the feed.
+pub trait Acker {
+    fn ack(&mut self, offset: u64) -> std::io::Result<()>;
+}
+
+impl<A: Acker + ?Sized> Acker for &mut A {
+    fn ack(&mut self, offset: u64) -> std::io::Result<()> {
+        (**self).ack(offset)
+    }
+}
+
+/// Persists the last acknowledged offset in a file.
+pub struct FileAcker {
+    path: PathBuf,
+}
+
+impl FileAcker {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn committed(&self) -> std::io::Result<Option<u64>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Acker for FileAcker {
+    fn ack(&mut self, offset: u64) -> std::io::Result<()> {
+        std::fs::write(&self.path, offset.to_string())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct LineProtocolReport {
+    pub applied: u64,
+    pub rejected: u64,
+    pub redelivered: u64,
+    /// Checkpoints taken by `run_line_protocol_with_checkpoints`.
+    pub checkpoints: u64,
+}
+
+/// Checkpoints taken while applying a feed, see `run_line_protocol_with_checkpoints`.
+pub struct CheckpointSchedule<'a> {
+    pub checkpointer: &'a Checkpointer,
+    /// Lines applied or rejected between two checkpoints.
+    pub checkpoint_every_n_tx: u64,
+}
+
+/// Applies a feed of transactions to the ledger, one CSV record (without header) or
+/// one JSON object per line. `start_offset` is the byte offset of the reader's first
+/// line within the feed.
+///
+/// Lines at or before the ledger's [`Ledger::applied_offset`] were already applied, so
+/// they are acknowledged again but not executed, which makes redelivery idempotent.
+pub async fn run_line_protocol(
+    reader: impl AsyncBufRead + Unpin,
+    start_offset: u64,
+    ledger: &mut Ledger,
+    acker: impl Acker,
+) -> std::io::Result<LineProtocolReport> {
+    run_lines(reader, start_offset, ledger, acker, None).await
+}
+
+/// Like `run_line_protocol`, checkpointing the ledger with the offset of the next
+/// line after every `checkpoint_every_n_tx` lines applied or rejected, and once more
+/// at the end of the feed. After a crash, the feed is replayed from the cursor of
+/// `Checkpointer::latest` on its ledger; lines it covers are recognized as
+/// redelivered, so replaying from an earlier offset is safe too.
+pub async fn run_line_protocol_with_checkpoints(
+    reader: impl AsyncBufRead + Unpin,
+    start_offset: u64,
+    ledger: &mut Ledger,
+    acker: impl Acker,
+    schedule: CheckpointSchedule<'_>,
+) -> std::io::Result<LineProtocolReport> {
+    run_lines(reader, start_offset, ledger, acker, Some(schedule)).await
+}
+
+async fn run_lines(
+    mut reader: impl AsyncBufRead + Unpin,
+    start_offset: u64,
+    ledger: &mut Ledger,
+    mut acker: impl Acker,
+    schedule: Option<CheckpointSchedule<'_>>,
+) -> std::io::Result<LineProtocolReport> {
+    let mut report = LineProtocolReport::default();
+    // Lines applied or rejected since the last checkpoint
+    let mut unsaved = 0;
+    let mut offset = start_offset;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line).await?;
+        if read == 0 {
+            break;
+        }
+        let line_offset = offset;
+        offset += read as u64;
+        if is_blank_line(&line) {
+            continue;
+        }
+
+        if ledger
+            .applied_offset()
+            .is_some_and(|applied_offset| line_offset <= applied_offset)
+        {
+            report.redelivered += 1;
+        } else {
+            let applied = std::str::from_utf8(&line)
+                .map_err(|_err| TransactionLogError::InvalidTxType)
+                .and_then(parse_line)
+                .map(|tx| ledger.execute(&tx).is_ok());
+            match applied {
+                Ok(true) => report.applied += 1,
+                _ => report.rejected += 1,
+            }
+            ledger.set_applied_offset(line_offset);
+            unsaved += 1;
+        }
+        acker.ack(line_offset)?;
+        if let Some(schedule) = &schedule {
+            if unsaved >= schedule.checkpoint_every_n_tx.max(1) {
+                checkpoint(schedule, ledger, offset, &mut report).await?;
+                unsaved = 0;
+            }
+        }
+    }
+    if let Some(schedule) = &schedule {
+        if unsaved > 0 {
+            checkpoint(schedule, ledger, offset, &mut report).await?;
+        }
+    }
+    Ok(report)
+}
+
+async fn checkpoint(
+    schedule: &CheckpointSchedule<'_>,
+    ledger: &Ledger,
+    offset: u64,
+    report: &mut LineProtocolReport,
+) -> std::io::Result<()> {
+    schedule
+        .checkpointer
+        .checkpoint(ledger, Cursor { offset })
+        .await
+        .map_err(std::io::Error::other)?;
+    report.checkpoints += 1;
+    Ok(())
+}
+
+/// Lines of a feed in the format of `run_line_protocol`, including JSON lines files,
+/// as a pipeline source.
+pub struct LineSource<R> {
+    reader: R,
+    line: Vec<u8>,
+    line_number: u64,
+    number_locale: NumberLocale,
+}
+
+impl<R: BufRead> LineSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+            line_number: 0,
+            number_locale: NumberLocale::default(),
+        }
+    }
+
+    /// Reads amounts with the separators of `number_locale`.
+    pub fn with_number_locale(mut self, number_locale: NumberLocale) -> Self {
+        self.number_locale = number_locale;
+        self
+    }
+}
+
+impl<R: BufRead> TransactionSource for LineSource<R> {
+    fn next_row(&mut self) -> std::io::Result<Option<SourceRow<'_>>> {
+        loop {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            if !is_blank_line(&self.line) {
+                let tx = std::str::from_utf8(&self.line)
+                    .map_err(|_err| TransactionLogError::InvalidTxType)
+                    .and_then(|line| parse_localized_line(line, self.number_locale));
+                return Ok(Some(SourceRow {
+                    line: Some(self.line_number),
+                    raw: None,
+                    tx,
+                    field: None,
+                }));
+            }
+        }
+    }
+}
+
+/// Parses a single line holding either a headerless CSV record or a JSON object.
+pub fn parse_line(line: &str) -> Result<Transaction, TransactionLogError> {
+    parse_localized_line(line, NumberLocale::default())
+}
+
+/// Like `parse_line`, for amounts with the separators of `number_locale`. Amounts of
+/// JSON objects are normalized when they are strings.
+pub fn parse_localized_line(
+    line: &str,
+    number_locale: NumberLocale,
+) -> Result<Transaction, TransactionLogError> {
+    let line = line.trim();
+    let log = if line.starts_with('{') {
+        let mut object = serde_json::from_str::<serde_json::Value>(line)
+            .map_err(|_err| TransactionLogError::InvalidTxType)?;
+        match object.get_mut("amount") {
+            Some(serde_json::Value::String(amount)) => {
+                if let Cow::Owned(normalized) = number_locale.normalize_amount(amount)? {
+                    *amount = normalized;
+                }
+            }
+            // Read by `parse_amount` like amounts in strings
+            Some(amount @ serde_json::Value::Number(_)) => {
+                *amount = serde_json::Value::String(amount.to_string());
+            }
+            _ => {}
+        }
+        serde_json::from_value::<TransactionLog>(object)
+            .map_err(|_err| TransactionLogError::InvalidTxType)?
+    } else {
+        let mut record = StringRecord::new();
+        let read = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .trim(Trim::All)
+            .from_reader(line.as_bytes())
+            .read_record(&mut record)
+            .map_err(|_err| TransactionLogError::InvalidTxType)?;
+        if !read {
+            return Err(TransactionLogError::InvalidTxType);
+        }
+        number_locale.normalize_field(&mut record, AMOUNT_COLUMN)?;
+        record
+            .deserialize::<TransactionLog>(None)
+            .map_err(|_err| TransactionLogError::InvalidTxType)?
+    };
+    Transaction::try_from(log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::config::LedgerConfig;
+    use crate::accounting::transactions::{Deposit, Dispute};
+    use crate::pipeline::{Pipeline, RowOutcome};
+    use crate::test_utils::render;
+    use rust_decimal_macros::dec;
+
+    const FEED: &str = "deposit, 1, 1, 10.0\n\
+                        {\"type\": \"deposit\", \"client\": 2, \"tx\": 2, \"amount\": \"20.0\"}\n\
+                        withdrawal, 1, 3, 4.0\n\
+                        dispute, 2, 2,\n\
+                        resolve, 2, 2,\n\
+                        {\"type\": \"dispute\", \"client\": 2, \"tx\": 2}\n\
+                        withdrawal, 2, 4, 100.0\n\
+                        refund, 1, 5, 1.0\n\
+                        chargeback, 2, 2,\n";
+
+    #[derive(Default)]
+    struct RecordingAcker {
+        offsets: Vec<u64>,
+    }
+
+    impl Acker for RecordingAcker {
+        fn ack(&mut self, offset: u64) -> std::io::Result<()> {
+            self.offsets.push(offset);
+            Ok(())
+        }
+    }
+
+    fn line_offsets(feed: &str) -> Vec<u64> {
+        let mut offsets = vec![0];
+        offsets.extend(feed.match_indices('\n').map(|(index, _)| index as u64 + 1));
+        offsets.pop();
+        offsets
+    }
+
+    #[test]
+    fn parse_localized_csv_and_json_lines() {
+        let deposit = Ok(Transaction::Deposit(Deposit::new(1, 1, dec!(1234.56))));
+        assert_eq!(
+            parse_localized_line("deposit,1,1,\"1.234,56\"", NumberLocale::Comma),
+            deposit
+        );
+        assert_eq!(
+            parse_localized_line(
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.234,56"}"#,
+                NumberLocale::Comma
+            ),
+            deposit
+        );
+        assert_eq!(
+            parse_localized_line(
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1,234"}"#,
+                NumberLocale::Auto
+            ),
+            Err(TransactionLogError::AmbiguousAmount)
+        );
+        assert_eq!(
+            parse_line("deposit,1,1,\"1,234.56\""),
+            deposit,
+            "thousands separators are dropped under the default locale"
+        );
+    }
+
+    #[test]
+    fn parse_csv_and_json_lines() {
+        assert_eq!(
+            parse_line("deposit, 1, 1, 10.0\n"),
+            Ok(Transaction::Deposit(Deposit::new(1, 1, dec!(10.0))))
+        );
+        assert_eq!(
+            parse_line(r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "10.0"}"#),
+            Ok(Transaction::Deposit(Deposit::new(1, 1, dec!(10.0))))
+        );
+        assert_eq!(
+            parse_line(r#"{"type": "dispute", "client": 1, "tx": 1}"#),
+            Ok(Transaction::Dispute(Dispute::new(1, 1)))
+        );
+        assert_eq!(
+            parse_line(r#"{"type": "deposit", "client": 1, "tx": 1}"#),
+            Err(TransactionLogError::MissingAmount)
+        );
+        assert_eq!(
+            parse_line("{\"type\": \"deposit\""),
+            Err(TransactionLogError::InvalidTxType)
+        );
+        assert_eq!(
+            parse_line("refund, 1, 1, 1.0"),
+            Err(TransactionLogError::InvalidTxType)
+        );
+    }
+
+    #[test]
+    fn csv_and_json_lines_read_amounts_alike() {
+        let json = |amount: &str| {
+            parse_line(&format!(
+                r#"{{"type": "deposit", "client": 1, "tx": 1, "amount": {}}}"#,
+                amount
+            ))
+        };
+        let deposit = |amount| Ok(Transaction::Deposit(Deposit::new(1, 1, amount)));
+        let trailing = Err(TransactionLogError::TrailingCharactersInAmount {
+            raw: "50.00abc".to_string(),
+            at_offset: 5,
+        });
+        assert_eq!(parse_line("deposit, 1, 1, +50.00"), deposit(dec!(50)));
+        assert_eq!(json("\"+50.00\""), deposit(dec!(50)));
+        assert_eq!(json("50.25"), deposit(dec!(50.25)));
+        assert_eq!(json("1e3"), deposit(dec!(1000)));
+        assert_eq!(parse_line("deposit, 1, 1, 50.00abc"), trailing);
+        assert_eq!(json("\"50.00abc\""), trailing);
+        for negative in ["-5", "\"-5\""] {
+            assert_eq!(
+                json(negative),
+                Err(TransactionLogError::NegativeAmountNotAllowed)
+            );
+        }
+        assert_eq!(
+            parse_line("deposit, 1, 1, -5"),
+            Err(TransactionLogError::NegativeAmountNotAllowed)
+        );
+    }
+
+    #[tokio::test]
+    async fn line_source_matches_line_protocol() {
+        let mut expected = Ledger::new();
+        run_line_protocol(FEED.as_bytes(), 0, &mut expected, RecordingAcker::default())
+            .await
+            .unwrap();
+
+        let mut malformed_lines = Vec::new();
+        let (ledger, stats) = Pipeline::new(LineSource::new(FEED.as_bytes()))
+            .audit(|line, outcome: &RowOutcome<'_>| {
+                if let RowOutcome::Malformed(..) = outcome {
+                    malformed_lines.push(line);
+                }
+                Ok(())
+            })
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        assert_eq!(render(&ledger), render(&expected));
+        assert_eq!(stats.rows_executed, 7);
+        assert_eq!(malformed_lines, vec![Some(8)]);
+    }
+
+    #[tokio::test]
+    async fn acks_every_line_after_processing() {
+        let mut ledger = Ledger::new();
+        let mut acker = RecordingAcker::default();
+        let report = run_line_protocol(FEED.as_bytes(), 0, &mut ledger, &mut acker)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            LineProtocolReport {
+                applied: 7,
+                rejected: 2,
+                redelivered: 0,
+                checkpoints: 0,
+            }
+        );
+        assert_eq!(acker.offsets, line_offsets(FEED));
+        assert_eq!(ledger.applied_offset(), acker.offsets.last().copied());
+        assert_eq!(ledger.verify_invariants(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn redelivered_suffix_is_not_applied_twice() {
+        let mut expected = Ledger::new();
+        run_line_protocol(FEED.as_bytes(), 0, &mut expected, RecordingAcker::default())
+            .await
+            .unwrap();
+
+        for suffix_start in line_offsets(FEED) {
+            let mut ledger = Ledger::new();
+            run_line_protocol(FEED.as_bytes(), 0, &mut ledger, RecordingAcker::default())
+                .await
+                .unwrap();
+            let mut acker = RecordingAcker::default();
+            let suffix = &FEED.as_bytes()[suffix_start as usize..];
+            let report = run_line_protocol(suffix, suffix_start, &mut ledger, &mut acker)
+                .await
+                .unwrap();
+            assert_eq!(report.applied + report.rejected, 0);
+            assert_eq!(report.redelivered, acker.offsets.len() as u64);
+            assert_eq!(render(&ledger), render(&expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_from_committed_offset() {
+        let path = std::env::temp_dir().join(format!(
+            "payments_engine_line_protocol_{}.offset",
+            std::process::id()
+        ));
+        let mut acker = FileAcker::new(&path);
+        assert_eq!(acker.committed().unwrap(), None);
+
+        let mut expected = Ledger::new();
+        run_line_protocol(FEED.as_bytes(), 0, &mut expected, RecordingAcker::default())
+            .await
+            .unwrap();
+
+        let mut ledger = Ledger::new();
+        let crash_at = line_offsets(FEED)[5] as usize;
+        run_line_protocol(&FEED.as_bytes()[..crash_at], 0, &mut ledger, &mut acker)
+            .await
+            .unwrap();
+        let committed = acker.committed().unwrap().unwrap();
+        assert_eq!(committed, line_offsets(FEED)[4]);
+
+        let report = run_line_protocol(
+            &FEED.as_bytes()[committed as usize..],
+            committed,
+            &mut ledger,
+            &mut acker,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.redelivered, 1);
+        assert_eq!(render(&ledger), render(&expected));
+        assert_eq!(
+            acker.committed().unwrap(),
+            line_offsets(FEED).last().copied()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
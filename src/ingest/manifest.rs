@@ -0,0 +1,289 @@
+//! Sidecar manifests declaring what an input file holds, so that files truncated or
+//! altered in transit fail the run instead of producing wrong balances.
+use crate::accounting::transactions::{Transaction, TransactionLogError};
+use crate::ingest::normalized_transaction_stream;
+use crate::ingest::IngestError;
+use crate::number_locale::{AmountParsing, NumberLocale};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_stream::StreamExt;
+
+/// Expected contents of an input file, read from JSON:
+/// ```json
+/// {"sha256": "9f86d0…", "rows": 3, "deposit_sum": "30.5", "withdrawal_sum": "2.5"}
+/// ```
+/// `rows` counts the data rows, without the header and blank lines, and the sums
+/// cover the deposits and withdrawals which parse. Sums left out are not checked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Lowercase hex digest of the bytes of the file.
+    pub sha256: String,
+    pub rows: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deposit_sum: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdrawal_sum: Option<Decimal>,
+}
+
+impl Manifest {
+    pub fn from_json(text: &str) -> Result<Self, IngestError> {
+        serde_json::from_str(text).map_err(|err| IngestError::InvalidManifest(err.to_string()))
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, IngestError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| {
+            IngestError::InvalidManifest(format!("failed to read {}: {}", path.display(), err))
+        })?;
+        Self::from_json(&text)
+    }
+
+    /// Path of the sidecar manifest of the input file, `<file_path>.manifest.json`.
+    pub fn sidecar_path(file_path: &str) -> String {
+        format!("{}.manifest.json", file_path)
+    }
+
+    /// Reads the sidecar manifest of the input file, if it has one.
+    pub fn read_sidecar(file_path: &str) -> Result<Option<Self>, IngestError> {
+        let path = Self::sidecar_path(file_path);
+        match Path::new(&path).try_exists() {
+            Ok(true) => Self::read(path).map(Some),
+            Ok(false) => Ok(None),
+            Err(err) => Err(IngestError::InvalidManifest(format!(
+                "failed to read {}: {}",
+                path, err
+            ))),
+        }
+    }
+
+    /// Checks `actual`, the manifest of what was read, against this one. The first
+    /// field which differs fails with `IngestError::ManifestMismatch`.
+    pub fn verify(&self, actual: &Manifest) -> Result<(), IngestError> {
+        if !self.sha256.eq_ignore_ascii_case(&actual.sha256) {
+            return Err(mismatch("sha256", &self.sha256, &actual.sha256));
+        }
+        if self.rows != actual.rows {
+            return Err(mismatch("rows", self.rows, actual.rows));
+        }
+        for (field, expected, actual) in [
+            ("deposit_sum", self.deposit_sum, actual.deposit_sum),
+            ("withdrawal_sum", self.withdrawal_sum, actual.withdrawal_sum),
+        ] {
+            let actual = actual.unwrap_or_default();
+            match expected {
+                Some(expected) if expected != actual => {
+                    return Err(mismatch(field, expected, actual));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mismatch(field: &'static str, expected: impl ToString, actual: impl ToString) -> IngestError {
+    IngestError::ManifestMismatch {
+        field,
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    }
+}
+
+/// Row count and type sums of the rows read.
+#[derive(Debug, Default)]
+pub(crate) struct RowTally {
+    rows: u64,
+    deposit_sum: Decimal,
+    withdrawal_sum: Decimal,
+}
+
+impl RowTally {
+    pub(crate) fn count(&mut self, row: &Result<Transaction, TransactionLogError>) {
+        self.rows += 1;
+        let Ok(tx) = row else {
+            return;
+        };
+        let sum = match tx {
+            Transaction::Deposit(_) => &mut self.deposit_sum,
+            Transaction::Withdrawal(_) => &mut self.withdrawal_sum,
+            _ => return,
+        };
+        *sum += tx.amount().unwrap_or_default();
+    }
+
+    pub(crate) fn into_manifest(self, sha256: String) -> Manifest {
+        Manifest {
+            sha256,
+            rows: self.rows,
+            deposit_sum: Some(self.deposit_sum),
+            withdrawal_sum: Some(self.withdrawal_sum),
+        }
+    }
+}
+
+/// Hashes the bytes passing through when enabled, so that the digest of the input is
+/// known once it was read, without reading it twice.
+pub(crate) struct HashingReader<R> {
+    pub(crate) inner: R,
+    hasher: Option<Sha256>,
+}
+
+impl<R> HashingReader<R> {
+    pub(crate) fn new(inner: R, enabled: bool) -> Self {
+        Self {
+            inner,
+            hasher: enabled.then(Sha256::new),
+        }
+    }
+
+    /// Lowercase hex digest of the bytes read so far, empty when disabled.
+    pub(crate) fn finish(&mut self) -> String {
+        self.hasher.take().map_or_else(String::new, hex_digest)
+    }
+}
+
+/// Lowercase hex digest of the bytes fed to `hasher`.
+pub(crate) fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .fold(String::with_capacity(64), |mut digest, byte| {
+            let _ = write!(digest, "{:02x}", byte);
+            digest
+        })
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(hasher)) = (&poll, self.hasher.as_mut()) {
+            hasher.update(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+/// Manifest of the input file for its producer, with both sums of the amounts read
+/// with `number_locale` and `amount_parsing`, as the run checking it will read them.
+pub async fn generate_manifest(
+    file_path: &str,
+    number_locale: NumberLocale,
+    amount_parsing: AmountParsing,
+) -> Result<Manifest, IngestError> {
+    let file = tokio::fs::File::open(file_path).await?;
+    let mut file = HashingReader::new(file, true);
+    let mut tally = RowTally::default();
+    let mut rows = normalized_transaction_stream(&mut file, number_locale, amount_parsing);
+    while let Some(row) = rows.next().await {
+        tally.count(&row);
+    }
+    drop(rows);
+    Ok(tally.into_manifest(file.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::write_input;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn generates_the_digest_count_and_sums() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     \n\
+                     withdrawal,1,2,2.5\n\
+                     deposit,2,3,20.5\n\
+                     dispute,1,1,\n\
+                     deposit,x,4,1.0\n";
+        let path = write_input("manifest_generate", input);
+        let manifest = generate_manifest(
+            path.to_str().unwrap(),
+            NumberLocale::Dot,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+        let digest: String = Sha256::digest(input.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(
+            manifest,
+            Manifest {
+                sha256: digest,
+                rows: 5,
+                deposit_sum: Some(dec!(30.5)),
+                withdrawal_sum: Some(dec!(2.5)),
+            }
+        );
+        assert_eq!(
+            Manifest::from_json(&serde_json::to_string(&manifest).unwrap()).unwrap(),
+            manifest
+        );
+    }
+
+    #[tokio::test]
+    async fn sums_the_normalized_amounts() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,$10.00\n\
+                     deposit,1,2,1.5E+1\n\
+                     withdrawal,1,3,\"\u{a0}2.5\"\n";
+        let path = write_input("manifest_normalized", input);
+        let manifest = generate_manifest(
+            path.to_str().unwrap(),
+            NumberLocale::Dot,
+            AmountParsing::permissive(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(manifest.deposit_sum, Some(dec!(25)));
+        assert_eq!(manifest.withdrawal_sum, Some(dec!(2.5)));
+    }
+
+    #[test]
+    fn verifies_only_the_declared_sums() {
+        let actual = Manifest {
+            sha256: "ab".repeat(32),
+            rows: 4,
+            deposit_sum: Some(dec!(30.5)),
+            withdrawal_sum: Some(dec!(2.5)),
+        };
+        let declared = Manifest::from_json(&format!(
+            r#"{{"sha256": "{}", "rows": 4, "deposit_sum": 30.50}}"#,
+            "AB".repeat(32)
+        ))
+        .unwrap();
+        assert_eq!(declared.verify(&actual), Ok(()));
+        assert_eq!(
+            Manifest {
+                withdrawal_sum: Some(dec!(3)),
+                ..declared
+            }
+            .verify(&actual),
+            Err(IngestError::ManifestMismatch {
+                field: "withdrawal_sum",
+                expected: "3".to_string(),
+                actual: "2.5".to_string(),
+            })
+        );
+        assert!(matches!(
+            Manifest::from_json(r#"{"sha256": "", "rows": 1, "lines": 2}"#),
+            Err(IngestError::InvalidManifest(_))
+        ));
+    }
+}
@@ -0,0 +1,1661 @@
+use crate::accounting::config::DisputeLimit;
+use crate::accounting::transactions::{
+    Transaction, TransactionLog, TransactionLogError, TxTypeTag,
+};
+use crate::amount_scale::AmountScale;
+use crate::core_types::{ClientId, TxId};
+use crate::field_error::FieldError;
+use crate::ingest::bom::SkipBom;
+use crate::ingest::client_ids::{check_tracked_columns, ClientIdTracker, PossibleClientCollision};
+use crate::ingest::column_map::{ColumnIndices, ColumnMap};
+use crate::ingest::dedup::{DedupConfig, DedupFingerprint, DedupWindow, Seen};
+use crate::ingest::manifest::{HashingReader, Manifest, RowTally};
+use crate::ingest::prefetch::{IngestIoConfig, IngestReader};
+use crate::number_locale::{AmountParsing, NumberLocale};
+use csv_async::{StringRecord, Trim};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc::Sender;
+use tokio_stream::{Stream, StreamExt};
+
+pub mod bom;
+pub mod client_ids;
+pub mod column_map;
+pub mod dedup;
+pub mod fast;
+pub mod line_protocol;
+pub mod manifest;
+pub mod parallel;
+pub mod prefetch;
+
+pub use crate::engine::EXPECTED_HEADERS;
+
+/// Optional column after `EXPECTED_HEADERS` holding the payout reference of
+/// withdrawals, accepted without `IngestOptions::allow_extra_columns`.
+pub const REFERENCE_COLUMN: &str = "reference";
+
+/// Optional column after `EXPECTED_HEADERS` holding the reason of disputes and
+/// chargebacks, accepted like `REFERENCE_COLUMN`, before or after it.
+pub const REASON_COLUMN: &str = "reason";
+
+/// Position of the amount in `EXPECTED_HEADERS`.
+pub(crate) const AMOUNT_COLUMN: usize = 3;
+
+#[derive(Debug)]
+pub enum IngestError {
+    /// The header of the input is not `EXPECTED_HEADERS`, so rows would be read into
+    /// the wrong fields.
+    UnexpectedHeader {
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    /// The column map could not be read, e.g. because it names an unknown field.
+    InvalidColumnMap(String),
+    /// The header of the input lacks the column mapped to `field`.
+    MissingColumn { field: &'static str, column: String },
+    /// A row of `file` repeats the type and tx id of a row within the dedup window,
+    /// but not its client or amount.
+    DedupConflict {
+        file: String,
+        tx_id: TxId,
+        kind: TxTypeTag,
+        first: DedupFingerprint,
+        repeat: DedupFingerprint,
+    },
+    /// The manifest of the input could not be read.
+    InvalidManifest(String),
+    /// The input read disagrees with its manifest on `field`, after all its rows
+    /// were sent.
+    ManifestMismatch {
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+    /// The input could not be opened or read.
+    Io(std::io::Error),
+}
+
+impl IngestError {
+    /// Stable snake_case code identifying the error in reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            IngestError::UnexpectedHeader { .. } => "unexpected_header",
+            IngestError::InvalidColumnMap(_) => "invalid_column_map",
+            IngestError::MissingColumn { .. } => "missing_column",
+            IngestError::DedupConflict { .. } => "dedup_conflict",
+            IngestError::InvalidManifest(_) => "invalid_manifest",
+            IngestError::ManifestMismatch { .. } => "manifest_mismatch",
+            IngestError::Io(_) => "io",
+        }
+    }
+}
+
+impl Display for IngestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IngestError::UnexpectedHeader { expected, found } => write!(
+                f,
+                "unexpected header {:?}, expected {:?}",
+                found.join(","),
+                expected.join(",")
+            ),
+            IngestError::InvalidColumnMap(message) => write!(f, "invalid column map: {}", message),
+            IngestError::MissingColumn { field, column } => write!(
+                f,
+                "input has no column {:?}, which the column map names for {:?}",
+                column, field
+            ),
+            IngestError::DedupConflict {
+                file,
+                tx_id,
+                kind,
+                first,
+                repeat,
+            } => write!(
+                f,
+                "{} of tx {} in {} with {} repeats one within the dedup window with {}",
+                kind, tx_id, file, repeat, first
+            ),
+            IngestError::InvalidManifest(message) => write!(f, "invalid manifest: {}", message),
+            IngestError::ManifestMismatch {
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "input does not match its manifest: {} is {} but {} was expected",
+                field, actual, expected
+            ),
+            IngestError::Io(err) => write!(f, "failed to read input: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IngestError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// I/O errors are equal when their kinds are.
+impl PartialEq for IngestError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                IngestError::UnexpectedHeader { expected, found },
+                IngestError::UnexpectedHeader {
+                    expected: other_expected,
+                    found: other_found,
+                },
+            ) => expected == other_expected && found == other_found,
+            (IngestError::InvalidColumnMap(message), IngestError::InvalidColumnMap(other)) => {
+                message == other
+            }
+            (
+                IngestError::MissingColumn { field, column },
+                IngestError::MissingColumn {
+                    field: other_field,
+                    column: other_column,
+                },
+            ) => field == other_field && column == other_column,
+            (
+                IngestError::DedupConflict {
+                    file,
+                    tx_id,
+                    kind,
+                    first,
+                    repeat,
+                },
+                IngestError::DedupConflict {
+                    file: other_file,
+                    tx_id: other_tx_id,
+                    kind: other_kind,
+                    first: other_first,
+                    repeat: other_repeat,
+                },
+            ) => {
+                file == other_file
+                    && tx_id == other_tx_id
+                    && kind == other_kind
+                    && first == other_first
+                    && repeat == other_repeat
+            }
+            (IngestError::InvalidManifest(message), IngestError::InvalidManifest(other)) => {
+                message == other
+            }
+            (
+                IngestError::ManifestMismatch {
+                    field,
+                    expected,
+                    actual,
+                },
+                IngestError::ManifestMismatch {
+                    field: other_field,
+                    expected: other_expected,
+                    actual: other_actual,
+                },
+            ) => field == other_field && expected == other_expected && actual == other_actual,
+            (IngestError::Io(err), IngestError::Io(other)) => err.kind() == other.kind(),
+            _ => false,
+        }
+    }
+}
+
+impl From<std::io::Error> for IngestError {
+    fn from(err: std::io::Error) -> Self {
+        IngestError::Io(err)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RowError {
+    pub line: u64,
+    pub error: TransactionLogError,
+    /// The value at fault, when the row is malformed because of a single field.
+    pub field: Option<FieldError>,
+}
+
+impl RowError {
+    pub fn new(line: u64, error: TransactionLogError) -> Self {
+        Self {
+            line,
+            error,
+            field: None,
+        }
+    }
+
+    pub fn with_field(mut self, field: Option<FieldError>) -> Self {
+        self.field = field;
+        self
+    }
+}
+
+/// Treatment of the rows skipped with `IngestOptions::skip`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SkipMode {
+    /// Skipped rows are not executed, so later disputes may reference missing deposits.
+    #[default]
+    Discard,
+    /// Skipped rows are executed but excluded from the row limit and per-row reporting.
+    ExecuteSilent,
+}
+
+/// Parser of the serial ingestion.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum IngestParser {
+    /// `csv_async` reader.
+    #[default]
+    Csv,
+    /// Hand-rolled parser of `fast::transaction_stream` for unquoted input.
+    Fast,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IngestOptions {
+    /// Number of data rows at the start of the input to skip.
+    pub skip: usize,
+    pub skip_mode: SkipMode,
+    /// Maximum number of data rows to process after the skipped ones.
+    pub limit: Option<usize>,
+    pub parser: IngestParser,
+    /// Accepts headers with more columns after `EXPECTED_HEADERS`.
+    pub allow_extra_columns: bool,
+    /// Names of the columns, when they differ from `EXPECTED_HEADERS`. Rows are read
+    /// with the csv parser whatever `parser` is.
+    pub column_map: Option<ColumnMap>,
+    /// Separators of the amounts. Rows are read with the csv parser unless it's
+    /// `NumberLocale::Dot`.
+    pub number_locale: NumberLocale,
+    /// Normalization of decorated amounts. Rows are read with the csv parser unless
+    /// it's the default.
+    pub amount_parsing: AmountParsing,
+    /// Skips rows repeated within a window, which spans the files of
+    /// `read_data_multi`.
+    pub dedup: Option<DedupConfig>,
+    /// Read size and read-ahead of the input files.
+    pub io: IngestIoConfig,
+    /// Records the raw client id of every row, from its `raw_client` column or its
+    /// client field, to report clients read for several raw ids. Client fields
+    /// beyond the `ClientId` range are wrapped into it instead of being malformed.
+    /// Rows are read with the csv parser whatever `parser` is.
+    pub track_raw_client_ids: bool,
+    /// Expected contents of every file read, checked instead of the
+    /// `<file>.manifest.json` sidecar which files may have. Files read up to a
+    /// reached limit are not checked.
+    pub manifest: Option<Manifest>,
+    /// Smallest unit of the amounts. Rows with finer amounts are malformed with
+    /// `TransactionLogError::ExcessPrecision` or have them rounded, see `AmountScale`.
+    /// Any precision is accepted when `None`.
+    pub amount_scale: Option<AmountScale>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct IngestReport {
+    /// Data rows processed after skipping, both parsed and malformed.
+    pub rows_read: u64,
+    pub rows_malformed: u64,
+    /// Data rows not executed because of `SkipMode::Discard`.
+    pub rows_skipped: u64,
+    /// Data rows executed without being reported because of `SkipMode::ExecuteSilent`.
+    pub rows_executed_silently: u64,
+    /// Set when the input had more rows than the limit allowed to process.
+    pub limit_reached: bool,
+    /// Lines with only whitespace and commas, which are not data rows.
+    pub blank_lines_skipped: u64,
+    /// Data rows not sent because they repeated a row within the dedup window.
+    pub rows_deduped: u64,
+    /// Clients read for several raw ids with `IngestOptions::track_raw_client_ids`,
+    /// across all the files read.
+    pub client_collisions: Vec<PossibleClientCollision>,
+}
+
+impl IngestReport {
+    /// Adds the counts of `other`, read after the rows of this report.
+    fn add(&mut self, other: IngestReport) {
+        self.rows_read += other.rows_read;
+        self.rows_malformed += other.rows_malformed;
+        self.rows_skipped += other.rows_skipped;
+        self.rows_executed_silently += other.rows_executed_silently;
+        self.limit_reached |= other.limit_reached;
+        self.blank_lines_skipped += other.blank_lines_skipped;
+        self.rows_deduped += other.rows_deduped;
+        self.client_collisions.extend(other.client_collisions);
+    }
+}
+
+/// Parses the CSV input into a stream with one item per data row. Records without
+/// data are skipped, as is a UTF-8 BOM before the header.
+pub fn transaction_stream<'r, R>(
+    reader: R,
+) -> impl Stream<Item = Result<Transaction, TransactionLogError>> + 'r
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    localized_transaction_stream(reader, NumberLocale::default())
+}
+
+/// Like `transaction_stream`, for an input whose amounts have the separators of
+/// `number_locale`.
+pub fn localized_transaction_stream<'r, R>(
+    reader: R,
+    number_locale: NumberLocale,
+) -> impl Stream<Item = Result<Transaction, TransactionLogError>> + 'r
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    normalized_transaction_stream(reader, number_locale, AmountParsing::default())
+}
+
+/// Like `localized_transaction_stream`, for an input whose amounts are decorated as
+/// `amount_parsing` allows.
+pub fn normalized_transaction_stream<'r, R>(
+    reader: R,
+    number_locale: NumberLocale,
+    amount_parsing: AmountParsing,
+) -> impl Stream<Item = Result<Transaction, TransactionLogError>> + 'r
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    record_stream(reader, move |headers, record| {
+        TransactionLogError::check_field_count(record.len(), headers.len())?;
+        amount_parsing.normalize_amount_field(number_locale, headers, record)?;
+        record
+            .deserialize::<TransactionLog>(Some(headers))
+            .map_err(|_err| TransactionLogError::InvalidTxType)
+    })
+}
+
+/// Like `transaction_stream`, for an input whose header `indices` were resolved from.
+pub fn mapped_transaction_stream<'r, R>(
+    reader: R,
+    indices: ColumnIndices,
+) -> impl Stream<Item = Result<Transaction, TransactionLogError>> + 'r
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    record_stream(reader, move |_headers, record| indices.extract(&*record))
+}
+
+/// Reads the data rows of the CSV input into transaction logs with `to_log`, which
+/// gets the header and the row.
+fn record_stream<'r, R>(
+    reader: R,
+    mut to_log: impl FnMut(&StringRecord, &mut StringRecord) -> Result<TransactionLog, TransactionLogError>
+        + Send
+        + 'r,
+) -> impl Stream<Item = Result<Transaction, TransactionLogError>> + 'r
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    let mut headers = None;
+    csv_async::AsyncReaderBuilder::new()
+        .trim(Trim::All)
+        .has_headers(false)
+        .flexible(true)
+        .create_reader(SkipBom::new(reader))
+        .into_records()
+        .filter_map(move |record| {
+            let mut record = match record {
+                Ok(record) => record,
+                Err(_err) => return Some(Err(TransactionLogError::InvalidTxType)),
+            };
+            if record.iter().all(str::is_empty) {
+                return None;
+            }
+            let Some(headers) = &headers else {
+                headers = Some(record);
+                return None;
+            };
+            Some(to_log(headers, &mut record).and_then(Transaction::try_from))
+        })
+}
+
+/// Checks that trimmed `headers` are `EXPECTED_HEADERS`, optionally followed by
+/// `REFERENCE_COLUMN` and `REASON_COLUMN` or, with `allow_extra_columns`, any extra
+/// columns.
+pub fn check_headers<'h>(
+    headers: impl IntoIterator<Item = &'h str>,
+    allow_extra_columns: bool,
+) -> Result<(), IngestError> {
+    let found = headers
+        .into_iter()
+        .map(|header| header.trim().to_string())
+        .collect::<Vec<_>>();
+    let columns_match = match found.get(EXPECTED_HEADERS.len()..) {
+        Some([]) => true,
+        Some([column]) => column == REFERENCE_COLUMN || column == REASON_COLUMN,
+        Some([first, second]) => {
+            (first == REFERENCE_COLUMN && second == REASON_COLUMN)
+                || (first == REASON_COLUMN && second == REFERENCE_COLUMN)
+        }
+        _ => false,
+    } || (allow_extra_columns && found.len() > EXPECTED_HEADERS.len());
+    if columns_match
+        && found
+            .iter()
+            .zip(EXPECTED_HEADERS)
+            .all(|(found, expected)| found == expected)
+    {
+        return Ok(());
+    }
+    Err(IngestError::UnexpectedHeader {
+        expected: EXPECTED_HEADERS.map(str::to_string).to_vec(),
+        found,
+    })
+}
+
+/// Checks the header of the input file with `check_headers`. Files without a header
+/// pass.
+pub fn check_file_headers(file_path: &str, allow_extra_columns: bool) -> Result<(), IngestError> {
+    match parallel::read_header(file_path)? {
+        Some(header) => check_headers(&header.record, allow_extra_columns),
+        None => Ok(()),
+    }
+}
+
+/// Finds the columns of `column_map` in the header of the input file. Files without
+/// a header have no rows, and resolve to the expected columns.
+pub fn check_file_columns(
+    file_path: &str,
+    column_map: &ColumnMap,
+    allow_extra_columns: bool,
+) -> Result<ColumnIndices, IngestError> {
+    match parallel::read_header(file_path)? {
+        Some(header) => column_map.resolve(&header.record, allow_extra_columns),
+        None => ColumnMap::default().resolve(EXPECTED_HEADERS, false),
+    }
+}
+
+/// Whether a line holds no data, that is only whitespace and field separators.
+pub(crate) fn is_blank_line(line: &[u8]) -> bool {
+    line.iter().all(|byte| is_blank_byte(*byte))
+}
+
+fn is_blank_byte(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || byte == b','
+}
+
+/// Counts blank lines passing through, which csv either skips silently when empty
+/// or turns into records without data.
+struct BlankLineCounter<R> {
+    inner: R,
+    /// `None` at the start of a line, otherwise whether the line is blank so far.
+    line_blank: Option<bool>,
+    blank_lines: u64,
+}
+
+impl<R> BlankLineCounter<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            line_blank: None,
+            blank_lines: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for BlankLineCounter<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = &buf.filled()[filled..];
+            if read.is_empty() && self.line_blank.take() == Some(true) {
+                self.blank_lines += 1;
+            }
+            for byte in read {
+                if *byte == b'\n' {
+                    if self.line_blank.take() != Some(false) {
+                        self.blank_lines += 1;
+                    }
+                } else {
+                    let line_blank = self.line_blank.unwrap_or(true) && is_blank_byte(*byte);
+                    self.line_blank = Some(line_blank);
+                }
+            }
+        }
+        poll
+    }
+}
+
+/// Sends the transactions of the input file to `sender`, after checking its header
+/// with `check_headers`, or with `check_file_columns` when `options` has a column
+/// map. Nothing is sent when the header is unexpected.
+///
+/// A conflicting repeat within the dedup window fails the ingestion after the rows
+/// before it were sent, and so does a file which disagrees with its manifest after
+/// all its rows were.
+pub async fn read_data_with_options(
+    file_path: String,
+    sender: Sender<Transaction>,
+    options: IngestOptions,
+) -> Result<IngestReport, IngestError> {
+    let mut dedup = options.dedup.map(DedupWindow::new);
+    let mut tracker = options.track_raw_client_ids.then(ClientIdTracker::default);
+    let mut report = read_file(
+        &file_path,
+        &sender,
+        &options,
+        dedup.as_mut(),
+        tracker.as_mut(),
+    )
+    .await?;
+    report.client_collisions = tracker.map_or_else(Vec::new, |tracker| tracker.collisions());
+    Ok(report)
+}
+
+/// Like `read_data_with_options` for each file in turn, as if they were one input
+/// whose rows are all sent to `sender`. The dedup window spans the files, so rows
+/// repeated at the start of a file from the end of the previous one are skipped.
+/// The skip and the limit apply to each file, and client collisions are reported
+/// across files.
+pub async fn read_data_multi(
+    file_paths: Vec<String>,
+    sender: Sender<Transaction>,
+    options: IngestOptions,
+) -> Result<IngestReport, IngestError> {
+    let mut dedup = options.dedup.map(DedupWindow::new);
+    let mut tracker = options.track_raw_client_ids.then(ClientIdTracker::default);
+    let mut report = IngestReport::default();
+    for file_path in &file_paths {
+        report.add(
+            read_file(
+                file_path,
+                &sender,
+                &options,
+                dedup.as_mut(),
+                tracker.as_mut(),
+            )
+            .await?,
+        );
+    }
+    report.client_collisions = tracker.map_or_else(Vec::new, |tracker| tracker.collisions());
+    Ok(report)
+}
+
+async fn read_file(
+    file_path: &str,
+    sender: &Sender<Transaction>,
+    options: &IngestOptions,
+    mut dedup: Option<&mut DedupWindow>,
+    tracker: Option<&mut ClientIdTracker>,
+) -> Result<IngestReport, IngestError> {
+    let (indices, tracked) = match (&options.column_map, tracker.is_some()) {
+        (column_map, true) => {
+            let (indices, columns) = check_tracked_columns(
+                file_path,
+                column_map.as_ref().unwrap_or(&ColumnMap::default()),
+                options.allow_extra_columns,
+            )?;
+            (Some(indices), Some(columns))
+        }
+        (Some(column_map), false) => (
+            Some(check_file_columns(
+                file_path,
+                column_map,
+                options.allow_extra_columns,
+            )?),
+            None,
+        ),
+        (None, false) => {
+            check_file_headers(file_path, options.allow_extra_columns)?;
+            (None, None)
+        }
+    };
+    let manifest = match &options.manifest {
+        Some(manifest) => Some(manifest.clone()),
+        None => Manifest::read_sidecar(file_path)?,
+    };
+    let mut file = tokio::fs::File::open(file_path).await?;
+    file.set_max_buf_size(options.io.buffer_size);
+    // The BOM is skipped after hashing, as the manifest hashes the file as stored
+    let mut file = BlankLineCounter::new(SkipBom::new(HashingReader::new(
+        IngestReader::new(file, options.io),
+        manifest.is_some(),
+    )));
+    let rows: Pin<Box<dyn Stream<Item = Result<Transaction, TransactionLogError>> + Send>> =
+        match (indices, tracker.zip(tracked), options.parser) {
+            (Some(indices), Some((tracker, columns)), _parser) => {
+                let indices = indices
+                    .with_number_locale(options.number_locale)
+                    .with_amount_parsing(options.amount_parsing.clone());
+                Box::pin(record_stream(&mut file, move |_headers, record| {
+                    tracker.track_record(record, &columns);
+                    indices.extract(&*record)
+                }))
+            }
+            (Some(indices), None, _parser) => Box::pin(mapped_transaction_stream(
+                &mut file,
+                indices
+                    .with_number_locale(options.number_locale)
+                    .with_amount_parsing(options.amount_parsing.clone()),
+            )),
+            (None, _tracker, IngestParser::Fast)
+                if options.number_locale == NumberLocale::Dot
+                    && options.amount_parsing == AmountParsing::default() =>
+            {
+                Box::pin(
+                    fast::transaction_stream(&mut file).map(|row| row.map_err(|err| err.error)),
+                )
+            }
+            (None, _tracker, _parser) => Box::pin(normalized_transaction_stream(
+                &mut file,
+                options.number_locale,
+                options.amount_parsing.clone(),
+            )),
+        };
+    let mut tally = RowTally::default();
+    let mut rows = rows.map(|row| {
+        tally.count(&row);
+        match options.amount_scale {
+            Some(scale) => row.and_then(|tx| tx.within_scale(scale)),
+            None => row,
+        }
+    });
+    let mut report = IngestReport::default();
+
+    let silent_rows = match options.skip_mode {
+        SkipMode::Discard => {
+            report.rows_skipped = (&mut rows)
+                .take(options.skip)
+                .fold(0, |skipped, _row| skipped + 1)
+                .await;
+            0
+        }
+        SkipMode::ExecuteSilent => options.skip,
+    };
+    let limit = options
+        .limit
+        .map_or(usize::MAX, |limit| limit.saturating_add(silent_rows));
+
+    let mut limited_rows = (&mut rows).take(limit);
+    let mut row_index = 0;
+    while let Some(fetched_tx) = limited_rows.next().await {
+        if row_index < silent_rows {
+            report.rows_executed_silently += 1;
+        } else {
+            report.rows_read += 1;
+        }
+        row_index += 1;
+        match fetched_tx {
+            Ok(tx) => {
+                match dedup.as_mut().map_or(Seen::New, |dedup| dedup.check(&tx)) {
+                    Seen::New => {}
+                    Seen::Repeat => {
+                        report.rows_deduped += 1;
+                        continue;
+                    }
+                    Seen::Conflict(first) => {
+                        return Err(IngestError::DedupConflict {
+                            file: file_path.to_string(),
+                            tx_id: tx.tx_id(),
+                            kind: tx.kind(),
+                            first,
+                            repeat: DedupFingerprint::of(&tx),
+                        });
+                    }
+                }
+                #[cfg(test)]
+                crate::test_utils::reader_jitter().await;
+                sender.send(tx).await.ok();
+            }
+            Err(_err) => report.rows_malformed += 1,
+        }
+    }
+    report.limit_reached = options.limit.is_some() && rows.next().await.is_some();
+    drop(rows);
+    report.blank_lines_skipped = file.blank_lines;
+    if let Some(manifest) = manifest.filter(|_manifest| !report.limit_reached) {
+        manifest.verify(&tally.into_manifest(file.inner.get_mut().finish()))?;
+    }
+    Ok(report)
+}
+
+#[derive(Deserialize)]
+struct RemapRow {
+    from: ClientId,
+    to: ClientId,
+}
+
+/// Reads a CSV file with `from,to` client id pairs for `Ledger::apply_client_remap`.
+pub fn read_client_remap(file_path: &str) -> Result<HashMap<ClientId, ClientId>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(file_path)?
+        .deserialize::<RemapRow>()
+        .map(|row| row.map(|row| (row.from, row.to)))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct OverdraftLimitRow {
+    client: ClientId,
+    limit: Decimal,
+}
+
+/// Reads a CSV file with `client,limit` pairs for `LedgerBuilder::overdraft_limits`.
+pub fn read_overdraft_limits(file_path: &str) -> Result<HashMap<ClientId, Decimal>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(file_path)?
+        .deserialize::<OverdraftLimitRow>()
+        .map(|row| row.map(|row| (row.client, row.limit)))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct DisputeLimitRow {
+    client: ClientId,
+    limit: DisputeLimit,
+}
+
+/// Reads a CSV file with `client,limit` pairs for `LedgerBuilder::dispute_limits`,
+/// where limits are amounts or percentages such as `25%`.
+pub fn read_dispute_limits(file_path: &str) -> Result<HashMap<ClientId, DisputeLimit>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(file_path)?
+        .deserialize::<DisputeLimitRow>()
+        .map(|row| row.map(|row| (row.client, row.limit)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::Ledger;
+    use crate::test_utils::{render, write_input};
+
+    const INPUT: &str = "type, client, tx, amount\n\
+                         deposit, 1, 1, 10.0\n\
+                         deposit, 2, 2, 20.0\n\
+                         dispute, 1, 1,\n\
+                         refund, 2, 3, 1.0\n\
+                         resolve, 1, 1,\n\
+                         withdrawal, 1, 4, 2.5\n\
+                         deposit, 3, 5, 30.0\n\
+                         withdrawal, 2, 6, 5.0\n\
+                         dispute, 2, 2,\n";
+
+    async fn run(file_name: &str, options: IngestOptions) -> (Vec<u8>, IngestReport) {
+        let path = write_input(file_name, INPUT);
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let reader = tokio::spawn(read_data_with_options(
+            path.to_str().unwrap().to_string(),
+            sender,
+            options,
+        ));
+        let mut ledger = Ledger::new();
+        while let Some(tx) = receiver.recv().await {
+            ledger.execute(&tx).ok();
+        }
+        let report = reader.await.unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+        (render(&ledger), report)
+    }
+
+    /// Runs the files through `read_data_multi`, removing them afterwards.
+    async fn run_multi(
+        files: &[(&str, String)],
+        options: IngestOptions,
+    ) -> (Vec<u8>, Result<IngestReport, IngestError>) {
+        let paths: Vec<_> = files
+            .iter()
+            .map(|(name, contents)| write_input(name, contents))
+            .collect();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let reader = tokio::spawn(read_data_multi(
+            paths
+                .iter()
+                .map(|path| path.to_str().unwrap().to_string())
+                .collect(),
+            sender,
+            options,
+        ));
+        let mut ledger = Ledger::new();
+        while let Some(tx) = receiver.recv().await {
+            ledger.execute(&tx).ok();
+        }
+        let report = reader.await.unwrap();
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+        (render(&ledger), report)
+    }
+
+    /// Deposits, withdrawals and disputes of distinct deposits, with unique tx ids.
+    fn overlapping_rows(rows: std::ops::RangeInclusive<u32>) -> String {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx_id in rows {
+            let row = match tx_id % 10 {
+                0 => format!("dispute,{},{},\n", (tx_id - 3) % 7 + 1, tx_id - 3),
+                5 => format!("withdrawal,{},{},{}.5\n", tx_id % 7 + 1, tx_id, tx_id % 3),
+                _ => format!("deposit,{},{},{}.25\n", tx_id % 7 + 1, tx_id, tx_id),
+            };
+            input.push_str(&row);
+        }
+        input
+    }
+
+    #[tokio::test]
+    async fn amount_scale_rejects_or_rounds_finer_amounts() {
+        let (unscaled, unscaled_report) = run("scale_none.csv", IngestOptions::default()).await;
+        let scaled = |amount_scale| IngestOptions {
+            amount_scale: Some(amount_scale),
+            ..IngestOptions::default()
+        };
+        let (four, four_report) = run("scale_four.csv", scaled(AmountScale::new(4))).await;
+        assert_eq!(four, unscaled);
+        assert_eq!(four_report, unscaled_report);
+
+        // The withdrawal of 2.5 is malformed, or rounded to 3
+        let (zero, zero_report) = run("scale_zero.csv", scaled(AmountScale::new(0))).await;
+        assert_eq!(
+            zero_report.rows_malformed,
+            unscaled_report.rows_malformed + 1
+        );
+        let (rounded, rounded_report) =
+            run("scale_round.csv", scaled(AmountScale::new(0).rounding())).await;
+        assert_eq!(rounded_report, unscaled_report);
+        let client_1 = |output: &[u8]| {
+            String::from_utf8(output.to_vec())
+                .unwrap()
+                .lines()
+                .find(|line| line.starts_with("1,"))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(client_1(&unscaled), "1,7.5,0,7.5,false");
+        assert_eq!(client_1(&zero), "1,10,0,10,false");
+        assert_eq!(client_1(&rounded), "1,7,0,7,false");
+    }
+
+    #[tokio::test]
+    async fn dedup_window_spans_overlapping_files() {
+        let options = IngestOptions {
+            dedup: Some(DedupConfig { window: 150 }),
+            ..IngestOptions::default()
+        };
+        let (union, union_report) = run_multi(
+            &[("dedup_union", overlapping_rows(1..=1000))],
+            options.clone(),
+        )
+        .await;
+        let (output, report) = run_multi(
+            &[
+                ("dedup_first", overlapping_rows(1..=600)),
+                ("dedup_second", overlapping_rows(501..=1000)),
+            ],
+            options,
+        )
+        .await;
+        assert_eq!(output, union);
+        let (report, union_report) = (report.unwrap(), union_report.unwrap());
+        assert_eq!(union_report.rows_deduped, 0);
+        assert_eq!(report.rows_deduped, 100);
+        assert_eq!(report.rows_read, 1100);
+
+        // Without the window the ledger rejects the overlapping rows instead, since
+        // they reuse the tx ids of the rows applied first
+        let (rejected, report) = run_multi(
+            &[
+                ("dedup_off_first", overlapping_rows(1..=600)),
+                ("dedup_off_second", overlapping_rows(501..=1000)),
+            ],
+            IngestOptions::default(),
+        )
+        .await;
+        assert_eq!(rejected, union);
+        assert_eq!(report.unwrap().rows_deduped, 0);
+    }
+
+    #[tokio::test]
+    async fn conflicting_repeat_aborts_ingestion() {
+        let second = "type,client,tx,amount\n\
+                      deposit,1,2,5.0\n\
+                      deposit,1,3,7.5\n\
+                      deposit,1,4,1.0\n";
+        let (output, result) = run_multi(
+            &[
+                (
+                    "dedup_conflict_first",
+                    "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\ndeposit,2,3,7.5\n"
+                        .to_string(),
+                ),
+                ("dedup_conflict_second", second.to_string()),
+            ],
+            IngestOptions {
+                dedup: Some(DedupConfig { window: 10 }),
+                ..IngestOptions::default()
+            },
+        )
+        .await;
+        let err = result.unwrap_err();
+        let file = match &err {
+            IngestError::DedupConflict { file, .. } => file.clone(),
+            _ => panic!("unexpected error {:?}", err),
+        };
+        assert!(file.contains("dedup_conflict_second"));
+        assert_eq!(
+            err,
+            IngestError::DedupConflict {
+                file: file.clone(),
+                tx_id: 3,
+                kind: TxTypeTag::Deposit,
+                first: DedupFingerprint {
+                    client_id: 2,
+                    amount: Some(Decimal::new(75, 1)),
+                },
+                repeat: DedupFingerprint {
+                    client_id: 1,
+                    amount: Some(Decimal::new(75, 1)),
+                },
+            }
+        );
+        assert_eq!(err.code(), "dedup_conflict");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "deposit of tx 3 in {} with client 1 amount 7.5 repeats one within the dedup \
+                 window with client 2 amount 7.5",
+                file
+            )
+        );
+        // Rows before the conflict were sent, the ones after it were not
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,15,0,15,false\n2,7.5,0,7.5,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn truncated_client_ids_are_reported_as_collisions() {
+        let options = IngestOptions {
+            track_raw_client_ids: true,
+            ..IngestOptions::default()
+        };
+        // 65543 and 131079 both wrap to client 7
+        let wrapped = "type,client,tx,amount\n\
+                       deposit,65543,1,10.0\n\
+                       deposit,131079,2,5.0\n\
+                       deposit,8,3,1.0\n\
+                       withdrawal,131079,4,2.0\n";
+        let (output, report) = run_multi(
+            &[("client_ids_wrapped", wrapped.to_string())],
+            options.clone(),
+        )
+        .await;
+        let expected = "client,available,held,total,locked\n7,13,0,13,false\n8,1,0,1,false\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+        let report = report.unwrap();
+        assert_eq!(report.rows_malformed, 0);
+        assert_eq!(
+            report.client_collisions,
+            vec![PossibleClientCollision {
+                client: 7,
+                raw_ids: vec![65_543, 131_079],
+            }]
+        );
+
+        // Inputs truncated upstream carry the raw ids in their own column, here split
+        // across files.
+        let first = "type,client,tx,amount,raw_client\ndeposit,7,1,10.0,65543\n";
+        let second = "type,client,tx,amount,raw_client\n\
+                      deposit,7,2,5.0,131079\n\
+                      deposit,8,3,1.0,8\n\
+                      withdrawal,7,4,2.0,131079\n";
+        let (output, report) = run_multi(
+            &[
+                ("client_ids_raw_first", first.to_string()),
+                ("client_ids_raw_second", second.to_string()),
+            ],
+            options,
+        )
+        .await;
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+        assert_eq!(
+            report.unwrap().client_collisions,
+            vec![PossibleClientCollision {
+                client: 7,
+                raw_ids: vec![65_543, 131_079],
+            }]
+        );
+
+        // Without tracking the ids beyond the range are malformed
+        let (output, report) = run_multi(
+            &[("client_ids_untracked", wrapped.to_string())],
+            IngestOptions::default(),
+        )
+        .await;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n8,1,0,1,false\n"
+        );
+        let report = report.unwrap();
+        assert_eq!(report.rows_malformed, 3);
+        assert!(report.client_collisions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn manifest_mismatches_fail_after_all_rows() {
+        let path = write_input("manifest_source", INPUT);
+        let manifest = manifest::generate_manifest(
+            path.to_str().unwrap(),
+            NumberLocale::Dot,
+            AmountParsing::default(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(manifest.rows, 9);
+        assert_eq!(manifest.deposit_sum, Some(Decimal::new(60, 0)));
+        assert_eq!(manifest.withdrawal_sum, Some(Decimal::new(75, 1)));
+        let (expected, _report) = run("manifest_baseline", IngestOptions::default()).await;
+
+        for parser in [IngestParser::Csv, IngestParser::Fast] {
+            let options = IngestOptions {
+                parser,
+                manifest: Some(manifest.clone()),
+                ..IngestOptions::default()
+            };
+            let (output, report) =
+                run_multi(&[("manifest_match", INPUT.to_string())], options).await;
+            assert_eq!(output, expected);
+            assert_eq!(report.unwrap().rows_read, 9);
+        }
+
+        let zeros = "0".repeat(64);
+        for (declared, field, expected_value, actual) in [
+            (
+                Manifest {
+                    sha256: zeros.clone(),
+                    ..manifest.clone()
+                },
+                "sha256",
+                zeros.clone(),
+                manifest.sha256.clone(),
+            ),
+            (
+                Manifest {
+                    rows: 10,
+                    ..manifest.clone()
+                },
+                "rows",
+                "10".to_string(),
+                "9".to_string(),
+            ),
+            (
+                Manifest {
+                    withdrawal_sum: Some(Decimal::new(10, 0)),
+                    ..manifest.clone()
+                },
+                "withdrawal_sum",
+                "10".to_string(),
+                "7.5".to_string(),
+            ),
+        ] {
+            let options = IngestOptions {
+                manifest: Some(declared),
+                ..IngestOptions::default()
+            };
+            let (output, report) =
+                run_multi(&[("manifest_mismatch", INPUT.to_string())], options).await;
+            // The rows were sent before the end of the file was known
+            assert_eq!(output, expected);
+            assert_eq!(
+                report,
+                Err(IngestError::ManifestMismatch {
+                    field,
+                    expected: expected_value,
+                    actual,
+                })
+            );
+        }
+
+        // A sidecar next to the input is found without options, here for an input
+        // truncated in transit
+        let truncated = &INPUT[..INPUT.len() - "dispute, 2, 2,\n".len()];
+        let sidecar = Manifest::sidecar_path(write_input("manifest_sidecar", "").to_str().unwrap());
+        std::fs::write(&sidecar, serde_json::to_string(&manifest).unwrap()).unwrap();
+        let (_output, report) = run_multi(
+            &[("manifest_sidecar", truncated.to_string())],
+            IngestOptions::default(),
+        )
+        .await;
+        std::fs::remove_file(sidecar).unwrap();
+        assert!(matches!(
+            report,
+            Err(IngestError::ManifestMismatch {
+                field: "sha256",
+                ..
+            })
+        ));
+
+        // Files stopped by the limit are not checked
+        let options = IngestOptions {
+            limit: Some(2),
+            manifest: Some(Manifest {
+                sha256: zeros,
+                ..manifest
+            }),
+            ..IngestOptions::default()
+        };
+        let (_output, report) = run_multi(&[("manifest_limit", INPUT.to_string())], options).await;
+        assert!(report.unwrap().limit_reached);
+    }
+
+    #[tokio::test]
+    async fn io_config_does_not_change_rows() {
+        let (expected, expected_report) = run("ingest_io_default", IngestOptions::default()).await;
+        // Buffers of a few bytes split every record, and fields within them
+        for parser in [IngestParser::Csv, IngestParser::Fast] {
+            for buffer_size in [1, 5, 64] {
+                for prefetch in [false, true] {
+                    let options = IngestOptions {
+                        parser,
+                        io: IngestIoConfig {
+                            buffer_size,
+                            prefetch,
+                        },
+                        ..IngestOptions::default()
+                    };
+                    let (output, report) = run("ingest_io", options).await;
+                    assert_eq!(output, expected);
+                    assert_eq!(report, expected_report);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn limit_stops_early() {
+        let (output, report) = run(
+            "ingest_limit",
+            IngestOptions {
+                limit: Some(3),
+                ..IngestOptions::default()
+            },
+        )
+        .await;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,0,10,10,false\n2,20,0,20,false\n"
+        );
+        assert_eq!(
+            report,
+            IngestReport {
+                rows_read: 3,
+                limit_reached: true,
+                ..IngestReport::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn limit_beyond_input() {
+        let (full, _report) = run("ingest_full", IngestOptions::default()).await;
+        let (output, report) = run(
+            "ingest_limit_beyond",
+            IngestOptions {
+                limit: Some(9),
+                ..IngestOptions::default()
+            },
+        )
+        .await;
+        assert_eq!(output, full);
+        assert_eq!(report.rows_read, 9);
+        assert_eq!(report.rows_malformed, 1);
+        assert!(!report.limit_reached);
+    }
+
+    #[tokio::test]
+    async fn skip_discards_rows() {
+        let (output, report) = run(
+            "ingest_skip",
+            IngestOptions {
+                skip: 6,
+                ..IngestOptions::default()
+            },
+        )
+        .await;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n3,30,0,30,false\n"
+        );
+        assert_eq!(
+            report,
+            IngestReport {
+                rows_read: 3,
+                rows_skipped: 6,
+                ..IngestReport::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_silent_matches_limited_full_run() {
+        let (silent, silent_report) = run(
+            "ingest_silent",
+            IngestOptions {
+                skip: 4,
+                skip_mode: SkipMode::ExecuteSilent,
+                limit: Some(3),
+                ..IngestOptions::default()
+            },
+        )
+        .await;
+        let (limited, _report) = run(
+            "ingest_silent_limited",
+            IngestOptions {
+                limit: Some(7),
+                ..IngestOptions::default()
+            },
+        )
+        .await;
+        assert_eq!(silent, limited);
+        assert_eq!(
+            silent_report,
+            IngestReport {
+                rows_read: 3,
+                rows_malformed: 1,
+                rows_executed_silently: 4,
+                limit_reached: true,
+                ..IngestReport::default()
+            }
+        );
+    }
+
+    #[test]
+    fn client_remap_file() {
+        let path = write_input("client_remap", "from, to\n1, 2\n3, 2\n");
+        let remap = read_client_remap(path.to_str().unwrap()).unwrap();
+        assert_eq!(remap, HashMap::from([(1, 2), (3, 2)]));
+        std::fs::remove_file(&path).unwrap();
+
+        let path = write_input("client_remap_invalid", "from, to\n1, x\n");
+        assert!(read_client_remap(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn overdraft_limits_file() {
+        let path = write_input("overdraft_limits", "client, limit\n1, -50\n2, -12.5\n");
+        let limits = read_overdraft_limits(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            limits,
+            HashMap::from([(1, Decimal::from(-50)), (2, Decimal::new(-125, 1))])
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn blank_lines_are_not_malformed() {
+        let input = "\n\
+                     type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     \n\
+                     \t \n\
+                     ,,,\n\
+                     \x20, , ,\r\n\
+                     , 1, 2, 5.0\n\
+                     deposit, 1, 3, 1.0\n\
+                     \n\
+                     \n";
+        let path = write_input("ingest_blank_lines", input);
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let reader = tokio::spawn(read_data_with_options(
+            path.to_str().unwrap().to_string(),
+            sender,
+            IngestOptions::default(),
+        ));
+        let mut transactions = Vec::new();
+        while let Some(tx) = receiver.recv().await {
+            transactions.push(tx);
+        }
+        let report = reader.await.unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(
+            report,
+            IngestReport {
+                rows_read: 3,
+                rows_malformed: 1,
+                blank_lines_skipped: 7,
+                ..IngestReport::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn blank_last_line_without_newline() {
+        let mut reader = BlankLineCounter::new("a,b\n  ,".as_bytes());
+        let mut contents = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut contents)
+            .await
+            .unwrap();
+        assert_eq!(reader.blank_lines, 1);
+        assert!(is_blank_line(b" ,\t,\r\n"));
+        assert!(!is_blank_line(b",1,,"));
+    }
+
+    #[test]
+    fn unexpected_headers() {
+        let unexpected = |found: &[&str]| {
+            Err(IngestError::UnexpectedHeader {
+                expected: EXPECTED_HEADERS.map(str::to_string).to_vec(),
+                found: found.iter().map(|header| header.to_string()).collect(),
+            })
+        };
+        assert_eq!(
+            check_headers([" type", "client ", "tx", "amount"], false),
+            Ok(())
+        );
+        let swapped = ["type", "tx", "client", "amount"];
+        assert_eq!(check_headers(swapped, true), unexpected(&swapped));
+        let misspelled = ["type", "clinet", "tx", "amount"];
+        assert_eq!(check_headers(misspelled, false), unexpected(&misspelled));
+        let extra = ["type", "client", "tx", "amount", "currency"];
+        assert_eq!(check_headers(extra, false), unexpected(&extra));
+        assert_eq!(check_headers(extra, true), Ok(()));
+        assert_eq!(
+            check_headers(["type", "client", "tx", "amount", " reference"], false),
+            Ok(())
+        );
+        assert_eq!(
+            check_headers(
+                ["type", "client", "tx", "amount", "reason", "reference"],
+                false
+            ),
+            Ok(())
+        );
+        let twice = ["type", "client", "tx", "amount", "reason", "reason"];
+        assert_eq!(check_headers(twice, false), unexpected(&twice));
+        assert_eq!(
+            check_headers(["type", "client", "tx"], true),
+            unexpected(&["type", "client", "tx"])
+        );
+    }
+
+    async fn read_file(
+        file_name: &str,
+        input: &str,
+        allow_extra_columns: bool,
+    ) -> (Vec<Transaction>, Result<IngestReport, IngestError>) {
+        let path = write_input(file_name, input);
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let reader = tokio::spawn(read_data_with_options(
+            path.to_str().unwrap().to_string(),
+            sender,
+            IngestOptions {
+                allow_extra_columns,
+                ..IngestOptions::default()
+            },
+        ));
+        let mut transactions = Vec::new();
+        while let Some(tx) = receiver.recv().await {
+            transactions.push(tx);
+        }
+        let report = reader.await.unwrap();
+        std::fs::remove_file(path).unwrap();
+        (transactions, report)
+    }
+
+    #[tokio::test]
+    async fn unexpected_header_stops_before_rows() {
+        let input = "type, tx, client, amount
+deposit, 1, 2, 1.0
+";
+        let (transactions, report) = read_file("ingest_swapped_header", input, false).await;
+        assert!(transactions.is_empty());
+        assert!(matches!(report, Err(IngestError::UnexpectedHeader { .. })));
+
+        // Without a header the first row is taken for one and rejected.
+        let input = "deposit, 1, 1, 1.0
+deposit, 1, 2, 1.0
+";
+        let (transactions, report) = read_file("ingest_headerless", input, true).await;
+        assert!(transactions.is_empty());
+        assert_eq!(
+            report.unwrap_err().to_string(),
+            "unexpected header \"deposit,1,1,1.0\", expected \"type,client,tx,amount\""
+        );
+    }
+
+    #[tokio::test]
+    async fn extra_columns_and_field_counts() {
+        let input = "type, client, tx, amount, note
+\
+                     deposit, 1, 1, 1.0, first
+\
+                     deposit, 1, 2, 1.0
+\
+                     deposit, 1, 3, 1.0, third, extra
+";
+        let (_transactions, report) = read_file("ingest_extra_strict", input, false).await;
+        assert!(report.is_err());
+
+        let (transactions, report) = read_file("ingest_extra_allowed", input, true).await;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(report.unwrap().rows_malformed, 2);
+
+        let mut rows = transaction_stream(input.as_bytes());
+        assert!(rows.next().await.unwrap().is_ok());
+        assert_eq!(
+            rows.next().await.unwrap(),
+            Err(TransactionLogError::TooFewFields)
+        );
+        assert_eq!(
+            rows.next().await.unwrap(),
+            Err(TransactionLogError::TooManyFields)
+        );
+    }
+
+    #[tokio::test]
+    async fn column_map_reads_bank_export() {
+        const BANK_INPUT: &str = "customer_id, reference, txn_type, value\n\
+                                  1, 1, credit, 10.0\n\
+                                  2, 2, credit, 20.0\n\
+                                  1, 1, dispute,\n\
+                                  2, 3, refund, 1.0\n\
+                                  1, 1, resolve,\n\
+                                  1, 4, debit, 2.5\n\
+                                  3, 5, deposit, 30.0\n\
+                                  2, 6, debit, 5.0\n\
+                                  2, 2, dispute,\n";
+        let column_map = ColumnMap::from_toml(
+            "type = \"txn_type\"\n\
+             client = \"customer_id\"\n\
+             tx = \"reference\"\n\
+             amount = \"value\"\n\
+             [type_aliases]\n\
+             credit = \"deposit\"\n\
+             debit = \"withdrawal\"\n",
+        )
+        .unwrap();
+        let run_mapped = |file_name, input, column_map| async move {
+            let path = write_input(file_name, input);
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+            let reader = tokio::spawn(read_data_with_options(
+                path.to_str().unwrap().to_string(),
+                sender,
+                IngestOptions {
+                    column_map: Some(column_map),
+                    ..IngestOptions::default()
+                },
+            ));
+            let mut ledger = Ledger::new();
+            while let Some(tx) = receiver.recv().await {
+                ledger.execute(&tx).ok();
+            }
+            let report = reader.await.unwrap();
+            std::fs::remove_file(path).unwrap();
+            (render(&ledger), report)
+        };
+
+        let (expected, expected_report) = run("ingest_spec_schema", IngestOptions::default()).await;
+        let (output, report) =
+            run_mapped("ingest_bank_schema", BANK_INPUT, column_map.clone()).await;
+        assert_eq!(output, expected);
+        assert_eq!(report.unwrap(), expected_report);
+
+        // The spec format is read with the default map.
+        let (output, _report) = run_mapped("ingest_default_map", INPUT, ColumnMap::default()).await;
+        assert_eq!(output, expected);
+
+        let (output, report) = run_mapped("ingest_bank_as_spec", INPUT, column_map).await;
+        assert_eq!(output, render(&Ledger::new()));
+        assert_eq!(
+            report.unwrap_err().to_string(),
+            "input has no column \"txn_type\", which the column map names for \"type\""
+        );
+    }
+
+    /// `rows` rows in which most deposits are disputed right after and most disputes
+    /// resolved or charged back right after, interleaved with withdrawals which only
+    /// succeed while the funds are available, so that any reordering changes balances.
+    fn order_sensitive_input(rows: u32) -> String {
+        let mut seed: u64 = 7;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as u32
+        };
+        let mut input = String::from("type,client,tx,amount\n");
+        let mut tx_id = 0;
+        while tx_id < rows {
+            tx_id += 1;
+            let client_id = next() % 100 + 1;
+            input.push_str(&format!(
+                "deposit,{},{},{}.{:02}\n",
+                client_id,
+                tx_id,
+                next() % 50,
+                next() % 100
+            ));
+            let deposit_id = tx_id;
+            if next() % 4 != 0 {
+                input.push_str(&format!("dispute,{},{},\n", client_id, deposit_id));
+                let settle = ["resolve", "resolve", "chargeback"][next() as usize % 3];
+                input.push_str(&format!("{},{},{},\n", settle, client_id, deposit_id));
+            }
+            tx_id += 1;
+            input.push_str(&format!(
+                "withdrawal,{},{},{}.{:02}\n",
+                client_id,
+                tx_id,
+                next() % 40,
+                next() % 100
+            ));
+        }
+        input
+    }
+
+    #[test]
+    fn application_order_is_file_order_under_any_scheduling() {
+        assert_deterministic("ingest_determinism", 6_000, 2 * 1024);
+    }
+
+    #[test]
+    #[ignore = "takes minutes without optimizations, run with `cargo test --release -- --ignored`"]
+    fn application_order_is_file_order_under_stress() {
+        assert_deterministic("ingest_determinism_stress", 200_000, 64 * 1024);
+    }
+
+    /// Runs an order sensitive input through the serial and parallel reader with every
+    /// channel capacity and runtime flavor under jitter, and compares the accounts to
+    /// a run without any channel. Parallel chunks of `chunk_size` bytes are small, so
+    /// that many are parsed out of order.
+    fn assert_deterministic(file_name: &str, rows: u32, chunk_size: u64) {
+        use crate::pipeline::{CsvSource, Pipeline};
+
+        let input = order_sensitive_input(rows);
+        let path = write_input(file_name, &input);
+        let mut expected_ledger = Ledger::new();
+        Pipeline::new(CsvSource::new(input.as_bytes()))
+            .run(&mut expected_ledger)
+            .unwrap();
+        let expected = render(&expected_ledger);
+
+        let mut seed = 0;
+        for capacity in [1, 2, 7, 4096] {
+            for multi_thread in [false, true] {
+                for workers in [None, Some(4)] {
+                    seed += 1;
+                    let runtime = match multi_thread {
+                        true => tokio::runtime::Builder::new_multi_thread()
+                            .worker_threads(4)
+                            .build(),
+                        false => tokio::runtime::Builder::new_current_thread().build(),
+                    }
+                    .unwrap();
+                    let file_path = path.to_str().unwrap().to_string();
+                    let output = runtime.block_on(run_with_jitter(
+                        file_path, capacity, workers, chunk_size, seed,
+                    ));
+                    assert!(
+                        output == expected,
+                        "capacity {}, multi-thread {}, workers {:?}",
+                        capacity,
+                        multi_thread,
+                        workers
+                    );
+                }
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Reads the file on a task and executes it on a blocking thread, both pausing
+    /// between rows as drawn from `seed`.
+    async fn run_with_jitter(
+        file_path: String,
+        capacity: usize,
+        workers: Option<usize>,
+        chunk_size: u64,
+        seed: u64,
+    ) -> Vec<u8> {
+        use crate::pipeline::Pipeline;
+        use crate::test_utils::{Jitter, CONSUMER_JITTER, READER_JITTER};
+        use std::cell::RefCell;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(capacity);
+        let read = async move {
+            match workers {
+                Some(workers) => {
+                    parallel::read_data_chunked(file_path, sender, workers, chunk_size, false)
+                        .await
+                        .map(|_row_errors| ())
+                }
+                None => read_data_with_options(file_path, sender, IngestOptions::default())
+                    .await
+                    .map(|_report| ()),
+            }
+        };
+        let reader = tokio::spawn(READER_JITTER.scope(RefCell::new(Jitter::new(seed)), read));
+        let ledger = tokio::task::spawn_blocking(move || {
+            CONSUMER_JITTER.with(|jitter| *jitter.borrow_mut() = Some(Jitter::new(!seed)));
+            let mut ledger = Ledger::new();
+            Pipeline::new(receiver).run(&mut ledger).unwrap();
+            CONSUMER_JITTER.with(|jitter| *jitter.borrow_mut() = None);
+            ledger
+        })
+        .await
+        .unwrap();
+        reader.await.unwrap().unwrap();
+        render(&ledger)
+    }
+}
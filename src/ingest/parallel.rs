@@ -0,0 +1,323 @@
+use crate::accounting::transactions::{Transaction, TransactionLog, TransactionLogError};
+use crate::engine::{EngineError, UTF8_BOM};
+use crate::field_error::diagnose_fields;
+use crate::ingest::{check_headers, is_blank_line, IngestError, RowError};
+use crate::number_locale::NumberLocale;
+use csv::{StringRecord, Trim};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+const MAX_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+pub(crate) struct Header {
+    pub(crate) record: StringRecord,
+    data_start: u64,
+    line_count: u64,
+}
+
+struct ParsedChunk {
+    sequence_index: usize,
+    line_count: u64,
+    transactions: Vec<Transaction>,
+    errors: Vec<RowError>,
+}
+
+/// Parses the input file on up to `workers` blocking tasks and sends transactions
+/// downstream in original file order. Returns rows which failed to parse, with
+/// line numbers relative to the start of the file. The header is checked first like
+/// in `read_data_with_options`.
+///
+/// Chunks are split on newlines, so quoted fields spanning multiple lines are not supported.
+pub async fn read_data_parallel(
+    file_path: String,
+    sender: Sender<Transaction>,
+    workers: usize,
+    allow_extra_columns: bool,
+) -> Result<Vec<RowError>, EngineError> {
+    Ok(read_data_chunked(
+        file_path,
+        sender,
+        workers,
+        MAX_CHUNK_SIZE,
+        allow_extra_columns,
+    )
+    .await?)
+}
+
+pub(crate) async fn read_data_chunked(
+    file_path: String,
+    sender: Sender<Transaction>,
+    workers: usize,
+    max_chunk_size: u64,
+    allow_extra_columns: bool,
+) -> Result<Vec<RowError>, IngestError> {
+    let workers = workers.max(1);
+    let file_len = std::fs::metadata(&file_path)?.len();
+    let header = match read_header(&file_path)? {
+        Some(header) => header,
+        None => return Ok(Vec::new()),
+    };
+    check_headers(&header.record, allow_extra_columns)?;
+    let data_len = file_len - header.data_start;
+    let chunk_size = data_len
+        .div_ceil(workers as u64)
+        .clamp(1, max_chunk_size.max(1));
+    let chunk_count = data_len.div_ceil(chunk_size) as usize;
+
+    let file_path = Arc::new(file_path);
+    let headers = Arc::new(header.record);
+    let mut in_flight: VecDeque<JoinHandle<std::io::Result<ParsedChunk>>> =
+        VecDeque::with_capacity(workers);
+    let mut next_chunk = 0;
+    let mut lines_before_chunk = header.line_count;
+    let mut row_errors = Vec::new();
+
+    while next_chunk < chunk_count || !in_flight.is_empty() {
+        while next_chunk < chunk_count && in_flight.len() < workers {
+            let start = header.data_start + next_chunk as u64 * chunk_size;
+            let end = (start + chunk_size).min(file_len);
+            let file_path = Arc::clone(&file_path);
+            let headers = Arc::clone(&headers);
+            let data_start = header.data_start;
+            let sequence_index = next_chunk;
+            in_flight.push_back(tokio::task::spawn_blocking(move || {
+                parse_chunk(&file_path, &headers, sequence_index, data_start, start, end)
+            }));
+            next_chunk += 1;
+        }
+
+        let chunk = in_flight
+            .pop_front()
+            .expect("At least one chunk is in flight")
+            .await
+            .expect("Chunk parser task panicked")?;
+        debug_assert_eq!(chunk.sequence_index, next_chunk - in_flight.len() - 1);
+
+        row_errors.extend(chunk.errors.into_iter().map(|mut row_error| {
+            row_error.line += lines_before_chunk;
+            if let Some(field) = &mut row_error.field {
+                field.line += lines_before_chunk;
+            }
+            row_error
+        }));
+        lines_before_chunk += chunk.line_count;
+        for tx in chunk.transactions {
+            #[cfg(test)]
+            crate::test_utils::reader_jitter().await;
+            sender.send(tx).await.ok();
+        }
+    }
+    Ok(row_errors)
+}
+
+pub(crate) fn read_header(file_path: &str) -> std::io::Result<Option<Header>> {
+    let mut reader = BufReader::new(File::open(file_path)?);
+    let mut line = Vec::new();
+    let mut data_start = 0;
+    let mut line_count = 0;
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        data_start += read as u64;
+        line_count += 1;
+        if line_count == 1 && line.starts_with(UTF8_BOM) {
+            line.drain(..UTF8_BOM.len());
+        }
+        if !is_blank_line(&line) {
+            break;
+        }
+    }
+
+    let mut record = StringRecord::new();
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(Trim::All)
+        .from_reader(line.as_slice())
+        .read_record(&mut record)?;
+    Ok(Some(Header {
+        record,
+        data_start,
+        line_count,
+    }))
+}
+
+/// Parses all records starting within `[start, end)`. A record belongs to the chunk
+/// its first byte falls into, so the partial line at `start` is left to the previous
+/// chunk and the line crossing `end` is read to completion.
+fn parse_chunk(
+    file_path: &str,
+    headers: &StringRecord,
+    sequence_index: usize,
+    data_start: u64,
+    start: u64,
+    end: u64,
+) -> std::io::Result<ParsedChunk> {
+    let mut file = File::open(file_path)?;
+    let mut position = if start > data_start { start - 1 } else { start };
+    file.seek(SeekFrom::Start(position))?;
+    let mut reader = BufReader::new(file);
+    if start > data_start {
+        let mut partial_line = Vec::new();
+        position += reader.read_until(b'\n', &mut partial_line)? as u64;
+    }
+
+    let mut buffer = Vec::new();
+    let mut line_starts = Vec::new();
+    while position < end {
+        line_starts.push(buffer.len() as u64);
+        let read = reader.read_until(b'\n', &mut buffer)?;
+        if read == 0 {
+            line_starts.pop();
+            break;
+        }
+        position += read as u64;
+    }
+    // csv neither counts skipped blank lines nor excludes them from a record's starting
+    // position, so a record's line is derived from the offset of its last byte instead
+    let line_at = |byte: u64| line_starts.partition_point(|&line_start| line_start <= byte) as u64;
+
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(buffer.as_slice());
+    let mut record = StringRecord::new();
+    let mut transactions = Vec::new();
+    let mut errors = Vec::new();
+    loop {
+        match csv_reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) if record.iter().all(str::is_empty) => {}
+            Ok(true) => {
+                let line = line_at(csv_reader.position().byte().saturating_sub(1));
+                let parsed = TransactionLogError::check_field_count(record.len(), headers.len())
+                    .and_then(|()| {
+                        NumberLocale::default().normalize_amount_field(headers, &mut record)
+                    })
+                    .and_then(|()| {
+                        record
+                            .deserialize::<TransactionLog>(Some(headers))
+                            .map_err(|_err| TransactionLogError::InvalidTxType)
+                    })
+                    .and_then(Transaction::try_from);
+                match parsed {
+                    Ok(tx) => transactions.push(tx),
+                    Err(error) => {
+                        let field = diagnose_fields(line, headers, &record, &error);
+                        errors.push(RowError::new(line, error).with_field(field));
+                    }
+                }
+            }
+            Err(_err) => {
+                let line = line_at(csv_reader.position().byte().saturating_sub(1));
+                errors.push(RowError::new(line, TransactionLogError::InvalidTxType));
+            }
+        }
+    }
+
+    Ok(ParsedChunk {
+        sequence_index,
+        line_count: line_starts.len() as u64,
+        transactions,
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::Ledger;
+    use crate::field_error::{FieldError, FieldErrorReason};
+    use crate::test_utils::{generate_input, render, write_input};
+    use std::path::Path;
+
+    async fn run_serial(path: &Path) -> Vec<u8> {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let reader = tokio::spawn(crate::read_data(path.to_str().unwrap().to_string(), sender));
+        let mut ledger = Ledger::new();
+        while let Some(tx) = receiver.recv().await {
+            ledger.execute(&tx).ok();
+        }
+        reader.await.unwrap().unwrap();
+        render(&ledger)
+    }
+
+    async fn run_parallel(
+        path: &Path,
+        workers: usize,
+        chunk_size: u64,
+    ) -> (Vec<u8>, Vec<RowError>) {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let reader = tokio::spawn(read_data_chunked(
+            path.to_str().unwrap().to_string(),
+            sender,
+            workers,
+            chunk_size,
+            false,
+        ));
+        let mut ledger = Ledger::new();
+        while let Some(tx) = receiver.recv().await {
+            ledger.execute(&tx).ok();
+        }
+        let row_errors = reader.await.unwrap().unwrap();
+        (render(&ledger), row_errors)
+    }
+
+    #[tokio::test]
+    async fn parallel_ledger_matches_serial() {
+        let path = write_input("parallel_equivalence", &generate_input(20_000));
+        let serial = run_serial(&path).await;
+        for (workers, chunk_size) in [
+            (1, MAX_CHUNK_SIZE),
+            (4, MAX_CHUNK_SIZE),
+            (8, 1024),
+            (3, 4096),
+        ] {
+            let (parallel, row_errors) = run_parallel(&path, workers, chunk_size).await;
+            assert_eq!(parallel, serial);
+            assert!(row_errors.is_empty());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn malformed_rows_have_global_line_numbers() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    deposit, 2, 2, 2.0\n\
+                    refund, 1, 3, 2.0\n\
+                    withdrawal, 1, 4, 0.5\n\
+                    \n\
+                    ,  , ,\n\
+                    withdrawal, 2, 5,\n\
+                    dispute, 1, 1\n\
+                    deposit, 3, 6, 3.0";
+        let path = write_input("parallel_malformed", data);
+        for (workers, chunk_size) in [(1, MAX_CHUNK_SIZE), (2, 16), (4, 1)] {
+            let (_output, row_errors) = run_parallel(&path, workers, chunk_size).await;
+            assert_eq!(
+                row_errors,
+                vec![
+                    RowError::new(4, TransactionLogError::InvalidTxType).with_field(Some(
+                        FieldError {
+                            line: 4,
+                            column_name: "type".to_string(),
+                            raw_value: "refund".to_string(),
+                            reason: FieldErrorReason::UnknownTxType,
+                        }
+                    )),
+                    RowError::new(8, TransactionLogError::MissingAmount),
+                    RowError::new(9, TransactionLogError::TooFewFields),
+                ]
+            );
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+//! Read sizes and read-ahead of the input, for files on network storage where every
+//! read call pays a round trip.
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Default of `IngestIoConfig::buffer_size`, 1 MiB.
+pub const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+
+/// Buffers read ahead of the one being parsed.
+const PREFETCH_SLOTS: usize = 2;
+
+/// How the input is read. The rows, and so the ledger, are the same whatever the
+/// config, since the parsers get the bytes of the input in order however they are
+/// chunked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IngestIoConfig {
+    /// Bytes requested from the input per read call.
+    pub buffer_size: usize,
+    /// Reads the next buffers in a separate task while the current one is parsed.
+    pub prefetch: bool,
+}
+
+impl Default for IngestIoConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            prefetch: false,
+        }
+    }
+}
+
+/// `inner` read according to an `IngestIoConfig`.
+pub enum IngestReader<R> {
+    Buffered(BufReader<R>),
+    Prefetched(PrefetchReader),
+}
+
+impl<R: AsyncRead + Send + Unpin + 'static> IngestReader<R> {
+    /// Starts the prefetch task when the config asks for it, so it must be called
+    /// within a tokio runtime then.
+    pub fn new(inner: R, config: IngestIoConfig) -> Self {
+        let buffer_size = config.buffer_size.max(1);
+        if config.prefetch {
+            Self::Prefetched(PrefetchReader::new(inner, buffer_size))
+        } else {
+            Self::Buffered(BufReader::with_capacity(buffer_size, inner))
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for IngestReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Buffered(reader) => Pin::new(reader).poll_read(cx, buf),
+            Self::Prefetched(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Reader of the buffers filled by a task reading ahead. The task stops at the end
+/// of the input, at its first error, or when the reader is dropped.
+pub struct PrefetchReader {
+    buffers: mpsc::Receiver<io::Result<Vec<u8>>>,
+    /// Buffer being read and the position in it.
+    current: Vec<u8>,
+    position: usize,
+    task: JoinHandle<()>,
+}
+
+impl PrefetchReader {
+    fn new<R: AsyncRead + Send + Unpin + 'static>(mut inner: R, buffer_size: usize) -> Self {
+        let (sender, buffers) = mpsc::channel(PREFETCH_SLOTS);
+        let task = tokio::spawn(async move {
+            loop {
+                let mut buffer = vec![0; buffer_size];
+                let read = match inner.read(&mut buffer).await {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(err) => {
+                        sender.send(Err(err)).await.ok();
+                        break;
+                    }
+                };
+                buffer.truncate(read);
+                if sender.send(Ok(buffer)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            buffers,
+            current: Vec::new(),
+            position: 0,
+            task,
+        }
+    }
+}
+
+impl AsyncRead for PrefetchReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.position == self.current.len() {
+            match ready!(self.buffers.poll_recv(cx)) {
+                Some(Ok(buffer)) => {
+                    self.current = buffer;
+                    self.position = 0;
+                }
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                // End of the input
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let len = buf.remaining().min(self.current.len() - self.position);
+        let start = self.position;
+        buf.put_slice(&self.current[start..start + len]);
+        self.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for PrefetchReader {
+    fn drop(&mut self) {
+        // Reading stops early with a limit, so the rest is not read ahead for nothing.
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns at most 3 bytes per read, and fails once `fail_at` bytes were read.
+    struct ShortReads {
+        data: Vec<u8>,
+        position: usize,
+        fail_at: Option<usize>,
+    }
+
+    impl AsyncRead for ShortReads {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if self.fail_at.is_some_and(|fail_at| self.position >= fail_at) {
+                return Poll::Ready(Err(io::Error::other("storage went away")));
+            }
+            let len = buf.remaining().min(3).min(self.data.len() - self.position);
+            let start = self.position;
+            buf.put_slice(&self.data[start..start + len]);
+            self.position += len;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn prefetch_reads_input_in_order_then_fails() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        for buffer_size in [1, 2, 7, 4096] {
+            let config = IngestIoConfig {
+                buffer_size,
+                prefetch: true,
+            };
+            let inner = ShortReads {
+                data: data.clone(),
+                position: 0,
+                fail_at: None,
+            };
+            let mut read = Vec::new();
+            IngestReader::new(inner, config)
+                .read_to_end(&mut read)
+                .await
+                .unwrap();
+            assert_eq!(read, data);
+
+            let inner = ShortReads {
+                data: data.clone(),
+                position: 0,
+                fail_at: Some(500),
+            };
+            let mut read = Vec::new();
+            let err = IngestReader::new(inner, config)
+                .read_to_end(&mut read)
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), "storage went away");
+            assert_eq!(read, data[..read.len()]);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Double-entry export of the journal for accounting: every journaled transaction as
+//! debit and credit lines between the sub-accounts it moved funds between.
+//!
+//! Client funds are the `client:<id>:available` and `client:<id>:held` accounts,
+//! owed to clients through `platform:liabilities`. A deposit debits the available
+//! account and credits the liabilities, a withdrawal the reverse, and disputes,
+//! resolves and chargebacks move funds between the client's held account and its
+//! available account or the liabilities.
+use crate::accounting::journal::JournalEntry;
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+pub const LIABILITIES_ACCOUNT: &str = "platform:liabilities";
+
+/// One side of the movement of a transaction. Exactly one of `debit` and `credit`
+/// is set, and it is positive.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct JournalLine {
+    pub seq: u64,
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    pub account: String,
+    pub debit: Option<Decimal>,
+    pub credit: Option<Decimal>,
+}
+
+impl JournalLine {
+    fn new(entry: &JournalEntry, account: String, change: Decimal) -> Self {
+        Self {
+            seq: entry.seq,
+            tx_id: entry.tx_id,
+            account,
+            debit: Some(change).filter(|change| change.is_sign_positive()),
+            credit: Some(-change).filter(|change| change.is_sign_positive()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum JournalExportError {
+    Csv(csv::Error),
+    Io(std::io::Error),
+    /// The debits and credits of the entry `seq`, or of the whole export when
+    /// `seq` is `None`, differ. Lines of earlier entries were written.
+    Unbalanced {
+        seq: Option<u64>,
+        tx_id: Option<TxId>,
+        debits: Decimal,
+        credits: Decimal,
+    },
+}
+
+impl Display for JournalExportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalExportError::Csv(err) => write!(f, "failed to write journal: {}", err),
+            JournalExportError::Io(err) => write!(f, "failed to write journal: {}", err),
+            JournalExportError::Unbalanced {
+                seq: Some(seq),
+                tx_id,
+                debits,
+                credits,
+            } => write!(
+                f,
+                "entry {} of tx {} debits {} but credits {}",
+                seq,
+                tx_id.map_or_else(|| "?".to_string(), |tx_id| tx_id.to_string()),
+                debits,
+                credits
+            ),
+            JournalExportError::Unbalanced {
+                seq: None,
+                debits,
+                credits,
+                ..
+            } => write!(f, "journal debits {} but credits {}", debits, credits),
+        }
+    }
+}
+
+impl std::error::Error for JournalExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JournalExportError::Csv(err) => Some(err),
+            JournalExportError::Io(err) => Some(err),
+            JournalExportError::Unbalanced { .. } => None,
+        }
+    }
+}
+
+impl From<csv::Error> for JournalExportError {
+    fn from(err: csv::Error) -> Self {
+        JournalExportError::Csv(err)
+    }
+}
+
+impl From<std::io::Error> for JournalExportError {
+    fn from(err: std::io::Error) -> Self {
+        JournalExportError::Io(err)
+    }
+}
+
+pub fn available_account(client_id: ClientId) -> String {
+    format!("client:{}:available", client_id)
+}
+
+pub fn held_account(client_id: ClientId) -> String {
+    format!("client:{}:held", client_id)
+}
+
+/// Lines of an entry, debits first. Entries which moved nothing have none.
+pub fn journal_lines(entry: &JournalEntry) -> Vec<JournalLine> {
+    let mut lines: Vec<_> = [
+        (available_account(entry.client_id), entry.available_delta),
+        (held_account(entry.client_id), entry.held_delta),
+        (
+            LIABILITIES_ACCOUNT.to_string(),
+            -(entry.available_delta + entry.held_delta),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_account, change)| !change.is_zero())
+    .map(|(account, change)| JournalLine::new(entry, account, change))
+    .collect();
+    lines.sort_by_key(|line| line.debit.is_none());
+    lines
+}
+
+/// Writes the lines of the entries of `journal`, such as `Ledger::journal` or
+/// `journal_entries` of a journal file, as CSV with a header. Returns the number of
+/// lines.
+///
+/// The debits of every entry must equal its credits, and so must the totals, or the
+/// export fails with `JournalExportError::Unbalanced`.
+pub fn output_journal(
+    journal: impl IntoIterator<Item = std::io::Result<JournalEntry>>,
+    writer: impl Write,
+) -> Result<usize, JournalExportError> {
+    // The header is written by hand, so that an empty journal has one too.
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer.write_record(["seq", "tx", "account", "debit", "credit"])?;
+    let (mut total_debits, mut total_credits) = (Decimal::ZERO, Decimal::ZERO);
+    let mut rows = 0;
+    for entry in journal {
+        let entry = entry?;
+        let lines = journal_lines(&entry);
+        let (debits, credits) = totals(&lines);
+        if debits != credits {
+            return Err(JournalExportError::Unbalanced {
+                seq: Some(entry.seq),
+                tx_id: Some(entry.tx_id),
+                debits,
+                credits,
+            });
+        }
+        for line in &lines {
+            writer.serialize(line)?;
+        }
+        total_debits += debits;
+        total_credits += credits;
+        rows += lines.len();
+    }
+    writer.flush()?;
+    if total_debits != total_credits {
+        return Err(JournalExportError::Unbalanced {
+            seq: None,
+            tx_id: None,
+            debits: total_debits,
+            credits: total_credits,
+        });
+    }
+    Ok(rows)
+}
+
+fn totals(lines: &[JournalLine]) -> (Decimal, Decimal) {
+    lines
+        .iter()
+        .fold((Decimal::ZERO, Decimal::ZERO), |(debits, credits), line| {
+            (
+                debits + line.debit.unwrap_or_default(),
+                credits + line.credit.unwrap_or_default(),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{
+        Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal,
+    };
+    use crate::accounting::Ledger;
+    use rust_decimal_macros::dec;
+
+    fn export(ledger: &Ledger) -> String {
+        let mut output = Vec::new();
+        output_journal(ledger.journal().iter().cloned().map(Ok), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn lines_of_each_transaction_type() {
+        let mut ledger = Ledger::builder().journal().build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(42, 1, dec!(50.0))),
+            Transaction::Withdrawal(Withdrawal::new(42, 2, dec!(20.0))),
+            Transaction::Deposit(Deposit::new(42, 3, dec!(12.5))),
+            Transaction::Dispute(Dispute::new(42, 3)),
+            Transaction::Resolve(Resolve::new(42, 3)),
+            Transaction::Dispute(Dispute::new(42, 1)),
+            Transaction::Chargeback(Chargeback::new(42, 1)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        assert_eq!(
+            export(&ledger),
+            "seq,tx,account,debit,credit\n\
+             0,1,client:42:available,50.0,\n\
+             0,1,platform:liabilities,,50.0\n\
+             1,2,platform:liabilities,20.0,\n\
+             1,2,client:42:available,,20.0\n\
+             2,3,client:42:available,12.5,\n\
+             2,3,platform:liabilities,,12.5\n\
+             3,3,client:42:held,12.5,\n\
+             3,3,client:42:available,,12.5\n\
+             4,3,client:42:available,12.5,\n\
+             4,3,client:42:held,,12.5\n\
+             5,1,client:42:held,50.0,\n\
+             5,1,client:42:available,,50.0\n\
+             6,1,platform:liabilities,50.0,\n\
+             6,1,client:42:held,,50.0\n"
+        );
+    }
+
+    #[test]
+    fn mixed_scenario_balances() {
+        use crate::pipeline::{CsvSource, Pipeline};
+
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100.0\n\
+                     deposit,2,2,40.1234\n\
+                     withdrawal,1,3,30.5\n\
+                     dispute,1,1,\n\
+                     withdrawal,1,4,80.0\n\
+                     resolve,1,1,\n\
+                     deposit,3,5,7.0\n\
+                     dispute,2,2,\n\
+                     chargeback,2,2,\n\
+                     deposit,2,6,5.0\n\
+                     withdrawal,3,7,7.0\n\
+                     dispute,3,5,\n\
+                     resolve,3,5,\n";
+        let mut ledger = Ledger::builder().journal().build();
+        Pipeline::new(CsvSource::new(input.as_bytes()))
+            .run(&mut ledger)
+            .unwrap();
+        let journal = ledger.journal();
+        assert_eq!(journal.len(), 11);
+
+        let mut lines = Vec::new();
+        for entry in journal {
+            let entry_lines = journal_lines(entry);
+            let (debits, credits) = totals(&entry_lines);
+            assert_eq!(debits, credits, "entry {}", entry.seq);
+            lines.extend(entry_lines);
+        }
+        let (debits, credits) = totals(&lines);
+        assert_eq!(debits, credits);
+        // What the platform owes is what the clients hold
+        let owed: Decimal = lines
+            .iter()
+            .filter(|line| line.account == LIABILITIES_ACCOUNT)
+            .map(|line| line.credit.unwrap_or_default() - line.debit.unwrap_or_default())
+            .sum();
+        let held_by_clients: Decimal = ledger
+            .accounts_iter()
+            .map(|(_client_id, account)| account.total())
+            .sum();
+        assert_eq!(owed, held_by_clients);
+        assert_eq!(owed, dec!(69.5));
+
+        let mut output = Vec::new();
+        let rows = output_journal(journal.iter().cloned().map(Ok), &mut output).unwrap();
+        assert_eq!(rows, lines.len());
+        assert_eq!(
+            String::from_utf8(output).unwrap().lines().count(),
+            lines.len() + 1
+        );
+
+        let err = JournalExportError::Unbalanced {
+            seq: Some(7),
+            tx_id: Some(3),
+            debits: dec!(1),
+            credits: dec!(2),
+        };
+        assert_eq!(err.to_string(), "entry 7 of tx 3 debits 1 but credits 2");
+    }
+}
@@ -0,0 +1,413 @@
+//! Two-lane scheduling of transactions, which lets disputes, resolves and
+//! chargebacks overtake deposits and withdrawals waiting to be executed, so that a
+//! burst of deposits does not delay a chargeback.
+//!
+//! Overtaking changes the order of execution, so it is only meant for streams
+//! without an order across producers, such as the connections of `server`. Files
+//! are always executed in order. Within a client, a transaction of the priority
+//! lane never overtakes the transaction it references, nor the transactions of the
+//! priority lane before it.
+use crate::accounting::transactions::{Transaction, TxTypeTag};
+use crate::core_types::{ClientId, TxId};
+use crate::pipeline::{SourceRow, TransactionSource};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use tokio::sync::Semaphore;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lane {
+    /// Deposits, withdrawals and every other transaction moving funds.
+    Normal,
+    /// Disputes, resolves and chargebacks.
+    Priority,
+}
+
+impl Lane {
+    pub fn of(kind: TxTypeTag) -> Self {
+        match kind {
+            TxTypeTag::Dispute | TxTypeTag::Resolve | TxTypeTag::Chargeback => Lane::Priority,
+            _ => Lane::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LaneConfig {
+    /// Transactions waiting in each lane before senders to it wait.
+    pub capacity: usize,
+    /// Transactions of the priority lane executed in a row while the normal lane
+    /// has transactions waiting, so that it is not starved.
+    pub max_priority_streak: usize,
+}
+
+impl Default for LaneConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4096,
+            max_priority_streak: 8,
+        }
+    }
+}
+
+/// Transactions waiting in their lanes, each with a `T` for whoever executes it,
+/// such as the channel answering the sender.
+#[derive(Debug)]
+pub struct LaneScheduler<T> {
+    normal: VecDeque<(Transaction, T)>,
+    priority: VecDeque<(Transaction, T)>,
+    /// Transactions of the normal lane by client and tx id, which the transactions
+    /// of the priority lane referencing them wait for.
+    waiting: HashMap<(ClientId, TxId), usize>,
+    /// Transactions of the priority lane taken since the last one of the normal lane,
+    /// counting only those taken while the normal lane had some.
+    priority_streak: usize,
+    max_priority_streak: usize,
+}
+
+impl<T> LaneScheduler<T> {
+    /// Takes at least one transaction of the normal lane between
+    /// `max_priority_streak` ones of the priority lane.
+    pub fn new(max_priority_streak: usize) -> Self {
+        Self {
+            normal: VecDeque::new(),
+            priority: VecDeque::new(),
+            waiting: HashMap::new(),
+            priority_streak: 0,
+            max_priority_streak,
+        }
+    }
+
+    pub fn push(&mut self, tx: Transaction, payload: T) {
+        match Lane::of(tx.kind()) {
+            Lane::Normal => {
+                *self
+                    .waiting
+                    .entry((tx.client_id(), tx.tx_id()))
+                    .or_default() += 1;
+                self.normal.push_back((tx, payload));
+            }
+            Lane::Priority => self.priority.push_back((tx, payload)),
+        }
+    }
+
+    /// Takes the next transaction to execute: the first one of the priority lane
+    /// whose client has no earlier one held back, unless it references a
+    /// transaction of the normal lane or the streak is over, and otherwise the
+    /// first one of the normal lane.
+    pub fn pop(&mut self) -> Option<(Transaction, T)> {
+        if self.priority_streak < self.max_priority_streak || self.normal.is_empty() {
+            if let Some(index) = self.next_priority() {
+                if !self.normal.is_empty() {
+                    self.priority_streak += 1;
+                }
+                return self.priority.remove(index);
+            }
+        }
+        let (tx, payload) = self.normal.pop_front()?;
+        let key = (tx.client_id(), tx.tx_id());
+        match self.waiting.get_mut(&key) {
+            Some(count) if *count > 1 => *count -= 1,
+            _ => {
+                self.waiting.remove(&key);
+            }
+        }
+        self.priority_streak = 0;
+        Some((tx, payload))
+    }
+
+    /// Position of the first transaction of the priority lane which may be taken.
+    /// One is always found while the normal lane is empty.
+    fn next_priority(&self) -> Option<usize> {
+        let mut held_back = HashSet::new();
+        for (index, (tx, _payload)) in self.priority.iter().enumerate() {
+            let client_id = tx.client_id();
+            if held_back.contains(&client_id) {
+                continue;
+            }
+            if self.waiting.contains_key(&(client_id, tx.tx_id())) {
+                held_back.insert(client_id);
+                continue;
+            }
+            return Some(index);
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.normal.len() + self.priority.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Signaled when a transaction is pushed or the last sender is dropped.
+    ready: Condvar,
+    normal_slots: Semaphore,
+    priority_slots: Semaphore,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn slots(&self, lane: Lane) -> &Semaphore {
+        match lane {
+            Lane::Normal => &self.normal_slots,
+            Lane::Priority => &self.priority_slots,
+        }
+    }
+}
+
+struct State<T> {
+    scheduler: LaneScheduler<T>,
+    senders: usize,
+}
+
+/// Creates a channel whose receiver takes the transactions in the order of a
+/// `LaneScheduler`. Each lane holds up to `config.capacity` transactions, so a full
+/// normal lane does not hold up senders to the priority lane.
+pub fn lanes<T>(config: LaneConfig) -> (LaneSender<T>, LaneReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            scheduler: LaneScheduler::new(config.max_priority_streak),
+            senders: 1,
+        }),
+        ready: Condvar::new(),
+        normal_slots: Semaphore::new(config.capacity),
+        priority_slots: Semaphore::new(config.capacity),
+    });
+    (
+        LaneSender {
+            shared: Arc::clone(&shared),
+        },
+        LaneReceiver { shared },
+    )
+}
+
+pub struct LaneSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> LaneSender<T> {
+    /// Waits for room in the lane of `tx`. Gives the transaction back when the
+    /// receiver was dropped.
+    pub async fn send(&self, tx: Transaction, payload: T) -> Result<(), (Transaction, T)> {
+        let lane = Lane::of(tx.kind());
+        match self.shared.slots(lane).acquire().await {
+            Ok(slot) => slot.forget(),
+            Err(_closed) => return Err((tx, payload)),
+        }
+        self.shared.lock().scheduler.push(tx, payload);
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for LaneSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for LaneSender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.ready.notify_one();
+    }
+}
+
+pub struct LaneReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> LaneReceiver<T> {
+    /// Takes the next transaction, or `None` once all senders were dropped and the
+    /// lanes are empty. Blocks the thread while waiting, so it must not be used on
+    /// the async runtime.
+    pub fn recv_blocking(&mut self) -> Option<(Transaction, T)> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some((tx, payload)) = state.scheduler.pop() {
+                self.shared.slots(Lane::of(tx.kind())).add_permits(1);
+                return Some((tx, payload));
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self
+                .shared
+                .ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl<T> Drop for LaneReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.normal_slots.close();
+        self.shared.priority_slots.close();
+    }
+}
+
+/// Transactions in the order of the lanes. Blocks the thread while waiting, so it
+/// must not be used on the async runtime.
+impl TransactionSource for LaneReceiver<()> {
+    fn next_row(&mut self) -> std::io::Result<Option<SourceRow<'_>>> {
+        Ok(self.recv_blocking().map(|(tx, ())| SourceRow {
+            line: None,
+            raw: None,
+            tx: Ok(tx),
+            field: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
+    use crate::accounting::Ledger;
+    use crate::pipeline::Pipeline;
+    use crate::test_utils::render;
+    use rust_decimal_macros::dec;
+
+    fn deposit(client_id: ClientId, tx_id: TxId) -> Transaction {
+        Transaction::Deposit(Deposit::new(client_id, tx_id, dec!(10)))
+    }
+
+    fn dispute(client_id: ClientId, tx_id: TxId) -> Transaction {
+        Transaction::Dispute(Dispute::new(client_id, tx_id))
+    }
+
+    fn drain(scheduler: &mut LaneScheduler<()>) -> Vec<(TxTypeTag, ClientId, TxId)> {
+        std::iter::from_fn(|| scheduler.pop())
+            .map(|(tx, ())| (tx.kind(), tx.client_id(), tx.tx_id()))
+            .collect()
+    }
+
+    #[test]
+    fn priority_lane_waits_for_referenced_deposits() {
+        use TxTypeTag::{Chargeback as C, Deposit as D, Dispute as P, Resolve as R};
+
+        let mut scheduler = LaneScheduler::new(8);
+        for tx in [
+            deposit(1, 1),
+            deposit(2, 2),
+            deposit(3, 3),
+            deposit(1, 4),
+            // Taken first by a naive scheduler, before deposit 4 it references
+            dispute(1, 4),
+            // Behind the held back dispute of the same client
+            Transaction::Resolve(Resolve::new(1, 1)),
+            // Its deposit was executed
+            dispute(9, 9),
+            dispute(2, 2),
+            Transaction::Chargeback(Chargeback::new(9, 9)),
+        ] {
+            scheduler.push(tx, ());
+        }
+        assert_eq!(scheduler.len(), 9);
+        assert_eq!(
+            drain(&mut scheduler),
+            vec![
+                (P, 9, 9),
+                (C, 9, 9),
+                (D, 1, 1),
+                (D, 2, 2),
+                (P, 2, 2),
+                (D, 3, 3),
+                (D, 1, 4),
+                (P, 1, 4),
+                (R, 1, 1),
+            ]
+        );
+        assert!(scheduler.is_empty());
+        assert!(scheduler.waiting.is_empty());
+    }
+
+    #[test]
+    fn normal_lane_is_not_starved() {
+        let mut scheduler = LaneScheduler::new(3);
+        for tx_id in 1..=3 {
+            scheduler.push(deposit(1, tx_id), ());
+        }
+        for client_id in 2..=9 {
+            scheduler.push(dispute(client_id, 100), ());
+        }
+        let order: Vec<_> = drain(&mut scheduler)
+            .into_iter()
+            .map(|(kind, _client_id, _tx_id)| kind)
+            .collect();
+        use TxTypeTag::{Deposit as D, Dispute as P};
+        assert_eq!(order, vec![P, P, P, D, P, P, P, D, P, P, D]);
+
+        // Without transactions waiting in the normal lane there is no streak to end
+        let mut scheduler = LaneScheduler::new(1);
+        for client_id in 1..=4 {
+            scheduler.push(dispute(client_id, 100), ());
+        }
+        scheduler.pop().unwrap();
+        scheduler.pop().unwrap();
+        scheduler.push(deposit(1, 1), ());
+        assert_eq!(
+            drain(&mut scheduler),
+            vec![(P, 3, 100), (D, 1, 1), (P, 4, 100)]
+        );
+    }
+
+    #[test]
+    fn channel_feeds_a_pipeline() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (sender, receiver) = lanes(LaneConfig {
+            capacity: 2,
+            max_priority_streak: 2,
+        });
+        let consumer = std::thread::spawn(move || {
+            let mut ledger = Ledger::new();
+            Pipeline::new(receiver).run(&mut ledger).unwrap();
+            ledger
+        });
+        runtime.block_on(async {
+            let second = sender.clone();
+            // The withdrawal is sent after the resolve, so it can't be executed while
+            // the deposit is held.
+            for tx in [
+                deposit(1, 1),
+                deposit(1, 2),
+                dispute(1, 1),
+                Transaction::Resolve(Resolve::new(1, 1)),
+                Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(4))),
+            ] {
+                sender.send(tx, ()).await.unwrap();
+            }
+            second.send(deposit(2, 4), ()).await.unwrap();
+        });
+        drop(sender);
+        let ledger = consumer.join().unwrap();
+        assert_eq!(
+            String::from_utf8(render(&ledger)).unwrap(),
+            "client,available,held,total,locked\n1,16,0,16,false\n2,10,0,10,false\n"
+        );
+    }
+
+    #[test]
+    fn sending_fails_without_receiver() {
+        let (sender, receiver) = lanes::<()>(LaneConfig::default());
+        drop(receiver);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert!(runtime.block_on(sender.send(deposit(1, 1), ())).is_err());
+    }
+}
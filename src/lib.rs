@@ -1,38 +1,98 @@
-use crate::accounting::transactions::{Transaction, TransactionLog, TransactionLogError};
-use crate::accounting::{AccountLog, Ledger};
-use csv_async::Trim;
+#[cfg(feature = "io")]
+use crate::accounting::transactions::Transaction;
+#[cfg(feature = "io")]
+use crate::accounting::view::LedgerView;
+#[cfg(feature = "io")]
+use crate::ingest::{IngestOptions, IngestReport};
+#[cfg(feature = "io")]
+use crate::staging::Staging;
+#[cfg(feature = "io")]
+use std::path::Path;
+#[cfg(feature = "io")]
 use tokio::sync::mpsc::Sender;
-use tokio_stream::StreamExt;
 
 pub mod accounting;
-mod core_types;
+#[cfg(all(feature = "admin", unix))]
+pub mod admin;
+pub mod admin_ops;
+pub mod amount_scale;
+#[cfg(feature = "io")]
+pub mod checkpoint;
+pub mod clock;
+pub mod compare;
+pub mod core_types;
+pub mod distribution;
+pub mod engine;
+pub mod enrichment;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod field_error;
+#[doc(hidden)]
+pub mod fuzzing;
+#[cfg(feature = "io")]
+pub mod ingest;
+pub mod journal_export;
+#[cfg(feature = "io")]
+pub mod lanes;
+pub mod number_locale;
+#[cfg(feature = "io")]
+pub mod output;
+#[cfg(feature = "parquet")]
+pub mod parquet_output;
+pub mod partitioned_output;
+pub mod pipeline;
+pub mod preflight;
+pub mod prelude;
+pub mod reconcile;
+#[cfg(feature = "io")]
+pub mod reports;
+pub mod run_id;
+#[cfg(any(test, feature = "testkit"))]
+pub mod scenario;
+#[cfg(all(feature = "server", unix))]
+pub mod server;
+pub mod sink;
+pub mod staging;
+pub mod statements;
+pub mod tag_rules;
+#[cfg(test)]
+mod test_utils;
+pub mod top_k;
+#[cfg(any(test, feature = "testkit"))]
+pub mod vectors;
+#[cfg(feature = "io")]
+pub mod watch;
 
-pub async fn read_data(file_path: String, sender: Sender<Transaction>) {
-    let mut file = tokio::fs::File::open(&file_path)
-        .await
-        .expect("Input file does not exist or no permissions to read");
-    let mut reader = csv_async::AsyncReaderBuilder::new()
-        .trim(Trim::All)
-        .create_deserializer(&mut file);
-    let mut records = reader.deserialize::<TransactionLog>();
-    while let Some(fetched_tx) = records.next().await {
-        if let Ok(tx) = fetched_tx
-            .map_err(|_err| TransactionLogError::InvalidTxType)
-            .and_then(Transaction::try_from)
-        {
-            sender.send(tx).await.ok();
-        }
-    }
+pub use engine::{process_csv_str, EngineError};
+#[cfg(feature = "io")]
+pub use ingest::parallel::read_data_parallel;
+
+#[cfg(feature = "io")]
+pub async fn read_data(
+    file_path: String,
+    sender: Sender<Transaction>,
+) -> Result<IngestReport, EngineError> {
+    Ok(ingest::read_data_with_options(file_path, sender, IngestOptions::default()).await?)
 }
 
-pub async fn output_data(ledger: &Ledger) {
-    let account_logs = ledger
-        .accounts_iter()
-        .map(|(_client_id, user_account)| AccountLog::from(user_account))
-        .collect::<Vec<AccountLog>>();
+/// Writes the account report to `stdout`, sorted by client id.
+#[cfg(feature = "io")]
+pub async fn output_data<'a>(ledger: impl Into<LedgerView<'a>>) -> Result<usize, EngineError> {
+    let ledger: LedgerView = ledger.into();
+    let rows = ledger.account_logs();
+    Ok(output::write_account_logs(rows, tokio::io::stdout()).await?)
+}
 
-    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(tokio::io::stdout());
-    for log in account_logs {
-        writer.serialize(log).await.ok();
-    }
+/// Writes the account report to a temporary file and renames it over `path` once it
+/// is complete and synced, so that `path` holds either its previous content or the
+/// whole report, see `staging::Staging`.
+#[cfg(feature = "io")]
+pub async fn output_accounts_atomic<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    path: impl AsRef<Path>,
+) -> Result<usize, EngineError> {
+    let mut staging = Staging::new();
+    let rows = output::stage_accounts(ledger.into(), &mut staging, path.as_ref()).await?;
+    staging.commit()?;
+    Ok(rows)
 }
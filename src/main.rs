@@ -1,27 +1,1827 @@
+use payments_engine::accounting::postings::ChargebackFeeRule;
 use payments_engine::accounting::Ledger;
+use payments_engine::admin_ops::{apply_admin_ops, AdminOpStatus, AdminOpsMode};
+use payments_engine::amount_scale::{AmountScale, MAX_SCALE};
+use payments_engine::compare::{compare_reports, CompareOptions};
+use payments_engine::core_types::ClientId;
+use payments_engine::enrichment::Enrichment;
+use payments_engine::ingest::client_ids::PossibleClientCollision;
+use payments_engine::ingest::column_map::ColumnMap;
+use payments_engine::ingest::dedup::DedupConfig;
+use payments_engine::ingest::manifest::{generate_manifest, Manifest};
+use payments_engine::ingest::prefetch::IngestIoConfig;
+use payments_engine::ingest::{
+    check_file_headers, read_client_remap, read_dispute_limits, read_overdraft_limits, IngestError,
+    IngestOptions, IngestParser, SkipMode,
+};
+use payments_engine::lanes::LaneConfig;
+use payments_engine::number_locale::{AmountParsing, NumberLocale};
+use payments_engine::output::{
+    write_accounts, write_accounts_enriched, write_accounts_extended, write_accounts_selected,
+    LineEnding, LineEndingWriter, OutputSelection,
+};
+use payments_engine::partitioned_output::{
+    output_accounts_partitioned, ModuloPartitioner, PartitionManifest, PartitionedOutputError,
+    RangePartitioner,
+};
+use payments_engine::pipeline::{
+    AuditLog, CsvSource, Pipeline, Quarantine, ReplayFilter, TransactionSource,
+};
+use payments_engine::preflight::{check_rows, preflight, CheckFinding, CheckSummary, FindingClass};
+use payments_engine::prelude::{
+    Decimal, DisputeAmountHandling, DisputeLimit, DisputeTracking, DuplicateDisputePolicy,
+    EngineError, LedgerBuilder, LedgerEvent, LedgerView, RunStats, TwoPassError, TxTypeTag,
+    UnknownClientOnDebit,
+};
+use payments_engine::reconcile::{reconcile_with_tolerance, ReconciliationReport};
+use payments_engine::run_id::RunId;
+use payments_engine::staging::Staging;
+use payments_engine::tag_rules::TagRules;
+use payments_engine::watch::{DirectoryWatcher, WatchConfig};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::ControlFlow;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 const CHANNEL_SIZE: usize = 4096;
 
+#[derive(PartialEq)]
+enum OutputFormat {
+    Csv,
+    Parquet,
+}
+
+/// When the ops of `--admin-ops` are applied.
+#[derive(Clone, Copy, PartialEq)]
+enum AdminOpsPhase {
+    /// Before the input is ingested.
+    Pre,
+    /// After the input was processed, before the output is written.
+    Post,
+}
+
+enum Partition {
+    Modulo(ModuloPartitioner),
+    Range(RangePartitioner),
+}
+
+impl Partition {
+    fn parse(value: &str) -> Option<Self> {
+        let (kind, size) = value.split_once(':')?;
+        let size = size.parse::<u32>().ok().filter(|size| *size > 0)?;
+        match kind {
+            "modulo" => Some(Partition::Modulo(ModuloPartitioner { buckets: size })),
+            "range" => Some(Partition::Range(RangePartitioner { bucket_size: size })),
+            _ => None,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let mut args = std::env::args();
+    let mut args = std::env::args().peekable();
     let exec_name = args.next().expect("Exec name should always exist");
-    let file_path = match args.next() {
-        Some(path) => path,
-        None => {
-            eprintln!("Usage: {} <input_file_path>", exec_name);
+    if args.peek().map(String::as_str) == Some("check") {
+        args.next();
+        std::process::exit(check(&exec_name, args));
+    }
+    if args.peek().map(String::as_str) == Some("manifest") {
+        args.next();
+        std::process::exit(manifest(&exec_name, args).await);
+    }
+    if args.peek().map(String::as_str) == Some("watch") {
+        args.next();
+        std::process::exit(watch(&exec_name, args));
+    }
+    if args.peek().map(String::as_str) == Some("compare") {
+        args.next();
+        std::process::exit(compare(&exec_name, args));
+    }
+    let usage = format!(
+        "Usage: {} [--workers <N>] [--skip <N>] [--skip-mode discard|execute-silent] \
+         [--limit <N>] [--parser csv|fast] [--remap-file <path>] [--sweep-dust <threshold>] \
+         [--sweep-locked-dust] [--two-pass [--strict-order]] [--quarantine <path>] \
+         [--overdraft-limits <path>] [--dispute-limit <amount>|<percent>%] \
+         [--dispute-limits <path>] [--max-open-disputes <N>] [--no-dispute-tracking] \
+         [--extended-output] [--crlf-output] [--output <path>] \
+         [--output-format csv|parquet] [--output-dir <dir> --partition modulo:<N>|range:<N>] \
+         [--output-clients <first>..=<last>] [--output-limit <N>] [--only-locked] [--only-negative] \
+         [--expire-disputes-after <N>] [--chargeback-fee <amount>] \
+         [--amount-scale <N> [--round-excess-precision]] \
+         [--allow-extra-columns] [--column-map <path>] [--idempotent-disputes] \
+         [--dispute-amounts ignore|warn|reject] [--unknown-client-on-debit error|create-empty] \
+         [--number-locale dot|comma|auto] [--allow-scientific-amounts] \
+         [--strip-amount-symbols <symbols>] [--trim-amount-whitespace] [--audit] [--only <types>] [--clients <ids>] \
+         [--tx-range <first>..=<last>] [--dedup-window <N>] [--io-buffer-size <bytes>] \
+         [--prefetch] [--track-raw-client-ids] [--collisions-report <path>] \
+         [--manifest <path>] [--reconcile <path> [--reconcile-tolerance <amount>] \
+         [--reconcile-report <path>]] [--enrich <path> [--only-flag <flag>]] [--force] \
+         [--run-id <id>] [--stamp-outputs] [--expect-digest <hex>] [--print-digest] [--top-k <N>] \
+         [--distribution-stats [--distribution-include-rejected]] \
+         [--audit-log <path>] [--tag-rules <path>] \
+         [--admin-ops <path> [--admin-ops-phase pre|post] [--admin-ops-on-error stop|continue]] \
+         <input_file_path>...\n       \
+         {} --serve <socket_path> [--report-interval <seconds>] [--output <path>] \
+         [--overdraft-limits <path>] [--dispute-limit <amount>|<percent>%] \
+         [--dispute-limits <path>] [--max-open-disputes <N>] [--idempotent-disputes] \
+         [--dispute-amounts ignore|warn|reject] [--admin <addr>] \
+         [--priority-lanes <max_streak>]\n       \
+         {} check [--fail-fast] [--report <path>] <input_file_path>\n       \
+         {} manifest <input_file_path>\n       \
+         {} compare [--ignore-column <name>]... <output_a> <output_b>\n       \
+         {} watch [--pattern <glob>] [--settle-ms <N>] [--poll-interval-ms <N>] \
+         [--exit-when-idle-ms <N>] [--snapshot <path>] [--strict-order] <dir>",
+        exec_name, exec_name, exec_name, exec_name, exec_name, exec_name
+    );
+
+    let mut file_paths = Vec::new();
+    let mut workers = None;
+    let mut options = IngestOptions::default();
+    let mut remap_file = None;
+    let mut overdraft_limits_file = None;
+    let mut dispute_limit = None;
+    let mut dispute_limits_file = None;
+    let mut max_open_disputes = None;
+    let mut extended_output = false;
+    let mut line_ending = LineEnding::Lf;
+    let mut admin_ops_file = None;
+    let mut admin_ops_phase = None;
+    let mut admin_ops_mode = None;
+    let mut output_path = None;
+    let mut output_format = OutputFormat::Csv;
+    let mut output_dir = None;
+    let mut partition = None;
+    let mut quarantine_file = None;
+    let mut two_pass = false;
+    let mut strict_order = false;
+    let mut dust_threshold = None;
+    let mut chargeback_fee = None;
+    let mut amount_scale = None;
+    let mut round_excess_precision = false;
+    let mut sweep_locked_dust = false;
+    let mut dispute_tracking = DisputeTracking::Full;
+    let mut expire_disputes_after = None;
+    let mut column_map_file = None;
+    let mut duplicate_dispute_policy = DuplicateDisputePolicy::Error;
+    let mut dispute_amount_handling = DisputeAmountHandling::Ignore;
+    let mut unknown_client_on_debit = UnknownClientOnDebit::Error;
+    let mut audit = false;
+    let mut serve_socket = None;
+    let mut report_interval = None;
+    let mut admin_addr = None;
+    let mut priority_lanes = None;
+    let mut replay_filter = ReplayFilter::default();
+    let mut io_config = IngestIoConfig::default();
+    let mut collisions_report = None;
+    let mut manifest_file = None;
+    let mut reconcile_file = None;
+    let mut reconcile_tolerance = Decimal::ZERO;
+    let mut reconcile_report = None;
+    let mut enrich_file = None;
+    let mut only_flag = None;
+    let mut force = false;
+    let mut run_id = None;
+    let mut stamp_outputs = false;
+    let mut expect_digest = None;
+    let mut print_digest = false;
+    let mut selection = OutputSelection::default();
+    let mut top_k = None;
+    let mut distribution_stats = false;
+    let mut distribution_include_rejected = false;
+    let mut audit_log_file = None;
+    let mut tag_rules_file = None;
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--workers" => parse_count(args.next())
+                .filter(|value| *value > 0)
+                .map(|value| workers = Some(value)),
+            "--skip" => parse_count(args.next()).map(|value| options.skip = value),
+            "--skip-mode" => match args.next().as_deref() {
+                Some("discard") => Some(SkipMode::Discard),
+                Some("execute-silent") => Some(SkipMode::ExecuteSilent),
+                _ => None,
+            }
+            .map(|skip_mode| options.skip_mode = skip_mode),
+            "--parser" => match args.next().as_deref() {
+                Some("csv") => Some(IngestParser::Csv),
+                Some("fast") => Some(IngestParser::Fast),
+                _ => None,
+            }
+            .map(|parser| options.parser = parser),
+            "--number-locale"
+            | "--allow-scientific-amounts"
+            | "--strip-amount-symbols"
+            | "--trim-amount-whitespace" => parse_amount_flag(
+                &arg,
+                &mut args,
+                &mut options.number_locale,
+                &mut options.amount_parsing,
+            ),
+            "--remap-file" => args.next().map(|value| remap_file = Some(value)),
+            "--overdraft-limits" => args.next().map(|value| overdraft_limits_file = Some(value)),
+            "--dispute-limit" => args
+                .next()
+                .and_then(|value| value.parse::<DisputeLimit>().ok())
+                .map(|limit| dispute_limit = Some(limit)),
+            "--dispute-limits" => args.next().map(|value| dispute_limits_file = Some(value)),
+            "--max-open-disputes" => args
+                .next()
+                .and_then(|value| value.parse::<u32>().ok())
+                .map(|limit| max_open_disputes = Some(limit)),
+            "--column-map" => args.next().map(|value| column_map_file = Some(value)),
+            "--allow-extra-columns" => {
+                options.allow_extra_columns = true;
+                Some(())
+            }
+            "--idempotent-disputes" => {
+                duplicate_dispute_policy = DuplicateDisputePolicy::Idempotent;
+                Some(())
+            }
+            "--dispute-amounts" => match args.next().as_deref() {
+                Some("ignore") => Some(DisputeAmountHandling::Ignore),
+                Some("warn") => Some(DisputeAmountHandling::WarnOnMismatch),
+                Some("reject") => Some(DisputeAmountHandling::RejectOnMismatch),
+                _ => None,
+            }
+            .map(|handling| dispute_amount_handling = handling),
+            "--unknown-client-on-debit" => match args.next().as_deref() {
+                Some("error") => Some(UnknownClientOnDebit::Error),
+                Some("create-empty") => Some(UnknownClientOnDebit::CreateEmpty),
+                _ => None,
+            }
+            .map(|policy| unknown_client_on_debit = policy),
+            "--audit" => {
+                audit = true;
+                Some(())
+            }
+            "--extended-output" => {
+                extended_output = true;
+                Some(())
+            }
+            "--crlf-output" => {
+                line_ending = LineEnding::Crlf;
+                Some(())
+            }
+            "--output" => args.next().map(|value| output_path = Some(value)),
+            "--admin-ops" => args.next().map(|value| admin_ops_file = Some(value)),
+            "--admin-ops-phase" => match args.next().as_deref() {
+                Some("pre") => Some(AdminOpsPhase::Pre),
+                Some("post") => Some(AdminOpsPhase::Post),
+                _ => None,
+            }
+            .map(|phase| admin_ops_phase = Some(phase)),
+            "--admin-ops-on-error" => match args.next().as_deref() {
+                Some("stop") => Some(AdminOpsMode::StopOnError),
+                Some("continue") => Some(AdminOpsMode::ContinueOnError),
+                _ => None,
+            }
+            .map(|mode| admin_ops_mode = Some(mode)),
+            "--output-format" => match args.next().as_deref() {
+                Some("csv") => Some(OutputFormat::Csv),
+                Some("parquet") => Some(OutputFormat::Parquet),
+                _ => None,
+            }
+            .map(|format| output_format = format),
+            "--output-dir" => args.next().map(|value| output_dir = Some(value)),
+            "--partition" => args
+                .next()
+                .and_then(|value| Partition::parse(&value))
+                .map(|value| partition = Some(value)),
+            "--quarantine" => args.next().map(|value| quarantine_file = Some(value)),
+            "--sweep-dust" => args
+                .next()
+                .and_then(|value| value.parse::<Decimal>().ok())
+                .map(|threshold| dust_threshold = Some(threshold)),
+            "--chargeback-fee" => args
+                .next()
+                .and_then(|value| value.parse::<Decimal>().ok())
+                .filter(|amount| amount.is_sign_positive() && !amount.is_zero())
+                .map(|amount| chargeback_fee = Some(amount)),
+            "--amount-scale" => parse_count(args.next())
+                .filter(|scale| *scale <= MAX_SCALE as usize)
+                .map(|scale| amount_scale = Some(scale as u32)),
+            "--round-excess-precision" => {
+                round_excess_precision = true;
+                Some(())
+            }
+            "--two-pass" => {
+                two_pass = true;
+                Some(())
+            }
+            "--strict-order" => {
+                strict_order = true;
+                Some(())
+            }
+            "--sweep-locked-dust" => {
+                sweep_locked_dust = true;
+                Some(())
+            }
+            "--no-dispute-tracking" => {
+                dispute_tracking = DisputeTracking::Disabled;
+                Some(())
+            }
+            "--limit" => parse_count(args.next()).map(|value| options.limit = Some(value)),
+            "--serve" => args.next().map(|value| serve_socket = Some(value)),
+            "--report-interval" => parse_count(args.next())
+                .filter(|value| *value > 0)
+                .map(|value| report_interval = Some(value as u64)),
+            "--priority-lanes" => {
+                parse_count(args.next())
+                    .filter(|value| *value > 0)
+                    .map(|max_priority_streak| {
+                        priority_lanes = Some(LaneConfig {
+                            max_priority_streak,
+                            ..LaneConfig::default()
+                        })
+                    })
+            }
+            "--admin" => args
+                .next()
+                .and_then(|value| value.parse().ok())
+                .map(|addr| admin_addr = Some(addr)),
+            "--only" => args
+                .next()
+                .and_then(|value| parse_kinds(&value))
+                .map(|kinds| replay_filter.include_kinds = Some(kinds)),
+            "--clients" => args
+                .next()
+                .and_then(|value| value.split(',').map(|id| id.trim().parse().ok()).collect())
+                .map(|clients| replay_filter.include_clients = Some(clients)),
+            "--tx-range" => args
+                .next()
+                .and_then(|value| {
+                    let (first, last) = value.split_once("..=")?;
+                    Some(first.trim().parse().ok()?..=last.trim().parse().ok()?)
+                })
+                .map(|range| replay_filter.tx_id_range = Some(range)),
+            "--dedup-window" => {
+                parse_count(args.next()).map(|window| options.dedup = Some(DedupConfig { window }))
+            }
+            "--io-buffer-size" => parse_count(args.next())
+                .filter(|value| *value > 0)
+                .map(|value| io_config.buffer_size = value),
+            "--prefetch" => {
+                io_config.prefetch = true;
+                Some(())
+            }
+            "--track-raw-client-ids" => {
+                options.track_raw_client_ids = true;
+                Some(())
+            }
+            "--collisions-report" => args.next().map(|value| {
+                options.track_raw_client_ids = true;
+                collisions_report = Some(value);
+            }),
+            "--manifest" => args.next().map(|value| manifest_file = Some(value)),
+            "--reconcile" => args.next().map(|value| reconcile_file = Some(value)),
+            "--reconcile-tolerance" => args
+                .next()
+                .and_then(|value| value.parse::<Decimal>().ok())
+                .filter(|tolerance| !tolerance.is_sign_negative())
+                .map(|tolerance| reconcile_tolerance = tolerance),
+            "--reconcile-report" => args.next().map(|value| reconcile_report = Some(value)),
+            "--enrich" => args.next().map(|value| enrich_file = Some(value)),
+            "--only-flag" => args.next().map(|value| only_flag = Some(value)),
+            "--force" => {
+                force = true;
+                Some(())
+            }
+            "--run-id" => args
+                .next()
+                .and_then(|value| value.parse::<RunId>().ok())
+                .map(|id| run_id = Some(id)),
+            "--stamp-outputs" => {
+                stamp_outputs = true;
+                Some(())
+            }
+            "--expect-digest" => args
+                .next()
+                .filter(|value| {
+                    value.len() == 64 && value.bytes().all(|byte| byte.is_ascii_hexdigit())
+                })
+                .map(|value| expect_digest = Some(value.to_ascii_lowercase())),
+            "--print-digest" => {
+                print_digest = true;
+                Some(())
+            }
+            "--output-clients" => args
+                .next()
+                .and_then(|value| {
+                    let (first, last) = value.split_once("..=")?;
+                    Some(first.trim().parse().ok()?..=last.trim().parse().ok()?)
+                })
+                .map(|range| selection.client_range = Some(range)),
+            "--top-k" => parse_count(args.next())
+                .filter(|k| *k > 0)
+                .map(|k| top_k = Some(k)),
+            "--distribution-stats" => {
+                distribution_stats = true;
+                Some(())
+            }
+            "--distribution-include-rejected" => {
+                distribution_include_rejected = true;
+                Some(())
+            }
+            "--audit-log" => args.next().map(|value| audit_log_file = Some(value)),
+            "--tag-rules" => args.next().map(|value| tag_rules_file = Some(value)),
+            "--output-limit" => parse_count(args.next()).map(|limit| selection.limit = Some(limit)),
+            "--only-locked" => {
+                selection.only_locked = true;
+                Some(())
+            }
+            "--only-negative" => {
+                selection.only_negative = true;
+                Some(())
+            }
+            "--expire-disputes-after" => {
+                parse_count(args.next()).map(|value| expire_disputes_after = Some(value))
+            }
+            _ => {
+                file_paths.push(arg);
+                Some(())
+            }
+        };
+        if parsed.is_none() {
+            eprintln!("{}", usage);
             return;
         }
+    }
+    if report_interval.is_some() && serve_socket.is_none() {
+        eprintln!("--report-interval requires --serve");
+        return;
+    }
+    if admin_addr.is_some() && serve_socket.is_none() {
+        eprintln!("--admin requires --serve");
+        return;
+    }
+    if priority_lanes.is_some() && serve_socket.is_none() {
+        eprintln!("--priority-lanes requires --serve");
+        return;
+    }
+    if round_excess_precision && amount_scale.is_none() {
+        eprintln!("--round-excess-precision requires --amount-scale");
+        return;
+    }
+    if distribution_include_rejected && !distribution_stats {
+        eprintln!("--distribution-include-rejected requires --distribution-stats");
+        return;
+    }
+    options.amount_scale = amount_scale.map(|scale| match round_excess_precision {
+        true => AmountScale::new(scale).rounding(),
+        false => AmountScale::new(scale),
+    });
+    if let Some(socket) = serve_socket {
+        if cfg!(not(all(feature = "server", unix))) {
+            eprintln!("--serve requires building with the server feature on Unix");
+            return;
+        }
+        if admin_addr.is_some() && cfg!(not(feature = "admin")) {
+            eprintln!("--admin requires building with the admin feature");
+            return;
+        }
+        if !file_paths.is_empty()
+            || workers.is_some()
+            || options != IngestOptions::default()
+            || column_map_file.is_some()
+            || manifest_file.is_some()
+            || remap_file.is_some()
+            || quarantine_file.is_some()
+            || two_pass
+            || extended_output
+            || line_ending != LineEnding::Lf
+            || admin_ops_file.is_some()
+            || output_format != OutputFormat::Csv
+            || output_dir.is_some()
+            || dust_threshold.is_some()
+            || chargeback_fee.is_some()
+            || sweep_locked_dust
+            || dispute_tracking != DisputeTracking::Full
+            || expire_disputes_after.is_some()
+            || audit
+            || replay_filter != ReplayFilter::default()
+            || enrich_file.is_some()
+            || only_flag.is_some()
+            || run_id.is_some()
+            || stamp_outputs
+            || expect_digest.is_some()
+            || print_digest
+            || selection.is_applied()
+            || unknown_client_on_debit != UnknownClientOnDebit::Error
+            || top_k.is_some()
+            || distribution_stats
+            || audit_log_file.is_some()
+            || tag_rules_file.is_some()
+        {
+            eprintln!(
+                "--serve takes no input file and can only be combined with --report-interval, \
+                 --output, --overdraft-limits, --dispute-limit, --dispute-limits, \
+                 --max-open-disputes, --idempotent-disputes, --dispute-amounts, --admin and --priority-lanes"
+            );
+            return;
+        }
+        let mut ledger_builder = Ledger::builder()
+            .overdraft_limits(overdraft_limits_or_exit(overdraft_limits_file.as_deref()))
+            .dispute_limits(dispute_limits_or_exit(dispute_limits_file.as_deref()))
+            .duplicate_dispute_policy(duplicate_dispute_policy);
+        ledger_builder = with_dispute_amounts(ledger_builder, dispute_amount_handling);
+        if let Some(limit) = dispute_limit {
+            ledger_builder = ledger_builder.dispute_limit(limit);
+        }
+        if let Some(limit) = max_open_disputes {
+            ledger_builder = ledger_builder.max_open_disputes_per_account(limit);
+        }
+        let ledger = ledger_builder.build();
+        serve(
+            socket,
+            ledger,
+            report_interval,
+            output_path,
+            admin_addr,
+            priority_lanes,
+        )
+        .await;
+        return;
+    }
+    let Some(file_path) = file_paths.first().cloned() else {
+        eprintln!("{}", usage);
+        return;
+    };
+    if file_paths.len() > 1 && (workers.is_some() || two_pass || quarantine_file.is_some()) {
+        eprintln!(
+            "Several input files cannot be combined with --workers, --two-pass or --quarantine"
+        );
+        return;
+    }
+    if let Some(path) = column_map_file {
+        if options.parser == IngestParser::Fast {
+            eprintln!("--column-map cannot be combined with --parser fast");
+            return;
+        }
+        match ColumnMap::read(&path) {
+            Ok(column_map) => options.column_map = Some(column_map),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = manifest_file {
+        if file_paths.len() > 1 || options.limit.is_some() {
+            eprintln!("--manifest cannot be combined with --limit or several input files");
+            return;
+        }
+        match Manifest::read(&path) {
+            Ok(manifest) => options.manifest = Some(manifest),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if (options.number_locale != NumberLocale::Dot
+        || options.amount_parsing != AmountParsing::default())
+        && options.parser == IngestParser::Fast
+    {
+        eprintln!(
+            "--number-locale, --allow-scientific-amounts, --strip-amount-symbols and \
+             --trim-amount-whitespace cannot be combined with --parser fast"
+        );
+        return;
+    }
+    if workers.is_some() && options != IngestOptions::default() {
+        eprintln!(
+            "--skip, --limit, --parser, --column-map, --number-locale, --allow-scientific-amounts, --strip-amount-symbols, --trim-amount-whitespace, --dedup-window, --track-raw-client-ids, --manifest and --amount-scale cannot be combined with --workers"
+        );
+        return;
+    }
+    if two_pass && (workers.is_some() || options != IngestOptions::default()) {
+        eprintln!(
+            "--two-pass cannot be combined with --workers, --skip, --limit, --parser, --column-map, --number-locale, --allow-scientific-amounts, --strip-amount-symbols, --trim-amount-whitespace, --dedup-window, --track-raw-client-ids, --manifest or --amount-scale"
+        );
+        return;
+    }
+    if io_config != IngestIoConfig::default()
+        && (workers.is_some() || two_pass || quarantine_file.is_some())
+    {
+        eprintln!("--io-buffer-size and --prefetch cannot be combined with --workers, --two-pass or --quarantine");
+        return;
+    }
+    options.io = io_config;
+    let replay_filter = Some(replay_filter).filter(|filter| *filter != ReplayFilter::default());
+    // Quarantined rows are read with the locale, so that ambiguous amounts end up there.
+    let quarantine_options = IngestOptions {
+        number_locale: NumberLocale::Dot,
+        amount_parsing: AmountParsing::default(),
+        ..options.clone()
+    };
+    if quarantine_file.is_some()
+        && (workers.is_some() || quarantine_options != IngestOptions::default())
+    {
+        eprintln!(
+            "--quarantine cannot be combined with --workers, --skip, --limit, --parser, --column-map, --dedup-window, --track-raw-client-ids, --manifest or --amount-scale"
+        );
+        return;
+    }
+
+    if output_format == OutputFormat::Parquet {
+        if cfg!(not(feature = "parquet")) {
+            eprintln!("--output-format parquet requires building with the parquet feature");
+            return;
+        }
+        if output_path.is_none() || extended_output || amount_scale.is_some() {
+            eprintln!("--output-format parquet requires --output and cannot be combined with --extended-output or --amount-scale");
+            return;
+        }
+    }
+    if output_dir.is_some() != partition.is_some() {
+        eprintln!("--output-dir and --partition must be given together");
+        return;
+    }
+    if output_dir.is_some()
+        && (output_path.is_some() || extended_output || output_format != OutputFormat::Csv)
+    {
+        eprintln!(
+            "--output-dir cannot be combined with --output, --output-format or --extended-output"
+        );
+        return;
+    }
+    if admin_ops_file.is_none() && (admin_ops_phase.is_some() || admin_ops_mode.is_some()) {
+        eprintln!("--admin-ops-phase and --admin-ops-on-error require --admin-ops");
+        return;
+    }
+    let admin_ops = admin_ops_file.map(|path| {
+        (
+            path,
+            admin_ops_phase.unwrap_or(AdminOpsPhase::Post),
+            admin_ops_mode.unwrap_or_default(),
+        )
+    });
+    if line_ending != LineEnding::Lf && (output_format != OutputFormat::Csv || output_dir.is_some())
+    {
+        eprintln!("--crlf-output cannot be combined with --output-format parquet or --output-dir");
+        return;
+    }
+    if reconcile_file.is_none() && (reconcile_report.is_some() || !reconcile_tolerance.is_zero()) {
+        eprintln!("--reconcile-report and --reconcile-tolerance require --reconcile");
+        return;
+    }
+    if only_flag.is_some() && enrich_file.is_none() {
+        eprintln!("--only-flag requires --enrich");
+        return;
+    }
+    if enrich_file.is_some() && (output_format != OutputFormat::Csv || output_dir.is_some()) {
+        eprintln!("--enrich cannot be combined with --output-format parquet or --output-dir");
+        return;
+    }
+    if selection.is_applied()
+        && (enrich_file.is_some() || output_format != OutputFormat::Csv || output_dir.is_some())
+    {
+        eprintln!(
+            "--output-clients, --output-limit, --only-locked and --only-negative cannot be \
+             combined with --enrich, --output-format parquet or --output-dir"
+        );
+        return;
+    }
+    // Read before the run, so that invalid rules do not waste it.
+    let tag_rules = match tag_rules_file.map(TagRules::read).transpose() {
+        Ok(tag_rules) => tag_rules,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let run_id = run_id.unwrap_or_else(RunId::generate);
+    // The outputs and stderr name the run only when asked to, as the comment line
+    // breaks readers which don't expect it.
+    let stamp = stamp_outputs.then_some(&run_id);
+    if let Some(run_id) = stamp {
+        eprintln!("Run id {}", run_id);
+    }
+
+    // Output files are staged and renamed over their paths together once everything
+    // was written, so that a failed run leaves the files of the previous one.
+    let mut staging = Staging::new();
+    // Created before the run, so that a bad path does not waste it.
+    let output_file = match output_path.map(|path| staging.create(path)).transpose() {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to create output file: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let reconcile_report = match reconcile_report
+        .map(|path| staging.create(&path).map(|file| (path, file)))
+        .transpose()
+    {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("Failed to create reconciliation report: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let audit_log = match audit_log_file.map(|path| staging.create(path)).transpose() {
+        Ok(file) => file.map(|file| AuditLog::new(BufWriter::new(file))),
+        Err(err) => {
+            eprintln!("Failed to create audit log: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let settings = RunSettings {
+        replay_filter,
+        run_id: run_id.clone(),
+        top_k,
+        distribution: distribution_stats.then_some(distribution_include_rejected),
+        audit_log,
+        tag_rules,
+    };
+    let collisions_file = match collisions_report
+        .map(|path| staging.create(path))
+        .transpose()
+    {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to create collisions report: {}", err);
+            std::process::exit(1);
+        }
     };
 
-    let (sender, mut receiver) = tokio::sync::mpsc::channel(CHANNEL_SIZE);
+    let enrichment = match enrich_file.map(Enrichment::from_path).transpose() {
+        Ok(enrichment) => enrichment.map(|enrichment| match only_flag {
+            Some(flag) => enrichment.only_flag(flag),
+            None => enrichment,
+        }),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
-    tokio::spawn(payments_engine::read_data(file_path, sender));
+    let remap = match remap_file.as_deref().map(read_client_remap).transpose() {
+        Ok(remap) => remap,
+        Err(err) => {
+            eprintln!("Failed to read remap file: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut ledger_builder = Ledger::builder()
+        .sweep_locked_dust(sweep_locked_dust)
+        .dispute_tracking(dispute_tracking)
+        .overdraft_limits(overdraft_limits_or_exit(overdraft_limits_file.as_deref()))
+        .dispute_limits(dispute_limits_or_exit(dispute_limits_file.as_deref()))
+        .duplicate_dispute_policy(duplicate_dispute_policy)
+        .unknown_client_on_debit(unknown_client_on_debit);
+    ledger_builder = with_dispute_amounts(ledger_builder, dispute_amount_handling);
+    if let Some(threshold) = dust_threshold {
+        ledger_builder = ledger_builder.dust_threshold(threshold);
+    }
+    if let Some(amount) = chargeback_fee {
+        ledger_builder = ledger_builder.posting_rule(ChargebackFeeRule { amount });
+    }
+    if let Some(scale) = amount_scale {
+        ledger_builder = ledger_builder.amount_scale(scale);
+    }
+    if let Some(limit) = dispute_limit {
+        ledger_builder = ledger_builder.dispute_limit(limit);
+    }
+    if let Some(limit) = max_open_disputes {
+        ledger_builder = ledger_builder.max_open_disputes_per_account(limit);
+    }
+    if expect_digest.is_some() || print_digest {
+        ledger_builder = ledger_builder.execution_digest();
+    }
+    let mut ledger = ledger_builder.build();
+    if let Some((path, AdminOpsPhase::Pre, mode)) = &admin_ops {
+        apply_admin_ops_or_exit(&mut ledger, path, *mode);
+    }
+    let (mut ledger, collisions) = if two_pass || quarantine_file.is_some() {
+        if let Err(err) = check_file_headers(&file_path, options.allow_extra_columns) {
+            eprintln!("Failed to read input file: {}", err);
+            std::process::exit(1);
+        }
+        let quarantine = match quarantine_file.map(|path| staging.create(path)).transpose() {
+            Ok(file) => {
+                file.map(|file| Quarantine::new(BufWriter::new(file)).stamp_run_id(stamp_outputs))
+            }
+            Err(err) => {
+                eprintln!("Failed to create quarantine file: {}", err);
+                std::process::exit(1);
+            }
+        };
+        let ledger = run_file(
+            file_path,
+            two_pass.then_some(strict_order),
+            quarantine,
+            &options,
+            settings,
+            ledger,
+        )
+        .await;
+        (ledger, Vec::new())
+    } else {
+        run_streaming(file_paths, workers, options, force, settings, ledger).await
+    };
 
-    let mut ledger = Ledger::new();
-    while let Some(tx) = receiver.recv().await {
-        ledger.execute(&tx).ok();
+    if let Some(file) = collisions_file {
+        if let Err(err) = write_collisions(&collisions, stamp, BufWriter::new(file)) {
+            eprintln!("Failed to write collisions report: {}", err);
+            std::process::exit(1);
+        }
     }
 
-    payments_engine::output_data(&ledger).await;
+    if ledger.is_poisoned() {
+        eprintln!(
+            "WARNING: transactions panicked during the run and may have been applied \
+             partially, see the invariant check below"
+        );
+    }
+
+    if let Some(after) = expire_disputes_after {
+        let older_than_seq = ledger.tx_seq().saturating_sub(after as u64);
+        for dispute in ledger.expire_disputes(older_than_seq) {
+            eprintln!(
+                "Expired dispute of tx {} of client {}, released {}",
+                dispute.tx_id, dispute.client_id, dispute.amount
+            );
+        }
+    }
+
+    if let Some(remap) = remap {
+        if let Err(err) = ledger.apply_client_remap(&remap) {
+            eprintln!("Failed to remap clients: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some((path, AdminOpsPhase::Post, mode)) = &admin_ops {
+        apply_admin_ops_or_exit(&mut ledger, path, *mode);
+    }
+
+    for (client_id, amount) in ledger.sweep_configured_dust() {
+        eprintln!("Swept dust {} from client {}", amount, client_id);
+    }
+
+    match ledger.verify_invariants() {
+        Err(violation) => eprintln!("Ledger invariant violated: {}", violation),
+        Ok(()) if ledger.is_poisoned() => eprintln!("Ledger invariants hold despite the panics"),
+        Ok(()) => {}
+    }
+
+    if audit {
+        if let Err(drift) = ledger.audit_liabilities() {
+            eprintln!("Audit failed: {}", drift);
+            std::process::exit(1);
+        }
+        if let Err(drift) = ledger.audit_totals() {
+            eprintln!("Audit failed: {}", drift);
+            std::process::exit(1);
+        }
+    }
+
+    let digest_matches = match ledger.execution_digest() {
+        Some(digest) => {
+            if print_digest {
+                eprintln!(
+                    "Execution digest {} and state digest {}, digest version {}",
+                    digest,
+                    ledger.state_digest(),
+                    digest.digest_version
+                );
+            }
+            let matches = expect_digest
+                .as_ref()
+                .is_none_or(|expected| *expected == digest.digest);
+            if !matches {
+                eprintln!(
+                    "Execution digest {} differs from the expected {}, digest version {}",
+                    digest,
+                    expect_digest.as_deref().unwrap_or_default(),
+                    digest.digest_version
+                );
+            }
+            matches
+        }
+        None => true,
+    };
+
+    // Outputs are still written when the balances do not reconcile or the digest
+    // differs, for inspection, and the run exits with 1 once they are in place.
+    let ledger = ledger.view();
+    let reconciled = match reconcile_file {
+        Some(path) => {
+            let mut report = reconcile_or_exit(ledger, &path, reconcile_tolerance);
+            report.run_id = Some(run_id.clone());
+            if let Some((report_path, file)) = reconcile_report {
+                write_reconciliation_report(&report, &report_path, stamp, file);
+            }
+            report.is_clean()
+        }
+        None => true,
+    };
+
+    if let Some(enrichment) = &enrichment {
+        let unknown = enrichment.unknown_clients(ledger);
+        if unknown > 0 {
+            eprintln!(
+                "Warning: {} clients of the enrichment file have no account",
+                unknown
+            );
+        }
+    }
+
+    if let (Some(dir), Some(partition)) = (output_dir, partition) {
+        let dir = std::path::Path::new(&dir);
+        if let Err(err) = write_partitioned(ledger, dir, partition, run_id) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        commit_or_exit(staging);
+        if !reconciled || !digest_matches {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let output: Result<usize, Box<dyn std::error::Error>> = match (output_format, output_file) {
+        (OutputFormat::Csv, None) => {
+            write_csv(
+                ledger,
+                enrichment.as_ref(),
+                stamp,
+                LineEndingWriter::new(tokio::io::stdout(), line_ending),
+                extended_output,
+                &selection,
+            )
+            .await
+        }
+        (OutputFormat::Csv, Some(file)) => {
+            write_csv(
+                ledger,
+                enrichment.as_ref(),
+                stamp,
+                LineEndingWriter::new(tokio::fs::File::from_std(file), line_ending),
+                extended_output,
+                &selection,
+            )
+            .await
+        }
+        (OutputFormat::Parquet, file) => write_parquet(ledger, file.expect("checked above")),
+    };
+    if let Err(err) = output {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    commit_or_exit(staging);
+    if !reconciled || !digest_matches {
+        std::process::exit(1);
+    }
+}
+
+/// `check` subcommand, returning its exit code: 0 for a clean input, 1 when rows would
+/// be rejected and 2 when the input is structurally wrong or unreadable. Prints the
+/// summary on stdout whenever the input was looked at.
+fn check(exec_name: &str, mut args: impl Iterator<Item = String>) -> i32 {
+    let usage = format!(
+        "Usage: {} check [--fail-fast] [--report <path>] <input_file_path>",
+        exec_name
+    );
+    let mut fail_fast = false;
+    let mut report_path = None;
+    let mut file_path = None;
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--fail-fast" => {
+                fail_fast = true;
+                Some(())
+            }
+            "--report" => args.next().map(|value| report_path = Some(value)),
+            _ if file_path.is_none() => {
+                file_path = Some(arg);
+                Some(())
+            }
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", usage);
+            return 2;
+        }
+    }
+    let Some(file_path) = file_path else {
+        eprintln!("{}", usage);
+        return 2;
+    };
+    let mut report = match report_path.map(File::create).transpose() {
+        Ok(file) => file.map(BufWriter::new),
+        Err(err) => {
+            eprintln!("Failed to create report file: {}", err);
+            return 2;
+        }
+    };
+    let mut report_error = None;
+    let mut on_finding = |finding: CheckFinding| {
+        if let (Some(report), None) = (report.as_mut(), &report_error) {
+            report_error = serde_json::to_writer(&mut *report, &finding)
+                .map_err(std::io::Error::from)
+                .and_then(|()| report.write_all(b"\n"))
+                .err();
+        }
+        if fail_fast {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    };
+
+    // Problems with the file as a whole are a single structural finding on line 0.
+    let checked = check_file_headers(&file_path, false)
+        .map_err(|err| (err.code(), err.to_string()))
+        .and_then(|()| {
+            let reader =
+                BufReader::new(File::open(&file_path).map_err(|err| ("io", err.to_string()))?);
+            check_rows(reader, &mut Ledger::new(), &mut on_finding)
+                .map_err(|err| ("io", err.to_string()))
+        });
+    let summary = match checked {
+        Ok(summary) => summary,
+        Err((code, message)) => {
+            // Nothing is left to check either way.
+            let _ = on_finding(CheckFinding {
+                line: 0,
+                class: FindingClass::Structural,
+                code,
+                message,
+            });
+            CheckSummary {
+                structural: 1,
+                ..CheckSummary::default()
+            }
+        }
+    };
+    if let Some(err) = report_error.or_else(|| report.and_then(|mut report| report.flush().err())) {
+        eprintln!("Failed to write report file: {}", err);
+        return 2;
+    }
+    println!("{}", summary);
+    summary.exit_code()
+}
+
+/// `manifest` subcommand, printing the manifest of the input as JSON for its producer
+/// to ship next to it, and returning the exit code. Amounts are read as with the same
+/// flags in a run.
+/// Compares two account reports, exiting with 0 when they hold the same accounts,
+/// 1 when they differ and 2 when one cannot be read.
+fn compare(exec_name: &str, mut args: impl Iterator<Item = String>) -> i32 {
+    let usage = format!(
+        "Usage: {} compare [--ignore-column <name>]... <output_a> <output_b>",
+        exec_name
+    );
+    let mut options = CompareOptions::default();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--ignore-column" => args.next().map(|column| {
+                options.ignore_columns.insert(column);
+            }),
+            _ if paths.len() < 2 => {
+                paths.push(arg);
+                Some(())
+            }
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", usage);
+            return 2;
+        }
+    }
+    let [path_a, path_b] = paths.as_slice() else {
+        eprintln!("{}", usage);
+        return 2;
+    };
+    let open = |path: &String| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| eprintln!("Failed to open {}: {}", path, err))
+    };
+    let (Ok(a), Ok(b)) = (open(path_a), open(path_b)) else {
+        return 2;
+    };
+    let report = match compare_reports(a, b, &options) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("Failed to compare {} and {}: {}", path_a, path_b, err);
+            return 2;
+        }
+    };
+    for column in &report.added_columns {
+        println!("added column {}", column);
+    }
+    for column in &report.removed_columns {
+        println!("removed column {}", column);
+    }
+    for client_id in &report.added_clients {
+        println!("added client {}", client_id);
+    }
+    for client_id in &report.removed_clients {
+        println!("removed client {}", client_id);
+    }
+    for difference in &report.differences {
+        match difference.delta {
+            Some(delta) => println!(
+                "client {} {}: {} -> {} (delta {})",
+                difference.client_id, difference.column, difference.a, difference.b, delta
+            ),
+            None => println!(
+                "client {} {}: {} -> {}",
+                difference.client_id, difference.column, difference.a, difference.b
+            ),
+        }
+    }
+    eprintln!("Comparison: {}", report);
+    if report.is_identical() {
+        0
+    } else {
+        1
+    }
+}
+
+async fn manifest(exec_name: &str, mut args: impl Iterator<Item = String>) -> i32 {
+    let usage = format!(
+        "Usage: {} manifest [--number-locale dot|comma|auto] [--allow-scientific-amounts] \
+         [--strip-amount-symbols <symbols>] [--trim-amount-whitespace] <input_file_path>",
+        exec_name
+    );
+    let mut number_locale = NumberLocale::Dot;
+    let mut amount_parsing = AmountParsing::default();
+    let mut file_path = None;
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--number-locale"
+            | "--allow-scientific-amounts"
+            | "--strip-amount-symbols"
+            | "--trim-amount-whitespace" => {
+                parse_amount_flag(&arg, &mut args, &mut number_locale, &mut amount_parsing)
+            }
+            _ if file_path.is_none() => {
+                file_path = Some(arg);
+                Some(())
+            }
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", usage);
+            return 2;
+        }
+    }
+    let Some(file_path) = file_path else {
+        eprintln!("{}", usage);
+        return 2;
+    };
+    match generate_manifest(&file_path, number_locale, amount_parsing).await {
+        Ok(manifest) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&manifest).expect("manifests serialize")
+            );
+            0
+        }
+        Err(err) => {
+            eprintln!("Failed to read input file: {}", err);
+            1
+        }
+    }
+}
+
+/// Parses one of the flags setting how amounts are read, with its value.
+fn parse_amount_flag(
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+    number_locale: &mut NumberLocale,
+    amount_parsing: &mut AmountParsing,
+) -> Option<()> {
+    match arg {
+        "--number-locale" => match args.next().as_deref() {
+            Some("dot") => Some(NumberLocale::Dot),
+            Some("comma") => Some(NumberLocale::Comma),
+            Some("auto") => Some(NumberLocale::Auto),
+            _ => None,
+        }
+        .map(|value| *number_locale = value),
+        "--allow-scientific-amounts" => {
+            amount_parsing.allow_scientific = true;
+            Some(())
+        }
+        "--strip-amount-symbols" => args
+            .next()
+            .filter(|symbols| !symbols.is_empty())
+            .map(|symbols| amount_parsing.strip_symbols = symbols.chars().collect()),
+        "--trim-amount-whitespace" => {
+            amount_parsing.trim_unicode_whitespace = true;
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+/// Writes one `client,raw_ids` row per collision, with the raw ids separated by `;`.
+fn write_collisions(
+    collisions: &[PossibleClientCollision],
+    stamp: Option<&RunId>,
+    mut writer: impl Write,
+) -> Result<(), csv::Error> {
+    if let Some(run_id) = stamp {
+        writer.write_all(run_id.csv_comment().as_bytes())?;
+    }
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["client", "raw_ids"])?;
+    for collision in collisions {
+        let raw_ids = collision
+            .raw_ids
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>();
+        writer.write_record([collision.client.to_string(), raw_ids.join(";")])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reconciles the ledger against the expected balances at `path`, printing the
+/// summary and every discrepancy on stderr.
+fn reconcile_or_exit(
+    ledger: LedgerView<'_>,
+    path: &str,
+    tolerance: Decimal,
+) -> ReconciliationReport {
+    let report = match File::open(path)
+        .map_err(|err| err.to_string())
+        .and_then(|file| {
+            reconcile_with_tolerance(ledger, BufReader::new(file), tolerance)
+                .map_err(|err| err.to_string())
+        }) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("Failed to reconcile against {}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Reconciliation: {}", report);
+    for client_id in report.missing_in_ledger() {
+        eprintln!("  client {} is expected but has no account", client_id);
+    }
+    for client_id in report.missing_in_expected() {
+        eprintln!("  client {} has an account but is not expected", client_id);
+    }
+    for mismatch in report.mismatches() {
+        let locked = if mismatch.actual_locked == mismatch.expected_locked {
+            ""
+        } else if mismatch.actual_locked == Some(true) {
+            ", locked but expected unlocked"
+        } else {
+            ", unlocked but expected locked"
+        };
+        eprintln!(
+            "  client {} available off by {}, held off by {}{}",
+            mismatch.client_id, mismatch.available_delta, mismatch.held_delta, locked
+        );
+    }
+    report
+}
+
+/// Writes the reconciliation report as JSON when `path` ends with `.json`, as one CSV
+/// row per discrepancy otherwise.
+fn write_reconciliation_report(
+    report: &ReconciliationReport,
+    path: &str,
+    stamp: Option<&RunId>,
+    file: File,
+) {
+    let mut writer = BufWriter::new(file);
+    let written = if path.ends_with(".json") {
+        serde_json::to_writer_pretty(&mut writer, report)
+            .map_err(|err| err.to_string())
+            .and_then(|()| writer.flush().map_err(|err| err.to_string()))
+    } else {
+        stamp
+            .map_or(Ok(()), |run_id| {
+                writer.write_all(run_id.csv_comment().as_bytes())
+            })
+            .map_err(|err| err.to_string())
+            .and_then(|()| report.write_csv(writer).map_err(|err| err.to_string()))
+    };
+    if let Err(err) = written {
+        eprintln!("Failed to write reconciliation report: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn commit_or_exit(staging: Staging) {
+    if let Err(err) = staging.commit() {
+        eprintln!("Failed to move output files into place: {}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn write_csv(
+    ledger: LedgerView<'_>,
+    enrichment: Option<&Enrichment>,
+    stamp: Option<&RunId>,
+    mut writer: impl AsyncWrite + Unpin,
+    extended_output: bool,
+    selection: &OutputSelection,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    if let Some(run_id) = stamp {
+        writer.write_all(run_id.csv_comment().as_bytes()).await?;
+    }
+    let rows = if selection.is_applied() {
+        writer.write_all(selection.csv_comment().as_bytes()).await?;
+        write_accounts_selected(ledger, selection, extended_output, writer).await
+    } else if let Some(enrichment) = enrichment {
+        write_accounts_enriched(ledger, enrichment, extended_output, writer).await
+    } else if extended_output {
+        write_accounts_extended(ledger, writer).await
+    } else {
+        write_accounts(ledger, writer).await
+    };
+    Ok(rows?)
+}
+
+/// Writes the partitions and then the manifest, whose presence marks a complete export.
+fn write_partitioned(
+    ledger: LedgerView<'_>,
+    dir: &std::path::Path,
+    partition: Partition,
+    run_id: RunId,
+) -> Result<PartitionManifest, PartitionedOutputError> {
+    let mut manifest = match partition {
+        Partition::Modulo(partitioner) => output_accounts_partitioned(ledger, dir, partitioner)?,
+        Partition::Range(partitioner) => output_accounts_partitioned(ledger, dir, partitioner)?,
+    };
+    manifest.run_id = Some(run_id);
+    manifest.write(dir)?;
+    Ok(manifest)
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(ledger: LedgerView<'_>, file: File) -> Result<usize, Box<dyn std::error::Error>> {
+    use std::io::Write;
+    let mut writer = BufWriter::new(file);
+    let rows = payments_engine::parquet_output::output_accounts_parquet(ledger, &mut writer)?;
+    writer.flush()?;
+    Ok(rows)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(
+    _ledger: LedgerView<'_>,
+    _file: File,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    unreachable!("rejected with the arguments")
+}
+
+/// Applies the admin ops of `path`, reporting every failing row. Exits when the file
+/// cannot be read, or when a row failed under `AdminOpsMode::StopOnError`.
+fn apply_admin_ops_or_exit(ledger: &mut Ledger, path: &str, mode: AdminOpsMode) {
+    let report = File::open(path)
+        .map_err(|err| err.to_string())
+        .and_then(|file| {
+            apply_admin_ops(ledger, BufReader::new(file), mode).map_err(|err| err.to_string())
+        });
+    let report = match report {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("Failed to read admin ops file: {}", err);
+            std::process::exit(1);
+        }
+    };
+    for outcome in &report.outcomes {
+        if let AdminOpStatus::Failed(err) = &outcome.status {
+            eprintln!(
+                "Admin op {:?} on line {} failed: {}",
+                outcome.op, outcome.line, err
+            );
+        }
+    }
+    eprintln!(
+        "Applied {} of {} admin ops",
+        report.applied(),
+        report.outcomes.len()
+    );
+    if mode == AdminOpsMode::StopOnError && !report.is_clean() {
+        std::process::exit(1);
+    }
+}
+
+fn overdraft_limits_or_exit(path: Option<&str>) -> HashMap<ClientId, Decimal> {
+    match path.map(read_overdraft_limits).transpose() {
+        Ok(limits) => limits.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("Failed to read overdraft limits file: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn dispute_limits_or_exit(path: Option<&str>) -> HashMap<ClientId, DisputeLimit> {
+    match path.map(read_dispute_limits).transpose() {
+        Ok(limits) => limits.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("Failed to read dispute limits file: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Sets the handling of the amounts stated on dispute rows, reporting mismatches on
+/// `stderr` when they are only warned about.
+fn with_dispute_amounts(builder: LedgerBuilder, handling: DisputeAmountHandling) -> LedgerBuilder {
+    let builder = builder.dispute_amount_handling(handling);
+    if handling != DisputeAmountHandling::WarnOnMismatch {
+        return builder;
+    }
+    builder.event_listener(|event| {
+        if let LedgerEvent::DisputedAmountMismatch {
+            client_id,
+            tx_id,
+            expected,
+            provided,
+        } = event
+        {
+            eprintln!(
+                "WARNING: tx {} of client {} stated amount {}, the deposit recorded {}",
+                tx_id, client_id, provided, expected
+            );
+        }
+        Ok(())
+    })
+}
+
+/// Executes the transactions sent to `socket` until accepting connections fails,
+/// writing the account report every `report_interval` seconds. Reports are taken
+/// from a snapshot, so writing them doesn't hold up the connections. With
+/// `admin_addr`, the admin routes of `payments_engine::admin` are served there. With
+/// `priority_lanes`, transactions are executed in the order of
+/// `payments_engine::lanes`.
+#[cfg(all(feature = "server", unix))]
+async fn serve(
+    socket: String,
+    ledger: Ledger,
+    report_interval: Option<u64>,
+    output_path: Option<String>,
+    admin_addr: Option<std::net::SocketAddr>,
+    priority_lanes: Option<LaneConfig>,
+) {
+    use payments_engine::accounting::shared::SharedLedger;
+    use payments_engine::server::{serve_unix_socket_with_lanes, serve_unix_socket_with_stats};
+
+    let ledger = SharedLedger::new(ledger);
+    let stats = std::sync::Arc::default();
+    #[cfg(feature = "admin")]
+    if let Some(addr) = admin_addr {
+        if let Err(err) =
+            payments_engine::admin::spawn_admin(addr, ledger.clone(), std::sync::Arc::clone(&stats))
+                .await
+        {
+            eprintln!("Failed to start the admin listener on {}: {}", addr, err);
+            std::process::exit(1);
+        }
+    }
+    #[cfg(not(feature = "admin"))]
+    let _ = admin_addr;
+    if let Some(seconds) = report_interval {
+        let ledger = ledger.clone();
+        let clock = ledger.clock();
+        tokio::spawn(async move {
+            loop {
+                clock.sleep(std::time::Duration::from_secs(seconds)).await;
+                let snapshot = ledger.snapshot_accounts();
+                if let Err(err) = write_report(&snapshot, output_path.as_deref()).await {
+                    eprintln!("Failed to write report: {}", err);
+                }
+            }
+        });
+    }
+    let served = match priority_lanes {
+        Some(config) => serve_unix_socket_with_lanes(socket, ledger, stats, config).await,
+        None => serve_unix_socket_with_stats(socket, ledger, stats).await,
+    };
+    if let Err(err) = served {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Writes the report to stdout, or replaces the file at `path` with it so that readers
+/// never see a report in part.
+#[cfg(all(feature = "server", unix))]
+async fn write_report(
+    snapshot: &payments_engine::accounting::shared::AccountsSnapshot,
+    path: Option<&str>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    use payments_engine::output::write_snapshot;
+
+    let Some(path) = path else {
+        return Ok(write_snapshot(snapshot, tokio::io::stdout()).await?);
+    };
+    let temp_path = format!("{}.tmp", path);
+    let rows = write_snapshot(snapshot, tokio::fs::File::create(&temp_path).await?).await?;
+    tokio::fs::rename(&temp_path, path).await?;
+    Ok(rows)
+}
+
+#[cfg(not(all(feature = "server", unix)))]
+async fn serve(
+    _socket: String,
+    _ledger: Ledger,
+    _report_interval: Option<u64>,
+    _output_path: Option<String>,
+    _admin_addr: Option<std::net::SocketAddr>,
+    _priority_lanes: Option<LaneConfig>,
+) {
+    unreachable!("rejected with the arguments")
+}
+
+/// `watch` subcommand, returning its exit code: 0 once `--exit-when-idle-ms` passed
+/// without files and 1 when watching failed. Prints what happened to each file on
+/// stderr.
+fn watch(exec_name: &str, mut args: impl Iterator<Item = String>) -> i32 {
+    let usage = format!(
+        "Usage: {} watch [--pattern <glob>] [--settle-ms <N>] [--poll-interval-ms <N>] \
+         [--exit-when-idle-ms <N>] [--snapshot <path>] [--strict-order] <dir>",
+        exec_name
+    );
+    let mut config = WatchConfig::default();
+    let mut dir = None;
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--pattern" => args.next().map(|value| config.pattern = value),
+            "--settle-ms" => parse_millis(args.next()).map(|value| config.settle_period = value),
+            "--poll-interval-ms" => {
+                parse_millis(args.next()).map(|value| config.poll_interval = value)
+            }
+            "--exit-when-idle-ms" => {
+                parse_millis(args.next()).map(|value| config.exit_when_idle = Some(value))
+            }
+            "--snapshot" => args
+                .next()
+                .map(|value| config.snapshot_path = Some(value.into())),
+            "--strict-order" => {
+                config.strict_order = true;
+                Some(())
+            }
+            _ if dir.is_none() => {
+                dir = Some(arg);
+                Some(())
+            }
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", usage);
+            return 2;
+        }
+    }
+    let Some(dir) = dir else {
+        eprintln!("{}", usage);
+        return 2;
+    };
+    let watched = DirectoryWatcher::open(&dir, config, Ledger::new())
+        .and_then(|mut watcher| watcher.run(|event| eprintln!("{}", event)));
+    match watched {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn parse_millis(value: Option<String>) -> Option<std::time::Duration> {
+    parse_count(value).map(|millis| std::time::Duration::from_millis(millis as u64))
+}
+
+fn parse_count(value: Option<String>) -> Option<usize> {
+    value.and_then(|value| value.parse().ok())
+}
+
+/// Parses a comma separated list of transaction types, each in the singular or plural.
+fn parse_kinds(value: &str) -> Option<HashSet<TxTypeTag>> {
+    value
+        .split(',')
+        .map(|kind| {
+            let kind = kind.trim();
+            match kind.strip_suffix('s').unwrap_or(kind) {
+                "deposit" => Some(TxTypeTag::Deposit),
+                "withdrawal" => Some(TxTypeTag::Withdrawal),
+                "dispute" => Some(TxTypeTag::Dispute),
+                "resolve" => Some(TxTypeTag::Resolve),
+                "chargeback" => Some(TxTypeTag::Chargeback),
+                "adjustment" => Some(TxTypeTag::Adjustment),
+                "withdraw_auth" => Some(TxTypeTag::WithdrawAuth),
+                "withdraw_capture" => Some(TxTypeTag::WithdrawCapture),
+                "withdraw_release" => Some(TxTypeTag::WithdrawRelease),
+                "settle" => Some(TxTypeTag::Settle),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Settings of the pipeline executing the input.
+struct RunSettings {
+    replay_filter: Option<ReplayFilter>,
+    run_id: RunId,
+    top_k: Option<usize>,
+    /// Whether the distribution stats include rejected amounts, unless not kept.
+    distribution: Option<bool>,
+    audit_log: Option<AuditLog<BufWriter<File>>>,
+    tag_rules: Option<TagRules>,
+}
+
+type RunPipeline<S> =
+    Pipeline<S, ((), Option<ReplayFilter>), ((), Option<AuditLog<BufWriter<File>>>)>;
+
+impl RunSettings {
+    fn pipeline<S: TransactionSource>(self, source: S) -> RunPipeline<S> {
+        let mut pipeline = Pipeline::new(source)
+            .filter(self.replay_filter)
+            .audit(self.audit_log)
+            .run_id(self.run_id);
+        if let Some(k) = self.top_k {
+            pipeline = pipeline.top_k(k);
+        }
+        if let Some(include_rejected) = self.distribution {
+            pipeline = pipeline.distribution_stats(include_rejected);
+        }
+        if let Some(rules) = self.tag_rules {
+            pipeline = pipeline.tag_rules(rules);
+        }
+        pipeline
+    }
+}
+
+async fn run_streaming(
+    file_paths: Vec<String>,
+    workers: Option<usize>,
+    options: IngestOptions,
+    force: bool,
+    settings: RunSettings,
+    ledger: Ledger,
+) -> (Ledger, Vec<PossibleClientCollision>) {
+    let (sender, receiver) = tokio::sync::mpsc::channel(CHANNEL_SIZE);
+
+    let allow_extra_columns = options.allow_extra_columns;
+    let ingest = match workers {
+        Some(workers) => tokio::spawn(async move {
+            let file_path = file_paths
+                .into_iter()
+                .next()
+                .expect("checked with the arguments");
+            payments_engine::read_data_parallel(file_path, sender, workers, allow_extra_columns)
+                .await
+                .map(|_row_errors| None)
+        }),
+        None => tokio::spawn(async move {
+            payments_engine::ingest::read_data_multi(file_paths, sender, options)
+                .await
+                .map(Some)
+                .map_err(EngineError::from)
+        }),
+    };
+
+    let ledger = run_blocking(ledger, move |ledger| {
+        settings.pipeline(receiver).run(ledger)
+    })
+    .await;
+
+    let report = match ingest.await {
+        Ok(Ok(report)) => report,
+        Ok(Err(EngineError::Ingest(err @ IngestError::ManifestMismatch { .. }))) if force => {
+            eprintln!(
+                "WARNING: {}, writing the output anyway because of --force",
+                err
+            );
+            None
+        }
+        Ok(Err(err)) => {
+            eprintln!("Failed to read input file: {}", err);
+            std::process::exit(1);
+        }
+        Err(_err) => None,
+    };
+    let Some(report) = report else {
+        return (ledger, Vec::new());
+    };
+    if report.rows_skipped > 0 {
+        eprintln!("Skipped {} rows", report.rows_skipped);
+    }
+    if report.rows_executed_silently > 0 {
+        eprintln!(
+            "Executed {} skipped rows silently",
+            report.rows_executed_silently
+        );
+    }
+    if report.rows_deduped > 0 {
+        eprintln!("Skipped {} repeated rows", report.rows_deduped);
+    }
+    if report.blank_lines_skipped > 0 {
+        eprintln!("Skipped {} blank lines", report.blank_lines_skipped);
+    }
+    if report.limit_reached {
+        eprintln!("Stopped after {} rows because of --limit", report.rows_read);
+    }
+    for collision in &report.client_collisions {
+        eprintln!("Possible client collision: {}", collision);
+    }
+    (ledger, report.client_collisions)
+}
+
+/// Executes the file on a blocking task, after validating it with preflight when
+/// `preflight_order` is set, to whether the order is strict. Amounts are read as
+/// `options` says.
+async fn run_file(
+    file_path: String,
+    preflight_order: Option<bool>,
+    quarantine: Option<Quarantine<BufWriter<File>>>,
+    options: &IngestOptions,
+    settings: RunSettings,
+    ledger: Ledger,
+) -> Ledger {
+    let number_locale = options.number_locale;
+    let amount_parsing = options.amount_parsing.clone();
+    run_blocking(ledger, move |ledger| {
+        let warnings = if let Some(strict_order) = preflight_order {
+            let report = preflight(BufReader::new(File::open(&file_path)?), strict_order)?;
+            if !report.is_clean() {
+                eprint!("{}", report);
+                eprintln!("Preflight failed, nothing was executed");
+                std::process::exit(1);
+            }
+            report.warnings
+        } else {
+            Vec::new()
+        };
+        let source = CsvSource::new(BufReader::new(File::open(&file_path)?))
+            .with_number_locale(number_locale)
+            .with_amount_parsing(amount_parsing);
+        let stats = settings
+            .pipeline(source)
+            .quarantine(quarantine)
+            .metrics(|stats: &RunStats| {
+                if stats.rows_quarantined > 0 {
+                    eprintln!("Quarantined {} rows", stats.rows_quarantined);
+                }
+            })
+            .run(ledger)?;
+        for warning in &warnings {
+            eprintln!("line {}: warning: {}", warning.line, warning.issue);
+        }
+        Ok(stats)
+    })
+    .await
+}
+
+async fn run_blocking(
+    mut ledger: Ledger,
+    run: impl FnOnce(&mut Ledger) -> std::io::Result<RunStats> + Send + 'static,
+) -> Ledger {
+    let (ledger, result) = tokio::task::spawn_blocking(move || {
+        let result = run(&mut ledger);
+        (ledger, result)
+    })
+    .await
+    .expect("Blocking run panicked");
+    match result {
+        Ok(stats) => {
+            if stats.rows_duplicate > 0 {
+                eprintln!("Accepted {} duplicate rows", stats.rows_duplicate);
+            }
+            if stats.rows_dispute_tracking_disabled > 0 {
+                eprintln!(
+                    "Rejected {} dispute, resolve, chargeback and adjustment rows because of \
+                     --no-dispute-tracking",
+                    stats.rows_dispute_tracking_disabled
+                );
+            }
+            if stats.rows_filtered > 0 {
+                eprintln!(
+                    "Filtered out {} rows, {} rejected rows referred to them",
+                    stats.rows_filtered, stats.rows_rejected_after_filter
+                );
+            }
+            if let Some(top_k) = &stats.top_k {
+                eprint!("{}", top_k);
+            }
+            if let Some(distribution) = &stats.distribution {
+                eprint!("{}", distribution);
+            }
+            for (tag, rows) in &stats.tag_counts {
+                eprintln!("Tagged {} rows {}", rows, tag);
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", TwoPassError::Io(err));
+            std::process::exit(1);
+        }
+    }
+    ledger
 }
@@ -0,0 +1,527 @@
+//! Amounts written with other separators than `1234.56`, such as `1.234,56` in
+//! European partner files, or decorated with currency symbols and exponents.
+use crate::accounting::transactions::TransactionLogError;
+use rust_decimal::Decimal;
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// Separators of the amounts in an input.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum NumberLocale {
+    /// `1,234.56`: a dot starts the fraction and commas group thousands.
+    #[default]
+    Dot,
+    /// `1.234,56`: a comma starts the fraction and dots group thousands.
+    Comma,
+    /// Either of the above, told apart per amount. The last separator starts the
+    /// fraction when both occur, and a repeated one groups thousands. A single one
+    /// followed by exactly three digits, as in `1,234` or `1.234`, is ambiguous.
+    Auto,
+}
+
+impl NumberLocale {
+    /// Rewrites `amount` into the `1234.56` format `Decimal` parses. Thousands must be
+    /// grouped by three digits, and amounts without separators are returned as they are.
+    ///
+    /// Amounts of CSV rows and JSON lines are all normalized here before parsing.
+    pub fn normalize_amount(self, amount: &str) -> Result<Cow<'_, str>, TransactionLogError> {
+        if !amount.contains([',', '.']) {
+            return Ok(Cow::Borrowed(amount));
+        }
+        let (decimal, group) = match self {
+            NumberLocale::Dot if !amount.contains(',') => return Ok(Cow::Borrowed(amount)),
+            NumberLocale::Dot => ('.', ','),
+            NumberLocale::Comma => (',', '.'),
+            NumberLocale::Auto => auto_separators(amount)?,
+        };
+        let (integer, fraction) = match amount.split_once(decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (amount, None),
+        };
+        let digits = integer.trim_start_matches(['-', '+']);
+        let mut groups = digits.split(group);
+        let grouped = groups.next().is_some_and(|first| {
+            first.len() == digits.len()
+                || ((1..=3).contains(&first.len()) && !first.starts_with('0'))
+        }) && groups.all(|group| group.len() == 3);
+        if !grouped || fraction.is_some_and(|fraction| fraction.contains([',', '.'])) {
+            return Err(TransactionLogError::AmbiguousAmount);
+        }
+
+        let mut normalized = integer[..integer.len() - digits.len()].to_string();
+        normalized.extend(digits.split(group));
+        if let Some(fraction) = fraction {
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        Ok(Cow::Owned(normalized))
+    }
+
+    /// Normalizes the field of `record` in the `amount` column of `headers`.
+    pub(crate) fn normalize_amount_field<R>(
+        self,
+        headers: &R,
+        record: &mut R,
+    ) -> Result<(), TransactionLogError>
+    where
+        R: for<'a> FromIterator<&'a str>,
+        for<'a> &'a R: IntoIterator<Item = &'a str>,
+    {
+        match headers.into_iter().position(|header| header == "amount") {
+            Some(index) => self.normalize_field(record, index),
+            None => Ok(()),
+        }
+    }
+
+    /// Normalizes field `index` of `record` with `normalize_amount`.
+    pub(crate) fn normalize_field<R>(
+        self,
+        record: &mut R,
+        index: usize,
+    ) -> Result<(), TransactionLogError>
+    where
+        R: for<'a> FromIterator<&'a str>,
+        for<'a> &'a R: IntoIterator<Item = &'a str>,
+    {
+        replace_field(record, index, |field| self.normalize_amount(field))
+    }
+}
+
+/// Steps turning decorated amounts such as `$25.00` into ones `Decimal` parses, each
+/// off by default so that such amounts are malformed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AmountParsing {
+    /// Expands exponents such as `1.5E+2` into `150` exactly. Amounts which still
+    /// fail to parse are left as they are, to be reported as malformed. When off,
+    /// exponents are still read exactly once the amount is parsed.
+    pub allow_scientific: bool,
+    /// Characters removed wherever they occur, such as currency symbols.
+    pub strip_symbols: Vec<char>,
+    /// Trims Unicode whitespace, such as non-breaking spaces, around the amount.
+    /// Fields are always trimmed of ASCII whitespace.
+    pub trim_unicode_whitespace: bool,
+}
+
+impl AmountParsing {
+    /// All steps, stripping the `$`, `€` and `£` symbols.
+    pub fn permissive() -> Self {
+        Self {
+            allow_scientific: true,
+            strip_symbols: vec!['$', '€', '£'],
+            trim_unicode_whitespace: true,
+        }
+    }
+
+    /// Rewrites `amount` into the `1234.56` format `Decimal` parses: strips symbols,
+    /// trims whitespace, applies `number_locale` and expands exponents, in that order.
+    pub fn normalize<'a>(
+        &self,
+        amount: &'a str,
+        number_locale: NumberLocale,
+    ) -> Result<Cow<'a, str>, TransactionLogError> {
+        let mut amount = Cow::Borrowed(amount);
+        if amount.contains(self.strip_symbols.as_slice()) {
+            amount = Cow::Owned(amount.replace(self.strip_symbols.as_slice(), ""));
+        }
+        if self.trim_unicode_whitespace && amount.trim().len() != amount.len() {
+            amount = match amount {
+                Cow::Borrowed(amount) => Cow::Borrowed(amount.trim()),
+                Cow::Owned(amount) => Cow::Owned(amount.trim().to_string()),
+            };
+        }
+        if let Cow::Owned(normalized) = number_locale.normalize_amount(&amount)? {
+            amount = Cow::Owned(normalized);
+        }
+        if self.allow_scientific && amount.contains(['e', 'E']) {
+            if let Ok(expanded) = Decimal::from_scientific(&amount) {
+                amount = Cow::Owned(expanded.to_string());
+            }
+        }
+        Ok(amount)
+    }
+
+    /// Normalizes the field of `record` in the `amount` column of `headers`.
+    pub(crate) fn normalize_amount_field<R>(
+        &self,
+        number_locale: NumberLocale,
+        headers: &R,
+        record: &mut R,
+    ) -> Result<(), TransactionLogError>
+    where
+        R: for<'a> FromIterator<&'a str>,
+        for<'a> &'a R: IntoIterator<Item = &'a str>,
+    {
+        match headers.into_iter().position(|header| header == "amount") {
+            Some(index) => self.normalize_field(number_locale, record, index),
+            None => Ok(()),
+        }
+    }
+
+    /// Normalizes field `index` of `record` with `normalize`.
+    pub(crate) fn normalize_field<R>(
+        &self,
+        number_locale: NumberLocale,
+        record: &mut R,
+        index: usize,
+    ) -> Result<(), TransactionLogError>
+    where
+        R: for<'a> FromIterator<&'a str>,
+        for<'a> &'a R: IntoIterator<Item = &'a str>,
+    {
+        replace_field(record, index, |field| self.normalize(field, number_locale))
+    }
+}
+
+/// Parses an amount of any input: an optional `+` or `-` sign, digits with an
+/// optional fraction after a `.`, and an optional exponent such as `E+2`. Amounts are
+/// parsed exactly, with fractions beyond the scale of `Decimal` rounded, and without
+/// trailing zeros, so `10.50` reads as `10.5` the way CSV amounts always have.
+///
+/// Amounts which start with a number followed by other characters, such as `50.00abc`,
+/// fail with `TrailingCharactersInAmount` at the first character not part of the
+/// number. Other amounts which are not numbers, or are out of the range of `Decimal`,
+/// fail with `MissingAmount`, as if there were no amount. The sign is not checked,
+/// see `NegativeAmountNotAllowed`.
+pub(crate) fn parse_amount(raw: &str) -> Result<Decimal, TransactionLogError> {
+    let bytes = raw.as_bytes();
+    let digits_from = |start: usize| {
+        bytes[start.min(bytes.len())..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count()
+    };
+    let mut end = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let integer_digits = digits_from(end);
+    end += integer_digits;
+    let mut fraction_digits = 0;
+    if bytes.get(end) == Some(&b'.') {
+        fraction_digits = digits_from(end + 1);
+        end += 1 + fraction_digits;
+    }
+    if integer_digits + fraction_digits == 0 {
+        return Err(TransactionLogError::MissingAmount);
+    }
+    let number_end = end;
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent_digits = digits_from(end + 1 + sign);
+        if exponent_digits > 0 {
+            end += 1 + sign + exponent_digits;
+        }
+    }
+    if end < raw.len() {
+        return Err(TransactionLogError::TrailingCharactersInAmount {
+            raw: raw.to_string(),
+            at_offset: end,
+        });
+    }
+    match end == number_end {
+        true => Decimal::from_str(raw),
+        false => Decimal::from_scientific(raw),
+    }
+    .map(|amount| amount.normalize())
+    .map_err(|_err| TransactionLogError::MissingAmount)
+}
+
+/// Replaces field `index` of `record` with what `normalize` makes of it, when that
+/// differs.
+fn replace_field<R>(
+    record: &mut R,
+    index: usize,
+    normalize: impl for<'f> FnOnce(&'f str) -> Result<Cow<'f, str>, TransactionLogError>,
+) -> Result<(), TransactionLogError>
+where
+    R: for<'a> FromIterator<&'a str>,
+    for<'a> &'a R: IntoIterator<Item = &'a str>,
+{
+    let Some(field) = (&*record).into_iter().nth(index) else {
+        return Ok(());
+    };
+    if let Cow::Owned(amount) = normalize(field)? {
+        *record = (&*record)
+            .into_iter()
+            .enumerate()
+            .map(|(field_index, field)| match field_index == index {
+                true => amount.as_str(),
+                false => field,
+            })
+            .collect();
+    }
+    Ok(())
+}
+
+/// Decimal and grouping separators of `amount` under `NumberLocale::Auto`.
+fn auto_separators(amount: &str) -> Result<(char, char), TransactionLogError> {
+    let (separator, other) = match (amount.rfind('.'), amount.rfind(',')) {
+        (Some(dot), Some(comma)) if dot > comma => return Ok(('.', ',')),
+        (Some(_dot), Some(_comma)) => return Ok((',', '.')),
+        (Some(_dot), None) => ('.', ','),
+        _ => (',', '.'),
+    };
+    if amount.matches(separator).count() > 1 {
+        return Ok((other, separator));
+    }
+    let (integer, fraction) = amount
+        .split_once(separator)
+        .expect("separator occurs in amount");
+    let leading_digits = integer.trim_start_matches(['-', '+', '0']);
+    if fraction.len() == 3 && !leading_digits.is_empty() {
+        return Err(TransactionLogError::AmbiguousAmount);
+    }
+    Ok((separator, other))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::str::FromStr;
+
+    const AMBIGUOUS: Result<Decimal, TransactionLogError> =
+        Err(TransactionLogError::AmbiguousAmount);
+
+    fn parse(locale: NumberLocale, amount: &str) -> Result<Decimal, TransactionLogError> {
+        let normalized = locale.normalize_amount(amount)?;
+        Ok(Decimal::from_str(&normalized).unwrap())
+    }
+
+    #[test]
+    fn amounts_under_each_locale() {
+        use NumberLocale::{Auto, Comma, Dot};
+        let cases = [
+            (
+                "1.234,56",
+                [AMBIGUOUS, Ok(dec!(1234.56)), Ok(dec!(1234.56))],
+            ),
+            (
+                "1,234.56",
+                [Ok(dec!(1234.56)), AMBIGUOUS, Ok(dec!(1234.56))],
+            ),
+            ("12,5", [AMBIGUOUS, Ok(dec!(12.5)), Ok(dec!(12.5))]),
+            ("1,234", [Ok(dec!(1234)), Ok(dec!(1.234)), AMBIGUOUS]),
+            ("1.5", [Ok(dec!(1.5)), AMBIGUOUS, Ok(dec!(1.5))]),
+            ("0,125", [AMBIGUOUS, Ok(dec!(0.125)), Ok(dec!(0.125))]),
+            (
+                "1.234.567,8",
+                [AMBIGUOUS, Ok(dec!(1234567.8)), Ok(dec!(1234567.8))],
+            ),
+            (
+                "1,234,567",
+                [Ok(dec!(1234567)), AMBIGUOUS, Ok(dec!(1234567))],
+            ),
+            ("12,34.5", [AMBIGUOUS, AMBIGUOUS, AMBIGUOUS]),
+            ("1,5,0", [AMBIGUOUS, AMBIGUOUS, AMBIGUOUS]),
+            ("42", [Ok(dec!(42)), Ok(dec!(42)), Ok(dec!(42))]),
+        ];
+        for (amount, expected) in cases {
+            for (locale, expected) in [Dot, Comma, Auto].into_iter().zip(expected) {
+                assert_eq!(
+                    parse(locale, amount),
+                    expected,
+                    "{} under {:?}",
+                    amount,
+                    locale
+                );
+            }
+        }
+        // Dot amounts without commas are passed on as they were read before locales.
+        assert_eq!(Dot.normalize_amount("1.2.3"), Ok(Cow::Borrowed("1.2.3")));
+    }
+
+    #[test]
+    fn decorated_amounts_under_strict_and_permissive_parsing() {
+        let parse = |parsing: &AmountParsing, amount: &str| {
+            let normalized = parsing.normalize(amount, NumberLocale::Dot).unwrap();
+            Decimal::from_str(&normalized).ok()
+        };
+        let (strict, permissive) = (AmountParsing::default(), AmountParsing::permissive());
+        for amount in ["1.5E+2", "$25.00", "\u{a0}12.50\u{a0}"] {
+            assert_eq!(
+                strict.normalize(amount, NumberLocale::Dot),
+                Ok(Cow::Borrowed(amount))
+            );
+        }
+        for (amount, expected) in [("1.5E+2", dec!(150.0000)), ("2.5e-1", dec!(0.25))] {
+            assert_eq!(parse(&permissive, amount), Some(expected), "{}", amount);
+        }
+        for (amount, expected) in [
+            ("$25.00", dec!(25.00)),
+            ("-€3", dec!(-3)),
+            ("\u{a0}12.50\u{a0}", dec!(12.50)),
+            ("£ 7", dec!(7)),
+        ] {
+            assert_eq!(parse(&strict, amount), None, "{}", amount);
+            assert_eq!(parse(&permissive, amount), Some(expected), "{}", amount);
+        }
+        let exponent = permissive.normalize("1.5E+2", NumberLocale::Dot).unwrap();
+        assert_eq!(Decimal::from_str(&exponent).unwrap().to_string(), "150");
+        assert_eq!(
+            Decimal::from_str(&exponent).unwrap().round_dp(4),
+            dec!(150.0000)
+        );
+        // A stray letter is left for the row to be malformed
+        for amount in ["12x", "$1.5Ex", "1 000"] {
+            assert_eq!(parse(&permissive, amount), None, "{}", amount);
+        }
+
+        // Each step on its own
+        let scientific = AmountParsing {
+            allow_scientific: true,
+            ..AmountParsing::default()
+        };
+        assert_eq!(
+            scientific.normalize("1.00000000000000001E+2", NumberLocale::Dot),
+            Ok(Cow::Owned("100.000000000000001".to_string()))
+        );
+        assert_eq!(parse(&scientific, "$25.00"), None);
+        let symbols = AmountParsing {
+            strip_symbols: vec!['$'],
+            ..AmountParsing::default()
+        };
+        assert_eq!(parse(&symbols, "$25.00"), Some(dec!(25)));
+        assert_eq!(parse(&symbols, "€25.00"), None);
+        assert_eq!(parse(&symbols, "$\u{a0}25"), None);
+        let whitespace = AmountParsing {
+            trim_unicode_whitespace: true,
+            ..AmountParsing::default()
+        };
+        assert_eq!(parse(&whitespace, "\u{2007}12.50\u{a0}"), Some(dec!(12.5)));
+        assert_eq!(parse(&whitespace, "$12.50"), None);
+
+        // Locales apply between stripping and expanding
+        assert_eq!(
+            permissive.normalize("$1.234,5", NumberLocale::Comma),
+            Ok(Cow::Owned("1234.5".to_string()))
+        );
+        assert_eq!(
+            permissive.normalize("1,5E+2", NumberLocale::Comma),
+            Ok(Cow::Owned("150".to_string()))
+        );
+        assert_eq!(
+            strict.normalize("12.5", NumberLocale::Dot),
+            Ok(Cow::Borrowed("12.5"))
+        );
+    }
+
+    fn trailing(raw: &str, at_offset: usize) -> Result<Decimal, TransactionLogError> {
+        Err(TransactionLogError::TrailingCharactersInAmount {
+            raw: raw.to_string(),
+            at_offset,
+        })
+    }
+
+    #[test]
+    fn parses_signed_amounts_and_pinpoints_trailing_characters() {
+        const INVALID: Result<Decimal, TransactionLogError> =
+            Err(TransactionLogError::MissingAmount);
+        for (raw, expected) in [
+            ("50.00", Ok(dec!(50))),
+            ("+50.00", Ok(dec!(50))),
+            ("-2.50", Ok(dec!(-2.5))),
+            ("0", Ok(dec!(0))),
+            ("-0", Ok(dec!(0))),
+            (".5", Ok(dec!(0.5))),
+            ("+.5", Ok(dec!(0.5))),
+            ("5.", Ok(dec!(5))),
+            ("007.10", Ok(dec!(7.1))),
+            ("1.5E+2", Ok(dec!(150))),
+            ("2.5e-1", Ok(dec!(0.25))),
+            ("1e3", Ok(dec!(1000))),
+            ("0.00001", Ok(dec!(0.00001))),
+            ("123456789012.123456789", Ok(dec!(123456789012.123456789))),
+            ("50.00abc", trailing("50.00abc", 5)),
+            ("1.2.3", trailing("1.2.3", 3)),
+            ("12x", trailing("12x", 2)),
+            ("5 0", trailing("5 0", 1)),
+            ("1,5", trailing("1,5", 1)),
+            ("1e", trailing("1e", 1)),
+            ("1e+", trailing("1e+", 1)),
+            ("1.5E+2x", trailing("1.5E+2x", 6)),
+            ("-3-", trailing("-3-", 2)),
+            ("12€", trailing("12€", 2)),
+            ("", INVALID),
+            ("+", INVALID),
+            ("-", INVALID),
+            (".", INVALID),
+            ("+-1", INVALID),
+            ("--5", INVALID),
+            ("abc", INVALID),
+            ("$5", INVALID),
+            ("e5", INVALID),
+            (".e5", INVALID),
+            ("١٢", INVALID),
+            ("1e400", INVALID),
+            ("79228162514264337593543950336", INVALID),
+        ] {
+            assert_eq!(parse_amount(raw), expected, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn accepted_amounts_round_trip_through_display() {
+        const ALPHABET: &[u8] = b"0123456789+-.eE x";
+        let mut rng = crate::scenario::Lcg::new(411);
+        let (mut accepted, mut trailing) = (0, 0);
+        for _ in 0..20_000 {
+            let len = rng.next() as usize % 12;
+            let raw: String = (0..len)
+                .map(|_| char::from(ALPHABET[rng.next() as usize % ALPHABET.len()]))
+                .collect();
+            match parse_amount(&raw) {
+                // Exactly, which is within the four decimal places of balances
+                Ok(amount) => {
+                    accepted += 1;
+                    assert_eq!(parse_amount(&amount.to_string()), Ok(amount), "{:?}", raw);
+                }
+                // The number ends at the offset, though it may be out of range
+                Err(TransactionLogError::TrailingCharactersInAmount {
+                    raw: read,
+                    at_offset,
+                }) => {
+                    trailing += 1;
+                    assert_eq!(read, raw);
+                    assert!(
+                        !matches!(
+                            parse_amount(&raw[..at_offset]),
+                            Err(TransactionLogError::TrailingCharactersInAmount { .. })
+                        ) && at_offset > 0,
+                        "{:?}",
+                        raw
+                    );
+                }
+                Err(_err) => {}
+            }
+        }
+        assert!(
+            accepted > 1000 && trailing > 1000,
+            "{} {}",
+            accepted,
+            trailing
+        );
+
+        // Amounts written by the engine with four decimal places read back exactly
+        for _ in 0..1000 {
+            let amount = Decimal::new(i64::from(rng.next()), rng.next() % 5);
+            for shown in [
+                amount.to_string(),
+                format!("+{}", amount),
+                format!("{:.6}", amount),
+            ] {
+                assert_eq!(parse_amount(&shown), Ok(amount.normalize()), "{}", shown);
+            }
+        }
+    }
+
+    #[test]
+    fn normalizes_one_field_of_a_record() {
+        let mut record = csv::StringRecord::from(vec!["deposit", "1", "1", "1.234,5"]);
+        NumberLocale::Comma.normalize_field(&mut record, 3).unwrap();
+        assert_eq!(record, vec!["deposit", "1", "1", "1234.5"]);
+        assert_eq!(NumberLocale::Comma.normalize_field(&mut record, 4), Ok(()));
+        assert_eq!(
+            NumberLocale::Comma.normalize_field(&mut record, 3),
+            Err(TransactionLogError::AmbiguousAmount)
+        );
+    }
+}
@@ -0,0 +1,1208 @@
+use crate::accounting::reasons::DisputeReason;
+use crate::accounting::shared::AccountsSnapshot;
+use crate::accounting::transactions::TxTypeTag;
+use crate::accounting::view::LedgerView;
+use crate::accounting::{AccountLog, TxRecord, UserAccount};
+use crate::core_types::{ClientId, TxId};
+use crate::engine::EngineError;
+use crate::enrichment::Enrichment;
+use crate::staging::Staging;
+use hmac::{Hmac, Mac};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sha2::Sha256;
+use std::fmt::{Display, Formatter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug)]
+pub struct OutputError {
+    /// Account rows that reached the underlying writer before the failure.
+    pub rows_written: usize,
+    pub error: csv_async::Error,
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to write accounts after {} rows: {}",
+            self.rows_written, self.error
+        )
+    }
+}
+
+impl std::error::Error for OutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Counts lines accepted by the inner writer, so that rows lost in the
+/// serializer's buffer are not reported as written.
+struct LineCountingWriter<W> {
+    inner: W,
+    lines: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for LineCountingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.lines += buf[..written].iter().filter(|byte| **byte == b'\n').count();
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// How output lines end. Outputs end lines with `\n` on every platform unless
+/// `Crlf` is asked for, e.g. for files read back by spreadsheets on Windows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+/// Ends the lines written through it with `line_ending`, given lines ending with `\n`.
+///
+/// With `LineEnding::Crlf` converted bytes are buffered until the inner writer
+/// accepts them, so the writer must be flushed or shut down like a `BufWriter`.
+pub struct LineEndingWriter<W> {
+    inner: W,
+    line_ending: LineEnding,
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<W> LineEndingWriter<W> {
+    pub fn new(inner: W, line_ending: LineEnding) -> Self {
+        Self {
+            inner,
+            line_ending,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> LineEndingWriter<W> {
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.written < self.pending.len() {
+            let pending = &self.pending[self.written..];
+            match ready!(Pin::new(&mut self.inner).poll_write(cx, pending))? {
+                0 => return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into())),
+                written => self.written += written,
+            }
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for LineEndingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_write_pending(cx))?;
+        if self.line_ending == LineEnding::Lf {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+        for line in buf.split_inclusive(|byte| *byte == b'\n') {
+            match line.strip_suffix(b"\n") {
+                Some(line) => {
+                    self.pending.extend_from_slice(line);
+                    self.pending.extend_from_slice(b"\r\n");
+                }
+                None => self.pending.extend_from_slice(line),
+            }
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_write_pending(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_write_pending(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Writes one row per account, sorted by client like `Ledger::account_logs`, and
+/// flushes the writer, returning the number of rows.
+///
+/// Rows are formatted without serde into one reused buffer, see `write_account_logs`.
+pub async fn write_accounts<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    write_account_logs(ledger.account_logs(), writer).await
+}
+
+/// Reference implementation of `write_accounts` serializing every row with serde,
+/// which the faster path must match byte for byte.
+pub async fn write_accounts_serde<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    write_rows(ledger.account_logs(), writer).await
+}
+
+/// Like `write_accounts`, with additional `overdraft_limit`, `deposited`, `withdrawn`,
+/// `charged_back`, `open_disputes` and `created_by_rejection` columns.
+pub async fn write_accounts_extended<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    let rows = ledger
+        .accounts_sorted()
+        .into_iter()
+        .map(|user_account| ledger.extended_account_log(user_account));
+    write_rows(rows, writer).await
+}
+
+/// Row of `write_accounts_enriched` with `extended`.
+#[derive(Serialize)]
+struct EnrichedAccountLog<'a> {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    overdraft_limit: Decimal,
+    deposited: Decimal,
+    withdrawn: Decimal,
+    charged_back: Decimal,
+    open_disputes: u32,
+    created_by_rejection: bool,
+    flags: String,
+    note: &'a str,
+}
+
+/// Writes the accounts `enrichment` includes, as `write_accounts` or, with `extended`,
+/// as `write_accounts_extended` followed by `flags` and `note` columns, which are
+/// empty for clients without enrichment.
+pub async fn write_accounts_enriched<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    enrichment: &Enrichment,
+    extended: bool,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    let accounts = ledger
+        .accounts_sorted()
+        .into_iter()
+        .filter(|user_account| enrichment.includes(user_account.client_id()));
+    if !extended {
+        let rows = accounts.map(|user_account| ledger.account_log(user_account));
+        return write_account_logs(rows, writer).await;
+    }
+    let rows = accounts.map(|user_account| {
+        let client_enrichment = enrichment.get(user_account.client_id());
+        EnrichedAccountLog {
+            client: user_account.client_id(),
+            available: ledger.render_amount(user_account.available()),
+            held: ledger.render_amount(user_account.held()),
+            total: ledger.render_amount(user_account.total()),
+            locked: user_account.locked(),
+            overdraft_limit: ledger.render_amount(user_account.overdraft_limit()),
+            deposited: ledger.render_amount(user_account.total_deposited()),
+            withdrawn: ledger.render_amount(user_account.total_withdrawn()),
+            charged_back: ledger.render_amount(user_account.total_charged_back()),
+            open_disputes: user_account.open_disputes(),
+            created_by_rejection: user_account.created_by_rejection(),
+            flags: client_enrichment.map_or_else(String::new, |enriched| enriched.flags_field()),
+            note: client_enrichment.map_or("", |enriched| enriched.note.as_str()),
+        }
+    });
+    write_rows(rows, writer).await
+}
+
+/// Columns of `write_accounts`.
+const ACCOUNT_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Columns of `write_accounts_extended`.
+const EXTENDED_ACCOUNT_COLUMNS: [&str; 11] = [
+    "client",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "overdraft_limit",
+    "deposited",
+    "withdrawn",
+    "charged_back",
+    "open_disputes",
+    "created_by_rejection",
+];
+
+/// Subset of the accounts written by `write_accounts_selected`, e.g. to preview a
+/// large report. The selectors operate on the accounts sorted by client id, so that a
+/// `limit` combined with the other selectors always picks the same accounts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputSelection {
+    /// Clients whose accounts to write, all clients when `None`.
+    pub client_range: Option<RangeInclusive<ClientId>>,
+    /// Most accounts to write, the first ones by client id among those selected by
+    /// the other selectors, all of them when `None`.
+    pub limit: Option<usize>,
+    /// Write locked accounts only.
+    pub only_locked: bool,
+    /// Write accounts with a negative available balance only.
+    pub only_negative: bool,
+}
+
+impl OutputSelection {
+    /// Whether any selector is set, so that the output may leave accounts out.
+    pub fn is_applied(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Comment line put at the top of CSV outputs written with a selection applied, so
+    /// that a preview is not mistaken for the full report.
+    pub fn csv_comment(&self) -> &'static str {
+        "# selection_applied=true\n"
+    }
+
+    /// Accounts of `ledger` the selection picks, sorted by client id.
+    pub fn select<'a>(&self, ledger: impl Into<LedgerView<'a>>) -> Vec<&'a UserAccount> {
+        let ledger: LedgerView = ledger.into();
+        ledger
+            .accounts_sorted()
+            .into_iter()
+            .filter(|user_account| {
+                self.client_range
+                    .as_ref()
+                    .is_none_or(|range| range.contains(&user_account.client_id()))
+                    && (!self.only_locked || user_account.locked())
+                    && (!self.only_negative || user_account.available() < Decimal::ZERO)
+            })
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// Writes the accounts `selection` picks, sorted by client id, as `write_accounts` or,
+/// with `extended`, as `write_accounts_extended`. Unlike those, the header is written
+/// even when no account is selected.
+pub async fn write_accounts_selected<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    selection: &OutputSelection,
+    extended: bool,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    let accounts = selection.select(ledger);
+    match (accounts.is_empty(), extended) {
+        (true, false) => write_header(&ACCOUNT_COLUMNS, writer).await,
+        (true, true) => write_header(&EXTENDED_ACCOUNT_COLUMNS, writer).await,
+        (false, false) => {
+            let rows = accounts
+                .into_iter()
+                .map(|user_account| ledger.account_log(user_account));
+            write_account_logs(rows, writer).await
+        }
+        (false, true) => {
+            let rows = accounts
+                .into_iter()
+                .map(|user_account| ledger.extended_account_log(user_account));
+            write_rows(rows, writer).await
+        }
+    }
+}
+
+/// Writes the account report to the temporary file of `target` in `staging`, to be
+/// renamed over it by `Staging::commit`.
+pub(crate) async fn stage_accounts(
+    ledger: LedgerView<'_>,
+    staging: &mut Staging,
+    target: &Path,
+) -> Result<usize, EngineError> {
+    let mut file = tokio::fs::File::from_std(staging.create(target)?);
+    Ok(write_accounts(ledger, &mut file).await?)
+}
+
+/// Like `write_accounts`, for accounts copied with `SharedLedger::snapshot_accounts`.
+pub async fn write_snapshot(
+    snapshot: &AccountsSnapshot,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let rows: Vec<AccountLog> = snapshot.iter().map(AccountLog::from).collect();
+    write_account_logs(rows, writer).await
+}
+
+/// Row of `output_tx_states`.
+#[derive(Serialize)]
+struct TxStateLog {
+    tx: TxId,
+    client: ClientId,
+    kind: TxTypeTag,
+    amount: Decimal,
+    state: &'static str,
+    reason: Option<DisputeReason>,
+}
+
+impl From<&TxRecord> for TxStateLog {
+    fn from(record: &TxRecord) -> Self {
+        TxStateLog {
+            tx: record.tx_id(),
+            client: record.client_id(),
+            kind: record.kind(),
+            amount: record.amount(),
+            state: record.state().as_str(),
+            reason: record.reason().cloned(),
+        }
+    }
+}
+
+/// Writes one `tx,client,kind,amount,state,reason` row per recorded transaction,
+/// sorted by tx id, for reconciling the ledger against the transactions of other
+/// systems. The reason of deposits which were never disputed for one is empty.
+pub async fn output_tx_states<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    let mut rows: Vec<_> = ledger.tx_records_iter().map(TxStateLog::from).collect();
+    rows.sort_unstable_by_key(|row| row.tx);
+    write_rows(rows, writer).await
+}
+
+/// Row of `output_watchlist`.
+#[derive(Serialize)]
+struct WatchlistLog {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    breached_threshold: Decimal,
+}
+
+/// Writes one `client,available,held,locked,breached_threshold` row per account whose
+/// available balance is below any of `thresholds`, with the lowest threshold it is
+/// below, sorted by available balance and then client id.
+pub async fn output_watchlist<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+    thresholds: &[Decimal],
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    let Some(highest) = thresholds.iter().max() else {
+        return write_rows(Vec::<WatchlistLog>::new(), writer).await;
+    };
+    let mut rows: Vec<_> = ledger
+        .accounts_below(*highest)
+        .map(|view| WatchlistLog {
+            client: view.client_id,
+            available: view.available,
+            held: view.held,
+            locked: view.locked,
+            breached_threshold: thresholds
+                .iter()
+                .copied()
+                .filter(|threshold| view.available < *threshold)
+                .min()
+                .expect("below the highest threshold"),
+        })
+        .collect();
+    rows.sort_unstable_by_key(|row| (row.available, row.client));
+    write_rows(rows, writer).await
+}
+
+/// How client ids appear in an account report shared outside the organisation.
+#[derive(Default)]
+pub enum IdMapping<'a> {
+    /// Client ids as they are.
+    #[default]
+    None,
+    /// Each client id replaced by a token of 16 lowercase hex digits, the first 8 bytes
+    /// of its HMAC-SHA256 under `key`. Ids get the same token in every report made with
+    /// the same key and can't be recovered from the tokens without it. Rows are in the
+    /// order of the tokens, which says nothing about the order of the ids.
+    Pseudonymize { key: [u8; 32] },
+    /// Clients numbered from 1 in the order of their ids. The `client,original_client`
+    /// pairs are written to `reverse_mapping`, for tracing numbers back internally.
+    Sequential {
+        reverse_mapping: &'a mut (dyn AsyncWrite + Unpin + Send),
+    },
+}
+
+/// Row of the reverse mapping written by `IdMapping::Sequential`.
+#[derive(Serialize)]
+struct ReverseMappingLog {
+    client: u32,
+    original_client: ClientId,
+}
+
+/// Token of `client_id` under `IdMapping::Pseudonymize`.
+pub fn pseudonymize(key: &[u8; 32], client_id: ClientId) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&client_id.to_be_bytes());
+    mac.finalize().into_bytes()[..8]
+        .iter()
+        .fold(String::with_capacity(16), |mut token, byte| {
+            let _ = write!(token, "{:02x}", byte);
+            token
+        })
+}
+
+/// Like `write_accounts`, with client ids mapped by `mapping`. The columns stay the
+/// same, but under `IdMapping::Pseudonymize` the client column holds hex tokens
+/// instead of integers.
+///
+/// A failure writing the reverse mapping is reported once all account rows were
+/// written.
+pub async fn output_accounts_with_mapping<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+    mapping: IdMapping<'_>,
+) -> Result<usize, OutputError> {
+    let ledger: LedgerView = ledger.into();
+    let mut accounts: Vec<_> = ledger.accounts_iter().collect();
+    match mapping {
+        IdMapping::None => write_accounts(ledger, writer).await,
+        IdMapping::Pseudonymize { key } => {
+            let mut rows: Vec<_> = accounts
+                .into_iter()
+                .map(|(client_id, user_account)| {
+                    ledger
+                        .account_log(user_account)
+                        .with_client(pseudonymize(&key, *client_id))
+                })
+                .collect();
+            rows.sort_by(|a, b| a.client().cmp(b.client()));
+            write_rows(rows, writer).await
+        }
+        IdMapping::Sequential { reverse_mapping } => {
+            accounts.sort_unstable_by_key(|(client_id, _user_account)| **client_id);
+            let numbered = (1u32..).zip(&accounts);
+            let rows = numbered
+                .clone()
+                .map(|(client, (_client_id, user_account))| {
+                    ledger.account_log(user_account).with_client(client)
+                });
+            let rows_written = write_rows(rows, writer).await?;
+            let reverse_rows =
+                numbered.map(|(client, (client_id, _user_account))| ReverseMappingLog {
+                    client,
+                    original_client: **client_id,
+                });
+            write_rows(reverse_rows, reverse_mapping)
+                .await
+                .map_err(|err| OutputError {
+                    rows_written,
+                    error: err.error,
+                })?;
+            Ok(rows_written)
+        }
+    }
+}
+
+/// Writes a header-only CSV, as `write_rows` writes nothing without rows.
+async fn write_header(
+    columns: &[&str],
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let mut csv_writer = csv_async::AsyncWriterBuilder::new().create_writer(writer);
+    let result = async {
+        csv_writer.write_record(columns).await?;
+        csv_writer.flush().await.map_err(csv_async::Error::from)
+    }
+    .await;
+    result.map(|()| 0).map_err(|error| OutputError {
+        rows_written: 0,
+        error,
+    })
+}
+
+/// Bytes of rows collected before they are handed to the writer.
+const ACCOUNT_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Upper bound of the length of an account row: a 5-digit client id, three decimals
+/// of at most 31 characters, `false`, the commas and the line feed take 108 bytes.
+const MAX_ACCOUNT_ROW_LEN: usize = 128;
+
+/// Like `write_rows` for account rows, which are formatted into one buffer reused
+/// for the whole output instead of going through serde and the CSV writer, and
+/// handed to the writer in large chunks. As with `write_rows`, nothing is written
+/// without rows, not even the header.
+pub(crate) async fn write_account_logs(
+    rows: impl IntoIterator<Item = AccountLog>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let mut counting_writer = LineCountingWriter {
+        inner: writer,
+        lines: 0,
+    };
+    let mut buffer = Vec::with_capacity(ACCOUNT_BUFFER_SIZE);
+    let mut formatted = 0;
+    let result = async {
+        for row in rows {
+            if formatted == 0 {
+                buffer.extend_from_slice(ACCOUNT_COLUMNS.join(",").as_bytes());
+                buffer.push(b'\n');
+            }
+            if buffer.len() + MAX_ACCOUNT_ROW_LEN > ACCOUNT_BUFFER_SIZE {
+                counting_writer.write_all(&buffer).await?;
+                buffer.clear();
+            }
+            row.write_csv_record(&mut buffer);
+            formatted += 1;
+        }
+        counting_writer.write_all(&buffer).await?;
+        counting_writer.flush().await
+    }
+    .await;
+    result.map(|()| formatted).map_err(|err| OutputError {
+        // The first line is the header.
+        rows_written: counting_writer.lines.saturating_sub(1),
+        error: csv_async::Error::from(err),
+    })
+}
+
+async fn write_rows<T: Serialize>(
+    rows: impl IntoIterator<Item = T>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<usize, OutputError> {
+    let mut counting_writer = LineCountingWriter {
+        inner: writer,
+        lines: 0,
+    };
+    let mut serialized = 0;
+    let result = async {
+        let mut serializer =
+            csv_async::AsyncWriterBuilder::new().create_serializer(&mut counting_writer);
+        for row in rows {
+            serializer.serialize(row).await?;
+            serialized += 1;
+        }
+        serializer.flush().await.map_err(csv_async::Error::from)
+    }
+    .await;
+    result.map(|()| serialized).map_err(|error| OutputError {
+        // The first line is the header.
+        rows_written: counting_writer.lines.saturating_sub(1),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Chargeback, Deposit, Dispute, Transaction, Withdrawal};
+    use crate::accounting::{AccountView, Ledger};
+    use crate::engine::EngineError;
+    use crate::test_utils::render;
+    use rust_decimal_macros::dec;
+    use std::error::Error;
+
+    /// Accepts the given number of lines and fails every write afterwards.
+    struct FailingWriter {
+        lines_left: usize,
+        written: Vec<u8>,
+    }
+
+    impl AsyncWrite for FailingWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut accepted = 0;
+            for byte in buf {
+                if self.lines_left == 0 {
+                    break;
+                }
+                accepted += 1;
+                if *byte == b'\n' {
+                    self.lines_left -= 1;
+                }
+            }
+            if accepted == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::StorageFull.into()));
+            }
+            self.written.extend_from_slice(&buf[..accepted]);
+            Poll::Ready(Ok(accepted))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn ledger_with_accounts(accounts: u16) -> Ledger {
+        let mut ledger = Ledger::new();
+        for client_id in 1..=accounts {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    client_id as u32,
+                    dec!(1.5),
+                )))
+                .unwrap();
+        }
+        ledger
+    }
+
+    #[tokio::test]
+    async fn error_reports_rows_written() {
+        let ledger = ledger_with_accounts(5);
+        let mut writer = FailingWriter {
+            lines_left: 3,
+            written: Vec::new(),
+        };
+        let err = write_accounts(&ledger, &mut writer).await.unwrap_err();
+        assert_eq!(err.rows_written, 2);
+        assert!(err.error.is_io_error());
+        assert_eq!(
+            writer.written.iter().filter(|byte| **byte == b'\n').count(),
+            3
+        );
+
+        let err = EngineError::from(err);
+        assert_eq!(err.code(), "output");
+        let csv_error = err
+            .source()
+            .and_then(|output_error| output_error.source())
+            .and_then(|csv_error| csv_error.downcast_ref::<csv_async::Error>())
+            .unwrap();
+        assert!(csv_error.is_io_error());
+    }
+
+    #[tokio::test]
+    async fn flushes_all_rows() {
+        let ledger = ledger_with_accounts(2000);
+        let mut output = Vec::new();
+        let rows = write_accounts(&ledger, &mut output).await.unwrap();
+        assert_eq!(rows, 2000);
+        assert_eq!(output.len(), render(&ledger).len());
+    }
+
+    #[tokio::test]
+    async fn accounts_are_written_in_client_order() {
+        let mut ledger = Ledger::new();
+        for client_id in (1..=300u16).rev() {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    client_id as u32,
+                    dec!(1.5),
+                )))
+                .unwrap();
+        }
+        let mut fast = Vec::new();
+        write_accounts(&ledger, &mut fast).await.unwrap();
+        let mut serde = Vec::new();
+        write_accounts_serde(&ledger, &mut serde).await.unwrap();
+        let mut extended = Vec::new();
+        write_accounts_extended(&ledger, &mut extended)
+            .await
+            .unwrap();
+        let clients = |output: &[u8]| {
+            String::from_utf8(output.to_vec())
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').next().unwrap().parse::<ClientId>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let expected: Vec<ClientId> = (1..=300).collect();
+        assert_eq!(clients(&fast), expected);
+        assert_eq!(clients(&serde), expected);
+        assert_eq!(clients(&extended), expected);
+        assert_eq!(fast, serde);
+    }
+
+    #[tokio::test]
+    async fn crlf_output_ends_every_line_with_crlf() {
+        let ledger = ledger_with_accounts(2000);
+        let mut lf = LineEndingWriter::new(Vec::new(), LineEnding::Lf);
+        write_accounts(&ledger, &mut lf).await.unwrap();
+        let mut crlf = LineEndingWriter::new(Vec::new(), LineEnding::Crlf);
+        let rows = write_accounts(&ledger, &mut crlf).await.unwrap();
+        assert_eq!(rows, 2000);
+        assert_eq!(
+            String::from_utf8(crlf.inner).unwrap(),
+            String::from_utf8(lf.inner).unwrap().replace('\n', "\r\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn fast_rows_match_serde_rows() {
+        let mut negative_zero = Decimal::ZERO;
+        negative_zero.set_sign_negative(true);
+        let values = [
+            Decimal::ZERO,
+            negative_zero,
+            dec!(0.0000),
+            dec!(-12.5),
+            dec!(1.2345),
+            dec!(7.1),
+            Decimal::MAX,
+            Decimal::MIN,
+            Decimal::new(-1, 28),
+        ];
+        // Long rows spanning several buffers
+        let views: Vec<_> = (0..12_000u32)
+            .map(|index| {
+                let value = |offset| values[(index as usize + offset) % values.len()];
+                AccountView {
+                    client_id: ClientId::MAX - index as ClientId,
+                    available: value(0),
+                    held: value(3),
+                    total: value(8),
+                    locked: index % 2 == 0,
+                    version: 0,
+                    chargebacks: 0,
+                    open_disputes: 0,
+                    disputes_opened: 0,
+                    disputes_resolved: 0,
+                    disputes_charged_back: 0,
+                }
+            })
+            .collect();
+        let (mut fast, mut serde) = (Vec::new(), Vec::new());
+        let rows = write_account_logs(views.iter().map(AccountLog::from), &mut fast)
+            .await
+            .unwrap();
+        write_rows(views.iter().map(AccountLog::from), &mut serde)
+            .await
+            .unwrap();
+        assert_eq!(rows, 12_000);
+        assert!(fast.len() > 2 * ACCOUNT_BUFFER_SIZE);
+        assert_eq!(
+            String::from_utf8(fast).unwrap(),
+            String::from_utf8(serde).unwrap()
+        );
+
+        let mut empty = Vec::new();
+        assert_eq!(write_account_logs([], &mut empty).await.unwrap(), 0);
+        assert!(empty.is_empty());
+    }
+
+    #[tokio::test]
+    async fn extended_output_includes_overdraft_limit() {
+        let mut ledger = ledger_with_accounts(1);
+        ledger.set_overdraft_limit(1, dec!(-25));
+        let mut output = Vec::new();
+        write_accounts_extended(&ledger, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,overdraft_limit,deposited,withdrawn,charged_back,\
+             open_disputes,created_by_rejection\n\
+             1,1.5,0,1.5,false,-25,1.5,0,0,0,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn enriched_output_appends_flags_and_notes() {
+        let ledger = ledger_with_accounts(3);
+        let enrichment = Enrichment::read(
+            "client,flags,note\n\
+             1,vip;under_review,\"called, awaiting documents\"\n\
+             3,collections,\n\
+             9,vip,no account\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        write_accounts_enriched(&ledger, &enrichment, true, &mut output)
+            .await
+            .unwrap();
+        let mut lines: Vec<_> = std::str::from_utf8(&output).unwrap().lines().collect();
+        lines[1..].sort_unstable();
+        assert_eq!(
+            lines,
+            [
+                "client,available,held,total,locked,overdraft_limit,deposited,withdrawn,\
+                 charged_back,open_disputes,created_by_rejection,flags,note",
+                "1,1.5,0,1.5,false,0,1.5,0,0,0,false,vip;under_review,\"called, awaiting documents\"",
+                "2,1.5,0,1.5,false,0,1.5,0,0,0,false,,",
+                "3,1.5,0,1.5,false,0,1.5,0,0,0,false,collections,",
+            ]
+        );
+
+        let mut output = Vec::new();
+        let rows = write_accounts_enriched(
+            &ledger,
+            &enrichment.clone().only_flag("under_review"),
+            true,
+            &mut output,
+        )
+        .await
+        .unwrap();
+        assert_eq!(rows, 1);
+        assert!(String::from_utf8(output).unwrap().ends_with(
+            "\n1,1.5,0,1.5,false,0,1.5,0,0,0,false,vip;under_review,\"called, awaiting documents\"\n"
+        ));
+
+        // The default output stays the same with enrichment loaded.
+        let (mut plain, mut enriched) = (Vec::new(), Vec::new());
+        write_accounts(&ledger, &mut plain).await.unwrap();
+        write_accounts_enriched(&ledger, &enrichment, false, &mut enriched)
+            .await
+            .unwrap();
+        assert_eq!(enriched, plain);
+    }
+
+    /// Clients 1 to 6, with 2 and 5 locked after a chargeback and 3 overdrawn.
+    fn ledger_for_selection() -> Ledger {
+        let mut ledger = ledger_with_accounts(6);
+        ledger.set_overdraft_limit(3, dec!(-10));
+        for tx in [
+            Transaction::Withdrawal(Withdrawal::new(3, 7, dec!(4))),
+            Transaction::Withdrawal(Withdrawal::new(2, 8, dec!(1))),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+            Transaction::Dispute(Dispute::new(5, 5)),
+            Transaction::Chargeback(Chargeback::new(5, 5)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        ledger
+    }
+
+    async fn selected(ledger: &Ledger, selection: OutputSelection) -> String {
+        let mut output = Vec::new();
+        let rows = write_accounts_selected(ledger, &selection, false, &mut output)
+            .await
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(rows, output.lines().count() - 1);
+        output
+    }
+
+    #[tokio::test]
+    async fn selection_picks_accounts_in_client_order() {
+        let ledger = ledger_for_selection();
+        assert!(!OutputSelection::default().is_applied());
+        assert_eq!(
+            selected(&ledger, OutputSelection::default()).await,
+            String::from_utf8(render(&ledger)).unwrap()
+        );
+        let header = "client,available,held,total,locked\n";
+        let cases = [
+            (
+                OutputSelection {
+                    client_range: Some(2..=4),
+                    ..OutputSelection::default()
+                },
+                "2,-1.0,0.0,-1.0,true\n3,-2.5,0,-2.5,false\n4,1.5,0,1.5,false\n",
+            ),
+            (
+                OutputSelection {
+                    limit: Some(2),
+                    ..OutputSelection::default()
+                },
+                "1,1.5,0,1.5,false\n2,-1.0,0.0,-1.0,true\n",
+            ),
+            (
+                OutputSelection {
+                    only_locked: true,
+                    ..OutputSelection::default()
+                },
+                "2,-1.0,0.0,-1.0,true\n5,0.0,0.0,0.0,true\n",
+            ),
+            (
+                OutputSelection {
+                    only_negative: true,
+                    ..OutputSelection::default()
+                },
+                "2,-1.0,0.0,-1.0,true\n3,-2.5,0,-2.5,false\n",
+            ),
+            (
+                OutputSelection {
+                    client_range: Some(3..=6),
+                    limit: Some(1),
+                    only_locked: true,
+                    only_negative: false,
+                },
+                "5,0.0,0.0,0.0,true\n",
+            ),
+            (
+                OutputSelection {
+                    client_range: Some(3..=6),
+                    limit: Some(1),
+                    only_locked: true,
+                    only_negative: true,
+                },
+                "",
+            ),
+        ];
+        for (selection, rows) in cases {
+            assert!(selection.is_applied());
+            assert_eq!(
+                selected(&ledger, selection.clone()).await,
+                format!("{}{}", header, rows),
+                "{:?}",
+                selection
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_selection_writes_the_header() {
+        let ledger = ledger_for_selection();
+        let selection = OutputSelection {
+            client_range: Some(100..=200),
+            ..OutputSelection::default()
+        };
+        assert_eq!(
+            selected(&ledger, selection.clone()).await,
+            "client,available,held,total,locked\n"
+        );
+        assert_eq!(
+            selected(&Ledger::new(), OutputSelection::default()).await,
+            "client,available,held,total,locked\n"
+        );
+
+        // The header-only extended output has the columns of the extended rows.
+        let mut empty = Vec::new();
+        write_accounts_selected(&ledger, &selection, true, &mut empty)
+            .await
+            .unwrap();
+        let mut full = Vec::new();
+        write_accounts_selected(&ledger, &OutputSelection::default(), true, &mut full)
+            .await
+            .unwrap();
+        assert!(full.starts_with(&empty));
+        assert_eq!(empty.iter().filter(|byte| **byte == b'\n').count(), 1);
+    }
+
+    #[tokio::test]
+    async fn tx_states_export_every_recorded_transaction() {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(2, 3, dec!(4.25))),
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(1))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Dispute(Dispute::new(2, 3).with_reason("10.4".parse().unwrap())),
+            Transaction::Chargeback(Chargeback::new(2, 3)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let mut output = Vec::new();
+        let rows = output_tx_states(&ledger, &mut output).await.unwrap();
+        assert_eq!(rows, 4);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,client,kind,amount,state,reason\n\
+             1,1,deposit,10,resolved,\n\
+             2,1,deposit,5,disputed,\n\
+             3,2,deposit,4.25,charged_back,10.4\n\
+             4,1,withdrawal,1,resolved,\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn watchlist_lists_tightest_breached_threshold() {
+        let mut ledger = Ledger::builder().overdraft_limit(dec!(-100)).build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(5))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(50))),
+            Transaction::Deposit(Deposit::new(3, 3, dec!(1))),
+            Transaction::Withdrawal(Withdrawal::new(3, 4, dec!(31))),
+            Transaction::Deposit(Deposit::new(4, 5, dec!(20))),
+            Transaction::Withdrawal(Withdrawal::new(4, 6, dec!(24))),
+            Transaction::Deposit(Deposit::new(5, 7, dec!(10))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let mut output = Vec::new();
+        let rows = output_watchlist(&ledger, &mut output, &[dec!(10), dec!(0), dec!(-25)])
+            .await
+            .unwrap();
+        assert_eq!(rows, 3);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,locked,breached_threshold\n\
+             3,-30,0,false,-25\n\
+             4,-4,0,false,0\n\
+             1,5,0,false,10\n"
+        );
+
+        let mut output = Vec::new();
+        assert_eq!(
+            output_watchlist(&ledger, &mut output, &[]).await.unwrap(),
+            0
+        );
+    }
+
+    async fn mapped(ledger: &Ledger, mapping: IdMapping<'_>) -> String {
+        let mut output = Vec::new();
+        output_accounts_with_mapping(ledger, &mut output, mapping)
+            .await
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pseudonyms_are_stable_per_key() {
+        let ledger = ledger_with_accounts(3);
+        let first = mapped(&ledger, IdMapping::Pseudonymize { key: [7; 32] }).await;
+        let second = mapped(&ledger, IdMapping::Pseudonymize { key: [7; 32] }).await;
+        let other_key = mapped(&ledger, IdMapping::Pseudonymize { key: [8; 32] }).await;
+        assert_eq!(first, second);
+        assert_ne!(first, other_key);
+
+        let tokens: Vec<_> = first
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        let mut expected: Vec<_> = (1..=3)
+            .map(|client_id| pseudonymize(&[7; 32], client_id))
+            .collect();
+        expected.sort();
+        assert_eq!(tokens, expected);
+        assert!(tokens
+            .iter()
+            .all(|token| token.len() == 16 && token.bytes().all(|byte| byte.is_ascii_hexdigit())));
+        assert!(first.starts_with("client,available,held,total,locked\n"));
+    }
+
+    #[tokio::test]
+    async fn sequential_reverse_mapping_round_trips() {
+        let mut ledger = Ledger::new();
+        for client_id in [40, 7, 65535] {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    client_id as u32,
+                    dec!(2.5),
+                )))
+                .unwrap();
+        }
+        let mut reverse_mapping = Vec::new();
+        let output = mapped(
+            &ledger,
+            IdMapping::Sequential {
+                reverse_mapping: &mut reverse_mapping,
+            },
+        )
+        .await;
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n\
+             1,2.5,0,2.5,false\n\
+             2,2.5,0,2.5,false\n\
+             3,2.5,0,2.5,false\n"
+        );
+
+        let mut reader = csv::Reader::from_reader(reverse_mapping.as_slice());
+        let originals: std::collections::HashMap<u32, ClientId> = reader
+            .deserialize::<(u32, ClientId)>()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(originals.len(), 3);
+        let restored = output
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let (client, rest) = line.split_once(',').unwrap();
+                format!("{},{}", originals[&client.parse::<u32>().unwrap()], rest)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            restored,
+            [
+                "7,2.5,0,2.5,false",
+                "40,2.5,0,2.5,false",
+                "65535,2.5,0,2.5,false"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_report_is_sorted_by_client() {
+        let shared = crate::accounting::shared::SharedLedger::new(Ledger::new());
+        for client_id in [3, 1, 2] {
+            shared
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    client_id as u32,
+                    dec!(1.5),
+                )))
+                .unwrap();
+        }
+        let snapshot = shared.snapshot_accounts();
+        let mut output = Vec::new();
+        assert_eq!(write_snapshot(&snapshot, &mut output).await.unwrap(), 3);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,1.5,0,1.5,false\n\
+             2,1.5,0,1.5,false\n\
+             3,1.5,0,1.5,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn crash_before_rename_keeps_previous_report() {
+        let dir =
+            std::env::temp_dir().join(format!("payments_engine_output_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("accounts.csv");
+        std::fs::write(&target, "previous report\n").unwrap();
+        let ledger = ledger_with_accounts(3);
+        let mut report = Vec::new();
+        write_accounts(&ledger, &mut report).await.unwrap();
+
+        let mut staging = Staging::new();
+        assert_eq!(
+            stage_accounts(ledger.view(), &mut staging, &target)
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(std::fs::read(Staging::temp_path(&target)).unwrap(), report);
+        // Dropped where the crash would stop the process, after serializing
+        drop(staging);
+        assert_eq!(
+            std::fs::read_to_string(&target).unwrap(),
+            "previous report\n"
+        );
+        assert!(!Staging::temp_path(&target).exists());
+
+        assert_eq!(
+            crate::output_accounts_atomic(&ledger, &target)
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(std::fs::read(&target).unwrap(), report);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+//! Account report as Parquet, keeping amounts exact as `Decimal128(20, 4)`.
+use crate::accounting::view::LedgerView;
+use crate::accounting::UserAccount;
+use crate::core_types::ClientId;
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, UInt16Array};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use rust_decimal::Decimal;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::sync::Arc;
+
+const PRECISION: u8 = 20;
+const SCALE: i8 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetOptions {
+    /// Maximum number of accounts per row group.
+    pub row_group_size: usize,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            row_group_size: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParquetOutputError {
+    /// An amount has more than four decimal places or twenty digits.
+    AmountNotRepresentable {
+        client_id: ClientId,
+        column: &'static str,
+        amount: Decimal,
+    },
+    Parquet(ParquetError),
+}
+
+impl Display for ParquetOutputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParquetOutputError::AmountNotRepresentable {
+                client_id,
+                column,
+                amount,
+            } => write!(
+                f,
+                "{} {} of client {} does not fit Decimal128({}, {})",
+                column, amount, client_id, PRECISION, SCALE
+            ),
+            ParquetOutputError::Parquet(err) => write!(f, "failed to write Parquet: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ParquetOutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParquetOutputError::AmountNotRepresentable { .. } => None,
+            ParquetOutputError::Parquet(err) => Some(err),
+        }
+    }
+}
+
+impl From<ParquetError> for ParquetOutputError {
+    fn from(err: ParquetError) -> Self {
+        ParquetOutputError::Parquet(err)
+    }
+}
+
+impl From<arrow_schema::ArrowError> for ParquetOutputError {
+    fn from(err: arrow_schema::ArrowError) -> Self {
+        ParquetOutputError::Parquet(err.into())
+    }
+}
+
+/// Writes one row per account sorted by client id, returning the number of rows.
+pub fn output_accounts_parquet<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl Write + Send,
+) -> Result<usize, ParquetOutputError> {
+    output_accounts_parquet_with_options(ledger, writer, &ParquetOptions::default())
+}
+
+pub fn output_accounts_parquet_with_options<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl Write + Send,
+    options: &ParquetOptions,
+) -> Result<usize, ParquetOutputError> {
+    let ledger: LedgerView = ledger.into();
+    let amount_field = |name| Field::new(name, DataType::Decimal128(PRECISION, SCALE), false);
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        amount_field("available"),
+        amount_field("held"),
+        amount_field("total"),
+        Field::new("locked", DataType::Boolean, false),
+    ]));
+    let row_group_size = options.row_group_size.max(1);
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(row_group_size)
+        .build();
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+
+    let accounts = ledger.accounts_sorted();
+    for chunk in accounts.chunks(row_group_size) {
+        let amounts = |column, amount: fn(&UserAccount) -> Decimal| {
+            let values = chunk
+                .iter()
+                .map(|user_account| {
+                    to_decimal128(user_account.client_id(), column, amount(user_account))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let array = Decimal128Array::from(values).with_precision_and_scale(PRECISION, SCALE)?;
+            Ok::<ArrayRef, ParquetOutputError>(Arc::new(array))
+        };
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(UInt16Array::from_iter_values(
+                    chunk.iter().map(|user_account| user_account.client_id()),
+                )),
+                amounts("available", UserAccount::available)?,
+                amounts("held", UserAccount::held)?,
+                amounts("total", UserAccount::total)?,
+                Arc::new(BooleanArray::from_iter(
+                    chunk.iter().map(|user_account| Some(user_account.locked())),
+                )),
+            ],
+        )?;
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+    Ok(accounts.len())
+}
+
+/// Unscaled value of `amount` at scale 4, failing instead of rounding.
+fn to_decimal128(
+    client_id: ClientId,
+    column: &'static str,
+    amount: Decimal,
+) -> Result<i128, ParquetOutputError> {
+    let mut scaled = amount;
+    scaled.rescale(SCALE as u32);
+    if scaled != amount
+        || scaled.scale() != SCALE as u32
+        || scaled.mantissa().abs() >= 10i128.pow(PRECISION.into())
+    {
+        return Err(ParquetOutputError::AmountNotRepresentable {
+            client_id,
+            column,
+            amount,
+        });
+    }
+    Ok(scaled.mantissa())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Chargeback, Deposit, Dispute, Transaction, Withdrawal};
+    use crate::accounting::Ledger;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Decimal128Type, UInt16Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use rust_decimal_macros::dec;
+
+    fn execute(ledger: &mut Ledger, txs: Vec<Transaction>) {
+        for tx in txs {
+            ledger.execute(&tx).unwrap();
+        }
+    }
+
+    #[test]
+    fn round_trips_exact_amounts() {
+        let mut ledger = Ledger::new();
+        execute(
+            &mut ledger,
+            vec![
+                Transaction::Deposit(Deposit::new(3, 1, dec!(1234.5678))),
+                Transaction::Deposit(Deposit::new(1, 2, dec!(0.0001))),
+                Transaction::Deposit(Deposit::new(2, 3, dec!(99999999.9999))),
+                Transaction::Withdrawal(Withdrawal::new(3, 4, dec!(0.5))),
+                Transaction::Deposit(Deposit::new(2, 5, dec!(7))),
+                Transaction::Dispute(Dispute::new(2, 5)),
+                Transaction::Deposit(Deposit::new(4, 6, dec!(2.5))),
+                Transaction::Dispute(Dispute::new(4, 6)),
+                Transaction::Chargeback(Chargeback::new(4, 6)),
+            ],
+        );
+        let path = std::env::temp_dir().join(format!(
+            "payments_engine_accounts_{}.parquet",
+            std::process::id()
+        ));
+        let rows = output_accounts_parquet_with_options(
+            &ledger,
+            std::fs::File::create(&path).unwrap(),
+            &ParquetOptions { row_group_size: 3 },
+        )
+        .unwrap();
+        assert_eq!(rows, 4);
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let mut client_ids = Vec::new();
+        for batch in reader.build().unwrap() {
+            let batch = batch.unwrap();
+            let column = |index: usize| batch.column(index).as_primitive::<Decimal128Type>();
+            for row in 0..batch.num_rows() {
+                let client_id = batch.column(0).as_primitive::<UInt16Type>().value(row);
+                let user_account = ledger.account(client_id).unwrap();
+                let amount = |index| Decimal::from_i128_with_scale(column(index).value(row), 4);
+                assert_eq!(amount(1), user_account.available());
+                assert_eq!(amount(2), user_account.held());
+                assert_eq!(amount(3), user_account.total());
+                assert_eq!(
+                    batch.column(4).as_boolean().value(row),
+                    user_account.locked()
+                );
+                client_ids.push(client_id);
+            }
+        }
+        assert_eq!(client_ids, vec![1, 2, 3, 4]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_amounts_which_would_be_rounded() {
+        let mut ledger = Ledger::new();
+        execute(
+            &mut ledger,
+            vec![Transaction::Deposit(Deposit::new(1, 1, dec!(0.00001)))],
+        );
+        let err = output_accounts_parquet(&ledger, Vec::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            ParquetOutputError::AmountNotRepresentable {
+                client_id: 1,
+                column: "available",
+                ..
+            }
+        ));
+    }
+}
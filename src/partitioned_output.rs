@@ -0,0 +1,423 @@
+//! Account report split into one CSV file per client partition.
+//!
+//! Every partition is first written to a hidden temporary file and only renamed to its final
+//! name once all partitions were written, so a failed run leaves no partial report behind.
+use crate::accounting::view::LedgerView;
+use crate::accounting::UserAccount;
+use crate::core_types::ClientId;
+use crate::run_id::RunId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Maps a client to the key of its partition, which becomes part of the file name.
+pub trait Partitioner {
+    fn partition(&self, client_id: ClientId) -> String;
+}
+
+impl<F: Fn(ClientId) -> String> Partitioner for F {
+    fn partition(&self, client_id: ClientId) -> String {
+        self(client_id)
+    }
+}
+
+/// Consecutive ranges of `bucket_size` client ids, keyed `<first>-<last>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangePartitioner {
+    pub bucket_size: u32,
+}
+
+impl Partitioner for RangePartitioner {
+    fn partition(&self, client_id: ClientId) -> String {
+        let bucket_size = self.bucket_size.max(1);
+        let first = u32::from(client_id) / bucket_size * bucket_size;
+        let last = (first + (bucket_size - 1)).min(u32::from(ClientId::MAX));
+        format!("{}-{}", first, last)
+    }
+}
+
+/// Client id modulo `buckets`, keyed by the remainder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuloPartitioner {
+    pub buckets: u32,
+}
+
+impl Partitioner for ModuloPartitioner {
+    fn partition(&self, client_id: ClientId) -> String {
+        (u32::from(client_id) % self.buckets.max(1)).to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PartitionFile {
+    pub partition: String,
+    pub file: String,
+    pub rows: usize,
+    /// CRC-32 (IEEE) of the file contents as eight lowercase hex digits.
+    pub crc32: String,
+}
+
+/// Files written by `output_accounts_partitioned`, sorted by partition key.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct PartitionManifest {
+    pub files: Vec<PartitionFile>,
+    /// Run which wrote the files, left out of `manifest.json` when not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<RunId>,
+}
+
+impl PartitionManifest {
+    pub fn rows(&self) -> usize {
+        self.files.iter().map(|file| file.rows).sum()
+    }
+
+    /// Writes `manifest.json` into `dir`, which should be the last step of the export so that
+    /// its presence marks a complete one.
+    pub fn write(&self, dir: &Path) -> Result<(), PartitionedOutputError> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+        let temp_path = temp_path(&path);
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|err| PartitionedOutputError::Io(err.into()))?;
+        writer.write_all(b"\n")?;
+        writer.into_inner().map_err(|err| err.into_error())?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum PartitionedOutputError {
+    /// The partitioner returned a key that is empty or not usable in a file name.
+    InvalidPartitionKey {
+        client_id: ClientId,
+        key: String,
+    },
+    Csv(csv::Error),
+    Io(std::io::Error),
+}
+
+impl Display for PartitionedOutputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PartitionedOutputError::InvalidPartitionKey { client_id, key } => write!(
+                f,
+                "invalid partition key {:?} for client {}",
+                key, client_id
+            ),
+            PartitionedOutputError::Csv(err) => write!(f, "failed to write partition: {}", err),
+            PartitionedOutputError::Io(err) => write!(f, "failed to write partition: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PartitionedOutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PartitionedOutputError::InvalidPartitionKey { .. } => None,
+            PartitionedOutputError::Csv(err) => Some(err),
+            PartitionedOutputError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<csv::Error> for PartitionedOutputError {
+    fn from(err: csv::Error) -> Self {
+        PartitionedOutputError::Csv(err)
+    }
+}
+
+impl From<std::io::Error> for PartitionedOutputError {
+    fn from(err: std::io::Error) -> Self {
+        PartitionedOutputError::Io(err)
+    }
+}
+
+/// Writes `accounts-<key>.csv` into `dir` for every partition key, each with its own header
+/// and sorted by client id. The directory is created if missing; existing files of the same
+/// names are replaced.
+pub fn output_accounts_partitioned<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    dir: &Path,
+    partitioner: impl Partitioner,
+) -> Result<PartitionManifest, PartitionedOutputError> {
+    write_partitions(ledger.into(), dir, &partitioner, &mut |path| {
+        Ok(Box::new(File::create(path)?))
+    })
+}
+
+fn write_partitions(
+    ledger: LedgerView<'_>,
+    dir: &Path,
+    partitioner: &dyn Partitioner,
+    create: &mut dyn FnMut(&Path) -> std::io::Result<Box<dyn Write>>,
+) -> Result<PartitionManifest, PartitionedOutputError> {
+    let mut partitions = BTreeMap::<String, Vec<&UserAccount>>::new();
+    for (&client_id, user_account) in ledger.accounts_iter() {
+        let key = partitioner.partition(client_id);
+        if !is_valid_key(&key) {
+            return Err(PartitionedOutputError::InvalidPartitionKey { client_id, key });
+        }
+        partitions.entry(key).or_default().push(user_account);
+    }
+    std::fs::create_dir_all(dir)?;
+
+    let mut manifest = PartitionManifest::default();
+    let mut staged = Vec::with_capacity(partitions.len());
+    for (partition, mut accounts) in partitions {
+        accounts.sort_unstable_by_key(|user_account| user_account.client_id());
+        let file = format!("accounts-{}.csv", partition);
+        let path = dir.join(&file);
+        let temp_path = temp_path(&path);
+
+        let mut writer = csv::Writer::from_writer(Crc32Writer {
+            inner: create(&temp_path)?,
+            crc: Crc32::new(),
+        });
+        for user_account in &accounts {
+            writer.serialize(ledger.account_log(user_account))?;
+        }
+        let mut checksummed = writer.into_inner().map_err(|err| err.into_error())?;
+        checksummed.inner.flush()?;
+
+        manifest.files.push(PartitionFile {
+            partition,
+            file,
+            rows: accounts.len(),
+            crc32: format!("{:08x}", checksummed.crc.finish()),
+        });
+        staged.push((temp_path, path));
+    }
+    for (temp_path, path) in staged {
+        std::fs::rename(temp_path, path)?;
+    }
+    Ok(manifest)
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+        && key != "."
+        && key != ".."
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().expect("joined from a file name");
+    path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()))
+}
+
+/// Checksums every byte accepted by the inner writer.
+struct Crc32Writer<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bitwise CRC-32 with the IEEE polynomial, as used by gzip and zip. Reports are small
+/// enough that a lookup table is not worth it.
+struct Crc32(u32);
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32(!0)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u32::from(*byte);
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Transaction};
+    use crate::accounting::Ledger;
+    use rust_decimal::Decimal;
+
+    fn ledger_with_clients(client_ids: impl IntoIterator<Item = ClientId>) -> Ledger {
+        let mut ledger = Ledger::default();
+        for (tx_id, client_id) in client_ids.into_iter().enumerate() {
+            let deposit =
+                Transaction::Deposit(Deposit::new(client_id, tx_id as u32, Decimal::new(15, 1)));
+            ledger.execute(&deposit).unwrap();
+        }
+        ledger
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("payments_engine_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn partitioner_keys() {
+        let range = RangePartitioner { bucket_size: 100 };
+        assert_eq!(range.partition(0), "0-99");
+        assert_eq!(range.partition(250), "200-299");
+        assert_eq!(range.partition(ClientId::MAX), "65500-65535");
+        let modulo = ModuloPartitioner { buckets: 16 };
+        assert_eq!(modulo.partition(35), "3");
+    }
+
+    #[test]
+    fn partitioned_output_with_manifest() {
+        let dir = scratch_dir("partitioned_output");
+        let ledger = ledger_with_clients(1..=10);
+        let manifest =
+            output_accounts_partitioned(&ledger, &dir, ModuloPartitioner { buckets: 3 }).unwrap();
+        manifest.write(&dir).unwrap();
+
+        assert_eq!(manifest.rows(), ledger.accounts_iter().count());
+        let partitions = manifest
+            .files
+            .iter()
+            .map(|file| (file.partition.as_str(), file.rows))
+            .collect::<Vec<_>>();
+        assert_eq!(partitions, [("0", 3), ("1", 4), ("2", 3)]);
+        for file in &manifest.files {
+            let contents = std::fs::read(dir.join(&file.file)).unwrap();
+            let mut crc = Crc32::new();
+            crc.update(&contents);
+            assert_eq!(file.crc32, format!("{:08x}", crc.finish()));
+            let mut reader = csv::Reader::from_reader(contents.as_slice());
+            assert_eq!(
+                reader.headers().unwrap(),
+                vec!["client", "available", "held", "total", "locked"]
+            );
+            assert_eq!(reader.records().count(), file.rows);
+        }
+        let written = std::fs::read_to_string(dir.join(MANIFEST_FILE_NAME)).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(written["files"][1]["file"], "accounts-1.csv");
+        assert_eq!(
+            written["files"][1]["crc32"],
+            manifest.files[1].crc32.as_str()
+        );
+        assert_eq!(
+            file_names(&dir),
+            [
+                "accounts-0.csv",
+                "accounts-1.csv",
+                "accounts-2.csv",
+                "manifest.json"
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Accepts `remaining` bytes, then fails like a full disk.
+    struct FailingWriter {
+        inner: File,
+        remaining: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(std::io::Error::other("disk full"));
+            }
+            let written = self.inner.write(&buf[..buf.len().min(self.remaining)])?;
+            self.remaining -= written;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn failure_mid_partition_leaves_only_temp_files() {
+        let dir = scratch_dir("partitioned_output_failure");
+        let ledger = ledger_with_clients(1..=10);
+        let mut created = 0;
+        let result = write_partitions(
+            ledger.view(),
+            &dir,
+            &ModuloPartitioner { buckets: 3 },
+            &mut |path| {
+                created += 1;
+                let inner = File::create(path)?;
+                Ok(if created == 2 {
+                    Box::new(FailingWriter {
+                        inner,
+                        remaining: 50,
+                    })
+                } else {
+                    Box::new(inner)
+                })
+            },
+        );
+
+        match result {
+            Err(PartitionedOutputError::Io(err)) => assert_eq!(err.to_string(), "disk full"),
+            other => panic!("expected a write failure, got {:?}", other),
+        }
+        assert_eq!(created, 2);
+        assert_eq!(
+            file_names(&dir),
+            [".accounts-0.csv.tmp", ".accounts-1.csv.tmp"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_partition_key_is_rejected_before_writing() {
+        let dir = scratch_dir("partitioned_output_invalid_key");
+        let ledger = ledger_with_clients([7]);
+        let err = output_accounts_partitioned(&ledger, &dir, |_client_id| "../x".to_string())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            PartitionedOutputError::InvalidPartitionKey { client_id: 7, .. }
+        ));
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,1987 @@
+//! Composable processing of a transaction source into a ledger.
+//!
+//! Stages are generic parameters defaulting to `()`, which implements every stage
+//! as a no-op, so absent stages cost nothing.
+//!
+//! Rows are executed one at a time in the order the source returns them, and every
+//! source of the crate returns them in input order, including the channel fed by the
+//! parallel reader. A run over the same input thus gives the same ledger whatever
+//! the channel capacity, runtime flavor or timing of the reader and the consumer.
+use crate::accounting::config::LedgerConfig;
+use crate::accounting::postings::DerivedOutcome;
+use crate::accounting::transactions::{Transaction, TransactionLogError, TxTypeTag};
+use crate::accounting::{ExecutableTransaction, Ledger, TxError, TxOutcome};
+use crate::clock::Clock;
+use crate::core_types::{ClientId, TxId};
+use crate::distribution::DistributionTracker;
+use crate::engine::{RowReader, RunStats};
+use crate::field_error::{diagnose_line, FieldError, DEFAULT_MAX_VALUE_CHARS};
+use crate::number_locale::{AmountParsing, NumberLocale};
+use crate::run_id::RunId;
+use crate::sink::{deliver, Delivery, SinkError, SinkFailurePolicy};
+use crate::tag_rules::TagRules;
+use crate::top_k::TopKTracker;
+use csv::StringRecord;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::ops::RangeInclusive;
+
+/// A row produced by a `TransactionSource`.
+pub struct SourceRow<'a> {
+    /// 1-based line number within the input, for sources reading lines.
+    pub line: Option<u64>,
+    /// The row as read with the header of its input, for the quarantine.
+    pub raw: Option<RawRow<'a>>,
+    pub tx: Result<Transaction, TransactionLogError>,
+    /// The value at fault, for sources which can tell it for a malformed row.
+    pub field: Option<FieldError>,
+}
+
+pub struct RawRow<'a> {
+    pub headers: &'a StringRecord,
+    /// The row without its line terminator.
+    pub bytes: &'a [u8],
+}
+
+pub trait TransactionSource {
+    /// Returns the next row in input order, or `None` at the end of the input.
+    fn next_row(&mut self) -> std::io::Result<Option<SourceRow<'_>>>;
+}
+
+/// Rows of a CSV input with a header, see `PaymentsEngine::run`.
+pub struct CsvSource<R> {
+    rows: RowReader<R>,
+}
+
+impl<R: BufRead> CsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            rows: RowReader::new(reader),
+        }
+    }
+
+    /// Reads amounts with the separators of `number_locale`.
+    pub fn with_number_locale(self, number_locale: NumberLocale) -> Self {
+        Self {
+            rows: self.rows.with_number_locale(number_locale),
+        }
+    }
+
+    /// Reads amounts decorated as `amount_parsing` allows. Malformed amounts are
+    /// reported as they are in the input.
+    pub fn with_amount_parsing(self, amount_parsing: AmountParsing) -> Self {
+        Self {
+            rows: self.rows.with_amount_parsing(amount_parsing),
+        }
+    }
+}
+
+impl<R: BufRead> TransactionSource for CsvSource<R> {
+    fn next_row(&mut self) -> std::io::Result<Option<SourceRow<'_>>> {
+        Ok(self.rows.next_row()?.map(|row| {
+            let tx = row.log.and_then(Transaction::try_from);
+            let field = tx
+                .as_ref()
+                .err()
+                .and_then(|error| diagnose_line(row.line, row.raw, row.headers, error));
+            SourceRow {
+                line: Some(row.line),
+                raw: Some(RawRow {
+                    headers: row.headers,
+                    bytes: row.raw,
+                }),
+                tx,
+                field,
+            }
+        }))
+    }
+}
+
+impl TransactionSource for std::vec::IntoIter<Transaction> {
+    fn next_row(&mut self) -> std::io::Result<Option<SourceRow<'_>>> {
+        Ok(self.next().map(|tx| SourceRow {
+            line: None,
+            raw: None,
+            tx: Ok(tx),
+            field: None,
+        }))
+    }
+}
+
+/// Transactions parsed by an ingestion task. Blocks the thread while waiting, so it
+/// must not be used on the async runtime.
+#[cfg(feature = "io")]
+impl TransactionSource for tokio::sync::mpsc::Receiver<Transaction> {
+    fn next_row(&mut self) -> std::io::Result<Option<SourceRow<'_>>> {
+        #[cfg(test)]
+        crate::test_utils::consumer_jitter();
+        Ok(self.blocking_recv().map(|tx| SourceRow {
+            line: None,
+            raw: None,
+            tx: Ok(tx),
+            field: None,
+        }))
+    }
+}
+
+/// Decides whether a parsed transaction is executed.
+pub trait TxFilter {
+    fn accept(&mut self, tx: &Transaction) -> bool;
+}
+
+impl TxFilter for () {
+    fn accept(&mut self, _tx: &Transaction) -> bool {
+        true
+    }
+}
+
+impl<F: FnMut(&Transaction) -> bool> TxFilter for F {
+    fn accept(&mut self, tx: &Transaction) -> bool {
+        self(tx)
+    }
+}
+
+/// Both filters must accept.
+impl<A: TxFilter, B: TxFilter> TxFilter for (A, B) {
+    fn accept(&mut self, tx: &Transaction) -> bool {
+        self.0.accept(tx) && self.1.accept(tx)
+    }
+}
+
+/// `None` accepts every transaction.
+impl<F: TxFilter> TxFilter for Option<F> {
+    fn accept(&mut self, tx: &Transaction) -> bool {
+        match self {
+            Some(filter) => filter.accept(tx),
+            None => true,
+        }
+    }
+}
+
+/// Selects the part of a history to replay. A transaction is executed when it matches
+/// every dimension which is set.
+///
+/// Filtering does not follow references between transactions: a dispute which is kept
+/// while its deposit is filtered out is rejected, typically as `OriginTxNotFound`.
+/// `RunStats::rows_rejected_after_filter` counts such rejections.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayFilter {
+    /// Types of the transactions to execute, all types when `None`.
+    pub include_kinds: Option<HashSet<TxTypeTag>>,
+    /// Clients whose transactions to execute, all clients when `None`.
+    pub include_clients: Option<HashSet<ClientId>>,
+    /// Tx ids of the transactions to execute, all tx ids when `None`.
+    pub tx_id_range: Option<RangeInclusive<TxId>>,
+}
+
+impl TxFilter for ReplayFilter {
+    fn accept(&mut self, tx: &Transaction) -> bool {
+        self.include_kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&tx.kind()))
+            && self
+                .include_clients
+                .as_ref()
+                .is_none_or(|clients| clients.contains(&tx.client_id()))
+            && self
+                .tx_id_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&tx.tx_id()))
+    }
+}
+
+/// Whether the tx id of `kind` refers to an earlier transaction rather than naming a
+/// new one.
+fn refers_to_earlier_tx(kind: TxTypeTag) -> bool {
+    match kind {
+        TxTypeTag::Deposit | TxTypeTag::Withdrawal | TxTypeTag::WithdrawAuth => false,
+        TxTypeTag::Dispute
+        | TxTypeTag::Resolve
+        | TxTypeTag::Chargeback
+        | TxTypeTag::Adjustment
+        | TxTypeTag::WithdrawCapture
+        | TxTypeTag::WithdrawRelease
+        | TxTypeTag::Settle => true,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RowOutcome<'a> {
+    Executed(&'a Transaction),
+    /// Accepted as a duplicate without any effect.
+    Duplicate(&'a Transaction),
+    /// Held until the dispute of its deposit arrives, see `TxOutcome::Deferred`.
+    Deferred(&'a Transaction),
+    /// Not executed because a filter did not accept it.
+    Filtered(&'a Transaction),
+    Rejected(&'a Transaction, &'a TxError),
+    /// Not parsed, with the value at fault when the source could tell it.
+    Malformed(&'a TransactionLogError, Option<&'a FieldError>),
+    /// Posting derived from the row, recorded with the line of the row right after
+    /// it, see `accounting::postings`.
+    Derived(&'a DerivedOutcome),
+}
+
+/// Receives the outcome of every row, after the row was applied to the ledger.
+///
+/// A failed record never rolls back the row. Depending on the `SinkFailurePolicy` of
+/// the pipeline it is dropped, so records are delivered at most once, or sent again,
+/// so they are delivered at least once, or the run stops after the row.
+pub trait AuditSink {
+    fn record(&mut self, line: Option<u64>, outcome: &RowOutcome<'_>) -> Result<(), SinkError>;
+
+    /// Receives the id of the run before its first row, e.g. to stamp it into the
+    /// records.
+    fn start_run(&mut self, _run_id: &RunId) {}
+
+    /// Like `record`, with the names of the tag rules the row matched, see
+    /// `Pipeline::tag_rules`. Sinks without a place for tags ignore them.
+    fn record_tagged(
+        &mut self,
+        line: Option<u64>,
+        outcome: &RowOutcome<'_>,
+        _tags: &[&str],
+    ) -> Result<(), SinkError> {
+        self.record(line, outcome)
+    }
+
+    /// Writes out buffered records once the source is exhausted.
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AuditSink for () {
+    fn record(&mut self, _line: Option<u64>, _outcome: &RowOutcome<'_>) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(Option<u64>, &RowOutcome<'_>) -> Result<(), SinkError>> AuditSink for F {
+    fn record(&mut self, line: Option<u64>, outcome: &RowOutcome<'_>) -> Result<(), SinkError> {
+        self(line, outcome)
+    }
+}
+
+/// The second sink receives a record once the first accepted it, so a retried
+/// record may reach the first sink again.
+impl<A: AuditSink, B: AuditSink> AuditSink for (A, B) {
+    fn record(&mut self, line: Option<u64>, outcome: &RowOutcome<'_>) -> Result<(), SinkError> {
+        self.0.record(line, outcome)?;
+        self.1.record(line, outcome)
+    }
+
+    fn start_run(&mut self, run_id: &RunId) {
+        self.0.start_run(run_id);
+        self.1.start_run(run_id);
+    }
+
+    fn record_tagged(
+        &mut self,
+        line: Option<u64>,
+        outcome: &RowOutcome<'_>,
+        tags: &[&str],
+    ) -> Result<(), SinkError> {
+        self.0.record_tagged(line, outcome, tags)?;
+        self.1.record_tagged(line, outcome, tags)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+impl<A: AuditSink> AuditSink for Option<A> {
+    fn record(&mut self, line: Option<u64>, outcome: &RowOutcome<'_>) -> Result<(), SinkError> {
+        self.record_tagged(line, outcome, &[])
+    }
+
+    fn start_run(&mut self, run_id: &RunId) {
+        if let Some(audit) = self {
+            audit.start_run(run_id);
+        }
+    }
+
+    fn record_tagged(
+        &mut self,
+        line: Option<u64>,
+        outcome: &RowOutcome<'_>,
+        tags: &[&str],
+    ) -> Result<(), SinkError> {
+        match self {
+            Some(audit) => audit.record_tagged(line, outcome, tags),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Some(audit) => audit.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes a `line,outcome,type,client,tx,amount,error_code,tags` row per record,
+/// with the names of the tag rules the row matched separated by `;` in `tags`, see
+/// `Pipeline::tag_rules`. Fields a record lacks are empty, such as the line of rows
+/// from a source without line numbers, or the transaction of a malformed row.
+/// Derived postings are recorded with the outcome `derived` and the line of the row
+/// they were derived from. The header is written with the first row, or on flush
+/// when there was none.
+pub struct AuditLog<W> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> AuditLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        if !self.header_written {
+            writeln!(
+                self.writer,
+                "line,outcome,type,client,tx,amount,error_code,tags"
+            )?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    fn write_record(
+        &mut self,
+        line: Option<u64>,
+        outcome: &RowOutcome<'_>,
+        tags: &[&str],
+    ) -> std::io::Result<()> {
+        self.write_header()?;
+        if let Some(line) = line {
+            write!(self.writer, "{}", line)?;
+        }
+        let fields = |tx: &Transaction| (tx.kind(), tx.client_id(), tx.tx_id(), tx.amount());
+        let (name, fields, error_code) = match outcome {
+            RowOutcome::Executed(tx) => ("executed", Some(fields(tx)), None),
+            RowOutcome::Duplicate(tx) => ("duplicate", Some(fields(tx)), None),
+            RowOutcome::Deferred(tx) => ("deferred", Some(fields(tx)), None),
+            RowOutcome::Filtered(tx) => ("filtered", Some(fields(tx)), None),
+            RowOutcome::Rejected(tx, err) => ("rejected", Some(fields(tx)), Some(err.code())),
+            RowOutcome::Malformed(err, _field) => ("malformed", None, Some(err.code())),
+            RowOutcome::Derived(derived) => {
+                let posting = &derived.posting;
+                (
+                    "derived",
+                    Some((
+                        posting.kind(),
+                        posting.client_id,
+                        posting.tx_id,
+                        Some(posting.amount),
+                    )),
+                    derived.result.as_ref().err().map(TxError::code),
+                )
+            }
+        };
+        write!(self.writer, ",{},", name)?;
+        match fields {
+            Some((kind, client_id, tx_id, amount)) => {
+                write!(self.writer, "{},{},{},", kind, client_id, tx_id)?;
+                if let Some(amount) = amount {
+                    write!(self.writer, "{}", amount)?;
+                }
+            }
+            None => self.writer.write_all(b",,,")?,
+        }
+        writeln!(
+            self.writer,
+            ",{},{}",
+            error_code.unwrap_or_default(),
+            tags.join(";")
+        )
+    }
+}
+
+impl<W: Write> AuditSink for AuditLog<W> {
+    fn record(&mut self, line: Option<u64>, outcome: &RowOutcome<'_>) -> Result<(), SinkError> {
+        self.record_tagged(line, outcome, &[])
+    }
+
+    fn record_tagged(
+        &mut self,
+        line: Option<u64>,
+        outcome: &RowOutcome<'_>,
+        tags: &[&str],
+    ) -> Result<(), SinkError> {
+        self.write_record(line, outcome, tags)
+            .map_err(|err| SinkError::new(format!("failed to write audit log: {}", err)))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+/// State of an account after a transaction changed it, see `Pipeline::account_updates`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AccountUpdate {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub available: rust_decimal::Decimal,
+    pub held: rust_decimal::Decimal,
+    pub locked: bool,
+    /// `Ledger::tx_seq` once the transaction was applied, increasing with every
+    /// update of an account.
+    pub seq: u64,
+}
+
+/// Receives the state of accounts as transactions change them. Failures are
+/// handled like the ones of audit sinks.
+pub trait AccountSink {
+    fn update(&mut self, update: AccountUpdate) -> Result<(), SinkError>;
+}
+
+impl AccountSink for () {
+    fn update(&mut self, _update: AccountUpdate) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(AccountUpdate) -> Result<(), SinkError>> AccountSink for F {
+    fn update(&mut self, update: AccountUpdate) -> Result<(), SinkError> {
+        self(update)
+    }
+}
+
+/// Waits for room in the channel, so a consumer more than the capacity of the channel
+/// behind stalls the ledger. Fails once the receiver was dropped.
+#[cfg(feature = "io")]
+impl AccountSink for tokio::sync::mpsc::Sender<AccountUpdate> {
+    fn update(&mut self, update: AccountUpdate) -> Result<(), SinkError> {
+        self.blocking_send(update)
+            .map_err(|_err| SinkError::new("account update receiver was dropped"))
+    }
+}
+
+/// Never waits: once a receiver is the capacity of the channel behind, the oldest
+/// updates it didn't read are dropped, which it learns from `RecvError::Lagged`.
+/// The ledger thus never stalls, at the cost of gaps in the updates of a slow
+/// consumer. Fails while there is no receiver.
+#[cfg(feature = "io")]
+impl AccountSink for tokio::sync::broadcast::Sender<AccountUpdate> {
+    fn update(&mut self, update: AccountUpdate) -> Result<(), SinkError> {
+        self.send(update)
+            .map(|_receivers| ())
+            .map_err(|_err| SinkError::new("no account update receiver"))
+    }
+}
+
+/// Sequence numbers of the last update sent and of the latest change not sent yet,
+/// of an account whose updates are coalesced.
+#[derive(Default)]
+struct CoalescedAccount {
+    sent_seq: Option<u64>,
+    pending_seq: Option<u64>,
+}
+
+/// Whether the change of the account at `seq` is sent now, rather than held back
+/// until `interval` transactions passed since the last update of the account.
+fn coalesce(
+    coalesced: &mut HashMap<ClientId, CoalescedAccount>,
+    interval: u64,
+    client_id: ClientId,
+    seq: u64,
+) -> bool {
+    if interval == 1 {
+        return true;
+    }
+    let account = coalesced.entry(client_id).or_default();
+    if account
+        .sent_seq
+        .is_some_and(|sent_seq| seq - sent_seq < interval)
+    {
+        account.pending_seq = Some(seq);
+        return false;
+    }
+    account.sent_seq = Some(seq);
+    account.pending_seq = None;
+    true
+}
+
+/// Current state of the account, last changed at `seq`.
+fn account_update(ledger: &Ledger, client_id: ClientId, seq: u64) -> Option<AccountUpdate> {
+    ledger.account(client_id).map(|account| AccountUpdate {
+        client_id,
+        available: account.available(),
+        held: account.held(),
+        locked: account.locked(),
+        seq,
+    })
+}
+
+fn send_update(
+    updates: &mut impl AccountSink,
+    policy: SinkFailurePolicy,
+    clock: &dyn Clock,
+    update: Option<AccountUpdate>,
+    stats: &mut RunStats,
+) {
+    let Some(update) = update else {
+        return;
+    };
+    match deliver(policy, clock, || updates.update(update)) {
+        Ok(Delivery::Delivered) => {}
+        Ok(Delivery::Dropped) => stats.events_dropped += 1,
+        Err(err) => stats.sink_error = Some(err),
+    }
+}
+
+/// Receives malformed and rejected rows which have their raw bytes available.
+pub trait QuarantineSink {
+    /// Returns whether the row was quarantined.
+    fn quarantine(
+        &mut self,
+        raw: &RawRow<'_>,
+        error_code: &str,
+        line: u64,
+        field: Option<&FieldError>,
+    ) -> std::io::Result<bool>;
+
+    fn flush(&mut self) -> std::io::Result<()>;
+
+    /// Receives the id of the run before its first row, see `Quarantine::stamp_run_id`.
+    fn start_run(&mut self, _run_id: &RunId) {}
+}
+
+impl QuarantineSink for () {
+    fn quarantine(
+        &mut self,
+        _raw: &RawRow<'_>,
+        _code: &str,
+        _line: u64,
+        _field: Option<&FieldError>,
+    ) -> std::io::Result<bool> {
+        Ok(false)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<Q: QuarantineSink> QuarantineSink for Option<Q> {
+    fn quarantine(
+        &mut self,
+        raw: &RawRow<'_>,
+        code: &str,
+        line: u64,
+        field: Option<&FieldError>,
+    ) -> std::io::Result<bool> {
+        match self {
+            Some(quarantine) => quarantine.quarantine(raw, code, line, field),
+            None => Ok(false),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Some(quarantine) => quarantine.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn start_run(&mut self, run_id: &RunId) {
+        if let Some(quarantine) = self {
+            quarantine.start_run(run_id);
+        }
+    }
+}
+
+impl<Q: QuarantineSink + ?Sized> QuarantineSink for &mut Q {
+    fn quarantine(
+        &mut self,
+        raw: &RawRow<'_>,
+        code: &str,
+        line: u64,
+        field: Option<&FieldError>,
+    ) -> std::io::Result<bool> {
+        (**self).quarantine(raw, code, line, field)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (**self).flush()
+    }
+
+    fn start_run(&mut self, run_id: &RunId) {
+        (**self).start_run(run_id)
+    }
+}
+
+/// Writes rows verbatim with `error_code`, `line_number`, `error_column`,
+/// `error_value` and `error_reason` columns appended, so that they can be fixed and
+/// replayed. The last three describe the value at fault and are empty when there is
+/// none. The header of the input followed by the five columns is written with the
+/// first row.
+pub struct Quarantine<W> {
+    writer: W,
+    header_written: bool,
+    max_value_chars: usize,
+    stamp_run_id: bool,
+    run_id: Option<RunId>,
+}
+
+impl<W: Write> Quarantine<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+            max_value_chars: DEFAULT_MAX_VALUE_CHARS,
+            stamp_run_id: false,
+            run_id: None,
+        }
+    }
+
+    /// Cuts values at fault to `max_value_chars` characters in `error_value`, see
+    /// `FieldError::truncated_value`. Defaults to `DEFAULT_MAX_VALUE_CHARS`.
+    pub fn with_max_value_chars(mut self, max_value_chars: usize) -> Self {
+        self.max_value_chars = max_value_chars;
+        self
+    }
+
+    /// Appends a `run_id` column with the id of the run which quarantined the row.
+    pub fn stamp_run_id(mut self, stamp: bool) -> Self {
+        self.stamp_run_id = stamp;
+        self
+    }
+}
+
+impl<W: Write> QuarantineSink for Quarantine<W> {
+    fn quarantine(
+        &mut self,
+        raw: &RawRow<'_>,
+        code: &str,
+        line: u64,
+        field: Option<&FieldError>,
+    ) -> std::io::Result<bool> {
+        if !self.header_written {
+            let headers = raw.headers.iter().collect::<Vec<_>>().join(",");
+            writeln!(
+                self.writer,
+                "{},error_code,line_number,error_column,error_value,error_reason{}",
+                headers,
+                if self.stamp_run_id { ",run_id" } else { "" }
+            )?;
+            self.header_written = true;
+        }
+        self.writer.write_all(raw.bytes)?;
+        write!(self.writer, ",{},{},", code, line)?;
+        if let Some(field) = field {
+            write_csv_field(&mut self.writer, &field.column_name)?;
+            self.writer.write_all(b",")?;
+            write_csv_field(
+                &mut self.writer,
+                &field.truncated_value(self.max_value_chars),
+            )?;
+            write!(self.writer, ",{}", field.reason)?;
+        } else {
+            self.writer.write_all(b",")?;
+            self.writer.write_all(b",")?;
+        }
+        if self.stamp_run_id {
+            let run_id = self.run_id.as_ref().map_or("", RunId::as_str);
+            write!(self.writer, ",{}", run_id)?;
+        }
+        writeln!(self.writer)?;
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn start_run(&mut self, run_id: &RunId) {
+        self.run_id = Some(run_id.clone());
+    }
+}
+
+/// Writes `value` as a CSV field, quoted when it holds a separator, quote or line break.
+fn write_csv_field(writer: &mut impl Write, value: &str) -> std::io::Result<()> {
+    if value.contains([',', '"', '\n', '\r']) {
+        write!(writer, "\"{}\"", value.replace('"', "\"\""))
+    } else {
+        writer.write_all(value.as_bytes())
+    }
+}
+
+/// Receives the statistics once the source is exhausted.
+pub trait MetricsReporter {
+    fn report(&mut self, stats: &RunStats);
+}
+
+impl MetricsReporter for () {
+    fn report(&mut self, _stats: &RunStats) {}
+}
+
+impl<F: FnMut(&RunStats)> MetricsReporter for F {
+    fn report(&mut self, stats: &RunStats) {
+        self(stats)
+    }
+}
+
+pub struct Pipeline<S, F = (), A = (), Q = (), M = (), U = ()> {
+    source: S,
+    filter: F,
+    audit: A,
+    quarantine: Q,
+    metrics: M,
+    updates: U,
+    /// Minimum number of transactions between two updates of an account.
+    update_interval: u64,
+    sink_failure_policy: SinkFailurePolicy,
+    run_id: Option<RunId>,
+    /// Length of the lists of `RunStats::top_k`, which are not kept when `None`.
+    top_k: Option<usize>,
+    /// Whether `RunStats::distribution` includes rejected amounts, which it is not
+    /// kept at all when `None`.
+    distribution: Option<bool>,
+    tag_rules: Option<TagRules>,
+}
+
+impl<S: TransactionSource> Pipeline<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            filter: (),
+            audit: (),
+            quarantine: (),
+            metrics: (),
+            updates: (),
+            update_interval: 1,
+            sink_failure_policy: SinkFailurePolicy::default(),
+            run_id: None,
+            top_k: None,
+            distribution: None,
+            tag_rules: None,
+        }
+    }
+}
+
+impl<S, F, A, Q, M, U> Pipeline<S, F, A, Q, M, U> {
+    /// Adds a filter; a transaction is executed only when all filters accept it.
+    pub fn filter<G: TxFilter>(self, filter: G) -> Pipeline<S, (F, G), A, Q, M, U> {
+        Pipeline {
+            source: self.source,
+            filter: (self.filter, filter),
+            audit: self.audit,
+            quarantine: self.quarantine,
+            metrics: self.metrics,
+            updates: self.updates,
+            update_interval: self.update_interval,
+            sink_failure_policy: self.sink_failure_policy,
+            run_id: self.run_id,
+            top_k: self.top_k,
+            distribution: self.distribution,
+            tag_rules: self.tag_rules,
+        }
+    }
+
+    /// Adds an audit sink, after the ones added before.
+    pub fn audit<B: AuditSink>(self, audit: B) -> Pipeline<S, F, (A, B), Q, M, U> {
+        Pipeline {
+            source: self.source,
+            filter: self.filter,
+            audit: (self.audit, audit),
+            quarantine: self.quarantine,
+            metrics: self.metrics,
+            updates: self.updates,
+            update_interval: self.update_interval,
+            sink_failure_policy: self.sink_failure_policy,
+            run_id: self.run_id,
+            top_k: self.top_k,
+            distribution: self.distribution,
+            tag_rules: self.tag_rules,
+        }
+    }
+
+    /// Sets the quarantine for malformed and rejected rows, typically a `Quarantine`
+    /// or an `Option` of one.
+    pub fn quarantine<R: QuarantineSink>(self, quarantine: R) -> Pipeline<S, F, A, R, M, U> {
+        Pipeline {
+            source: self.source,
+            filter: self.filter,
+            audit: self.audit,
+            quarantine,
+            metrics: self.metrics,
+            updates: self.updates,
+            update_interval: self.update_interval,
+            sink_failure_policy: self.sink_failure_policy,
+            run_id: self.run_id,
+            top_k: self.top_k,
+            distribution: self.distribution,
+            tag_rules: self.tag_rules,
+        }
+    }
+
+    pub fn metrics<N: MetricsReporter>(self, metrics: N) -> Pipeline<S, F, A, Q, N, U> {
+        Pipeline {
+            source: self.source,
+            filter: self.filter,
+            audit: self.audit,
+            quarantine: self.quarantine,
+            metrics,
+            updates: self.updates,
+            update_interval: self.update_interval,
+            sink_failure_policy: self.sink_failure_policy,
+            run_id: self.run_id,
+            top_k: self.top_k,
+            distribution: self.distribution,
+            tag_rules: self.tag_rules,
+        }
+    }
+
+    /// Sets the sink receiving the state of every account a transaction changed, e.g.
+    /// a channel to a dashboard. Updates of an account arrive in the order of their
+    /// `seq`; the order between accounts is unspecified.
+    pub fn account_updates<V: AccountSink>(self, updates: V) -> Pipeline<S, F, A, Q, M, V> {
+        Pipeline {
+            source: self.source,
+            filter: self.filter,
+            audit: self.audit,
+            quarantine: self.quarantine,
+            metrics: self.metrics,
+            updates,
+            update_interval: self.update_interval,
+            sink_failure_policy: self.sink_failure_policy,
+            run_id: self.run_id,
+            top_k: self.top_k,
+            distribution: self.distribution,
+            tag_rules: self.tag_rules,
+        }
+    }
+
+    /// Sends at most one update per account every `transactions` transactions. Later
+    /// changes within the interval are sent with the next change after it, and at the
+    /// end of the run, so the last update of every account is its final state.
+    pub fn coalesce_account_updates(mut self, transactions: u64) -> Self {
+        self.update_interval = transactions.max(1);
+        self
+    }
+
+    /// Sets the handling of failures of the audit and account sinks. Failures of the
+    /// ledger's event listener follow `LedgerConfig::event_failure_policy`, and either kind of
+    /// failure which is not ignored stops the run, see `RunStats::sink_error`.
+    pub fn sink_failure_policy(mut self, policy: SinkFailurePolicy) -> Self {
+        self.sink_failure_policy = policy;
+        self
+    }
+
+    /// Sets the id of the run, e.g. the correlation id of an orchestrator. Without
+    /// it every run generates its own with `RunId::generate_at` the time of the clock of
+    /// its ledger. The id is passed to the
+    /// audit and quarantine sinks before the first row and returned in
+    /// `RunStats::run_id`.
+    pub fn run_id(mut self, run_id: RunId) -> Self {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    /// Keeps the `k` largest accounts, open disputes and most rejected clients of the
+    /// run in `RunStats::top_k`, see `top_k::TopKTracker`.
+    pub fn top_k(mut self, k: usize) -> Self {
+        self.top_k = Some(k);
+        self
+    }
+
+    /// Keeps the count, sum, extremes and percentiles of the amounts of the applied
+    /// deposits and withdrawals of the run in `RunStats::distribution`, of the
+    /// rejected ones too when `include_rejected` is set, see `distribution`.
+    pub fn distribution_stats(mut self, include_rejected: bool) -> Self {
+        self.distribution = Some(include_rejected);
+        self
+    }
+
+    /// Tags every row with the names of the rules it matches, which are passed to
+    /// the audit sinks and counted in `RunStats::tag_counts`, see `tag_rules`.
+    pub fn tag_rules(mut self, rules: TagRules) -> Self {
+        self.tag_rules = Some(rules);
+        self
+    }
+}
+
+impl<S, F, A, Q, M, U> Pipeline<S, F, A, Q, M, U>
+where
+    S: TransactionSource,
+    F: TxFilter,
+    A: AuditSink,
+    Q: QuarantineSink,
+    M: MetricsReporter,
+    U: AccountSink,
+{
+    /// Executes all rows of the source on a new ledger.
+    pub fn into_ledger(self, config: LedgerConfig) -> std::io::Result<(Ledger, RunStats)> {
+        let mut ledger = Ledger::with_config(config);
+        let stats = self.run(&mut ledger)?;
+        Ok((ledger, stats))
+    }
+
+    /// Executes all rows of the source on `ledger`, stopping early when a sink
+    /// failure is not ignored.
+    pub fn run(mut self, ledger: &mut Ledger) -> std::io::Result<RunStats> {
+        let clock = ledger.clock().clone();
+        let run_id = self
+            .run_id
+            .take()
+            .unwrap_or_else(|| RunId::generate_at(clock.now()));
+        self.audit.start_run(&run_id);
+        self.quarantine.start_run(&run_id);
+        let mut stats = RunStats {
+            run_id: Some(run_id),
+            ..RunStats::default()
+        };
+        let events_dropped = ledger.events_dropped();
+        let velocity_flagged = ledger.velocity_flagged();
+        let dispute_amount_mismatches = ledger.dispute_amount_mismatches();
+        // Tx ids of filtered rows, to tell rejections caused by the filtering.
+        let mut filtered_tx_ids = HashSet::new();
+        let mut coalesced = HashMap::new();
+        let mut top_k = self.top_k.map(TopKTracker::new);
+        let mut distribution = self.distribution.map(DistributionTracker::new);
+        let rules = self.tag_rules.take().unwrap_or_default();
+        let mut tag_counts = vec![0; rules.rules().len()];
+        while let Some(row) = self.source.next_row()? {
+            let rejected;
+            let (outcome, error_code) = match &row.tx {
+                Ok(tx) if !self.filter.accept(tx) => {
+                    stats.rows_filtered += 1;
+                    filtered_tx_ids.insert(tx.tx_id());
+                    (RowOutcome::Filtered(tx), None)
+                }
+                Ok(tx) => match ledger.execute_catch_unwind(tx) {
+                    Ok(TxOutcome::Applied) => {
+                        stats.rows_executed += 1;
+                        (RowOutcome::Executed(tx), None)
+                    }
+                    Ok(TxOutcome::Duplicate) => {
+                        stats.rows_duplicate += 1;
+                        (RowOutcome::Duplicate(tx), None)
+                    }
+                    Ok(TxOutcome::Deferred) => {
+                        stats.rows_deferred += 1;
+                        (RowOutcome::Deferred(tx), None)
+                    }
+                    Err(err) => {
+                        stats.rows_rejected += 1;
+                        if let Some(top_k) = top_k.as_mut() {
+                            top_k.record_rejection(tx.client_id());
+                        }
+                        match err {
+                            TxError::InternalPanic { .. } => stats.rows_panicked += 1,
+                            TxError::DisputeTrackingDisabled => {
+                                stats.rows_dispute_tracking_disabled += 1
+                            }
+                            _ => {}
+                        }
+                        if refers_to_earlier_tx(tx.kind()) && filtered_tx_ids.contains(&tx.tx_id())
+                        {
+                            stats.rows_rejected_after_filter += 1;
+                        }
+                        rejected = err;
+                        (RowOutcome::Rejected(tx, &rejected), Some(rejected.code()))
+                    }
+                },
+                Err(err) => {
+                    stats.rows_malformed += 1;
+                    (
+                        RowOutcome::Malformed(err, row.field.as_ref()),
+                        Some(err.code()),
+                    )
+                }
+            };
+            if let (Some(distribution), RowOutcome::Executed(tx) | RowOutcome::Rejected(tx, _)) =
+                (distribution.as_mut(), &outcome)
+            {
+                if let Some(amount) = tx.amount() {
+                    let applied = matches!(outcome, RowOutcome::Executed(_));
+                    distribution.record(tx.kind(), amount, applied);
+                }
+            }
+            let tags: Vec<_> = rules
+                .matching(&outcome)
+                .into_iter()
+                .map(|index| {
+                    tag_counts[index] += 1;
+                    rules.rules()[index].name.as_str()
+                })
+                .collect();
+            match deliver(self.sink_failure_policy, &*clock, || {
+                self.audit.record_tagged(row.line, &outcome, &tags)
+            }) {
+                Ok(Delivery::Delivered) => {}
+                Ok(Delivery::Dropped) => stats.events_dropped += 1,
+                Err(err) => stats.sink_error = Some(err),
+            }
+            if !matches!(outcome, RowOutcome::Filtered(_) | RowOutcome::Malformed(..)) {
+                for derived in ledger.last_derived_postings() {
+                    match derived.result {
+                        Ok(()) => stats.derived_postings += 1,
+                        Err(_) => stats.derived_postings_rejected += 1,
+                    }
+                    if stats.sink_error.is_some() {
+                        continue;
+                    }
+                    match deliver(self.sink_failure_policy, &*clock, || {
+                        self.audit.record(row.line, &RowOutcome::Derived(derived))
+                    }) {
+                        Ok(Delivery::Delivered) => {}
+                        Ok(Delivery::Dropped) => stats.events_dropped += 1,
+                        Err(err) => stats.sink_error = Some(err),
+                    }
+                }
+            }
+            if let (RowOutcome::Executed(tx), None) = (&outcome, &stats.sink_error) {
+                let client_id = tx.client_id();
+                if coalesce(
+                    &mut coalesced,
+                    self.update_interval,
+                    client_id,
+                    ledger.tx_seq(),
+                ) {
+                    let update = account_update(ledger, client_id, ledger.tx_seq());
+                    send_update(
+                        &mut self.updates,
+                        self.sink_failure_policy,
+                        &*clock,
+                        update,
+                        &mut stats,
+                    );
+                }
+            }
+            if let (Some(error_code), Some(raw), Some(line)) = (error_code, &row.raw, row.line) {
+                if self
+                    .quarantine
+                    .quarantine(raw, error_code, line, row.field.as_ref())?
+                {
+                    stats.rows_quarantined += 1;
+                }
+            }
+            if let Some(err) = ledger.take_event_sink_error() {
+                stats.sink_error.get_or_insert(err);
+            }
+            if stats.sink_error.is_some() {
+                break;
+            }
+        }
+        if stats.sink_error.is_none() {
+            let mut pending: Vec<_> = coalesced
+                .into_iter()
+                .filter_map(|(client_id, account): (ClientId, CoalescedAccount)| {
+                    Some((client_id, account.pending_seq?))
+                })
+                .collect();
+            pending.sort_unstable();
+            for (client_id, seq) in pending {
+                let update = account_update(ledger, client_id, seq);
+                send_update(
+                    &mut self.updates,
+                    self.sink_failure_policy,
+                    &*clock,
+                    update,
+                    &mut stats,
+                );
+                if stats.sink_error.is_some() {
+                    break;
+                }
+            }
+        }
+        stats.top_k = top_k.map(|top_k| top_k.report(&*ledger));
+        stats.distribution = distribution.map(|distribution| distribution.report());
+        stats.tag_counts = rules
+            .rules()
+            .iter()
+            .zip(tag_counts)
+            .map(|(rule, count)| (rule.name.clone(), count))
+            .collect();
+        stats.events_dropped += ledger.events_dropped() - events_dropped;
+        stats.velocity_flagged = ledger.velocity_flagged() - velocity_flagged;
+        stats.dispute_amount_mismatches =
+            ledger.dispute_amount_mismatches() - dispute_amount_mismatches;
+        self.quarantine.flush()?;
+        self.audit.flush()?;
+        debug_assert!(
+            ledger.is_poisoned() || ledger.audit_liabilities().is_ok(),
+            "{:?}",
+            ledger.audit_liabilities()
+        );
+        if ledger.is_poisoned() {
+            stats.poison_check = Some(ledger.verify_invariants());
+        }
+        #[cfg(feature = "digest")]
+        {
+            stats.execution_digest = ledger.execution_digest();
+        }
+        self.metrics.report(&stats);
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::config::{DisputeAmountHandling, DuplicateDisputePolicy};
+    use crate::accounting::postings::{ChargebackFeeRule, DERIVED_TX_ID_START};
+    use crate::accounting::transactions::{
+        Chargeback, Deposit, Dispute, Resolve, WithdrawAuth, WithdrawCapture, Withdrawal,
+    };
+    use crate::accounting::AccountView;
+    use crate::tag_rules::{TagPredicates, TagRule};
+    use crate::test_utils::FlakySink;
+    use rust_decimal_macros::dec;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq)]
+    enum Audited {
+        Executed(u32),
+        Filtered(u32),
+        Rejected(u32, &'static str),
+    }
+
+    #[test]
+    fn filtered_rows_are_audited_not_executed() {
+        let source = vec![
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(500))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(4))),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(11))),
+        ];
+        let mut audited = Vec::new();
+        let mut reported = None;
+        let (ledger, stats) = Pipeline::new(source.into_iter())
+            .filter(|tx: &Transaction| tx.client_id() != 2)
+            .filter(|tx: &Transaction| tx.tx_id() != 3)
+            .audit(|_line, outcome: &RowOutcome<'_>| {
+                audited.push(match outcome {
+                    RowOutcome::Executed(tx) => Audited::Executed(tx.tx_id()),
+                    RowOutcome::Filtered(tx) => Audited::Filtered(tx.tx_id()),
+                    RowOutcome::Rejected(tx, err) => Audited::Rejected(tx.tx_id(), err.code()),
+                    RowOutcome::Duplicate(_)
+                    | RowOutcome::Deferred(_)
+                    | RowOutcome::Malformed(..)
+                    | RowOutcome::Derived(_) => unreachable!(),
+                });
+                Ok(())
+            })
+            .metrics(|stats: &RunStats| reported = Some(stats.rows_filtered))
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+
+        assert_eq!(
+            audited,
+            vec![
+                Audited::Executed(1),
+                Audited::Filtered(2),
+                Audited::Filtered(3),
+                Audited::Filtered(2),
+                Audited::Rejected(4, "insufficient_funds"),
+            ]
+        );
+        assert_eq!(stats.rows_executed, 1);
+        assert_eq!(stats.rows_filtered, 3);
+        assert_eq!(stats.rows_rejected, 1);
+        assert_eq!(stats.rows_quarantined, 0);
+        assert_eq!(reported, Some(3));
+        assert!(ledger.account(2).is_none());
+        assert_eq!(
+            ledger.account(1).map(|account| account.available()),
+            Some(dec!(10))
+        );
+        ledger.verify_invariants().unwrap();
+    }
+
+    #[test]
+    fn top_k_counts_rejections_of_executed_rows_only() {
+        let source = || {
+            vec![
+                Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+                Transaction::Withdrawal(Withdrawal::new(2, 2, dec!(1))),
+                Transaction::Withdrawal(Withdrawal::new(3, 3, dec!(1))),
+                Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(11))),
+            ]
+        };
+        let (_ledger, stats) = Pipeline::new(source().into_iter())
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        assert_eq!(stats.top_k, None);
+
+        let (_ledger, stats) = Pipeline::new(source().into_iter())
+            .filter(|tx: &Transaction| tx.client_id() != 3)
+            .top_k(5)
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        let top_k = stats.top_k.unwrap();
+        assert_eq!(top_k.k, 5);
+        assert_eq!(top_k.accounts.len(), 1);
+        let rejected: Vec<_> = top_k
+            .rejected_clients
+            .iter()
+            .map(|client| (client.client_id, client.rejections))
+            .collect();
+        assert_eq!(rejected, [(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn distribution_stats_keep_rejected_amounts_apart() {
+        let source = || {
+            vec![
+                Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+                Transaction::Deposit(Deposit::new(1, 2, dec!(30))),
+                Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(5))),
+                Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(500))),
+                Transaction::Dispute(Dispute::new(1, 1)),
+            ]
+        };
+        let (_ledger, stats) = Pipeline::new(source().into_iter())
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        assert_eq!(stats.distribution, None);
+
+        let (_ledger, stats) = Pipeline::new(source().into_iter())
+            .distribution_stats(false)
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        let distribution = stats.distribution.unwrap();
+        assert_eq!(distribution.deposits.amounts.count, 2);
+        assert_eq!(distribution.deposits.amounts.sum, dec!(40));
+        assert_eq!(distribution.withdrawals.amounts.max, Some(dec!(5)));
+        assert_eq!(distribution.withdrawals.rejected, 1);
+
+        let (_ledger, stats) = Pipeline::new(source().into_iter())
+            .distribution_stats(true)
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        let withdrawals = stats.distribution.unwrap().withdrawals;
+        assert_eq!(withdrawals.amounts.max, Some(dec!(500)));
+        assert_eq!(withdrawals.rejected, 1);
+    }
+
+    fn replay(filter: ReplayFilter, source: Vec<Transaction>) -> (Ledger, RunStats) {
+        Pipeline::new(source.into_iter())
+            .filter(filter)
+            .into_ledger(LedgerConfig::default())
+            .unwrap()
+    }
+
+    fn history() -> Vec<Transaction> {
+        vec![
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(20))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(4))),
+            Transaction::Deposit(Deposit::new(3, 4, dec!(30))),
+            Transaction::Withdrawal(Withdrawal::new(2, 5, dec!(5))),
+        ]
+    }
+
+    fn available(ledger: &Ledger, client_id: ClientId) -> Option<rust_decimal::Decimal> {
+        ledger.account(client_id).map(|account| account.available())
+    }
+
+    #[test]
+    fn replay_filter_selects_kinds() {
+        let filter = ReplayFilter {
+            include_kinds: Some(HashSet::from([TxTypeTag::Deposit])),
+            ..ReplayFilter::default()
+        };
+        let (ledger, stats) = replay(filter, history());
+        assert_eq!((stats.rows_executed, stats.rows_filtered), (3, 2));
+        assert_eq!(available(&ledger, 1), Some(dec!(10)));
+        assert_eq!(available(&ledger, 2), Some(dec!(20)));
+    }
+
+    #[test]
+    fn replay_filter_selects_clients() {
+        let filter = ReplayFilter {
+            include_clients: Some(HashSet::from([1, 3])),
+            ..ReplayFilter::default()
+        };
+        let (ledger, stats) = replay(filter, history());
+        assert_eq!((stats.rows_executed, stats.rows_filtered), (3, 2));
+        assert_eq!(available(&ledger, 1), Some(dec!(6)));
+        assert_eq!(available(&ledger, 2), None);
+        assert_eq!(available(&ledger, 3), Some(dec!(30)));
+    }
+
+    #[test]
+    fn replay_filter_selects_tx_id_range() {
+        let filter = ReplayFilter {
+            tx_id_range: Some(2..=4),
+            ..ReplayFilter::default()
+        };
+        let (ledger, stats) = replay(filter, history());
+        assert_eq!((stats.rows_executed, stats.rows_rejected), (2, 1));
+        assert_eq!(stats.rows_filtered, 2);
+        // The withdrawal of client 1 is kept while its deposit is not
+        assert_eq!(available(&ledger, 1), None);
+        assert_eq!(available(&ledger, 2), Some(dec!(20)));
+        assert_eq!(available(&ledger, 3), Some(dec!(30)));
+        // A withdrawal names a new tx, so its rejection is not counted against the filter
+        assert_eq!(stats.rows_rejected_after_filter, 0);
+    }
+
+    #[test]
+    fn rejections_referring_to_filtered_rows_are_counted() {
+        let source = vec![
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(1))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Dispute(Dispute::new(1, 9)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+        ];
+        let filter = ReplayFilter {
+            include_kinds: Some(HashSet::from([TxTypeTag::Deposit, TxTypeTag::Dispute])),
+            tx_id_range: Some(2..=9),
+            ..ReplayFilter::default()
+        };
+        let (ledger, stats) = replay(filter, source);
+        // Deposit 1, withdrawal 3, dispute of 1 and the resolve are filtered
+        assert_eq!(stats.rows_filtered, 4);
+        assert_eq!(stats.rows_executed, 2);
+        // The dispute of the unknown tx 9 fails on its own
+        assert_eq!(stats.rows_rejected, 1);
+        assert_eq!(stats.rows_rejected_after_filter, 0);
+        assert_eq!(ledger.account(1).unwrap().held(), dec!(5));
+
+        // Only the rejections referring to the filtered withdrawal and authorization count
+        let filter = ReplayFilter {
+            include_kinds: Some(HashSet::from([
+                TxTypeTag::Deposit,
+                TxTypeTag::Dispute,
+                TxTypeTag::WithdrawCapture,
+            ])),
+            ..ReplayFilter::default()
+        };
+        let mut rejected = Vec::new();
+        let stats = Pipeline::new(
+            vec![
+                Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+                Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(3))),
+                Transaction::Dispute(Dispute::new(1, 2)),
+                Transaction::WithdrawAuth(WithdrawAuth::new(1, 3, dec!(2))),
+                Transaction::WithdrawCapture(WithdrawCapture::new(1, 3)),
+                Transaction::Dispute(Dispute::new(1, 7)),
+            ]
+            .into_iter(),
+        )
+        .filter(filter)
+        .audit(|_line, outcome: &RowOutcome<'_>| {
+            if let RowOutcome::Rejected(tx, err) = outcome {
+                rejected.push((tx.tx_id(), err.code()));
+            }
+            Ok(())
+        })
+        .run(&mut Ledger::new())
+        .unwrap();
+        assert_eq!(stats.rows_rejected, 3);
+        assert_eq!(stats.rows_rejected_after_filter, 2);
+        assert_eq!(
+            rejected,
+            vec![
+                (2, "origin_tx_not_found"),
+                (3, "hold_not_found"),
+                (7, "origin_tx_not_found"),
+            ]
+        );
+    }
+
+    /// Deposits, withdrawals, disputes, resolves and chargebacks of 7 clients.
+    fn account_update_history() -> Vec<Transaction> {
+        let client_of = |tx_id: u32| (tx_id % 7 + 1) as ClientId;
+        (1..=300u32)
+            .map(|tx_id| match tx_id % 6 {
+                // Rows with a remainder of 3 are deposits
+                0 => Transaction::Dispute(Dispute::new(client_of(tx_id - 3), tx_id - 3)),
+                1 => Transaction::Withdrawal(Withdrawal::new(client_of(tx_id), tx_id, dec!(3))),
+                2 if tx_id > 12 => {
+                    Transaction::Resolve(Resolve::new(client_of(tx_id - 11), tx_id - 11))
+                }
+                4 if tx_id % 60 == 4 && tx_id > 60 => {
+                    Transaction::Chargeback(Chargeback::new(client_of(tx_id - 7), tx_id - 7))
+                }
+                _ => Transaction::Deposit(Deposit::new(client_of(tx_id), tx_id, dec!(5))),
+            })
+            .collect()
+    }
+
+    /// Last update of every account, checking that the updates of each account come
+    /// in increasing `seq` and at least `interval` transactions apart, except for the
+    /// last one.
+    fn last_updates(updates: &[AccountUpdate], interval: u64) -> HashMap<ClientId, AccountUpdate> {
+        let mut by_account: HashMap<ClientId, Vec<AccountUpdate>> = HashMap::new();
+        for update in updates {
+            by_account
+                .entry(update.client_id)
+                .or_default()
+                .push(*update);
+        }
+        by_account
+            .into_iter()
+            .map(|(client_id, updates)| {
+                for pair in updates[..updates.len() - 1].windows(2) {
+                    assert!(pair[1].seq - pair[0].seq >= interval, "{:?}", pair);
+                }
+                assert!(updates.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+                (client_id, *updates.last().unwrap())
+            })
+            .collect()
+    }
+
+    fn assert_final_state(ledger: &Ledger, last: &HashMap<ClientId, AccountUpdate>) {
+        assert_eq!(last.len(), ledger.accounts_iter().count());
+        for (client_id, account) in ledger.accounts_iter() {
+            let update = last[client_id];
+            assert_eq!(
+                (update.available, update.held, update.locked),
+                (account.available(), account.held(), account.locked())
+            );
+        }
+    }
+
+    #[test]
+    fn last_account_update_is_final_state() {
+        for interval in [1, 25] {
+            let mut updates = Vec::new();
+            let (ledger, stats) = Pipeline::new(account_update_history().into_iter())
+                .account_updates(|update| {
+                    updates.push(update);
+                    Ok(())
+                })
+                .coalesce_account_updates(interval)
+                .into_ledger(LedgerConfig::default())
+                .unwrap();
+            assert!(stats.rows_rejected > 0);
+            assert!(ledger
+                .accounts_iter()
+                .any(|(_client_id, account)| account.locked()));
+            if interval == 1 {
+                assert_eq!(updates.len() as u64, stats.rows_executed);
+            } else {
+                assert!((updates.len() as u64) < stats.rows_executed / 2);
+            }
+            assert_final_state(&ledger, &last_updates(&updates, interval));
+        }
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn account_updates_through_blocking_channel() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let consumer = std::thread::spawn(move || {
+            let mut updates = Vec::new();
+            while let Some(update) = receiver.blocking_recv() {
+                updates.push(update);
+            }
+            updates
+        });
+        let (ledger, stats) = Pipeline::new(account_update_history().into_iter())
+            .account_updates(sender)
+            .coalesce_account_updates(10)
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        let updates = consumer.join().unwrap();
+        assert_eq!(stats.events_dropped, 0);
+        assert_final_state(&ledger, &last_updates(&updates, 10));
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn drop_oldest_channel_never_stalls_the_ledger() {
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(8);
+        // Nothing is read during the run, which would block a channel waiting for room
+        let (ledger, stats) = Pipeline::new(account_update_history().into_iter())
+            .account_updates(sender)
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+        assert_eq!(stats.events_dropped, 0);
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(tokio::sync::broadcast::error::TryRecvError::Lagged(lagged))
+                if lagged == stats.rows_executed - 8
+        ));
+        let mut updates = Vec::new();
+        while let Ok(update) = receiver.try_recv() {
+            updates.push(update);
+        }
+        assert_eq!(updates.len(), 8);
+        assert_eq!(updates.last().unwrap().seq, ledger.tx_seq());
+    }
+
+    #[test]
+    fn quarantines_rows_of_csv_source() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     refund,1,2,1.0\r\n\
+                     withdrawal,1,3,2.0\n";
+        let mut quarantined = Vec::new();
+        let stats = Pipeline::new(CsvSource::new(input.as_bytes()))
+            .quarantine(Quarantine::new(&mut quarantined))
+            .run(&mut Ledger::new())
+            .unwrap();
+        assert_eq!(stats.rows_quarantined, 2);
+        assert_eq!(
+            String::from_utf8(quarantined).unwrap(),
+            "type,client,tx,amount,error_code,line_number,error_column,error_value,error_reason\n\
+             refund,1,2,1.0,invalid_tx_type,3,type,refund,unknown_tx_type\n\
+             withdrawal,1,3,2.0,insufficient_funds,4,,,\n"
+        );
+    }
+
+    #[test]
+    fn tagged_rows_are_written_to_the_audit_log_and_counted() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5000\n\
+                     withdrawal,1,2,1200\n\
+                     withdrawal,2,3,1500\n\
+                     deposit,1,x,1\n\
+                     withdrawal,1,4,10\n";
+        let debit = |rule: TagPredicates| TagPredicates {
+            kind: Some(TxTypeTag::Withdrawal),
+            ..rule
+        };
+        let rules = TagRules::new(vec![
+            TagRule {
+                name: "large_debit".to_string(),
+                when: debit(TagPredicates {
+                    amount_gt: Some(dec!(1000)),
+                    ..TagPredicates::default()
+                }),
+            },
+            TagRule {
+                name: "failed_debit".to_string(),
+                when: debit(TagPredicates {
+                    is_rejected: Some(true),
+                    ..TagPredicates::default()
+                }),
+            },
+            TagRule {
+                name: "unused".to_string(),
+                when: TagPredicates {
+                    kind: Some(TxTypeTag::Chargeback),
+                    ..TagPredicates::default()
+                },
+            },
+        ]);
+        let mut log = Vec::new();
+        let (_ledger, stats) = Pipeline::new(CsvSource::new(input.as_bytes()))
+            .audit(AuditLog::new(&mut log))
+            .tag_rules(rules)
+            .into_ledger(LedgerConfig::default())
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(log).unwrap(),
+            "line,outcome,type,client,tx,amount,error_code,tags\n\
+             2,executed,deposit,1,1,5000,,\n\
+             3,executed,withdrawal,1,2,1200,,large_debit\n\
+             4,rejected,withdrawal,2,3,1500,client_account_not_found,large_debit;failed_debit\n\
+             5,malformed,,,,,invalid_tx_type,\n\
+             6,executed,withdrawal,1,4,10,,\n"
+        );
+        assert_eq!(
+            stats.tag_counts,
+            BTreeMap::from([
+                ("failed_debit".to_string(), 1),
+                ("large_debit".to_string(), 2),
+                ("unused".to_string(), 0),
+            ])
+        );
+    }
+
+    #[test]
+    fn derived_postings_are_audited_after_their_row() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,\n\
+                     deposit,1,2,1.0\n";
+        let mut audited = Vec::new();
+        let mut ledger = Ledger::builder()
+            .posting_rule(ChargebackFeeRule { amount: dec!(15) })
+            .build();
+        let stats = Pipeline::new(CsvSource::new(input.as_bytes()))
+            .audit(|line: Option<u64>, outcome: &RowOutcome<'_>| {
+                audited.push(match outcome {
+                    RowOutcome::Executed(tx) => (line, tx.tx_id(), None),
+                    RowOutcome::Derived(derived) => {
+                        assert_eq!(derived.result, Ok(()));
+                        (line, derived.posting.tx_id, Some(derived.posting.cause))
+                    }
+                    _ => unreachable!(),
+                });
+                Ok(())
+            })
+            .run(&mut ledger)
+            .unwrap();
+        assert_eq!(
+            audited,
+            [
+                (Some(2), 1, None),
+                (Some(3), 1, None),
+                (Some(4), 1, None),
+                (Some(4), DERIVED_TX_ID_START, Some(1)),
+                (Some(5), 2, None),
+            ]
+        );
+        assert_eq!((stats.rows_executed, stats.derived_postings), (4, 1));
+        assert_eq!(stats.derived_postings_rejected, 0);
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(-14.0));
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn stats_carry_the_execution_digest() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     withdrawal,1,2,2.0\n";
+        let run = |mut ledger: Ledger| {
+            let stats = Pipeline::new(CsvSource::new(input.as_bytes()))
+                .run(&mut ledger)
+                .unwrap();
+            assert_eq!(stats.execution_digest, ledger.execution_digest());
+            stats.execution_digest
+        };
+        let digest = run(Ledger::builder().execution_digest().build());
+        assert!(digest.is_some());
+        assert_eq!(run(Ledger::builder().execution_digest().build()), digest);
+        assert_eq!(run(Ledger::new()), None);
+    }
+
+    #[test]
+    fn run_id_reaches_sinks_and_stats() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     withdrawal,1,2,2.0\n";
+        let run_id: RunId = "job-7".parse().unwrap();
+        let mut started = None;
+        let mut quarantined = Vec::new();
+        let stats = Pipeline::new(CsvSource::new(input.as_bytes()))
+            .audit(StartRecorder(&mut started))
+            .quarantine(Quarantine::new(&mut quarantined).stamp_run_id(true))
+            .run_id(run_id.clone())
+            .run(&mut Ledger::new())
+            .unwrap();
+        assert_eq!(stats.run_id, Some(run_id.clone()));
+        assert_eq!(started, Some(run_id));
+        assert_eq!(
+            String::from_utf8(quarantined).unwrap(),
+            "type,client,tx,amount,error_code,line_number,error_column,error_value,error_reason,run_id\n\
+             withdrawal,1,2,2.0,insufficient_funds,3,,,,job-7\n"
+        );
+
+        let generated = || {
+            Pipeline::new(CsvSource::new(input.as_bytes()))
+                .run(&mut Ledger::new())
+                .unwrap()
+                .run_id
+        };
+        assert_ne!(generated(), generated());
+    }
+
+    struct StartRecorder<'a>(&'a mut Option<RunId>);
+
+    impl AuditSink for StartRecorder<'_> {
+        fn record(
+            &mut self,
+            _line: Option<u64>,
+            _outcome: &RowOutcome<'_>,
+        ) -> Result<(), SinkError> {
+            Ok(())
+        }
+
+        fn start_run(&mut self, run_id: &RunId) {
+            *self.0 = Some(run_id.clone());
+        }
+    }
+
+    #[test]
+    fn malformed_rows_carry_the_field_at_fault() {
+        let input = "type,client,tx,amount\n\
+                     dépôt,1,1,1.0\n\
+                     \"dep,osit\",1,2,1.0\n\
+                     deposit,65536,3,1.0\n\
+                     deposit,1,4,1.2.3.4.5\n\
+                     deposit,1,5,\n";
+        let mut fields = Vec::new();
+        let mut quarantined = Vec::new();
+        Pipeline::new(CsvSource::new(input.as_bytes()))
+            .audit(|line, outcome: &RowOutcome<'_>| {
+                if let RowOutcome::Malformed(_err, field) = outcome {
+                    fields.push((line, field.map(|field| field.column_name.clone())));
+                }
+                Ok(())
+            })
+            .quarantine(Quarantine::new(&mut quarantined).with_max_value_chars(6))
+            .run(&mut Ledger::new())
+            .unwrap();
+        assert_eq!(
+            fields,
+            vec![
+                (Some(2), Some("type".to_string())),
+                (Some(3), Some("type".to_string())),
+                (Some(4), Some("client".to_string())),
+                (Some(5), Some("amount".to_string())),
+                (Some(6), None),
+            ]
+        );
+        assert_eq!(
+            String::from_utf8(quarantined).unwrap(),
+            "type,client,tx,amount,error_code,line_number,error_column,error_value,error_reason\n\
+             dépôt,1,1,1.0,invalid_tx_type,2,type,dépôt,unknown_tx_type\n\
+             \"dep,osit\",1,2,1.0,invalid_tx_type,3,type,\"dep,o…\",unknown_tx_type\n\
+             deposit,65536,3,1.0,invalid_tx_type,4,client,65536,id_overflow\n\
+             deposit,1,4,1.2.3.4.5,trailing_characters_in_amount,5,amount,1.2.3…,\
+             trailing_characters at offset 3\n\
+             deposit,1,5,,missing_amount,6,,,\n"
+        );
+    }
+
+    #[test]
+    fn quarantines_ambiguous_amounts() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,\"1.234,5\"\n\
+                     deposit,1,2,\"12,5\"\n\
+                     deposit,1,3,\"1,234\"\n";
+        let mut quarantined = Vec::new();
+        let mut ledger = Ledger::new();
+        let stats =
+            Pipeline::new(CsvSource::new(input.as_bytes()).with_number_locale(NumberLocale::Auto))
+                .quarantine(Quarantine::new(&mut quarantined))
+                .run(&mut ledger)
+                .unwrap();
+        assert_eq!(stats.rows_executed, 2);
+        assert_eq!(
+            ledger.account(1).map(|account| account.available()),
+            Some(dec!(1247.0))
+        );
+        assert_eq!(
+            String::from_utf8(quarantined).unwrap(),
+            "type,client,tx,amount,error_code,line_number,error_column,error_value,error_reason\n\
+             deposit,1,3,\"1,234\",ambiguous_amount,4,,,\n"
+        );
+    }
+
+    #[test]
+    fn counts_dispute_amount_mismatches() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     dispute,1,1,10\n\
+                     resolve,1,1,\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,12.5\n";
+        let mut ledger = Ledger::builder()
+            .dispute_amount_handling(DisputeAmountHandling::WarnOnMismatch)
+            .build();
+        let stats = Pipeline::new(CsvSource::new(input.as_bytes()))
+            .run(&mut ledger)
+            .unwrap();
+        assert_eq!(stats.rows_executed, 5);
+        assert_eq!(stats.dispute_amount_mismatches, 1);
+    }
+
+    #[test]
+    fn quarantines_amounts_left_unparseable() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,$25.00\n\
+                     deposit,1,2,1.5E+2\n\
+                     deposit,1,3,\"\u{a0}12.50\u{a0}\"\n\
+                     deposit,1,4,$12x\n";
+        let mut quarantined = Vec::new();
+        let mut ledger = Ledger::new();
+        let stats = Pipeline::new(
+            CsvSource::new(input.as_bytes()).with_amount_parsing(AmountParsing::permissive()),
+        )
+        .quarantine(Quarantine::new(&mut quarantined))
+        .run(&mut ledger)
+        .unwrap();
+        assert_eq!(stats.rows_executed, 3);
+        assert_eq!(
+            ledger.account(1).map(|account| account.available()),
+            Some(dec!(187.5))
+        );
+        // The value is reported as it is in the input, symbol included
+        assert_eq!(
+            String::from_utf8(quarantined).unwrap(),
+            "type,client,tx,amount,error_code,line_number,error_column,error_value,error_reason\n\
+             deposit,1,4,$12x,trailing_characters_in_amount,5,amount,$12x,\
+             trailing_characters at offset 2\n"
+        );
+    }
+
+    #[test]
+    fn panicking_transaction_is_recorded_and_run_continues() {
+        let source = vec![
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(4))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ];
+        let mut ledger = Ledger::new();
+        ledger.panic_on_tx(2);
+        let mut rejected = Vec::new();
+        let stats = Pipeline::new(source.into_iter())
+            .audit(|_line, outcome: &RowOutcome<'_>| {
+                if let RowOutcome::Rejected(tx, err) = outcome {
+                    rejected.push((tx.tx_id(), err.code()));
+                }
+                Ok(())
+            })
+            .run(&mut ledger)
+            .unwrap();
+        assert_eq!(rejected, vec![(2, "internal_panic")]);
+        assert_eq!(stats.rows_executed, 3);
+        assert_eq!(stats.rows_rejected, 1);
+        assert_eq!(stats.rows_panicked, 1);
+        assert_eq!(stats.poison_check, Some(Ok(())));
+        assert!(ledger.is_poisoned());
+        assert_eq!(ledger.account(1).unwrap().available(), dec!(-4));
+        assert_eq!(ledger.account(1).unwrap().held(), dec!(10));
+    }
+
+    #[test]
+    fn retried_disputes_are_duplicates_under_idempotent_policy() {
+        let source = || {
+            vec![
+                Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+                Transaction::Deposit(Deposit::new(1, 2, dec!(5))),
+                // Never disputed, so not a retry.
+                Transaction::Resolve(Resolve::new(1, 1)),
+                Transaction::Dispute(Dispute::new(1, 1)),
+                Transaction::Dispute(Dispute::new(1, 1)),
+                Transaction::Resolve(Resolve::new(1, 1)),
+                Transaction::Resolve(Resolve::new(1, 1)),
+                Transaction::Dispute(Dispute::new(1, 2)),
+                Transaction::Chargeback(Chargeback::new(1, 2)),
+                Transaction::Chargeback(Chargeback::new(1, 2)),
+                // Final after a chargeback, not a retry.
+                Transaction::Dispute(Dispute::new(1, 2)),
+                // Another client's transaction is never a duplicate.
+                Transaction::Resolve(Resolve::new(2, 1)),
+            ]
+            .into_iter()
+        };
+        let run = |policy| {
+            let mut duplicates = Vec::new();
+            let (ledger, stats) = Pipeline::new(source())
+                .audit(|_line, outcome: &RowOutcome<'_>| {
+                    if let RowOutcome::Duplicate(tx) = outcome {
+                        duplicates.push(tx.tx_id());
+                    }
+                    Ok(())
+                })
+                .into_ledger(LedgerConfig {
+                    duplicate_dispute_policy: policy,
+                    ..LedgerConfig::default()
+                })
+                .unwrap();
+            ledger.verify_invariants().unwrap();
+            (
+                AccountView::from(ledger.account(1).unwrap()),
+                stats,
+                duplicates,
+            )
+        };
+
+        let (strict, strict_stats, strict_duplicates) = run(DuplicateDisputePolicy::Error);
+        let (idempotent, idempotent_stats, idempotent_duplicates) =
+            run(DuplicateDisputePolicy::Idempotent);
+        assert_eq!(strict, idempotent);
+        assert_eq!(idempotent.available, dec!(10));
+        assert!(idempotent.locked);
+        assert_eq!(
+            (strict_stats.rows_executed, strict_stats.rows_duplicate),
+            (6, 0)
+        );
+        assert_eq!(strict_stats.rows_rejected, 6);
+        assert!(strict_duplicates.is_empty());
+        assert_eq!(
+            (
+                idempotent_stats.rows_executed,
+                idempotent_stats.rows_duplicate
+            ),
+            (6, 3)
+        );
+        assert_eq!(idempotent_stats.rows_rejected, 3);
+        assert_eq!(idempotent_duplicates, vec![1, 1, 2]);
+    }
+
+    fn deposits(count: u32) -> Vec<Transaction> {
+        (1..=count)
+            .map(|tx_id| Transaction::Deposit(Deposit::new(tx_id as u16, tx_id, dec!(1))))
+            .collect()
+    }
+
+    #[test]
+    fn audit_sink_failure_policies() {
+        let run = |policy| {
+            let mut sink = FlakySink::new(2);
+            let mut ledger = Ledger::new();
+            let stats = Pipeline::new(deposits(5).into_iter())
+                .audit(|_line, _outcome: &RowOutcome<'_>| sink.send())
+                .sink_failure_policy(policy)
+                .run(&mut ledger)
+                .unwrap();
+            (stats, sink.calls, ledger.accounts_iter().count())
+        };
+
+        let (stats, calls, accounts) = run(SinkFailurePolicy::Ignore);
+        assert_eq!((stats.rows_executed, stats.events_dropped), (5, 2));
+        assert_eq!((stats.sink_error, calls, accounts), (None, 5, 5));
+
+        let (stats, calls, accounts) = run(SinkFailurePolicy::RetryN(2));
+        assert_eq!((stats.rows_executed, stats.events_dropped), (5, 0));
+        assert_eq!((stats.sink_error, calls, accounts), (None, 7, 5));
+
+        let (stats, calls, accounts) = run(SinkFailurePolicy::RetryN(1));
+        assert_eq!(stats.rows_executed, 1);
+        assert_eq!(stats.sink_error, Some(SinkError::new("call 2 failed")));
+        assert_eq!((calls, accounts), (2, 1));
+
+        let (stats, calls, accounts) = run(SinkFailurePolicy::Abort);
+        assert_eq!(stats.rows_executed, 1);
+        assert_eq!(stats.sink_error, Some(SinkError::new("call 1 failed")));
+        assert_eq!((calls, accounts), (1, 1));
+    }
+
+    #[test]
+    fn event_listener_failure_stops_run_after_applied_row() {
+        let run = |policy| {
+            let mut source = deposits(4);
+            source.insert(3, Transaction::Dispute(Dispute::new(2, 2)));
+            let mut sink = FlakySink::new(1);
+            let mut ledger = Ledger::builder()
+                .event_listener(move |_event| sink.send())
+                .event_failure_policy(policy)
+                .build();
+            let stats = Pipeline::new(source.into_iter()).run(&mut ledger).unwrap();
+            (stats, ledger)
+        };
+
+        let (stats, ledger) = run(SinkFailurePolicy::Ignore);
+        assert_eq!((stats.rows_executed, stats.events_dropped), (5, 1));
+        assert_eq!(stats.sink_error, None);
+        assert_eq!(ledger.events_dropped(), 1);
+
+        // The dispute is the fourth row, and stays applied.
+        let (stats, ledger) = run(SinkFailurePolicy::Abort);
+        assert_eq!((stats.rows_executed, stats.events_dropped), (4, 0));
+        assert_eq!(stats.sink_error, Some(SinkError::new("call 1 failed")));
+        assert_eq!(ledger.account(2).unwrap().held(), dec!(1));
+        assert!(ledger.account(4).is_none());
+        ledger.verify_invariants().unwrap();
+    }
+}
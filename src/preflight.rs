@@ -0,0 +1,490 @@
+//! Structural validation of a whole input file before any transaction is executed.
+use crate::accounting::transactions::{
+    Transaction, TransactionLog, TransactionLogError, TxTypeTag,
+};
+use crate::accounting::Ledger;
+use crate::amount_scale::DEFAULT_AMOUNT_SCALE;
+use crate::core_types::TxId;
+use crate::engine::{read_rows, RowReader};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::BufRead;
+use std::ops::ControlFlow;
+
+/// Maximum number of decimal places of an amount, the default `AmountScale`.
+pub const MAX_AMOUNT_SCALE: u32 = DEFAULT_AMOUNT_SCALE;
+
+const PAGE_BITS: usize = 1 << 16;
+
+/// Set of transaction ids backed by bitmap pages of 2^16 ids, allocated on first use,
+/// so memory stays bounded by 512 MiB for the whole `TxId` range and is much lower
+/// for ids clustered in a few ranges.
+#[derive(Default)]
+pub(crate) struct TxIdSet {
+    pages: HashMap<u16, Box<[u64]>>,
+}
+
+impl TxIdSet {
+    /// Returns whether the id was not in the set yet.
+    pub(crate) fn insert(&mut self, tx_id: TxId) -> bool {
+        let (page, word, bit) = Self::locate(tx_id);
+        let page = self
+            .pages
+            .entry(page)
+            .or_insert_with(|| vec![0; PAGE_BITS / 64].into_boxed_slice());
+        let newly_inserted = page[word] & bit == 0;
+        page[word] |= bit;
+        newly_inserted
+    }
+
+    pub(crate) fn contains(&self, tx_id: TxId) -> bool {
+        let (page, word, bit) = Self::locate(tx_id);
+        self.pages
+            .get(&page)
+            .is_some_and(|page| page[word] & bit != 0)
+    }
+
+    fn locate(tx_id: TxId) -> (u16, usize, u64) {
+        let offset = tx_id as usize % PAGE_BITS;
+        ((tx_id >> 16) as u16, offset / 64, 1 << (offset % 64))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PreflightIssue {
+    Malformed(TransactionLogError),
+    /// A deposit, withdrawal or withdrawal authorization reuses the id of an earlier one.
+    DuplicateTxId(TxId),
+    /// A dispute, resolve, chargeback, adjustment, capture or release references an id
+    /// missing from the file.
+    UnknownTxReference(TxId),
+    /// A reference precedes the transaction it references, which fails at execution.
+    ReferenceBeforeTx(TxId),
+    ExcessPrecision {
+        tx_id: TxId,
+        amount: Decimal,
+    },
+}
+
+impl Display for PreflightIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreflightIssue::Malformed(
+                error @ TransactionLogError::TrailingCharactersInAmount { raw, at_offset },
+            ) => write!(
+                f,
+                "malformed row: {} at offset {} of {:?}",
+                error, at_offset, raw
+            ),
+            PreflightIssue::Malformed(error) => write!(f, "malformed row: {}", error),
+            PreflightIssue::DuplicateTxId(tx_id) => write!(f, "duplicate tx {}", tx_id),
+            PreflightIssue::UnknownTxReference(tx_id) => {
+                write!(f, "reference to unknown tx {}", tx_id)
+            }
+            PreflightIssue::ReferenceBeforeTx(tx_id) => {
+                write!(f, "reference to tx {} before it appears", tx_id)
+            }
+            PreflightIssue::ExcessPrecision { tx_id, amount } => write!(
+                f,
+                "tx {} amount {} has more than {} decimal places",
+                tx_id, amount, MAX_AMOUNT_SCALE
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PreflightFinding {
+    pub line: u64,
+    pub issue: PreflightIssue,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PreflightReport {
+    pub rows: u64,
+    /// Findings which prevent execution, ordered by line.
+    pub errors: Vec<PreflightFinding>,
+    /// Findings reported without preventing execution, ordered by line.
+    pub warnings: Vec<PreflightFinding>,
+}
+
+impl PreflightReport {
+    pub fn is_clean(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl Display for PreflightReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (severity, findings) in [("error", &self.errors), ("warning", &self.warnings)] {
+            for finding in findings {
+                writeln!(f, "line {}: {}: {}", finding.line, severity, finding.issue)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the whole input and reports structural problems. References preceding
+/// their transaction are warnings unless `strict_order` is set.
+pub fn preflight(reader: impl BufRead, strict_order: bool) -> std::io::Result<PreflightReport> {
+    let mut report = PreflightReport::default();
+    let mut tx_ids = TxIdSet::default();
+    let mut early_references = Vec::new();
+    read_rows(reader, |row| {
+        report.rows += 1;
+        let log = match row.log {
+            Ok(log) => log,
+            Err(error) => {
+                report.errors.push(PreflightFinding {
+                    line: row.line,
+                    issue: PreflightIssue::Malformed(error),
+                });
+                return;
+            }
+        };
+        let tx_id = log.tx_id();
+        match log.tx_type() {
+            TxTypeTag::Deposit | TxTypeTag::Withdrawal | TxTypeTag::WithdrawAuth => {
+                if !tx_ids.insert(tx_id) {
+                    report.errors.push(PreflightFinding {
+                        line: row.line,
+                        issue: PreflightIssue::DuplicateTxId(tx_id),
+                    });
+                }
+                match log.required_amount() {
+                    Ok(amount) if amount.normalize().scale() > MAX_AMOUNT_SCALE => {
+                        report.errors.push(PreflightFinding {
+                            line: row.line,
+                            issue: PreflightIssue::ExcessPrecision { tx_id, amount },
+                        });
+                    }
+                    Ok(_amount) => {}
+                    Err(error) => report.errors.push(PreflightFinding {
+                        line: row.line,
+                        issue: PreflightIssue::Malformed(error),
+                    }),
+                }
+            }
+            TxTypeTag::Dispute
+            | TxTypeTag::Resolve
+            | TxTypeTag::Chargeback
+            | TxTypeTag::Adjustment
+            | TxTypeTag::WithdrawCapture
+            | TxTypeTag::WithdrawRelease
+            | TxTypeTag::Settle => {
+                if let Err(error) = log.read_amount() {
+                    report.errors.push(PreflightFinding {
+                        line: row.line,
+                        issue: PreflightIssue::Malformed(error),
+                    });
+                }
+                if !tx_ids.contains(tx_id) {
+                    early_references.push((row.line, tx_id));
+                }
+            }
+        }
+    })?;
+
+    for (line, tx_id) in early_references {
+        if !tx_ids.contains(tx_id) {
+            report.errors.push(PreflightFinding {
+                line,
+                issue: PreflightIssue::UnknownTxReference(tx_id),
+            });
+        } else {
+            let finding = PreflightFinding {
+                line,
+                issue: PreflightIssue::ReferenceBeforeTx(tx_id),
+            };
+            if strict_order {
+                report.errors.push(finding);
+            } else {
+                report.warnings.push(finding);
+            }
+        }
+    }
+    report.errors.sort_by_key(|finding| finding.line);
+    Ok(report)
+}
+
+/// Whether a finding of `check_rows` is about the shape of a row or its execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingClass {
+    /// The ledger would reject the transaction of the row.
+    Rejected,
+    /// The row is malformed, reuses a transaction id or has an amount too precise.
+    Structural,
+}
+
+/// Row which would not be applied, see `check_rows`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckFinding {
+    pub line: u64,
+    pub class: FindingClass,
+    /// `TxError::code` of a rejected row, `TransactionLogError::code` of a malformed one,
+    /// or `duplicate_tx_id` and `excess_precision`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Counts of `check_rows`, displayed as `checked=N ok=N rejected=K structural=J`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CheckSummary {
+    pub checked: u64,
+    pub ok: u64,
+    pub rejected: u64,
+    pub structural: u64,
+}
+
+impl CheckSummary {
+    /// 0 for a clean input, 1 when rows would be rejected and 2 when any is structurally
+    /// wrong.
+    pub fn exit_code(&self) -> i32 {
+        if self.structural > 0 {
+            2
+        } else if self.rejected > 0 {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+impl Display for CheckSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "checked={} ok={} rejected={} structural={}",
+            self.checked, self.ok, self.rejected, self.structural
+        )
+    }
+}
+
+/// Dry run of the input against `ledger`, reading it row by row. Rows which would not
+/// be applied are passed to `on_finding`, which stops the check by returning
+/// `ControlFlow::Break`.
+///
+/// Structural problems are those `preflight` reports as errors, except references to
+/// unknown transactions, which can only be told apart once the whole input was read
+/// and are rejected executions here.
+pub fn check_rows(
+    reader: impl BufRead,
+    ledger: &mut Ledger,
+    mut on_finding: impl FnMut(CheckFinding) -> ControlFlow<()>,
+) -> std::io::Result<CheckSummary> {
+    let mut summary = CheckSummary::default();
+    let mut tx_ids = TxIdSet::default();
+    let mut rows = RowReader::new(reader);
+    while let Some(row) = rows.next_row()? {
+        summary.checked += 1;
+        let finding = match structural_issue(row.log, &mut tx_ids) {
+            Err((code, message)) => {
+                summary.structural += 1;
+                Some((FindingClass::Structural, code, message))
+            }
+            Ok(tx) => match ledger.execute_catch_unwind(&tx) {
+                Ok(_outcome) => {
+                    summary.ok += 1;
+                    None
+                }
+                Err(err) => {
+                    summary.rejected += 1;
+                    let message = format!("tx {} rejected: {}", tx.tx_id(), err);
+                    Some((FindingClass::Rejected, err.code(), message))
+                }
+            },
+        };
+        if let Some((class, code, message)) = finding {
+            let finding = CheckFinding {
+                line: row.line,
+                class,
+                code,
+                message,
+            };
+            if on_finding(finding).is_break() {
+                break;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+fn structural_issue(
+    log: Result<TransactionLog, TransactionLogError>,
+    tx_ids: &mut TxIdSet,
+) -> Result<Transaction, (&'static str, String)> {
+    let malformed =
+        |error: TransactionLogError| (error.code(), PreflightIssue::Malformed(error).to_string());
+    let log = log.map_err(malformed)?;
+    let tx_id = log.tx_id();
+    if matches!(
+        log.tx_type(),
+        TxTypeTag::Deposit | TxTypeTag::Withdrawal | TxTypeTag::WithdrawAuth
+    ) {
+        if !tx_ids.insert(tx_id) {
+            return Err((
+                "duplicate_tx_id",
+                PreflightIssue::DuplicateTxId(tx_id).to_string(),
+            ));
+        }
+        if let Some(amount) = log.amount() {
+            if amount.normalize().scale() > MAX_AMOUNT_SCALE {
+                let issue = PreflightIssue::ExcessPrecision { tx_id, amount };
+                return Err(("excess_precision", issue.to_string()));
+            }
+        }
+    }
+    Transaction::try_from(log).map_err(malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn tx_id_set() {
+        let mut set = TxIdSet::default();
+        for tx_id in [0, 63, 64, 65_535, 65_536, TxId::MAX] {
+            assert!(!set.contains(tx_id));
+            assert!(set.insert(tx_id));
+            assert!(!set.insert(tx_id));
+            assert!(set.contains(tx_id));
+        }
+        assert!(!set.contains(1));
+        assert!(!set.contains(TxId::MAX - 1));
+        assert_eq!(set.pages.len(), 3);
+    }
+
+    #[test]
+    fn clean_file() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    deposit, 2, 2, 2.12340\n\
+                    withdrawal, 1, 3, 0.5\n\
+                    dispute, 2, 2,\n\
+                    resolve, 2, 2,\n";
+        let report = preflight(data.as_bytes(), true).unwrap();
+        assert_eq!(
+            report,
+            PreflightReport {
+                rows: 5,
+                ..PreflightReport::default()
+            }
+        );
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn structural_findings() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    dispute, 2, 4,\n\
+                    deposit, 1, 2, 1.00001\n\
+                    \n\
+                    withdrawal, 1, 1, 0.5\n\
+                    refund, 1, 3, 1.0\n\
+                    chargeback, 1, 9,\n\
+                    deposit, 2, 4, 3.0\n\
+                    withdrawal, 2, 5,\n";
+        let report = preflight(data.as_bytes(), false).unwrap();
+        assert_eq!(
+            report.errors,
+            vec![
+                PreflightFinding {
+                    line: 4,
+                    issue: PreflightIssue::ExcessPrecision {
+                        tx_id: 2,
+                        amount: dec!(1.00001)
+                    },
+                },
+                PreflightFinding {
+                    line: 6,
+                    issue: PreflightIssue::DuplicateTxId(1),
+                },
+                PreflightFinding {
+                    line: 7,
+                    issue: PreflightIssue::Malformed(TransactionLogError::InvalidTxType),
+                },
+                PreflightFinding {
+                    line: 8,
+                    issue: PreflightIssue::UnknownTxReference(9),
+                },
+                PreflightFinding {
+                    line: 10,
+                    issue: PreflightIssue::Malformed(TransactionLogError::MissingAmount),
+                },
+            ]
+        );
+        assert_eq!(
+            report.warnings,
+            vec![PreflightFinding {
+                line: 3,
+                issue: PreflightIssue::ReferenceBeforeTx(4),
+            }]
+        );
+        assert_eq!(report.rows, 8);
+    }
+
+    #[test]
+    fn strict_order() {
+        let data = "type, client, tx, amount\n\
+                    dispute, 1, 1,\n\
+                    deposit, 1, 1, 1.0\n";
+        let lenient = preflight(data.as_bytes(), false).unwrap();
+        assert!(lenient.is_clean());
+        assert_eq!(lenient.warnings.len(), 1);
+
+        let strict = preflight(data.as_bytes(), true).unwrap();
+        assert!(!strict.is_clean());
+        assert_eq!(
+            strict.errors,
+            vec![PreflightFinding {
+                line: 2,
+                issue: PreflightIssue::ReferenceBeforeTx(1),
+            }]
+        );
+        assert!(strict.warnings.is_empty());
+    }
+
+    #[test]
+    fn check_classifies_rows() {
+        let data = "type, client, tx, amount\n\
+                    dispute, 1, 1,\n\
+                    deposit, 1, 1, 1.0\n\
+                    deposit, 1, 1, 2.0\n\
+                    withdrawal, 1, 2, 0.5\n";
+        let mut findings = Vec::new();
+        let summary = check_rows(data.as_bytes(), &mut Ledger::new(), |finding| {
+            findings.push((finding.line, finding.class, finding.code));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(
+            findings,
+            vec![
+                (2, FindingClass::Rejected, "client_account_not_found"),
+                (4, FindingClass::Structural, "duplicate_tx_id"),
+            ]
+        );
+        assert_eq!(
+            summary.to_string(),
+            "checked=4 ok=2 rejected=1 structural=1"
+        );
+        assert_eq!(summary.exit_code(), 2);
+
+        let summary = check_rows(data.as_bytes(), &mut Ledger::new(), |_finding| {
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(
+            summary.to_string(),
+            "checked=1 ok=0 rejected=1 structural=0"
+        );
+        assert_eq!(summary.exit_code(), 1);
+    }
+}
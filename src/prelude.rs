@@ -0,0 +1,94 @@
+//! Types needed to embed the engine, importable with `use payments_engine::prelude::*`.
+pub use crate::accounting::causality::{CausalityStats, PendingLifecycle};
+pub use crate::accounting::config::{
+    AccountTiering, CausalityMode, ChargebackPolicy, DisputeAmountHandling, DisputeLimit,
+    DisputeTracking, DuplicateDisputePolicy, LedgerBuilder, LedgerConfig, LockPolicy,
+    ReasonAllowlist, UnknownClientOnDebit, UnknownReasonAction, VelocityAction, VelocityLimit,
+    WindowSpec,
+};
+pub use crate::accounting::digest::{LedgerDigest, DIGEST_VERSION};
+pub use crate::accounting::events::LedgerEvent;
+pub use crate::accounting::fork::{AccountDiff, LedgerFork, SimulationResult};
+pub use crate::accounting::journal::{journal_entries, read_journal, write_journal, JournalEntry};
+pub use crate::accounting::postings::{
+    ChargebackFeeRule, DerivedKind, DerivedOutcome, DerivedPosting, DerivedTransaction,
+    PostingRule, PostingRules, DERIVED_TX_ID_START,
+};
+pub use crate::accounting::reasons::{DisputeReason, MAX_REASON_LEN};
+pub use crate::accounting::sampling::{LiabilitySample, LiabilitySampling};
+pub use crate::accounting::shared::{AccountsSnapshot, ExecuteIfError, SharedLedger};
+pub use crate::accounting::storage::MemoryStats;
+pub use crate::accounting::transactions::{
+    Adjustment, Chargeback, Deposit, Dispute, Resolve, Settle, Transaction, TransactionLog,
+    TransactionLogError, TxTypeTag, WithdrawAuth, WithdrawCapture, WithdrawRelease, Withdrawal,
+    MAX_REFERENCE_LEN,
+};
+pub use crate::accounting::velocity::VelocityWindowState;
+pub use crate::accounting::view::{LedgerSummary, LedgerView};
+pub use crate::accounting::{
+    AccountLog, AccountView, ExecutableTransaction, ExtendedAccountLog, InvariantViolation, Ledger,
+    LiabilityDrift, MergeConflict, MergeError, MergePolicy, OpenDispute, OpenWithdrawalHold,
+    RemoveError, RemovedAccount, SettledDeposit, TotalsDrift, TxError, TxOutcome, TxRecord,
+    TxState, UnknownErrorCode, UnsettledWithdrawal, UserAccount, WithdrawalRecord,
+};
+pub use crate::admin_ops::{
+    apply_admin_ops, AdminOp, AdminOpError, AdminOpOutcome, AdminOpStatus, AdminOpsError,
+    AdminOpsMode, AdminOpsReport,
+};
+pub use crate::amount_scale::{AmountScale, ExcessDigits, DEFAULT_AMOUNT_SCALE};
+#[cfg(feature = "io")]
+pub use crate::checkpoint::{CheckpointError, CheckpointId, Checkpointer, Cursor, SnapshotError};
+pub use crate::clock::{Clock, ManualClock, SharedClock, SystemClock, Timestamp};
+pub use crate::compare::{
+    compare_reports, CompareError, CompareOptions, CompareReport, FieldDifference, ReportSide,
+};
+pub use crate::core_types::{ClientId, TxId};
+pub use crate::distribution::{
+    AmountSketch, AmountSummary, DistributionReport, DistributionTracker, KindDistribution,
+};
+pub use crate::engine::{
+    process_csv_str, write_transactions_csv, EngineError, PaymentsEngine, RunStats, TwoPassError,
+};
+pub use crate::enrichment::{ClientEnrichment, Enrichment, EnrichmentError};
+pub use crate::field_error::{FieldError, FieldErrorReason};
+#[cfg(feature = "io")]
+pub use crate::ingest::column_map::ColumnMap;
+#[cfg(feature = "io")]
+pub use crate::ingest::dedup::{DedupConfig, DedupFingerprint};
+#[cfg(feature = "io")]
+pub use crate::ingest::prefetch::IngestIoConfig;
+#[cfg(feature = "io")]
+pub use crate::ingest::{
+    IngestError, IngestOptions, IngestParser, IngestReport, RowError, SkipMode,
+};
+pub use crate::journal_export::{output_journal, JournalExportError, JournalLine};
+pub use crate::number_locale::{AmountParsing, NumberLocale};
+#[cfg(feature = "io")]
+pub use crate::output::{IdMapping, LineEnding, LineEndingWriter, OutputError, OutputSelection};
+pub use crate::partitioned_output::{
+    ModuloPartitioner, PartitionFile, PartitionManifest, PartitionedOutputError, Partitioner,
+    RangePartitioner,
+};
+pub use crate::pipeline::{
+    AccountSink, AccountUpdate, AuditLog, AuditSink, CsvSource, MetricsReporter, Pipeline,
+    Quarantine, QuarantineSink, ReplayFilter, RowOutcome, SourceRow, TransactionSource, TxFilter,
+};
+pub use crate::preflight::{
+    CheckFinding, CheckSummary, FindingClass, PreflightFinding, PreflightIssue, PreflightReport,
+};
+pub use crate::reconcile::{
+    reconcile, reconcile_with_tolerance, Discrepancy, DiscrepancyKind, ReconcileError,
+    ReconciliationReport,
+};
+#[cfg(feature = "io")]
+pub use crate::reports::HeldBreakdownError;
+pub use crate::run_id::{InvalidRunId, RunId};
+pub use crate::sink::{SinkError, SinkFailurePolicy};
+pub use crate::statements::{
+    generate_statement, generate_statements, StatementError, StatementRow,
+};
+pub use crate::tag_rules::{TagPredicates, TagRule, TagRuleError, TagRules};
+pub use crate::top_k::{TopAccount, TopDispute, TopKReport, TopKTracker, TopRejectedClient};
+#[cfg(feature = "io")]
+pub use crate::watch::{watch_directory, DirectoryWatcher, WatchConfig, WatchError, WatchEvent};
+pub use rust_decimal::Decimal;
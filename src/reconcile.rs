@@ -0,0 +1,444 @@
+//! Reconciliation of the balances at the end of a run against the balances another
+//! system expects, read from a file in the account output schema.
+use crate::accounting::view::LedgerView;
+use crate::amount_scale::fits;
+use crate::core_types::ClientId;
+use crate::run_id::RunId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+/// Row of the expected balances file. The `total` column of the account output is
+/// accepted and ignored, as are any further columns.
+#[derive(Deserialize)]
+struct ExpectedRow {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    /// Client in the expected file without an account in the ledger.
+    MissingInLedger,
+    /// Account in the ledger without a row in the expected file.
+    MissingInExpected,
+    /// Client on both sides whose balances differ by more than the tolerance, or whose
+    /// locked flags differ.
+    Mismatch,
+}
+
+/// One client which did not reconcile. Deltas are the ledger's balance minus the
+/// expected one, with a missing side counting as zero.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub kind: DiscrepancyKind,
+    pub expected_available: Option<Decimal>,
+    pub actual_available: Option<Decimal>,
+    pub available_delta: Decimal,
+    pub expected_held: Option<Decimal>,
+    pub actual_held: Option<Decimal>,
+    pub held_delta: Decimal,
+    pub expected_locked: Option<bool>,
+    pub actual_locked: Option<bool>,
+}
+
+/// Outcome of `reconcile`, with the discrepancies sorted by client.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ReconciliationReport {
+    pub tolerance: Decimal,
+    /// Clients on both sides within the tolerance.
+    pub clients_matched: usize,
+    pub discrepancies: Vec<Discrepancy>,
+    /// Sum of the available deltas of the discrepancies.
+    pub available_delta: Decimal,
+    /// Sum of the held deltas of the discrepancies.
+    pub held_delta: Decimal,
+    /// Run which reconciled, left out of the JSON when not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<RunId>,
+}
+
+impl ReconciliationReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    pub fn missing_in_ledger(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients_of(DiscrepancyKind::MissingInLedger)
+    }
+
+    pub fn missing_in_expected(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients_of(DiscrepancyKind::MissingInExpected)
+    }
+
+    pub fn mismatches(&self) -> impl Iterator<Item = &Discrepancy> + '_ {
+        self.discrepancies
+            .iter()
+            .filter(|discrepancy| discrepancy.kind == DiscrepancyKind::Mismatch)
+    }
+
+    fn clients_of(&self, kind: DiscrepancyKind) -> impl Iterator<Item = ClientId> + '_ {
+        self.discrepancies
+            .iter()
+            .filter(move |discrepancy| discrepancy.kind == kind)
+            .map(|discrepancy| discrepancy.client_id)
+    }
+
+    /// Writes one CSV row per discrepancy, with a header even when there are none.
+    pub fn write_csv(&self, writer: impl Write) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        if self.discrepancies.is_empty() {
+            writer.write_record([
+                "client",
+                "kind",
+                "expected_available",
+                "actual_available",
+                "available_delta",
+                "expected_held",
+                "actual_held",
+                "held_delta",
+                "expected_locked",
+                "actual_locked",
+            ])?;
+        }
+        for discrepancy in &self.discrepancies {
+            writer.serialize(discrepancy)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Display for ReconciliationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} clients matched, {} missing from the ledger, {} missing from the expected \
+             balances, {} mismatching; available off by {}, held off by {}",
+            self.clients_matched,
+            self.missing_in_ledger().count(),
+            self.missing_in_expected().count(),
+            self.mismatches().count(),
+            self.available_delta,
+            self.held_delta
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ReconcileError {
+    Csv(csv::Error),
+    DuplicateClient(ClientId),
+    /// An expected balance of the client has more decimals than the amount scale of
+    /// the ledger, see `LedgerBuilder::amount_scale`.
+    ExcessPrecision {
+        client_id: ClientId,
+        amount: Decimal,
+        scale: u32,
+    },
+}
+
+impl Display for ReconcileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReconcileError::Csv(err) => write!(f, "failed to read expected balances: {}", err),
+            ReconcileError::DuplicateClient(client_id) => {
+                write!(
+                    f,
+                    "client {} appears twice in the expected balances",
+                    client_id
+                )
+            }
+            ReconcileError::ExcessPrecision {
+                client_id,
+                amount,
+                scale,
+            } => write!(
+                f,
+                "expected balance {} of client {} has more than {} decimals",
+                amount, client_id, scale
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReconcileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReconcileError::Csv(err) => Some(err),
+            ReconcileError::DuplicateClient(_) | ReconcileError::ExcessPrecision { .. } => None,
+        }
+    }
+}
+
+impl From<csv::Error> for ReconcileError {
+    fn from(err: csv::Error) -> Self {
+        ReconcileError::Csv(err)
+    }
+}
+
+/// Compares the accounts of `ledger` against `expected`, CSV in the account output
+/// schema, requiring exact matches.
+pub fn reconcile<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    expected: impl Read,
+) -> Result<ReconciliationReport, ReconcileError> {
+    reconcile_with_tolerance(ledger, expected, Decimal::ZERO)
+}
+
+/// Like `reconcile`, accepting available and held balances which differ from the
+/// expected ones by at most `tolerance`. Locked flags must always match.
+///
+/// Under an amount scale, expected balances with more decimals fail with
+/// `ReconcileError::ExcessPrecision`, and the balances of the ledger are compared as
+/// the account report writes them.
+pub fn reconcile_with_tolerance<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    expected: impl Read,
+    tolerance: Decimal,
+) -> Result<ReconciliationReport, ReconcileError> {
+    let ledger: LedgerView = ledger.into();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(expected);
+    let mut expected = BTreeMap::new();
+    for row in reader.deserialize() {
+        let row: ExpectedRow = row?;
+        let client_id = row.client;
+        if let Some(scale) = ledger.amount_scale() {
+            for amount in [row.available, row.held] {
+                if !fits(amount, scale) {
+                    return Err(ReconcileError::ExcessPrecision {
+                        client_id,
+                        amount,
+                        scale,
+                    });
+                }
+            }
+        }
+        if expected.insert(client_id, row).is_some() {
+            return Err(ReconcileError::DuplicateClient(client_id));
+        }
+    }
+
+    let mut actual = ledger
+        .accounts_iter()
+        .map(|(client_id, user_account)| (*client_id, ledger.account_log(user_account)))
+        .collect::<BTreeMap<_, _>>();
+    let mut report = ReconciliationReport {
+        tolerance,
+        clients_matched: 0,
+        discrepancies: Vec::new(),
+        available_delta: Decimal::ZERO,
+        held_delta: Decimal::ZERO,
+        run_id: None,
+    };
+    for (client_id, row) in expected {
+        let discrepancy = match actual.remove(&client_id) {
+            None => Discrepancy {
+                client_id,
+                kind: DiscrepancyKind::MissingInLedger,
+                expected_available: Some(row.available),
+                actual_available: None,
+                available_delta: -row.available,
+                expected_held: Some(row.held),
+                actual_held: None,
+                held_delta: -row.held,
+                expected_locked: Some(row.locked),
+                actual_locked: None,
+            },
+            Some(account) => {
+                let available_delta = account.available - row.available;
+                let held_delta = account.held - row.held;
+                if available_delta.abs() <= tolerance
+                    && held_delta.abs() <= tolerance
+                    && account.locked == row.locked
+                {
+                    report.clients_matched += 1;
+                    continue;
+                }
+                Discrepancy {
+                    client_id,
+                    kind: DiscrepancyKind::Mismatch,
+                    expected_available: Some(row.available),
+                    actual_available: Some(account.available),
+                    available_delta,
+                    expected_held: Some(row.held),
+                    actual_held: Some(account.held),
+                    held_delta,
+                    expected_locked: Some(row.locked),
+                    actual_locked: Some(account.locked),
+                }
+            }
+        };
+        report.discrepancies.push(discrepancy);
+    }
+    for (client_id, account) in actual {
+        report.discrepancies.push(Discrepancy {
+            client_id,
+            kind: DiscrepancyKind::MissingInExpected,
+            expected_available: None,
+            actual_available: Some(account.available),
+            available_delta: account.available,
+            expected_held: None,
+            actual_held: Some(account.held),
+            held_delta: account.held,
+            expected_locked: None,
+            actual_locked: Some(account.locked),
+        });
+    }
+    report
+        .discrepancies
+        .sort_unstable_by_key(|discrepancy| discrepancy.client_id);
+    report.available_delta = report
+        .discrepancies
+        .iter()
+        .map(|discrepancy| discrepancy.available_delta)
+        .sum();
+    report.held_delta = report
+        .discrepancies
+        .iter()
+        .map(|discrepancy| discrepancy.held_delta)
+        .sum();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Dispute, Transaction};
+    use crate::accounting::Ledger;
+    use rust_decimal_macros::dec;
+
+    fn ledger() -> Ledger {
+        let mut ledger = Ledger::new();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10.0))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(5.0))),
+            Transaction::Deposit(Deposit::new(2, 3, dec!(2.5))),
+            Transaction::Dispute(Dispute::new(2, 3)),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        ledger
+    }
+
+    #[test]
+    fn perfectly_matching_balances() {
+        let expected = "client,available,held,total,locked\n\
+                        2, 5.00, 2.5, 7.5, false\n\
+                        1,10,0,10,false\n";
+        let report = reconcile(&ledger(), expected.as_bytes()).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.clients_matched, 2);
+        assert_eq!(
+            report.to_string(),
+            "2 clients matched, 0 missing from the ledger, 0 missing from the expected \
+             balances, 0 mismatching; available off by 0, held off by 0"
+        );
+    }
+
+    #[test]
+    fn clients_missing_on_either_side() {
+        let expected = "client,available,held,total,locked\n\
+                        1,10,0,10,false\n\
+                        3,4,1,5,true\n";
+        let report = reconcile(&ledger(), expected.as_bytes()).unwrap();
+        assert_eq!(report.missing_in_ledger().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(report.missing_in_expected().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(report.mismatches().count(), 0);
+        assert_eq!(report.available_delta, dec!(1));
+        assert_eq!(report.held_delta, dec!(1.5));
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,kind,expected_available,actual_available,available_delta,expected_held,\
+             actual_held,held_delta,expected_locked,actual_locked\n\
+             2,missing_in_expected,,5.0,5.0,,2.5,2.5,,false\n\
+             3,missing_in_ledger,4,,-4,1,,-1,true,\n"
+        );
+    }
+
+    #[test]
+    fn held_mismatch_and_tolerance_boundary() {
+        let expected = "client,available,held,total,locked\n\
+                        1,10,0,10,false\n\
+                        2,5,2.49,7.49,false\n";
+        let report = reconcile(&ledger(), expected.as_bytes()).unwrap();
+        assert_eq!(report.clients_matched, 1);
+        assert_eq!(
+            report.mismatches().collect::<Vec<_>>(),
+            vec![&Discrepancy {
+                client_id: 2,
+                kind: DiscrepancyKind::Mismatch,
+                expected_available: Some(dec!(5)),
+                actual_available: Some(dec!(5.0)),
+                available_delta: dec!(0),
+                expected_held: Some(dec!(2.49)),
+                actual_held: Some(dec!(2.5)),
+                held_delta: dec!(0.01),
+                expected_locked: Some(false),
+                actual_locked: Some(false),
+            }]
+        );
+
+        let at_tolerance =
+            reconcile_with_tolerance(&ledger(), expected.as_bytes(), dec!(0.01)).unwrap();
+        assert!(at_tolerance.is_clean());
+        let below_tolerance =
+            reconcile_with_tolerance(&ledger(), expected.as_bytes(), dec!(0.0099)).unwrap();
+        assert_eq!(below_tolerance.mismatches().count(), 1);
+    }
+
+    #[test]
+    fn duplicate_and_malformed_rows() {
+        let duplicate = "client,available,held,total,locked\n1,10,0,10,false\n1,10,0,10,false\n";
+        assert!(matches!(
+            reconcile(&ledger(), duplicate.as_bytes()),
+            Err(ReconcileError::DuplicateClient(1))
+        ));
+        let malformed = "client,available,held,total,locked\n1,ten,0,10,false\n";
+        assert!(matches!(
+            reconcile(&ledger(), malformed.as_bytes()),
+            Err(ReconcileError::Csv(_))
+        ));
+    }
+
+    #[test]
+    fn expected_balances_finer_than_the_amount_scale() {
+        let mut ledger = Ledger::builder().amount_scale(2).build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10.25))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(5))),
+        ] {
+            ledger.execute(&tx).unwrap();
+        }
+        let expected = "client,available,held,total,locked\n\
+                        1,10.250,0,10.25,false\n\
+                        2,5.00,0.00,5.00,false\n";
+        assert!(reconcile(&ledger, expected.as_bytes()).unwrap().is_clean());
+        let finer = "client,available,held,total,locked\n1,10.25,0.001,10.251,false\n";
+        let err = reconcile(&ledger, finer.as_bytes()).unwrap_err();
+        assert!(matches!(
+            err,
+            ReconcileError::ExcessPrecision {
+                client_id: 1,
+                scale: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "expected balance 0.001 of client 1 has more than 2 decimals"
+        );
+    }
+}
@@ -0,0 +1,669 @@
+use crate::accounting::reasons::DisputeReason;
+use crate::accounting::sampling::LiabilitySample;
+use crate::accounting::view::LedgerView;
+use crate::accounting::TxState;
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use tokio::io::AsyncWrite;
+
+#[derive(Serialize)]
+struct HeldBreakdownRow {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    tx_id: TxId,
+    kind: HoldKind,
+    amount: Decimal,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HoldKind {
+    /// Deposit disputed or held on arrival into a locked account.
+    Dispute,
+    /// Authorized withdrawal which was neither captured nor released.
+    Withdrawal,
+}
+
+#[derive(Serialize)]
+struct ChargebackRow<'a> {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    tx_id: TxId,
+    amount: Decimal,
+    reason: Option<&'a DisputeReason>,
+}
+
+#[derive(Serialize)]
+struct ActivityRow {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    deposited: Decimal,
+    withdrawn: Decimal,
+    charged_back: Decimal,
+    ending_available: Decimal,
+    ending_held: Decimal,
+}
+
+#[derive(Serialize)]
+struct RiskMetricsRow {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    disputes_opened: u32,
+    resolved: u32,
+    charged_back: u32,
+    chargeback_ratio: Decimal,
+}
+
+#[derive(Debug)]
+pub enum HeldBreakdownError {
+    /// Sum of open dispute and withdrawal hold amounts does not match the client's
+    /// held balance.
+    HeldMismatch {
+        client_id: ClientId,
+        held: Decimal,
+        disputed: Decimal,
+    },
+    Csv(csv_async::Error),
+}
+
+impl Display for HeldBreakdownError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeldBreakdownError::HeldMismatch {
+                client_id,
+                held,
+                disputed,
+            } => write!(
+                f,
+                "client {} held balance {} differs from the sum of open holds {}",
+                client_id, held, disputed
+            ),
+            HeldBreakdownError::Csv(err) => write!(f, "failed to write held breakdown: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for HeldBreakdownError {}
+
+impl From<csv_async::Error> for HeldBreakdownError {
+    fn from(err: csv_async::Error) -> Self {
+        HeldBreakdownError::Csv(err)
+    }
+}
+
+/// Writes one row per currently disputed deposit and open withdrawal hold, sorted by
+/// client and tx, telling them apart in the `kind` column.
+///
+/// Nothing is written when the holds of any client do not add up to its held balance.
+pub async fn output_held_breakdown<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), HeldBreakdownError> {
+    let ledger: LedgerView = ledger.into();
+    let mut rows = ledger
+        .open_disputes()
+        .map(|dispute| HeldBreakdownRow {
+            client_id: dispute.client_id,
+            tx_id: dispute.tx_id,
+            kind: HoldKind::Dispute,
+            amount: dispute.amount,
+        })
+        .chain(ledger.open_withdrawal_holds().map(|hold| HeldBreakdownRow {
+            client_id: hold.client_id,
+            tx_id: hold.tx_id,
+            kind: HoldKind::Withdrawal,
+            amount: hold.amount,
+        }))
+        .collect::<Vec<_>>();
+    rows.sort_unstable_by_key(|row| (row.client_id, row.tx_id));
+
+    let mut disputed_per_client: BTreeMap<ClientId, Decimal> = BTreeMap::new();
+    for row in &rows {
+        *disputed_per_client.entry(row.client_id).or_default() += row.amount;
+    }
+    for user_account in ledger.accounts_sorted() {
+        let client_id = user_account.client_id();
+        let disputed = disputed_per_client
+            .get(&client_id)
+            .copied()
+            .unwrap_or_default();
+        if disputed != user_account.held() {
+            return Err(HeldBreakdownError::HeldMismatch {
+                client_id,
+                held: user_account.held(),
+                disputed,
+            });
+        }
+    }
+
+    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(writer);
+    for row in rows {
+        writer.serialize(row).await?;
+    }
+    writer.flush().await.map_err(csv_async::Error::from)?;
+    Ok(())
+}
+
+/// Writes one `client,tx,amount,reason` row per currently disputed deposit, sorted by
+/// client and tx. Deposits held on arrival into a locked account are listed too,
+/// without a reason.
+pub async fn output_open_disputes<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), csv_async::Error> {
+    let ledger: LedgerView = ledger.into();
+    let mut disputes = ledger.open_disputes().collect::<Vec<_>>();
+    disputes.sort_unstable_by_key(|dispute| (dispute.client_id, dispute.tx_id));
+    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(writer);
+    for dispute in disputes {
+        writer.serialize(dispute).await?;
+    }
+    writer.flush().await.map_err(csv_async::Error::from)
+}
+
+/// Writes one `client,tx,amount,reason` row per charged back deposit whose record is
+/// kept, sorted by client and tx. The reason is the one of the chargeback, or else of
+/// the dispute.
+pub async fn output_chargebacks<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), csv_async::Error> {
+    let ledger: LedgerView = ledger.into();
+    let mut rows = ledger
+        .tx_records_iter()
+        .filter(|record| record.state() == TxState::ChargedBack)
+        .map(|record| ChargebackRow {
+            client_id: record.client_id(),
+            tx_id: record.tx_id(),
+            amount: record.amount(),
+            reason: record.reason(),
+        })
+        .collect::<Vec<_>>();
+    rows.sort_unstable_by_key(|row| (row.client_id, row.tx_id));
+    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(writer);
+    for row in rows {
+        writer.serialize(row).await?;
+    }
+    writer.flush().await.map_err(csv_async::Error::from)
+}
+
+/// Writes the deposits, withdrawals and chargebacks executed on the ledger and the
+/// ending balances of every client, sorted by client id.
+pub async fn output_activity_report<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), csv_async::Error> {
+    let ledger: LedgerView = ledger.into();
+    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(writer);
+    for user_account in ledger.accounts_sorted() {
+        writer
+            .serialize(ActivityRow {
+                client_id: user_account.client_id(),
+                deposited: user_account.total_deposited(),
+                withdrawn: user_account.total_withdrawn(),
+                charged_back: user_account.total_charged_back(),
+                ending_available: user_account.available(),
+                ending_held: user_account.held(),
+            })
+            .await?;
+    }
+    writer.flush().await.map_err(csv_async::Error::from)
+}
+
+/// Writes the dispute outcomes of every client for risk scoring, as
+/// `client,disputes_opened,resolved,charged_back,chargeback_ratio` rows, see
+/// `UserAccount::disputes_opened`. The ratio of charged back to opened disputes has
+/// four decimals and is 0 for clients without disputes. Rows are sorted by ratio,
+/// highest first, then by client id.
+pub async fn output_risk_metrics<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), csv_async::Error> {
+    let ledger: LedgerView = ledger.into();
+    let mut rows = ledger
+        .accounts_sorted()
+        .into_iter()
+        .map(|user_account| {
+            let opened = user_account.disputes_opened();
+            let charged_back = user_account.disputes_charged_back();
+            let mut chargeback_ratio = match opened {
+                0 => Decimal::ZERO,
+                _ => (Decimal::from(charged_back) / Decimal::from(opened)).round_dp(4),
+            };
+            chargeback_ratio.rescale(4);
+            RiskMetricsRow {
+                client_id: user_account.client_id(),
+                disputes_opened: opened,
+                resolved: user_account.disputes_resolved(),
+                charged_back,
+                chargeback_ratio,
+            }
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        b.chargeback_ratio
+            .cmp(&a.chargeback_ratio)
+            .then(a.client_id.cmp(&b.client_id))
+    });
+    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(writer);
+    for row in rows {
+        writer.serialize(row).await?;
+    }
+    writer.flush().await.map_err(csv_async::Error::from)
+}
+
+/// Writes the withdrawals not yet confirmed by a `settle` transaction with their payout
+/// reference, sorted by client and tx.
+pub async fn output_unsettled_withdrawals<'a>(
+    ledger: impl Into<LedgerView<'a>>,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), csv_async::Error> {
+    let ledger: LedgerView = ledger.into();
+    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(writer);
+    for withdrawal in ledger.unsettled_withdrawals() {
+        writer.serialize(withdrawal).await?;
+    }
+    writer.flush().await.map_err(csv_async::Error::from)
+}
+
+/// Writes the samples of `Ledger::liability_samples` as CSV, in the same format as
+/// `LedgerBuilder::liability_sampling_writer`.
+pub async fn output_liability_curve(
+    samples: &[LiabilitySample],
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), csv_async::Error> {
+    let mut writer = csv_async::AsyncWriterBuilder::new().create_serializer(writer);
+    for sample in samples {
+        writer.serialize(sample).await?;
+    }
+    writer.flush().await.map_err(csv_async::Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::config::{ChargebackPolicy, DuplicateDisputePolicy, LockPolicy};
+    use crate::accounting::sampling::LiabilitySampling;
+    use crate::accounting::transactions::{
+        Adjustment, Chargeback, Deposit, Dispute, Resolve, Settle, Transaction, WithdrawAuth,
+        WithdrawCapture, Withdrawal,
+    };
+    use crate::accounting::Ledger;
+    use rust_decimal_macros::dec;
+
+    fn disputed_ledger() -> Ledger {
+        let mut ledger = Ledger::new();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(100.0))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(50.0))),
+            Transaction::Deposit(Deposit::new(1, 3, dec!(200.0))),
+            Transaction::Deposit(Deposit::new(2, 4, dec!(25.5))),
+            Transaction::Deposit(Deposit::new(1, 5, dec!(0.25))),
+            Transaction::Dispute(Dispute::new(1, 3)),
+            Transaction::Dispute(Dispute::new(2, 4)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Dispute(Dispute::new(1, 5)),
+            Transaction::Resolve(Resolve::new(1, 5)),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        ledger
+    }
+
+    #[tokio::test]
+    async fn held_breakdown() {
+        let ledger = disputed_ledger();
+        let mut output = Vec::new();
+        output_held_breakdown(&ledger, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,kind,amount\n\
+             1,1,dispute,100.0\n\
+             1,3,dispute,200.0\n\
+             2,2,dispute,50.0\n\
+             2,4,dispute,25.5\n"
+        );
+        assert_eq!(ledger.account(1).unwrap().held(), dec!(300.0));
+        assert_eq!(ledger.account(2).unwrap().held(), dec!(75.5));
+    }
+
+    #[tokio::test]
+    async fn held_breakdown_with_withdrawal_holds() {
+        let mut ledger = disputed_ledger();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(2, 6, dec!(1.5))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(1, 7, dec!(0.2))),
+            Transaction::WithdrawAuth(WithdrawAuth::new(2, 8, dec!(0.3))),
+            Transaction::WithdrawCapture(WithdrawCapture::new(2, 8)),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        let mut output = Vec::new();
+        output_held_breakdown(&ledger, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,kind,amount\n\
+             1,1,dispute,100.0\n\
+             1,3,dispute,200.0\n\
+             1,7,withdrawal,0.2\n\
+             2,2,dispute,50.0\n\
+             2,4,dispute,25.5\n"
+        );
+        assert_eq!(ledger.account(1).unwrap().held(), dec!(300.2));
+    }
+
+    #[tokio::test]
+    async fn held_breakdown_without_disputes() {
+        let mut ledger = disputed_ledger();
+        for (client_id, tx_id) in [(1, 1), (1, 3), (2, 2), (2, 4)] {
+            ledger
+                .execute(&Transaction::Resolve(Resolve::new(client_id, tx_id)))
+                .unwrap();
+        }
+        let mut output = Vec::new();
+        output_held_breakdown(&ledger, &mut output).await.unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn held_breakdown_sum_mismatch() {
+        let mut ledger = disputed_ledger();
+        ledger.move_held_to_available(2, dec!(0.5));
+        let mut output = Vec::new();
+        let result = output_held_breakdown(&ledger, &mut output).await;
+        assert!(matches!(
+            result,
+            Err(HeldBreakdownError::HeldMismatch {
+                client_id: 2,
+                held,
+                disputed,
+            }) if held == dec!(75.0) && disputed == dec!(75.5)
+        ));
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn open_disputes_and_chargebacks_with_reasons() {
+        let mut ledger = disputed_ledger();
+        let reason = |reason: &str| reason.parse::<DisputeReason>().unwrap();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(3, 6, dec!(7))),
+            Transaction::Deposit(Deposit::new(3, 7, dec!(8))),
+            Transaction::Dispute(Dispute::new(3, 6).with_reason(reason("10.4"))),
+            Transaction::Dispute(Dispute::new(3, 7).with_reason(reason("13.1"))),
+            Transaction::Chargeback(Chargeback::new(3, 6)),
+            Transaction::Chargeback(Chargeback::new(3, 7).with_reason(reason("4853, \"x\""))),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+            Transaction::Dispute(Dispute::new(1, 5).with_reason(reason("13.1"))),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        let mut output = Vec::new();
+        output_open_disputes(&ledger, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount,reason\n\
+             1,1,100.0,\n\
+             1,3,200.0,\n\
+             1,5,0.25,13.1\n\
+             2,4,25.5,\n"
+        );
+        let mut output = Vec::new();
+        output_chargebacks(&ledger, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount,reason\n\
+             2,2,50.0,\n\
+             3,6,7,10.4\n\
+             3,7,8,\"4853, \"\"x\"\"\"\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn activity_report() {
+        let mut ledger = Ledger::new();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(30.0))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Chargeback(Chargeback::new(1, 2)),
+            Transaction::Deposit(Deposit::new(2, 4, dec!(60.0))),
+            Transaction::Withdrawal(Withdrawal::new(2, 5, dec!(20.0))),
+            Transaction::Dispute(Dispute::new(2, 4)),
+            Transaction::Chargeback(Chargeback::new(2, 4)),
+            Transaction::Deposit(Deposit::new(3, 6, dec!(5))),
+            Transaction::Deposit(Deposit::new(3, 7, dec!(2))),
+            Transaction::Adjustment(Adjustment::new(3, 6)),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        // Rejected transactions are not counted.
+        for tx in [
+            Transaction::Withdrawal(Withdrawal::new(1, 8, dec!(1))),
+            Transaction::Withdrawal(Withdrawal::new(3, 9, dec!(100))),
+        ] {
+            assert!(ledger.execute(&tx).is_err());
+        }
+        let mut output = Vec::new();
+        output_activity_report(&ledger, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,deposited,withdrawn,charged_back,ending_available,ending_held\n\
+             1,80.0,20.0,30.0,30.0,0.0\n\
+             2,60.0,20.0,60.0,-20.0,0.0\n\
+             3,2,0,0,2,0\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn activity_report_of_merged_account() {
+        let mut ledger = Ledger::new();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, dec!(4))),
+            Transaction::Deposit(Deposit::new(2, 3, dec!(1))),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        ledger.merge_accounts(1, 2).unwrap();
+        let mut output = Vec::new();
+        output_activity_report(&ledger, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,deposited,withdrawn,charged_back,ending_available,ending_held\n\
+             2,11,4,0,7,0\n"
+        );
+    }
+
+    async fn risk_metrics(ledger: &Ledger) -> String {
+        let mut output = Vec::new();
+        output_risk_metrics(ledger, &mut output).await.unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn risk_metrics_count_every_reopened_dispute() {
+        let mut ledger = Ledger::new();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(5))),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        // Rejected disputes, resolves and chargebacks are not counted
+        for tx in [
+            Transaction::Dispute(Dispute::new(2, 3)),
+            Transaction::Resolve(Resolve::new(2, 2)),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+        ] {
+            assert!(ledger.execute(&tx).is_err());
+        }
+        // Clients without disputes have a ratio of 0
+        assert_eq!(
+            risk_metrics(&ledger).await,
+            "client,disputes_opened,resolved,charged_back,chargeback_ratio\n\
+             1,3,2,1,0.3333\n\
+             2,0,0,0,0.0000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn risk_metrics_ignore_duplicates_and_holds_on_arrival() {
+        let mut ledger = Ledger::builder()
+            .duplicate_dispute_policy(DuplicateDisputePolicy::Idempotent)
+            .lock_policy(LockPolicy::HoldIncoming)
+            .build();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(10))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(10))),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+            // Held on arrival into the locked account, which is not a dispute
+            Transaction::Deposit(Deposit::new(1, 3, dec!(10))),
+            Transaction::Chargeback(Chargeback::new(1, 3)),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        let account = ledger.account(1).unwrap();
+        assert_eq!(account.chargebacks(), 2);
+        assert_eq!(
+            risk_metrics(&ledger).await,
+            "client,disputes_opened,resolved,charged_back,chargeback_ratio\n\
+             1,1,0,1,1.0000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn risk_metrics_sorted_by_ratio_then_client() {
+        let mut ledger = Ledger::builder()
+            .chargeback_policy(ChargebackPolicy::NoLock)
+            .build();
+        let mut tx_id = 0;
+        // Disputes of each client, the first `lost` of which are charged back
+        for (client_id, disputes, lost) in [(1, 2, 1), (2, 3, 3), (3, 1, 0), (4, 4, 2), (5, 0, 0)] {
+            tx_id += 1;
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id,
+                    tx_id,
+                    dec!(1),
+                )))
+                .unwrap();
+            for index in 0..disputes {
+                let deposit = tx_id + index;
+                if index > 0 {
+                    ledger
+                        .execute(&Transaction::Deposit(Deposit::new(
+                            client_id,
+                            deposit,
+                            dec!(1),
+                        )))
+                        .unwrap();
+                }
+                ledger
+                    .execute(&Transaction::Dispute(Dispute::new(client_id, deposit)))
+                    .unwrap();
+                let outcome = match index < lost {
+                    true => Transaction::Chargeback(Chargeback::new(client_id, deposit)),
+                    false => Transaction::Resolve(Resolve::new(client_id, deposit)),
+                };
+                ledger.execute(&outcome).unwrap();
+            }
+            tx_id += disputes.max(1);
+        }
+        assert_eq!(
+            risk_metrics(&ledger).await,
+            "client,disputes_opened,resolved,charged_back,chargeback_ratio\n\
+             2,3,0,3,1.0000\n\
+             1,2,1,1,0.5000\n\
+             4,4,2,2,0.5000\n\
+             3,1,1,0,0.0000\n\
+             5,0,0,0,0.0000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn unsettled_withdrawals_after_mixed_run() {
+        let mut ledger = Ledger::new();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(2, 1, dec!(100))),
+            Transaction::Deposit(Deposit::new(1, 2, dec!(50))),
+            Transaction::Withdrawal(
+                Withdrawal::new(2, 3, dec!(10))
+                    .with_reference("po_3, batch \"a\"")
+                    .unwrap(),
+            ),
+            Transaction::Withdrawal(
+                Withdrawal::new(1, 4, dec!(5))
+                    .with_reference("po_4")
+                    .unwrap(),
+            ),
+            Transaction::Withdrawal(Withdrawal::new(2, 5, dec!(1.5))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Settle(Settle::new(1, 4)),
+            Transaction::Resolve(Resolve::new(1, 2)),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        let mut output = Vec::new();
+        output_unsettled_withdrawals(&ledger, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount,reference\n\
+             2,3,10,\"po_3, batch \"\"a\"\"\"\n\
+             2,5,1.5,\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn liability_curve_of_sampled_run() {
+        let mut ledger = Ledger::builder()
+            .liability_sampling(LiabilitySampling::every(2))
+            .build();
+        let transactions = [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(100))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(40))),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(25))),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+        ];
+        for tx in &transactions {
+            ledger.execute(tx).unwrap();
+        }
+        let mut output = Vec::new();
+        output_liability_curve(ledger.liability_samples(), &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "seq,liabilities,total_available,total_held,accounts\n\
+             2,-140,140,0,2\n\
+             4,-115,75,40,2\n"
+        );
+    }
+}
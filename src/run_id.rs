@@ -0,0 +1,142 @@
+//! Identifier of a run, stamped into the files it produces so that the reports,
+//! quarantines and manifests of one run can be correlated.
+use crate::clock::{Clock, SystemClock, Timestamp};
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::fmt::{Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Longest run id accepted by `RunId::from_str`.
+pub const MAX_RUN_ID_LEN: usize = 128;
+
+/// Id of a run, either generated with `RunId::generate` or supplied by an
+/// orchestrator with `RunId::from_str`, e.g. its own correlation id.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct RunId(String);
+
+impl RunId {
+    /// New UUID v7 of the current time of the `SystemClock`, see `RunId::generate_at`.
+    pub fn generate() -> Self {
+        Self::generate_at(SystemClock.now())
+    }
+
+    /// New UUID v7: the Unix time `now` in milliseconds followed by random bits, so ids
+    /// generated later sort after earlier ones. The random bits come from the hasher
+    /// seeds of the standard library and are not suitable as secrets.
+    pub fn generate_at(now: Timestamp) -> Self {
+        static GENERATED: AtomicU64 = AtomicU64::new(0);
+        let millis = now.unix_millis();
+        let random = |salt: u64| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(GENERATED.fetch_add(1, Ordering::Relaxed));
+            hasher.write_u64(salt);
+            hasher.write_u32(std::process::id());
+            hasher.finish()
+        };
+        let (high, low) = (random(millis), random(!millis));
+        let uuid = (u128::from(millis & 0xffff_ffff_ffff) << 80)
+            | (0x7 << 76)
+            | (u128::from(high & 0xfff) << 64)
+            | (0b10 << 62)
+            | u128::from(low & 0x3fff_ffff_ffff_ffff);
+        let hex = format!("{:032x}", uuid);
+        RunId(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        ))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Comment line put at the top of CSV outputs stamped with the run id.
+    pub fn csv_comment(&self) -> String {
+        format!("# run_id={}\n", self.0)
+    }
+}
+
+impl Display for RunId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Accepts 1 to `MAX_RUN_ID_LEN` printable ASCII characters other than commas and
+/// quotes, which can go into CSV fields and comments as they are.
+impl FromStr for RunId {
+    type Err = InvalidRunId;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let valid = (1..=MAX_RUN_ID_LEN).contains(&id.len())
+            && id
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() && byte != b',' && byte != b'"');
+        if valid {
+            Ok(RunId(id.to_string()))
+        } else {
+            Err(InvalidRunId(id.to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRunId(pub String);
+
+impl Display for InvalidRunId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid run id {:?}: expected 1 to {} printable ASCII characters without commas or quotes",
+            self.0, MAX_RUN_ID_LEN
+        )
+    }
+}
+
+impl std::error::Error for InvalidRunId {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_ids_are_uuid_v7() {
+        let id = RunId::generate();
+        let other = RunId::generate();
+        assert_ne!(id, other);
+        let parts: Vec<_> = id.as_str().split('-').collect();
+        assert_eq!(
+            parts.iter().map(|part| part.len()).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+        assert!(parts[2].starts_with('7'));
+        assert!(matches!(parts[3].as_bytes()[0], b'8' | b'9' | b'a' | b'b'));
+        assert_eq!(id.as_str().parse::<RunId>(), Ok(id.clone()));
+        assert_eq!(id.csv_comment(), format!("# run_id={}\n", id));
+
+        let at = RunId::generate_at(Timestamp::from_unix_millis(0x0123_4567_89ab));
+        assert!(at.as_str().starts_with("01234567-89ab-7"));
+        let later = RunId::generate_at(Timestamp::from_unix_millis(0x0123_4567_89ac));
+        assert!(later.as_str() > at.as_str());
+    }
+
+    #[test]
+    fn supplied_ids_must_fit_csv() {
+        assert!("airflow:scheduled__2026-10-16T00:00:00+00:00"
+            .parse::<RunId>()
+            .is_ok());
+        for invalid in ["", "a b", "a,b", "a\"b", "a\nb", &"x".repeat(129)] {
+            assert_eq!(
+                invalid.parse::<RunId>(),
+                Err(InvalidRunId(invalid.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Scripted transaction scenarios with expectations, shared by the unit tests and the
+//! random workload generator. Available outside the crate's own tests with the
+//! `testkit` feature.
+use crate::accounting::transactions::{
+    Chargeback, Deposit, Dispute, Resolve, Settle, Transaction, Withdrawal,
+};
+use crate::accounting::{Ledger, TxError};
+use crate::core_types::{ClientId, TxId};
+use crate::engine::write_transactions_csv;
+use rust_decimal::Decimal;
+use std::fmt::Write;
+
+enum Step {
+    /// Transaction with the result it must have.
+    Execute {
+        tx: Transaction,
+        expected: Result<(), TxError>,
+    },
+    Balance {
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+    },
+    Locked(ClientId, bool),
+    Liabilities(Decimal),
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::Execute { tx, expected } => {
+                let amount = tx
+                    .amount()
+                    .or(tx.stated_amount())
+                    .map_or_else(String::new, |amount| amount.to_string());
+                let row = format!("{},{},{},{}", tx.kind(), tx.client_id(), tx.tx_id(), amount);
+                match expected {
+                    Ok(()) => row,
+                    Err(err) => format!("{} expecting {}", row, err.code()),
+                }
+            }
+            Step::Balance {
+                client_id,
+                available,
+                held,
+            } => format!(
+                "expect client {} available {} held {}",
+                client_id, available, held
+            ),
+            Step::Locked(client_id, true) => format!("expect client {} locked", client_id),
+            Step::Locked(client_id, false) => format!("expect client {} unlocked", client_id),
+            Step::Liabilities(liabilities) => format!("expect liabilities {}", liabilities),
+        }
+    }
+
+    /// Runs the step, describing what was found instead when it fails.
+    fn apply(&self, ledger: &mut Ledger) -> Result<(), String> {
+        match self {
+            Step::Execute { tx, expected } => {
+                let result = ledger.execute(tx);
+                if &result != expected {
+                    return Err(format!("expected {:?}, found {:?}", expected, result));
+                }
+            }
+            Step::Balance {
+                client_id,
+                available,
+                held,
+            } => {
+                let Some(account) = ledger.account(*client_id) else {
+                    return Err(format!("client {} has no account", client_id));
+                };
+                if account.available() != *available || account.held() != *held {
+                    return Err(format!(
+                        "found available {} held {}",
+                        account.available(),
+                        account.held()
+                    ));
+                }
+            }
+            Step::Locked(client_id, locked) => {
+                let Some(account) = ledger.account(*client_id) else {
+                    return Err(format!("client {} has no account", client_id));
+                };
+                if account.locked() != *locked {
+                    return Err(format!("found locked {}", account.locked()));
+                }
+            }
+            Step::Liabilities(liabilities) => {
+                let found = ledger.recompute_liabilities();
+                if found != *liabilities {
+                    return Err(format!("found liabilities {}", found));
+                }
+            }
+        }
+        ledger
+            .audit_liabilities()
+            .map_err(|drift| format!("liabilities drifted: {}", drift))?;
+        ledger
+            .audit_totals()
+            .map_err(|drift| format!("totals drifted: {}", drift))
+    }
+}
+
+/// Fluent script of transactions and the balances, locks and errors they must lead to.
+/// Transactions must succeed unless followed by `expect_err`.
+#[derive(Default)]
+pub struct ScenarioBuilder {
+    ledger: Option<Ledger>,
+    steps: Vec<Step>,
+}
+
+impl ScenarioBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the scenario against `ledger` instead of a fresh `Ledger::new()`.
+    pub fn with_ledger(mut self, ledger: Ledger) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Appends any transaction, for the types without a shorthand.
+    pub fn transaction(mut self, tx: Transaction) -> Self {
+        self.steps.push(Step::Execute {
+            tx,
+            expected: Ok(()),
+        });
+        self
+    }
+
+    /// Panics when `amount` is not a decimal.
+    pub fn deposit(self, client_id: ClientId, tx_id: TxId, amount: &str) -> Self {
+        self.transaction(Transaction::Deposit(Deposit::new(
+            client_id,
+            tx_id,
+            parse_amount(amount),
+        )))
+    }
+
+    /// Panics when `amount` is not a decimal.
+    pub fn withdraw(self, client_id: ClientId, tx_id: TxId, amount: &str) -> Self {
+        self.transaction(Transaction::Withdrawal(Withdrawal::new(
+            client_id,
+            tx_id,
+            parse_amount(amount),
+        )))
+    }
+
+    pub fn dispute(self, client_id: ClientId, tx_id: TxId) -> Self {
+        self.transaction(Transaction::Dispute(Dispute::new(client_id, tx_id)))
+    }
+
+    pub fn resolve(self, client_id: ClientId, tx_id: TxId) -> Self {
+        self.transaction(Transaction::Resolve(Resolve::new(client_id, tx_id)))
+    }
+
+    pub fn chargeback(self, client_id: ClientId, tx_id: TxId) -> Self {
+        self.transaction(Transaction::Chargeback(Chargeback::new(client_id, tx_id)))
+    }
+
+    pub fn settle(self, client_id: ClientId, tx_id: TxId) -> Self {
+        self.transaction(Transaction::Settle(Settle::new(client_id, tx_id)))
+    }
+
+    /// Expects the previous transaction to fail with `err`.
+    ///
+    /// Panics when the previous step is not a transaction.
+    pub fn expect_err(mut self, err: TxError) -> Self {
+        match self.steps.last_mut() {
+            Some(Step::Execute { expected, .. }) => *expected = Err(err),
+            _ => panic!("expect_err must follow a transaction"),
+        }
+        self
+    }
+
+    /// Panics when an amount is not a decimal.
+    pub fn expect_balance(mut self, client_id: ClientId, available: &str, held: &str) -> Self {
+        self.steps.push(Step::Balance {
+            client_id,
+            available: parse_amount(available),
+            held: parse_amount(held),
+        });
+        self
+    }
+
+    pub fn expect_locked(mut self, client_id: ClientId) -> Self {
+        self.steps.push(Step::Locked(client_id, true));
+        self
+    }
+
+    pub fn expect_unlocked(mut self, client_id: ClientId) -> Self {
+        self.steps.push(Step::Locked(client_id, false));
+        self
+    }
+
+    /// Panics when `liabilities` is not a decimal.
+    pub fn expect_liabilities(mut self, liabilities: &str) -> Self {
+        self.steps
+            .push(Step::Liabilities(parse_amount(liabilities)));
+        self
+    }
+
+    /// Executes the scenario and returns the ledger for further checks.
+    ///
+    /// Panics on the first failed expectation, or when the liabilities drift, with the
+    /// script up to that step and what was found.
+    pub fn run(self) -> Ledger {
+        let mut ledger = self.ledger.unwrap_or_default();
+        for (index, step) in self.steps.iter().enumerate() {
+            if let Err(found) = step.apply(&mut ledger) {
+                let mut script = String::new();
+                for (number, step) in self.steps[..=index].iter().enumerate() {
+                    let _ = writeln!(script, "{:>4}. {}", number + 1, step.describe());
+                }
+                panic!(
+                    "scenario failed at step {}: {}\n{}",
+                    index + 1,
+                    found,
+                    script
+                );
+            }
+        }
+        ledger
+    }
+
+    /// Transactions of the scenario as an input file with a header, without the
+    /// expectations.
+    pub fn to_csv_string(&self) -> String {
+        let transactions = self.steps.iter().filter_map(|step| match step {
+            Step::Execute { tx, .. } => Some(tx),
+            _ => None,
+        });
+        let mut output = Vec::new();
+        write_transactions_csv(&mut output, transactions).expect("writing to memory does not fail");
+        String::from_utf8(output).expect("CSV writer produces UTF-8 from UTF-8 fields")
+    }
+}
+
+/// Linear congruential generator behind the seeded workloads, reproducible across
+/// platforms and releases.
+pub(crate) struct Lcg(u64);
+
+impl Lcg {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as u32
+    }
+}
+
+/// `rows` pseudo-random transactions of `clients` clients, with disputes, resolves and
+/// chargebacks referencing random earlier transaction ids, for `to_csv_string`. Their
+/// outcomes are not expected, so `run` fails on the first rejected one.
+pub fn random_workload(seed: u64, rows: u32, clients: ClientId) -> ScenarioBuilder {
+    let mut rng = Lcg::new(seed);
+    let mut scenario = ScenarioBuilder::new();
+    for tx_id in 1..=rows {
+        let client_id = (rng.next() % u32::from(clients) + 1) as ClientId;
+        let origin_tx = rng.next() % tx_id + 1;
+        scenario = match rng.next() % 10 {
+            0..=4 => {
+                let amount = format!("{}.{:04}", rng.next() % 100, rng.next() % 10000);
+                scenario.deposit(client_id, tx_id, &amount)
+            }
+            5..=6 => {
+                let amount = format!("{}.{:04}", rng.next() % 50, rng.next() % 10000);
+                scenario.withdraw(client_id, tx_id, &amount)
+            }
+            7 => scenario.dispute(client_id, origin_tx),
+            8 => scenario.resolve(client_id, origin_tx),
+            _ => scenario.chargeback(client_id, origin_tx),
+        };
+    }
+    scenario
+}
+
+fn parse_amount(amount: &str) -> Decimal {
+    amount
+        .parse()
+        .unwrap_or_else(|err| panic!("invalid scenario amount {:?}: {}", amount, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expectations_hold() {
+        let ledger = ScenarioBuilder::new()
+            .deposit(1, 1, "50.0")
+            .deposit(2, 2, "5")
+            .dispute(1, 1)
+            .expect_balance(1, "0", "50.0")
+            .withdraw(1, 3, "1")
+            .expect_err(TxError::InsufficientFunds)
+            .chargeback(1, 1)
+            .expect_balance(1, "0", "0")
+            .expect_locked(1)
+            .expect_unlocked(2)
+            .expect_liabilities("-5")
+            .run();
+        assert_eq!(
+            ledger.account(1).unwrap().total_charged_back(),
+            "50".parse::<Decimal>().unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "scenario failed at step 3: found available 30.0 held 0\n   \
+                               1. deposit,1,1,50.0\n   \
+                               2. withdrawal,1,2,20.0\n   \
+                               3. expect client 1 available 20 held 0\n"
+    )]
+    fn failed_expectation_prints_the_script() {
+        ScenarioBuilder::new()
+            .deposit(1, 1, "50.0")
+            .withdraw(1, 2, "20.0")
+            .expect_balance(1, "20", "0")
+            .deposit(1, 3, "1")
+            .run();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected Err(InsufficientFunds), found Ok(())")]
+    fn unexpected_success_fails() {
+        ScenarioBuilder::new()
+            .deposit(1, 1, "50.0")
+            .withdraw(1, 2, "20.0")
+            .expect_err(TxError::InsufficientFunds)
+            .run();
+    }
+
+    #[test]
+    fn csv_string_of_the_transactions() {
+        let scenario = ScenarioBuilder::new()
+            .deposit(1, 1, "50.0")
+            .expect_balance(1, "50", "0")
+            .withdraw(1, 2, "1.5")
+            .dispute(1, 1)
+            .resolve(1, 1);
+        assert_eq!(
+            scenario.to_csv_string(),
+            "type,client,tx,amount\n\
+             deposit,1,1,50.0\n\
+             withdrawal,1,2,1.5\n\
+             dispute,1,1,\n\
+             resolve,1,1,\n"
+        );
+    }
+}
@@ -0,0 +1,519 @@
+//! Binary transaction feed over a Unix socket, for producers which keep a connection
+//! open instead of handing over files.
+//!
+//! Every frame is a big-endian `u32` length followed by that many bytes of bincode.
+//! Clients send `WireTransaction`s and get one `WireResponse` per transaction, in the
+//! order they were sent.
+use crate::accounting::shared::SharedLedger;
+use crate::accounting::transactions::{Transaction, TransactionLog, TxTypeTag};
+use crate::core_types::{ClientId, TxId};
+use crate::lanes::{lanes, LaneConfig, LaneSender};
+use bincode::Options;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::oneshot;
+
+/// Largest frame accepted, far above the size of any transaction or response.
+pub const MAX_FRAME_SIZE: u32 = 4096;
+
+/// Amount as the mantissa and scale of a `Decimal`, whose own serde representation
+/// needs a self-describing format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WireAmount {
+    pub mantissa: i128,
+    pub scale: u32,
+}
+
+impl From<Decimal> for WireAmount {
+    fn from(amount: Decimal) -> Self {
+        Self {
+            mantissa: amount.mantissa(),
+            scale: amount.scale(),
+        }
+    }
+}
+
+impl TryFrom<WireAmount> for Decimal {
+    type Error = rust_decimal::Error;
+
+    fn try_from(amount: WireAmount) -> Result<Self, Self::Error> {
+        Decimal::try_from_i128_with_scale(amount.mantissa, amount.scale)
+    }
+}
+
+/// Transaction sent by a client. New versions are added as variants, so that servers
+/// can keep accepting older clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WireTransaction {
+    V1 {
+        /// Chosen by the client and echoed in the response.
+        request_id: u64,
+        tx_type: TxTypeTag,
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Option<WireAmount>,
+    },
+}
+
+impl WireTransaction {
+    pub fn new(request_id: u64, tx: &Transaction) -> Self {
+        let log = TransactionLog::from(tx);
+        WireTransaction::V1 {
+            request_id,
+            tx_type: log.tx_type(),
+            client_id: log.client_id(),
+            tx_id: log.tx_id(),
+            amount: log.amount().map(WireAmount::from),
+        }
+    }
+
+    pub fn request_id(&self) -> u64 {
+        match self {
+            WireTransaction::V1 { request_id, .. } => *request_id,
+        }
+    }
+
+    /// The row of the transaction, failing on amounts out of the range of `Decimal`.
+    fn to_log(&self) -> Result<TransactionLog, FrameError> {
+        match self {
+            WireTransaction::V1 {
+                tx_type,
+                client_id,
+                tx_id,
+                amount,
+                ..
+            } => {
+                let amount = amount
+                    .map(|amount| Decimal::try_from(amount).map_err(|_err| amount))
+                    .transpose()
+                    .map_err(FrameError::InvalidAmount)?;
+                Ok(TransactionLog::new(*tx_type, *client_id, *tx_id, amount))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WireResponse {
+    V1 {
+        request_id: u64,
+        /// The code of the `TxError` rejecting the transaction, or of the
+        /// `TransactionLogError` of a transaction missing its amount.
+        result: Result<(), String>,
+    },
+}
+
+impl WireResponse {
+    pub fn request_id(&self) -> u64 {
+        match self {
+            WireResponse::V1 { request_id, .. } => *request_id,
+        }
+    }
+
+    pub fn result(&self) -> Result<(), &str> {
+        match self {
+            WireResponse::V1 { result, .. } => {
+                result.as_ref().map(|_ok| ()).map_err(|code| code.as_str())
+            }
+        }
+    }
+}
+
+/// Frame which could not be read, written or decoded. The connection is dropped, as
+/// the stream can't be trusted to be at the start of a frame anymore.
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    /// The length prefix is above `MAX_FRAME_SIZE`.
+    TooLarge(u32),
+    Decode(bincode::Error),
+    /// The mantissa and scale don't make a `Decimal`.
+    InvalidAmount(WireAmount),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Io(err) => write!(f, "{}", err),
+            FrameError::TooLarge(len) => write!(
+                f,
+                "frame of {} bytes exceeds the maximum of {}",
+                len, MAX_FRAME_SIZE
+            ),
+            FrameError::Decode(err) => write!(f, "malformed frame: {}", err),
+            FrameError::InvalidAmount(amount) => write!(
+                f,
+                "invalid amount with mantissa {} and scale {}",
+                amount.mantissa, amount.scale
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FrameError::Io(err) => Some(err),
+            FrameError::Decode(err) => Some(err),
+            FrameError::TooLarge(_) | FrameError::InvalidAmount(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> Self {
+        FrameError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for FrameError {
+    fn from(err: bincode::Error) -> Self {
+        FrameError::Decode(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum ServeError {
+    Bind(io::Error),
+    Accept(io::Error),
+}
+
+impl Display for ServeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServeError::Bind(err) => write!(f, "cannot bind socket: {}", err),
+            ServeError::Accept(err) => write!(f, "cannot accept connection: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ServeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServeError::Bind(err) | ServeError::Accept(err) => Some(err),
+        }
+    }
+}
+
+/// Counters of a running server, shared with whoever reports them, such as the admin
+/// listener of the `admin` feature.
+#[derive(Debug, Default)]
+pub struct ServiceStats {
+    /// Indexed by the position of the kind in `TxTypeTag::ALL`.
+    applied: [AtomicU64; TxTypeTag::ALL.len()],
+    rejected: [AtomicU64; TxTypeTag::ALL.len()],
+    queue_depth: AtomicU64,
+    accepting: AtomicBool,
+}
+
+impl ServiceStats {
+    pub fn applied(&self, kind: TxTypeTag) -> u64 {
+        self.applied[Self::index(kind)].load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self, kind: TxTypeTag) -> u64 {
+        self.rejected[Self::index(kind)].load(Ordering::Relaxed)
+    }
+
+    /// Transactions received and waiting for the ledger.
+    pub fn queue_depth(&self) -> u64 {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Whether the server listens for transactions.
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Relaxed)
+    }
+
+    fn count(&self, kind: TxTypeTag, applied: bool) {
+        let counters = if applied {
+            &self.applied
+        } else {
+            &self.rejected
+        };
+        counters[Self::index(kind)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn index(kind: TxTypeTag) -> usize {
+        TxTypeTag::ALL
+            .iter()
+            .position(|tag| *tag == kind)
+            .expect("ALL lists every kind")
+    }
+}
+
+/// Fixed-size integers, so that frames don't depend on the values, and no trailing
+/// bytes, so that a frame is exactly one message.
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .with_limit(u64::from(MAX_FRAME_SIZE))
+}
+
+fn encode(message: &impl Serialize) -> Result<Vec<u8>, FrameError> {
+    Ok(bincode_options().serialize(message)?)
+}
+
+fn decode<T: DeserializeOwned>(frame: &[u8]) -> Result<T, FrameError> {
+    Ok(bincode_options().deserialize(frame)?)
+}
+
+/// Reads the next frame, or `None` when the stream ends between frames.
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut prefix = [0; 4];
+    let mut filled = 0;
+    while filled < prefix.len() {
+        match reader.read(&mut prefix[filled..]).await? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            read => filled += read,
+        }
+    }
+    let len = u32::from_be_bytes(prefix);
+    if len > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge(len));
+    }
+    let mut frame = vec![0; len as usize];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    frame: &[u8],
+) -> Result<(), FrameError> {
+    let len = u32::try_from(frame.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_SIZE)
+        .ok_or(FrameError::TooLarge(u32::MAX))?;
+    let mut buf = Vec::with_capacity(4 + frame.len());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(frame);
+    writer.write_all(&buf).await?;
+    Ok(writer.flush().await?)
+}
+
+/// Listens on `path` and executes the transactions of every connection on `ledger`,
+/// until accepting a connection fails.
+///
+/// Connections are served concurrently, each executing its transactions in order. A
+/// connection sending a malformed frame is dropped and the error written to stderr,
+/// leaving the others and the ledger as they are.
+pub async fn serve_unix_socket(
+    path: impl AsRef<Path>,
+    ledger: SharedLedger,
+) -> Result<(), ServeError> {
+    serve_unix_socket_with_stats(path, ledger, Arc::default()).await
+}
+
+/// Like `serve_unix_socket`, counting transactions in `stats`. The stats report the
+/// server as accepting once the socket is bound.
+pub async fn serve_unix_socket_with_stats(
+    path: impl AsRef<Path>,
+    ledger: SharedLedger,
+    stats: Arc<ServiceStats>,
+) -> Result<(), ServeError> {
+    serve(path, Executor::Direct(ledger), stats).await
+}
+
+/// Like `serve_unix_socket_with_stats`, executing the transactions of all
+/// connections on a single thread in the order of `lanes`, so that disputes,
+/// resolves and chargebacks overtake the deposits and withdrawals of other
+/// connections waiting to be executed.
+pub async fn serve_unix_socket_with_lanes(
+    path: impl AsRef<Path>,
+    ledger: SharedLedger,
+    stats: Arc<ServiceStats>,
+    config: LaneConfig,
+) -> Result<(), ServeError> {
+    let (sender, mut receiver) = lanes::<Responder>(config);
+    tokio::task::spawn_blocking(move || {
+        while let Some((tx, responder)) = receiver.recv_blocking() {
+            let result = ledger.execute(&tx).map_err(|err| err.code().to_string());
+            // The connection may have been dropped in the meantime.
+            let _ = responder.send(result);
+        }
+    });
+    serve(path, Executor::Lanes(sender), stats).await
+}
+
+/// Sends back the result of a transaction executed from the lanes.
+type Responder = oneshot::Sender<Result<(), String>>;
+
+/// Where connections have their transactions executed.
+#[derive(Clone)]
+enum Executor {
+    Direct(SharedLedger),
+    Lanes(LaneSender<Responder>),
+}
+
+impl Executor {
+    /// The code of the error rejecting `tx`. Fails when the lanes are no longer
+    /// executed.
+    async fn execute(&self, tx: Transaction) -> Result<Result<(), String>, FrameError> {
+        match self {
+            Executor::Direct(ledger) => {
+                Ok(ledger.execute(&tx).map_err(|err| err.code().to_string()))
+            }
+            Executor::Lanes(sender) => {
+                let (responder, response) = oneshot::channel();
+                let stopped =
+                    || io::Error::new(io::ErrorKind::BrokenPipe, "lanes are not executed");
+                sender
+                    .send(tx, responder)
+                    .await
+                    .map_err(|_unsent| stopped())?;
+                Ok(response.await.map_err(|_dropped| stopped())?)
+            }
+        }
+    }
+}
+
+async fn serve(
+    path: impl AsRef<Path>,
+    executor: Executor,
+    stats: Arc<ServiceStats>,
+) -> Result<(), ServeError> {
+    let listener = UnixListener::bind(path).map_err(ServeError::Bind)?;
+    stats.accepting.store(true, Ordering::Relaxed);
+    let accepted = loop {
+        let (stream, _addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => break err,
+        };
+        let executor = executor.clone();
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_connection(stream, &executor, &stats).await {
+                eprintln!("Dropped connection: {}", err);
+            }
+        });
+    };
+    stats.accepting.store(false, Ordering::Relaxed);
+    Err(ServeError::Accept(accepted))
+}
+
+async fn serve_connection(
+    mut stream: UnixStream,
+    executor: &Executor,
+    stats: &ServiceStats,
+) -> Result<(), FrameError> {
+    while let Some(frame) = read_frame(&mut stream).await? {
+        let request: WireTransaction = decode(&frame)?;
+        let log = request.to_log()?;
+        let kind = log.tx_type();
+        stats.queue_depth.fetch_add(1, Ordering::Relaxed);
+        let result = match Transaction::try_from(log) {
+            Ok(tx) => executor.execute(tx).await,
+            Err(err) => Ok(Err(err.code().to_string())),
+        };
+        stats.queue_depth.fetch_sub(1, Ordering::Relaxed);
+        let result = result?;
+        stats.count(kind, result.is_ok());
+        let response = WireResponse::V1 {
+            request_id: request.request_id(),
+            result,
+        };
+        write_frame(&mut stream, &encode(&response)?).await?;
+    }
+    Ok(())
+}
+
+/// Sends `txs` with request ids counting from 0 and returns their responses.
+///
+/// Responses are read while sending, so that a server blocked on writing them back
+/// can't stall a long batch.
+pub async fn send_transactions(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    txs: &[Transaction],
+) -> Result<Vec<WireResponse>, FrameError> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let send = async {
+        for (request_id, tx) in (0..).zip(txs) {
+            write_frame(&mut writer, &encode(&WireTransaction::new(request_id, tx))?).await?;
+        }
+        Ok::<_, FrameError>(())
+    };
+    let receive = async {
+        let mut responses = Vec::with_capacity(txs.len());
+        while responses.len() < txs.len() {
+            let frame = read_frame(&mut reader)
+                .await?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            responses.push(decode(&frame)?);
+        }
+        Ok(responses)
+    };
+    let ((), responses) = tokio::try_join!(send, receive)?;
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Dispute};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn round_trips_transactions() {
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 2, dec!(10.0001))),
+            Transaction::Dispute(Dispute::new(1, 2)),
+        ] {
+            let wire = WireTransaction::new(7, &tx);
+            let decoded: WireTransaction = decode(&encode(&wire).unwrap()).unwrap();
+            assert_eq!(decoded.request_id(), 7);
+            assert_eq!(
+                Transaction::try_from(decoded.to_log().unwrap()).unwrap(),
+                tx
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_and_malformed_frames() {
+        let mut oversized = &(MAX_FRAME_SIZE + 1).to_be_bytes()[..];
+        assert!(matches!(
+            read_frame(&mut oversized).await,
+            Err(FrameError::TooLarge(len)) if len == MAX_FRAME_SIZE + 1
+        ));
+
+        let mut truncated = &[0, 0, 0, 8, 1, 2][..];
+        assert!(matches!(
+            read_frame(&mut truncated).await,
+            Err(FrameError::Io(_))
+        ));
+
+        let mut empty = &[][..];
+        assert!(matches!(read_frame(&mut empty).await, Ok(None)));
+
+        assert!(matches!(
+            decode::<WireTransaction>(&[9, 9, 9]),
+            Err(FrameError::Decode(_))
+        ));
+
+        let out_of_range = WireTransaction::V1 {
+            request_id: 0,
+            tx_type: TxTypeTag::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(WireAmount {
+                mantissa: 1,
+                scale: 29,
+            }),
+        };
+        assert!(matches!(
+            out_of_range.to_log(),
+            Err(FrameError::InvalidAmount(_))
+        ));
+    }
+}
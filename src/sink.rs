@@ -0,0 +1,125 @@
+//! Failure handling of the sinks receiving audit records and ledger events.
+//!
+//! A sink is called after the ledger applied the change it reports, so a failing
+//! sink never rolls back a transaction. What happens to the record is decided by
+//! the `SinkFailurePolicy`.
+use crate::clock::Clock;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// Delay before the first retry, doubled for every further one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Failure of a sink to accept a record, e.g. a downstream service being unavailable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkError {
+    message: String,
+}
+
+impl SinkError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for SinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sink failed: {}", self.message)
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SinkFailurePolicy {
+    /// The record is dropped and counted, so records are delivered at most once.
+    #[default]
+    Ignore,
+    /// The record is sent again up to N times with exponential backoff, and the run
+    /// is aborted when all retries failed. Records are delivered at least once, and
+    /// may be delivered more than once when a sink fails after accepting a record.
+    RetryN(u32),
+    /// The run stops after the row whose record failed.
+    Abort,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Delivery {
+    Delivered,
+    Dropped,
+}
+
+/// Calls `send` according to `policy`, returning the error which should abort the run.
+///
+/// Retries block the thread on `clock`, which is fine since the pipeline runs on
+/// blocking threads.
+pub(crate) fn deliver(
+    policy: SinkFailurePolicy,
+    clock: &dyn Clock,
+    mut send: impl FnMut() -> Result<(), SinkError>,
+) -> Result<Delivery, SinkError> {
+    let Err(mut err) = send() else {
+        return Ok(Delivery::Delivered);
+    };
+    match policy {
+        SinkFailurePolicy::Ignore => Ok(Delivery::Dropped),
+        SinkFailurePolicy::Abort => Err(err),
+        SinkFailurePolicy::RetryN(retries) => {
+            let mut backoff = RETRY_BACKOFF;
+            for _ in 0..retries {
+                clock.sleep_blocking(backoff);
+                backoff *= 2;
+                match send() {
+                    Ok(()) => return Ok(Delivery::Delivered),
+                    Err(retry_err) => err = retry_err,
+                }
+            }
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, Timestamp};
+    use crate::test_utils::FlakySink;
+
+    #[test]
+    fn policies_of_a_failing_send() {
+        let clock = ManualClock::default();
+        let mut sink = FlakySink::new(2);
+        assert_eq!(
+            deliver(SinkFailurePolicy::Ignore, &clock, || sink.send()),
+            Ok(Delivery::Dropped)
+        );
+        assert_eq!(sink.calls, 1);
+
+        let mut sink = FlakySink::new(2);
+        assert_eq!(
+            deliver(SinkFailurePolicy::Abort, &clock, || sink.send()),
+            Err(SinkError::new("call 1 failed"))
+        );
+
+        let mut sink = FlakySink::new(2);
+        assert_eq!(
+            deliver(SinkFailurePolicy::RetryN(2), &clock, || sink.send()),
+            Ok(Delivery::Delivered)
+        );
+        assert_eq!(sink.calls, 3);
+        // Backoffs of 1 and 2ms, waited on the clock
+        assert_eq!(clock.now(), Timestamp::from_unix_millis(3));
+
+        let mut sink = FlakySink::new(2);
+        assert_eq!(
+            deliver(SinkFailurePolicy::RetryN(1), &clock, || sink.send()),
+            Err(SinkError::new("call 2 failed"))
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Output files written under temporary names and renamed over their targets together,
+//! so that a crash never leaves a half-written file at a target path.
+//!
+//! Files are staged as `<target>.tmp.<pid>`. `Staging::commit` first syncs every staged
+//! file and only then renames them, so a failure while writing or syncing leaves all
+//! targets as they were. The renames themselves follow one another, and a failure in
+//! between, which takes a full disk or a vanished directory, can leave the earlier
+//! targets replaced and the later ones not.
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Files staged for their targets, removed when dropped without being committed.
+#[derive(Debug, Default)]
+pub struct Staging {
+    /// Temporary path and target of every staged file, in staging order.
+    files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Staging {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Temporary path `target` is staged under.
+    pub fn temp_path(target: &Path) -> PathBuf {
+        let mut file_name = target.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(".tmp.{}", std::process::id()));
+        target.with_file_name(file_name)
+    }
+
+    /// Creates the temporary file of `target`, to be renamed over it on commit.
+    pub fn create(&mut self, target: impl AsRef<Path>) -> io::Result<File> {
+        let target = target.as_ref().to_path_buf();
+        let temp_path = Self::temp_path(&target);
+        let file = File::create(&temp_path)?;
+        self.files.push((temp_path, target));
+        Ok(file)
+    }
+
+    /// Syncs the staged files, renames each over its target and syncs the directories
+    /// of the targets. The staged files must have been written and closed.
+    pub fn commit(mut self) -> io::Result<()> {
+        let files = std::mem::take(&mut self.files);
+        let result = commit_files(&files);
+        if result.is_err() {
+            remove_temp_files(&files);
+        }
+        result
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        remove_temp_files(&self.files);
+    }
+}
+
+fn commit_files(files: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    for (temp_path, _target) in files {
+        // Opened for writing, which Windows requires to flush a file.
+        OpenOptions::new().write(true).open(temp_path)?.sync_all()?;
+    }
+    for (temp_path, target) in files {
+        replace(temp_path, target)?;
+    }
+    let mut dirs: Vec<_> = files
+        .iter()
+        .map(|(_temp_path, target)| target.parent().unwrap_or(Path::new("")))
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// Renames `temp_path` over `target`. Where the rename refuses to replace an existing
+/// file, the target is removed first, leaving a short window without it.
+fn replace(temp_path: &Path, target: &Path) -> io::Result<()> {
+    match fs::rename(temp_path, target) {
+        Err(err) if cfg!(windows) && err.kind() == io::ErrorKind::AlreadyExists => {
+            fs::remove_file(target)?;
+            fs::rename(temp_path, target)
+        }
+        result => result,
+    }
+}
+
+/// Makes the renames in `dir` durable. Windows has no handles to directories to sync.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn remove_temp_files(files: &[(PathBuf, PathBuf)]) {
+    for (temp_path, _target) in files {
+        let _ = fs::remove_file(temp_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "payments_engine_staging_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn commit_replaces_all_targets() {
+        let dir = temp_dir("commit");
+        let (accounts, quarantine) = (dir.join("accounts.csv"), dir.join("quarantine.csv"));
+        fs::write(&accounts, "old").unwrap();
+
+        let mut staging = Staging::new();
+        staging
+            .create(&accounts)
+            .unwrap()
+            .write_all(b"new")
+            .unwrap();
+        staging
+            .create(&quarantine)
+            .unwrap()
+            .write_all(b"rows")
+            .unwrap();
+        assert_eq!(fs::read_to_string(&accounts).unwrap(), "old");
+        assert!(!quarantine.exists());
+        staging.commit().unwrap();
+
+        assert_eq!(fs::read_to_string(&accounts).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&quarantine).unwrap(), "rows");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dropped_staging_leaves_targets_untouched() {
+        let dir = temp_dir("drop");
+        let accounts = dir.join("accounts.csv");
+        fs::write(&accounts, "old").unwrap();
+
+        let mut staging = Staging::new();
+        staging.create(&accounts).unwrap().write_all(b"ne").unwrap();
+        assert!(Staging::temp_path(&accounts).exists());
+        drop(staging);
+
+        assert_eq!(fs::read_to_string(&accounts).unwrap(), "old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_removes_staged_files() {
+        let dir = temp_dir("failed");
+        let accounts = dir.join("accounts.csv");
+        fs::write(&accounts, "old").unwrap();
+        // A staged file whose temporary file vanished fails before any rename.
+        let missing = dir.join("missing.csv");
+
+        let mut staging = Staging::new();
+        staging
+            .create(&accounts)
+            .unwrap()
+            .write_all(b"new")
+            .unwrap();
+        staging.create(&missing).unwrap();
+        fs::remove_file(Staging::temp_path(&missing)).unwrap();
+        assert!(staging.commit().is_err());
+
+        assert_eq!(fs::read_to_string(&accounts).unwrap(), "old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+//! Statements of clients: every journaled transaction of a client in order, with the
+//! balances after it.
+//!
+//! Statements are built from journal entries, so the ledger must journal from the
+//! start, see `LedgerBuilder::journal` and `LedgerBuilder::journal_writer`.
+use crate::accounting::journal::JournalEntry;
+use crate::accounting::transactions::TxTypeTag;
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Statement files `generate_statements` keeps open at most.
+pub const MAX_OPEN_STATEMENTS: usize = 256;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StatementRow {
+    pub seq: u64,
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    pub kind: TxTypeTag,
+    /// Amount moved by the transaction, the disputed amount for disputes, resolves
+    /// and chargebacks.
+    pub amount: Decimal,
+    pub available_after: Decimal,
+    pub held_after: Decimal,
+}
+
+#[derive(Debug)]
+pub enum StatementError {
+    Csv(csv::Error),
+    Io(std::io::Error),
+}
+
+impl Display for StatementError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatementError::Csv(err) => write!(f, "failed to write statement: {}", err),
+            StatementError::Io(err) => write!(f, "failed to write statement: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StatementError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StatementError::Csv(err) => Some(err),
+            StatementError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<csv::Error> for StatementError {
+    fn from(err: csv::Error) -> Self {
+        StatementError::Csv(err)
+    }
+}
+
+impl From<std::io::Error> for StatementError {
+    fn from(err: std::io::Error) -> Self {
+        StatementError::Io(err)
+    }
+}
+
+/// Balances of a client after the entries seen so far.
+#[derive(Default)]
+struct RunningBalance {
+    available: Decimal,
+    held: Decimal,
+}
+
+impl RunningBalance {
+    fn apply(&mut self, entry: &JournalEntry) -> StatementRow {
+        self.available += entry.available_delta;
+        self.held += entry.held_delta;
+        StatementRow {
+            seq: entry.seq,
+            tx_id: entry.tx_id,
+            kind: entry.kind,
+            // Transactions either move funds in or out of one balance, or between both.
+            amount: entry.available_delta.abs().max(entry.held_delta.abs()),
+            available_after: self.available,
+            held_after: self.held,
+        }
+    }
+}
+
+/// Writes the statement of `client_id` from `journal`, such as `Ledger::journal`, as
+/// CSV with a header. Returns the number of rows, which is 0 for a client without
+/// entries.
+pub fn generate_statement(
+    journal: &[JournalEntry],
+    client_id: ClientId,
+    writer: impl Write,
+) -> Result<usize, StatementError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut balance = RunningBalance::default();
+    let mut rows = 0;
+    for entry in journal.iter().filter(|entry| entry.client_id == client_id) {
+        writer.serialize(balance.apply(entry))?;
+        rows += 1;
+    }
+    if rows == 0 {
+        writer.write_record([
+            "seq",
+            "tx",
+            "kind",
+            "amount",
+            "available_after",
+            "held_after",
+        ])?;
+    }
+    writer.flush()?;
+    Ok(rows)
+}
+
+/// Writes `client_<id>.csv` into `dir` for every client with entries in `journal`,
+/// which is read once, e.g. from `journal_entries`. Returns the clients, sorted.
+///
+/// At most `MAX_OPEN_STATEMENTS` files are open at a time; the least recently
+/// written one is closed to make room and reopened for appending when its client
+/// shows up again. The directory is created if missing; existing statements of the
+/// clients are replaced.
+pub fn generate_statements(
+    journal: impl IntoIterator<Item = std::io::Result<JournalEntry>>,
+    dir: &Path,
+) -> Result<Vec<ClientId>, StatementError> {
+    write_statements(journal, dir, MAX_OPEN_STATEMENTS)
+}
+
+fn write_statements(
+    journal: impl IntoIterator<Item = std::io::Result<JournalEntry>>,
+    dir: &Path,
+    max_open: usize,
+) -> Result<Vec<ClientId>, StatementError> {
+    std::fs::create_dir_all(dir)?;
+    let mut balances = HashMap::<ClientId, RunningBalance>::new();
+    let mut open = OpenStatements::new(dir, max_open);
+    for entry in journal {
+        let entry = entry?;
+        let new = !balances.contains_key(&entry.client_id);
+        let row = balances.entry(entry.client_id).or_default().apply(&entry);
+        open.writer(entry.client_id, new)?.serialize(row)?;
+    }
+    open.close_all()?;
+    let mut client_ids: Vec<_> = balances.into_keys().collect();
+    client_ids.sort_unstable();
+    Ok(client_ids)
+}
+
+pub fn statement_path(dir: &Path, client_id: ClientId) -> PathBuf {
+    dir.join(format!("client_{}.csv", client_id))
+}
+
+/// Statement writers, closing the least recently used one beyond `max_open`.
+struct OpenStatements<'d> {
+    dir: &'d Path,
+    max_open: usize,
+    writers: HashMap<ClientId, (u64, csv::Writer<BufWriter<File>>)>,
+    uses: u64,
+}
+
+impl<'d> OpenStatements<'d> {
+    fn new(dir: &'d Path, max_open: usize) -> Self {
+        Self {
+            dir,
+            max_open: max_open.max(1),
+            writers: HashMap::new(),
+            uses: 0,
+        }
+    }
+
+    /// Writer of the statement of `client_id`, created with a header when `new`.
+    fn writer(
+        &mut self,
+        client_id: ClientId,
+        new: bool,
+    ) -> Result<&mut csv::Writer<BufWriter<File>>, StatementError> {
+        self.uses += 1;
+        if !self.writers.contains_key(&client_id) {
+            if self.writers.len() == self.max_open {
+                self.close_least_recent()?;
+            }
+            let path = statement_path(self.dir, client_id);
+            let file = if new {
+                File::create(path)?
+            } else {
+                OpenOptions::new().append(true).open(path)?
+            };
+            let writer = csv::WriterBuilder::new()
+                .has_headers(new)
+                .from_writer(BufWriter::new(file));
+            self.writers.insert(client_id, (self.uses, writer));
+        }
+        let (last_use, writer) = self.writers.get_mut(&client_id).expect("inserted above");
+        *last_use = self.uses;
+        Ok(writer)
+    }
+
+    fn close_least_recent(&mut self) -> Result<(), StatementError> {
+        let client_id = self
+            .writers
+            .iter()
+            .min_by_key(|(_client_id, (last_use, _writer))| *last_use)
+            .map(|(client_id, _writer)| *client_id);
+        if let Some((_last_use, writer)) = client_id.and_then(|id| self.writers.remove(&id)) {
+            close(writer)?;
+        }
+        Ok(())
+    }
+
+    fn close_all(self) -> Result<(), StatementError> {
+        for (_last_use, writer) in self.writers.into_values() {
+            close(writer)?;
+        }
+        Ok(())
+    }
+}
+
+fn close(writer: csv::Writer<BufWriter<File>>) -> Result<(), StatementError> {
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .into_inner()
+        .map_err(|err| err.into_error())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Chargeback, Deposit, Dispute, Transaction, Withdrawal};
+    use crate::accounting::Ledger;
+    use rust_decimal_macros::dec;
+
+    fn chargeback_scenario() -> Ledger {
+        let mut ledger = Ledger::builder().journal().build();
+        for tx in [
+            Transaction::Deposit(Deposit::new(1, 1, dec!(50.0))),
+            Transaction::Deposit(Deposit::new(2, 2, dec!(7.5))),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(20.0))),
+            Transaction::Deposit(Deposit::new(1, 4, dec!(30.0))),
+            Transaction::Dispute(Dispute::new(1, 4)),
+            // Rejected, so not on the statement
+            Transaction::Withdrawal(Withdrawal::new(1, 5, dec!(100.0))),
+            Transaction::Chargeback(Chargeback::new(1, 4)),
+            Transaction::Deposit(Deposit::new(3, 6, dec!(1.0))),
+        ] {
+            ledger.execute(&tx).ok();
+        }
+        ledger
+    }
+
+    #[test]
+    fn statement_has_running_balances() {
+        let ledger = chargeback_scenario();
+        let mut output = Vec::new();
+        let rows = generate_statement(ledger.journal(), 1, &mut output).unwrap();
+        assert_eq!(rows, 5);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "seq,tx,kind,amount,available_after,held_after\n\
+             0,1,deposit,50.0,50.0,0\n\
+             2,3,withdrawal,20.0,30.0,0\n\
+             3,4,deposit,30.0,60.0,0\n\
+             4,4,dispute,30.0,30.0,30.0\n\
+             5,4,chargeback,30.0,30.0,0.0\n"
+        );
+
+        let mut output = Vec::new();
+        assert_eq!(
+            generate_statement(ledger.journal(), 9, &mut output).unwrap(),
+            0
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "seq,tx,kind,amount,available_after,held_after\n"
+        );
+    }
+
+    #[test]
+    fn bulk_statements_match_single_ones() {
+        let dir =
+            std::env::temp_dir().join(format!("payments_engine_statements_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let ledger = chargeback_scenario();
+        let journal = ledger.journal();
+        // A single open file makes clients 1 and 2 evict each other.
+        let client_ids = write_statements(journal.iter().cloned().map(Ok), &dir, 1).unwrap();
+        assert_eq!(client_ids, vec![1, 2, 3]);
+
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["client_1.csv", "client_2.csv", "client_3.csv"]);
+        for client_id in client_ids {
+            let mut expected = Vec::new();
+            generate_statement(journal, client_id, &mut expected).unwrap();
+            assert_eq!(
+                std::fs::read(statement_path(&dir, client_id)).unwrap(),
+                expected
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,412 @@
+//! Classification tags of processed rows, computed from declarative rules read from
+//! TOML:
+//! ```toml
+//! [rule.large_debit]
+//! when = { kind = "withdrawal", amount_gt = "1000" }
+//!
+//! [rule.watched_rejection]
+//! when = { client_in = [7, 12], is_rejected = true }
+//! ```
+//! A rule matches a row when all its predicates hold, and several rules can match
+//! the same row. The names of the matching rules are passed to the audit sinks, see
+//! `AuditSink::record_tagged`, and counted in `RunStats::tag_counts`. Rules hold no
+//! code: the predicates of `TagPredicates` are all there is.
+use crate::accounting::transactions::{Transaction, TxTypeTag};
+use crate::accounting::TxError;
+use crate::core_types::ClientId;
+use crate::pipeline::RowOutcome;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Predicates of a rule. Unset predicates always hold, but a rule needs at least one.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagPredicates {
+    pub kind: Option<TxTypeTag>,
+    /// Bounds of the amount, which never hold for transactions without one, such as
+    /// disputes.
+    pub amount_gt: Option<Decimal>,
+    pub amount_lt: Option<Decimal>,
+    pub amount_eq: Option<Decimal>,
+    pub client_in: Option<Vec<ClientId>>,
+    /// Whether the ledger rejected the transaction.
+    pub is_rejected: Option<bool>,
+    /// Code of the error the ledger rejected the transaction with, see `TxError::code`.
+    /// Unknown codes are rejected when reading the rules.
+    #[serde(default, deserialize_with = "deserialize_error_code")]
+    pub error_code: Option<String>,
+}
+
+fn deserialize_error_code<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let code = String::deserialize(deserializer)?;
+    TxError::from_str(&code)
+        .map(|err| Some(err.code().to_string()))
+        .map_err(|_err| serde::de::Error::custom(format!("unknown error code `{}`", code)))
+}
+
+impl TagPredicates {
+    /// Whether the predicates hold for `tx`, rejected with `error` if any.
+    pub fn matches(&self, tx: &Transaction, error: Option<&TxError>) -> bool {
+        let amount = tx.amount();
+        let compares = |bound: Option<Decimal>, ordering: Ordering| {
+            bound.is_none_or(|bound| amount.is_some_and(|amount| amount.cmp(&bound) == ordering))
+        };
+        self.kind.is_none_or(|kind| kind == tx.kind())
+            && compares(self.amount_gt, Ordering::Greater)
+            && compares(self.amount_lt, Ordering::Less)
+            && compares(self.amount_eq, Ordering::Equal)
+            && self
+                .client_in
+                .as_ref()
+                .is_none_or(|clients| clients.contains(&tx.client_id()))
+            && self
+                .is_rejected
+                .is_none_or(|rejected| rejected == error.is_some())
+            && self
+                .error_code
+                .as_ref()
+                .is_none_or(|code| error.is_some_and(|err| err.code() == code))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagRule {
+    /// Name of the rule, which is the tag of the rows it matches.
+    pub name: String,
+    pub when: TagPredicates,
+}
+
+/// Rules tagging the rows of a run, see `Pipeline::tag_rules`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagRules {
+    rules: Vec<TagRule>,
+}
+
+impl TagRules {
+    /// Rules in the order their tags are listed in.
+    pub fn new(rules: Vec<TagRule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn rules(&self) -> &[TagRule] {
+        &self.rules
+    }
+
+    /// Indices of the rules matching the row. Only rows with a transaction which
+    /// the ledger executed, accepted as a duplicate, deferred or rejected are
+    /// tagged, so filtered and malformed rows and derived postings never are.
+    pub fn matching(&self, outcome: &RowOutcome<'_>) -> Vec<usize> {
+        let (tx, error) = match outcome {
+            RowOutcome::Executed(tx) | RowOutcome::Duplicate(tx) | RowOutcome::Deferred(tx) => {
+                (*tx, None)
+            }
+            RowOutcome::Rejected(tx, err) => (*tx, Some(*err)),
+            RowOutcome::Filtered(_) | RowOutcome::Malformed(..) | RowOutcome::Derived(_) => {
+                return Vec::new()
+            }
+        };
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(|(_index, rule)| rule.when.matches(tx, error))
+            .map(|(index, _rule)| index)
+            .collect()
+    }
+
+    /// Reads the rules of a `[rule.<name>]` table each, in name order. Names consist
+    /// of ASCII letters, digits, `_` and `-`, so that they can be listed in a CSV
+    /// field.
+    #[cfg(feature = "io")]
+    pub fn from_toml(text: &str) -> Result<Self, TagRuleError> {
+        use std::collections::BTreeMap;
+        use toml::Spanned;
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct RuleFile {
+            #[serde(default)]
+            rule: BTreeMap<Spanned<String>, RuleTable>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct RuleTable {
+            when: Spanned<TagPredicates>,
+        }
+
+        let file: RuleFile = toml::from_str(text).map_err(|err| {
+            TagRuleError::invalid(text, err.span().map_or(0, |span| span.start), err.message())
+        })?;
+        let mut rules = Vec::with_capacity(file.rule.len());
+        for (name, table) in file.rule {
+            let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+            if name.as_ref().is_empty() || !name.as_ref().chars().all(valid_name) {
+                return Err(TagRuleError::invalid(
+                    text,
+                    name.span().start,
+                    &format!(
+                        "rule name `{}` may only hold ASCII letters, digits, `_` and `-`",
+                        name.as_ref()
+                    ),
+                ));
+            }
+            if *table.when.as_ref() == TagPredicates::default() {
+                return Err(TagRuleError::invalid(
+                    text,
+                    table.when.span().start,
+                    &format!("rule `{}` has no predicates", name.as_ref()),
+                ));
+            }
+            rules.push(TagRule {
+                name: name.into_inner(),
+                when: table.when.into_inner(),
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    #[cfg(feature = "io")]
+    pub fn read(path: impl AsRef<std::path::Path>) -> Result<Self, TagRuleError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| {
+            TagRuleError::Read(format!("failed to read {}: {}", path.display(), err))
+        })?;
+        Self::from_toml(&text)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TagRuleError {
+    Read(String),
+    /// The rules are invalid at the 1-based `line` and `column` of the TOML.
+    Invalid {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl TagRuleError {
+    /// Stable snake_case code identifying the error in reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TagRuleError::Read(_) => "unreadable_tag_rules",
+            TagRuleError::Invalid { .. } => "invalid_tag_rules",
+        }
+    }
+
+    /// Error at byte `offset` of `text`.
+    #[cfg(feature = "io")]
+    fn invalid(text: &str, offset: usize, message: &str) -> Self {
+        let before = &text[..offset.min(text.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        TagRuleError::Invalid {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for TagRuleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagRuleError::Read(message) => write!(f, "invalid tag rules: {}", message),
+            TagRuleError::Invalid {
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "invalid tag rules at line {}, column {}: {}",
+                line, column, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TagRuleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Dispute, Withdrawal};
+    use rust_decimal_macros::dec;
+
+    fn withdrawal(client_id: ClientId, amount: Decimal) -> Transaction {
+        Transaction::Withdrawal(Withdrawal::new(client_id, 1, amount))
+    }
+
+    #[cfg(feature = "io")]
+    fn tags(rules: &TagRules, outcome: &RowOutcome<'_>) -> Vec<String> {
+        rules
+            .matching(outcome)
+            .into_iter()
+            .map(|index| rules.rules()[index].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn each_predicate() {
+        let large = withdrawal(1, dec!(1500));
+        let small = withdrawal(2, dec!(10));
+        let dispute = Transaction::Dispute(Dispute::new(1, 1));
+        let funds = TxError::InsufficientFunds;
+        let matches = |when: TagPredicates, tx: &Transaction, error: Option<&TxError>| {
+            when.matches(tx, error)
+        };
+
+        let kind = TagPredicates {
+            kind: Some(TxTypeTag::Withdrawal),
+            ..TagPredicates::default()
+        };
+        assert!(matches(kind.clone(), &large, None));
+        assert!(!matches(kind, &dispute, None));
+
+        let above = TagPredicates {
+            amount_gt: Some(dec!(1000)),
+            ..TagPredicates::default()
+        };
+        assert!(matches(above.clone(), &large, None));
+        assert!(!matches(above.clone(), &small, None));
+        assert!(!matches(above, &dispute, None));
+        let below = TagPredicates {
+            amount_lt: Some(dec!(1000)),
+            ..TagPredicates::default()
+        };
+        assert!(matches(below.clone(), &small, None));
+        assert!(!matches(below, &large, None));
+        let equal = TagPredicates {
+            amount_eq: Some(dec!(10.00)),
+            ..TagPredicates::default()
+        };
+        assert!(matches(equal.clone(), &small, None));
+        assert!(!matches(equal, &large, None));
+
+        let clients = TagPredicates {
+            client_in: Some(vec![2, 3]),
+            ..TagPredicates::default()
+        };
+        assert!(matches(clients.clone(), &small, None));
+        assert!(!matches(clients, &large, None));
+
+        let rejected = TagPredicates {
+            is_rejected: Some(true),
+            ..TagPredicates::default()
+        };
+        assert!(matches(rejected.clone(), &large, Some(&funds)));
+        assert!(!matches(rejected, &large, None));
+        let applied = TagPredicates {
+            is_rejected: Some(false),
+            ..TagPredicates::default()
+        };
+        assert!(matches(applied, &large, None));
+
+        let code = TagPredicates {
+            error_code: Some("insufficient_funds".to_string()),
+            ..TagPredicates::default()
+        };
+        assert!(matches(code.clone(), &large, Some(&funds)));
+        assert!(!matches(
+            code.clone(),
+            &large,
+            Some(&TxError::ClientAccountLocked)
+        ));
+        assert!(!matches(code, &large, None));
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn several_rules_match_one_row_in_name_order() {
+        use crate::accounting::transactions::Deposit;
+
+        let rules = TagRules::from_toml(
+            r#"
+[rule.large_debit]
+when = { kind = "withdrawal", amount_gt = "1000" }
+
+[rule.failed_debit]
+when = { kind = "withdrawal", is_rejected = true, error_code = "insufficient_funds" }
+
+[rule.watched]
+when = { client_in = [1, 7] }
+"#,
+        )
+        .unwrap();
+        let tx = withdrawal(7, dec!(1000.01));
+        let err = TxError::InsufficientFunds;
+        assert_eq!(
+            tags(&rules, &RowOutcome::Rejected(&tx, &err)),
+            ["failed_debit", "large_debit", "watched"]
+        );
+        assert_eq!(
+            tags(&rules, &RowOutcome::Executed(&tx)),
+            ["large_debit", "watched"]
+        );
+        assert!(tags(&rules, &RowOutcome::Filtered(&tx)).is_empty());
+        let deposit = Transaction::Deposit(Deposit::new(2, 2, dec!(5000)));
+        assert!(tags(&rules, &RowOutcome::Executed(&deposit)).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn invalid_rules_are_rejected_at_their_position() {
+        let invalid = |text: &str| TagRules::from_toml(text).unwrap_err().to_string();
+        assert_eq!(
+            invalid("[rule.a]\nwhen = { kind = \"withdraw\" }\n"),
+            "invalid tag rules at line 2, column 17: unknown variant `withdraw`, expected one \
+             of `deposit`, `withdrawal`, `dispute`, `resolve`, `chargeback`, `adjustment`, \
+             `withdraw_auth`, `withdraw_capture`, `withdraw_release`, `settle`"
+        );
+        assert_eq!(
+            invalid("[rule.a]\nwhen = { amount_gt = \"ten\" }\n"),
+            "invalid tag rules at line 2, column 22: invalid value: string \"ten\", expected a \
+             Decimal type representing a fixed-point number"
+        );
+        assert_eq!(
+            invalid("[rule.a]\nwhen = { client_in = [1, 70000] }\n"),
+            "invalid tag rules at line 2, column 26: invalid value: integer `70000`, expected u16"
+        );
+        assert_eq!(
+            invalid("[rule.a]\nwhen = { error_code = \"broke\" }\n"),
+            "invalid tag rules at line 2, column 23: unknown error code `broke`"
+        );
+        assert_eq!(
+            invalid("[rule.a]\nwhen = { amount_above = \"1\" }\n"),
+            "invalid tag rules at line 2, column 10: unknown field `amount_above`, expected one \
+             of `kind`, `amount_gt`, `amount_lt`, `amount_eq`, `client_in`, `is_rejected`, \
+             `error_code`"
+        );
+        assert_eq!(
+            invalid("[rule.a]\nwhen = {}\n"),
+            "invalid tag rules at line 2, column 8: rule `a` has no predicates"
+        );
+        assert_eq!(
+            invalid("[rule.\"a,b\"]\nwhen = { is_rejected = true }\n"),
+            "invalid tag rules at line 1, column 7: rule name `a,b` may only hold ASCII \
+             letters, digits, `_` and `-`"
+        );
+        assert_eq!(TagRules::from_toml(""), Ok(TagRules::default()));
+    }
+
+    #[test]
+    fn error_codes() {
+        let errors = [
+            TagRuleError::Read(String::new()),
+            TagRuleError::Invalid {
+                line: 1,
+                column: 1,
+                message: String::new(),
+            },
+        ];
+        assert_eq!(
+            errors.map(|err| err.code()),
+            ["unreadable_tag_rules", "invalid_tag_rules"]
+        );
+    }
+}
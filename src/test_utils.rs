@@ -0,0 +1,136 @@
+#[cfg(feature = "io")]
+use crate::accounting::{AccountLog, Ledger};
+#[cfg(feature = "io")]
+use crate::scenario::random_workload;
+use crate::sink::SinkError;
+#[cfg(feature = "io")]
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+#[cfg(feature = "io")]
+use std::time::Duration;
+
+/// Sink failing its first `failures` calls.
+pub(crate) struct FlakySink {
+    failures: u32,
+    pub(crate) calls: u32,
+}
+
+impl FlakySink {
+    pub(crate) fn new(failures: u32) -> Self {
+        Self { failures, calls: 0 }
+    }
+
+    pub(crate) fn send(&mut self) -> Result<(), SinkError> {
+        self.calls += 1;
+        if self.calls <= self.failures {
+            return Err(SinkError::new(format!("call {} failed", self.calls)));
+        }
+        Ok(())
+    }
+}
+
+/// Writes `contents` into a temporary file unique to `name` and the test process.
+pub(crate) fn write_input(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_{}_{}.csv",
+        name,
+        std::process::id()
+    ));
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(contents.as_bytes())
+        .unwrap();
+    path
+}
+
+/// Generates `rows` pseudo-random transactions of 50 clients, with disputes, resolves
+/// and chargebacks referencing random earlier transaction ids.
+#[cfg(feature = "io")]
+pub(crate) fn generate_input(rows: u32) -> String {
+    random_workload(42, rows, 50).to_csv_string()
+}
+
+/// Serializes the ledger accounts sorted by client id.
+#[cfg(feature = "io")]
+pub(crate) fn render(ledger: &Ledger) -> Vec<u8> {
+    let mut accounts = ledger.accounts_iter().collect::<Vec<_>>();
+    accounts.sort_by_key(|(client_id, _)| **client_id);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for (_client_id, user_account) in accounts {
+        writer.serialize(AccountLog::from(user_account)).unwrap();
+    }
+    writer.into_inner().unwrap()
+}
+
+#[cfg(feature = "io")]
+/// Seeded pauses injected between rows by `reader_jitter` and `consumer_jitter`, so
+/// that the determinism test perturbs the scheduling of ingestion reproducibly.
+pub(crate) struct Jitter {
+    seed: u64,
+}
+
+#[cfg(feature = "io")]
+enum Pause {
+    Yield,
+    Sleep(Duration),
+}
+
+#[cfg(feature = "io")]
+impl Jitter {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Yields on one in 16 rows and sleeps up to 50µs on one in 512.
+    fn next_pause(&mut self) -> Option<Pause> {
+        self.seed = self
+            .seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let value = (self.seed >> 33) as u32;
+        match value % 512 {
+            0 => Some(Pause::Sleep(Duration::from_micros(u64::from(value % 50)))),
+            1..=32 => Some(Pause::Yield),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "io")]
+tokio::task_local! {
+    /// Jitter of the ingestion task, set with `READER_JITTER.scope`.
+    pub(crate) static READER_JITTER: RefCell<Jitter>;
+}
+
+#[cfg(feature = "io")]
+thread_local! {
+    /// Jitter of the thread consuming the ingestion channel.
+    pub(crate) static CONSUMER_JITTER: RefCell<Option<Jitter>> = const { RefCell::new(None) };
+}
+
+/// Pauses the ingestion task before sending a row, when it runs in the scope of
+/// `READER_JITTER`.
+#[cfg(feature = "io")]
+pub(crate) async fn reader_jitter() {
+    let pause = READER_JITTER
+        .try_with(|jitter| jitter.borrow_mut().next_pause())
+        .ok()
+        .flatten();
+    match pause {
+        Some(Pause::Yield) => tokio::task::yield_now().await,
+        Some(Pause::Sleep(duration)) => std::thread::sleep(duration),
+        None => {}
+    }
+}
+
+#[cfg(feature = "io")]
+/// Pauses the consumer before receiving a row, when its thread set `CONSUMER_JITTER`.
+pub(crate) fn consumer_jitter() {
+    let pause = CONSUMER_JITTER.with(|jitter| jitter.borrow_mut().as_mut()?.next_pause());
+    match pause {
+        Some(Pause::Yield) => std::thread::yield_now(),
+        Some(Pause::Sleep(duration)) => std::thread::sleep(duration),
+        None => {}
+    }
+}
@@ -0,0 +1,283 @@
+//! Top-K lists giving a quick overview of a run: the largest accounts, the largest
+//! open disputes and the clients with the most rejected transactions.
+//!
+//! Balances keep changing during a run, so the accounts and disputes are ranked once
+//! at the end, in a single pass over the ledger keeping K entries in a heap. Rejections
+//! are counted while the run executes instead, one exact counter per rejected client
+//! in a `HashMap`: client ids are 16 bits wide, so the map is bounded by 65,536
+//! counters, which an approximate count-min sketch would not improve on.
+//!
+//! Every list is sorted from the largest value down, ties broken by ascending client
+//! id and then tx id, so that the lists are the same on every run.
+use crate::accounting::view::LedgerView;
+use crate::core_types::{ClientId, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt::{Display, Formatter};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopAccount {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub total: Decimal,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopDispute {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    pub amount: Decimal,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopRejectedClient {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub rejections: u64,
+}
+
+/// Top-K lists of a run, see `Pipeline::top_k` and `RunStats::top_k`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TopKReport {
+    pub k: usize,
+    /// Accounts with the largest total balance.
+    pub accounts: Vec<TopAccount>,
+    /// Open disputes with the largest amount.
+    pub disputes: Vec<TopDispute>,
+    /// Clients with the most rejected transactions.
+    pub rejected_clients: Vec<TopRejectedClient>,
+}
+
+/// Counts rejections during a run, and ranks the ledger at its end.
+#[derive(Debug, Clone)]
+pub struct TopKTracker {
+    k: usize,
+    rejections: HashMap<ClientId, u64>,
+}
+
+impl TopKTracker {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            rejections: HashMap::new(),
+        }
+    }
+
+    /// Counts a rejected transaction of `client_id`.
+    pub fn record_rejection(&mut self, client_id: ClientId) {
+        *self.rejections.entry(client_id).or_default() += 1;
+    }
+
+    /// Lists of the rejections counted so far and of the accounts and open disputes of
+    /// `ledger` as they are now.
+    pub fn report<'a>(&self, ledger: impl Into<LedgerView<'a>>) -> TopKReport {
+        let ledger: LedgerView = ledger.into();
+        let accounts = top_k(
+            ledger
+                .accounts_iter()
+                .map(|(client_id, user_account)| Ranked {
+                    value: user_account.total(),
+                    client_id: *client_id,
+                    tx_id: 0,
+                }),
+            self.k,
+        );
+        let disputes = top_k(
+            ledger.open_disputes().map(|dispute| Ranked {
+                value: dispute.amount,
+                client_id: dispute.client_id,
+                tx_id: dispute.tx_id,
+            }),
+            self.k,
+        );
+        let rejected_clients = top_k(
+            self.rejections
+                .iter()
+                .map(|(client_id, rejections)| Ranked {
+                    value: *rejections,
+                    client_id: *client_id,
+                    tx_id: 0,
+                }),
+            self.k,
+        );
+        TopKReport {
+            k: self.k,
+            accounts: accounts
+                .into_iter()
+                .map(|ranked| TopAccount {
+                    client_id: ranked.client_id,
+                    total: ranked.value,
+                })
+                .collect(),
+            disputes: disputes
+                .into_iter()
+                .map(|ranked| TopDispute {
+                    client_id: ranked.client_id,
+                    tx_id: ranked.tx_id,
+                    amount: ranked.value,
+                })
+                .collect(),
+            rejected_clients: rejected_clients
+                .into_iter()
+                .map(|ranked| TopRejectedClient {
+                    client_id: ranked.client_id,
+                    rejections: ranked.value,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Entry of a list, ordered so that the greatest entry ranks first: the largest value,
+/// then the lowest client id and tx id.
+#[derive(PartialEq, Eq)]
+struct Ranked<V> {
+    value: V,
+    client_id: ClientId,
+    tx_id: TxId,
+}
+
+impl<V: Ord> Ord for Ranked<V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value
+            .cmp(&other.value)
+            .then_with(|| other.client_id.cmp(&self.client_id))
+            .then_with(|| other.tx_id.cmp(&self.tx_id))
+    }
+}
+
+impl<V: Ord> PartialOrd for Ranked<V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The `k` greatest items, greatest first, holding at most `k` items at a time.
+fn top_k<T: Ord>(items: impl Iterator<Item = T>, k: usize) -> Vec<T> {
+    let mut heap = BinaryHeap::with_capacity(k.saturating_add(1).min(1024));
+    for item in items {
+        heap.push(Reverse(item));
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(item)| item)
+        .collect()
+}
+
+/// Writes the lists as an indented block, e.g. for `stderr`.
+impl Display for TopKReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Top {} accounts by total:", self.k)?;
+        for account in &self.accounts {
+            writeln!(f, "  client {}: {}", account.client_id, account.total)?;
+        }
+        writeln!(f, "Top {} open disputes by amount:", self.k)?;
+        for dispute in &self.disputes {
+            writeln!(
+                f,
+                "  client {} tx {}: {}",
+                dispute.client_id, dispute.tx_id, dispute.amount
+            )?;
+        }
+        writeln!(f, "Top {} clients by rejected transactions:", self.k)?;
+        for client in &self.rejected_clients {
+            writeln!(f, "  client {}: {}", client.client_id, client.rejections)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::transactions::{Deposit, Dispute, Transaction};
+    use crate::accounting::Ledger;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn ties_rank_the_lower_client_first() {
+        let mut ledger = Ledger::new();
+        for (client_id, tx_id, amount) in [
+            (5, 1, dec!(10)),
+            (3, 2, dec!(10)),
+            (4, 3, dec!(30)),
+            (1, 4, dec!(5)),
+            (2, 5, dec!(10)),
+            (4, 6, dec!(10)),
+        ] {
+            ledger
+                .execute(&Transaction::Deposit(Deposit::new(
+                    client_id, tx_id, amount,
+                )))
+                .unwrap();
+        }
+        for (client_id, tx_id) in [(4, 6), (5, 1), (3, 2), (1, 4)] {
+            ledger
+                .execute(&Transaction::Dispute(Dispute::new(client_id, tx_id)))
+                .unwrap();
+        }
+        let mut tracker = TopKTracker::new(3);
+        for client_id in [7, 2, 9, 7, 2, 1, 9] {
+            tracker.record_rejection(client_id);
+        }
+        let report = tracker.report(&ledger);
+        let accounts: Vec<_> = report
+            .accounts
+            .iter()
+            .map(|account| (account.client_id, account.total))
+            .collect();
+        assert_eq!(accounts, [(4, dec!(40)), (2, dec!(10)), (3, dec!(10))]);
+        let disputes: Vec<_> = report
+            .disputes
+            .iter()
+            .map(|dispute| (dispute.client_id, dispute.tx_id))
+            .collect();
+        assert_eq!(disputes, [(3, 2), (4, 6), (5, 1)]);
+        let rejected: Vec<_> = report
+            .rejected_clients
+            .iter()
+            .map(|client| (client.client_id, client.rejections))
+            .collect();
+        assert_eq!(rejected, [(2, 2), (7, 2), (9, 2)]);
+        assert_eq!(
+            report.to_string(),
+            "Top 3 accounts by total:\n  client 4: 40\n  client 2: 10\n  client 3: 10\n\
+             Top 3 open disputes by amount:\n  client 3 tx 2: 10\n  client 4 tx 6: 10\n  \
+             client 5 tx 1: 10\n\
+             Top 3 clients by rejected transactions:\n  client 2: 2\n  client 7: 2\n  \
+             client 9: 2\n"
+        );
+    }
+
+    #[test]
+    fn top_k_matches_a_full_sort() {
+        let mut lcg = crate::scenario::Lcg::new(414);
+        for k in [0, 1, 5, 50] {
+            let items: Vec<_> = (0..40)
+                .map(|tx_id| Ranked {
+                    value: lcg.next() % 8,
+                    client_id: (lcg.next() % 6) as ClientId,
+                    tx_id,
+                })
+                .collect();
+            let mut sorted: Vec<_> = items
+                .iter()
+                .map(|ranked| (ranked.value, ranked.client_id, ranked.tx_id))
+                .collect();
+            sorted.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+            sorted.truncate(k);
+            let top: Vec<_> = top_k(items.into_iter(), k)
+                .into_iter()
+                .map(|ranked| (ranked.value, ranked.client_id, ranked.tx_id))
+                .collect();
+            assert_eq!(top, sorted);
+        }
+    }
+}
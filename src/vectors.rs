@@ -0,0 +1,388 @@
+//! Canonical test vectors for other implementations of the rules: generated inputs
+//! with the account output of this engine, written by
+//! `cargo run --features testkit --bin genvectors -- --seed 42` into `tests/vectors/`.
+//!
+//! The committed vectors are reviewed once and then frozen, and `tests/vectors.rs`
+//! fails when the engine stops producing their expected output byte for byte. A
+//! deliberate change to the rules regenerates them.
+use crate::engine::process_csv_str;
+use crate::scenario::{random_workload, Lcg, ScenarioBuilder};
+use crate::EngineError;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Contents of `meta.json`, describing what a vector exercises.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorMetadata {
+    /// Name of the directory of the vector.
+    pub name: String,
+    pub description: String,
+    /// Rules the vector exercises, as short tags such as `chargeback`.
+    pub exercises: Vec<String>,
+    /// Seed the input was generated with.
+    pub seed: u64,
+    /// Lines of the input after the header, including blank and malformed ones.
+    pub rows: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVector {
+    pub metadata: VectorMetadata,
+    pub input: String,
+}
+
+impl TestVector {
+    fn new(seed: u64, name: &str, description: &str, exercises: &[&str], input: String) -> Self {
+        Self {
+            metadata: VectorMetadata {
+                name: name.to_string(),
+                description: description.to_string(),
+                exercises: exercises.iter().map(|tag| tag.to_string()).collect(),
+                seed,
+                rows: input.lines().count().saturating_sub(1),
+            },
+            input,
+        }
+    }
+
+    /// Account output of the engine for the input, sorted by client id.
+    pub fn expected_output(&self) -> Result<String, EngineError> {
+        process_csv_str(&self.input)
+    }
+
+    /// Writes `input.csv`, `expected.csv` and `meta.json` into `<dir>/<name>/`.
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<(), EngineError> {
+        let dir = dir.as_ref().join(&self.metadata.name);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("input.csv"), &self.input)?;
+        std::fs::write(dir.join("expected.csv"), self.expected_output()?)?;
+        let mut metadata =
+            serde_json::to_string_pretty(&self.metadata).expect("vector metadata serializes");
+        metadata.push('\n');
+        std::fs::write(dir.join("meta.json"), metadata)?;
+        Ok(())
+    }
+}
+
+/// Every vector, of escalating complexity, generated from `seed`. Each vector draws
+/// from its own generator, so changing one leaves the others as they were.
+pub fn generate_vectors(seed: u64) -> Vec<TestVector> {
+    let generators: [fn(u64) -> TestVector; 12] = [
+        deposits,
+        withdrawals,
+        resolved_disputes,
+        open_disputes,
+        chargebacks,
+        invalid_references,
+        malformed_rows,
+        decimal_boundaries,
+        duplicate_tx_ids,
+        whitespace_and_layout,
+        random_small,
+        random_large,
+    ];
+    generators
+        .iter()
+        .enumerate()
+        .map(|(index, generate)| generate(seed.wrapping_add(index as u64)))
+        .collect()
+}
+
+/// Amount with up to four decimal places below `max`.
+fn amount(rng: &mut Lcg, max: u32) -> String {
+    format!("{}.{:04}", rng.next() % max, rng.next() % 10000)
+}
+
+fn deposits(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let mut scenario = ScenarioBuilder::new();
+    for tx_id in 1..=30 {
+        let client_id = (rng.next() % 5 + 1) as u16;
+        scenario = scenario.deposit(client_id, tx_id, &amount(&mut rng, 1000));
+    }
+    TestVector::new(
+        seed,
+        "01_deposits",
+        "Deposits only, summed per client.",
+        &["deposit"],
+        scenario.to_csv_string(),
+    )
+}
+
+fn withdrawals(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let mut scenario = ScenarioBuilder::new();
+    for tx_id in 1..=40 {
+        let client_id = (rng.next() % 4 + 1) as u16;
+        scenario = if rng.next().is_multiple_of(2) {
+            scenario.deposit(client_id, tx_id, &amount(&mut rng, 100))
+        } else {
+            scenario.withdraw(client_id, tx_id, &amount(&mut rng, 150))
+        };
+    }
+    TestVector::new(
+        seed,
+        "02_withdrawals",
+        "Deposits and withdrawals, some of which exceed the available funds and are \
+         rejected.",
+        &["deposit", "withdrawal", "insufficient_funds"],
+        scenario.to_csv_string(),
+    )
+}
+
+fn resolved_disputes(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let mut scenario = ScenarioBuilder::new();
+    for tx_id in (1..=30).step_by(3) {
+        let client_id = (rng.next() % 3 + 1) as u16;
+        scenario = scenario
+            .deposit(client_id, tx_id, &amount(&mut rng, 100))
+            .withdraw(client_id, tx_id + 1, &amount(&mut rng, 20))
+            .dispute(client_id, tx_id)
+            .resolve(client_id, tx_id);
+    }
+    TestVector::new(
+        seed,
+        "03_resolved_disputes",
+        "Deposits disputed and resolved again, moving funds to held and back.",
+        &["deposit", "withdrawal", "dispute", "resolve"],
+        scenario.to_csv_string(),
+    )
+}
+
+fn open_disputes(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let mut scenario = ScenarioBuilder::new();
+    for tx_id in (1..=40).step_by(4) {
+        let client_id = (rng.next() % 3 + 1) as u16;
+        scenario = scenario
+            .deposit(client_id, tx_id, &amount(&mut rng, 100))
+            .dispute(client_id, tx_id)
+            // Held funds are not available to withdraw.
+            .withdraw(client_id, tx_id + 1, &amount(&mut rng, 100))
+            .dispute(client_id, tx_id)
+            .deposit(client_id, tx_id + 2, &amount(&mut rng, 10));
+    }
+    TestVector::new(
+        seed,
+        "04_open_disputes",
+        "Disputes left open, withdrawals of held funds and disputes of already disputed \
+         deposits.",
+        &["dispute", "held_funds", "already_disputed"],
+        scenario.to_csv_string(),
+    )
+}
+
+fn chargebacks(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let mut scenario = ScenarioBuilder::new();
+    for client_id in 1..=4 {
+        let tx_id = u32::from(client_id) * 10;
+        scenario = scenario
+            .deposit(client_id, tx_id, &amount(&mut rng, 100))
+            .deposit(client_id, tx_id + 1, &amount(&mut rng, 100))
+            .dispute(client_id, tx_id)
+            .chargeback(client_id, tx_id)
+            // Follow-ups on the locked account.
+            .deposit(client_id, tx_id + 2, &amount(&mut rng, 10))
+            .withdraw(client_id, tx_id + 3, "0.0001")
+            .dispute(client_id, tx_id + 1)
+            .resolve(client_id, tx_id + 1)
+            .dispute(client_id, tx_id)
+            .chargeback(client_id, tx_id);
+    }
+    TestVector::new(
+        seed,
+        "05_chargebacks",
+        "Chargebacks locking accounts, followed by deposits, withdrawals, disputes and \
+         repeated chargebacks on the locked accounts.",
+        &["chargeback", "locked_account", "already_charged_back"],
+        scenario.to_csv_string(),
+    )
+}
+
+fn invalid_references(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let scenario = ScenarioBuilder::new()
+        .deposit(1, 1, &amount(&mut rng, 100))
+        .deposit(2, 2, &amount(&mut rng, 100))
+        .withdraw(1, 3, &amount(&mut rng, 10))
+        // Unknown tx, another client's tx, a withdrawal, and unknown clients.
+        .dispute(1, 99)
+        .dispute(1, 2)
+        .dispute(1, 3)
+        .dispute(3, 1)
+        .withdraw(4, 4, "1")
+        // Resolving and charging back undisputed deposits.
+        .resolve(1, 1)
+        .chargeback(2, 2)
+        .dispute(2, 2)
+        .resolve(1, 2)
+        .chargeback(2, 2);
+    TestVector::new(
+        seed,
+        "06_invalid_references",
+        "Disputes, resolves and chargebacks of unknown transactions, of other clients' \
+         transactions, of withdrawals and of undisputed deposits, and withdrawals of \
+         unknown clients.",
+        &["origin_tx_not_found", "tx_not_disputed", "client_mismatch"],
+        scenario.to_csv_string(),
+    )
+}
+
+fn malformed_rows(seed: u64) -> TestVector {
+    const MALFORMED: [&str; 8] = [
+        "refund,1,100,1.0",
+        "deposit,1,101,",
+        "deposit,x,102,1.0",
+        "deposit,1,-103,1.0",
+        "deposit,70000,104,1.0",
+        "withdrawal,1,105,abc",
+        "deposit,1,106,1.0,extra,fields",
+        ",,,",
+    ];
+    let mut rng = Lcg::new(seed);
+    let input = random_workload(seed, 40, 5).to_csv_string();
+    let mut lines: Vec<&str> = input.lines().collect();
+    for malformed in MALFORMED {
+        let position = rng.next() as usize % (lines.len() - 1) + 1;
+        lines.insert(position, malformed);
+    }
+    let mut input = lines.join("\n");
+    input.push('\n');
+    TestVector::new(
+        seed,
+        "07_malformed_rows",
+        "Random transactions interleaved with rows of unknown types, missing or invalid \
+         amounts, invalid client and tx ids and wrong field counts, which are skipped.",
+        &["malformed"],
+        input,
+    )
+}
+
+fn decimal_boundaries(seed: u64) -> TestVector {
+    let scenario = ScenarioBuilder::new()
+        .deposit(1, 1, "0.0001")
+        .deposit(1, 2, "0.0001")
+        .withdraw(1, 3, "0.0002")
+        .withdraw(1, 4, "0.0001")
+        .deposit(2, 5, "79228162514264.3375")
+        .withdraw(2, 6, "79228162514264.3374")
+        .deposit(3, 7, "1.2345")
+        .dispute(3, 7)
+        .resolve(3, 7)
+        .withdraw(3, 8, "1.2345");
+    let mut input = scenario.to_csv_string();
+    // Amounts beyond four decimal places and with trailing zeros.
+    input.push_str(
+        "deposit,4,9,1.00000\n\
+         deposit,4,10,0.00001\n\
+         deposit,4,11,2.50\n\
+         withdrawal,4,12,0.00005\n",
+    );
+    TestVector::new(
+        seed,
+        "08_decimal_boundaries",
+        "Amounts at four decimal places, withdrawals of exactly the available funds and \
+         one unit more, very large amounts, and amounts with more than four decimal \
+         places or trailing zeros.",
+        &["precision", "exact_balance"],
+        input,
+    )
+}
+
+fn duplicate_tx_ids(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let scenario = ScenarioBuilder::new()
+        .deposit(1, 1, &amount(&mut rng, 100))
+        .deposit(1, 1, &amount(&mut rng, 100))
+        .deposit(2, 1, &amount(&mut rng, 100))
+        .deposit(2, 2, &amount(&mut rng, 100))
+        .withdraw(2, 2, &amount(&mut rng, 10))
+        .withdraw(1, 3, &amount(&mut rng, 10))
+        .withdraw(1, 3, &amount(&mut rng, 10))
+        .dispute(1, 1)
+        .chargeback(1, 1)
+        .deposit(3, 4, &amount(&mut rng, 100))
+        .dispute(3, 4)
+        .dispute(3, 4)
+        .resolve(3, 4)
+        .resolve(3, 4);
+    TestVector::new(
+        seed,
+        "09_duplicate_tx_ids",
+        "Deposits and withdrawals reusing the transaction id of an earlier deposit or \
+         withdrawal, of the same or another client, which are rejected without moving \
+         funds, and repeated disputes and resolves, of which only the first applies.",
+        &["duplicate_tx", "already_disputed"],
+        scenario.to_csv_string(),
+    )
+}
+
+fn whitespace_and_layout(seed: u64) -> TestVector {
+    let mut rng = Lcg::new(seed);
+    let mut input = String::from(" type , client , tx , amount \n");
+    for tx_id in 1..=10 {
+        input.push_str(&format!(
+            "deposit , {} , {} , {}\n",
+            rng.next() % 3 + 1,
+            tx_id,
+            amount(&mut rng, 50)
+        ));
+    }
+    input.push_str(
+        "withdrawal,1,11,1.5\r\n\
+         \n\
+         dispute, 2, 2,\n\
+         dispute, 2, 3\n\
+         Deposit,1,12,1.0\n",
+    );
+    TestVector::new(
+        seed,
+        "10_whitespace_and_layout",
+        "Padded headers and fields, CRLF line endings, blank lines, dispute rows without \
+         the trailing comma and a capitalized transaction type.",
+        &["whitespace", "layout"],
+        input,
+    )
+}
+
+fn random_small(seed: u64) -> TestVector {
+    TestVector::new(
+        seed,
+        "11_random_small",
+        "500 random transactions of 10 clients, with disputes, resolves and chargebacks \
+         of random earlier transactions.",
+        &["deposit", "withdrawal", "dispute", "resolve", "chargeback"],
+        random_workload(seed, 500, 10).to_csv_string(),
+    )
+}
+
+fn random_large(seed: u64) -> TestVector {
+    TestVector::new(
+        seed,
+        "12_random_large",
+        "2000 random transactions of 50 clients, with disputes, resolves and chargebacks \
+         of random earlier transactions.",
+        &["deposit", "withdrawal", "dispute", "resolve", "chargeback"],
+        random_workload(seed, 2000, 50).to_csv_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generation_is_reproducible() {
+        let vectors = generate_vectors(42);
+        assert_eq!(vectors, generate_vectors(42));
+        assert_ne!(vectors, generate_vectors(43));
+        let mut names: Vec<_> = vectors.iter().map(|v| v.metadata.name.as_str()).collect();
+        names.dedup();
+        assert_eq!(names.len(), 12);
+        for vector in &vectors {
+            assert!(vector.expected_output().is_ok(), "{}", vector.metadata.name);
+        }
+    }
+}
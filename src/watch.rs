@@ -0,0 +1,677 @@
+//! Watches a directory for input files dropped by a producer, such as hourly exports,
+//! and runs each of them once against a single ledger.
+//!
+//! A file is picked up once its name matches the pattern and its size and
+//! modification time stayed the same for the settle period, so files still being
+//! written are left alone. Settled files run in filename order, and a file waits while
+//! a file sorting before it is still settling. Each file runs in two passes with
+//! `PaymentsEngine::run_two_pass`, so a file failing preflight leaves the ledger as it
+//! was:
+//!
+//! * a processed file is moved into `processed/`, after the account snapshot was
+//!   rewritten and the file was appended to the journal `processed/.journal.csv`,
+//! * a failed file is moved into `failed/`, next to `<file>.error.txt` describing why.
+//!
+//! The journal holds the name and SHA-256 digest of every processed file. A file
+//! found in the journal is moved into `processed/` without running again, and a file
+//! reusing a processed name with other contents fails. When the watcher opens a
+//! directory, the files of the journal are replayed from `processed/` into the ledger,
+//! so that a restarted watcher continues with the balances it stopped at.
+use crate::accounting::Ledger;
+use crate::clock::{SharedClock, Timestamp};
+use crate::engine::{accounts_csv, PaymentsEngine, RunStats, TwoPassError};
+use crate::ingest::manifest::hex_digest;
+use crate::staging::Staging;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Subdirectory of the processed files and the journal.
+pub const PROCESSED_DIR: &str = "processed";
+/// Subdirectory of the failed files and their error reports.
+pub const FAILED_DIR: &str = "failed";
+/// Name of the journal within `PROCESSED_DIR`.
+pub const JOURNAL_FILE: &str = ".journal.csv";
+
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// File names to pick up, where `*` matches any characters and `?` a single one.
+    /// Names starting with a dot never match.
+    pub pattern: String,
+    /// How long size and modification time must stay the same before a file runs.
+    pub settle_period: Duration,
+    pub poll_interval: Duration,
+    /// Stops `watch_directory` once nothing was found or settling for this long.
+    /// `None` watches until an error.
+    pub exit_when_idle: Option<Duration>,
+    /// Account snapshot rewritten after every processed file, `accounts.snapshot.csv`
+    /// in the watched directory by default. It is never picked up as input.
+    pub snapshot_path: Option<PathBuf>,
+    /// See `PaymentsEngine::strict_order`.
+    pub strict_order: bool,
+    /// Clock timing the settle period, polls and idleness.
+    pub clock: SharedClock,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            pattern: "*.csv".to_string(),
+            settle_period: Duration::from_secs(2),
+            poll_interval: Duration::from_millis(500),
+            exit_when_idle: None,
+            snapshot_path: None,
+            strict_order: false,
+            clock: SharedClock::default(),
+        }
+    }
+}
+
+/// What happened to a settled file.
+#[derive(Debug)]
+pub enum WatchEvent {
+    Processed {
+        file: String,
+        stats: Box<RunStats>,
+    },
+    /// The file failed and was moved into `failed/` with `reason` next to it.
+    Failed {
+        file: String,
+        reason: String,
+    },
+    /// The journal holds the file with the same contents, so it did not run again.
+    Duplicate {
+        file: String,
+    },
+}
+
+impl Display for WatchEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchEvent::Processed { file, stats } => write!(
+                f,
+                "processed {}: {} rows executed, {} rejected, {} malformed",
+                file, stats.rows_executed, stats.rows_rejected, stats.rows_malformed
+            ),
+            WatchEvent::Failed { file, reason } => write!(f, "failed {}: {}", file, reason),
+            WatchEvent::Duplicate { file } => {
+                write!(f, "skipped {}: already processed", file)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WatchError {
+    Io(io::Error),
+    Journal(csv::Error),
+    /// A journaled file could not be replayed when opening the directory.
+    Replay {
+        file: String,
+        reason: String,
+    },
+}
+
+impl WatchError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WatchError::Io(_) => "io",
+            WatchError::Journal(_) => "journal",
+            WatchError::Replay { .. } => "replay",
+        }
+    }
+}
+
+impl Display for WatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchError::Io(err) => write!(f, "I/O error while watching: {}", err),
+            WatchError::Journal(err) => write!(f, "failed to access the journal: {}", err),
+            WatchError::Replay { file, reason } => {
+                write!(f, "failed to replay processed file {}: {}", file, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WatchError::Io(err) => Some(err),
+            WatchError::Journal(err) => Some(err),
+            WatchError::Replay { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for WatchError {
+    fn from(err: io::Error) -> Self {
+        WatchError::Io(err)
+    }
+}
+
+impl From<csv::Error> for WatchError {
+    fn from(err: csv::Error) -> Self {
+        WatchError::Journal(err)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalRow {
+    file: String,
+    sha256: String,
+}
+
+/// Size and modification time of a file, and since when they are unchanged.
+struct Observation {
+    len: u64,
+    modified: Option<SystemTime>,
+    since: Timestamp,
+}
+
+/// Watcher of one directory, see the module documentation.
+pub struct DirectoryWatcher {
+    dir: PathBuf,
+    config: WatchConfig,
+    snapshot_path: PathBuf,
+    engine: PaymentsEngine,
+    /// Digest of every journaled file by name.
+    journal: HashMap<String, String>,
+    pending: BTreeMap<String, Observation>,
+}
+
+impl DirectoryWatcher {
+    /// Creates `processed/` and `failed/` in `dir` when missing and replays the
+    /// journaled files into `ledger`.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        config: WatchConfig,
+        ledger: Ledger,
+    ) -> Result<Self, WatchError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join(PROCESSED_DIR))?;
+        fs::create_dir_all(dir.join(FAILED_DIR))?;
+        let snapshot_path = config
+            .snapshot_path
+            .clone()
+            .unwrap_or_else(|| dir.join("accounts.snapshot.csv"));
+        let engine = PaymentsEngine::new(ledger).strict_order(config.strict_order);
+        let mut watcher = Self {
+            dir,
+            config,
+            snapshot_path,
+            engine,
+            journal: HashMap::new(),
+            pending: BTreeMap::new(),
+        };
+        watcher.replay_journal()?;
+        Ok(watcher)
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        self.engine.ledger()
+    }
+
+    pub fn into_ledger(self) -> Ledger {
+        self.engine.into_ledger()
+    }
+
+    /// Names of the files found but not settled yet, in the order they will run.
+    pub fn pending_files(&self) -> impl Iterator<Item = &str> {
+        self.pending.keys().map(String::as_str)
+    }
+
+    fn replay_journal(&mut self) -> Result<(), WatchError> {
+        let journal_path = self.dir.join(PROCESSED_DIR).join(JOURNAL_FILE);
+        if !journal_path.try_exists()? {
+            return Ok(());
+        }
+        let mut reader = csv::Reader::from_path(journal_path)?;
+        for row in reader.deserialize::<JournalRow>() {
+            let row = row?;
+            // The watcher may have stopped between journaling the file and moving it.
+            let mut path = self.dir.join(PROCESSED_DIR).join(&row.file);
+            if !path.try_exists()? {
+                path = self.dir.join(&row.file);
+            }
+            let replay_failed = |reason: String| WatchError::Replay {
+                file: row.file.clone(),
+                reason,
+            };
+            let contents = fs::read(&path).map_err(|err| replay_failed(err.to_string()))?;
+            if sha256(&contents) != row.sha256 {
+                return Err(replay_failed(
+                    "contents differ from the journaled digest".to_string(),
+                ));
+            }
+            self.engine
+                .run_two_pass(&path)
+                .map_err(|err| replay_failed(err.to_string()))?;
+            self.journal.insert(row.file, row.sha256);
+        }
+        Ok(())
+    }
+
+    /// Looks at the directory once and runs the files which settled, returning what
+    /// happened to each.
+    pub fn poll(&mut self) -> Result<Vec<WatchEvent>, WatchError> {
+        let now = self.config.clock.now();
+        let found = self.matching_files()?;
+        self.pending.retain(|name, _| found.contains_key(name));
+        for (name, (len, modified)) in found {
+            let observation = self.pending.entry(name).or_insert(Observation {
+                len,
+                modified,
+                since: now,
+            });
+            if (observation.len, observation.modified) != (len, modified) {
+                *observation = Observation {
+                    len,
+                    modified,
+                    since: now,
+                };
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(entry) = self.pending.first_entry() {
+            if now.saturating_duration_since(entry.get().since) < self.config.settle_period {
+                break;
+            }
+            let (name, _) = entry.remove_entry();
+            events.push(self.run_file(name)?);
+        }
+        Ok(events)
+    }
+
+    /// Polls every `poll_interval`, passing each event to `on_event`, until
+    /// `exit_when_idle` passed without files.
+    pub fn run(&mut self, mut on_event: impl FnMut(&WatchEvent)) -> Result<(), WatchError> {
+        let clock = self.config.clock.clone();
+        let mut last_activity = clock.now();
+        loop {
+            let events = self.poll()?;
+            for event in &events {
+                on_event(event);
+            }
+            if !events.is_empty() || !self.pending.is_empty() {
+                last_activity = clock.now();
+            } else if let Some(idle) = self.config.exit_when_idle {
+                if clock.now().saturating_duration_since(last_activity) >= idle {
+                    return Ok(());
+                }
+            }
+            clock.sleep_blocking(self.config.poll_interval);
+        }
+    }
+
+    /// Regular files of the directory matching the pattern, with their size and
+    /// modification time.
+    fn matching_files(&self) -> io::Result<BTreeMap<String, (u64, Option<SystemTime>)>> {
+        let snapshot_name = match self.snapshot_path.parent() {
+            Some(parent) if parent == self.dir => self.snapshot_path.file_name(),
+            _ => None,
+        };
+        let mut found = BTreeMap::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let is_snapshot = snapshot_name
+                .and_then(|snapshot| snapshot.to_str())
+                .is_some_and(|snapshot| name.starts_with(snapshot));
+            if name.starts_with('.') || is_snapshot || !glob_matches(&self.config.pattern, &name) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                found.insert(name, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+        Ok(found)
+    }
+
+    fn run_file(&mut self, name: String) -> Result<WatchEvent, WatchError> {
+        let path = self.dir.join(&name);
+        let digest = sha256(&fs::read(&path)?);
+        match self.journal.get(&name) {
+            Some(journaled) if *journaled == digest => {
+                fs::rename(&path, self.dir.join(PROCESSED_DIR).join(&name))?;
+                return Ok(WatchEvent::Duplicate { file: name });
+            }
+            Some(_) => {
+                let reason = "a file of this name with other contents was processed".to_string();
+                return self.fail(name, reason);
+            }
+            None => {}
+        }
+        let stats = match self.engine.run_two_pass(&path) {
+            Ok(stats) => stats,
+            Err(TwoPassError::Preflight(report)) => {
+                let reason = format!("preflight found {} errors\n{}", report.errors.len(), report);
+                return self.fail(name, reason);
+            }
+            Err(TwoPassError::Io(err)) => return self.fail(name, err.to_string()),
+        };
+        self.write_snapshot()?;
+        self.append_journal(&name, &digest)?;
+        fs::rename(&path, self.dir.join(PROCESSED_DIR).join(&name))?;
+        self.journal.insert(name.clone(), digest);
+        Ok(WatchEvent::Processed {
+            file: name,
+            stats: Box::new(stats),
+        })
+    }
+
+    fn fail(&self, name: String, reason: String) -> Result<WatchEvent, WatchError> {
+        let failed_dir = self.dir.join(FAILED_DIR);
+        fs::write(failed_dir.join(format!("{}.error.txt", name)), &reason)?;
+        fs::rename(self.dir.join(&name), failed_dir.join(&name))?;
+        Ok(WatchEvent::Failed { file: name, reason })
+    }
+
+    fn write_snapshot(&self) -> Result<(), WatchError> {
+        let mut staging = Staging::new();
+        let mut file = staging.create(&self.snapshot_path)?;
+        let accounts = accounts_csv(self.ledger().view()).map_err(io::Error::from)?;
+        file.write_all(accounts.as_bytes())?;
+        drop(file);
+        Ok(staging.commit()?)
+    }
+
+    fn append_journal(&self, name: &str, digest: &str) -> Result<(), WatchError> {
+        let path = self.dir.join(PROCESSED_DIR).join(JOURNAL_FILE);
+        let is_new = !path.try_exists()?;
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(is_new)
+            .from_writer(file);
+        writer.serialize(JournalRow {
+            file: name.to_string(),
+            sha256: digest.to_string(),
+        })?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Watches `dir` as described in the module documentation until
+/// `WatchConfig::exit_when_idle` passed without files, returning the ledger.
+pub fn watch_directory(
+    dir: impl AsRef<Path>,
+    config: WatchConfig,
+    ledger: Ledger,
+) -> Result<Ledger, WatchError> {
+    let mut watcher = DirectoryWatcher::open(dir, config, ledger)?;
+    watcher.run(|_event| {})?;
+    Ok(watcher.into_ledger())
+}
+
+fn sha256(contents: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(contents);
+    hex_digest(hasher)
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any characters and `?` a
+/// single one.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it is matched up to.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::sync::Arc;
+
+    /// Empty directory unique to `name` and the test process.
+    fn watched_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "payments_engine_watch_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(settle_ms: u64) -> WatchConfig {
+        WatchConfig {
+            settle_period: Duration::from_millis(settle_ms),
+            poll_interval: Duration::from_millis(10),
+            exit_when_idle: Some(Duration::from_millis(settle_ms * 2)),
+            ..WatchConfig::default()
+        }
+    }
+
+    fn available(ledger: &Ledger, client_id: u16) -> Decimal {
+        ledger.account(client_id).unwrap().available()
+    }
+
+    fn files(events: &[WatchEvent]) -> Vec<String> {
+        events.iter().map(|event| event.to_string()).collect()
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_matches("*.csv", "hourly_01.csv"));
+        assert!(glob_matches("*.csv", ".csv"));
+        assert!(glob_matches("hourly_??.csv", "hourly_01.csv"));
+        assert!(glob_matches("*_*_*.csv", "a_b_c_d.csv"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("*.csv", "hourly_01.csv.part"));
+        assert!(!glob_matches("hourly_??.csv", "hourly_1.csv"));
+        assert!(!glob_matches("*.csv", "hourly.CSV"));
+    }
+
+    #[test]
+    fn settled_files_run_in_name_order() {
+        let dir = watched_dir("order");
+        // The withdrawal needs the deposit of the file sorting before it.
+        fs::write(
+            dir.join("02.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,8\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("01.csv"),
+            "type,client,tx,amount\ndeposit,1,1,10\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not an input").unwrap();
+        let clock = Arc::new(ManualClock::default());
+        let config = WatchConfig {
+            clock: Arc::clone(&clock).into(),
+            ..config(100)
+        };
+        let mut watcher = DirectoryWatcher::open(&dir, config, Ledger::new()).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        assert_eq!(
+            watcher.pending_files().collect::<Vec<_>>(),
+            ["01.csv", "02.csv"]
+        );
+
+        clock.advance(Duration::from_millis(99));
+        assert!(watcher.poll().unwrap().is_empty());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(
+            files(&watcher.poll().unwrap()),
+            [
+                "processed 01.csv: 1 rows executed, 0 rejected, 0 malformed",
+                "processed 02.csv: 1 rows executed, 0 rejected, 0 malformed",
+            ]
+        );
+        assert_eq!(available(watcher.ledger(), 1), dec!(2));
+        assert!(dir.join(PROCESSED_DIR).join("01.csv").exists());
+        assert!(dir.join("notes.txt").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("accounts.snapshot.csv")).unwrap(),
+            "client,available,held,total,locked\n1,2,0,2,false\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join(PROCESSED_DIR).join(JOURNAL_FILE))
+                .unwrap()
+                .lines()
+                .map(|line| line.split(',').next().unwrap())
+                .collect::<Vec<_>>(),
+            ["file", "01.csv", "02.csv"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn growing_file_runs_once_complete() {
+        let dir = watched_dir("growing");
+        let path = dir.join("01.csv");
+        fs::write(&path, "type,client,tx,amount\n").unwrap();
+        // Complete early, but must wait for the growing file sorting before it.
+        fs::write(
+            dir.join("02.csv"),
+            "type,client,tx,amount\nwithdrawal,1,9,1\n",
+        )
+        .unwrap();
+        let writer = std::thread::spawn(move || {
+            for tx_id in 1..=8 {
+                std::thread::sleep(Duration::from_millis(50));
+                let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+                writeln!(file, "deposit,1,{},1", tx_id).unwrap();
+            }
+        });
+
+        let mut watcher = DirectoryWatcher::open(&dir, config(300), Ledger::new()).unwrap();
+        while !writer.is_finished() {
+            let events = watcher.poll().unwrap();
+            assert!(events.is_empty(), "ran while growing: {:?}", events);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        writer.join().unwrap();
+
+        let mut events = Vec::new();
+        watcher.run(|event| events.push(event.to_string())).unwrap();
+        assert_eq!(
+            events,
+            [
+                "processed 01.csv: 8 rows executed, 0 rejected, 0 malformed",
+                "processed 02.csv: 1 rows executed, 0 rejected, 0 malformed",
+            ]
+        );
+        assert_eq!(available(watcher.ledger(), 1), dec!(7));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_files_leave_the_ledger_alone() {
+        let dir = watched_dir("failed");
+        fs::write(
+            dir.join("01.csv"),
+            "type,client,tx,amount\ndeposit,1,1,10\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("02.csv"),
+            "type,client,tx,amount\ndeposit,1,2,5\ndeposit,1,2,5\n",
+        )
+        .unwrap();
+        let ledger = watch_directory(&dir, config(50), Ledger::new()).unwrap();
+        assert_eq!(available(&ledger, 1), dec!(10));
+        let report = fs::read_to_string(dir.join(FAILED_DIR).join("02.csv.error.txt")).unwrap();
+        assert!(report.starts_with("preflight found 1 errors\nline 3: error: "));
+        assert!(dir.join(FAILED_DIR).join("02.csv").exists());
+        assert!(!dir.join(PROCESSED_DIR).join("02.csv").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restart_replays_the_journal_and_skips_processed_files() {
+        let dir = watched_dir("restart");
+        let first = "type,client,tx,amount\ndeposit,1,1,10\n";
+        fs::write(dir.join("01.csv"), first).unwrap();
+        let ledger = watch_directory(&dir, config(50), Ledger::new()).unwrap();
+        assert_eq!(available(&ledger, 1), dec!(10));
+
+        // Dropped again, as if by a retrying producer, and reusing its name for
+        // other contents.
+        fs::write(dir.join("01.csv"), first).unwrap();
+        fs::write(
+            dir.join("02.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,4\n",
+        )
+        .unwrap();
+        let mut watcher = DirectoryWatcher::open(&dir, config(50), Ledger::new()).unwrap();
+        assert_eq!(available(watcher.ledger(), 1), dec!(10));
+        let mut events = Vec::new();
+        watcher.run(|event| events.push(event.to_string())).unwrap();
+        fs::write(
+            dir.join("01.csv"),
+            "type,client,tx,amount\ndeposit,1,3,99\n",
+        )
+        .unwrap();
+        watcher.run(|event| events.push(event.to_string())).unwrap();
+        assert_eq!(
+            events,
+            [
+                "skipped 01.csv: already processed",
+                "processed 02.csv: 1 rows executed, 0 rejected, 0 malformed",
+                "failed 01.csv: a file of this name with other contents was processed",
+            ]
+        );
+        assert_eq!(available(watcher.ledger(), 1), dec!(6));
+
+        let watcher = DirectoryWatcher::open(&dir, config(50), Ledger::new()).unwrap();
+        assert_eq!(available(watcher.ledger(), 1), dec!(6));
+        assert_eq!(
+            fs::read_to_string(dir.join("accounts.snapshot.csv")).unwrap(),
+            "client,available,held,total,locked\n1,6,0,6,false\n"
+        );
+
+        fs::write(dir.join(PROCESSED_DIR).join("02.csv"), "tampered").unwrap();
+        assert_eq!(
+            DirectoryWatcher::open(&dir, config(50), Ledger::new())
+                .err()
+                .unwrap()
+                .to_string(),
+            "failed to replay processed file 02.csv: contents differ from the journaled digest"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
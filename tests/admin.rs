@@ -0,0 +1,144 @@
+//! Queries the admin listener over HTTP while the socket server executes transactions.
+use payments_engine::accounting::shared::SharedLedger;
+use payments_engine::accounting::transactions::{Deposit, Dispute, Transaction, Withdrawal};
+use payments_engine::accounting::Ledger;
+use payments_engine::admin::spawn_admin;
+use payments_engine::server::{send_transactions, serve_unix_socket_with_stats, ServiceStats};
+use rust_decimal_macros::dec;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_admin_{}_{}.sock",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Connects once the spawned server has bound the socket.
+async fn connect(path: &Path) -> UnixStream {
+    for _ in 0..10_000 {
+        if let Ok(stream) = UnixStream::connect(path).await {
+            return stream;
+        }
+        tokio::task::yield_now().await;
+    }
+    panic!("server did not bind {}", path.display());
+}
+
+/// Sends a `GET` for `path` and returns the status code and body.
+async fn get(addr: SocketAddr, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[tokio::test]
+async fn reports_metrics_accounts_and_disputes() {
+    let path = socket_path("metrics");
+    let ledger = SharedLedger::new(Ledger::new());
+    let stats = Arc::new(ServiceStats::default());
+    let admin = spawn_admin(
+        "127.0.0.1:0".parse().unwrap(),
+        ledger.clone(),
+        stats.clone(),
+    )
+    .await
+    .unwrap();
+    let addr = admin.local_addr();
+    assert_eq!(get(addr, "/healthz").await.0, 503);
+
+    tokio::spawn(serve_unix_socket_with_stats(
+        path.clone(),
+        ledger.clone(),
+        stats.clone(),
+    ));
+    let mut stream = connect(&path).await;
+    assert_eq!(get(addr, "/healthz").await, (200, "ok\n".to_string()));
+
+    let txs = [
+        Transaction::Deposit(Deposit::new(1, 1, dec!(10.0))),
+        Transaction::Deposit(Deposit::new(2, 2, dec!(5.0))),
+        Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(3.0))),
+        Transaction::Withdrawal(Withdrawal::new(2, 4, dec!(8.0))),
+        Transaction::Dispute(Dispute::new(1, 1)),
+    ];
+    send_transactions(&mut stream, &txs).await.unwrap();
+
+    let (status, metrics) = get(addr, "/metrics").await;
+    assert_eq!(status, 200);
+    for line in [
+        "# TYPE payments_engine_transactions_applied_total counter",
+        "payments_engine_transactions_applied_total{kind=\"deposit\"} 2",
+        "payments_engine_transactions_applied_total{kind=\"withdrawal\"} 1",
+        "payments_engine_transactions_applied_total{kind=\"dispute\"} 1",
+        "payments_engine_transactions_applied_total{kind=\"chargeback\"} 0",
+        "payments_engine_transactions_rejected_total{kind=\"withdrawal\"} 1",
+        "payments_engine_transactions_rejected_total{kind=\"deposit\"} 0",
+        "payments_engine_accounts 2",
+        "payments_engine_accounts_locked 0",
+        "payments_engine_queue_depth 0",
+    ] {
+        assert!(
+            metrics.lines().any(|metric| metric == line),
+            "missing {:?} in\n{}",
+            line,
+            metrics
+        );
+    }
+
+    let (status, account) = get(addr, "/account/1").await;
+    assert_eq!(status, 200);
+    let account: serde_json::Value = serde_json::from_str(&account).unwrap();
+    assert_eq!(account["client"], 1);
+    assert_eq!(account["available"], "-3.0");
+    assert_eq!(account["held"], "10.0");
+    assert_eq!(account["total"], "7.0");
+    assert_eq!(account["locked"], false);
+
+    assert_eq!(get(addr, "/account/3").await.0, 404);
+
+    let (status, disputes) = get(addr, "/disputes").await;
+    assert_eq!(status, 200);
+    let disputes: serde_json::Value = serde_json::from_str(&disputes).unwrap();
+    assert_eq!(
+        disputes,
+        serde_json::json!([{"client": 1, "tx": 1, "amount": "10.0", "reason": null}])
+    );
+    admin.abort();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn invalid_client_ids_are_bad_requests() {
+    let ledger = SharedLedger::new(Ledger::new());
+    let admin = spawn_admin(
+        "127.0.0.1:0".parse().unwrap(),
+        ledger,
+        Arc::new(ServiceStats::default()),
+    )
+    .await
+    .unwrap();
+    let addr = admin.local_addr();
+    for path in ["/account/abc", "/account/-1", "/account/70000", "/account/"] {
+        assert_eq!(get(addr, path).await.0, 400, "{}", path);
+    }
+    assert_eq!(get(addr, "/nowhere").await.0, 404);
+    // The listener survives the bad requests.
+    assert_eq!(get(addr, "/metrics").await.0, 200);
+    admin.abort();
+}
@@ -0,0 +1,121 @@
+//! `--admin-ops` applies a file of operator interventions before the input is
+//! ingested or after it was processed, stopping at the first failing op or going on.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Runs the binary with the mixed ops file, where every op appears and removing
+/// client 3 and unlocking client 9 fail, returning the exit code, stderr and stdout.
+fn run(phase: &str, on_error: &str) -> (i32, String, String) {
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--admin-ops")
+        .arg(fixture("admin_ops.csv"))
+        .args(["--admin-ops-phase", phase, "--admin-ops-on-error", on_error])
+        .args(["--output-clients", "1..=9"])
+        .arg(fixture("admin_ops_input.csv"))
+        .output()
+        .unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn post_phase_stops_at_the_first_failure() {
+    let (code, stderr, stdout) = run("post", "stop");
+    assert_eq!(code, 1);
+    assert_eq!(
+        stderr,
+        "Admin op \"remove_account\" on line 4 failed: deposit 3 of the account is still held\n\
+         Applied 2 of 6 admin ops\n"
+    );
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn post_phase_continues_past_failures() {
+    let (code, stderr, stdout) = run("post", "continue");
+    assert_eq!(code, 0);
+    assert_eq!(
+        stderr,
+        "Admin op \"remove_account\" on line 4 failed: deposit 3 of the account is still held\n\
+         Admin op \"unlock\" on line 6 failed: client_account_not_found\n\
+         Applied 4 of 6 admin ops\n"
+    );
+    // Client 1 was closed and client 2 merged into 4 after its withdrawal failed
+    assert_eq!(
+        stdout,
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,15,0,15,true\n\
+         3,0,30,30,false\n\
+         4,20,0,20,false\n"
+    );
+}
+
+#[test]
+fn pre_phase_stops_at_the_first_failure() {
+    let (code, stderr, stdout) = run("pre", "stop");
+    assert_eq!(code, 1);
+    assert_eq!(
+        stderr,
+        "Admin op \"close\" on line 3 failed: client_account_not_found\n\
+         Applied 1 of 6 admin ops\n"
+    );
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn pre_phase_continues_past_failures() {
+    let (code, stderr, stdout) = run("pre", "continue");
+    assert_eq!(code, 0);
+    // Only the limits apply before the accounts exist
+    assert_eq!(
+        stderr,
+        "Admin op \"close\" on line 3 failed: client_account_not_found\n\
+         Admin op \"remove_account\" on line 4 failed: client 3 has no account to remove\n\
+         Admin op \"unlock\" on line 6 failed: client_account_not_found\n\
+         Admin op \"merge_accounts\" on line 7 failed: client 2 has no account to merge\n\
+         Applied 2 of 6 admin ops\n"
+    );
+    // The overdraft limit let client 2 withdraw more than its deposit
+    assert_eq!(
+        stdout,
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,15,0,15,false\n\
+         2,-2,0,-2,false\n\
+         3,0,30,30,false\n"
+    );
+}
+
+#[test]
+fn invalid_files_are_rejected() {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--admin-ops-phase", "pre"])
+        .arg(fixture("admin_ops_input.csv"))
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "--admin-ops-phase and --admin-ops-on-error require --admin-ops\n"
+    );
+
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--admin-ops")
+        .arg(fixture("admin_ops_input.csv"))
+        .arg(fixture("admin_ops_input.csv"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Failed to read admin ops file: unexpected admin ops header"));
+}
@@ -0,0 +1,106 @@
+//! `--amount-scale` rejects amounts finer than the scale, or rounds them with
+//! `--round-excess-precision`, and writes the report with exactly that many decimals.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::Path;
+
+fn fixture() -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/amount_scale.csv")
+}
+
+fn run(args: &[&str]) -> String {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(args)
+        .args(["--output-clients", "1..=2"])
+        .arg(fixture())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn the_same_input_at_scales_4_2_and_0() {
+    assert_eq!(
+        run(&["--amount-scale", "4"]),
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,11.2500,0.0000,11.2500,false\n\
+         2,3.0001,0.0000,3.0001,false\n"
+    );
+    // The deposit of 0.0001 is rejected
+    assert_eq!(
+        run(&["--amount-scale", "2"]),
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,11.25,0.00,11.25,false\n\
+         2,3.00,0.00,3.00,false\n"
+    );
+    // So are the deposit of 2.5 and the withdrawal of 1.25
+    assert_eq!(
+        run(&["--amount-scale", "0"]),
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,10,0,10,false\n\
+         2,3,0,3,false\n"
+    );
+}
+
+#[test]
+fn finer_amounts_are_rounded_on_request() {
+    // 2.5 is rounded to 3 and 1.25 to 1
+    assert_eq!(
+        run(&["--amount-scale", "0", "--round-excess-precision"]),
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,12,0,12,false\n\
+         2,3,0,3,false\n"
+    );
+}
+
+#[test]
+fn expected_balances_are_checked_against_the_scale() {
+    let dir = std::env::temp_dir().join(format!("amount_scale_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let expected = dir.join("expected.csv");
+    std::fs::write(
+        &expected,
+        "client,available,held,total,locked\n1,11.25,0,11.25,false\n2,3.001,0,3.001,false\n",
+    )
+    .unwrap();
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--amount-scale", "2", "--reconcile"])
+        .arg(&expected)
+        .arg(fixture())
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("expected balance 3.001 of client 2 has more than 2 decimals"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn scales_are_bounded_and_rounding_needs_one() {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--amount-scale", "29"])
+        .arg(fixture())
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Usage:"));
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--round-excess-precision")
+        .arg(fixture())
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "--round-excess-precision requires --amount-scale\n"
+    );
+    assert!(output.stdout.is_empty());
+}
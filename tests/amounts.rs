@@ -0,0 +1,119 @@
+//! Every malformed amount shape of `tests/fixtures/` is reported with its own error
+//! code, whichever way the input is read.
+use assert_cmd::cargo::cargo_bin_cmd;
+use payments_engine::ingest::line_protocol::parse_line;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Error code expected for each data row of both fixtures, `None` for valid rows.
+const EXPECTED_CODES: [Option<&str>; 9] = [
+    None,
+    Some("trailing_characters_in_amount"),
+    Some("negative_amount_not_allowed"),
+    Some("negative_amount_not_allowed"),
+    Some("trailing_characters_in_amount"),
+    Some("negative_amount_not_allowed"),
+    Some("trailing_characters_in_amount"),
+    Some("trailing_characters_in_amount"),
+    None,
+];
+
+const EXPECTED_OUTPUT: &str = "client,available,held,total,locked\n1,39.5,0,39.5,false\n";
+
+#[test]
+fn check_report_names_each_malformed_amount() {
+    let report = std::env::temp_dir().join(format!(
+        "payments_engine_amounts_{}.jsonl",
+        std::process::id()
+    ));
+    cargo_bin_cmd!("payments_engine")
+        .arg("check")
+        .arg("--report")
+        .arg(&report)
+        .arg(fixture("malformed_amounts.csv"))
+        .assert()
+        .code(2)
+        .stdout("checked=9 ok=2 rejected=0 structural=7\n");
+    let findings = std::fs::read_to_string(&report).unwrap();
+    std::fs::remove_file(report).unwrap();
+    let findings: Vec<serde_json::Value> = findings
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let expected: Vec<_> = EXPECTED_CODES
+        .iter()
+        .enumerate()
+        .filter_map(|(index, code)| code.map(|code| (index as u64 + 2, code)))
+        .collect();
+    let reported: Vec<_> = findings
+        .iter()
+        .map(|finding| {
+            (
+                finding["line"].as_u64().unwrap(),
+                finding["code"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(reported, expected);
+    assert_eq!(
+        findings[0]["message"],
+        "malformed row: trailing_characters_in_amount at offset 5 of \"50.00abc\""
+    );
+}
+
+#[test]
+fn quarantine_pinpoints_trailing_characters() {
+    let quarantine = std::env::temp_dir().join(format!(
+        "payments_engine_amounts_quarantine_{}.csv",
+        std::process::id()
+    ));
+    cargo_bin_cmd!("payments_engine")
+        .arg("--quarantine")
+        .arg(&quarantine)
+        .arg(fixture("malformed_amounts.csv"))
+        .assert()
+        .success()
+        .stdout(EXPECTED_OUTPUT);
+    let quarantined = std::fs::read_to_string(&quarantine).unwrap();
+    std::fs::remove_file(quarantine).unwrap();
+    assert_eq!(
+        quarantined,
+        "type,client,tx,amount,error_code,line_number,error_column,error_value,error_reason\n\
+         deposit,1,2,50.00abc,trailing_characters_in_amount,3,amount,50.00abc,\
+         trailing_characters at offset 5\n\
+         deposit,1,3,-5.00,negative_amount_not_allowed,4,amount,-5.00,negative_amount\n\
+         withdrawal,1,4,-1,negative_amount_not_allowed,5,amount,-1,negative_amount\n\
+         withdrawal,1,5,1.2.3,trailing_characters_in_amount,6,amount,1.2.3,\
+         trailing_characters at offset 3\n\
+         withdraw_auth,1,6,-2,negative_amount_not_allowed,7,amount,-2,negative_amount\n\
+         deposit,1,7,7 5,trailing_characters_in_amount,8,amount,7 5,\
+         trailing_characters at offset 1\n\
+         dispute,1,1,10x,trailing_characters_in_amount,9,amount,10x,\
+         trailing_characters at offset 2\n"
+    );
+}
+
+#[test]
+fn fast_parser_reads_amounts_like_the_csv_parser() {
+    cargo_bin_cmd!("payments_engine")
+        .args(["--parser", "fast"])
+        .arg(fixture("malformed_amounts.csv"))
+        .assert()
+        .success()
+        .stdout(EXPECTED_OUTPUT);
+}
+
+#[test]
+fn json_lines_fail_with_the_same_codes() {
+    let lines = std::fs::read_to_string(fixture("malformed_amounts.jsonl")).unwrap();
+    let codes: Vec<_> = lines
+        .lines()
+        .map(|line| parse_line(line).err().map(|err| err.code()))
+        .collect();
+    assert_eq!(codes, EXPECTED_CODES);
+}
@@ -0,0 +1,53 @@
+//! `--chargeback-fee` charges a fee for every chargeback as a derived posting,
+//! which may take the balance of the locked account below zero.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::Path;
+
+fn run(args: &[&str]) -> String {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(args)
+        .args(["--output-clients", "1..=2"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/chargeback_fee.csv"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn chargebacks_are_charged_the_fee() {
+    // The withdrawal of the locked client 1 is rejected either way
+    assert_eq!(
+        run(&["--chargeback-fee", "15.00"]),
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,85.00,0,85.00,true\n\
+         2,-15.00,0,-15.00,true\n"
+    );
+}
+
+#[test]
+fn without_the_fee_nothing_is_derived() {
+    assert_eq!(
+        run(&[]),
+        "# selection_applied=true\n\
+         client,available,held,total,locked\n\
+         1,100,0,100,true\n\
+         2,0,0,0,true\n"
+    );
+}
+
+#[test]
+fn fees_must_be_positive() {
+    for fee in ["0", "-1", "abc"] {
+        let output = cargo_bin_cmd!("payments_engine")
+            .args(["--chargeback-fee", fee])
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/chargeback_fee.csv"))
+            .output()
+            .unwrap();
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("Usage:"));
+        assert!(output.stdout.is_empty());
+    }
+}
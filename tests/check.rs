@@ -0,0 +1,105 @@
+//! Exit codes and stdout of `payments_engine check`, which pipelines gate on.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::PathBuf;
+
+/// Writes `contents` into a temporary file unique to `name` and the test process.
+fn write_input(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_check_{}_{}.csv",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn clean_file_exits_0() {
+    let path = write_input(
+        "clean",
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 10.0\n\
+         withdrawal, 1, 2, 4.0\n\
+         dispute, 1, 1,\n",
+    );
+    cargo_bin_cmd!("payments_engine")
+        .arg("check")
+        .arg(&path)
+        .assert()
+        .code(0)
+        .stdout("checked=3 ok=3 rejected=0 structural=0\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rejected_rows_exit_1_with_report() {
+    let path = write_input(
+        "rejected",
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 10.0\n\
+         withdrawal, 1, 2, 40.0\n\
+         resolve, 1, 1,\n",
+    );
+    let report = path.with_extension("jsonl");
+    cargo_bin_cmd!("payments_engine")
+        .arg("check")
+        .arg("--report")
+        .arg(&report)
+        .arg(&path)
+        .assert()
+        .code(1)
+        .stdout("checked=3 ok=1 rejected=2 structural=0\n");
+    assert_eq!(
+        std::fs::read_to_string(&report).unwrap(),
+        "{\"line\":3,\"class\":\"rejected\",\"code\":\"insufficient_funds\",\
+         \"message\":\"tx 2 rejected: insufficient_funds\"}\n\
+         {\"line\":4,\"class\":\"rejected\",\"code\":\"tx_not_disputed\",\
+         \"message\":\"tx 1 rejected: tx_not_disputed\"}\n"
+    );
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(report).unwrap();
+}
+
+#[test]
+fn structural_problems_exit_2() {
+    let path = write_input(
+        "structural",
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 10.0\n\
+         deposit, 1, 1, 5.0\n\
+         withdrawal, 1, 2, 40.0\n\
+         deposit, 2, 3, 1.00001\n",
+    );
+    cargo_bin_cmd!("payments_engine")
+        .arg("check")
+        .arg(&path)
+        .assert()
+        .code(2)
+        .stdout("checked=4 ok=1 rejected=1 structural=2\n");
+    // Only the first problem is looked at
+    cargo_bin_cmd!("payments_engine")
+        .args(["check", "--fail-fast"])
+        .arg(&path)
+        .assert()
+        .code(2)
+        .stdout("checked=2 ok=1 rejected=0 structural=1\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unreadable_or_unexpected_file_exits_2() {
+    let path = write_input("header", "kind, client, tx, amount\ndeposit, 1, 1, 1.0\n");
+    cargo_bin_cmd!("payments_engine")
+        .arg("check")
+        .arg(&path)
+        .assert()
+        .code(2)
+        .stdout("checked=0 ok=0 rejected=0 structural=1\n");
+    std::fs::remove_file(&path).unwrap();
+    cargo_bin_cmd!("payments_engine")
+        .arg("check")
+        .arg(&path)
+        .assert()
+        .code(2)
+        .stdout("checked=0 ok=0 rejected=0 structural=1\n");
+}
@@ -0,0 +1,162 @@
+//! A feed consumer killed at a random point and restarted from its latest checkpoint
+//! ends in the same state as one which was never interrupted.
+use payments_engine::accounting::Ledger;
+use payments_engine::checkpoint::{Checkpointer, Cursor};
+use payments_engine::ingest::line_protocol::{
+    run_line_protocol_with_checkpoints, Acker, CheckpointSchedule,
+};
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+
+/// Same generator as the seeded workloads of the crate.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as u32
+    }
+}
+
+/// Lines of deposits, withdrawals, disputes, resolves and chargebacks of 5 clients,
+/// referring to random earlier transactions.
+fn feed(seed: u64, rows: u32) -> String {
+    let mut rng = Lcg(seed);
+    let mut feed = String::new();
+    for tx_id in 1..=rows {
+        let client_id = rng.next() % 5 + 1;
+        let origin_tx = rng.next() % tx_id + 1;
+        let line = match rng.next() % 10 {
+            0..=4 => format!(
+                "deposit,{},{},{}.{:02}",
+                client_id,
+                tx_id,
+                rng.next() % 100,
+                rng.next() % 100
+            ),
+            5..=6 => format!("withdrawal,{},{},{}", client_id, tx_id, rng.next() % 40),
+            7 => format!("dispute,{},{},", client_id, origin_tx),
+            8 => format!("resolve,{},{},", client_id, origin_tx),
+            _ => format!("chargeback,{},{},", client_id, origin_tx),
+        };
+        feed.push_str(&line);
+        feed.push('\n');
+    }
+    feed
+}
+
+struct NoAck;
+
+impl Acker for NoAck {
+    fn ack(&mut self, _offset: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader failing on every read, standing in for the process being killed.
+struct Killed;
+
+impl AsyncRead for Killed {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::Error::other("killed")))
+    }
+}
+
+fn checkpoint_dir(seed: u64) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "payments_engine_checkpoint_recovery_{}_{}",
+        seed,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn schedule(checkpointer: &Checkpointer) -> CheckpointSchedule<'_> {
+    CheckpointSchedule {
+        checkpointer,
+        checkpoint_every_n_tx: 16,
+    }
+}
+
+#[tokio::test]
+async fn recovery_from_the_latest_checkpoint_matches_an_uninterrupted_run() {
+    for seed in [1, 7, 415, 2024, 99_991] {
+        let feed = feed(seed, 300);
+        let mut expected = Ledger::new();
+        let uninterrupted = Checkpointer::new(checkpoint_dir(seed));
+        run_line_protocol_with_checkpoints(
+            feed.as_bytes(),
+            0,
+            &mut expected,
+            NoAck,
+            schedule(&uninterrupted),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_dir_all(uninterrupted.dir()).unwrap();
+
+        // Killed at a random byte, possibly within a line
+        let killed_at = Lcg(seed).next() as usize % feed.len();
+        let checkpointer = Checkpointer::new(checkpoint_dir(seed)).retain(2);
+        let reader = BufReader::new((&feed.as_bytes()[..killed_at]).chain(Killed));
+        let mut crashed = Ledger::new();
+        run_line_protocol_with_checkpoints(reader, 0, &mut crashed, NoAck, schedule(&checkpointer))
+            .await
+            .unwrap_err();
+        drop(crashed);
+        assert!(checkpointer.list().unwrap().len() <= 2);
+
+        let (mut ledger, cursor) = checkpointer
+            .latest()
+            .unwrap_or_else(|| (Ledger::new(), Cursor::default()));
+        assert!(cursor.offset <= killed_at as u64, "seed {}", seed);
+        let offset = cursor.offset as usize;
+        let report = run_line_protocol_with_checkpoints(
+            &feed.as_bytes()[offset..],
+            cursor.offset,
+            &mut ledger,
+            NoAck,
+            schedule(&checkpointer),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.redelivered, 0, "seed {}", seed);
+        assert_eq!(
+            ledger.state_digest(),
+            expected.state_digest(),
+            "seed {}",
+            seed
+        );
+
+        // Replaying the whole feed on the recovered ledger changes nothing
+        let (mut replayed, _cursor) = checkpointer.latest().unwrap();
+        run_line_protocol_with_checkpoints(
+            feed.as_bytes(),
+            0,
+            &mut replayed,
+            NoAck,
+            schedule(&checkpointer),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            replayed.state_digest(),
+            expected.state_digest(),
+            "seed {}",
+            seed
+        );
+        std::fs::remove_dir_all(checkpointer.dir()).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+//! `compare` diffs two account reports semantically, exiting with 0 when they hold
+//! the same accounts, 1 when they differ and 2 when one cannot be read.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Exit code, stdout and stderr of comparing `compare_a.csv` with `other`.
+fn compare(args: &[&str], other: &str) -> (i32, String, String) {
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("compare")
+        .args(args)
+        .arg(fixture("compare_a.csv"))
+        .arg(fixture(other))
+        .output()
+        .unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn reordered_rows_and_columns_are_identical() {
+    let (code, stdout, stderr) = compare(&[], "compare_reordered.csv");
+    assert_eq!(code, 0, "{}", stderr);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "Comparison: 3 clients compared, 0 added, 0 removed, 0 field differences, \
+         0 columns added, 0 columns removed\n"
+    );
+}
+
+#[test]
+fn one_cent_difference_is_reported_with_its_delta() {
+    let (code, stdout, _stderr) = compare(&[], "compare_one_cent.csv");
+    assert_eq!(code, 1);
+    assert_eq!(
+        stdout,
+        "client 1 available: 10.50 -> 10.51 (delta 0.01)\n\
+         client 1 total: 10.50 -> 10.51 (delta 0.01)\n"
+    );
+}
+
+#[test]
+fn extra_column_differs_unless_ignored() {
+    let (code, stdout, _stderr) = compare(&[], "compare_flags.csv");
+    assert_eq!(code, 1);
+    assert_eq!(stdout, "added column flags\n");
+
+    let (code, stdout, _stderr) = compare(&["--ignore-column", "flags"], "compare_flags.csv");
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn malformed_report_exits_with_2() {
+    let (code, stdout, stderr) = compare(&[], "compare_malformed.csv");
+    assert_eq!(code, 2);
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.ends_with("second report has invalid available \"three\" on line 3\n"),
+        "{}",
+        stderr
+    );
+
+    let (code, _stdout, stderr) = compare(&[], "missing.csv");
+    assert_eq!(code, 2);
+    assert!(stderr.starts_with("Failed to open"), "{}", stderr);
+}
@@ -0,0 +1,419 @@
+//! Cross-checks of the state ledgers derive for each account, run after every step
+//! of scenario scripts under several configurations.
+//!
+//! Invariants are closures registered on a `Harness`, so a feature maintaining more
+//! derived state adds its own with `Harness::register` without touching the
+//! scenarios.
+use payments_engine::accounting::config::{
+    ChargebackPolicy, DuplicateDisputePolicy, LedgerBuilder, LockPolicy, VelocityAction,
+    VelocityLimit, WindowSpec,
+};
+use payments_engine::accounting::journal::{read_journal, write_journal};
+use payments_engine::accounting::transactions::{
+    Adjustment, Chargeback, Deposit, Dispute, Resolve, Transaction, TxTypeTag, WithdrawAuth,
+    WithdrawCapture, WithdrawRelease, Withdrawal,
+};
+use payments_engine::accounting::{AccountView, Ledger, TxState};
+use payments_engine::core_types::ClientId;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, HashSet};
+
+const VELOCITY_WINDOW: usize = 3;
+
+enum Step {
+    Execute(Transaction),
+    Unlock(ClientId),
+}
+
+fn deposit(client_id: ClientId, tx_id: u32, amount: Decimal) -> Step {
+    Step::Execute(Transaction::Deposit(Deposit::new(client_id, tx_id, amount)))
+}
+
+fn withdrawal(client_id: ClientId, tx_id: u32, amount: Decimal) -> Step {
+    Step::Execute(Transaction::Withdrawal(Withdrawal::new(
+        client_id, tx_id, amount,
+    )))
+}
+
+fn dispute(client_id: ClientId, tx_id: u32) -> Step {
+    Step::Execute(Transaction::Dispute(Dispute::new(client_id, tx_id)))
+}
+
+fn resolve(client_id: ClientId, tx_id: u32) -> Step {
+    Step::Execute(Transaction::Resolve(Resolve::new(client_id, tx_id)))
+}
+
+fn chargeback(client_id: ClientId, tx_id: u32) -> Step {
+    Step::Execute(Transaction::Chargeback(Chargeback::new(client_id, tx_id)))
+}
+
+fn adjustment(client_id: ClientId, tx_id: u32) -> Step {
+    Step::Execute(Transaction::Adjustment(Adjustment::new(client_id, tx_id)))
+}
+
+fn withdraw_auth(client_id: ClientId, tx_id: u32, amount: Decimal) -> Step {
+    Step::Execute(Transaction::WithdrawAuth(WithdrawAuth::new(
+        client_id, tx_id, amount,
+    )))
+}
+
+fn withdraw_capture(client_id: ClientId, tx_id: u32) -> Step {
+    Step::Execute(Transaction::WithdrawCapture(WithdrawCapture::new(
+        client_id, tx_id,
+    )))
+}
+
+fn withdraw_release(client_id: ClientId, tx_id: u32) -> Step {
+    Step::Execute(Transaction::WithdrawRelease(WithdrawRelease::new(
+        client_id, tx_id,
+    )))
+}
+
+/// Scenario scripts. Steps may be rejected under some configurations, which the
+/// invariants must survive as well.
+fn scenarios() -> Vec<(&'static str, Vec<Step>)> {
+    vec![
+        (
+            "chargeback_then_unlock",
+            vec![
+                deposit(1, 1, dec!(50)),
+                dispute(1, 1),
+                deposit(1, 2, dec!(20)),
+                chargeback(1, 1),
+                deposit(1, 3, dec!(5)),
+                Step::Unlock(1),
+                resolve(1, 3),
+                withdrawal(1, 4, dec!(10)),
+            ],
+        ),
+        (
+            "dispute_and_chargeback_interleaved_with_deposits",
+            vec![
+                deposit(1, 1, dec!(10)),
+                deposit(2, 2, dec!(3)),
+                dispute(1, 1),
+                deposit(1, 3, dec!(7.5)),
+                dispute(1, 1),
+                dispute(1, 3),
+                chargeback(1, 1),
+                chargeback(1, 1),
+                deposit(1, 4, dec!(2)),
+                chargeback(1, 3),
+                resolve(1, 4),
+                dispute(1, 4),
+                chargeback(1, 4),
+            ],
+        ),
+        (
+            "withdrawal_holds_mixed_with_disputes",
+            vec![
+                deposit(1, 1, dec!(100)),
+                withdraw_auth(1, 2, dec!(30)),
+                dispute(1, 1),
+                withdraw_auth(1, 3, dec!(80)),
+                resolve(1, 1),
+                withdraw_auth(1, 4, dec!(60)),
+                withdraw_capture(1, 2),
+                dispute(1, 1),
+                withdraw_release(1, 4),
+                chargeback(1, 1),
+                withdraw_capture(1, 4),
+                deposit(2, 5, dec!(12)),
+                withdraw_auth(2, 6, dec!(12)),
+                withdraw_release(2, 6),
+            ],
+        ),
+        (
+            "deposits_held_on_arrival",
+            vec![
+                deposit(1, 1, dec!(10)),
+                dispute(1, 1),
+                chargeback(1, 1),
+                deposit(1, 2, dec!(6)),
+                adjustment(1, 2),
+                resolve(1, 2),
+                dispute(1, 2),
+                deposit(1, 3, dec!(4)),
+                chargeback(1, 3),
+                Step::Unlock(1),
+                resolve(1, 2),
+                adjustment(1, 2),
+                withdrawal(1, 4, dec!(1)),
+            ],
+        ),
+        (
+            "adjustments_after_partial_disputes",
+            vec![
+                deposit(1, 1, dec!(40)),
+                deposit(1, 2, dec!(15)),
+                deposit(1, 3, dec!(8)),
+                dispute(1, 1),
+                adjustment(1, 2),
+                adjustment(1, 1),
+                resolve(1, 1),
+                adjustment(1, 1),
+                dispute(1, 2),
+                dispute(1, 3),
+                chargeback(1, 3),
+                withdrawal(1, 4, dec!(1)),
+            ],
+        ),
+    ]
+}
+
+type Configure = fn() -> LedgerBuilder;
+
+/// Configurations every scenario runs under, all with the journal on.
+fn configurations() -> Vec<(&'static str, Configure)> {
+    vec![
+        ("default", || Ledger::builder().journal()),
+        ("all_features", || {
+            Ledger::builder()
+                .journal()
+                .lock_policy(LockPolicy::HoldIncoming)
+                .duplicate_dispute_policy(DuplicateDisputePolicy::Idempotent)
+                .velocity_limit(VelocityLimit {
+                    max_total: dec!(60),
+                    window: WindowSpec::Transactions(VELOCITY_WINDOW),
+                    action: VelocityAction::Flag,
+                })
+                .overdraft_limit(dec!(-5))
+        }),
+        ("rejecting_velocity", || {
+            Ledger::builder()
+                .journal()
+                .velocity_limit(VelocityLimit {
+                    max_total: dec!(60),
+                    window: WindowSpec::Transactions(VELOCITY_WINDOW),
+                    action: VelocityAction::Reject,
+                })
+                .chargeback_policy(ChargebackPolicy::NoLock)
+        }),
+        ("lock_after_second_chargeback", || {
+            Ledger::builder()
+                .journal()
+                .lock_policy(LockPolicy::HoldIncoming)
+                .velocity_limit(VelocityLimit {
+                    max_total: Decimal::MAX,
+                    window: WindowSpec::Transactions(VELOCITY_WINDOW),
+                    action: VelocityAction::Reject,
+                })
+                .chargeback_policy(ChargebackPolicy::LockAfterN(2))
+        }),
+    ]
+}
+
+struct Check<'a> {
+    ledger: &'a Ledger,
+    build: Configure,
+    /// Clients unlocked with `Ledger::unlock`, which is not journaled.
+    unlocked: &'a HashSet<ClientId>,
+}
+
+type Invariant = Box<dyn Fn(&Check) -> Result<(), String>>;
+
+struct Harness {
+    invariants: Vec<(&'static str, Invariant)>,
+}
+
+impl Harness {
+    fn new() -> Self {
+        Self {
+            invariants: Vec::new(),
+        }
+    }
+
+    fn register(
+        &mut self,
+        name: &'static str,
+        invariant: impl Fn(&Check) -> Result<(), String> + 'static,
+    ) -> &mut Self {
+        self.invariants.push((name, Box::new(invariant)));
+        self
+    }
+
+    /// Runs every scenario under every configuration, checking all invariants after
+    /// each step, and fails listing every broken one.
+    fn run(&self) {
+        let mut failures = Vec::new();
+        for (config_name, build) in configurations() {
+            for (scenario_name, steps) in scenarios() {
+                let mut ledger = build().build();
+                let mut unlocked = HashSet::new();
+                for (index, step) in steps.iter().enumerate() {
+                    match step {
+                        Step::Execute(tx) => {
+                            ledger.execute(tx).ok();
+                        }
+                        Step::Unlock(client_id) => {
+                            ledger.unlock(*client_id).ok();
+                            unlocked.insert(*client_id);
+                        }
+                    }
+                    let check = Check {
+                        ledger: &ledger,
+                        build,
+                        unlocked: &unlocked,
+                    };
+                    for (name, invariant) in &self.invariants {
+                        if let Err(err) = invariant(&check) {
+                            failures.push(format!(
+                                "{}/{} step {}: {}: {}",
+                                config_name, scenario_name, index, name, err
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
+
+fn core_invariants() -> Harness {
+    let mut harness = Harness::new();
+    harness
+        .register("accounting_identity", |check| {
+            check
+                .ledger
+                .verify_invariants()
+                .map_err(|violation| violation.to_string())
+        })
+        .register("activity_totals", |check| {
+            for (client_id, account) in check.ledger.accounts_iter() {
+                let flows = account.total_deposited()
+                    - account.total_withdrawn()
+                    - account.total_charged_back();
+                if flows != account.total() {
+                    return Err(format!(
+                        "client {} total {} differs from deposited - withdrawn - charged back {}",
+                        client_id,
+                        account.total(),
+                        flows
+                    ));
+                }
+            }
+            Ok(())
+        })
+        .register("held_matches_open_holds", |check| {
+            let mut held = BTreeMap::<ClientId, Decimal>::new();
+            for dispute in check.ledger.open_disputes() {
+                *held.entry(dispute.client_id).or_default() += dispute.amount;
+            }
+            for hold in check.ledger.open_withdrawal_holds() {
+                *held.entry(hold.client_id).or_default() += hold.amount;
+            }
+            for (client_id, account) in check.ledger.accounts_iter() {
+                let expected = held.get(client_id).copied().unwrap_or_default();
+                if account.held() != expected {
+                    return Err(format!(
+                        "client {} held {} but open disputes and holds sum to {}",
+                        client_id,
+                        account.held(),
+                        expected
+                    ));
+                }
+            }
+            Ok(())
+        })
+        .register("charged_back_matches_records", |check| {
+            let mut charged_back = BTreeMap::<ClientId, (Decimal, u32)>::new();
+            for record in check
+                .ledger
+                .tx_records_iter()
+                .filter(|record| record.state() == TxState::ChargedBack)
+            {
+                let (amount, count) = charged_back.entry(record.client_id()).or_default();
+                *amount += record.amount();
+                *count += 1;
+            }
+            for (client_id, account) in check.ledger.accounts_iter() {
+                let expected = charged_back.get(client_id).copied().unwrap_or_default();
+                let actual = (account.total_charged_back(), account.chargebacks());
+                if actual != expected {
+                    return Err(format!(
+                        "client {} charged back {:?} but its records sum to {:?}",
+                        client_id, actual, expected
+                    ));
+                }
+            }
+            Ok(())
+        })
+        .register("journal_replay", |check| {
+            let journal = check.ledger.journal();
+            let replayed = Ledger::replay_until(journal, journal.len() as u64);
+            let comparable = |view: AccountView| {
+                let locked = (!check.unlocked.contains(&view.client_id)).then_some(view.locked);
+                (view.available, view.held, view.chargebacks, locked)
+            };
+            for (client_id, account) in check.ledger.accounts_iter() {
+                let live = comparable(AccountView::from(account));
+                let replayed = replayed
+                    .account(*client_id)
+                    .map(|account| comparable(AccountView::from(account)));
+                if replayed != Some(live) {
+                    return Err(format!(
+                        "client {} is {:?} live but {:?} replayed",
+                        client_id, live, replayed
+                    ));
+                }
+            }
+            Ok(())
+        })
+        .register("journal_round_trip", |check| {
+            let mut written = Vec::new();
+            write_journal(check.ledger.journal(), &mut written).map_err(|err| err.to_string())?;
+            let read = read_journal(written.as_slice()).map_err(|err| err.to_string())?;
+            if read != check.ledger.journal() {
+                return Err("journal read back differs".to_string());
+            }
+            Ok(())
+        })
+        .register("velocity_windows_round_trip", |check| {
+            let saved = check.ledger.velocity_windows();
+            let mut restored = (check.build)().build();
+            restored.restore_velocity_windows(saved.clone());
+            if restored.velocity_windows() != saved {
+                return Err("restored velocity windows differ".to_string());
+            }
+            Ok(())
+        })
+        .register("velocity_windows_match_journal", |check| {
+            if check.ledger.config().velocity_limit.is_none() {
+                return Ok(());
+            }
+            let mut deposits = BTreeMap::<ClientId, Vec<Decimal>>::new();
+            for entry in check
+                .ledger
+                .journal()
+                .iter()
+                .filter(|entry| entry.kind == TxTypeTag::Deposit)
+            {
+                deposits
+                    .entry(entry.client_id)
+                    .or_default()
+                    .push(entry.available_delta + entry.held_delta);
+            }
+            for window in check.ledger.velocity_windows() {
+                let all = deposits.remove(&window.client_id).unwrap_or_default();
+                let expected = &all[all.len().saturating_sub(VELOCITY_WINDOW)..];
+                if window.recent_deposits != expected {
+                    return Err(format!(
+                        "client {} window {:?} but journaled deposits end with {:?}",
+                        window.client_id, window.recent_deposits, expected
+                    ));
+                }
+            }
+            match deposits.keys().next() {
+                Some(client_id) => Err(format!("client {} deposited without a window", client_id)),
+                None => Ok(()),
+            }
+        });
+    harness
+}
+
+#[test]
+fn derived_state_stays_consistent() {
+    core_invariants().run();
+}
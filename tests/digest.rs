@@ -0,0 +1,87 @@
+//! Golden runs compare the execution digest with `--expect-digest`.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::PathBuf;
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,5.0\n\
+                     withdrawal,1,3,2.5\n\
+                     dispute,2,2,\n\
+                     withdrawal,2,4,1.0\n";
+
+/// Writes `contents` into a temporary file unique to `name` and the test process.
+fn write_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_digest_{}_{}",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Execution and state digest printed by `--print-digest`.
+fn digests(input: &PathBuf) -> (String, String) {
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--print-digest")
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let line = stderr
+        .strip_prefix("Execution digest ")
+        .and_then(|line| line.strip_suffix(", digest version 1\n"))
+        .unwrap_or_else(|| panic!("unexpected stderr {:?}", stderr));
+    let (execution, state) = line.split_once(" and state digest ").unwrap();
+    (execution.to_string(), state.to_string())
+}
+
+#[test]
+fn digests_are_stable_and_follow_amounts() {
+    let input = write_file("stable.csv", INPUT);
+    let changed = write_file("changed.csv", &INPUT.replace("2.5", "2.4"));
+    let (execution, state) = digests(&input);
+    let again = digests(&input);
+    let other = digests(&changed);
+    for path in [input, changed] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_eq!(execution.len(), 64);
+    assert_eq!(again, (execution.clone(), state.clone()));
+    assert_ne!(other.0, execution);
+    assert_ne!(other.1, state);
+}
+
+#[test]
+fn unexpected_digest_fails_the_run_after_the_output() {
+    let input = write_file("expect.csv", INPUT);
+    let (execution, _state) = digests(&input);
+
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--expect-digest", &execution.to_uppercase()])
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stderr, b"");
+
+    let expected = "0".repeat(64);
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--expect-digest", &expected])
+        .arg(&input)
+        .output()
+        .unwrap();
+    std::fs::remove_file(input).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("client,available,held,total,locked\n"));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "Execution digest {} differs from the expected {}, digest version 1\n",
+            execution, expected
+        )
+    );
+}
@@ -0,0 +1,62 @@
+//! `--no-dispute-tracking` writes the same report as the default for inputs without
+//! disputes, and rejects and counts the dispute rows of other inputs.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::Path;
+
+/// Report with its rows sorted, and stderr.
+fn run(args: &[&str], fixture: &str) -> (String, String) {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(args)
+        .arg(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(fixture),
+        )
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<_> = stdout.lines().collect();
+    lines[1..].sort_unstable();
+    (lines.join("\n"), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn dispute_free_inputs_have_the_same_report() {
+    let (report, _stderr) = run(&[], "dispute_free.csv");
+    assert_eq!(
+        report,
+        "client,available,held,total,locked\n\
+         1,8.75,0,8.75,false\n\
+         2,5.5,0,5.5,false"
+    );
+    let (untracked, stderr) = run(&["--no-dispute-tracking"], "dispute_free.csv");
+    assert_eq!(untracked, report);
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn dispute_rows_are_rejected_and_counted() {
+    let (report, _stderr) = run(&[], "dispute_tracking_mis_flagged.csv");
+    assert_eq!(
+        report,
+        "client,available,held,total,locked\n\
+         1,-1.25,0,-1.25,true\n\
+         2,5.5,0,5.5,false"
+    );
+    let (report, stderr) = run(
+        &["--no-dispute-tracking"],
+        "dispute_tracking_mis_flagged.csv",
+    );
+    assert_eq!(
+        report,
+        "client,available,held,total,locked\n\
+         1,8.75,0,8.75,false\n\
+         2,5.5,0,5.5,false"
+    );
+    assert_eq!(
+        stderr,
+        "Rejected 2 dispute, resolve, chargeback and adjustment rows because of \
+         --no-dispute-tracking\n"
+    );
+}
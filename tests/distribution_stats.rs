@@ -0,0 +1,51 @@
+//! `--distribution-stats` prints the count, sum, extremes and percentile estimates of
+//! the applied deposit and withdrawal amounts, and of the rejected ones with
+//! `--distribution-include-rejected`.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::Path;
+
+fn run(args: &[&str]) -> (bool, String) {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(args)
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/distribution_stats.csv"))
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn applied_amounts_only_by_default() {
+    // Deposits of 1 to 100, whose exact percentiles are 50, 90, 95 and 99
+    let (success, stderr) = run(&["--distribution-stats"]);
+    assert!(success);
+    assert_eq!(
+        stderr,
+        "Deposits: count 100, sum 5050, min 1, max 100, p50 49.9278, p90 90.432, \
+         p95 94.0851, p99 99.8432; 0 rejected, not included\n\
+         Withdrawals: count 1, sum 50, min 50, max 50, p50 50, p90 50, p95 50, p99 50; \
+         1 rejected, not included\n"
+    );
+}
+
+#[test]
+fn rejected_amounts_are_included_on_request() {
+    let (success, stderr) = run(&["--distribution-stats", "--distribution-include-rejected"]);
+    assert!(success);
+    assert!(
+        stderr.ends_with(
+            "Withdrawals: count 2, sum 10050, min 50, max 10000, p50 50, p90 10000, \
+             p95 10000, p99 10000; 1 rejected, included\n"
+        ),
+        "{}",
+        stderr
+    );
+
+    let (_success, stderr) = run(&["--distribution-include-rejected"]);
+    assert_eq!(
+        stderr,
+        "--distribution-include-rejected requires --distribution-stats\n"
+    );
+}
@@ -0,0 +1,95 @@
+//! Client flags and notes from `--enrich` decorate the output without changing the
+//! balances.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::PathBuf;
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,20.0\n\
+                     deposit,3,3,5.0\n\
+                     withdrawal,1,4,2.5\n";
+
+const ENRICHMENT: &str = "client,flags,note\n\
+                          1,vip;under_review,\"called, awaiting documents\"\n\
+                          3,collections,\n\
+                          8,vip,\n\
+                          9,under_review,closed\n";
+
+/// Writes `contents` into a temporary file unique to `name` and the test process.
+fn write_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_enrich_{}_{}",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Stdout with the data rows sorted, and stderr.
+fn run(args: &[&str], input: &PathBuf) -> (Vec<String>, String) {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let mut lines: Vec<String> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines[1..].sort_unstable();
+    (lines, String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn extended_output_joins_flags_and_notes() {
+    let input = write_file("join_input.csv", INPUT);
+    let enrichment = write_file("join_enrichment.csv", ENRICHMENT);
+    let enrich = enrichment.to_str().unwrap();
+    let (lines, stderr) = run(&["--enrich", enrich, "--extended-output"], &input);
+    assert_eq!(
+        lines,
+        [
+            "client,available,held,total,locked,overdraft_limit,deposited,withdrawn,\
+             charged_back,open_disputes,created_by_rejection,flags,note",
+            "1,7.5,0,7.5,false,0,10,2.5,0,0,false,vip;under_review,\"called, awaiting documents\"",
+            "2,20,0,20,false,0,20,0,0,0,false,,",
+            "3,5,0,5,false,0,5,0,0,0,false,collections,",
+        ]
+    );
+    assert_eq!(
+        stderr,
+        "Warning: 2 clients of the enrichment file have no account\n"
+    );
+
+    let (lines, _stderr) = run(&["--enrich", enrich, "--only-flag", "under_review"], &input);
+    assert_eq!(
+        lines,
+        ["client,available,held,total,locked", "1,7.5,0,7.5,false"]
+    );
+
+    // Without --extended-output the report is the same as without enrichment.
+    let (enriched, _stderr) = run(&["--enrich", enrich], &input);
+    let (plain, stderr) = run(&[], &input);
+    assert_eq!(enriched, plain);
+    assert_eq!(stderr, "");
+    std::fs::remove_file(input).unwrap();
+    std::fs::remove_file(enrichment).unwrap();
+}
+
+#[test]
+fn only_flag_requires_enrich() {
+    let input = write_file("only_flag_input.csv", INPUT);
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--only-flag", "vip"])
+        .arg(&input)
+        .output()
+        .unwrap();
+    std::fs::remove_file(input).unwrap();
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "--only-flag requires --enrich\n"
+    );
+}
@@ -0,0 +1,43 @@
+//! The account report written without serde is byte for byte the one serde writes,
+//! on balances whose formatting is easy to get wrong.
+use payments_engine::accounting::transactions::{Transaction, TransactionLog};
+use payments_engine::accounting::Ledger;
+use payments_engine::output::{write_accounts, write_accounts_serde};
+use std::path::Path;
+
+#[tokio::test]
+async fn fast_report_matches_the_serde_report() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tricky_balances.csv");
+    let mut ledger = Ledger::new();
+    for log in csv::Reader::from_path(fixture).unwrap().deserialize() {
+        let log: TransactionLog = log.unwrap();
+        ledger
+            .execute(&Transaction::try_from(log).unwrap())
+            .unwrap();
+    }
+    let (mut fast, mut serde) = (Vec::new(), Vec::new());
+    assert_eq!(write_accounts(&ledger, &mut fast).await.unwrap(), 6);
+    write_accounts_serde(&ledger, &mut serde).await.unwrap();
+    let fast = String::from_utf8(fast).unwrap();
+    assert_eq!(fast, String::from_utf8(serde).unwrap());
+
+    let mut rows: Vec<_> = fast.lines().collect();
+    rows[1..].sort_unstable();
+    assert_eq!(
+        rows,
+        [
+            "client,available,held,total,locked",
+            // zero left by a withdrawal of the whole balance
+            "1,0,0,0,false",
+            // negative available balance of a disputed deposit
+            "2,-8,10,2,false",
+            // exactly 4 decimal places
+            "3,1.2345,0,1.2345,false",
+            // fewer than 4 decimal places
+            "4,2.5,0,2.5,false",
+            // zero keeping the scale of the amounts it was computed from
+            "5,0.0000,0,0,false",
+            "6,0,0,0,true",
+        ]
+    );
+}
@@ -0,0 +1,114 @@
+//! Calls the C ABI the way a foreign caller would, through raw pointers only.
+use payments_engine::ffi::*;
+use std::ffi::{c_char, CStr};
+use std::ptr;
+
+fn execute(ledger: *mut PeLedger, row: &[u8]) -> i32 {
+    unsafe { pe_execute_csv_row(ledger, row.as_ptr(), row.len()) }
+}
+
+fn last_error() -> String {
+    let message = pe_last_error_message();
+    assert!(!message.is_null());
+    let text = unsafe { CStr::from_ptr(message) }
+        .to_str()
+        .unwrap()
+        .to_string();
+    unsafe { pe_string_free(message) };
+    text
+}
+
+fn export(ledger: *mut PeLedger) -> String {
+    let mut buf: *mut c_char = ptr::null_mut();
+    let mut len = 0;
+    assert_eq!(
+        unsafe { pe_export_accounts_csv(ledger, &mut buf, &mut len) },
+        PE_OK
+    );
+    let csv = unsafe { CStr::from_ptr(buf) }.to_str().unwrap().to_string();
+    assert_eq!(csv.len(), len);
+    unsafe { pe_string_free(buf) };
+    csv
+}
+
+#[test]
+fn executes_rows_and_exports_accounts() {
+    let ledger = pe_ledger_new();
+    assert!(!ledger.is_null());
+    assert_eq!(execute(ledger, b"deposit, 1, 1, 5.0"), PE_OK);
+    assert_eq!(execute(ledger, b"deposit,2,2,1.5\n"), PE_OK);
+    assert_eq!(execute(ledger, b"withdrawal, 1, 3, 2.0"), PE_OK);
+    assert_eq!(
+        execute(ledger, b"withdrawal, 1, 4, 9.0"),
+        PE_ERR_INSUFFICIENT_FUNDS
+    );
+    assert_eq!(last_error(), "transaction rejected: insufficient_funds");
+    assert_eq!(execute(ledger, b"dispute, 2, 2,"), PE_OK);
+    assert_eq!(execute(ledger, b"chargeback, 2, 2,"), PE_OK);
+    assert_eq!(
+        execute(ledger, b"dispute, 2, 2,"),
+        PE_ERR_TX_ALREADY_DISPUTED
+    );
+
+    assert_eq!(
+        export(ledger),
+        "client,available,held,total,locked\n1,3,0,3,false\n2,0.0,0.0,0.0,true\n"
+    );
+    unsafe { pe_ledger_free(ledger) };
+}
+
+#[test]
+fn rejects_bad_utf8_and_invalid_rows() {
+    let ledger = pe_ledger_new();
+    assert_eq!(
+        execute(ledger, b"deposit, 1, 1, \xff\xfe"),
+        PE_ERR_INVALID_UTF8
+    );
+    assert!(last_error().starts_with("row is not valid UTF-8"));
+    assert_eq!(
+        execute(ledger, b"refund, 1, 2, 1.0"),
+        PE_ERR_INVALID_TX_TYPE
+    );
+    assert_eq!(execute(ledger, b"deposit, 1, 3"), PE_ERR_TOO_FEW_FIELDS);
+    assert_eq!(
+        execute(ledger, b"deposit, 1, 3, 1.0, 2.0"),
+        PE_ERR_TOO_MANY_FIELDS
+    );
+    assert_eq!(execute(ledger, b""), PE_ERR_INVALID_TX_TYPE);
+    assert_eq!(execute(ledger, b"deposit, 1, 4,"), PE_ERR_MISSING_AMOUNT);
+    assert_eq!(last_error(), "invalid row: missing_amount");
+    assert_eq!(export(ledger), "");
+
+    assert_eq!(
+        unsafe { pe_execute_csv_row(ptr::null_mut(), b"x".as_ptr(), 1) },
+        PE_ERR_NULL_POINTER
+    );
+    assert_eq!(
+        unsafe { pe_execute_csv_row(ledger, ptr::null(), 0) },
+        PE_ERR_NULL_POINTER
+    );
+    assert_eq!(
+        unsafe { pe_export_accounts_csv(ledger, ptr::null_mut(), ptr::null_mut()) },
+        PE_ERR_NULL_POINTER
+    );
+    unsafe {
+        pe_ledger_free(ledger);
+        pe_ledger_free(ptr::null_mut());
+        pe_string_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn header_declares_all_functions() {
+    let header = include_str!("../include/payments_engine.h");
+    for function in [
+        "pe_ledger_new",
+        "pe_ledger_free",
+        "pe_execute_csv_row",
+        "pe_export_accounts_csv",
+        "pe_last_error_message",
+        "pe_string_free",
+    ] {
+        assert!(header.contains(&format!("{}(", function)), "{}", function);
+    }
+}
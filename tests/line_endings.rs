@@ -0,0 +1,130 @@
+//! Inputs exported from spreadsheets on Windows, starting with a UTF-8 BOM and ending
+//! lines with `\r\n`, produce the same ledger as their clean `\n` versions on every
+//! read path, and outputs end lines with `\n` unless `--crlf-output` is given.
+use assert_cmd::cargo::cargo_bin_cmd;
+use payments_engine::accounting::Ledger;
+use payments_engine::ingest::{fast, transaction_stream};
+use std::path::{Path, PathBuf};
+use tokio_stream::StreamExt;
+
+const VARIANTS: [&str; 4] = ["bom_crlf", "bom", "crlf", "no_final_newline"];
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/fixtures/excel/{}.csv", name))
+}
+
+/// Digest lines and account rows in client order, the order of the rows being
+/// unspecified.
+fn run(args: &[&str], input: &Path) -> String {
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--print-digest")
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} {:?}: {:?}",
+        args,
+        input,
+        output
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains('\r'), "{:?} {:?}", args, input);
+    let mut lines: Vec<_> = stdout.lines().skip(1).collect();
+    lines.sort();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let digest = stderr
+        .lines()
+        .find(|line| line.starts_with("Execution digest"))
+        .unwrap();
+    format!("{}\n{}", digest, lines.join("\n"))
+}
+
+#[test]
+fn every_read_path_matches_the_clean_input() {
+    for args in [
+        &[][..],
+        &["--parser", "fast"][..],
+        &["--workers", "2"][..],
+        &["--two-pass"][..],
+        &["--io-buffer-size", "2"][..],
+        &["--prefetch"][..],
+    ] {
+        let expected = run(args, &fixture("lf"));
+        for variant in VARIANTS {
+            assert_eq!(
+                run(args, &fixture(variant)),
+                expected,
+                "{:?} {}",
+                args,
+                variant
+            );
+        }
+    }
+}
+
+#[test]
+fn headers_of_every_variant_are_valid() {
+    for variant in VARIANTS {
+        let output = cargo_bin_cmd!("payments_engine")
+            .arg("check")
+            .arg(fixture(variant))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}: {:?}", variant, output);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "checked=9 ok=9 rejected=0 structural=0\n"
+        );
+    }
+}
+
+#[tokio::test]
+async fn both_parsers_build_the_same_ledger() {
+    async fn ledgers(input: &[u8]) -> [Ledger; 2] {
+        let mut csv = Ledger::new();
+        let mut rows = Box::pin(transaction_stream(input));
+        while let Some(tx) = rows.next().await {
+            csv.execute(&tx.unwrap()).ok();
+        }
+        let mut fast = Ledger::new();
+        let mut rows = fast::transaction_stream(input);
+        while let Some(tx) = rows.next().await {
+            fast.execute(&tx.unwrap()).ok();
+        }
+        [csv, fast]
+    }
+
+    let clean = std::fs::read(fixture("lf")).unwrap();
+    let [expected, _fast] = ledgers(&clean).await;
+    for variant in VARIANTS {
+        let input = std::fs::read(fixture(variant)).unwrap();
+        for ledger in ledgers(&input).await {
+            assert_eq!(
+                ledger.state_digest(),
+                expected.state_digest(),
+                "{}",
+                variant
+            );
+        }
+    }
+}
+
+#[test]
+fn crlf_output_is_opt_in() {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--crlf-output", "--output-clients", "1..=3"])
+        .arg(fixture("bom_crlf"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "# selection_applied=true\r\n\
+         client,available,held,total,locked\r\n\
+         1,8.25,3,11.25,false\r\n\
+         2,14.5,0,14.5,false\r\n\
+         3,0.00,0.00,0.00,true\r\n"
+    );
+}
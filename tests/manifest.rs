@@ -0,0 +1,116 @@
+//! Runs against sidecar manifests, which must fail without output when the input
+//! disagrees unless `--force` is given.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::{Path, PathBuf};
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,20.0\n\
+                     withdrawal,1,3,2.5\n";
+
+/// Account rows of the output, which come in no particular order.
+fn sorted_rows(stdout: Vec<u8>) -> Vec<String> {
+    let mut rows: Vec<_> = String::from_utf8(stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    rows.sort();
+    rows
+}
+
+/// Writes `contents` into a temporary file unique to `name` and the test process.
+fn write_input(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_manifest_{}_{}.csv",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn sidecar(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.manifest.json", path.display()))
+}
+
+/// Generates the manifest of `INPUT` with the `manifest` subcommand.
+fn generate(name: &str) -> String {
+    let path = write_input(name, INPUT);
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("manifest")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn generated_sidecar_matches() {
+    let manifest = generate("generated");
+    assert!(manifest.contains("\"rows\": 3"));
+    assert!(manifest.contains("\"deposit_sum\": \"30\""));
+    assert!(manifest.contains("\"withdrawal_sum\": \"2.5\""));
+    let path = write_input("matching", INPUT);
+    std::fs::write(sidecar(&path), &manifest).unwrap();
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_rows(output.stdout),
+        [
+            "1,7.5,0,7.5,false",
+            "2,20,0,20,false",
+            "client,available,held,total,locked"
+        ]
+    );
+    std::fs::remove_file(sidecar(&path)).unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn truncated_input_fails_without_output_unless_forced() {
+    let manifest_path = write_input("declared", &generate("declared_source"));
+    let truncated = &INPUT[..INPUT.len() - "withdrawal,1,3,2.5\n".len()];
+    let path = write_input("truncated", truncated);
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--manifest")
+        .arg(&manifest_path)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("does not match its manifest: sha256"),
+        "{}",
+        stderr
+    );
+
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--manifest")
+        .arg(&manifest_path)
+        .arg("--force")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_rows(output.stdout),
+        [
+            "1,10,0,10,false",
+            "2,20,0,20,false",
+            "client,available,held,total,locked"
+        ]
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("WARNING"));
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(manifest_path).unwrap();
+}
@@ -0,0 +1,128 @@
+//! `--output-clients`, `--output-limit`, `--only-locked` and `--only-negative` write a
+//! marked preview of the account report, sorted by client.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::PathBuf;
+
+/// Clients 1 to 5, where client 2 is locked by a chargeback leaving it negative and
+/// client 4 is locked with a positive balance.
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,5,1,5.0\n\
+                     deposit,4,2,4.0\n\
+                     deposit,3,3,3.0\n\
+                     deposit,2,4,2.0\n\
+                     deposit,1,5,1.0\n\
+                     withdrawal,2,6,1.5\n\
+                     dispute,2,4,\n\
+                     chargeback,2,4,\n\
+                     deposit,4,7,1.0\n\
+                     dispute,4,7,\n\
+                     chargeback,4,7,\n";
+
+const MARKER: &str = "# selection_applied=true\n";
+const HEADER: &str = "client,available,held,total,locked\n";
+
+/// Writes `INPUT` into a temporary file unique to `name` and the test process.
+fn input(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_output_selection_{}_{}.csv",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, INPUT).unwrap();
+    path
+}
+
+fn run(args: &[&str]) -> String {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(args)
+        .arg(input(&args.join("_")))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn each_selector_narrows_the_sorted_report() {
+    let cases: [(&[&str], &str); 4] = [
+        (
+            &["--output-clients", "2..=3"],
+            "2,-1.5,0,-1.5,true\n3,3,0,3,false\n",
+        ),
+        (
+            &["--output-limit", "2"],
+            "1,1,0,1,false\n2,-1.5,0,-1.5,true\n",
+        ),
+        (&["--only-locked"], "2,-1.5,0,-1.5,true\n4,4,0,4,true\n"),
+        (&["--only-negative"], "2,-1.5,0,-1.5,true\n"),
+    ];
+    for (args, rows) in cases {
+        assert_eq!(
+            run(args),
+            format!("{}{}{}", MARKER, HEADER, rows),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn selectors_combine() {
+    assert_eq!(
+        run(&[
+            "--output-clients",
+            "3..=5",
+            "--only-locked",
+            "--output-limit",
+            "1"
+        ]),
+        format!("{}{}4,4,0,4,true\n", MARKER, HEADER)
+    );
+    assert_eq!(
+        run(&[
+            "--output-clients",
+            "1..=3",
+            "--only-locked",
+            "--only-negative",
+            "--extended-output",
+        ]),
+        format!(
+            "{}client,available,held,total,locked,overdraft_limit,deposited,withdrawn,\
+             charged_back,open_disputes,created_by_rejection\n\
+             2,-1.5,0,-1.5,true,0,2,1.5,2,0,false\n",
+            MARKER
+        )
+    );
+}
+
+#[test]
+fn empty_selection_is_a_header_only_csv() {
+    assert_eq!(
+        run(&["--output-clients", "100..=200"]),
+        format!("{}{}", MARKER, HEADER)
+    );
+    assert_eq!(
+        run(&["--output-limit", "0"]),
+        format!("{}{}", MARKER, HEADER)
+    );
+}
+
+#[test]
+fn no_selection_writes_the_full_report_unmarked() {
+    let output = run(&[]);
+    assert!(output.starts_with(HEADER));
+    assert_eq!(output.lines().count(), 6);
+}
+
+#[test]
+fn selection_is_csv_only() {
+    cargo_bin_cmd!("payments_engine")
+        .args(["--only-locked", "--enrich", "enrichment.csv"])
+        .arg(input("csv_only"))
+        .assert()
+        .stderr(
+            "--output-clients, --output-limit, --only-locked and --only-negative cannot be \
+             combined with --enrich, --output-format parquet or --output-dir\n",
+        );
+}
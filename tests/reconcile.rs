@@ -0,0 +1,74 @@
+//! Runs reconciled against expected balances, which still write their output but exit
+//! with 1 when the balances differ by more than the tolerance.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::PathBuf;
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,20.0\n\
+                     withdrawal,1,3,2.5\n";
+
+/// Writes `contents` into a temporary file unique to `name` and the test process.
+fn write_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_reconcile_{}_{}",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn matching_balances_succeed() {
+    let input = write_file("matching_input.csv", INPUT);
+    let expected = write_file(
+        "matching_expected.csv",
+        "client,available,held,total,locked\n2,20.00,0,20,false\n1,7.5,0,7.5,false\n",
+    );
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--reconcile")
+        .arg(&expected)
+        .arg(&input)
+        .output()
+        .unwrap();
+    std::fs::remove_file(input).unwrap();
+    std::fs::remove_file(expected).unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Reconciliation: 2 clients matched, 0 missing"));
+}
+
+#[test]
+fn discrepancies_beyond_tolerance_fail_after_writing_the_report() {
+    let input = write_file("mismatch_input.csv", INPUT);
+    let expected = write_file(
+        "mismatch_expected.csv",
+        "client,available,held,total,locked\n1,7.45,0,7.45,false\n3,1,0,1,false\n",
+    );
+    let report = write_file("mismatch_report.json", "");
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--reconcile")
+        .arg(&expected)
+        .arg("--reconcile-tolerance")
+        .arg("0.05")
+        .arg("--reconcile-report")
+        .arg(&report)
+        .arg(&input)
+        .output()
+        .unwrap();
+    let written = std::fs::read_to_string(&report).unwrap();
+    std::fs::remove_file(input).unwrap();
+    std::fs::remove_file(expected).unwrap();
+    std::fs::remove_file(report).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 3);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("client 3 is expected but has no account"));
+    assert!(stderr.contains("client 2 has an account but is not expected"));
+    let written: serde_json::Value = serde_json::from_str(&written).unwrap();
+    assert_eq!(written["clients_matched"], 1);
+    assert_eq!(written["discrepancies"][0]["kind"], "missing_in_expected");
+    assert_eq!(written["discrepancies"][1]["kind"], "missing_in_ledger");
+}
@@ -0,0 +1,119 @@
+//! Every artifact of a run names the same run id, while the default output stays
+//! unstamped.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::PathBuf;
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,1,x,1.0\n\
+                     withdrawal,1,2,2.5\n";
+
+/// Writes `contents` into a temporary file unique to `name` and the test process.
+fn write_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_run_id_{}_{}",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Stdout and stderr.
+fn run(args: &[&str], input: &PathBuf) -> (String, String) {
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+/// The run id reported on stderr by stamped runs.
+fn logged_run_id(stderr: &str) -> String {
+    stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Run id "))
+        .expect("run id on stderr")
+        .to_string()
+}
+
+#[test]
+fn all_artifacts_of_a_run_name_its_id() {
+    let input = write_file("all_input.csv", INPUT);
+    let expected = write_file(
+        "all_expected.csv",
+        "client,available,held,total,locked\n1,7.5,0,7.5,false\n",
+    );
+    let output = write_file("all_output.csv", "");
+    let quarantine = write_file("all_quarantine.csv", "");
+    let report = write_file("all_report.json", "");
+    let (stdout, stderr) = run(
+        &[
+            "--stamp-outputs",
+            "--output",
+            output.to_str().unwrap(),
+            "--quarantine",
+            quarantine.to_str().unwrap(),
+            "--reconcile",
+            expected.to_str().unwrap(),
+            "--reconcile-report",
+            report.to_str().unwrap(),
+        ],
+        &input,
+    );
+    let run_id = logged_run_id(&stderr);
+    let written =
+        [&output, &quarantine, &report].map(|path| std::fs::read_to_string(path).unwrap());
+    for path in [input, expected, output, quarantine, report] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_eq!(stdout, "");
+    assert_eq!(
+        written[0],
+        format!(
+            "# run_id={}\nclient,available,held,total,locked\n1,7.5,0,7.5,false\n",
+            run_id
+        )
+    );
+    let quarantined: Vec<_> = written[1].lines().collect();
+    assert!(quarantined[0].ends_with(",error_reason,run_id"));
+    assert!(quarantined[1].starts_with("deposit,1,x,1.0,"));
+    assert!(quarantined[1].ends_with(&format!(",{}", run_id)));
+    let report: serde_json::Value = serde_json::from_str(&written[2]).unwrap();
+    assert_eq!(report["run_id"], run_id.as_str());
+}
+
+#[test]
+fn supplied_ids_are_used() {
+    let input = write_file("supplied_input.csv", INPUT);
+    let (stdout, stderr) = run(
+        &["--run-id", "nightly-2026-10-16", "--stamp-outputs"],
+        &input,
+    );
+    std::fs::remove_file(input).unwrap();
+    assert_eq!(logged_run_id(&stderr), "nightly-2026-10-16");
+    assert!(stdout.starts_with("# run_id=nightly-2026-10-16\nclient,"));
+}
+
+#[test]
+fn runs_differ_and_default_output_is_unstamped() {
+    let input = write_file("default_input.csv", INPUT);
+    let (first, first_stderr) = run(&["--stamp-outputs"], &input);
+    let (second, second_stderr) = run(&["--stamp-outputs"], &input);
+    let (unstamped, unstamped_stderr) = run(&[], &input);
+    std::fs::remove_file(input).unwrap();
+    let (first_id, second_id) = (logged_run_id(&first_stderr), logged_run_id(&second_stderr));
+    assert_ne!(first_id, second_id);
+    assert!(first.starts_with(&format!("# run_id={}\n", first_id)));
+    assert!(second.starts_with(&format!("# run_id={}\n", second_id)));
+    assert_eq!(
+        unstamped,
+        "client,available,held,total,locked\n1,7.5,0,7.5,false\n"
+    );
+    assert_eq!(unstamped_stderr, "");
+}
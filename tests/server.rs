@@ -0,0 +1,155 @@
+//! Talks to the socket server the way a producer would, over a real Unix socket.
+use payments_engine::accounting::shared::SharedLedger;
+use payments_engine::accounting::transactions::{
+    Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal,
+};
+use payments_engine::accounting::Ledger;
+use payments_engine::lanes::LaneConfig;
+use payments_engine::server::{
+    send_transactions, serve_unix_socket, serve_unix_socket_with_lanes, MAX_FRAME_SIZE,
+};
+use rust_decimal_macros::dec;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments_engine_{}_{}.sock",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Connects once the spawned server has bound the socket.
+async fn connect(path: &Path) -> UnixStream {
+    for _ in 0..10_000 {
+        if let Ok(stream) = UnixStream::connect(path).await {
+            return stream;
+        }
+        tokio::task::yield_now().await;
+    }
+    panic!("server did not bind {}", path.display());
+}
+
+fn lifecycle() -> [Transaction; 9] {
+    [
+        Transaction::Deposit(Deposit::new(1, 1, dec!(10.0))),
+        Transaction::Deposit(Deposit::new(1, 2, dec!(5.0))),
+        Transaction::Withdrawal(Withdrawal::new(1, 3, dec!(3.0))),
+        Transaction::Dispute(Dispute::new(1, 1)),
+        Transaction::Resolve(Resolve::new(1, 1)),
+        Transaction::Resolve(Resolve::new(1, 1)),
+        Transaction::Dispute(Dispute::new(1, 2)),
+        Transaction::Chargeback(Chargeback::new(1, 2)),
+        Transaction::Withdrawal(Withdrawal::new(1, 4, dec!(1.0))),
+    ]
+}
+
+/// Sends `lifecycle` and checks the responses and the account of client 1.
+async fn assert_lifecycle(stream: &mut UnixStream, ledger: &SharedLedger) {
+    let responses = send_transactions(stream, &lifecycle()).await.unwrap();
+    let results: Vec<_> = responses
+        .iter()
+        .map(|response| (response.request_id(), response.result()))
+        .collect();
+    assert_eq!(
+        results,
+        vec![
+            (0, Ok(())),
+            (1, Ok(())),
+            (2, Ok(())),
+            (3, Ok(())),
+            (4, Ok(())),
+            (5, Err("tx_not_disputed")),
+            (6, Ok(())),
+            (7, Ok(())),
+            (8, Err("client_account_locked")),
+        ]
+    );
+
+    let account = ledger.account(1).unwrap();
+    assert_eq!(account.available, dec!(7.0));
+    assert_eq!(account.held, dec!(0.0));
+    assert_eq!(account.total, dec!(7.0));
+    assert!(account.locked);
+}
+
+#[tokio::test]
+async fn dispute_lifecycle_over_socket() {
+    let path = socket_path("lifecycle");
+    let ledger = SharedLedger::new(Ledger::new());
+    tokio::spawn(serve_unix_socket(path.clone(), ledger.clone()));
+    let mut stream = connect(&path).await;
+    assert_lifecycle(&mut stream, &ledger).await;
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn priority_lanes_keep_the_order_of_each_connection() {
+    let path = socket_path("lanes");
+    let ledger = SharedLedger::new(Ledger::new());
+    tokio::spawn(serve_unix_socket_with_lanes(
+        path.clone(),
+        ledger.clone(),
+        Default::default(),
+        LaneConfig {
+            capacity: 4,
+            max_priority_streak: 2,
+        },
+    ));
+    // A burst of deposits of other clients on their own connections
+    let mut bursts = Vec::new();
+    for client_id in 2..6 {
+        let mut stream = connect(&path).await;
+        bursts.push(tokio::spawn(async move {
+            let deposits: Vec<_> = (0..500)
+                .map(|tx_id| {
+                    Transaction::Deposit(Deposit::new(
+                        client_id,
+                        client_id as u32 * 1000 + tx_id,
+                        dec!(1.0),
+                    ))
+                })
+                .collect();
+            send_transactions(&mut stream, &deposits).await.unwrap()
+        }));
+    }
+    let mut stream = connect(&path).await;
+    assert_lifecycle(&mut stream, &ledger).await;
+    for burst in bursts {
+        let responses = burst.await.unwrap();
+        assert!(responses.iter().all(|response| response.result() == Ok(())));
+    }
+    for client_id in 2..6 {
+        assert_eq!(ledger.account(client_id).unwrap().total, dec!(500.0));
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn malformed_frames_drop_only_their_connection() {
+    let path = socket_path("malformed");
+    let ledger = SharedLedger::new(Ledger::new());
+    tokio::spawn(serve_unix_socket(path.clone(), ledger.clone()));
+
+    let mut oversized = connect(&path).await;
+    oversized
+        .write_all(&(MAX_FRAME_SIZE + 1).to_be_bytes())
+        .await
+        .unwrap();
+    assert_eq!(oversized.read(&mut [0; 16]).await.unwrap(), 0);
+
+    let mut garbage = connect(&path).await;
+    garbage.write_all(&[0, 0, 0, 3, 9, 9, 9]).await.unwrap();
+    assert_eq!(garbage.read(&mut [0; 16]).await.unwrap(), 0);
+
+    let mut stream = connect(&path).await;
+    let deposit = [Transaction::Deposit(Deposit::new(2, 1, dec!(1.5)))];
+    let responses = send_transactions(&mut stream, &deposit).await.unwrap();
+    assert_eq!(responses[0].result(), Ok(()));
+    assert_eq!(ledger.account(2).unwrap().available, dec!(1.5));
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,62 @@
+//! `--tag-rules` tags rows with the names of the rules they match in the `tags`
+//! column of `--audit-log` and counts them on `stderr`.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+#[test]
+fn matching_rules_are_listed_in_the_audit_log_and_counted() {
+    let dir = std::env::temp_dir().join(format!("tag_rules_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let audit_log = dir.join("audit.csv");
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--tag-rules")
+        .arg(fixture("tag_rules.toml"))
+        .arg("--audit-log")
+        .arg(&audit_log)
+        .arg(fixture("tag_rules.csv"))
+        .output()
+        .unwrap();
+    let log = std::fs::read_to_string(&audit_log).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    // The streaming run does not know the lines of the rows
+    assert_eq!(
+        log,
+        "line,outcome,type,client,tx,amount,error_code,tags\n\
+         ,executed,deposit,1,1,5000,,\n\
+         ,executed,withdrawal,1,2,1200,,large_debit\n\
+         ,rejected,withdrawal,7,3,20,client_account_not_found,watched_client\n\
+         ,executed,deposit,7,4,100,,watched_client\n\
+         ,rejected,withdrawal,7,5,2000,insufficient_funds,large_debit;overdraft;watched_client\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Tagged 2 rows large_debit\n\
+         Tagged 1 rows overdraft\n\
+         Tagged 3 rows watched_client\n"
+    );
+}
+
+#[test]
+fn invalid_rules_are_rejected_before_the_run() {
+    let output = cargo_bin_cmd!("payments_engine")
+        .arg("--tag-rules")
+        .arg(fixture("tag_rules_invalid.toml"))
+        .arg(fixture("tag_rules.csv"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "invalid tag rules at line 2, column 31: unknown field `amount_over`, expected one of \
+         `kind`, `amount_gt`, `amount_lt`, `amount_eq`, `client_in`, `is_rejected`, \
+         `error_code`\n"
+    );
+}
@@ -0,0 +1,43 @@
+//! `--top-k` prints the largest accounts, the largest open disputes and the most
+//! rejected clients, ties going to the lowest client id.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::Path;
+
+const EXPECTED: &str = "Top 3 accounts by total:\n  \
+                        client 1: 70\n  \
+                        client 2: 30\n  \
+                        client 3: 30\n\
+                        Top 3 open disputes by amount:\n  \
+                        client 2 tx 2: 30\n  \
+                        client 4 tx 4: 30\n  \
+                        client 1 tx 6: 20\n\
+                        Top 3 clients by rejected transactions:\n  \
+                        client 3: 2\n  \
+                        client 6: 2\n  \
+                        client 5: 1\n";
+
+#[test]
+fn top_k_block_breaks_ties_by_client() {
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/top_k.csv");
+    let quarantine =
+        std::env::temp_dir().join(format!("payments_engine_top_k_{}.csv", std::process::id()));
+    let quarantine = quarantine.to_str().unwrap();
+    for args in [
+        &[][..],
+        &["--workers", "2"][..],
+        &["--parser", "fast"][..],
+        &["--quarantine", quarantine][..],
+    ] {
+        let output = cargo_bin_cmd!("payments_engine")
+            .args(["--top-k", "3"])
+            .args(args)
+            .arg(&input)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        // after the count of quarantined rows
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.ends_with(EXPECTED), "{:?}: {}", args, stderr);
+    }
+    std::fs::remove_file(quarantine).unwrap();
+}
@@ -0,0 +1,10 @@
+use payments_engine::prelude::*;
+
+fn report(view: LedgerView<'_>) {
+    let _ = view.execute(&Transaction::Deposit(Deposit::new(1, 1, Decimal::ONE)));
+}
+
+fn main() {
+    let ledger = Ledger::new();
+    report(ledger.view());
+}
@@ -0,0 +1,95 @@
+//! `--unknown-client-on-debit create-empty` lists every client the input refers to
+//! in the report, while rejecting the same rows as the default.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::{Path, PathBuf};
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/unknown_clients.csv")
+}
+
+/// Extended report and `(line_number, error_code)` of every quarantined row.
+fn run(policy: &str) -> (String, Vec<(String, String)>) {
+    let quarantine = std::env::temp_dir().join(format!(
+        "payments_engine_unknown_clients_{}_{}.csv",
+        policy,
+        std::process::id()
+    ));
+    let output = cargo_bin_cmd!("payments_engine")
+        .args(["--unknown-client-on-debit", policy, "--extended-output"])
+        .arg("--quarantine")
+        .arg(&quarantine)
+        .arg(fixture())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let quarantined = std::fs::read_to_string(&quarantine).unwrap();
+    std::fs::remove_file(quarantine).unwrap();
+    let mut reader = csv::Reader::from_reader(quarantined.as_bytes());
+    let rejections = reader
+        .deserialize::<std::collections::HashMap<String, String>>()
+        .map(|row| {
+            let row = row.unwrap();
+            (row["line_number"].clone(), row["error_code"].clone())
+        })
+        .collect();
+    let mut lines: Vec<_> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines[1..].sort_unstable();
+    (lines.join("\n"), rejections)
+}
+
+fn rejected_lines(rejections: &[(String, String)]) -> Vec<&str> {
+    rejections
+        .iter()
+        .map(|(line, _code)| line.as_str())
+        .collect()
+}
+
+#[test]
+fn create_empty_completes_the_roster() {
+    let (report, rejections) = run("error");
+    assert_eq!(
+        report,
+        "client,available,held,total,locked,overdraft_limit,deposited,withdrawn,charged_back,\
+         open_disputes,created_by_rejection\n\
+         1,7.5,0,7.5,false,0,10,2.5,0,0,false\n\
+         2,3,0,3,false,0,3,0,0,0,false"
+    );
+    assert!(rejections
+        .iter()
+        .all(|(_line, code)| code == "client_account_not_found"));
+
+    let (report, created_rejections) = run("create-empty");
+    assert_eq!(
+        report,
+        "client,available,held,total,locked,overdraft_limit,deposited,withdrawn,charged_back,\
+         open_disputes,created_by_rejection\n\
+         1,7.5,0,7.5,false,0,10,2.5,0,0,false\n\
+         2,3,0,3,false,0,3,0,0,0,true\n\
+         3,0,0,0,false,0,0,0,0,0,true\n\
+         4,0,0,0,false,0,0,0,0,0,true\n\
+         5,0,0,0,false,0,0,0,0,0,true"
+    );
+    // The same rows are rejected, with the codes of the created accounts
+    assert_eq!(
+        rejected_lines(&created_rejections),
+        rejected_lines(&rejections)
+    );
+    let codes: Vec<_> = created_rejections
+        .iter()
+        .map(|(_line, code)| code.as_str())
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            "insufficient_funds",
+            "origin_tx_not_found",
+            "origin_tx_not_found",
+            "origin_tx_not_found",
+            "client_account_not_found",
+        ]
+    );
+}
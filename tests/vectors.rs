@@ -0,0 +1,30 @@
+//! Re-runs the frozen test vectors of `tests/vectors/` and compares the account output
+//! byte for byte. A failure means the rules changed: either the change is a bug, or it
+//! is deliberate and the vectors are regenerated with
+//! `cargo run --features testkit --bin genvectors -- --seed 42` and reviewed.
+use payments_engine::process_csv_str;
+use std::path::Path;
+
+#[test]
+fn frozen_vectors_produce_their_expected_output() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors");
+    let mut dirs: Vec<_> = std::fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    dirs.sort();
+    assert!(dirs.len() >= 10, "only {} vectors", dirs.len());
+    for dir in dirs {
+        let name = dir.file_name().unwrap().to_str().unwrap();
+        let metadata: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("meta.json")).unwrap()).unwrap();
+        assert_eq!(metadata["name"], name);
+        let input = std::fs::read_to_string(dir.join("input.csv")).unwrap();
+        let expected = std::fs::read_to_string(dir.join("expected.csv")).unwrap();
+        assert!(
+            process_csv_str(&input).unwrap() == expected,
+            "vector {} no longer produces its expected.csv",
+            name
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! Compile-time checks of the read-only `LedgerView`.
+
+#[test]
+fn views_cannot_change_the_ledger() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/view_*.rs");
+}
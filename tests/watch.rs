@@ -0,0 +1,67 @@
+//! `payments_engine watch` runs files dropped into a directory once, also across
+//! restarts of the watcher.
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+/// Empty directory unique to `name` and the test process.
+fn watched_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "payments_engine_watch_cli_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn watch(dir: &Path) -> Output {
+    cargo_bin_cmd!("payments_engine")
+        .args(["watch", "--settle-ms", "100", "--poll-interval-ms", "20"])
+        .args(["--exit-when-idle-ms", "300"])
+        .arg(dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn restarted_watcher_skips_processed_files() {
+    let dir = watched_dir("restart");
+    let hourly = "type,client,tx,amount\ndeposit,1,1,10\n";
+    std::fs::write(dir.join("hourly_01.csv"), hourly).unwrap();
+    let output = watch(&dir);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "processed hourly_01.csv: 1 rows executed, 0 rejected, 0 malformed\n"
+    );
+
+    std::fs::write(dir.join("hourly_01.csv"), hourly).unwrap();
+    std::fs::write(
+        dir.join("hourly_02.csv"),
+        "type,client,tx,amount\nwithdrawal,1,2,2.5\n",
+    )
+    .unwrap();
+    let output = watch(&dir);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "skipped hourly_01.csv: already processed\n\
+         processed hourly_02.csv: 1 rows executed, 0 rejected, 0 malformed\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("accounts.snapshot.csv")).unwrap(),
+        "client,available,held,total,locked\n1,7.5,0,7.5,false\n"
+    );
+    assert!(dir.join("processed").join("hourly_02.csv").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn missing_directory_argument_exits_2() {
+    cargo_bin_cmd!("payments_engine")
+        .arg("watch")
+        .assert()
+        .code(2);
+}